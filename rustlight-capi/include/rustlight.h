@@ -0,0 +1,45 @@
+#ifndef RUSTLIGHT_H
+#define RUSTLIGHT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Functions returning an int give 0 on success and -1 on error */
+typedef struct RustlightScene RustlightScene;
+
+/* Last error message of the calling thread (valid until the next call) */
+const char* rustlight_last_error(void);
+
+/* Load a scene (JSON or PBRT). Returns NULL on error. */
+RustlightScene* rustlight_scene_load(const char* filename);
+void rustlight_scene_free(RustlightScene* scene);
+
+/* Options: "nb_samples", "nb_threads" (0 for all the cores),
+   "image_scale" and "output_variance" (0 or 1).
+   Must not be called during rustlight_render. */
+int rustlight_scene_set_option(RustlightScene* scene, const char* name, const char* value);
+int rustlight_scene_image_size(const RustlightScene* scene, uint32_t* width, uint32_t* height);
+
+/* Render the scene inside buffer (RGB float, row major, width * height * 3 values).
+   integrator: "path", "light", "vpl", "mrcs", "volpath", "direct", "ao" or "debug".
+   params: JSON object with the integrator parameters of the command line (can be NULL),
+   e.g. "{\"max\": 5}". */
+int rustlight_render(const RustlightScene* scene,
+                     const char* integrator,
+                     const char* params,
+                     float* buffer,
+                     size_t buffer_len);
+
+/* Progress of the current rendering pass in [0, 1].
+   Can be called from another thread during rustlight_render. */
+float rustlight_progress(const RustlightScene* scene);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,225 @@
+//! Flat C interface (see include/rustlight.h)
+//! All the functions returning an int give 0 on success and -1 on error,
+//! the error message can be retrieved with rustlight_last_error.
+use rustlight::integrators::registry::IntegratorManager;
+use rustlight::integrators::RenderObserver;
+use rustlight::scene::Scene;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_float, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = msg);
+}
+
+/// Run f and convert the errors (and panics) to the C convention
+fn wrap<F: FnOnce() -> Result<(), String>>(f: F) -> c_int {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            set_error(e);
+            -1
+        }
+        Err(e) => {
+            let msg = if let Some(s) = e.downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = e.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "unknown panic".to_string()
+            };
+            set_error(format!("rustlight panicked: {}", msg));
+            -1
+        }
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| format!("{} is not UTF-8", name))
+}
+
+/// Progress of the current rendering pass (readable from any thread)
+#[derive(Default)]
+struct Progress {
+    nb_tiles: AtomicUsize,
+    nb_tiles_done: AtomicUsize,
+}
+impl RenderObserver for Progress {
+    fn on_start(&self, nb_tiles: usize) {
+        self.nb_tiles_done.store(0, Ordering::SeqCst);
+        self.nb_tiles.store(nb_tiles, Ordering::SeqCst);
+    }
+    fn on_tile_done(&self) {
+        self.nb_tiles_done.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+pub struct RustlightScene {
+    scene: Scene,
+    progress: Progress,
+}
+
+/// Last error message of the calling thread (valid until the next call)
+#[no_mangle]
+pub extern "C" fn rustlight_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+/// Load a scene (JSON or PBRT). Returns NULL on error.
+/// # Safety
+/// filename needs to be a valid C string
+#[no_mangle]
+pub unsafe extern "C" fn rustlight_scene_load(filename: *const c_char) -> *mut RustlightScene {
+    let mut scene = None;
+    wrap(|| {
+        let filename = to_str(filename, "filename")?;
+        let s = rustlight::scene_loader::SceneLoaderManager::default()
+            .load(filename.to_string())
+            .map_err(|e| e.to_string())?;
+        scene = Some(s);
+        Ok(())
+    });
+    match scene {
+        Some(scene) => Box::into_raw(Box::new(RustlightScene {
+            scene,
+            progress: Progress::default(),
+        })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// scene needs to come from rustlight_scene_load (or be NULL)
+#[no_mangle]
+pub unsafe extern "C" fn rustlight_scene_free(scene: *mut RustlightScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// Options: "nb_samples", "nb_threads" (0 for all the cores),
+/// "image_scale" and "output_variance" (0 or 1)
+/// # Safety
+/// scene needs to be valid and not used by rustlight_render
+#[no_mangle]
+pub unsafe extern "C" fn rustlight_scene_set_option(
+    scene: *mut RustlightScene,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    wrap(|| {
+        let scene = &mut scene.as_mut().ok_or("scene is NULL")?.scene;
+        let name = to_str(name, "name")?;
+        let value = to_str(value, "value")?;
+        let parse_error = || format!("wrong value for {}: {}", name, value);
+        match name {
+            "nb_samples" => scene.nb_samples = value.parse().map_err(|_| parse_error())?,
+            "nb_threads" => {
+                scene.nb_threads = match value.parse().map_err(|_| parse_error())? {
+                    0 => None,
+                    v => Some(v),
+                }
+            }
+            "image_scale" => {
+                let s: f32 = value.parse().map_err(|_| parse_error())?;
+                if s <= 0.0 {
+                    return Err("the image scale needs to be positive".to_string());
+                }
+                scene.camera.scale_image(s)
+            }
+            "output_variance" => scene.output_variance = value == "1",
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// scene needs to be valid, width and height need to be valid pointers
+#[no_mangle]
+pub unsafe extern "C" fn rustlight_scene_image_size(
+    scene: *const RustlightScene,
+    width: *mut u32,
+    height: *mut u32,
+) -> c_int {
+    wrap(|| {
+        let scene = &scene.as_ref().ok_or("scene is NULL")?.scene;
+        if width.is_null() || height.is_null() {
+            return Err("width or height is NULL".to_string());
+        }
+        *width = scene.camera.size().x;
+        *height = scene.camera.size().y;
+        Ok(())
+    })
+}
+
+/// Render the scene inside buffer (RGB float, row major, width * height * 3 values).
+/// params is a JSON object with the integrator parameters (can be NULL).
+/// # Safety
+/// scene needs to be valid, buffer needs to contain buffer_len floats
+#[no_mangle]
+pub unsafe extern "C" fn rustlight_render(
+    scene: *const RustlightScene,
+    integrator_name: *const c_char,
+    params: *const c_char,
+    buffer: *mut c_float,
+    buffer_len: usize,
+) -> c_int {
+    wrap(|| {
+        let scene = scene.as_ref().ok_or("scene is NULL")?;
+        let name = to_str(integrator_name, "integrator")?;
+        let params = if params.is_null() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_str(to_str(params, "params")?).map_err(|e| e.to_string())?
+        };
+        let size = scene.scene.camera.size();
+        let nb_values = (size.x * size.y * 3) as usize;
+        if buffer.is_null() || buffer_len < nb_values {
+            return Err(format!("the buffer needs {} floats", nb_values));
+        }
+
+        let mut int = IntegratorManager::default()
+            .create_from_json(name, &params)
+            .map_err(|e| e.to_string())?;
+        let img = int.compute(&scene.scene, &scene.progress);
+        let buffer = std::slice::from_raw_parts_mut(buffer, nb_values);
+        let primal = img.values["primal"].to_f32();
+        for (p, c) in buffer.chunks_mut(3).zip(&primal.colors) {
+            p.copy_from_slice(&[c.r, c.g, c.b]);
+        }
+        Ok(())
+    })
+}
+
+/// Progress of the current rendering pass in [0, 1].
+/// Can be called from another thread during rustlight_render.
+/// # Safety
+/// scene needs to be valid
+#[no_mangle]
+pub unsafe extern "C" fn rustlight_progress(scene: *const RustlightScene) -> c_float {
+    match scene.as_ref() {
+        None => 0.0,
+        Some(scene) => {
+            let nb_tiles = scene.progress.nb_tiles.load(Ordering::SeqCst);
+            let nb_tiles_done = scene.progress.nb_tiles_done.load(Ordering::SeqCst);
+            if nb_tiles == 0 {
+                0.0
+            } else {
+                nb_tiles_done as f32 / nb_tiles as f32
+            }
+        }
+    }
+}
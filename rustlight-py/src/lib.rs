@@ -0,0 +1,244 @@
+use ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rustlight::bsdfs::parse_bsdf;
+use rustlight::error::Error;
+use rustlight::geometry::bounding_sphere;
+use rustlight::integrators::registry::{IntegratorManager, IntegratorParams};
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::scene_loader::{parse_camera, parse_environment, parse_scene};
+use rustlight::structure::{Bitmap, Color};
+use std::path::Path;
+
+fn to_pyerr(e: Error) -> PyErr {
+    match e {
+        Error::Io(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// JSON description given as a string (e.g. json.dumps of a dictionary)
+fn parse_json(description: &str) -> PyResult<serde_json::Value> {
+    serde_json::from_str(description).map_err(|e| to_pyerr(e.into()))
+}
+
+/// Index of the mesh with this name
+fn mesh_id(scene: &rustlight::scene::Scene, name: &str) -> PyResult<usize> {
+    let mut ids = scene
+        .meshes
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.name == name);
+    match (ids.next(), ids.next()) {
+        (Some((id, _)), None) => Ok(id),
+        (None, _) => Err(to_pyerr(Error::MeshNotFound(name.to_string()))),
+        (Some(_), Some(_)) => Err(to_pyerr(Error::MeshDuplicated(name.to_string()))),
+    }
+}
+
+/// Bitmap to a (height, width, 3) float array
+fn to_numpy<'py>(py: Python<'py>, img: &Bitmap) -> &'py PyArray3<f32> {
+    let mut data = Vec::with_capacity(img.colors.len() * 3);
+    for c in &img.colors {
+        data.extend_from_slice(&[c.r, c.g, c.b]);
+    }
+    Array3::from_shape_vec((img.size.y as usize, img.size.x as usize, 3), data)
+        .unwrap()
+        .into_pyarray(py)
+}
+
+/// Scene loaded from a JSON or PBRT file, or built from a JSON description
+/// and modified by the set_* methods
+#[pyclass(unsendable)]
+pub struct Scene {
+    scene: rustlight::scene::Scene,
+}
+
+#[pymethods]
+impl Scene {
+    #[new]
+    fn new(filename: &str) -> PyResult<Self> {
+        let scene = rustlight::scene_loader::SceneLoaderManager::default()
+            .load(filename.to_string())
+            .map_err(to_pyerr)?;
+        Ok(Scene { scene })
+    }
+
+    /// Same format as the JSON scene files, the meshes and lens files
+    /// are relative to the working directory
+    #[staticmethod]
+    #[args(working_dir = "\".\"")]
+    fn from_json(description: &str, working_dir: &str) -> PyResult<Self> {
+        let scene =
+            parse_scene(&parse_json(description)?, Path::new(working_dir)).map_err(to_pyerr)?;
+        Ok(Scene { scene })
+    }
+
+    #[getter]
+    fn mesh_names(&self) -> Vec<String> {
+        self.scene.meshes.iter().map(|m| m.name.clone()).collect()
+    }
+
+    /// Main camera, same description as the "camera" of the JSON scenes
+    #[args(working_dir = "\".\"")]
+    fn set_camera(&mut self, description: &str, working_dir: &str) -> PyResult<()> {
+        self.scene.camera =
+            parse_camera(&parse_json(description)?, Path::new(working_dir)).map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    /// Emitted radiance (r, g, b) of the mesh (0 to stop emitting light)
+    fn set_emission(&mut self, mesh: &str, emission: (f32, f32, f32)) -> PyResult<()> {
+        let id = mesh_id(&self.scene, mesh)?;
+        let (r, g, b) = emission;
+        self.scene.meshes[id].emission = Color::new(r, g, b);
+        self.scene.emitter_changed(id);
+        Ok(())
+    }
+
+    /// Material of the mesh, same description as the "bsdfs" of the JSON scenes
+    /// ({"type": "diffuse", "data": {...}})
+    fn set_bsdf(&mut self, mesh: &str, description: &str) -> PyResult<()> {
+        let id = mesh_id(&self.scene, mesh)?;
+        self.scene.meshes[id].bsdf = parse_bsdf(&parse_json(description)?).map_err(to_pyerr)?;
+        Ok(())
+    }
+
+    /// Environment, same description as the "environment" of the JSON scenes
+    /// (None removes it)
+    fn set_environment(&mut self, description: Option<&str>) -> PyResult<()> {
+        self.scene.emitter_environment = match description {
+            Some(description) => {
+                let mut env = parse_environment(&parse_json(description)?).map_err(to_pyerr)?;
+                env.world = bounding_sphere(&self.scene.meshes);
+                Some(env)
+            }
+            None => None,
+        };
+        // The environment sampling is learned again
+        *self.scene.emitters_cache.get_mut().unwrap() = None;
+        Ok(())
+    }
+
+    #[getter]
+    fn nb_samples(&self) -> usize {
+        self.scene.nb_samples
+    }
+    #[setter]
+    fn set_nb_samples(&mut self, v: usize) {
+        self.scene.nb_samples = v;
+    }
+
+    /// None uses all the cores
+    #[getter]
+    fn nb_threads(&self) -> Option<usize> {
+        self.scene.nb_threads
+    }
+    #[setter]
+    fn set_nb_threads(&mut self, v: Option<usize>) {
+        self.scene.nb_threads = v;
+    }
+
+    /// Also output the "variance" and "spp" buffers (Monte Carlo integrators)
+    #[getter]
+    fn output_variance(&self) -> bool {
+        self.scene.output_variance
+    }
+    #[setter]
+    fn set_output_variance(&mut self, v: bool) {
+        self.scene.output_variance = v;
+    }
+
+    /// Also output the "surface" and "volume" buffers (volpath)
+    #[getter]
+    fn output_volume(&self) -> bool {
+        self.scene.output_volume
+    }
+    #[setter]
+    fn set_output_volume(&mut self, v: bool) {
+        self.scene.output_volume = v;
+    }
+
+    /// (width, height)
+    #[getter]
+    fn image_size(&self) -> (u32, u32) {
+        let size = self.scene.camera.size();
+        (size.x, size.y)
+    }
+
+    fn scale_image(&mut self, s: f32) -> PyResult<()> {
+        if s <= 0.0 {
+            return Err(PyValueError::new_err("the scale needs to be positive"));
+        }
+        self.scene.camera.scale_image(s);
+        Ok(())
+    }
+}
+
+/// Create an integrator by its name
+/// (same names, parameters and defaults as the command line, None for the default value)
+fn integrator(name: &str, params: Option<&PyDict>) -> PyResult<IntegratorType> {
+    let mut values = IntegratorParams::new();
+    for (k, v) in params.into_iter().flat_map(|p| p.iter()) {
+        if v.is_none() {
+            continue;
+        }
+        let v = match v.extract::<bool>() {
+            Ok(b) => b.to_string(),
+            Err(_) => v.str()?.to_str()?.to_string(),
+        };
+        values.insert(k.extract()?, v);
+    }
+    IntegratorManager::default()
+        .create(name, &values)
+        .map_err(to_pyerr)
+}
+
+/// Render all the buffers produced by the integrator
+/// (dictionary of (height, width, 3) float arrays)
+#[pyfunction]
+fn render_buffers<'py>(
+    py: Python<'py>,
+    scene: &Scene,
+    integrator_name: &str,
+    params: Option<&PyDict>,
+) -> PyResult<&'py PyDict> {
+    let mut int = integrator(integrator_name, params)?;
+    let img = int.compute(&scene.scene, &NoObserver);
+    let buffers = PyDict::new(py);
+    for (name, bitmap) in &img.values {
+        buffers.set_item(name, to_numpy(py, &bitmap.to_f32()))?;
+    }
+    Ok(buffers)
+}
+
+/// Render the image (primal buffer) as a (height, width, 3) float array
+#[pyfunction]
+fn render<'py>(
+    py: Python<'py>,
+    scene: &Scene,
+    integrator_name: &str,
+    params: Option<&PyDict>,
+) -> PyResult<&'py PyArray3<f32>> {
+    let mut int = integrator(integrator_name, params)?;
+    let img = int.compute(&scene.scene, &NoObserver);
+    Ok(to_numpy(py, &img.values["primal"].to_f32()))
+}
+
+/// Read an image (pfm, exr, hdr, png, ...) as a (height, width, 3) float array
+#[pyfunction]
+fn read_image<'py>(py: Python<'py>, filename: &str) -> PyResult<&'py PyArray3<f32>> {
+    let img = Bitmap::read(filename).map_err(to_pyerr)?;
+    Ok(to_numpy(py, &img))
+}
+
+#[pymodule]
+fn rustlight_py(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Scene>()?;
+    m.add_wrapped(wrap_pyfunction!(render))?;
+    m.add_wrapped(wrap_pyfunction!(render_buffers))?;
+    m.add_wrapped(wrap_pyfunction!(read_image))?;
+    Ok(())
+}
@@ -1,5 +1,6 @@
+use crate::geometry::Mesh;
 use crate::structure::*;
-use cgmath::Point3;
+use cgmath::*;
 
 #[derive(Debug)]
 struct BVHNode {
@@ -23,6 +24,15 @@ pub struct BHVAccel<D, T: BVHElement<D>> {
     phantom: std::marker::PhantomData<D>,
 }
 
+/// Work done by the traversals (to find the pathological primitives)
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct TraversalStats {
+    /// Nodes tested against the ray
+    pub nodes: usize,
+    /// Primitives tested against the ray
+    pub primitives: usize,
+}
+
 pub trait BVHElement<D> {
     // Used to build AABB hierachy
     fn aabb(&self) -> AABB;
@@ -30,6 +40,122 @@ pub trait BVHElement<D> {
     fn position(&self) -> Point3<f32>;
     // Used when collecting the different objects
     fn intersection(&self, r: &Ray) -> Option<D>;
+    /// Closest point of the element to p (None: ignored by the point queries)
+    fn closest_point(&self, _p: &Point3<f32>) -> Option<Point3<f32>> {
+        None
+    }
+}
+
+/// Triangle of a mesh, to build a native BVH over the meshes (see TriangleBVH)
+pub struct Triangle {
+    /// Index of the mesh
+    pub mesh: usize,
+    /// Index of the triangle inside the mesh
+    pub face: usize,
+    pub vertices: [Vector3<f32>; 3],
+}
+
+/// Native BVH over the triangles of the meshes, the intersections are
+/// the distance and the barycentric coordinates (see Intersection::face_uv)
+pub type TriangleBVH = BHVAccel<(f32, Vector2<f32>), Triangle>;
+
+impl Triangle {
+    pub fn from_meshes(meshes: &[Mesh]) -> Vec<Triangle> {
+        meshes
+            .iter()
+            .enumerate()
+            .flat_map(|(mesh, m)| {
+                m.indices.iter().enumerate().map(move |(face, i)| Triangle {
+                    mesh,
+                    face,
+                    vertices: [m.vertices[i.x], m.vertices[i.y], m.vertices[i.z]],
+                })
+            })
+            .collect()
+    }
+
+    /// Closest point of the triangle to p and its barycentric coordinates
+    /// (weights of the second and third vertices), from "Real-Time Collision Detection"
+    pub fn closest(&self, p: &Point3<f32>) -> (Point3<f32>, Vector2<f32>) {
+        let [a, b, c] = self.vertices;
+        let p = p.to_vec();
+        let (ab, ac) = (b - a, c - a);
+        let point = |u: f32, v: f32| (Point3::from_vec(a + ab * u + ac * v), Vector2::new(u, v));
+        // Regions of the vertices and of the edges
+        let (d1, d2) = (ab.dot(p - a), ac.dot(p - a));
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return point(0.0, 0.0);
+        }
+        let (d3, d4) = (ab.dot(p - b), ac.dot(p - b));
+        if d3 >= 0.0 && d4 <= d3 {
+            return point(1.0, 0.0);
+        }
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return point(d1 / (d1 - d3), 0.0);
+        }
+        let (d5, d6) = (ab.dot(p - c), ac.dot(p - c));
+        if d6 >= 0.0 && d5 <= d6 {
+            return point(0.0, 1.0);
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return point(0.0, d2 / (d2 - d6));
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && d4 >= d3 && d5 >= d6 {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            return point(1.0 - w, w);
+        }
+        // Inside the triangle (degenerate triangles: first vertex)
+        let sum = va + vb + vc;
+        if sum <= 0.0 {
+            return point(0.0, 0.0);
+        }
+        point(vb / sum, vc / sum)
+    }
+}
+
+impl BVHElement<(f32, Vector2<f32>)> for Triangle {
+    fn aabb(&self) -> AABB {
+        self.vertices
+            .iter()
+            .fold(AABB::default(), |aabb, v| aabb.union_vec(v))
+    }
+    fn position(&self) -> Point3<f32> {
+        let [a, b, c] = self.vertices;
+        Point3::from_vec((a + b + c) / 3.0)
+    }
+    /// Moller-Trumbore, hits between ray.tnear and ray.tfar
+    fn intersection(&self, r: &Ray) -> Option<(f32, Vector2<f32>)> {
+        let [a, b, c] = self.vertices;
+        let (e1, e2) = (b - a, c - a);
+        let pvec = r.d.cross(e2);
+        let det = e1.dot(pvec);
+        if det == 0.0 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.o.to_vec() - a;
+        let u = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let v = r.d.dot(qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t > r.tnear && t < r.tfar {
+            Some((t, Vector2::new(u, v)))
+        } else {
+            None
+        }
+    }
+    fn closest_point(&self, p: &Point3<f32>) -> Option<Point3<f32>> {
+        Some(self.closest(p).0)
+    }
 }
 
 // Implementation from (C++): https://github.com/shiinamiyuki/minpt/blob/master/minpt.cpp
@@ -114,14 +240,91 @@ impl<D, T: BVHElement<D>> BHVAccel<D, T> {
         info!("BVH stats: ");
         info!(" - Number of elements: {}", accel.elements.len());
         info!(" - Number of nodes: {}", accel.nodes.len());
-        info!(
-            " - AABB size root: {:?}",
-            accel.nodes[accel.root.unwrap()].aabb.size()
-        );
+        if let Some(root) = accel.root {
+            info!(" - AABB size root: {:?}", accel.nodes[root].aabb.size());
+        }
         accel
     }
 
+    /// Number of nodes (interior and leaves)
+    pub fn nb_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Number of leaves
+    pub fn nb_leaves(&self) -> usize {
+        self.nodes.iter().filter(|n| n.is_leaf()).count()
+    }
+
+    /// Is any element intersected by the ray (stops at the first one)
+    pub fn any(&self, r: &Ray) -> bool {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(curr_id) = stack.pop() {
+            let n = &self.nodes[curr_id];
+            if n.aabb.intersect(r).is_none() {
+                continue;
+            }
+            if n.is_leaf() {
+                if (n.first..(n.first + n.count))
+                    .any(|i| self.elements[i].intersection(r).is_some())
+                {
+                    return true;
+                }
+            } else {
+                stack.extend(n.left);
+                stack.extend(n.right);
+            }
+        }
+        false
+    }
+
+    /// Element closest to p, closer than max_distance (see BVHElement::closest_point):
+    /// its index and its closest point
+    pub fn closest_point(
+        &self,
+        p: &Point3<f32>,
+        max_distance: f32,
+    ) -> Option<(usize, Point3<f32>)> {
+        let mut best = None;
+        let mut best_dist2 = max_distance * max_distance;
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(curr_id) = stack.pop() {
+            let n = &self.nodes[curr_id];
+            if n.aabb.distance2(p) >= best_dist2 {
+                continue;
+            }
+            if n.is_leaf() {
+                for i in n.first..(n.first + n.count) {
+                    if let Some(q) = self.elements[i].closest_point(p) {
+                        let dist2 = (q - p).magnitude2();
+                        if dist2 < best_dist2 {
+                            best_dist2 = dist2;
+                            best = Some((i, q));
+                        }
+                    }
+                }
+            } else {
+                // The closest child is visited first
+                let mut children = n.left.into_iter().chain(n.right).collect::<Vec<_>>();
+                children.sort_by(|a, b| {
+                    let (da, db) = (
+                        self.nodes[*a].aabb.distance2(p),
+                        self.nodes[*b].aabb.distance2(p),
+                    );
+                    db.partial_cmp(&da).unwrap()
+                });
+                stack.extend(children);
+            }
+        }
+        best
+    }
+
     pub fn gather(&self, r: Ray) -> Vec<(D, usize)> {
+        self.gather_with_stats(r, &mut TraversalStats::default())
+    }
+
+    /// Same as gather, but also count the nodes and primitives visited
+    pub fn gather_with_stats(&self, r: Ray, stats: &mut TraversalStats) -> Vec<(D, usize)> {
         let mut res = vec![];
         if self.root.is_none() {
             return res;
@@ -134,12 +337,14 @@ impl<D, T: BVHElement<D>> BHVAccel<D, T> {
 
         while let Some(curr_id) = stack.pop() {
             let n = &self.nodes[curr_id];
+            stats.nodes += 1;
             let t_aabb = n.aabb.intersect(&r);
             match (n.is_leaf(), t_aabb) {
                 (_, None) => {
                     // Nothing to do as we miss the node
                 }
                 (true, Some(_t)) => {
+                    stats.primitives += n.count;
                     for i in n.first..(n.first + n.count) {
                         if let Some(d) = self.elements[i].intersection(&r) {
                             res.push((d, i));
@@ -0,0 +1,696 @@
+//! Baking of the global illumination for the real-time renderers.
+//!
+//! Irradiance volume: a grid of probes over the scene bounds, each one storing the
+//! incoming radiance projected on the real spherical harmonics (see math::sh_basis
+//! for the order of the coefficients). The probes are saved in JSON:
+//!
+//! ```json
+//! {
+//!   "p_min": [x, y, z], "p_max": [x, y, z], "resolution": [nx, ny, nz], "bands": 3,
+//!   "probes": [{"position": [x, y, z], "radiance": [[r, g, b], ...], "backface": 0.0}, ...]
+//! }
+//! ```
+//! The probes are on the vertices of the grid (x varies first). The irradiance around
+//! a normal is the radiance convolved with the clamped cosine (SphericalHarmonics::
+//! convolve_cosine). `backface` is the fraction of the rays of the probe hitting the back
+//! of a surface: the probes inside the geometry (close to 1) are better ignored.
+//!
+//! Lightmaps: the texture space film (TextureSpace) replaces the pixels of the camera by
+//! the texels of the texture coordinates of a mesh. Each camera ray arrives on the point
+//! of the mesh covered by its texel along the normal, so any integrator gives the radiance
+//! leaving the surface toward its normal (the irradiance times albedo / pi plus the
+//! emission for a diffuse surface). The same film can also lay the vertices of a mesh out
+//! as texels (TextureSpace::vertices) to bake vertex colors (save_vertex_colors).
+use crate::camera::{CameraSample, RayDifferentials};
+use crate::emitter::*;
+use crate::error::{Error, Result};
+use crate::geometry::Mesh;
+use crate::integrators::{embree_scene, mis_weight};
+use crate::math::{sample_uniform_sphere, SphericalHarmonics};
+use crate::samplers::Sampler;
+use crate::scene::*;
+use crate::structure::*;
+use cgmath::*;
+use rayon::prelude::*;
+
+/// Parameters of the irradiance volume baking
+#[derive(Clone, Debug)]
+pub struct IrradianceVolumeConfig {
+    /// Number of probes along each axis (at least 2)
+    pub resolution: Vector3<usize>,
+    /// Number of spherical harmonics bands (3: 9 coefficients per probe)
+    pub bands: usize,
+    /// The rays of a probe are stratified on nb_strata * nb_strata cells of the sphere
+    pub nb_strata: usize,
+    /// Maximum number of bounces after the first intersection
+    pub max_depth: u32,
+    /// Volume covered by the probes (scene bounds if None)
+    pub aabb: Option<AABB>,
+}
+
+impl Default for IrradianceVolumeConfig {
+    fn default() -> Self {
+        IrradianceVolumeConfig {
+            resolution: Vector3::new(8, 8, 8),
+            bands: 3,
+            nb_strata: 16,
+            max_depth: 8,
+            aabb: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Probe {
+    pub position: [f32; 3],
+    /// Incoming radiance (spherical harmonics coefficients)
+    pub radiance: Vec<[f32; 3]>,
+    /// Fraction of the rays hitting a back face
+    pub backface: f32,
+}
+
+impl Probe {
+    pub fn radiance_sh(&self) -> SphericalHarmonics {
+        let bands = (self.radiance.len() as f32).sqrt() as usize;
+        SphericalHarmonics {
+            bands,
+            coeffs: self
+                .radiance
+                .iter()
+                .map(|c| Color::new(c[0], c[1], c[2]))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IrradianceVolume {
+    pub p_min: [f32; 3],
+    pub p_max: [f32; 3],
+    pub resolution: [usize; 3],
+    pub bands: usize,
+    pub probes: Vec<Probe>,
+}
+
+impl IrradianceVolume {
+    /// Bake the probes (builds its own acceleration data structure)
+    pub fn bake_scene(scene: &Scene, config: &IrradianceVolumeConfig) -> IrradianceVolume {
+        info!("Build acceleration data structure...");
+        let embree_device = embree_rs::Device::new();
+        let embree_scene = embree_scene(&embree_device, &scene.meshes);
+        let accel = EmbreeAcceleration::new(&scene.meshes, &embree_scene);
+        IrradianceVolume::bake(&accel, scene, config)
+    }
+
+    pub fn bake(
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        config: &IrradianceVolumeConfig,
+    ) -> IrradianceVolume {
+        let res = config.resolution;
+        assert!(
+            res.x >= 2 && res.y >= 2 && res.z >= 2,
+            "at least 2 probes per axis"
+        );
+        assert!(config.nb_strata > 0);
+        let aabb = config.aabb.unwrap_or_else(|| {
+            scene
+                .meshes
+                .iter()
+                .fold(AABB::default(), |aabb, m| aabb.union_aabb(&m.aabb()))
+        });
+        scene.prepare_emitters(accel);
+        let emitters = scene.emitters_sampler();
+
+        info!(
+            "Bake {}x{}x{} probes ({} rays each)...",
+            res.x,
+            res.y,
+            res.z,
+            config.nb_strata * config.nb_strata
+        );
+        let size = aabb.size();
+        let pool = crate::integrators::generate_pool(scene);
+        let probes = pool.install(|| {
+            (0..res.x * res.y * res.z)
+                .into_par_iter()
+                .map(|id| {
+                    let i = Vector3::new(id % res.x, (id / res.x) % res.y, id / (res.x * res.y));
+                    let t = Vector3::new(
+                        i.x as f32 / (res.x - 1) as f32,
+                        i.y as f32 / (res.y - 1) as f32,
+                        i.z as f32 / (res.z - 1) as f32,
+                    );
+                    let p = Point3::from_vec(aabb.p_min + size.mul_element_wise(t));
+                    let mut sampler = scene.sampler.for_task(scene.pass_seed(), id);
+                    bake_probe(accel, scene, &emitters, p, config, sampler.as_mut())
+                })
+                .collect()
+        });
+        IrradianceVolume {
+            p_min: aabb.p_min.into(),
+            p_max: aabb.p_max.into(),
+            resolution: res.into(),
+            bands: config.bands,
+            probes,
+        }
+    }
+
+    pub fn load(filename: &str) -> Result<IrradianceVolume> {
+        let data = std::fs::read_to_string(filename)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let file = std::fs::File::create(filename)?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Irradiance around the normal n, interpolated (trilinear) between the probes around p
+    pub fn irradiance(&self, p: Point3<f32>, n: Vector3<f32>) -> Color {
+        let [rx, ry, rz] = self.resolution;
+        // Continuous probe coordinates, clamped inside the grid
+        let coord = |v: f32, min: f32, max: f32, res: usize| {
+            let t = if max > min {
+                (v - min) / (max - min)
+            } else {
+                0.0
+            };
+            (t * (res - 1) as f32).max(0.0).min((res - 1) as f32)
+        };
+        let c = [
+            coord(p.x, self.p_min[0], self.p_max[0], rx),
+            coord(p.y, self.p_min[1], self.p_max[1], ry),
+            coord(p.z, self.p_min[2], self.p_max[2], rz),
+        ];
+        let i0 = [
+            (c[0] as usize).min(rx - 2),
+            (c[1] as usize).min(ry - 2),
+            (c[2] as usize).min(rz - 2),
+        ];
+        let mut irradiance = Color::zero();
+        for corner in 0..8 {
+            let o = [corner & 1, (corner >> 1) & 1, corner >> 2];
+            let w = (0..3).fold(1.0, |w, k| {
+                let f = c[k] - i0[k] as f32;
+                w * if o[k] == 1 { f } else { 1.0 - f }
+            });
+            let id = ((i0[2] + o[2]) * ry + i0[1] + o[1]) * rx + i0[0] + o[0];
+            irradiance += self.probes[id].radiance_sh().convolve_cosine().eval(n) * w;
+        }
+        irradiance
+    }
+}
+
+/// Number of texels padded around the charts by default (against the seams of the
+/// bilinear filtering and the mip-mapping)
+pub const LIGHTMAP_DILATION: u32 = 4;
+
+/// Triangle of the mesh, with its texture coordinates in texels
+struct TexelTriangle {
+    p: [Point3<f32>; 3],
+    /// Geometric normal (same side as the shading normals)
+    ng: Vector3<f32>,
+    uv: [Point2<f32>; 3],
+}
+
+impl TexelTriangle {
+    /// Barycentric coordinates of the texel position (None outside of the triangle)
+    fn barycentric(&self, px: Point2<f32>) -> Option<[f32; 3]> {
+        let [a, b, c] = self.uv;
+        let edge = |p0: Point2<f32>, p1: Point2<f32>| {
+            (p1.x - p0.x) * (px.y - p0.y) - (p1.y - p0.y) * (px.x - p0.x)
+        };
+        let area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+        if area == 0.0 {
+            return None;
+        }
+        let w = [edge(b, c) / area, edge(c, a) / area, edge(a, b) / area];
+        if w.iter().all(|&w| w >= -1e-5) {
+            Some(w)
+        } else {
+            None
+        }
+    }
+}
+
+/// Number of vertices per row of the vertex layout (see TextureSpace::vertices)
+pub const VERTICES_PER_ROW: u32 = 1024;
+
+/// Surface seen by a texel
+#[derive(Clone, Copy)]
+enum Texel {
+    /// Triangle covering the texel center (index in TextureSpace::triangles)
+    Triangle(usize),
+    /// Vertex, with its normal
+    Vertex(Point3<f32>, Vector3<f32>),
+}
+
+/// Texture space film: the pixels of the camera are the texels of a mesh
+/// (see Camera::set_texture_space). The texture coordinates of the mesh must not overlap.
+pub struct TextureSpace {
+    pub size: Vector2<u32>,
+    /// Texels padded around the charts after the rendering (see dilate)
+    pub dilation: u32,
+    triangles: Vec<TexelTriangle>,
+    texels: Vec<Option<Texel>>,
+}
+
+impl TextureSpace {
+    /// Rasterize the texture coordinates of the mesh (v goes up in the image)
+    pub fn new(mesh: &Mesh, size: Vector2<u32>) -> Result<TextureSpace> {
+        let uv = mesh.uv.as_ref().ok_or_else(|| {
+            Error::InvalidData(format!("the mesh {} has no texture coordinates", mesh.name))
+        })?;
+        let mut texture_space = TextureSpace {
+            size,
+            dilation: LIGHTMAP_DILATION,
+            triangles: vec![],
+            texels: vec![None; (size.x * size.y) as usize],
+        };
+        let mut nb_overlaps = 0;
+        for id in &mesh.indices {
+            let ids = [id.x, id.y, id.z];
+            let p = ids.map(|i| Point3::from_vec(mesh.vertices[i]));
+            let ng = (p[1] - p[0]).cross(p[2] - p[0]);
+            if ng.magnitude2() == 0.0 {
+                continue;
+            }
+            let ng = ng.normalize();
+            let n = match &mesh.normals {
+                Some(normals) => ids.map(|i| normals[i]),
+                None => [ng; 3],
+            };
+            let ng = if ng.dot(n[0] + n[1] + n[2]) < 0.0 {
+                -ng
+            } else {
+                ng
+            };
+            let triangle = TexelTriangle {
+                p,
+                ng,
+                uv: ids
+                    .map(|i| Point2::new(uv[i].x * size.x as f32, (1.0 - uv[i].y) * size.y as f32)),
+            };
+
+            // Texel centers inside the triangle
+            let (min, max) = triangle.uv.iter().fold(
+                (
+                    Point2::new(f32::MAX, f32::MAX),
+                    Point2::new(f32::MIN, f32::MIN),
+                ),
+                |(min, max), p| {
+                    (
+                        Point2::new(min.x.min(p.x), min.y.min(p.y)),
+                        Point2::new(max.x.max(p.x), max.y.max(p.y)),
+                    )
+                },
+            );
+            let range = |min: f32, max: f32, size: u32| {
+                let first = (min - 0.5).ceil().max(0.0) as u32;
+                let last = ((max - 0.5).floor() + 1.0).max(0.0).min(size as f32) as u32;
+                first..last
+            };
+            let index = texture_space.triangles.len();
+            let mut covered = false;
+            for y in range(min.y, max.y, size.y) {
+                for x in range(min.x, max.x, size.x) {
+                    let center = Point2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    if triangle.barycentric(center).is_some() {
+                        let texel = &mut texture_space.texels[(y * size.x + x) as usize];
+                        if texel.is_some() {
+                            nb_overlaps += 1;
+                        }
+                        *texel = Some(Texel::Triangle(index));
+                        covered = true;
+                    }
+                }
+            }
+            if covered {
+                texture_space.triangles.push(triangle);
+            }
+        }
+        if nb_overlaps != 0 {
+            warn!(
+                "{} texels covered by several triangles of {}: the texture coordinates overlap",
+                nb_overlaps, mesh.name
+            );
+        }
+        Ok(texture_space)
+    }
+
+    /// One texel per vertex of the mesh (VERTICES_PER_ROW vertices per row, in order),
+    /// without dilation. The normals are the vertex normals or the average of the normals
+    /// of the triangles around the vertices.
+    pub fn vertices(mesh: &Mesh) -> TextureSpace {
+        let nb_vertices = mesh.vertices.len() as u32;
+        let size = Vector2::new(
+            nb_vertices.clamp(1, VERTICES_PER_ROW),
+            nb_vertices.div_ceil(VERTICES_PER_ROW).max(1),
+        );
+        // Area weighted normals, and a triangle around each vertex (the largest)
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); mesh.vertices.len()];
+        let mut triangles: Vec<Option<(f32, Vector3<f32>)>> = vec![None; mesh.vertices.len()];
+        for id in &mesh.indices {
+            let ids = [id.x, id.y, id.z];
+            let v = ids.map(|i| mesh.vertices[i]);
+            let n = (v[1] - v[0]).cross(v[2] - v[0]);
+            let area = n.magnitude();
+            let centroid = (v[0] + v[1] + v[2]) / 3.0;
+            for &i in &ids {
+                normals[i] += n;
+                match triangles[i] {
+                    Some((a, _)) if a >= area => {}
+                    _ => triangles[i] = Some((area, centroid)),
+                }
+            }
+        }
+        let mut texels = vec![None; (size.x * size.y) as usize];
+        for (i, v) in mesh.vertices.iter().enumerate() {
+            let n = match &mesh.normals {
+                Some(n) => n[i],
+                None => normals[i],
+            };
+            // The vertices of no triangle are not baked
+            if let (Some((_, centroid)), true) = (triangles[i], n.magnitude2() > 0.0) {
+                // Slightly inside the triangle so the ray does not miss the mesh border
+                let p = Point3::from_vec(v + (centroid - v) * 1e-3);
+                texels[i] = Some(Texel::Vertex(p, n.normalize()));
+            }
+        }
+        TextureSpace {
+            size,
+            dilation: 0,
+            triangles: vec![],
+            texels,
+        }
+    }
+
+    /// Texel of the vertex in the vertex layout (see vertices)
+    pub fn vertex_texel(i: usize) -> Point2<u32> {
+        let i = i as u32;
+        Point2::new(i % VERTICES_PER_ROW, i / VERTICES_PER_ROW)
+    }
+
+    /// Is the center of the texel covered by the mesh
+    pub fn is_covered(&self, p: Point2<u32>) -> bool {
+        self.texels[(p.y * self.size.x + p.x) as usize].is_some()
+    }
+
+    /// Ray arriving on the mesh point of the texel position along its normal
+    /// (the ray of an uncovered texel does not intersect anything)
+    fn ray(&self, px: Point2<f32>) -> Ray {
+        let x = (px.x.max(0.0) as u32).min(self.size.x - 1);
+        let y = (px.y.max(0.0) as u32).min(self.size.y - 1);
+        let triangle = match self.texels[(y * self.size.x + x) as usize] {
+            Some(Texel::Triangle(id)) => &self.triangles[id],
+            Some(Texel::Vertex(p, n)) => return arriving_ray(p, n),
+            None => {
+                let mut ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::unit_z());
+                ray.tfar = 0.0;
+                return ray;
+            }
+        };
+        // Texel center when the position is outside of the triangle (overflowing texel)
+        let w = triangle.barycentric(px).unwrap_or_else(|| {
+            triangle
+                .barycentric(Point2::new(x as f32 + 0.5, y as f32 + 0.5))
+                .unwrap()
+        });
+        let p = Point3::from_vec(
+            triangle.p[0].to_vec() * w[0]
+                + triangle.p[1].to_vec() * w[1]
+                + triangle.p[2].to_vec() * w[2],
+        );
+        arriving_ray(p, triangle.ng)
+    }
+
+    /// Camera ray of the texel position (see Camera::sample)
+    pub fn sample(&self, px: Point2<f32>, lens_uv: Point2<f32>, time: f32) -> CameraSample {
+        let dx = self.ray(px + Vector2::new(1.0, 0.0));
+        let dy = self.ray(px + Vector2::new(0.0, 1.0));
+        CameraSample {
+            ray: self.ray(px),
+            differentials: RayDifferentials { dx, dy },
+            time,
+            lens_uv,
+            we_pdf: 1.0,
+            weight: 1.0,
+        }
+    }
+
+    /// Clear the uncovered texels of the rendered image, then fill the dilation texels
+    /// around the charts with the average of their covered neighbors
+    pub fn dilate<C: PixelValue>(&self, img: &mut Bitmap<C>) {
+        assert_eq!(img.size, self.size);
+        let (w, h) = (self.size.x as usize, self.size.y as usize);
+        let mut covered = self.texels.iter().map(|t| t.is_some()).collect::<Vec<_>>();
+        for (c, covered) in img.colors.iter_mut().zip(&covered) {
+            if !covered {
+                *c = C::default();
+            }
+        }
+        for _ in 0..self.dilation {
+            let mut next = covered.clone();
+            for y in 0..h {
+                for x in 0..w {
+                    if covered[y * w + x] {
+                        continue;
+                    }
+                    let neighbors = [
+                        (x > 0).then(|| y * w + x - 1),
+                        (x + 1 < w).then(|| y * w + x + 1),
+                        (y > 0).then(|| (y - 1) * w + x),
+                        (y + 1 < h).then(|| (y + 1) * w + x),
+                    ];
+                    let mut sum = C::default();
+                    let mut count = 0;
+                    for &i in neighbors.iter().flatten() {
+                        if covered[i] {
+                            sum += img.colors[i];
+                            count += 1;
+                        }
+                    }
+                    if count != 0 {
+                        sum.scale(1.0 / count as f32);
+                        img.colors[y * w + x] = sum;
+                        next[y * w + x] = true;
+                    }
+                }
+            }
+            covered = next;
+        }
+    }
+}
+
+/// Ray arriving on p along the normal n, from above the surface
+/// (far enough from the floating point errors)
+fn arriving_ray(p: Point3<f32>, n: Vector3<f32>) -> Ray {
+    let offset = 1e-4 * p.x.abs().max(p.y.abs()).max(p.z.abs()).max(1.0);
+    Ray::new(p + n * offset, -n)
+}
+
+/// Save the mesh with the colors of its vertices baked with TextureSpace::vertices:
+/// PLY (ASCII, float colors) or OBJ (colors after the vertex positions).
+/// The colors are linear and not clamped.
+pub fn save_vertex_colors(mesh: &Mesh, colors: &Bitmap, filename: &str) -> Result<()> {
+    use std::io::Write;
+    let ext = crate::tools::extension(filename)?;
+    let color = |i: usize| colors.pixel(TextureSpace::vertex_texel(i));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(filename)?);
+    match ext {
+        "ply" => {
+            writeln!(file, "ply\nformat ascii 1.0\ncomment {}", mesh.name)?;
+            writeln!(file, "element vertex {}", mesh.vertices.len())?;
+            for p in &["x", "y", "z"] {
+                writeln!(file, "property float {}", p)?;
+            }
+            if mesh.normals.is_some() {
+                for p in &["nx", "ny", "nz"] {
+                    writeln!(file, "property float {}", p)?;
+                }
+            }
+            for p in &["red", "green", "blue"] {
+                writeln!(file, "property float {}", p)?;
+            }
+            writeln!(file, "element face {}", mesh.indices.len())?;
+            writeln!(file, "property list uchar int vertex_indices\nend_header")?;
+            for (i, v) in mesh.vertices.iter().enumerate() {
+                write!(file, "{} {} {}", v.x, v.y, v.z)?;
+                if let Some(normals) = &mesh.normals {
+                    let n = normals[i];
+                    write!(file, " {} {} {}", n.x, n.y, n.z)?;
+                }
+                let c = color(i);
+                writeln!(file, " {} {} {}", c.r, c.g, c.b)?;
+            }
+            for id in &mesh.indices {
+                writeln!(file, "3 {} {} {}", id.x, id.y, id.z)?;
+            }
+        }
+        "obj" => {
+            writeln!(file, "o {}", mesh.name)?;
+            for (i, v) in mesh.vertices.iter().enumerate() {
+                let c = color(i);
+                writeln!(file, "v {} {} {} {} {} {}", v.x, v.y, v.z, c.r, c.g, c.b)?;
+            }
+            if let Some(normals) = &mesh.normals {
+                for n in normals {
+                    writeln!(file, "vn {} {} {}", n.x, n.y, n.z)?;
+                }
+            }
+            for id in &mesh.indices {
+                let (a, b, c) = (id.x + 1, id.y + 1, id.z + 1);
+                match mesh.normals {
+                    Some(_) => writeln!(file, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?,
+                    None => writeln!(file, "f {} {} {}", a, b, c)?,
+                }
+            }
+        }
+        _ => return Err(Error::UnsupportedFormat(ext.to_string())),
+    }
+    Ok(())
+}
+
+/// Radiance arriving at p projected on the spherical harmonics
+/// (one path per stratum of the sphere)
+fn bake_probe(
+    accel: &dyn Acceleration,
+    scene: &Scene,
+    emitters: &EmitterSampler,
+    p: Point3<f32>,
+    config: &IrradianceVolumeConfig,
+    sampler: &mut dyn Sampler,
+) -> Probe {
+    let n = config.nb_strata;
+    let weight = 4.0 * std::f32::consts::PI / (n * n) as f32;
+    let mut sh = SphericalHarmonics::new(config.bands);
+    let mut nb_backface = 0;
+    for i in 0..n * n {
+        sampler.start_pixel_sample(Point2::new(i as u32, 0), 0);
+        let u = sampler.next2d();
+        let d = sample_uniform_sphere(Point2::new(
+            ((i % n) as f32 + u.x) / n as f32,
+            ((i / n) as f32 + u.y) / n as f32,
+        ));
+        let (radiance, backface) = incident_radiance(
+            accel,
+            scene,
+            emitters,
+            Ray::new(p, d),
+            config.max_depth,
+            sampler,
+        );
+        let radiance = scene
+            .non_finite
+            .check(radiance, || format!("probe ({}, {}, {})", p.x, p.y, p.z));
+        sh.add_sample(d, radiance, weight);
+        nb_backface += backface as usize;
+    }
+    Probe {
+        position: p.into(),
+        radiance: sh.coeffs.iter().map(|c| [c.r, c.g, c.b]).collect(),
+        backface: nb_backface as f32 / (n * n) as f32,
+    }
+}
+
+/// Radiance arriving along the ray (path tracing with the emitters sampled at each bounce,
+/// weighted with MIS against the BSDF sampling). The boolean is true if the ray hits
+/// the back of a surface.
+pub fn incident_radiance(
+    accel: &dyn Acceleration,
+    scene: &Scene,
+    emitters: &EmitterSampler,
+    ray: Ray,
+    max_depth: u32,
+    sampler: &mut dyn Sampler,
+) -> (Color, bool) {
+    let mut radiance = Color::zero();
+    let mut throughput = Color::one();
+    let mut ray = ray;
+    // Solid angle density of the last BSDF sampling (None: not sampled by the emitters)
+    let mut bsdf_pdf: Option<f32> = None;
+    for depth in 0..=max_depth {
+        let its = match accel.trace(&ray) {
+            Some(its) => its,
+            None => {
+                let weight = match (&scene.emitter_environment, bsdf_pdf) {
+                    (Some(env), Some(pdf)) => mis_weight(pdf, emitters.environment_pdf(env, ray.d)),
+                    _ => 1.0,
+                };
+                radiance += throughput * scene.enviroment_luminance(ray.d) * weight;
+                break;
+            }
+        };
+        if its.cos_theta() <= 0.0 {
+            return (radiance, depth == 0);
+        }
+        if its.mesh.is_light() {
+            let weight = match bsdf_pdf {
+                Some(pdf) => {
+                    let light_pdf = emitters
+                        .direct_pdf(its.mesh, &LightSamplingPDF::new(&ray, &its))
+                        .value();
+                    mis_weight(pdf, light_pdf)
+                }
+                None => 1.0,
+            };
+            radiance += throughput * its.mesh.emission * weight;
+        }
+        if depth == max_depth {
+            break;
+        }
+
+        // Emitters (meshes and environment)
+        let bsdf = &its.mesh.bsdf;
+        if !bsdf.is_smooth() {
+            let mut records = vec![];
+            if !emitters.emitters.is_empty() {
+                records.push(emitters.sample_light(
+                    &its.p,
+                    sampler.next(),
+                    sampler.next(),
+                    sampler.next2d(),
+                ));
+            }
+            if let Some(env) = &scene.emitter_environment {
+                records.push(emitters.sample_environment(
+                    env,
+                    &its.p,
+                    sampler.next(),
+                    sampler.next2d(),
+                ));
+            }
+            for record in &records {
+                let d_local = its.frame.to_local(record.d);
+                if !record.is_valid()
+                    || d_local.z <= 0.0
+                    || !accel.visible(&its.offset_toward(&record.p), &record.p)
+                {
+                    continue;
+                }
+                if let (&PDF::SolidAngle(light_pdf), PDF::SolidAngle(pdf)) = (
+                    &record.pdf,
+                    bsdf.pdf(&its.tex_coords(), &its.wi, &d_local, Domain::SolidAngle),
+                ) {
+                    let value = bsdf.eval(&its.tex_coords(), &its.wi, &d_local, Domain::SolidAngle);
+                    radiance += throughput * value * record.weight * mis_weight(light_pdf, pdf);
+                }
+            }
+        }
+
+        // Next direction
+        let sampled = match bsdf.sample(&its.tex_coords(), &its.wi, sampler.next2d()) {
+            Some(s) => s,
+            None => break,
+        };
+        throughput *= sampled.weight;
+        bsdf_pdf = match sampled.pdf {
+            PDF::SolidAngle(pdf) => Some(pdf),
+            _ => None,
+        };
+        ray = its.spawn_ray(its.frame.to_world(sampled.d));
+    }
+    (radiance, false)
+}
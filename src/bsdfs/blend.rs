@@ -8,7 +8,7 @@ pub struct BSDFBlend {
 impl BSDF for BSDFBlend {
     fn sample(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
     ) -> Option<SampledDirection> {
@@ -38,7 +38,7 @@ impl BSDF for BSDFBlend {
 
     fn pdf(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -54,7 +54,7 @@ impl BSDF for BSDFBlend {
 
     fn eval(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -62,7 +62,7 @@ impl BSDF for BSDFBlend {
         self.bsdf1.eval(uv, d_in, d_out, domain) + self.bsdf2.eval(uv, d_in, d_out, domain)
     }
 
-    fn roughness(&self, uv: &Option<Vector2<f32>>) -> f32 {
+    fn roughness(&self, uv: &TexCoords) -> f32 {
         // TODO: Use a more finer scheme when multiple component
         // BSDF will be implemented
         self.bsdf1.roughness(uv).min(self.bsdf2.roughness(uv))
@@ -81,4 +81,8 @@ impl BSDF for BSDFBlend {
         }
         true
     }
+
+    fn texture_memory(&self) -> usize {
+        self.bsdf1.texture_memory() + self.bsdf2.texture_memory()
+    }
 }
@@ -10,7 +10,7 @@ pub struct BSDFDiffuse {
 impl BSDF for BSDFDiffuse {
     fn sample(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
     ) -> Option<SampledDirection> {
@@ -28,7 +28,7 @@ impl BSDF for BSDFDiffuse {
 
     fn pdf(
         &self,
-        _uv: &Option<Vector2<f32>>,
+        _uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -47,7 +47,7 @@ impl BSDF for BSDFDiffuse {
 
     fn eval(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -64,7 +64,7 @@ impl BSDF for BSDFDiffuse {
         }
     }
 
-    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+    fn roughness(&self, _uv: &TexCoords) -> f32 {
         std::f32::INFINITY
     }
 
@@ -74,4 +74,8 @@ impl BSDF for BSDFDiffuse {
     fn is_twosided(&self) -> bool {
         true
     }
+
+    fn texture_memory(&self) -> usize {
+        self.diffuse.memory()
+    }
 }
@@ -1,8 +1,12 @@
+use crate::error::Error;
 use crate::structure::*;
+use crate::texture::udim::{UdimTexture, UDIM_TOKEN};
+use crate::texture::ptex::PtexTexture;
+use crate::texture::{parse_texture, BitmapTexture, Filter, Texture};
 use serde::{Deserialize, Deserializer};
 use serde_json;
 
-use cgmath::{InnerSpace, Point2, Vector2, Vector3};
+use cgmath::{InnerSpace, Point2, Vector3};
 #[cfg(feature = "pbrt")]
 use pbrt_rs;
 use std;
@@ -22,54 +26,68 @@ pub fn check_direlectric_condition(
     let dot_p = -wi.x * wo.x * eta - wi.y * wo.y * eta - cos_theta.copysign(wi.z) * wo.z;
     (dot_p - 1.0).abs() < 0.0001
 }
-// Texture or uniform color buffers
-#[derive(Deserialize)]
-pub struct Texture {
-    #[serde(deserialize_with = "deserialize_from_str")]
-    pub img: Bitmap,
+/// Texture or uniform color buffers
+/// JSON: {"UniformColor": {"r": 0.5, "g": 0.5, "b": 0.5}}
+/// or {"TextureColor": {"type": "bitmap", "data": {...}}} (see texture::parse_texture)
+pub enum BSDFColor {
+    UniformColor(Color),
+    TextureColor(Box<dyn Texture>),
 }
 
-impl Texture {
-    pub fn load(path: &str) -> Texture {
-        Texture {
-            img: Bitmap::read(path),
+impl<'de> Deserialize<'de> for BSDFColor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error as _;
+        let v = serde_json::Value::deserialize(deserializer)?;
+        if let Some(c) = v.get("UniformColor") {
+            Ok(BSDFColor::UniformColor(
+                serde_json::from_value(c.clone()).map_err(D::Error::custom)?,
+            ))
+        } else if let Some(t) = v.get("TextureColor") {
+            Ok(BSDFColor::TextureColor(
+                parse_texture(t).map_err(D::Error::custom)?,
+            ))
+        } else {
+            Err(D::Error::custom(format!(
+                "expected UniformColor or TextureColor: {}",
+                v
+            )))
         }
     }
-    // Access to the texture
-    pub fn pixel(&self, uv: Vector2<f32>) -> Color {
-        self.img.pixel_uv(uv)
-    }
-}
-
-#[cfg(feature = "image")]
-fn deserialize_from_str<'de, D>(deserializer: D) -> Result<Bitmap, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let _s: String = Deserialize::deserialize(deserializer)?;
-    unimplemented!();
-}
-
-#[derive(Deserialize)]
-pub enum BSDFColor {
-    UniformColor(Color),
-    TextureColor(Texture), // FIXME
 }
 
 impl BSDFColor {
-    pub fn color(&self, uv: &Option<Vector2<f32>>) -> Color {
+    /// Image texture, UDIM texture set if the path contains <UDIM> or Ptex file (.ptx)
+    pub fn texture(path: &str) -> crate::error::Result<BSDFColor> {
+        Ok(BSDFColor::TextureColor(if path.contains(UDIM_TOKEN) {
+            Box::new(UdimTexture::load(path, None, None, Filter::default())?)
+        } else if path.ends_with(".ptx") {
+            Box::new(PtexTexture::load(path)?)
+        } else {
+            Box::new(BitmapTexture::load(path)?)
+        }))
+    }
+    pub fn color(&self, uv: &TexCoords) -> Color {
         match self {
             BSDFColor::UniformColor(ref c) => *c,
             BSDFColor::TextureColor(ref t) => {
-                if let Some(uv_coords) = uv {
-                    t.pixel(*uv_coords)
-                } else {
+                // Filtered with the footprint of the camera rays (finest level otherwise)
+                t.eval_at(uv, uv.width).unwrap_or_else(|| {
                     warn!("Found a texture but no uv coordinate given");
                     Color::zero()
-                }
+                })
             }
         }
     }
+    /// Bytes of the texels (0 for the uniform colors)
+    pub fn memory(&self) -> usize {
+        match self {
+            BSDFColor::UniformColor(_) => 0,
+            BSDFColor::TextureColor(ref t) => t.memory(),
+        }
+    }
 }
 
 // Helpers
@@ -77,6 +95,14 @@ fn reflect(d: &Vector3<f32>) -> Vector3<f32> {
     Vector3::new(-d.x, -d.y, d.z)
 }
 
+/// Scattering type of a BSDF, used by the per-lobe depth limits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Glossy,
+    Specular,
+}
+
 /// Struct that represent a sampled direction
 #[derive(Clone)]
 pub struct SampledDirection {
@@ -92,14 +118,14 @@ pub trait BSDF: Send + Sync {
     /// @return: the outgoing direction, the pdf and the bsdf value $fs(...) * | n . d_out |$
     fn sample(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
     ) -> Option<SampledDirection>;
     /// eval the bsdf pdf value in solid angle
     fn pdf(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -107,18 +133,36 @@ pub trait BSDF: Send + Sync {
     /// eval the bsdf value : $fs(...)$
     fn eval(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
     ) -> Color;
     /// return the roughness of the material
-    fn roughness(&self, uv: &Option<Vector2<f32>>) -> f32;
+    fn roughness(&self, uv: &TexCoords) -> f32;
     /// check if it is smooth
     //TODO: Replace this using flags
     fn is_smooth(&self) -> bool;
     /// Used to automatically flip the normal vector
     fn is_twosided(&self) -> bool;
+    /// classify the material from its roughness (smoothest component for the blend)
+    fn lobe(&self, uv: &TexCoords) -> Lobe {
+        if self.is_smooth() {
+            Lobe::Specular
+        } else if self.roughness(uv).is_infinite() {
+            Lobe::Diffuse
+        } else {
+            Lobe::Glossy
+        }
+    }
+    /// same BSDF with a roughness of at least min_roughness (None if it is already rougher)
+    fn roughen(&self, _min_roughness: f32) -> Option<Box<dyn BSDF + '_>> {
+        None
+    }
+    /// bytes of the textures used by the BSDF (scene report)
+    fn texture_memory(&self) -> usize {
+        0
+    }
 }
 
 pub mod blend;
@@ -131,25 +175,34 @@ use crate::bsdfs::phong::BSDFPhong;
 use crate::bsdfs::specular::BSDFSpecular;
 
 /// Dispatch coded BSDF
-pub fn parse_bsdf(
-    b: &serde_json::Value,
-) -> Result<Box<dyn BSDF + Send + Sync>, Box<dyn std::error::Error>> {
+pub fn parse_bsdf(b: &serde_json::Value) -> crate::error::Result<Box<dyn BSDF + Send + Sync>> {
     let new_bsdf_type: String = serde_json::from_value(b["type"].clone())?;
     let new_bsdf: Box<dyn BSDF + Send + Sync> = match new_bsdf_type.as_ref() {
         "phong" => Box::<BSDFPhong>::new(serde_json::from_value(b["data"].clone())?),
         "diffuse" => Box::<BSDFDiffuse>::new(serde_json::from_value(b["data"].clone())?),
         "specular" => Box::<BSDFSpecular>::new(serde_json::from_value(b["data"].clone())?),
-        _ => panic!("Unknown BSDF type {}", new_bsdf_type),
+        _ => {
+            return Err(Error::InvalidData(format!(
+                "Unknown BSDF type {}",
+                new_bsdf_type
+            )))
+        }
     };
     Ok(new_bsdf)
 }
 
 #[cfg(feature = "pbrt")]
-fn bsdf_texture_match(v: &pbrt_rs::Param, scene_info: &pbrt_rs::Scene) -> Option<BSDFColor> {
-    match v {
+fn bsdf_texture_match(
+    v: &pbrt_rs::Param,
+    scene_info: &pbrt_rs::Scene,
+) -> crate::error::Result<Option<BSDFColor>> {
+    Ok(match v {
         pbrt_rs::Param::Float(ref v) => {
             if v.len() != 1 {
-                panic!("Impossible to build textureColor with: {:?}", v);
+                return Err(Error::InvalidData(format!(
+                    "Impossible to build textureColor with: {:?}",
+                    v
+                )));
             }
             let v = v[0];
             Some(BSDFColor::UniformColor(Color::new(v, v, v)))
@@ -159,14 +212,24 @@ fn bsdf_texture_match(v: &pbrt_rs::Param, scene_info: &pbrt_rs::Scene) -> Option
         }
         pbrt_rs::Param::Name(ref name) => {
             if let Some(texture) = scene_info.textures.get(name) {
-                Some(BSDFColor::TextureColor(Texture::load(&texture.filename)))
+                Some(BSDFColor::texture(&texture.filename)?)
             } else {
                 warn!("Impossible to found an texture with name: {}", name);
                 None
             }
         }
         _ => None,
-    }
+    })
+}
+
+/// Same as bsdf_texture_match but the color is mandatory
+#[cfg(feature = "pbrt")]
+fn bsdf_texture_required(
+    v: &pbrt_rs::Param,
+    scene_info: &pbrt_rs::Scene,
+) -> crate::error::Result<BSDFColor> {
+    bsdf_texture_match(v, scene_info)?
+        .ok_or_else(|| Error::InvalidData(format!("Impossible to build a color with: {:?}", v)))
 }
 
 // Debug macro for color
@@ -181,34 +244,38 @@ fn bsdf_texture_match(v: &pbrt_rs::Param, scene_info: &pbrt_rs::Scene) -> Option
 // }
 
 #[cfg(feature = "pbrt")]
-pub fn bsdf_pbrt(bsdf: &pbrt_rs::BSDF, scene_info: &pbrt_rs::Scene) -> Box<dyn BSDF + Sync + Send> {
+pub fn bsdf_pbrt(
+    bsdf: &pbrt_rs::BSDF,
+    scene_info: &pbrt_rs::Scene,
+) -> crate::error::Result<Box<dyn BSDF + Sync + Send>> {
     let bsdf: Option<Box<dyn BSDF + Sync + Send>> = match bsdf {
         pbrt_rs::BSDF::Matte(ref v) => {
-            if let Some(diffuse) = bsdf_texture_match(&v.kd, scene_info) {
+            if let Some(diffuse) = bsdf_texture_match(&v.kd, scene_info)? {
                 Some(Box::new(BSDFDiffuse { diffuse }))
             } else {
                 None
             }
         }
         pbrt_rs::BSDF::Metal(ref v) => {
-            let _eta = bsdf_texture_match(&v.eta, scene_info).unwrap();
-            let _k = bsdf_texture_match(&v.k, scene_info).unwrap();
+            let _eta = bsdf_texture_required(&v.eta, scene_info)?;
+            let _k = bsdf_texture_required(&v.k, scene_info)?;
             let (u_roughness, v_roughness) = if let (Some(ref u_rough), Some(ref v_rough)) =
                 (v.u_roughness.as_ref(), v.v_roughness.as_ref())
             {
                 (
-                    bsdf_texture_match(u_rough, scene_info).unwrap(),
-                    bsdf_texture_match(v_rough, scene_info).unwrap(),
+                    bsdf_texture_required(u_rough, scene_info)?,
+                    bsdf_texture_required(v_rough, scene_info)?,
                 )
             } else {
                 (
-                    bsdf_texture_match(&v.roughness, scene_info).unwrap(),
-                    bsdf_texture_match(&v.roughness, scene_info).unwrap(),
+                    bsdf_texture_required(&v.roughness, scene_info)?,
+                    bsdf_texture_required(&v.roughness, scene_info)?,
                 )
             };
             // FIXME: be able to load float textures?
+            let none = TexCoords::default();
             let (u_roughness, v_roughness) =
-                (u_roughness.color(&None).r, v_roughness.color(&None).r);
+                (u_roughness.color(&none).r, v_roughness.color(&none).r);
             assert!(u_roughness != 0.0);
             assert!(v_roughness != 0.0);
             // FIXME: remap
@@ -222,17 +289,18 @@ pub fn bsdf_pbrt(bsdf: &pbrt_rs::BSDF, scene_info: &pbrt_rs::Scene) -> Box<dyn B
             unimplemented!();
         }
         pbrt_rs::BSDF::Mirror(ref v) => {
-            let specular = bsdf_texture_match(&v.kr, scene_info).unwrap();
+            let specular = bsdf_texture_required(&v.kr, scene_info)?;
             Some(Box::new(BSDFSpecular { specular }))
         }
         pbrt_rs::BSDF::Substrate(ref v) => {
-            let _kd = bsdf_texture_match(&v.kd, scene_info).unwrap();
-            let _ks = bsdf_texture_match(&v.ks, scene_info).unwrap();
-            let u_roughness = bsdf_texture_match(&v.u_roughness, scene_info).unwrap();
-            let v_roughness = bsdf_texture_match(&v.v_roughness, scene_info).unwrap();
+            let _kd = bsdf_texture_required(&v.kd, scene_info)?;
+            let _ks = bsdf_texture_required(&v.ks, scene_info)?;
+            let u_roughness = bsdf_texture_required(&v.u_roughness, scene_info)?;
+            let v_roughness = bsdf_texture_required(&v.v_roughness, scene_info)?;
             // FIXME: be able to load float textures?
+            let none = TexCoords::default();
             let (u_roughness, v_roughness) =
-                (u_roughness.color(&None).r, v_roughness.color(&None).r);
+                (u_roughness.color(&none).r, v_roughness.color(&none).r);
             assert!(u_roughness != 0.0);
             assert!(v_roughness != 0.0);
 
@@ -253,11 +321,11 @@ pub fn bsdf_pbrt(bsdf: &pbrt_rs::BSDF, scene_info: &pbrt_rs::Scene) -> Box<dyn B
         _ => None,
     };
 
-    if let Some(bsdf) = bsdf {
+    Ok(if let Some(bsdf) = bsdf {
         bsdf
     } else {
         Box::new(BSDFDiffuse {
             diffuse: BSDFColor::UniformColor(Color::value(0.8)),
         })
-    }
+    })
 }
@@ -9,10 +9,92 @@ pub struct BSDFPhong {
     pub exponent: f32,
 }
 
+/// Phong lobe with a borrowed color, also used to roughen the BSDFs
+pub struct PhongLobe<'a> {
+    pub specular: &'a BSDFColor,
+    pub exponent: f32,
+}
+
+/// Phong exponent for a given roughness (inverse of BSDF::roughness)
+pub fn phong_exponent(roughness: f32) -> f32 {
+    (2.0 / (roughness * roughness) - 2.0).max(0.0)
+}
+
+/// Phong lobe with an exponent decreased to reach the minimum roughness
+fn roughen(specular: &BSDFColor, exponent: f32, min_roughness: f32) -> Option<Box<dyn BSDF + '_>> {
+    if exponent <= phong_exponent(min_roughness) {
+        None
+    } else {
+        Some(Box::new(PhongLobe {
+            specular,
+            exponent: phong_exponent(min_roughness),
+        }))
+    }
+}
+
+impl BSDFPhong {
+    fn phong(&self) -> PhongLobe<'_> {
+        PhongLobe {
+            specular: &self.specular,
+            exponent: self.exponent,
+        }
+    }
+}
+
 impl BSDF for BSDFPhong {
     fn sample(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
+        d_in: &Vector3<f32>,
+        sample: Point2<f32>,
+    ) -> Option<SampledDirection> {
+        self.phong().sample(uv, d_in, sample)
+    }
+
+    fn pdf(
+        &self,
+        uv: &TexCoords,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+    ) -> PDF {
+        self.phong().pdf(uv, d_in, d_out, domain)
+    }
+
+    fn eval(
+        &self,
+        uv: &TexCoords,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+    ) -> Color {
+        self.phong().eval(uv, d_in, d_out, domain)
+    }
+
+    fn roughness(&self, uv: &TexCoords) -> f32 {
+        self.phong().roughness(uv)
+    }
+
+    fn is_smooth(&self) -> bool {
+        false
+    }
+    fn is_twosided(&self) -> bool {
+        true
+    }
+
+    fn roughen(&self, min_roughness: f32) -> Option<Box<dyn BSDF + '_>> {
+        roughen(&self.specular, self.exponent, min_roughness)
+    }
+
+    fn texture_memory(&self) -> usize {
+        self.specular.memory()
+    }
+}
+
+impl<'a> BSDF for PhongLobe<'a> {
+    fn sample(
+        &self,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
     ) -> Option<SampledDirection> {
@@ -41,7 +123,7 @@ impl BSDF for BSDFPhong {
 
     fn pdf(
         &self,
-        _uv: &Option<Vector2<f32>>,
+        _uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -68,7 +150,7 @@ impl BSDF for BSDFPhong {
 
     fn eval(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
@@ -84,7 +166,7 @@ impl BSDF for BSDFPhong {
             let alpha = reflect(d_in).dot(*d_out);
             if alpha > 0.0 {
                 self.specular.color(uv)
-                    * (alpha.powf(self.exponent) * (self.exponent + 2.0)
+                    * (alpha.powf(self.exponent) * (self.exponent + 2.0) * d_out.z
                         / (2.0 * std::f32::consts::PI))
             } else {
                 Color::zero()
@@ -92,7 +174,7 @@ impl BSDF for BSDFPhong {
         }
     }
 
-    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+    fn roughness(&self, _uv: &TexCoords) -> f32 {
         (2.0 / (2.0 + self.exponent)).sqrt()
     }
 
@@ -102,4 +184,8 @@ impl BSDF for BSDFPhong {
     fn is_twosided(&self) -> bool {
         true
     }
+
+    fn roughen(&self, min_roughness: f32) -> Option<Box<dyn BSDF + '_>> {
+        roughen(self.specular, self.exponent, min_roughness)
+    }
 }
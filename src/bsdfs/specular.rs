@@ -1,3 +1,4 @@
+use crate::bsdfs::phong::{phong_exponent, PhongLobe};
 use crate::bsdfs::*;
 
 #[derive(Deserialize)]
@@ -8,7 +9,7 @@ pub struct BSDFSpecular {
 impl BSDF for BSDFSpecular {
     fn sample(
         &self,
-        uv: &Option<Vector2<f32>>,
+        uv: &TexCoords,
         d_in: &Vector3<f32>,
         _: Point2<f32>,
     ) -> Option<SampledDirection> {
@@ -23,13 +24,7 @@ impl BSDF for BSDFSpecular {
         }
     }
 
-    fn pdf(
-        &self,
-        _uv: &Option<Vector2<f32>>,
-        wi: &Vector3<f32>,
-        wo: &Vector3<f32>,
-        domain: Domain,
-    ) -> PDF {
+    fn pdf(&self, _uv: &TexCoords, wi: &Vector3<f32>, wo: &Vector3<f32>, domain: Domain) -> PDF {
         assert!(domain == Domain::Discrete);
         if check_reflection_condition(wi, wo) {
             PDF::Discrete(1.0)
@@ -39,13 +34,7 @@ impl BSDF for BSDFSpecular {
         }
     }
 
-    fn eval(
-        &self,
-        uv: &Option<Vector2<f32>>,
-        wi: &Vector3<f32>,
-        wo: &Vector3<f32>,
-        domain: Domain,
-    ) -> Color {
+    fn eval(&self, uv: &TexCoords, wi: &Vector3<f32>, wo: &Vector3<f32>, domain: Domain) -> Color {
         assert!(domain == Domain::Discrete);
         if check_reflection_condition(wi, wo) {
             self.specular.color(uv)
@@ -55,7 +44,7 @@ impl BSDF for BSDFSpecular {
         }
     }
 
-    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+    fn roughness(&self, _uv: &TexCoords) -> f32 {
         0.0
     }
 
@@ -65,4 +54,18 @@ impl BSDF for BSDFSpecular {
     fn is_twosided(&self) -> bool {
         true
     }
+
+    fn texture_memory(&self) -> usize {
+        self.specular.memory()
+    }
+
+    fn roughen(&self, min_roughness: f32) -> Option<Box<dyn BSDF + '_>> {
+        if min_roughness <= 0.0 {
+            return None;
+        }
+        Some(Box::new(PhongLobe {
+            specular: &self.specular,
+            exponent: phong_exponent(min_roughness),
+        }))
+    }
 }
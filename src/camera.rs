@@ -1,10 +1,173 @@
+use crate::bake::TextureSpace;
+use crate::error::{Error, Result};
+use crate::lens_system::LensSystem;
+use crate::samplers::{dimensions, Sampler};
 use crate::structure::{Color, Ray};
 use cgmath::*;
 use std::f32;
+use std::sync::Arc;
 
+/// Projection used for each eye of a stereo camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoProjection {
+    /// Two parallel perspective cameras (same field of view as the camera)
+    Perspective,
+    /// Omni-directional stereo: one equirectangular panorama per eye
+    /// (the eyes turn on a circle around the camera position for VR viewers)
+    ODS,
+}
+
+impl std::str::FromStr for StereoProjection {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "perspective" => Ok(StereoProjection::Perspective),
+            "ods" => Ok(StereoProjection::ODS),
+            _ => Err(format!(
+                "unknown stereo projection: {} (perspective, ods)",
+                s
+            )),
+        }
+    }
+}
+
+/// Position of the two eyes inside the output image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left
+    SideBySide,
+    /// Left eye on the top
+    OverUnder,
+}
+
+impl std::str::FromStr for StereoLayout {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sbs" => Ok(StereoLayout::SideBySide),
+            "ou" => Ok(StereoLayout::OverUnder),
+            _ => Err(format!("unknown stereo layout: {} (sbs, ou)", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Stereo {
+    pub projection: StereoProjection,
+    pub layout: StereoLayout,
+    /// Distance between the two eyes (scene units)
+    pub interocular: f32,
+}
+
+impl Stereo {
+    /// Image size of one eye
+    fn eye_size(&self, img: Vector2<u32>) -> Vector2<u32> {
+        match self.layout {
+            StereoLayout::SideBySide => Vector2::new(img.x / 2, img.y),
+            StereoLayout::OverUnder => Vector2::new(img.x, img.y / 2),
+        }
+    }
+
+    /// Offset of the eye (-0.5 left, 0.5 right) and pixel position inside its image
+    fn eye(&self, px: Point2<f32>, img: Vector2<u32>) -> (f32, Point2<f32>) {
+        let size = self.eye_size(img);
+        match self.layout {
+            StereoLayout::SideBySide if px.x >= size.x as f32 => {
+                (0.5, Point2::new(px.x - size.x as f32, px.y))
+            }
+            StereoLayout::OverUnder if px.y >= size.y as f32 => {
+                (0.5, Point2::new(px.x, px.y - size.y as f32))
+            }
+            _ => (-0.5, px),
+        }
+    }
+}
+
+/// Radial distortion of the perspective cameras (Brown-Conrady, same coefficients as OpenCV):
+/// the undistorted point p of the image plane (at distance 1) is seen at p (1 + k1 r^2 + k2 r^4)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadialDistortion {
+    pub k1: f32,
+    pub k2: f32,
+    /// Undistorted radius of the image corners
+    max_radius: f32,
+}
+
+impl RadialDistortion {
+    /// Distorted radius of the undistorted radius r
+    fn distort(&self, r: f32) -> f32 {
+        let r2 = r * r;
+        r * (1.0 + r2 * (self.k1 + self.k2 * r2))
+    }
+    fn derivative(&self, r: f32) -> f32 {
+        let r2 = r * r;
+        1.0 + r2 * (3.0 * self.k1 + 5.0 * self.k2 * r2)
+    }
+
+    /// Undistorted radius (Newton iterations kept inside [0, max_radius])
+    fn undistort(&self, rd: f32) -> f32 {
+        let (mut lo, mut hi) = (0.0, self.max_radius);
+        let mut r = rd.min(hi);
+        for _ in 0..32 {
+            let f = self.distort(r) - rd;
+            if f.abs() < 1e-7 * rd.max(1.0) {
+                break;
+            }
+            if f > 0.0 {
+                hi = r;
+            } else {
+                lo = r;
+            }
+            r -= f / self.derivative(r);
+            if r <= lo || r >= hi {
+                r = 0.5 * (lo + hi);
+            }
+        }
+        r
+    }
+
+    /// Ratio between the areas of the distorted and undistorted image planes at the radius r
+    fn jacobian(&self, r: f32) -> f32 {
+        let r2 = r * r;
+        self.derivative(r) * (1.0 + r2 * (self.k1 + self.k2 * r2))
+    }
+}
+
+/// Rays through the next pixels (+1 along x and y), to estimate the footprint of a camera ray
+#[derive(Clone, Copy)]
+pub struct RayDifferentials {
+    pub dx: Ray,
+    pub dy: Ray,
+}
+
+/// Camera ray with the information the integrators can need beside the ray itself
+#[derive(Clone, Copy)]
+pub struct CameraSample {
+    pub ray: Ray,
+    pub differentials: RayDifferentials,
+    /// Time inside the shutter interval [0, 1] (ignored by the static cameras)
+    pub time: f32,
+    /// Position on the lens in [0, 1]^2 (ignored by the pinhole cameras)
+    pub lens_uv: Point2<f32>,
+    /// Solid angle density of the ray direction, inside the image of its eye for the stereo
+    /// cameras (the importance We of the pinhole camera, 0 for the lens systems)
+    pub we_pdf: f32,
+    /// Factor of the radiance carried by the ray: 1 for the pinhole cameras,
+    /// the vignetting of the lens systems (0 if the lens blocks the ray)
+    pub weight: f32,
+}
+
+#[derive(Clone)]
 pub struct Camera {
+    /// Size of the output image (both eyes for a stereo camera)
     pub img: Vector2<u32>,
     pub fov: f32, //< y
+    stereo: Option<Stereo>,
+    distortion: Option<RadialDistortion>,
+    /// Texture space film: the pixels are the texels of a mesh (lightmap baking)
+    texture_space: Option<Arc<TextureSpace>>,
+    /// Lens elements between the film and the scene (replace the pinhole and the fov)
+    lens_system: Option<Arc<LensSystem>>,
     // Internally
     camera_to_sample: Matrix4<f32>,
     sample_to_camera: Matrix4<f32>,
@@ -39,6 +202,10 @@ impl Camera {
         Camera {
             img,
             fov,
+            stereo: None,
+            distortion: None,
+            texture_space: None,
+            lens_system: None,
             camera_to_sample,
             sample_to_camera,
             to_world,
@@ -52,6 +219,78 @@ impl Camera {
         &self.img
     }
 
+    /// Render both eyes in the same image: the image size becomes
+    /// the size of one eye doubled along the layout direction.
+    /// The splatting (sample_direct) is not supported by the stereo cameras.
+    pub fn set_stereo(&mut self, stereo: Stereo) {
+        assert!(self.stereo.is_none(), "the camera is already stereo");
+        assert!(self.lens_system.is_none(), "the stereo cameras are pinholes");
+        self.img = match stereo.layout {
+            StereoLayout::SideBySide => Vector2::new(self.img.x * 2, self.img.y),
+            StereoLayout::OverUnder => Vector2::new(self.img.x, self.img.y * 2),
+        };
+        self.stereo = Some(stereo);
+    }
+
+    pub fn stereo(&self) -> Option<&Stereo> {
+        self.stereo.as_ref()
+    }
+
+    /// Distort the image of the perspective camera (also the eyes of the perspective stereo).
+    /// The distortion must be monotonic up to the image corners: each pixel sees one direction.
+    pub fn set_distortion(&mut self, k1: f32, k2: f32) -> Result<()> {
+        let corner = self.image_rect_min.to_vec().magnitude();
+        let corner = corner.max(self.image_rect_max.to_vec().magnitude());
+        let mut distortion = RadialDistortion {
+            k1,
+            k2,
+            max_radius: 0.0,
+        };
+        // Undistorted radius of the corners, while checking that the distortion increases
+        let step = corner / 1024.0;
+        let mut r = 0.0;
+        while distortion.distort(r) < corner {
+            if distortion.derivative(r) <= 0.0 || r > 1024.0 * corner {
+                return Err(Error::InvalidData(format!(
+                    "the distortion k1 = {}, k2 = {} folds the image before its corners",
+                    k1, k2
+                )));
+            }
+            r += step;
+        }
+        distortion.max_radius = r;
+        distortion.max_radius = distortion.undistort(corner);
+        self.distortion = Some(distortion);
+        Ok(())
+    }
+
+    pub fn distortion(&self) -> Option<&RadialDistortion> {
+        self.distortion.as_ref()
+    }
+
+    /// Render the texels of a mesh instead of the pixels: the image size becomes
+    /// the texture size. The splatting (sample_direct) is not supported either.
+    pub fn set_texture_space(&mut self, texture_space: Arc<TextureSpace>) {
+        self.img = texture_space.size;
+        self.texture_space = Some(texture_space);
+    }
+
+    pub fn texture_space(&self) -> Option<&TextureSpace> {
+        self.texture_space.as_deref()
+    }
+
+    /// Trace the camera rays through the lens elements: the field of view and
+    /// the distortion of the camera are replaced by the ones of the lens.
+    /// The splatting (sample_direct) is not supported either.
+    pub fn set_lens_system(&mut self, lens_system: Arc<LensSystem>) {
+        assert!(self.stereo.is_none(), "the stereo cameras are pinholes");
+        self.lens_system = Some(lens_system);
+    }
+
+    pub fn lens_system(&self) -> Option<&LensSystem> {
+        self.lens_system.as_deref()
+    }
+
     pub fn scale_image(&mut self, s: f32) {
         self.img = Vector2::new(
             (s * self.img.x as f32) as u32,
@@ -59,27 +298,181 @@ impl Camera {
         );
     }
 
-    /// Compute the ray direction going through the pixel passed
-    pub fn generate(&self, px: Point2<f32>) -> Ray {
+    /// Camera ray going through the pixel position passed
+    /// (center of the lens at the beginning of the shutter interval)
+    pub fn generate(&self, px: Point2<f32>) -> CameraSample {
+        self.sample(px, Point2::new(0.5, 0.5), 0.0)
+    }
+
+    /// Camera ray going through the pixel position passed, with a random point of the lens
+    /// for the lens systems (dimensions::LENS). The other cameras use the center of the lens
+    /// and do not consume random numbers.
+    pub fn sample_lens(&self, px: Point2<f32>, sampler: &mut dyn Sampler) -> CameraSample {
+        let lens_uv = match self.lens_system {
+            Some(_) => sampler.next2d_dim(dimensions::LENS),
+            None => Point2::new(0.5, 0.5),
+        };
+        self.sample(px, lens_uv, 0.0)
+    }
+
+    /// Camera ray going through the pixel position passed, from the point lens_uv
+    /// of the lens at the given time
+    pub fn sample(&self, px: Point2<f32>, lens_uv: Point2<f32>, time: f32) -> CameraSample {
+        if let Some(texture_space) = &self.texture_space {
+            return texture_space.sample(px, lens_uv, time);
+        }
+        if let Some(lens_system) = &self.lens_system {
+            let (ray, weight) = self.lens_ray(lens_system, px, lens_uv);
+            let dx = self.lens_ray(lens_system, px + Vector2::new(1.0, 0.0), lens_uv).0;
+            let dy = self.lens_ray(lens_system, px + Vector2::new(0.0, 1.0), lens_uv).0;
+            return CameraSample {
+                ray,
+                differentials: RayDifferentials { dx, dy },
+                time,
+                lens_uv,
+                we_pdf: 0.0,
+                weight,
+            };
+        }
+        let (eye, px, size) = match self.stereo {
+            None => (0.0, px, self.img),
+            Some(ref stereo) => {
+                let (eye, px) = stereo.eye(px, self.img);
+                (eye, px, stereo.eye_size(self.img))
+            }
+        };
+        let (ray, we_pdf) = self.eye_ray(eye, px, size);
+        let (dx, _) = self.eye_ray(eye, px + Vector2::new(1.0, 0.0), size);
+        let (dy, _) = self.eye_ray(eye, px + Vector2::new(0.0, 1.0), size);
+        CameraSample {
+            ray,
+            differentials: RayDifferentials { dx, dy },
+            time,
+            lens_uv,
+            we_pdf,
+            weight: 1.0,
+        }
+    }
+
+    /// Ray leaving the lens system from the film point of the pixel position and its weight
+    /// (the ray starts on the film with tfar = 0 if the lens blocks it)
+    fn lens_ray(
+        &self,
+        lens_system: &LensSystem,
+        px: Point2<f32>,
+        lens_uv: Point2<f32>,
+    ) -> (Ray, f32) {
+        // The image is upside down on the film
+        let extent = lens_system.film_extent(self.img);
+        let p_film = Point2::new(
+            (0.5 - px.x / self.img.x as f32) * extent.x,
+            (px.y / self.img.y as f32 - 0.5) * extent.y,
+        );
+        match lens_system.sample(p_film, lens_uv) {
+            Some((o, d, weight)) => {
+                let ray = Ray::new(
+                    self.to_world.transform_point(o),
+                    self.to_world.transform_vector(d),
+                );
+                (ray, weight)
+            }
+            None => {
+                let o = Point3::new(p_film.x, p_film.y, 0.0);
+                let mut ray = Ray::new(
+                    self.to_world.transform_point(o),
+                    self.to_world.transform_vector(Vector3::unit_z()),
+                );
+                ray.tfar = 0.0;
+                (ray, 0.0)
+            }
+        }
+    }
+
+    /// Ray of the eye (-0.5 left, 0.5 right, 0 without stereo) through the pixel position
+    /// inside the image of this eye, with the solid angle density of its direction
+    fn eye_ray(&self, eye: f32, px: Point2<f32>, size: Vector2<u32>) -> (Ray, f32) {
+        let interocular = self.stereo.map_or(0.0, |s| s.interocular);
+        match self.stereo.map(|s| s.projection) {
+            None | Some(StereoProjection::Perspective) => {
+                let o = Point3::new(eye * interocular, 0.0, 0.0);
+                let (d, jacobian) = self.local_direction(px, size);
+                let ray = Ray::new(
+                    self.to_world.transform_point(o),
+                    self.to_world.transform_vector(d),
+                );
+                (ray, jacobian / (self.image_area() * d.z.powi(3)))
+            }
+            Some(StereoProjection::ODS) => {
+                // Equirectangular (longitude, latitude) with the forward direction
+                // at the center and the eye tangent to the viewing circle
+                let theta = (px.x / size.x as f32 - 0.5) * 2.0 * f32::consts::PI;
+                let phi = (0.5 - px.y / size.y as f32) * f32::consts::PI;
+                let d = Vector3::new(theta.sin() * phi.cos(), phi.sin(), theta.cos() * phi.cos());
+                let right = Vector3::new(theta.cos(), 0.0, -theta.sin());
+                let o = Point3::from_vec(right * eye * interocular);
+                let ray = Ray::new(
+                    self.to_world.transform_point(o),
+                    self.to_world.transform_vector(d),
+                );
+                // Uniform density over the (longitude, latitude) rectangle
+                let pi = f32::consts::PI;
+                (ray, 1.0 / (2.0 * pi * pi * phi.cos()))
+            }
+        }
+    }
+
+    /// Local direction of the perspective camera (image of the given size)
+    /// and the jacobian of the distortion at the undistorted point of the image plane
+    fn local_direction(&self, px: Point2<f32>, size: Vector2<u32>) -> (Vector3<f32>, f32) {
         let near_p = self.sample_to_camera.transform_point(Point3::new(
-            px.x / (self.img.x as f32),
-            px.y / (self.img.y as f32),
+            px.x / (size.x as f32),
+            px.y / (size.y as f32),
             0.0,
         ));
-        let d = near_p.to_vec().normalize();
-        // info!("d: {:?}",  self.to_world.transform_vector(d));
+        match self.distortion {
+            None => (near_p.to_vec().normalize(), 1.0),
+            Some(ref distortion) => {
+                let p = Vector2::new(near_p.x / near_p.z, near_p.y / near_p.z);
+                let rd = p.magnitude();
+                let r = distortion.undistort(rd);
+                let p = if rd > 0.0 { p * (r / rd) } else { p };
+                (p.extend(1.0).normalize(), distortion.jacobian(r))
+            }
+        }
+    }
 
-        Ray::new(self.position(), self.to_world.transform_vector(d))
+    /// Area of the image plane at distance 1 from the pinhole
+    fn image_area(&self) -> f32 {
+        (self.image_rect_max.x - self.image_rect_min.x)
+            * (self.image_rect_max.y - self.image_rect_min.y)
     }
 
     /// Method to splat a given sample on the camera
     pub fn sample_direct(&self, p: &Point3<f32>) -> Option<(Color, Point2<f32>)> {
+        if self.stereo.is_some() || self.texture_space.is_some() || self.lens_system.is_some() {
+            return None;
+        }
         let ref_p = self.to_local.transform_point(*p);
         if ref_p.z < 0.0 {
             return None;
         }
 
-        let screen_pos = self.camera_to_sample.transform_point(ref_p);
+        // Point of the image plane seen in this direction
+        let plane_p = Point2::new(ref_p.x / ref_p.z, ref_p.y / ref_p.z);
+        let (plane_p, jacobian) = match self.distortion {
+            None => (plane_p, 1.0),
+            Some(ref distortion) => {
+                let r = plane_p.to_vec().magnitude();
+                if r > distortion.max_radius {
+                    return None;
+                }
+                let s = if r > 0.0 { distortion.distort(r) / r } else { 1.0 };
+                (plane_p * s, distortion.jacobian(r))
+            }
+        };
+        let screen_pos = self
+            .camera_to_sample
+            .transform_point(Point3::new(plane_p.x, plane_p.y, 1.0));
         if screen_pos.x < 0.0 || screen_pos.x > 1.0 || screen_pos.y < 0.0 || screen_pos.y > 1.0 {
             return None;
         }
@@ -91,7 +484,7 @@ impl Camera {
         let inv_dist = 1.0 / local_d.magnitude();
         local_d *= inv_dist;
 
-        let importance = self.importance(local_d);
+        let importance = self.importance(local_d, plane_p) * jacobian;
         if importance == 0.0 {
             None
         } else {
@@ -99,24 +492,22 @@ impl Camera {
         }
     }
 
-    fn importance(&self, d: Vector3<f32>) -> f32 {
+    /// Importance of the direction d seeing the point p of the (distorted) image plane
+    fn importance(&self, d: Vector3<f32>, p: Point2<f32>) -> f32 {
         let cos_theta = d.z;
         if cos_theta <= 0.0 {
             return 0.0;
         }
         let inv_cos_theta = 1.0 / cos_theta;
-        let p = Point2::new(d.x * inv_cos_theta, d.y * inv_cos_theta);
         if p.x < self.image_rect_min.x
             || p.x > self.image_rect_max.x
             || p.y < self.image_rect_min.y
-            || p.x > self.image_rect_max.y
+            || p.y > self.image_rect_max.y
         {
             return 0.0;
         }
 
-        let size = (self.image_rect_max.x - self.image_rect_min.x)
-            * (self.image_rect_max.y - self.image_rect_min.y);
-        (1.0 / size as f32) * inv_cos_theta * inv_cos_theta * inv_cos_theta
+        (1.0 / self.image_area()) * inv_cos_theta * inv_cos_theta * inv_cos_theta
     }
 
     pub fn position(&self) -> Point3<f32> {
@@ -125,7 +516,7 @@ impl Camera {
 
     pub fn print_info(&self) {
         let pix = Point2::new(self.img.x as f32 * 0.5 + 0.5, self.img.y as f32 * 0.5 + 0.5);
-        let view_dir = self.generate(pix).d;
+        let view_dir = self.generate(pix).ray.d;
         info!(" - Position: {:?}", self.position());
         info!(" - View direction: {:?}", view_dir);
     }
@@ -0,0 +1,1566 @@
+//! Command line interface of the `rustlight` binary.
+//!
+//! A program with its own integrators registers them in an IntegratorManager
+//! and calls `run`: they get the same options as the built-in ones.
+
+use crate::config::RenderConfig;
+use crate::integrators::registry::IntegratorManager;
+use crate::integrators::IntegratorType;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+/// None for "inf", otherwise the value (a wrong value stops the program)
+fn match_infinity<T: std::str::FromStr>(input: &str) -> Option<T> {
+    match input {
+        "inf" => None,
+        _ => match input.parse::<T>() {
+            Ok(x) => Some(x),
+            Err(_e) => {
+                error!("Wrong value: {} (inf or a number)", input);
+                std::process::exit(1)
+            }
+        },
+    }
+}
+
+/// Print the error and stop the program
+fn exit_on_error<T, E: std::fmt::Display>(r: Result<T, E>, context: &str) -> T {
+    r.unwrap_or_else(|e| {
+        error!("{}: {}", context, e);
+        std::process::exit(1)
+    })
+}
+
+/// Add the integrators (built-in and registered) as subcommands.
+/// The argument names are also their long names
+/// to be able to give them inside the configuration file.
+fn integrator_subcommands<'a, 'b>(
+    app: App<'a, 'b>,
+    integrators: &'a IntegratorManager,
+) -> App<'a, 'b> {
+    let registered_integrators = integrators
+        .factories()
+        .into_iter()
+        .map(|(name, factory)| {
+            factory.params().into_iter().fold(
+                SubCommand::with_name(name).about(factory.about()),
+                |subcommand, p| {
+                    let mut arg = Arg::with_name(p.name).long(p.name).help(p.help);
+                    if let Some(short) = p.short {
+                        arg = arg.short(short);
+                    }
+                    subcommand.arg(match (p.flag, p.default) {
+                        (true, _) => arg,
+                        (false, Some(v)) => arg.takes_value(true).default_value(v),
+                        (false, None) => arg.takes_value(true).required(true),
+                    })
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    app.subcommands(registered_integrators)
+}
+
+/// Parse the command line and run the subcommand.
+/// The custom integrators of the manager are available as render subcommands,
+/// and to the configuration files, the render server and the datasets.
+pub fn run(integrators: &IntegratorManager) {
+    let render_subcommand =
+        SubCommand::with_name("render")
+            .about("render a scene")
+            .arg(
+                Arg::with_name("config")
+                    .takes_value(true)
+                    .short("c")
+                    .long("config")
+                    .help("TOML render configuration (overridden by the command line)"),
+            )
+            .arg(
+                Arg::with_name("scene")
+                    .takes_value(true)
+                    .index(1)
+                    .help("JSON or PBRT file description"),
+            )
+            .arg(Arg::with_name("average").short("a").takes_value(true).help(
+                "average several pass of the integrator with a time limit ('inf' is possible)",
+            ))
+            .arg(
+                Arg::with_name("nbthreads")
+                    .takes_value(true)
+                    .allow_hyphen_values(true)
+                    .short("t")
+                    .help("number of thread for the computation [default: auto]"),
+            )
+            .arg(
+                Arg::with_name("pin_threads")
+                    .long("pin-threads")
+                    .takes_value(true)
+                    .help("pin the threads to the cores: none, cores or a list of core ids"),
+            )
+            .arg(
+                Arg::with_name("tile_size")
+                    .long("tile-size")
+                    .takes_value(true)
+                    .help("size of the rendering blocks in pixels [default: auto]"),
+            )
+            .arg(
+                Arg::with_name("image_scale")
+                    .takes_value(true)
+                    .short("s")
+                    .help("image scaling factor [default: 1.0]"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .takes_value(true)
+                    .short("o")
+                    .help("output image file [default: <scene name>.pfm]"),
+            )
+            .arg(
+                Arg::with_name("medium")
+                    .short("m")
+                    .help("add a test medium"),
+            )
+            .arg(
+                Arg::with_name("variance")
+                    .short("v")
+                    .help("output per-pixel variance and spp buffers"),
+            )
+            .arg(
+                Arg::with_name("nbsamples")
+                    .short("n")
+                    .takes_value(true)
+                    .help("number of samples per pixel"),
+            )
+            .arg(
+                Arg::with_name("light_selection")
+                    .long("light-selection")
+                    .takes_value(true)
+                    .help("emitter selection: uniform, power, bvh or grid [default: power]"),
+            )
+            .arg(
+                Arg::with_name("env_guiding")
+                    .long("env-guiding")
+                    .takes_value(true)
+                    .value_name("spp")
+                    .help("learn the environment sampling with spp camera rays per pixel"),
+            )
+            .arg(Arg::with_name("film").long("film").takes_value(true).help(
+                "opaque, transparent (alpha channel) or a background image [default: opaque]",
+            ))
+            .arg(
+                Arg::with_name("physical_exposure")
+                    .long("physical-exposure")
+                    .takes_value(true)
+                    .number_of_values(3)
+                    .value_names(&["iso", "shutter", "f_number"])
+                    .help("expose the radiance (cd/m^2) like a camera (shutter in seconds)"),
+            )
+            .arg(
+                Arg::with_name("response")
+                    .long("response")
+                    .takes_value(true)
+                    .help("camera response curve of the LDR outputs (1D .cube or text LUT)"),
+            )
+            .arg(
+                Arg::with_name("rejection")
+                    .long("rejection")
+                    .takes_value(true)
+                    .help("confidence of the per-pixel outlier rejection (e.g., 0.99)"),
+            )
+            .arg(
+                Arg::with_name("check_nan")
+                    .long("check-nan")
+                    .takes_value(true)
+                    .help("log or discard (and log) the NaN and infinite contributions"),
+            )
+            .arg(
+                Arg::with_name("debug_pixel")
+                    .long("debug-pixel")
+                    .takes_value(true)
+                    .number_of_values(2)
+                    .value_names(&["x", "y"])
+                    .help("save the paths of this pixel (<output>_paths.json and .obj)"),
+            )
+            .arg(
+                Arg::with_name("stereo")
+                    .long("stereo")
+                    .takes_value(true)
+                    .help("stereo rendering: perspective or ods (omni-directional stereo)"),
+            )
+            .arg(
+                Arg::with_name("stereo_layout")
+                    .long("stereo-layout")
+                    .takes_value(true)
+                    .help(
+                        "eyes inside the image: sbs or ou [default: sbs (perspective), ou (ods)]",
+                    ),
+            )
+            .arg(
+                Arg::with_name("interocular")
+                    .long("interocular")
+                    .takes_value(true)
+                    .help("distance between the eyes [default: 0.064]"),
+            )
+            .arg(
+                Arg::with_name("lightmap")
+                    .long("lightmap")
+                    .takes_value(true)
+                    .help("bake the lightmap of this mesh (texels of its texture coordinates)"),
+            )
+            .arg(
+                Arg::with_name("lightmap_size")
+                    .long("lightmap-size")
+                    .takes_value(true)
+                    .number_of_values(2)
+                    .value_names(&["width", "height"])
+                    .help("size of the lightmap [default: 512 512]"),
+            )
+            .arg(
+                Arg::with_name("vertex_colors")
+                    .long("vertex-colors")
+                    .takes_value(true)
+                    .help("bake the vertex colors of this mesh (PLY or OBJ output)"),
+            )
+            .arg(
+                Arg::with_name("camera")
+                    .long("camera")
+                    .takes_value(true)
+                    .help("named camera of the scene or all (one output per camera)"),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .help("seed of the samplers, hashed with the pass index [default: random]"),
+            )
+            .arg(
+                Arg::with_name("lock_passes")
+                    .long("lock-passes")
+                    .help("all the passes use the same random numbers (debugging)"),
+            )
+            .arg(
+                Arg::with_name("deterministic")
+                    .long("deterministic")
+                    .help("same image whatever the number of threads [default seed: 0]"),
+            )
+            .arg(
+                Arg::with_name("watch")
+                    .long("watch")
+                    .help("render again when the scene files change"),
+            );
+    let render_subcommand = integrator_subcommands(render_subcommand, integrators);
+
+    let matches =
+        App::new("rustlight")
+            .version("0.2.0")
+            .author("Adrien Gruson <adrien.gruson@gmail.com>")
+            .about("A Rusty Light Transport simulation program")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .arg(Arg::with_name("debug").short("d").help("debug output"))
+            .subcommand(render_subcommand)
+            .subcommand(
+                SubCommand::with_name("diff")
+                    .about("compare an image against a reference")
+                    .arg(
+                        Arg::with_name("reference")
+                            .required(true)
+                            .index(1)
+                            .help("reference image"),
+                    )
+                    .arg(
+                        Arg::with_name("test")
+                            .required(true)
+                            .index(2)
+                            .help("image to compare"),
+                    )
+                    .arg(
+                        Arg::with_name("metric")
+                            .takes_value(true)
+                            .short("m")
+                            .default_value("relmse")
+                            .help("metric checked against the threshold (mse, relmse, mape, flip)"),
+                    )
+                    .arg(
+                        Arg::with_name("threshold")
+                            .takes_value(true)
+                            .short("t")
+                            .help("exit with an error code if the error is above"),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .takes_value(true)
+                            .short("o")
+                            .help("prefix of the false-color error maps (<output>_<metric>.png)"),
+                    )
+                    .arg(
+                        Arg::with_name("crop")
+                            .long("crop")
+                            .takes_value(true)
+                            .number_of_values(4)
+                            .value_names(&["x", "y", "width", "height"])
+                            .help("only compare this region of the images"),
+                    )
+                    .arg(Arg::with_name("max").takes_value(true).short("x").help(
+                        "error mapped to white in the error maps [default: 99th percentile]",
+                    )),
+            )
+            .subcommand(
+                SubCommand::with_name("convert")
+                    .about("convert an image to another format (deduced from the extension)")
+                    .arg(
+                        Arg::with_name("input")
+                            .required(true)
+                            .index(1)
+                            .help("input image"),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .required(true)
+                            .index(2)
+                            .help("output image"),
+                    )
+                    .arg(
+                        Arg::with_name("scale")
+                            .takes_value(true)
+                            .short("s")
+                            .default_value("1.0")
+                            .help("multiply the pixel values"),
+                    )
+                    .arg(
+                        Arg::with_name("exposure")
+                            .long("exposure")
+                            .takes_value(true)
+                            .allow_hyphen_values(true)
+                            .help("exposure correction in stops (multiply by 2^stops)"),
+                    )
+                    .arg(
+                        Arg::with_name("crop")
+                            .long("crop")
+                            .takes_value(true)
+                            .number_of_values(4)
+                            .value_names(&["x", "y", "width", "height"])
+                            .help("keep this region of the image"),
+                    )
+                    .arg(
+                        Arg::with_name("resize")
+                            .long("resize")
+                            .takes_value(true)
+                            .number_of_values(2)
+                            .value_names(&["width", "height"])
+                            .help("resample the image (after the crop)"),
+                    )
+                    .arg(
+                        Arg::with_name("filter")
+                            .long("filter")
+                            .takes_value(true)
+                            .default_value("box")
+                            .help("filter of the resize: box or lanczos"),
+                    )
+                    .arg(
+                        Arg::with_name("flip")
+                            .long("flip")
+                            .takes_value(true)
+                            .possible_values(&["horizontal", "vertical"])
+                            .help("mirror the image"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("info")
+                    .about("print information about a scene or an image")
+                    .arg(
+                        Arg::with_name("file")
+                            .required(true)
+                            .index(1)
+                            .help("scene (json, pbrt) or image"),
+                    )
+                    .arg(
+                        Arg::with_name("json")
+                            .long("json")
+                            .help("report of the scene in JSON (meshes, memory, emitters, BVH)"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("check")
+                    .about("check a scene description (meshes, emitters, textures, camera)")
+                    .arg(
+                        Arg::with_name("scene")
+                            .required(true)
+                            .index(1)
+                            .help("JSON or PBRT file description"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("probes")
+                    .about("bake an irradiance volume (spherical harmonics probes) in JSON")
+                    .arg(
+                        Arg::with_name("scene")
+                            .required(true)
+                            .index(1)
+                            .help("JSON or PBRT file description"),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .takes_value(true)
+                            .short("o")
+                            .default_value("probes.json")
+                            .help("output file"),
+                    )
+                    .arg(
+                        Arg::with_name("resolution")
+                            .long("resolution")
+                            .takes_value(true)
+                            .number_of_values(3)
+                            .value_names(&["x", "y", "z"])
+                            .help("number of probes along each axis [default: 8 8 8]"),
+                    )
+                    .arg(
+                        Arg::with_name("bands")
+                            .long("bands")
+                            .takes_value(true)
+                            .default_value("3")
+                            .help("spherical harmonics bands (1 to 5)"),
+                    )
+                    .arg(
+                        Arg::with_name("strata")
+                            .short("n")
+                            .takes_value(true)
+                            .default_value("16")
+                            .help("rays of a probe: n * n stratified directions"),
+                    )
+                    .arg(
+                        Arg::with_name("max")
+                            .short("m")
+                            .takes_value(true)
+                            .default_value("8")
+                            .help("max number of bounces"),
+                    )
+                    .arg(
+                        Arg::with_name("seed")
+                            .long("seed")
+                            .takes_value(true)
+                            .help("seed of the random numbers"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("server")
+                    .about("render service with an HTTP API (needs the server feature)")
+                    .arg(
+                        Arg::with_name("address")
+                            .takes_value(true)
+                            .short("a")
+                            .default_value("127.0.0.1:8080")
+                            .help("listening address"),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .takes_value(true)
+                            .short("o")
+                            .default_value("jobs")
+                            .help("directory of the rendered images"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("dataset")
+                    .about("render noisy/reference pairs and features to train denoisers")
+                    .arg(
+                        Arg::with_name("config")
+                            .required(true)
+                            .index(1)
+                            .help("TOML dataset description"),
+                    ),
+            )
+            .get_matches();
+
+    /////////////// Setup logging system
+    if matches.is_present("debug") {
+        // FIXME: add debug flag?
+        env_logger::Builder::from_default_env()
+            .format_timestamp(None)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .format_timestamp(None)
+            .parse_filters("info")
+            .init();
+    }
+
+    match matches.subcommand() {
+        ("render", Some(m)) => render(m, integrators),
+        ("diff", Some(m)) => diff(m),
+        ("convert", Some(m)) => convert(m),
+        ("info", Some(m)) => info(m),
+        ("check", Some(m)) => check(m),
+        ("probes", Some(m)) => probes(m),
+        ("server", Some(m)) => server(m, integrators),
+        ("dataset", Some(m)) => dataset(m, integrators),
+        _ => unreachable!(),
+    }
+}
+
+/// Compare an image against a reference (no scene needed)
+fn diff(m: &ArgMatches) {
+    use crate::metrics::{ErrorMap, Metric};
+    let read = |name| {
+        let filename = m.value_of(name).unwrap();
+        crate::structure::Bitmap::read_linear(filename, None, None).unwrap_or_else(|e| {
+            error!("Impossible to read {}: {}", filename, e);
+            std::process::exit(1)
+        })
+    };
+    let (mut reference, mut test) = (read("reference"), read("test"));
+    if reference.size != test.size {
+        error!(
+            "The two images need to have the same size: {:?} and {:?}",
+            reference.size, test.size
+        );
+        std::process::exit(1);
+    }
+    if m.is_present("crop") {
+        let (pos, size) = crop_region(m, reference.size);
+        reference = reference.crop(pos, size);
+        test = test.crop(pos, size);
+    }
+    let metric = value_t_or_exit!(m.value_of("metric"), Metric);
+    let mut error = 0.0;
+    for &current in Metric::all().iter() {
+        let map = ErrorMap::new(current, &reference, &test).unwrap_or_else(|e| {
+            error!("Impossible to compare the images: {}", e);
+            std::process::exit(1)
+        });
+        let mean = map.mean();
+        println!("{}: {}", current.name(), mean);
+        if current == metric {
+            error = mean;
+        }
+        if let Some(output) = m.value_of("output") {
+            let max = match m.value_of("max") {
+                Some(_) => value_t_or_exit!(m.value_of("max"), f32),
+                None => map.percentile(0.99),
+            };
+            let filename = format!("{}_{}.png", output, current.name());
+            if let Err(e) = map.false_color(max).save(&filename) {
+                error!("Impossible to save {}: {}", filename, e);
+            }
+        }
+    }
+    if m.is_present("threshold") {
+        let threshold = value_t_or_exit!(m.value_of("threshold"), f32);
+        // NaN are considered as a failure
+        if error > threshold || error.is_nan() {
+            error!(
+                "{} above the threshold: {} > {}",
+                metric.name(),
+                error,
+                threshold
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Render a scene with the configuration file and the command line
+fn render(m: &ArgMatches, integrators: &IntegratorManager) {
+    let config = render_config(m);
+    let options = RenderOptions::new(&config);
+    // Before the rendering, to catch the invalid files
+    let response = config
+        .response
+        .as_ref()
+        .map(|r| exit_on_error(r.load(), "Impossible to load the response curve"));
+
+    //////////////// Get the integrator (command line or configuration file)
+    let config_matches;
+    let subcommand = match m.subcommand() {
+        (name, Some(int_m)) => (name, int_m),
+        (_, None) => {
+            config_matches = config_integrator_matches(&config, integrators);
+            match config_matches.subcommand() {
+                (name, Some(int_m)) => (name, int_m),
+                _ => unreachable!(),
+            }
+        }
+    };
+
+    //////////////// Load the scene
+    // (again for each modification of the scene files with --watch)
+    let mut scene = exit_on_error(
+        load_scene(&config, &options, subcommand.0),
+        &format!("Impossible to load the scene {}", options.scene_path),
+    );
+
+    let observer = crate::integrators::ProgressBarObserver::default();
+    let start = std::time::Instant::now();
+    let mut int = create_integrator(subcommand, &config, integrators);
+    if config.watch.unwrap_or(false) {
+        watch(
+            &mut int,
+            scene,
+            &options.scene_path,
+            || load_scene(&config, &options, subcommand.0),
+            |scene, img, elapsed| {
+                let metadata = crate::metadata::RenderMetadata::new(
+                    subcommand.0,
+                    &options.scene_path,
+                    options.nb_samples,
+                    scene.seed,
+                    elapsed,
+                );
+                save_outputs(
+                    vec![(options.output.clone(), img)],
+                    scene,
+                    &config,
+                    response.as_ref(),
+                    &metadata,
+                );
+            },
+        );
+        return;
+    }
+    let outputs = render_cameras(&mut int, &mut scene, &config, &options.output, &observer);
+    let metadata = crate::metadata::RenderMetadata::new(
+        subcommand.0,
+        &options.scene_path,
+        options.nb_samples,
+        scene.seed,
+        start.elapsed(),
+    );
+    save_outputs(outputs, &scene, &config, response.as_ref(), &metadata);
+}
+
+/// Load the rendering configuration:
+/// the command line values override the configuration file
+fn render_config(m: &ArgMatches) -> RenderConfig {
+    let mut config = match m.value_of("config") {
+        Some(filename) => exit_on_error(
+            RenderConfig::load(filename),
+            "Impossible to read the configuration",
+        ),
+        None => RenderConfig::default(),
+    };
+    if let Some(v) = m.value_of("scene") {
+        config.scene = Some(v.to_string());
+    }
+    if let Some(v) = m.value_of("output") {
+        config.output = Some(v.to_string());
+    }
+    if m.is_present("nbsamples") {
+        config.nb_samples = Some(value_t_or_exit!(m.value_of("nbsamples"), usize));
+    }
+    if let Some(v) = m.value_of("nbthreads") {
+        config.nb_threads = Some(crate::config::ConfigValue::Text(v.to_string()));
+    }
+    if let Some(v) = m.value_of("pin_threads") {
+        config.pin_threads = Some(v.to_string());
+    }
+    if let Some(v) = m.value_of("tile_size") {
+        config.tile_size = Some(crate::config::ConfigValue::Text(v.to_string()));
+    }
+    if let Some(v) = m.value_of("average") {
+        config.average = Some(crate::config::ConfigValue::Text(v.to_string()));
+    }
+    if m.is_present("image_scale") {
+        config.image_scale = Some(value_t_or_exit!(m.value_of("image_scale"), f32));
+    }
+    if m.is_present("medium") {
+        config.medium = Some(true);
+    }
+    if let Some(v) = m.value_of("light_selection") {
+        config.light_selection = Some(v.to_string());
+    }
+    if m.is_present("env_guiding") {
+        config.env_guiding = Some(value_t_or_exit!(m.value_of("env_guiding"), usize));
+    }
+    if let Some(v) = m.value_of("film") {
+        config.film = Some(v.to_string());
+    }
+    if m.is_present("physical_exposure") {
+        let v = values_t_or_exit!(m.values_of("physical_exposure"), f32);
+        config.exposure = Some(crate::response::Exposure {
+            iso: v[0],
+            shutter: v[1],
+            f_number: v[2],
+        });
+    }
+    if let Some(v) = m.value_of("response") {
+        config.response = Some(crate::response::ResponseDesc::Lut(v.to_string()));
+    }
+    if m.is_present("rejection") {
+        config.rejection = Some(value_t_or_exit!(m.value_of("rejection"), f32));
+    }
+    if let Some(v) = m.value_of("check_nan") {
+        config.check_nan = Some(v.to_string());
+    }
+    if m.is_present("debug_pixel") {
+        let v = values_t_or_exit!(m.values_of("debug_pixel"), u32);
+        config.debug_pixel = Some([v[0], v[1]]);
+    }
+    if let Some(v) = m.value_of("stereo") {
+        config.stereo = Some(v.to_string());
+    }
+    if let Some(v) = m.value_of("stereo_layout") {
+        config.stereo_layout = Some(v.to_string());
+    }
+    if m.is_present("interocular") {
+        config.interocular = Some(value_t_or_exit!(m.value_of("interocular"), f32));
+    }
+    if let Some(v) = m.value_of("lightmap") {
+        config.lightmap = Some(v.to_string());
+    }
+    if m.is_present("lightmap_size") {
+        let v = values_t_or_exit!(m.values_of("lightmap_size"), u32);
+        config.lightmap_size = Some([v[0], v[1]]);
+    }
+    if let Some(v) = m.value_of("vertex_colors") {
+        config.vertex_colors = Some(v.to_string());
+    }
+    if let Some(v) = m.value_of("camera") {
+        config.camera = Some(v.to_string());
+    }
+    if m.is_present("seed") {
+        config.seed = Some(value_t_or_exit!(m.value_of("seed"), u64));
+    }
+    if m.is_present("lock_passes") {
+        config.lock_passes = Some(true);
+    }
+    if m.is_present("deterministic") {
+        config.deterministic = Some(true);
+    }
+    if m.is_present("watch") {
+        config.watch = Some(true);
+    }
+    if m.is_present("variance") && !config.aovs.iter().any(|v| v == "variance") {
+        config.aovs.push("variance".to_string());
+    }
+    config
+}
+
+/// Parse a value of the configuration, or print the error and stop the program
+fn parse_or_exit<T>(v: &str) -> T
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    v.parse().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1)
+    })
+}
+
+/// Number of threads: "auto" (None), a number or the number of cores minus this value
+/// when negative
+fn parse_nb_threads(v: &str) -> Option<usize> {
+    if v == "auto" {
+        return None;
+    }
+    let n = v.parse::<i32>().unwrap_or_else(|_| {
+        error!("Wrong number of threads: {} (auto or a number)", v);
+        std::process::exit(1)
+    });
+    let nb_threads = if n < 0 { num_cpus::get() as i32 + n } else { n };
+    if nb_threads <= 0 {
+        error!(
+            "Impossible to use {} threads for the computation ({} cores)",
+            v,
+            num_cpus::get()
+        );
+        std::process::exit(1)
+    }
+    if n < 0 {
+        info!("Run with {} threads", nb_threads);
+    }
+    Some(nb_threads as usize)
+}
+
+/// Rendering options parsed from the configuration
+/// (the invalid values print an error and stop the program)
+struct RenderOptions {
+    scene_path: String,
+    output: String,
+    nb_samples: usize,
+    nb_threads: Option<usize>,
+    thread_pinning: crate::scene::ThreadPinning,
+    tile_size: Option<u32>,
+    sampler: crate::samplers::SamplerType,
+    emitter_selection: crate::emitter::EmitterSelection,
+    sample_rejection: Option<crate::structure::SampleRejection>,
+    non_finite: Option<crate::scene::NonFiniteMode>,
+    output_variance: bool,
+    output_volume: bool,
+    output_ids: Option<crate::integrators::ids::PixelFilter>,
+    image_scale: f32,
+}
+
+impl RenderOptions {
+    fn new(config: &RenderConfig) -> RenderOptions {
+        let scene_path = config.scene.clone().unwrap_or_else(|| {
+            error!("No scene given (command line or configuration file)");
+            std::process::exit(1)
+        });
+        let output = config.output.clone().unwrap_or_else(|| {
+            let name = std::path::Path::new(&scene_path)
+                .file_stem()
+                .and_then(std::ffi::OsStr::to_str)
+                .unwrap_or("out");
+            match config.vertex_colors {
+                Some(_) => format!("{}.ply", name),
+                None => format!("{}.pfm", name),
+            }
+        });
+        let nb_samples = config.nb_samples.unwrap_or_else(|| {
+            error!("No number of samples given (-n or nb_samples inside the configuration file)");
+            std::process::exit(1)
+        });
+        let sample_rejection = config.rejection.map(|confidence| {
+            if !(confidence > 0.0 && confidence < 1.0) {
+                error!("The rejection confidence must be in ]0, 1[: {}", confidence);
+                std::process::exit(1)
+            }
+            crate::structure::SampleRejection { confidence }
+        });
+        if let Some(e) = config.exposure {
+            exit_on_error(
+                crate::response::Exposure::new(e.iso, e.shutter, e.f_number),
+                "Invalid exposure",
+            );
+        }
+        let mut output_variance = false;
+        let mut output_volume = false;
+        let mut output_ids = None;
+        for aov in &config.aovs {
+            match aov.as_str() {
+                "variance" => output_variance = true,
+                "volume" => output_volume = true,
+                "ids" => {
+                    output_ids = Some(parse_or_exit(config.id_filter.as_deref().unwrap_or("box")));
+                }
+                _ => {
+                    error!("Unsupported AOV: {} (variance, volume, ids)", aov);
+                    std::process::exit(1)
+                }
+            }
+        }
+        if config.id_filter.is_some() && output_ids.is_none() {
+            warn!("The ID filter is only used by the ids AOV");
+        }
+        let tile_size = match config.tile_size.as_ref().map(|v| v.to_string()) {
+            None => None,
+            Some(v) if v == "auto" => None,
+            Some(v) => match v.parse::<u32>() {
+                Ok(size) if size > 0 => Some(size),
+                _ => {
+                    error!("Wrong tile size: {} (auto or a number of pixels)", v);
+                    std::process::exit(1)
+                }
+            },
+        };
+        let image_scale = config.image_scale.unwrap_or(1.0);
+        if image_scale.is_nan() || image_scale <= 0.0 {
+            error!("The image scale must be positive: {}", image_scale);
+            std::process::exit(1)
+        }
+        if config.watch.unwrap_or(false) && config.camera.is_some() {
+            error!("--watch only renders the main camera");
+            std::process::exit(1)
+        }
+        if config.lightmap.is_some() && config.vertex_colors.is_some() {
+            error!("--lightmap and --vertex-colors cannot be used together");
+            std::process::exit(1)
+        }
+        if (config.lightmap.is_some() || config.vertex_colors.is_some()) && config.camera.is_some()
+        {
+            error!("--lightmap and --vertex-colors replace the camera: --camera cannot be used");
+            std::process::exit(1)
+        }
+        RenderOptions {
+            scene_path,
+            output,
+            nb_samples,
+            nb_threads: config
+                .nb_threads
+                .as_ref()
+                .and_then(|v| parse_nb_threads(&v.to_string())),
+            thread_pinning: config
+                .pin_threads
+                .as_deref()
+                .map_or_else(Default::default, parse_or_exit),
+            tile_size,
+            sampler: config
+                .sampler
+                .as_deref()
+                .map_or_else(Default::default, parse_or_exit),
+            emitter_selection: config
+                .light_selection
+                .as_deref()
+                .map_or_else(Default::default, parse_or_exit),
+            sample_rejection,
+            non_finite: config.check_nan.as_deref().map(parse_or_exit),
+            output_variance,
+            output_volume,
+            output_ids,
+            image_scale,
+        }
+    }
+}
+
+/// Arguments of the integrator given by the configuration file
+fn config_integrator_matches<'a>(
+    config: &RenderConfig,
+    integrators: &'a IntegratorManager,
+) -> ArgMatches<'a> {
+    let int_config = config.integrator.as_ref().unwrap_or_else(|| {
+        error!("No integrator given (command line or configuration file)");
+        std::process::exit(1)
+    });
+    // Convert the parameters to arguments
+    let mut args = vec!["integrator".to_string(), int_config.name.clone()];
+    let mut params = int_config.params.iter().collect::<Vec<_>>();
+    params.sort_by(|a, b| a.0.cmp(b.0));
+    for (k, v) in params {
+        match v {
+            crate::config::ConfigValue::Bool(false) => {}
+            crate::config::ConfigValue::Bool(true) => args.push(format!("--{}", k)),
+            v => {
+                args.push(format!("--{}", k));
+                args.push(v.to_string());
+            }
+        }
+    }
+    exit_on_error(
+        integrator_subcommands(
+            App::new("integrator").setting(AppSettings::SubcommandRequired),
+            integrators,
+        )
+        .get_matches_from_safe(args),
+        "Wrong integrator configuration",
+    )
+}
+
+/// Create the integrator from the registry (the built-in ones included),
+/// averaged over several passes with --average
+fn create_integrator(
+    (name, m): (&str, &ArgMatches),
+    config: &RenderConfig,
+    integrators: &IntegratorManager,
+) -> IntegratorType {
+    // The flags are given without value
+    let value_of = |p: &str| {
+        m.value_of(p)
+            .or_else(|| if m.is_present(p) { Some("true") } else { None })
+    };
+    let int = integrators
+        .create_from_args(name, value_of)
+        .unwrap_or_else(|e| {
+            error!("Impossible to create the integrator {}: {}", name, e);
+            std::process::exit(1)
+        });
+    match &config.average {
+        Some(average) => {
+            IntegratorType::Primal(Box::new(crate::integrators::avg::IntegratorAverage {
+                time_out: match_infinity(&average.to_string()),
+                integrator: int,
+            }))
+        }
+        None => int,
+    }
+}
+
+/// Load the scene with the rendering options.
+/// integrator: name of the integrator (warnings about the unsupported cameras)
+fn load_scene(
+    config: &RenderConfig,
+    options: &RenderOptions,
+    integrator: &str,
+) -> crate::error::Result<crate::scene::Scene> {
+    let mut scene =
+        crate::scene_loader::SceneLoaderManager::default().load(options.scene_path.clone())?;
+    if let Some(nb_threads) = options.nb_threads {
+        scene = scene.nb_threads(nb_threads);
+    }
+    let film = match config.film.as_deref() {
+        None | Some("opaque") => crate::scene::Film::Opaque,
+        Some("transparent") => crate::scene::Film::Transparent,
+        Some(filename) => crate::scene::Film::Background(exit_on_error(
+            crate::structure::Bitmap::read_linear(filename, None, None),
+            &format!("Impossible to read the background {}", filename),
+        )),
+    };
+    let mut scene = scene
+        .nb_samples(options.nb_samples)
+        .output_img(&options.output)
+        .output_variance(options.output_variance)
+        .output_volume(options.output_volume)
+        .output_ids(options.output_ids)
+        .demodulate(config.demodulate.unwrap_or(false))
+        .emitter_selection(options.emitter_selection)
+        .env_guiding(config.env_guiding)
+        .lock_passes(config.lock_passes.unwrap_or(false))
+        .deterministic(config.deterministic.unwrap_or(false))
+        .thread_pinning(options.thread_pinning.clone())
+        .tile_size(options.tile_size)
+        .sampler(options.sampler)
+        .film(film)
+        .sample_rejection(options.sample_rejection)
+        .non_finite(options.non_finite)
+        .debug_pixel(config.debug_pixel.map(|[x, y]| cgmath::Point2::new(x, y)));
+    scene.seed = config.seed;
+
+    ///////////////// Medium
+    // TODO: Read from PBRT file
+    if config.medium.unwrap_or(false) {
+        scene.volume = Some(medium(config));
+    }
+    ///////////////// Tweak the image size
+    if options.image_scale != 1.0 {
+        info!("Scale the image: {:?}", options.image_scale);
+        for camera in scene.cameras_mut() {
+            camera.scale_image(options.image_scale);
+        }
+    }
+    if integrator == "light" && scene.camera.lens_system().is_some() {
+        warn!("The light tracing does not support the lens systems (no splatting)");
+    }
+    if let Some(projection) = config.stereo.as_deref() {
+        set_stereo(&mut scene, config, projection, integrator)?;
+    }
+    if let Some(name) = config.lightmap.as_deref() {
+        let [width, height] = config.lightmap_size.unwrap_or([512, 512]);
+        let mesh = scene
+            .meshes
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| crate::error::Error::MeshNotFound(name.to_string()))?;
+        let texture_space =
+            crate::bake::TextureSpace::new(mesh, cgmath::Vector2::new(width, height))?;
+        info!("Bake the lightmap of {} ({}x{})", name, width, height);
+        if integrator == "light" {
+            warn!("The light tracing does not support the lightmaps (no splatting)");
+        }
+        scene
+            .camera
+            .set_texture_space(std::sync::Arc::new(texture_space));
+    }
+    if let Some(name) = config.vertex_colors.as_deref() {
+        let mesh = scene
+            .meshes
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| crate::error::Error::MeshNotFound(name.to_string()))?;
+        let texture_space = crate::bake::TextureSpace::vertices(mesh);
+        info!(
+            "Bake the colors of the {} vertices of {}",
+            mesh.vertices.len(),
+            name
+        );
+        scene
+            .camera
+            .set_texture_space(std::sync::Arc::new(texture_space));
+    }
+    Ok(scene)
+}
+
+/// Homogeneous medium filling the scene (--medium)
+fn medium(config: &RenderConfig) -> crate::volume::HomogenousVolume {
+    const FACTOR_DENSITY: f32 = 0.5;
+    let sigma_a = crate::structure::Color::value(0.05) * FACTOR_DENSITY;
+    let sigma_s = crate::structure::Color::value(0.9) * FACTOR_DENSITY;
+    let sigma_t = sigma_a + sigma_s;
+    let mut transmittance = crate::volume::TransmittanceOptions::default();
+    if let Some(v) = config.transmittance.as_deref() {
+        transmittance.estimator = parse_or_exit(v);
+    }
+    if let Some(v) = config.majorant_scale {
+        transmittance.majorant_scale = v;
+    }
+
+    info!("Create volume with: ");
+    info!(" - sigma_a: {:?}", sigma_a);
+    info!(" - sigma_s: {:?}", sigma_s);
+    info!(" - sigma_t: {:?}", sigma_t);
+    info!(" - transmittance: {:?}", transmittance);
+    crate::volume::HomogenousVolume {
+        sigma_a,
+        sigma_s,
+        sigma_t,
+        density: 1.0,
+        transmittance,
+    }
+}
+
+/// Replace the cameras of the scene by stereo cameras
+fn set_stereo(
+    scene: &mut crate::scene::Scene,
+    config: &RenderConfig,
+    projection: &str,
+    integrator: &str,
+) -> crate::error::Result<()> {
+    use crate::camera::{Stereo, StereoLayout, StereoProjection};
+    let projection: StereoProjection = parse_or_exit(projection);
+    let layout = match config.stereo_layout.as_deref() {
+        None if projection == StereoProjection::ODS => StereoLayout::OverUnder,
+        None => StereoLayout::SideBySide,
+        Some(v) => parse_or_exit(v),
+    };
+    info!("Stereo rendering: {:?} ({:?})", projection, layout);
+    if scene.cameras_mut().any(|c| c.lens_system().is_some()) {
+        return Err(crate::error::Error::InvalidData(
+            "the stereo cameras cannot use a lens system".to_string(),
+        ));
+    }
+    if integrator == "light" {
+        warn!("The light tracing does not support the stereo cameras (no splatting)");
+    }
+    let stereo = Stereo {
+        projection,
+        layout,
+        interocular: config.interocular.unwrap_or(0.064),
+    };
+    for camera in scene.cameras_mut() {
+        camera.set_stereo(stereo);
+    }
+    Ok(())
+}
+
+/// Render the main camera, or the named cameras of the scene (--camera).
+/// Returns the output filename and the image of each rendered camera
+fn render_cameras(
+    int: &mut IntegratorType,
+    scene: &mut crate::scene::Scene,
+    config: &RenderConfig,
+    output: &str,
+    observer: &dyn crate::integrators::RenderObserver,
+) -> Vec<(String, crate::integrators::BufferCollection)> {
+    let camera = match config.camera.as_deref() {
+        None => return vec![(output.to_string(), int.compute(scene, observer))],
+        Some(camera) => camera,
+    };
+    let names = if camera == "all" {
+        scene.cameras.iter().map(|(n, _)| n.clone()).collect()
+    } else {
+        vec![camera.to_string()]
+    };
+    if names.is_empty() {
+        error!("The scene does not have named cameras");
+        std::process::exit(1)
+    }
+    let imgs = exit_on_error(
+        int.compute_cameras(scene, &names, observer),
+        "Impossible to render the cameras",
+    );
+    // One output per camera when rendering all of them
+    names
+        .iter()
+        .zip(imgs)
+        .map(|(name, img)| match camera {
+            "all" => (output_with_suffix(output, name), img),
+            _ => (output.to_string(), img),
+        })
+        .collect()
+}
+
+/// Save the images (output filename and image of each rendered camera) with their AOVs
+fn save_outputs(
+    outputs: Vec<(String, crate::integrators::BufferCollection)>,
+    scene: &crate::scene::Scene,
+    config: &RenderConfig,
+    response: Option<&crate::response::ResponseCurve>,
+    metadata: &crate::metadata::RenderMetadata,
+) {
+    scene.non_finite.report();
+    if let (Some(_), Some((output, _))) = (scene.path_debugger.pixel, outputs.first()) {
+        save_paths(&scene.path_debugger, output);
+    }
+    let color_space = config
+        .output_color_space
+        .unwrap_or(crate::color::ColorSpace::SRGB);
+    if response.is_some() && config.output_color_space.is_some() {
+        warn!("The response curve replaces the output color space of the LDR outputs");
+    }
+    for (output, mut img) in outputs {
+        if scene.demodulate {
+            // The vertex colors are only written remodulated
+            if config.vertex_colors.is_none() {
+                for (name, suffix) in &[("primal", "demodulated"), ("albedo", "albedo")] {
+                    exit_on_error(
+                        img.save(name, &output_with_suffix(&output, suffix)),
+                        "Impossible to save the image",
+                    );
+                }
+            }
+            crate::integrators::demodulation::remodulate(&mut img);
+        }
+        let mut primal = img.values["primal"].to_f32();
+        if let Some(exposure) = config.exposure {
+            primal.to_mut().scale(exposure.scale());
+        }
+        if let Some(name) = config.vertex_colors.as_deref() {
+            let mesh = scene.meshes.iter().find(|m| m.name == name).unwrap();
+            exit_on_error(
+                crate::bake::save_vertex_colors(mesh, &primal, &output),
+                "Impossible to save the vertex colors",
+            );
+            continue;
+        }
+        if let Some(white_point) = config.white_point {
+            primal
+                .to_mut()
+                .adapt_white_point(white_point, crate::color::WhitePoint::D65);
+        }
+        let mut color_space = color_space;
+        if let Some(response) = response {
+            if crate::tools::extension(&output).ok() == Some("png") {
+                *primal.to_mut() = primal.map(|c| response.apply(c));
+                color_space = crate::color::ColorSpace::Linear;
+            } else {
+                warn!(
+                    "The response curve only applies to the LDR outputs (png): {}",
+                    output
+                );
+            }
+        }
+        exit_on_error(
+            metadata.save(
+                &primal,
+                &output,
+                color_space,
+                config.png_16bits.unwrap_or(false),
+            ),
+            "Impossible to save the image",
+        );
+        let mut aov_names = vec![];
+        if scene.output_variance {
+            aov_names.extend(&["variance", "spp"]);
+        }
+        if scene.output_volume {
+            aov_names.extend(&["surface", "volume"]);
+        }
+        for name in aov_names {
+            if img.values.contains_key(name) {
+                exit_on_error(
+                    img.save(name, &output_with_suffix(&output, name)),
+                    "Impossible to save the image",
+                );
+            } else {
+                warn!("The integrator does not provide the {} buffer", name);
+            }
+        }
+        if let Some(ids) = &img.ids {
+            let filename = std::path::Path::new(&output_with_suffix(&output, "cryptomatte"))
+                .with_extension("exr")
+                .to_string_lossy()
+                .to_string();
+            info!("Write cryptomatte layers: {}", filename);
+            exit_on_error(
+                crate::integrators::cryptomatte::save_cryptomatte(
+                    &filename,
+                    scene,
+                    ids,
+                    crate::integrators::cryptomatte::AOV_DEPTH,
+                ),
+                "Impossible to save the ID buffers",
+            );
+        }
+    }
+}
+
+/// Save the paths recorded by the path debugger next to the image
+fn save_paths(debugger: &crate::paths::debugger::PathDebugger, output: &str) {
+    if debugger.paths().is_empty() {
+        warn!("No path recorded: the path debugger needs an explicit path tracer (path, gradient-path-explicit)");
+        return;
+    }
+    for ext in ["json", "obj"] {
+        let filename = std::path::Path::new(&output_with_suffix(output, "paths"))
+            .with_extension(ext)
+            .to_string_lossy()
+            .to_string();
+        exit_on_error(
+            debugger.save(&filename),
+            &format!("Impossible to save the paths {}", filename),
+        );
+        info!("Paths of the pixel saved: {}", filename);
+    }
+}
+
+/// Render the scene again each time its files change, until the program is stopped.
+/// The files are polled, and the scene is reloaded once they stop changing
+/// (the editors can write them in several steps).
+fn watch<L, S>(
+    int: &mut IntegratorType,
+    scene: crate::scene::Scene,
+    scene_path: &str,
+    load_scene: L,
+    mut save: S,
+) where
+    L: Fn() -> crate::error::Result<crate::scene::Scene>,
+    S: FnMut(&crate::scene::Scene, crate::integrators::BufferCollection, std::time::Duration),
+{
+    use crate::watch::{scene_files, FileWatcher};
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+    let list_files = || {
+        let files = scene_files(scene_path).unwrap_or_else(|e| {
+            warn!("Impossible to list the files of the scene: {}", e);
+            vec![std::path::PathBuf::from(scene_path)]
+        });
+        FileWatcher::new(files)
+    };
+    let mut watcher = list_files();
+    let mut start = std::time::Instant::now();
+    let observer = crate::integrators::ProgressBarObserver::default();
+    int.compute_reload(scene, &observer, |scene, img| {
+        save(scene, img, start.elapsed());
+        info!(
+            "Watch the {} files of the scene (Ctrl-C to stop)",
+            watcher.files().count()
+        );
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let mut changed = watcher.changed();
+            if changed.is_empty() {
+                continue;
+            }
+            while !changed.is_empty() {
+                for f in &changed {
+                    info!("Modified: {}", f.display());
+                }
+                std::thread::sleep(POLL_INTERVAL);
+                changed = watcher.changed();
+            }
+            start = std::time::Instant::now();
+            match load_scene() {
+                Ok(scene) => {
+                    // New textures or material libraries
+                    watcher = list_files();
+                    return Some(scene);
+                }
+                // Keep the previous image until the next modification
+                Err(e) => error!("Impossible to reload the scene {}: {}", scene_path, e),
+            }
+        }
+    });
+}
+
+/// "<base>_<suffix>.<ext>" from "<base>.<ext>"
+fn output_with_suffix(output: &str, suffix: &str) -> String {
+    let output_ext = std::path::Path::new(output)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or_else(|| {
+            error!("No file extension provided: {}", output);
+            std::process::exit(1)
+        });
+    let base = &output[..output.len() - output_ext.len() - 1];
+    format!("{}_{}.{}", base, suffix, output_ext)
+}
+
+/// Convert an image (format given by the extensions)
+fn convert(m: &ArgMatches) {
+    let input = m.value_of("input").unwrap();
+    let output = m.value_of("output").unwrap();
+    let scale = value_t_or_exit!(m.value_of("scale"), f32);
+    let mut img = exit_on_error(
+        crate::structure::Bitmap::read_linear(input, None, None),
+        &format!("Impossible to read {}", input),
+    );
+    if scale != 1.0 {
+        img.scale(scale);
+    }
+    if m.is_present("exposure") {
+        img.exposure(value_t_or_exit!(m.value_of("exposure"), f32));
+    }
+    if m.is_present("crop") {
+        let (pos, size) = crop_region(m, img.size);
+        img = img.crop(pos, size);
+    }
+    if m.is_present("resize") {
+        let v = values_t_or_exit!(m.values_of("resize"), u32);
+        if v[0] == 0 || v[1] == 0 {
+            error!("Impossible to resize the image to {}x{}", v[0], v[1]);
+            std::process::exit(1);
+        }
+        let filter = value_t_or_exit!(m.value_of("filter"), crate::image_ops::ResizeFilter);
+        img = img.resize(cgmath::Vector2::new(v[0], v[1]), filter);
+    }
+    match m.value_of("flip") {
+        Some("horizontal") => img = img.flip_horizontal(),
+        Some("vertical") => img = img.flip_vertical(),
+        _ => {}
+    }
+    exit_on_error(img.save(output), &format!("Impossible to save {}", output));
+}
+
+/// Region given by --crop x y width height (exit if it is empty or outside of the image)
+fn crop_region(
+    m: &ArgMatches,
+    img_size: cgmath::Vector2<u32>,
+) -> (cgmath::Point2<u32>, cgmath::Vector2<u32>) {
+    let v = values_t_or_exit!(m.values_of("crop"), u32);
+    if v[2] == 0 || v[3] == 0 || v[0] >= img_size.x || v[1] >= img_size.y {
+        error!(
+            "Crop region {}x{} at ({}, {}) outside of the image {}x{}",
+            v[2], v[3], v[0], v[1], img_size.x, img_size.y
+        );
+        std::process::exit(1);
+    }
+    (
+        cgmath::Point2::new(v[0], v[1]),
+        cgmath::Vector2::new(v[2], v[3]),
+    )
+}
+
+/// Print scene or image statistics
+fn info(m: &ArgMatches) {
+    let filename = m.value_of("file").unwrap();
+    let ext = exit_on_error(crate::tools::extension(filename), filename);
+    if ext == "json" || ext == "pbrt" {
+        let scene = exit_on_error(
+            crate::scene_loader::SceneLoaderManager::default().load(filename.to_string()),
+            &format!("Impossible to load the scene {}", filename),
+        );
+        let report = scene.report();
+        if m.is_present("json") {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            return;
+        }
+        let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+        let size = scene.camera.size();
+        println!("image size: {}x{}", size.x, size.y);
+        println!("meshes: {}", scene.meshes.len());
+        println!("triangles: {}", report.triangles);
+        println!("vertices: {}", report.vertices);
+        println!("geometry memory: {:.1} MiB", mib(report.geometry_memory));
+        println!("texture memory: {:.1} MiB", mib(report.texture_memory));
+        println!("emitters:");
+        for e in &report.emitters {
+            println!(
+                " - {} (flux: {:?}, {:.1}%)",
+                e.name,
+                e.flux,
+                100.0 * e.fraction
+            );
+        }
+        println!(
+            "BVH: {} nodes, {} leaves, built in {:.3}s (embree: {:.3}s)",
+            report.bvh.nodes,
+            report.bvh.leaves,
+            report.bvh.build_time,
+            report.bvh.embree_build_time
+        );
+    } else {
+        let img = exit_on_error(
+            crate::structure::Bitmap::read_linear(filename, None, None),
+            &format!("Impossible to read {}", filename),
+        );
+        let luminances = img.luminance().into_iter();
+        let nb_invalid = img
+            .colors
+            .iter()
+            .filter(|c| !(c.r.is_finite() && c.g.is_finite() && c.b.is_finite()))
+            .count();
+        println!("size: {}x{}", img.size.x, img.size.y);
+        println!("average: {:?}", img.average());
+        println!(
+            "luminance: [{}, {}]",
+            luminances.clone().fold(f32::INFINITY, f32::min),
+            luminances.fold(f32::NEG_INFINITY, f32::max)
+        );
+        println!("NaN/inf pixels: {}", nb_invalid);
+    }
+}
+
+/// Report the problems of a scene without rendering it
+/// (exit with an error code if there is any)
+fn check(m: &ArgMatches) {
+    let filename = m.value_of("scene").unwrap();
+    let warnings = exit_on_error(
+        crate::scene_check::check_scene(filename),
+        &format!("Impossible to check the scene {}", filename),
+    );
+    for w in &warnings {
+        warn!("{}", w);
+    }
+    if warnings.is_empty() {
+        println!("{}: no problem found", filename);
+    } else {
+        println!("{}: {} problem(s) found", filename, warnings.len());
+        std::process::exit(1);
+    }
+}
+
+/// Bake the irradiance probes of a scene
+fn probes(m: &ArgMatches) {
+    use crate::bake::{IrradianceVolume, IrradianceVolumeConfig};
+    let filename = m.value_of("scene").unwrap();
+    let mut scene = exit_on_error(
+        crate::scene_loader::SceneLoaderManager::default().load(filename.to_string()),
+        &format!("Impossible to load the scene {}", filename),
+    );
+    if m.is_present("seed") {
+        scene.seed = Some(value_t_or_exit!(m.value_of("seed"), u64));
+    }
+    let mut config = IrradianceVolumeConfig {
+        bands: value_t_or_exit!(m.value_of("bands"), usize),
+        nb_strata: value_t_or_exit!(m.value_of("strata"), usize),
+        max_depth: value_t_or_exit!(m.value_of("max"), u32),
+        ..Default::default()
+    };
+    if m.is_present("resolution") {
+        let v = values_t_or_exit!(m.values_of("resolution"), usize);
+        config.resolution = cgmath::Vector3::new(v[0], v[1], v[2]);
+    }
+    if !(1..=crate::math::SH_MAX_BANDS).contains(&config.bands)
+        || config.resolution.x < 2
+        || config.resolution.y < 2
+        || config.resolution.z < 2
+        || config.nb_strata == 0
+    {
+        error!("Invalid probes parameters (bands: 1-5, resolution >= 2, n > 0)");
+        std::process::exit(1);
+    }
+    let volume = IrradianceVolume::bake_scene(&scene, &config);
+    let output = m.value_of("output").unwrap();
+    exit_on_error(
+        volume.save(output),
+        &format!("Impossible to write {}", output),
+    );
+    let nb_inside = volume.probes.iter().filter(|p| p.backface > 0.5).count();
+    println!(
+        "{}: {} probes ({} inside the geometry)",
+        output,
+        volume.probes.len(),
+        nb_inside
+    );
+}
+
+/// Generate a denoising dataset
+fn dataset(m: &ArgMatches, integrators: &IntegratorManager) {
+    let filename = m.value_of("config").unwrap();
+    let config = exit_on_error(
+        crate::dataset::DatasetConfig::load(filename),
+        "Impossible to read the dataset description",
+    );
+    let samples = exit_on_error(
+        crate::dataset::generate(
+            &config,
+            integrators,
+            &crate::integrators::ProgressBarObserver::default(),
+        ),
+        "Impossible to generate the dataset",
+    );
+    println!("{}: {} samples", config.output, samples.len());
+}
+
+/// Render the jobs submitted through HTTP
+#[cfg(feature = "server")]
+fn server(m: &ArgMatches, integrators: &IntegratorManager) {
+    exit_on_error(
+        crate::server::run(
+            m.value_of("address").unwrap(),
+            std::path::Path::new(m.value_of("output").unwrap()),
+            integrators,
+        ),
+        "Render server error",
+    );
+}
+#[cfg(not(feature = "server"))]
+fn server(_m: &ArgMatches, _integrators: &IntegratorManager) {
+    error!("{}", crate::error::Error::MissingFeature("server"));
+    std::process::exit(1)
+}
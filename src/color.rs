@@ -0,0 +1,214 @@
+//! Color spaces of the images read and written by rustlight.
+//! The rendering is done in linear RGB with the sRGB (Rec. 709) primaries and the D65 white point:
+//! the images are decoded (transfer function and white point) on load and encoded on save.
+use crate::error::{Error, Result};
+use crate::structure::Color;
+use cgmath::{Matrix, Matrix3, SquareMatrix, Vector3};
+use serde::{Deserialize, Deserializer};
+
+/// Transfer function of the values stored inside an image
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    /// Values proportional to the radiance
+    Linear,
+    /// Piecewise sRGB curve (IEC 61966-2-1)
+    SRGB,
+    /// Pure power function: {"gamma": 2.2}
+    Gamma(f32),
+}
+
+impl ColorSpace {
+    /// Default color space of the image files:
+    /// the HDR formats are linear, the others are considered sRGB encoded.
+    pub fn from_extension(filename: &str) -> Result<ColorSpace> {
+        Ok(match crate::tools::extension(filename)? {
+            "exr" | "pfm" | "hdr" => ColorSpace::Linear,
+            _ => ColorSpace::SRGB,
+        })
+    }
+
+    /// Encoded value to linear value
+    pub fn decode(self, v: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => v,
+            ColorSpace::SRGB => {
+                if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            ColorSpace::Gamma(g) => v.max(0.0).powf(g),
+        }
+    }
+    /// Linear value to encoded value
+    pub fn encode(self, v: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => v,
+            ColorSpace::SRGB => {
+                if v <= 0.003_130_8 {
+                    v * 12.92
+                } else {
+                    1.055 * v.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            ColorSpace::Gamma(g) => v.max(0.0).powf(1.0 / g),
+        }
+    }
+
+    pub fn decode_color(self, c: Color) -> Color {
+        Color::new(self.decode(c.r), self.decode(c.g), self.decode(c.b))
+    }
+    pub fn encode_color(self, c: Color) -> Color {
+        Color::new(self.encode(c.r), self.encode(c.g), self.encode(c.b))
+    }
+}
+
+/// CIE 1931 chromaticity of a white point.
+/// JSON/TOML: a name ("d65", "d50", "d60", "a", "e"), a temperature in Kelvin or [x, y].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WhitePoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl WhitePoint {
+    /// White point of sRGB, used for the rendering
+    pub const D65: WhitePoint = WhitePoint {
+        x: 0.312_71,
+        y: 0.329_02,
+    };
+    /// Printing and ICC profile connection space
+    pub const D50: WhitePoint = WhitePoint {
+        x: 0.345_67,
+        y: 0.358_50,
+    };
+    /// ACES
+    pub const D60: WhitePoint = WhitePoint {
+        x: 0.321_68,
+        y: 0.337_67,
+    };
+    /// Incandescent lighting
+    pub const A: WhitePoint = WhitePoint {
+        x: 0.447_57,
+        y: 0.407_45,
+    };
+    /// Equal energy
+    pub const E: WhitePoint = WhitePoint {
+        x: 1.0 / 3.0,
+        y: 1.0 / 3.0,
+    };
+
+    pub fn from_name(name: &str) -> Result<WhitePoint> {
+        match name.to_lowercase().as_ref() {
+            "d65" => Ok(WhitePoint::D65),
+            "d50" => Ok(WhitePoint::D50),
+            "d60" | "aces" => Ok(WhitePoint::D60),
+            "a" => Ok(WhitePoint::A),
+            "e" => Ok(WhitePoint::E),
+            _ => Err(Error::InvalidData(format!("unknown white point: {}", name))),
+        }
+    }
+
+    /// Planckian locus (Kim et al. approximation), clamped to [1667, 25000] K
+    pub fn from_temperature(t: f32) -> WhitePoint {
+        let t = f64::from(t.clamp(1667.0, 25000.0));
+        let (t2, t3) = (t * t, t * t * t);
+        let x = if t <= 4000.0 {
+            -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+        } else {
+            -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+        };
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t <= 2222.0 {
+            -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+        } else if t <= 4000.0 {
+            -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+        } else {
+            3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+        };
+        WhitePoint {
+            x: x as f32,
+            y: y as f32,
+        }
+    }
+
+    /// XYZ coordinates with Y = 1
+    pub fn xyz(&self) -> Vector3<f32> {
+        Vector3::new(self.x / self.y, 1.0, (1.0 - self.x - self.y) / self.y)
+    }
+}
+
+impl<'de> Deserialize<'de> for WhitePoint {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error as _;
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum WhitePointDesc {
+            Name(String),
+            Temperature(f32),
+            Chromaticity([f32; 2]),
+        }
+        match WhitePointDesc::deserialize(deserializer)? {
+            WhitePointDesc::Name(name) => WhitePoint::from_name(&name).map_err(D::Error::custom),
+            WhitePointDesc::Temperature(t) => Ok(WhitePoint::from_temperature(t)),
+            WhitePointDesc::Chromaticity([x, y]) => {
+                if x <= 0.0 || y <= 0.0 || x + y >= 1.0 {
+                    Err(D::Error::custom(format!(
+                        "invalid chromaticity: {} {}",
+                        x, y
+                    )))
+                } else {
+                    Ok(WhitePoint { x, y })
+                }
+            }
+        }
+    }
+}
+
+/// Matrix given by rows (cgmath constructors are column-major)
+fn from_rows(m: [[f32; 3]; 3]) -> Matrix3<f32> {
+    Matrix3::new(
+        m[0][0], m[0][1], m[0][2], m[1][0], m[1][1], m[1][2], m[2][0], m[2][1], m[2][2],
+    )
+    .transpose()
+}
+
+/// Linear sRGB to XYZ (D65)
+pub fn rgb_to_xyz() -> Matrix3<f32> {
+    from_rows([
+        [0.412_456_4, 0.357_576_1, 0.180_437_5],
+        [0.212_672_9, 0.715_152_2, 0.072_175_0],
+        [0.019_333_9, 0.119_192_0, 0.950_304_1],
+    ])
+}
+pub fn xyz_to_rgb() -> Matrix3<f32> {
+    rgb_to_xyz().invert().unwrap()
+}
+
+/// Bradford chromatic adaptation between two white points (XYZ to XYZ)
+pub fn bradford(src: WhitePoint, dst: WhitePoint) -> Matrix3<f32> {
+    let m = from_rows([
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ]);
+    let (s, d) = (m * src.xyz(), m * dst.xyz());
+    let scale = Matrix3::from_diagonal(Vector3::new(d.x / s.x, d.y / s.y, d.z / s.z));
+    m.invert().unwrap() * scale * m
+}
+
+/// Chromatic adaptation applied to the linear sRGB values:
+/// a color with the chromaticity of src becomes a color with the chromaticity of dst.
+pub fn rgb_adaptation(src: WhitePoint, dst: WhitePoint) -> Matrix3<f32> {
+    xyz_to_rgb() * bradford(src, dst) * rgb_to_xyz()
+}
+
+pub fn transform_color(m: &Matrix3<f32>, c: Color) -> Color {
+    let v = m * Vector3::new(c.r, c.g, c.b);
+    Color::new(v.x, v.y, v.z)
+}
@@ -0,0 +1,144 @@
+use crate::color::{ColorSpace, WhitePoint};
+use crate::error::{Error, Result};
+use crate::response::{Exposure, ResponseDesc};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Value of a configuration entry.
+/// Converted to text to be parsed like the command line arguments
+/// (e.g., "inf" or a number for the maximum depth)
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigValue::Bool(v) => write!(f, "{}", v),
+            ConfigValue::Int(v) => write!(f, "{}", v),
+            ConfigValue::Float(v) => write!(f, "{}", v),
+            ConfigValue::Text(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Integrator section: its name (type) and its parameters
+/// (same names as the long command line arguments)
+#[derive(Deserialize, Debug, Clone)]
+pub struct IntegratorConfig {
+    #[serde(rename = "type")]
+    pub name: String,
+    #[serde(flatten)]
+    pub params: HashMap<String, ConfigValue>,
+}
+
+/// Render configuration (TOML file).
+/// All the entries are optional as the command line can provide or override them.
+///
+/// ```toml
+/// scene = "data/cbox.json"
+/// output = "cbox.exr"
+/// nb_samples = 128
+/// aovs = ["variance"]
+/// [integrator]
+/// type = "path"
+/// max = 5
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RenderConfig {
+    pub scene: Option<String>,
+    pub output: Option<String>,
+    pub nb_samples: Option<usize>,
+    /// "auto", a number of threads or a negative number of cores to leave out
+    pub nb_threads: Option<ConfigValue>,
+    /// Pinning of the threads to the cores: "none" (default), "cores" or a list of core ids
+    pub pin_threads: Option<String>,
+    /// Size of the rendering blocks in pixels or "auto" (default, from a warm-up pass)
+    pub tile_size: Option<ConfigValue>,
+    /// Average several passes with a time limit in seconds ("inf" is possible)
+    pub average: Option<ConfigValue>,
+    pub image_scale: Option<f32>,
+    /// Add the test medium
+    pub medium: Option<bool>,
+    /// Transmittance estimator of the shadow rays inside the medium: "analytic" (default),
+    /// "track-length", "ratio", "residual-ratio" or "power-series"
+    pub transmittance: Option<String>,
+    /// Majorant of the null-collision estimators relative to the extinction (2 by default)
+    pub majorant_scale: Option<f32>,
+    /// Sampler of the Monte Carlo integrators: "independent" (default) or "halton"
+    pub sampler: Option<String>,
+    /// Emitter selection: "uniform", "power" (default), "bvh" or "grid"
+    pub light_selection: Option<String>,
+    /// Camera rays per pixel learning the sampling of the environment from its
+    /// contributions before the rendering (disabled by default)
+    pub env_guiding: Option<usize>,
+    /// Escaped camera rays: "opaque" (default), "transparent" (alpha channel)
+    /// or the filename of a background plate
+    pub film: Option<String>,
+    /// Confidence in ]0, 1[ of the per-pixel outlier rejection (robust estimate
+    /// instead of the mean of the samples, disabled by default)
+    pub rejection: Option<f32>,
+    /// Detection of the NaN and infinite contributions: "log" or "discard" (disabled by default)
+    pub check_nan: Option<String>,
+    /// Pixel [x, y] whose paths are saved as <output>_paths.json and <output>_paths.obj
+    /// (path debugger, only the explicit path tracers)
+    pub debug_pixel: Option<[u32; 2]>,
+    /// Stereo rendering: "perspective" or "ods" (omni-directional stereo)
+    pub stereo: Option<String>,
+    /// Position of the eyes: "sbs" (side by side) or "ou" (over-under).
+    /// By default "sbs" for "perspective" and "ou" for "ods"
+    pub stereo_layout: Option<String>,
+    /// Distance between the eyes (0.064 by default)
+    pub interocular: Option<f32>,
+    /// Bake the lightmap of this mesh (texture space film) instead of rendering the camera
+    pub lightmap: Option<String>,
+    /// Size [width, height] of the lightmap (512 x 512 by default)
+    pub lightmap_size: Option<[u32; 2]>,
+    /// Bake the vertex colors of this mesh (PLY or OBJ output) instead of rendering the camera
+    pub vertex_colors: Option<String>,
+    /// Named camera of the scene to render or "all" (one output per camera)
+    pub camera: Option<String>,
+    /// Seed of the samplers (random by default), hashed with the index of the passes
+    pub seed: Option<u64>,
+    /// All the passes use the random numbers of the first one (debugging)
+    pub lock_passes: Option<bool>,
+    /// Bitwise identical images whatever the number of threads (seed 0 by default)
+    pub deterministic: Option<bool>,
+    /// Render again when the scene or its meshes and textures change (main camera only)
+    pub watch: Option<bool>,
+    /// Additional outputs: "variance" (with the number of samples),
+    /// "volume" (surface and in-scattered radiance) and "ids" (object and material
+    /// coverages, written as <output>_cryptomatte.exr)
+    #[serde(default)]
+    pub aovs: Vec<String>,
+    /// Pixel filter of the ids AOV: "box" (default) or "gaussian"
+    pub id_filter: Option<String>,
+    /// Render the radiance divided by the albedo of the first intersection, written with
+    /// the albedo as <output>_demodulated and <output>_albedo (remodulated main output)
+    pub demodulate: Option<bool>,
+    pub integrator: Option<IntegratorConfig>,
+    /// Encoding of the LDR outputs ("srgb" by default, "linear" or {gamma = 2.2})
+    pub output_color_space: Option<ColorSpace>,
+    /// Write the png output on 16 bits per channel (8 bits by default)
+    pub png_16bits: Option<bool>,
+    /// Output white point: the colors of this chromaticity become white (white balance).
+    /// A name ("d65", "d50", ...), a temperature in Kelvin or a [x, y] chromaticity.
+    pub white_point: Option<WhitePoint>,
+    /// Camera settings scaling the output: {iso = 100, shutter = 0.01, f_number = 8}
+    pub exposure: Option<Exposure>,
+    /// Response curve of the LDR outputs replacing their encoding:
+    /// a LUT ("curve.cube") or {emor = "emor.txt", coefficients = [...]}
+    pub response: Option<ResponseDesc>,
+}
+
+impl RenderConfig {
+    pub fn load(filename: &str) -> Result<RenderConfig> {
+        let data = std::fs::read_to_string(filename)?;
+        toml::from_str(&data).map_err(|e| Error::InvalidData(format!("{}: {}", filename, e)))
+    }
+}
@@ -0,0 +1,233 @@
+//! Training data for the learned denoisers: for each scene, a high spp reference
+//! and several low spp noisy renderings (one per seed) with their auxiliary buffers.
+//!
+//! Layout of the output directory:
+//! - `<scene>/reference.exr`
+//! - `<scene>/<seed>/noisy.exr`, `variance.exr`, `albedo.exr` and `normal.exr`
+//! - `dataset.json`: list of the samples (paths relative to the output directory)
+//!
+//! The noisy and reference images carry the render metadata (EXR header and JSON sidecar).
+use crate::error::{Error, Result};
+use crate::integrators::gradient::features::compute_features;
+use crate::integrators::registry::IntegratorManager;
+use crate::integrators::*;
+use crate::metadata::RenderMetadata;
+use crate::scene::{Acceleration, Scene};
+use crate::scene_loader::SceneLoaderManager;
+use crate::structure::Bitmap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Seed of the reference renderings (the noisy ones use the seeds of the configuration)
+pub const REFERENCE_SEED: u64 = 0x5EED_0000_0000_0000;
+
+/// Dataset description (TOML file)
+///
+/// ```toml
+/// output = "dataset"
+/// scenes = ["data/cbox.json"]
+/// seeds = [0, 1, 2, 3]
+/// noisy_samples = 4
+/// reference_samples = 4096
+/// [integrator]
+/// type = "path"
+/// max = 8
+/// ```
+/// The integrator parameters are the same as the render server ones.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetConfig {
+    /// Output directory
+    pub output: String,
+    pub scenes: Vec<String>,
+    /// One noisy rendering per seed
+    pub seeds: Vec<u64>,
+    pub noisy_samples: usize,
+    pub reference_samples: usize,
+    pub image_scale: Option<f32>,
+    pub integrator: serde_json::Value,
+}
+
+impl DatasetConfig {
+    pub fn load(filename: &str) -> Result<DatasetConfig> {
+        let data = std::fs::read_to_string(filename)?;
+        toml::from_str(&data).map_err(|e| Error::InvalidData(format!("{}: {}", filename, e)))
+    }
+}
+
+/// Entry of dataset.json (paths relative to the output directory)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatasetSample {
+    pub scene: String,
+    pub seed: u64,
+    pub noisy: String,
+    pub variance: String,
+    pub albedo: String,
+    pub normal: String,
+    pub reference: String,
+}
+
+/// Auxiliary buffers of the first intersection
+struct IntegratorFeatures;
+impl Integrator for IntegratorFeatures {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        _observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        compute_features(accel, scene)
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::InvalidData(format!("invalid path {:?}", path)))
+}
+
+fn save_exr(img: &Bitmap, path: &Path) -> Result<()> {
+    img.save_exr(path_str(path)?)
+}
+
+/// Render the scene and save the primal image with its metadata
+fn render(
+    scene: &Scene,
+    config: &DatasetConfig,
+    integrator: &mut IntegratorType,
+    observer: &dyn RenderObserver,
+    scene_file: &str,
+    filename: &Path,
+) -> Result<BufferCollection> {
+    let start = Instant::now();
+    let img = integrator.compute(scene, observer);
+    let integrator_name = config.integrator.get("type").and_then(|v| v.as_str());
+    let metadata = RenderMetadata::new(
+        integrator_name.unwrap_or("unknown"),
+        scene_file,
+        scene.nb_samples,
+        scene.seed,
+        start.elapsed(),
+    );
+    metadata.save(
+        &img.values["primal"].to_f32(),
+        path_str(filename)?,
+        crate::color::ColorSpace::SRGB,
+        false,
+    )?;
+    Ok(img)
+}
+
+/// Render the samples of a scene inside <output>/<name>.
+/// scene_file is only used by the metadata.
+pub fn generate_scene(
+    name: &str,
+    scene_file: &str,
+    scene: &mut Scene,
+    config: &DatasetConfig,
+    integrator: &mut IntegratorType,
+    observer: &dyn RenderObserver,
+) -> Result<Vec<DatasetSample>> {
+    let dir = PathBuf::from(&config.output).join(name);
+    std::fs::create_dir_all(&dir)?;
+
+    info!("Dataset {}: reference", name);
+    scene.nb_samples = config.reference_samples;
+    scene.seed = Some(REFERENCE_SEED);
+    scene.output_variance = false;
+    let filename = dir.join("reference.exr");
+    render(scene, config, integrator, observer, scene_file, &filename)?;
+
+    let mut samples = vec![];
+    for &seed in &config.seeds {
+        info!("Dataset {}: seed {}", name, seed);
+        let seed_dir = dir.join(seed.to_string());
+        std::fs::create_dir_all(&seed_dir)?;
+        scene.nb_samples = config.noisy_samples;
+        scene.seed = Some(seed);
+        scene.output_variance = true;
+        let filename = seed_dir.join("noisy.exr");
+        let img = render(scene, config, integrator, observer, scene_file, &filename)?;
+        match img.values.get("variance") {
+            Some(variance) => save_exr(&variance.to_f32(), &seed_dir.join("variance.exr"))?,
+            None => warn!("The integrator does not provide the variance buffer"),
+        }
+        let features =
+            IntegratorType::Primal(Box::new(IntegratorFeatures)).compute(scene, observer);
+        for buffer in &["albedo", "normal"] {
+            save_exr(
+                &features.values[*buffer].to_f32(),
+                &seed_dir.join(format!("{}.exr", buffer)),
+            )?;
+        }
+
+        let relative = |filename: &str| format!("{}/{}/{}", name, seed, filename);
+        samples.push(DatasetSample {
+            scene: name.to_string(),
+            seed,
+            noisy: relative("noisy.exr"),
+            variance: relative("variance.exr"),
+            albedo: relative("albedo.exr"),
+            normal: relative("normal.exr"),
+            reference: format!("{}/reference.exr", name),
+        });
+    }
+    Ok(samples)
+}
+
+/// Render all the scenes of the dataset and write dataset.json
+pub fn generate(
+    config: &DatasetConfig,
+    integrators: &IntegratorManager,
+    observer: &dyn RenderObserver,
+) -> Result<Vec<DatasetSample>> {
+    if config.noisy_samples == 0 || config.reference_samples == 0 {
+        return Err(Error::InvalidData(
+            "the number of samples needs to be positive".to_string(),
+        ));
+    }
+    let name = config
+        .integrator
+        .get("type")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| Error::InvalidData("the integrator type is missing".to_string()))?;
+    let mut params = config.integrator.clone();
+    if let Some(params) = params.as_object_mut() {
+        params.remove("type");
+    }
+    let mut integrator = integrators.create_from_json(name, &params)?;
+
+    let mut samples = vec![];
+    let mut names = vec![];
+    for filename in &config.scenes {
+        // The scenes are identified by their file name
+        let name = Path::new(filename)
+            .file_stem()
+            .and_then(std::ffi::OsStr::to_str)
+            .ok_or_else(|| Error::InvalidData(format!("invalid scene name {}", filename)))?;
+        if names.contains(&name) {
+            return Err(Error::InvalidData(format!(
+                "duplicated scene name {}",
+                name
+            )));
+        }
+        names.push(name);
+        let mut scene = SceneLoaderManager::default().load(filename.clone())?;
+        if let Some(s) = config.image_scale {
+            for camera in scene.cameras_mut() {
+                camera.scale_image(s);
+            }
+        }
+        samples.extend(generate_scene(
+            name,
+            filename,
+            &mut scene,
+            config,
+            &mut integrator,
+            observer,
+        )?);
+    }
+
+    let index = std::fs::File::create(Path::new(&config.output).join("dataset.json"))?;
+    serde_json::to_writer_pretty(index, &samples)?;
+    Ok(samples)
+}
@@ -1,12 +1,21 @@
+use crate::env_guiding::EnvironmentGuide;
 use crate::geometry::Mesh;
-use crate::math::{sample_uniform_sphere, Distribution1D};
+use crate::light_bvh::{DirectionCone, LightBVH, LightBounds};
+use crate::light_grid::LightGrid;
+use crate::math::{
+    sample_uniform_sphere, AliasTable, Distribution1D, Distribution1DConstruct, Distribution2D,
+    Frame,
+};
+use crate::samplers::Sampler;
 use crate::structure::*;
 use cgmath::*;
+use std::sync::Arc;
 
 pub struct LightSampling<'a> {
     pub emitter: &'a dyn Emitter,
     pub pdf: PDF,
     pub p: Point3<f32>,
+    pub p_error: Vector3<f32>,
     pub n: Vector3<f32>,
     pub d: Vector3<f32>,
     pub weight: Color,
@@ -41,47 +50,383 @@ pub trait Emitter: Send + Sync {
     fn sample_direct(&self, p: &Point3<f32>, r: f32, uv: Point2<f32>) -> LightSampling;
     fn flux(&self) -> Color;
     fn emitted_luminance(&self, d: Vector3<f32>) -> Color;
+    /// Used by the light BVH (None for the infinite emitters)
+    fn bounds(&self) -> Option<LightBounds>;
 }
 
+/// Latitude-longitude environment map (y is up) with its importance sampling distribution
+pub struct EnvironmentMap {
+    pub image: Bitmap,
+    /// Average luminance over the sphere of directions
+    pub average: Color,
+    pub mis_compensation: bool,
+    distribution: Distribution2D,
+    /// Orientation of the map in the scene (see rotate)
+    to_world: Matrix3<f32>,
+}
+
+impl EnvironmentMap {
+    /// mis_compensation: subtract the average from the sampling values before building
+    /// the distribution (Karlik et al., "MIS Compensation", 2019). The directions darker
+    /// than the average are left to the BSDF sampling, which reduces the variance when both
+    /// are combined with MIS (but is biased without BSDF sampling). Useful when the map
+    /// is low-res or blurred compared to the BSDF lobes.
+    pub fn new(image: Bitmap, mis_compensation: bool) -> EnvironmentMap {
+        let (w, h) = (image.size.x as usize, image.size.y as usize);
+        let sin_theta = |y: usize| (std::f32::consts::PI * (y as f32 + 0.5) / h as f32).sin();
+        let luminance = |c: &Color| {
+            let l = c.luminance();
+            if l.is_finite() {
+                l.max(0.0)
+            } else {
+                0.0
+            }
+        };
+
+        // Averages weighted by the solid angle of the texels
+        let mut average = Color::zero();
+        let (mut average_luminance, mut norm) = (0.0, 0.0);
+        for (i, c) in image.colors.iter().enumerate() {
+            let s = sin_theta(i / w);
+            average += *c * s;
+            average_luminance += luminance(c) * s;
+            norm += s;
+        }
+        average /= norm;
+        average_luminance /= norm;
+
+        let values = |offset: f32| {
+            image
+                .colors
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (luminance(c) - offset).max(0.0) * sin_theta(i / w))
+                .collect::<Vec<_>>()
+        };
+        let mut sampling_values = values(0.0);
+        if mis_compensation {
+            let compensated = values(average_luminance);
+            // A constant map has no texel above the average
+            if compensated.iter().any(|v| *v > 0.0) {
+                sampling_values = compensated;
+            }
+        }
+        let distribution = Distribution2D::new(&sampling_values, Vector2::new(w, h));
+        EnvironmentMap {
+            image,
+            average,
+            mis_compensation,
+            distribution,
+            to_world: Matrix3::identity(),
+        }
+    }
+
+    /// Turn the map around the up axis (azimuth, counterclockwise seen from above),
+    /// after tilting it around the x axis (elevation, the horizon goes up at -z)
+    pub fn rotate(mut self, azimuth: Deg<f32>, elevation: Deg<f32>) -> EnvironmentMap {
+        self.to_world = Matrix3::from_angle_y(azimuth) * Matrix3::from_angle_x(elevation);
+        self
+    }
+    fn to_map(&self, d: Vector3<f32>) -> Vector3<f32> {
+        self.to_world.transpose() * d
+    }
+
+    pub(crate) fn to_direction(uv: Point2<f32>) -> Vector3<f32> {
+        let theta = uv.y * std::f32::consts::PI;
+        let phi = uv.x * 2.0 * std::f32::consts::PI;
+        let sin_theta = theta.sin();
+        Vector3::new(sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos())
+    }
+    pub(crate) fn to_uv(d: Vector3<f32>) -> Point2<f32> {
+        let d = d.normalize();
+        let mut phi = d.x.atan2(-d.z);
+        if phi < 0.0 {
+            phi += 2.0 * std::f32::consts::PI;
+        }
+        Point2::new(
+            phi * 0.5 * std::f32::consts::FRAC_1_PI,
+            d.y.clamp(-1.0, 1.0).acos() * std::f32::consts::FRAC_1_PI,
+        )
+    }
+    /// Conversion of the [0, 1]^2 density to solid angle
+    pub(crate) fn jacobian(uv: Point2<f32>) -> f32 {
+        let sin_theta = (uv.y * std::f32::consts::PI).sin();
+        if sin_theta <= 0.0 {
+            0.0
+        } else {
+            1.0 / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
+        }
+    }
+
+    /// Nearest texel (same resolution as the sampling distribution)
+    pub fn eval(&self, d: Vector3<f32>) -> Color {
+        let uv = Self::to_uv(self.to_map(d));
+        let x = ((uv.x * self.image.size.x as f32) as u32).min(self.image.size.x - 1);
+        let y = ((uv.y * self.image.size.y as f32) as u32).min(self.image.size.y - 1);
+        self.image.pixel(Point2::new(x, y))
+    }
+    /// Returns the direction and its pdf (solid angle)
+    pub fn sample(&self, u: Point2<f32>) -> (Vector3<f32>, f32) {
+        let (uv, pdf) = self.distribution.sample(u);
+        (
+            self.to_world * Self::to_direction(uv),
+            pdf * Self::jacobian(uv),
+        )
+    }
+    /// Density (solid angle) of a direction
+    pub fn pdf(&self, d: Vector3<f32>) -> f32 {
+        let uv = Self::to_uv(self.to_map(d));
+        self.distribution.pdf(uv) * Self::jacobian(uv)
+    }
+}
+
+/// Sun seen as a disk of finite solid angle, darker on its edges (linear limb darkening:
+/// the radiance is scaled by 1 - u (1 - mu), mu the cosine on the surface of the sun).
+/// The unit disk is mapped onto the cone of directions preserving the areas,
+/// so the directions are sampled exactly proportionally to the radiance.
+pub struct SunDisk {
+    /// Radiance at the center of the disk
+    pub radiance: Color,
+    /// Angular radius in radians (0.00465 for the sun seen from the earth)
+    pub radius: f32,
+    /// Limb darkening coefficient u in [0, 1] (about 0.6 for the visible light)
+    pub limb_darkening: f32,
+    frame: Frame,
+    /// 1 - cos(radius), without cancellation for the small disks
+    one_minus_cos: f32,
+}
+
+impl SunDisk {
+    pub fn new(
+        direction: Vector3<f32>,
+        radius: f32,
+        radiance: Color,
+        limb_darkening: f32,
+    ) -> SunDisk {
+        assert!(radius > 0.0 && radius < std::f32::consts::PI);
+        assert!((0.0..=1.0).contains(&limb_darkening));
+        SunDisk {
+            radiance,
+            radius,
+            limb_darkening,
+            frame: Frame::new(direction.normalize()),
+            one_minus_cos: 2.0 * (0.5 * radius).sin().powi(2),
+        }
+    }
+
+    /// Direction toward the center of the disk
+    pub fn direction(&self) -> Vector3<f32> {
+        self.frame.to_world(Vector3::unit_z())
+    }
+
+    pub fn solid_angle(&self) -> f32 {
+        2.0 * std::f32::consts::PI * self.one_minus_cos
+    }
+
+    /// Integral of the radiance over the disk
+    /// (irradiance of a surface facing the sun, up to the cosines inside the disk)
+    pub fn irradiance(&self) -> Color {
+        self.radiance * (self.solid_angle() * self.average_profile())
+    }
+
+    /// Squared distance to the center of the unit disk, None outside of the sun
+    fn disk_radius2(&self, d: Vector3<f32>) -> Option<f32> {
+        let l = self.frame.to_local(d.normalize());
+        if l.z <= 0.0 {
+            return None;
+        }
+        let s2 = (l.x * l.x + l.y * l.y) / (1.0 + l.z) / self.one_minus_cos;
+        if s2 <= 1.0 {
+            Some(s2)
+        } else {
+            None
+        }
+    }
+    fn profile(&self, s2: f32) -> f32 {
+        1.0 - self.limb_darkening * (1.0 - (1.0 - s2).max(0.0).sqrt())
+    }
+    fn average_profile(&self) -> f32 {
+        1.0 - self.limb_darkening / 3.0
+    }
+
+    pub fn eval(&self, d: Vector3<f32>) -> Color {
+        match self.disk_radius2(d) {
+            Some(s2) => self.radiance * self.profile(s2),
+            None => Color::zero(),
+        }
+    }
+    /// Returns the direction and its pdf (solid angle)
+    pub fn sample(&self, u: Point2<f32>) -> (Vector3<f32>, f32) {
+        // Mixture of the uniform disk and of the density proportional to mu
+        let uniform = (1.0 - self.limb_darkening) / self.average_profile();
+        let s2 = if u.x < uniform {
+            u.x / uniform
+        } else {
+            let v = (u.x - uniform) / (1.0 - uniform);
+            1.0 - (1.0 - v).powf(2.0 / 3.0)
+        };
+        let t = s2 * self.one_minus_cos;
+        let sin_theta = (t * (2.0 - t)).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u.y;
+        let d = self.frame.to_world(Vector3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            1.0 - t,
+        ));
+        (
+            d,
+            self.profile(s2) / (self.average_profile() * self.solid_angle()),
+        )
+    }
+    /// Density (solid angle) of a direction
+    pub fn pdf(&self, d: Vector3<f32>) -> f32 {
+        match self.disk_radius2(d) {
+            Some(s2) => self.profile(s2) / (self.average_profile() * self.solid_angle()),
+            None => 0.0,
+        }
+    }
+}
+
+/// Distant lighting: uniform, or an environment map scaled by the luminance,
+/// and an optional sun disk
 pub struct EnvironmentLight {
     pub luminance: Color,
-    pub world_radius: f32,
-    pub world_position: Point3<f32>,
+    pub map: Option<EnvironmentMap>,
+    pub sun: Option<SunDisk>,
+    /// Bounding sphere of the scene (see Scene::bounding_sphere)
+    pub world: BoundingSphere,
+}
+impl EnvironmentLight {
+    /// The sampling of the map leaves the dark directions to the BSDF sampling
+    /// (biased for the integrators sampling only the emitters)
+    pub fn mis_compensated(&self) -> bool {
+        self.map.as_ref().map_or(false, |m| m.mis_compensation)
+    }
+
+    /// Average radiance of the sky (without the sun)
+    fn sky_average(&self) -> Color {
+        match &self.map {
+            Some(map) => self.luminance * map.average,
+            None => self.luminance,
+        }
+    }
+
+    /// Probability to sample the sun rather than the sky: proportional to their power,
+    /// but each one keeps at least 10% of the samples (MIS with the BSDF sampling)
+    fn sun_probability(&self) -> f32 {
+        match &self.sun {
+            Some(sun) => {
+                let sun = sun.irradiance().luminance();
+                let sky = 4.0 * std::f32::consts::PI * self.sky_average().luminance();
+                if sun + sky > 0.0 {
+                    (sun / (sun + sky)).clamp(0.1, 0.9)
+                } else {
+                    0.5
+                }
+            }
+            None => 0.0,
+        }
+    }
+
+    fn sky_pdf(&self, d: Vector3<f32>) -> f32 {
+        match &self.map {
+            Some(map) => map.pdf(d),
+            None => 0.25 * std::f32::consts::FRAC_1_PI,
+        }
+    }
+
+    /// Returns the direction and its pdf (solid angle), r chooses between the sun and the sky
+    pub fn sample_direction(&self, r: f32, uv: Point2<f32>) -> (Vector3<f32>, f32) {
+        let sky = |uv| match &self.map {
+            Some(map) => map.sample(uv),
+            None => (
+                sample_uniform_sphere(uv),
+                0.25 * std::f32::consts::FRAC_1_PI,
+            ),
+        };
+        match &self.sun {
+            Some(sun) => {
+                let d = if r < self.sun_probability() {
+                    sun.sample(uv).0
+                } else {
+                    sky(uv).0
+                };
+                (d, self.direction_pdf(d))
+            }
+            None => sky(uv),
+        }
+    }
+    pub fn direction_pdf(&self, d: Vector3<f32>) -> f32 {
+        match &self.sun {
+            Some(sun) => {
+                let p_sun = self.sun_probability();
+                p_sun * sun.pdf(d) + (1.0 - p_sun) * self.sky_pdf(d)
+            }
+            None => self.sky_pdf(d),
+        }
+    }
 }
 impl Emitter for EnvironmentLight {
     fn sample_position(&self, _s: f32, uv: Point2<f32>) -> SampledPosition {
         // TODO: Check this function
         let d = sample_uniform_sphere(uv);
-        let pdf = 1.0 / (self.world_radius * self.world_radius * std::f32::consts::PI * 4.0);
+        let pdf = 1.0 / self.world.area();
         SampledPosition {
-            p: self.world_position + d * self.world_radius,
+            p: self.world.center + d * self.world.radius,
+            p_error: Vector3::zero(),
             n: -d,
             pdf: PDF::Area(pdf),
         }
     }
-    fn direct_pdf(&self, _light_sampling: &LightSamplingPDF) -> PDF {
-        unimplemented!();
+    fn direct_pdf(&self, light_sampling: &LightSamplingPDF) -> PDF {
+        PDF::SolidAngle(self.direction_pdf(light_sampling.dir))
     }
-    fn sample_direct(&self, _p: &Point3<f32>, _r: f32, _uv: Point2<f32>) -> LightSampling {
-        unimplemented!();
+    fn sample_direct(&self, p: &Point3<f32>, r: f32, uv: Point2<f32>) -> LightSampling {
+        let (d, pdf) = self.sample_direction(r, uv);
+        let weight = if pdf == 0.0 {
+            Color::zero()
+        } else {
+            self.emitted_luminance(d) / pdf
+        };
+        LightSampling {
+            emitter: self,
+            pdf: PDF::SolidAngle(pdf),
+            // Point outside of the scene bounding sphere (for the visibility test)
+            p: self.world.outside_point(p, d),
+            p_error: Vector3::zero(),
+            n: -d,
+            d,
+            weight,
+        }
     }
     fn flux(&self) -> Color {
-        std::f32::consts::PI * self.world_radius.powi(2) * self.luminance
+        let mut average = self.sky_average();
+        if let Some(sun) = &self.sun {
+            average += sun.irradiance() * (0.25 * std::f32::consts::FRAC_1_PI);
+        }
+        // Flux through the disk of the scene facing each direction
+        self.world.disk_area() * average
     }
-    fn emitted_luminance(&self, _d: Vector3<f32>) -> Color {
-        self.luminance
+    fn emitted_luminance(&self, d: Vector3<f32>) -> Color {
+        let sky = match &self.map {
+            Some(map) => self.luminance * map.eval(d),
+            None => self.luminance,
+        };
+        match &self.sun {
+            Some(sun) => sky + sun.eval(d),
+            None => sky,
+        }
+    }
+    fn bounds(&self) -> Option<LightBounds> {
+        None
     }
 }
 
 impl Emitter for Mesh {
     fn direct_pdf(&self, light_sampling: &LightSamplingPDF) -> PDF {
-        let cos_light = light_sampling.n.dot(-light_sampling.dir).max(0.0);
-        if cos_light == 0.0 {
-            PDF::SolidAngle(0.0)
-        } else {
-            let geom_inv = (light_sampling.p - light_sampling.o).magnitude2() / cos_light;
-            PDF::SolidAngle(self.pdf() * geom_inv) // TODO: Check
-        }
+        let cos_light = light_sampling.n.dot(-light_sampling.dir);
+        let dist = (light_sampling.p - light_sampling.o).magnitude();
+        PDF::Area(self.pdf()).to_solid_angle(dist, cos_light)
     }
 
     fn flux(&self) -> Color {
@@ -102,17 +447,8 @@ impl Emitter for Mesh {
 
         // Compute the geometry
         let pdf = match sampled_pos.pdf {
-            PDF::Area(v) => {
-                let cos_light = sampled_pos.n.dot(-d).max(0.0);
-                if cos_light == 0.0 {
-                    PDF::SolidAngle(0.0)
-                } else {
-                    // FIXME: Make the conversion as a method
-                    PDF::SolidAngle((v * dist * dist) / cos_light)
-                }
-            }
-            PDF::SolidAngle(v) => PDF::SolidAngle(v),
             PDF::Discrete(_v) => panic!("Discrete pdf is not handled yet"),
+            pdf => pdf.to_solid_angle(dist, sampled_pos.n.dot(-d)),
         };
 
         let emission = if pdf.is_zero() {
@@ -124,6 +460,7 @@ impl Emitter for Mesh {
             emitter: self,
             pdf,
             p: sampled_pos.p,
+            p_error: sampled_pos.p_error,
             n: sampled_pos.n,
             d,
             weight: emission,
@@ -133,15 +470,260 @@ impl Emitter for Mesh {
     fn sample_position(&self, s: f32, uv: Point2<f32>) -> SampledPosition {
         self.sample(s, uv)
     }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // The sampled points use the shading normals and the pdf the geometric ones
+        let mut aabb = AABB::default();
+        let mut cone: Option<DirectionCone> = None;
+        let mut add_normal = |n: Vector3<f32>| {
+            if n.magnitude2() > 0.0 {
+                let c = DirectionCone::new(n);
+                cone = Some(cone.map_or(c, |v| v.union(&c)));
+            }
+        };
+        for id in &self.indices {
+            let (v0, v1, v2) = (
+                self.vertices[id.x],
+                self.vertices[id.y],
+                self.vertices[id.z],
+            );
+            aabb = aabb.union_vec(&v0).union_vec(&v1).union_vec(&v2);
+            add_normal((v1 - v0).cross(v2 - v0));
+            if let Some(normals) = &self.normals {
+                add_normal(normals[id.x]);
+                add_normal(normals[id.y]);
+                add_normal(normals[id.z]);
+            }
+        }
+        Some(LightBounds {
+            aabb,
+            power: self.flux().channel_max(),
+            cone: cone.unwrap_or_else(DirectionCone::entire_sphere),
+        })
+    }
+}
+
+/// Strategy used to select the emitter to sample
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum EmitterSelection {
+    Uniform,
+    /// Proportional to the flux
+    #[default]
+    Power,
+    /// Light BVH: power, distance and orientation from the shading point
+    /// (the selections without shading point use the power)
+    LightBVH,
+    /// Power among the emitters seen from the cell of the shading point (see LightGrid),
+    /// built with the acceleration data structure before the rendering
+    LightGrid,
+}
+
+impl std::str::FromStr for EmitterSelection {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(EmitterSelection::Uniform),
+            "power" => Ok(EmitterSelection::Power),
+            "bvh" => Ok(EmitterSelection::LightBVH),
+            "grid" => Ok(EmitterSelection::LightGrid),
+            _ => Err(format!(
+                "unknown emitter selection: {} (uniform, power, bvh, grid)",
+                s
+            )),
+        }
+    }
+}
+
+/// Changes of the emitters not yet applied to the selection structures
+#[derive(Clone, Default)]
+struct EmitterChanges {
+    /// The flux of some emitters changed (CDF and alias table)
+    power: bool,
+    /// Emitters whose bounds changed in the light BVH
+    refit: Vec<usize>,
+    /// Emitters were added or removed (everything is rebuilt)
+    rebuild: bool,
+}
+
+/// Selection structures of the emitters. They do not borrow the emitters
+/// so the scene can keep them between the renderings (see Scene::emitters_sampler)
+/// and only rebuild the parts invalidated by the changes.
+#[derive(Clone)]
+pub struct EmitterSelectionData {
+    pub emitters_cdf: Distribution1D,
+    /// Used for the selection (same probabilities as emitters_cdf)
+    pub emitters_alias: AliasTable,
+    /// Selection used (power if the light BVH cannot be built)
+    pub selection: EmitterSelection,
+    /// Selection asked when the structures were built
+    pub requested_selection: EmitterSelection,
+    /// Only built for EmitterSelection::LightBVH
+    pub light_bvh: Option<LightBVH>,
+    /// Only built for EmitterSelection::LightGrid, by Scene::prepare_emitters
+    /// (power selection until then)
+    pub light_grid: Option<Arc<LightGrid>>,
+    /// Environment sampling learned by Scene::prepare_emitters (see Scene::env_guiding)
+    pub environment_guide: Option<Arc<EnvironmentGuide>>,
+    changes: EmitterChanges,
+}
+
+impl EmitterSelectionData {
+    pub fn new(emitters: &[&dyn Emitter], selection: EmitterSelection) -> EmitterSelectionData {
+        let (emitters_cdf, emitters_alias) = Self::build_cdf(emitters);
+        let mut data = EmitterSelectionData {
+            emitters_cdf,
+            emitters_alias,
+            selection,
+            requested_selection: selection,
+            light_bvh: None,
+            light_grid: None,
+            environment_guide: None,
+            changes: EmitterChanges::default(),
+        };
+        if selection == EmitterSelection::LightBVH && !emitters.is_empty() {
+            match emitters
+                .iter()
+                .map(|e| e.bounds())
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(bounds) => data.light_bvh = Some(LightBVH::new(&bounds)),
+                None => data.unbounded_emitters(),
+            }
+        }
+        data
+    }
+
+    /// Construct the CDF for all the emitters
+    fn build_cdf(emitters: &[&dyn Emitter]) -> (Distribution1D, AliasTable) {
+        let emitters_cdf = {
+            let mut cdf_construct = Distribution1DConstruct::new(emitters.len());
+            emitters
+                .iter()
+                .map(|e| e.flux())
+                .for_each(|f| cdf_construct.add(f.channel_max()));
+            cdf_construct.normalize()
+        };
+        let emitters_alias = AliasTable::new(&emitters_cdf);
+        (emitters_cdf, emitters_alias)
+    }
+
+    fn unbounded_emitters(&mut self) {
+        warn!("Light BVH: some emitters are not bounded, use the power selection");
+        self.selection = EmitterSelection::Power;
+        self.light_bvh = None;
+    }
+
+    /// The emitter id changed (flux or geometry), applied by update
+    pub fn emitter_changed(&mut self, id: usize) {
+        self.changes.power = true;
+        self.changes.refit.push(id);
+    }
+
+    /// Emitters were added or removed, applied by update
+    pub fn emitters_changed(&mut self) {
+        self.changes.rebuild = true;
+    }
+
+    /// Some changes are not applied yet
+    pub fn is_outdated(&self) -> bool {
+        let c = &self.changes;
+        c.rebuild || c.power || !c.refit.is_empty()
+    }
+
+    /// Apply the changes: the CDF is rebuilt if some flux changed and the
+    /// light BVH is only refitted, unless emitters were added or removed.
+    pub fn update(&mut self, emitters: &[&dyn Emitter]) {
+        let changes = std::mem::take(&mut self.changes);
+        if changes.rebuild {
+            *self = EmitterSelectionData::new(emitters, self.requested_selection);
+            return;
+        }
+        if changes.power || !changes.refit.is_empty() {
+            // The visibility and the power of the cells are out of date
+            self.light_grid = None;
+        }
+        if changes.power {
+            let (emitters_cdf, emitters_alias) = Self::build_cdf(emitters);
+            self.emitters_cdf = emitters_cdf;
+            self.emitters_alias = emitters_alias;
+        }
+        for id in changes.refit {
+            let bvh = match self.light_bvh.as_mut() {
+                Some(bvh) => bvh,
+                None => break,
+            };
+            match emitters[id].bounds() {
+                Some(bounds) => bvh.refit(id, bounds),
+                None => self.unbounded_emitters(),
+            }
+        }
+    }
 }
 
 pub struct EmitterSampler<'scene> {
     pub emitters: Vec<&'scene dyn Emitter>,
-    pub emitters_cdf: Distribution1D,
+    /// Shared with the scene cache (copied on write by the changes)
+    pub data: Arc<EmitterSelectionData>,
+    /// Distant lighting, sampled apart from the emitters (see sample_environment)
+    pub environment: Option<&'scene EnvironmentLight>,
 }
 
 impl<'scene> EmitterSampler<'scene> {
-    fn pdf(&self, emitter: &dyn Emitter) -> f32 {
+    pub fn new(
+        emitters: Vec<&'scene dyn Emitter>,
+        selection: EmitterSelection,
+    ) -> EmitterSampler<'scene> {
+        let data = Arc::new(EmitterSelectionData::new(&emitters, selection));
+        EmitterSampler {
+            emitters,
+            data,
+            environment: None,
+        }
+    }
+
+    /// Add an emitter (call update before sampling), returns its id
+    pub fn add_emitter(&mut self, emitter: &'scene dyn Emitter) -> usize {
+        self.emitters.push(emitter);
+        Arc::make_mut(&mut self.data).emitters_changed();
+        self.emitters.len() - 1
+    }
+
+    /// Remove an emitter (call update before sampling),
+    /// the ids of the following emitters are shifted
+    pub fn remove_emitter(&mut self, id: usize) -> &'scene dyn Emitter {
+        Arc::make_mut(&mut self.data).emitters_changed();
+        self.emitters.remove(id)
+    }
+
+    /// Replace an emitter by its modified version (call update before sampling)
+    pub fn replace_emitter(&mut self, id: usize, emitter: &'scene dyn Emitter) {
+        self.emitters[id] = emitter;
+        Arc::make_mut(&mut self.data).emitter_changed(id);
+    }
+
+    /// Rebuild the selection structures affected by the changes
+    pub fn update(&mut self) {
+        if self.data.is_outdated() {
+            Arc::make_mut(&mut self.data).update(&self.emitters);
+        }
+    }
+
+    /// Probability to select an emitter from p
+    fn selection_pdf(&self, id: usize, p: &Point3<f32>) -> f32 {
+        debug_assert!(!self.data.is_outdated(), "emitter sampler not updated");
+        match (
+            self.data.selection,
+            &self.data.light_bvh,
+            &self.data.light_grid,
+        ) {
+            (EmitterSelection::Uniform, _, _) => 1.0 / self.emitters.len() as f32,
+            (EmitterSelection::LightBVH, Some(bvh), _) => bvh.pmf(*p, id),
+            (EmitterSelection::LightGrid, _, Some(grid)) => grid.pmf(*p, id),
+            _ => self.data.emitters_cdf.pdf(id),
+        }
+    }
+
+    fn index(&self, emitter: &dyn Emitter) -> usize {
         let emitter_addr: [usize; 2] = unsafe { std::mem::transmute(emitter) };
         for (i, e) in self.emitters.iter().enumerate() {
             let other_addr: [usize; 2] = unsafe { std::mem::transmute(*e) };
@@ -149,7 +731,7 @@ impl<'scene> EmitterSampler<'scene> {
                 //if std::ptr::eq(emitter, *e) {
                 // I need the index to retrive an info
                 // This info cannot be stored inside the Emitter
-                return self.emitters_cdf.pdf(i);
+                return i;
             }
         }
 
@@ -162,8 +744,42 @@ impl<'scene> EmitterSampler<'scene> {
         panic!("Impossible to found the emitter: {:p}", emitter);
     }
 
+    /// The emitter is the environment of the sampler
+    pub fn is_environment(&self, emitter: &dyn Emitter) -> bool {
+        self.environment.map_or(false, |env| {
+            std::ptr::eq(
+                emitter as *const dyn Emitter as *const u8,
+                env as *const EnvironmentLight as *const u8,
+            )
+        })
+    }
+
+    /// Sample the environment with the learned distribution if any
+    /// (the environment is not one of the emitters)
+    pub fn sample_environment<'a>(
+        &self,
+        env: &'a EnvironmentLight,
+        p: &Point3<f32>,
+        r: f32,
+        uv: Point2<f32>,
+    ) -> LightSampling<'a> {
+        match &self.data.environment_guide {
+            Some(guide) => guide.sample_direct(env, p, r, uv),
+            None => env.sample_direct(p, r, uv),
+        }
+    }
+
+    /// Density (solid angle) of the directions sampled by sample_environment
+    pub fn environment_pdf(&self, env: &EnvironmentLight, d: Vector3<f32>) -> f32 {
+        match &self.data.environment_guide {
+            Some(guide) => guide.direction_pdf(env, d),
+            None => env.direction_pdf(d),
+        }
+    }
+
     pub fn direct_pdf(&self, emitter: &dyn Emitter, light_sampling: &LightSamplingPDF) -> PDF {
-        emitter.direct_pdf(light_sampling) * self.pdf(emitter)
+        emitter.direct_pdf(light_sampling)
+            * self.selection_pdf(self.index(emitter), &light_sampling.o)
     }
 
     pub fn sample_light(
@@ -173,15 +789,139 @@ impl<'scene> EmitterSampler<'scene> {
         r: f32,
         uv: Point2<f32>,
     ) -> LightSampling {
+        self.sample_selected(p, self.select_emitter(p, r_sel), r, uv)
+    }
+
+    /// Same as sample_light for the sample i among the n taken at p: the selection numbers
+    /// are stratified over the selection probabilities, so the n samples are shared between
+    /// the emitters proportionally to them (up to one sample) instead of independent draws
+    pub fn sample_light_stratified(
+        &self,
+        p: &Point3<f32>,
+        (i, n): (usize, usize),
+        r_sel: f32,
+        r: f32,
+        uv: Point2<f32>,
+    ) -> LightSampling<'_> {
+        if n == 1 {
+            return self.sample_light(p, r_sel, r, uv);
+        }
+        let v = ((i as f32 + r_sel) / n as f32).min(1.0 - f32::EPSILON);
+        let selected = match (
+            self.data.selection,
+            &self.data.light_bvh,
+            &self.data.light_grid,
+        ) {
+            (EmitterSelection::Uniform, _, _)
+            | (EmitterSelection::LightBVH, Some(_), _)
+            | (EmitterSelection::LightGrid, _, Some(_)) => self.select_emitter(p, v),
+            // Inversion of the CDF: the alias table does not give contiguous strata
+            _ => {
+                let id = self.data.emitters_cdf.sample(v);
+                Some((self.data.emitters_cdf.pdf(id), self.emitters[id]))
+            }
+        };
+        self.sample_selected(p, selected.filter(|(pdf, _)| *pdf > 0.0), r, uv)
+    }
+
+    /// Sample a point on the selected emitter (no contribution without emitter)
+    fn sample_selected(
+        &self,
+        p: &Point3<f32>,
+        selected: Option<(f32, &'scene dyn Emitter)>,
+        r: f32,
+        uv: Point2<f32>,
+    ) -> LightSampling<'_> {
         // Select the point on the light
-        let (pdf_sel, emitter) = self.random_select_emitter(r_sel);
+        let (pdf_sel, emitter) = match selected {
+            Some(v) => v,
+            None => {
+                // No emitter can contribute to p
+                return LightSampling {
+                    emitter: self.emitters[0],
+                    pdf: PDF::SolidAngle(0.0),
+                    p: *p,
+                    p_error: Vector3::zero(),
+                    n: Vector3::zero(),
+                    d: Vector3::zero(),
+                    weight: Color::zero(),
+                };
+            }
+        };
         let mut res = emitter.sample_direct(p, r, uv);
         res.pdf = res.pdf * pdf_sel;
+        res.weight /= pdf_sel;
         res
     }
+
+    /// Select an emitter for the shading point p (None if no emitter can contribute)
+    pub fn select_emitter(&self, p: &Point3<f32>, v: f32) -> Option<(f32, &dyn Emitter)> {
+        debug_assert!(!self.data.is_outdated(), "emitter sampler not updated");
+        match (
+            self.data.selection,
+            &self.data.light_bvh,
+            &self.data.light_grid,
+        ) {
+            (EmitterSelection::LightBVH, Some(bvh), _) => {
+                bvh.sample(*p, v).map(|(id, pdf)| (pdf, self.emitters[id]))
+            }
+            (EmitterSelection::LightGrid, _, Some(grid)) => {
+                grid.sample(*p, v).map(|(id, pdf)| (pdf, self.emitters[id]))
+            }
+            _ => Some(self.random_select_emitter(v)),
+        }
+    }
+    /// Stochastic lightcuts: one emitter is selected inside each node of the cut for p
+    /// (at most max_cut nodes), each emitter that can contribute belongs to one node.
+    /// Without light BVH, the cut is a single node containing all the emitters.
+    pub fn select_lightcut(
+        &self,
+        p: &Point3<f32>,
+        max_cut: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Vec<(f32, &dyn Emitter)> {
+        match (self.data.selection, &self.data.light_bvh) {
+            (EmitterSelection::LightBVH, Some(bvh)) => bvh
+                .cut(*p, max_cut)
+                .into_iter()
+                .filter_map(|node| bvh.sample_from(node, *p, sampler.next()))
+                .map(|(id, pdf)| (pdf, self.emitters[id]))
+                .collect(),
+            _ => self.select_emitter(p, sampler.next()).into_iter().collect(),
+        }
+    }
+
+    /// Same as direct_pdf for the emitters selected by select_lightcut
+    pub fn lightcut_pdf(
+        &self,
+        emitter: &dyn Emitter,
+        light_sampling: &LightSamplingPDF,
+        max_cut: usize,
+    ) -> PDF {
+        match (self.data.selection, &self.data.light_bvh) {
+            (EmitterSelection::LightBVH, Some(bvh)) => {
+                let cut = bvh.cut(light_sampling.o, max_cut);
+                emitter.direct_pdf(light_sampling)
+                    * bvh.cut_pmf(&cut, light_sampling.o, self.index(emitter))
+            }
+            _ => self.direct_pdf(emitter, light_sampling),
+        }
+    }
+
+    /// Select an emitter without shading point (uniform or power selection)
     pub fn random_select_emitter(&self, v: f32) -> (f32, &dyn Emitter) {
-        let id_light = self.emitters_cdf.sample(v);
-        (self.emitters_cdf.pdf(id_light), self.emitters[id_light])
+        debug_assert!(!self.data.is_outdated(), "emitter sampler not updated");
+        if self.data.selection == EmitterSelection::Uniform {
+            let n = self.emitters.len();
+            let id_light = ((v * n as f32) as usize).min(n - 1);
+            (1.0 / n as f32, self.emitters[id_light])
+        } else {
+            let id_light = self.data.emitters_alias.sample(v);
+            (
+                self.data.emitters_alias.pdf(id_light),
+                self.emitters[id_light],
+            )
+        }
     }
 
     pub fn random_sample_emitter_position(
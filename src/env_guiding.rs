@@ -0,0 +1,176 @@
+//! Environment sampling learned from the scene: before the rendering, the first intersections
+//! of the camera rays sample the environment and the contributions of the unoccluded samples
+//! (radiance, BSDF and cosine) are accumulated in a latitude-longitude histogram, one per
+//! image block, summed and blurred at the end. The directions are then sampled
+//! proportionally to this histogram, which skips the parts of the map hidden from the
+//! visible surfaces (e.g. the sky behind the walls of an interior lit through a window).
+//! A fraction of the samples keeps the sampling of the environment itself, so the
+//! directions missed by the training are still sampled (unbiased).
+use crate::emitter::{Emitter, EnvironmentLight, EnvironmentMap, LightSampling};
+use crate::integrators::{generate_img_blocks, generate_pool};
+use crate::math::Distribution2D;
+use crate::samplers::independent::IndependentSampler;
+use crate::samplers::Sampler;
+use crate::scene::{Acceleration, Scene};
+use crate::structure::*;
+use cgmath::*;
+use rayon::prelude::*;
+
+/// Size of the histogram (longitude, latitude)
+pub const GUIDE_RESOLUTION: (usize, usize) = (64, 32);
+/// Probability to sample the environment with its own distribution
+const DEFENSIVE_PROBABILITY: f32 = 0.2;
+
+/// 3x3 box filter of the histogram (the longitude wraps around): the bins missed
+/// by the training samples get the contributions of their neighbors
+fn blur(histogram: &[f32]) -> Vec<f32> {
+    let (w, h) = GUIDE_RESOLUTION;
+    let mut blurred = vec![0.0; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let rows = y.saturating_sub(1)..(y + 2).min(h);
+            let nb_rows = rows.len();
+            let sum = rows
+                .flat_map(|ny| (0..3).map(move |i| ny * w + (x + w + i - 1) % w))
+                .map(|i| histogram[i])
+                .sum::<f32>();
+            blurred[y * w + x] = sum / (3 * nb_rows) as f32;
+        }
+    }
+    blurred
+}
+
+/// Distribution of the environment directions learned from their contributions
+/// (world space, y is up)
+pub struct EnvironmentGuide {
+    distribution: Distribution2D,
+}
+
+impl EnvironmentGuide {
+    /// Distribution proportional to the histogram (scanline order, GUIDE_RESOLUTION),
+    /// None if it is empty
+    pub fn from_histogram(histogram: &[f32]) -> Option<EnvironmentGuide> {
+        if histogram.iter().sum::<f32>() <= 0.0 {
+            return None;
+        }
+        let size = Vector2::new(GUIDE_RESOLUTION.0, GUIDE_RESOLUTION.1);
+        Some(EnvironmentGuide {
+            distribution: Distribution2D::new(histogram, size),
+        })
+    }
+
+    /// Histogram bin of a direction
+    fn bin(d: Vector3<f32>) -> usize {
+        let uv = EnvironmentMap::to_uv(d);
+        let (w, h) = GUIDE_RESOLUTION;
+        let x = ((uv.x * w as f32) as usize).min(w - 1);
+        let y = ((uv.y * h as f32) as usize).min(h - 1);
+        y * w + x
+    }
+
+    /// Train the guide with nb_samples camera rays per pixel,
+    /// each one sampling the environment once at its first intersection
+    pub fn learn(
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        env: &EnvironmentLight,
+        nb_samples: usize,
+    ) -> Option<EnvironmentGuide> {
+        let blocks = generate_img_blocks(scene, &[]);
+        let pool = generate_pool(scene);
+        let histograms = pool.install(|| {
+            blocks
+                .par_iter()
+                .enumerate()
+                .map(|(id, block)| {
+                    let mut sampler = IndependentSampler::for_task(scene.pass_seed(), id);
+                    let mut histogram = vec![0.0; GUIDE_RESOLUTION.0 * GUIDE_RESOLUTION.1];
+                    for iy in 0..block.size.y {
+                        for ix in 0..block.size.x {
+                            for _ in 0..nb_samples {
+                                let pix = Point2::new(
+                                    (ix + block.pos.x) as f32 + sampler.next(),
+                                    (iy + block.pos.y) as f32 + sampler.next(),
+                                );
+                                let ray = scene.camera.generate(pix).ray;
+                                let its = match accel.trace(&ray) {
+                                    Some(its) if its.cos_theta() > 0.0 => its,
+                                    _ => continue,
+                                };
+                                let record =
+                                    env.sample_direct(&its.p, sampler.next(), sampler.next2d());
+                                let d_local = its.frame.to_local(record.d);
+                                if !record.is_valid()
+                                    || d_local.z <= 0.0
+                                    || !accel.visible(&its.offset_toward(&record.p), &record.p)
+                                {
+                                    continue;
+                                }
+                                let f = its.mesh.bsdf.eval(
+                                    &its.tex_coords(),
+                                    &its.wi,
+                                    &d_local,
+                                    Domain::SolidAngle,
+                                );
+                                let contribution = (f * record.weight).luminance();
+                                if contribution.is_finite() && contribution > 0.0 {
+                                    histogram[Self::bin(record.d)] += contribution;
+                                }
+                            }
+                        }
+                    }
+                    histogram
+                })
+                .collect::<Vec<_>>()
+        });
+        // Summed in the order of the blocks (same guide whatever the number of threads)
+        let mut histogram = vec![0.0; GUIDE_RESOLUTION.0 * GUIDE_RESOLUTION.1];
+        for h in &histograms {
+            histogram.iter_mut().zip(h).for_each(|(a, b)| *a += b);
+        }
+        EnvironmentGuide::from_histogram(&blur(&histogram))
+    }
+
+    /// Density (solid angle) of the learned distribution
+    fn guide_pdf(&self, d: Vector3<f32>) -> f32 {
+        let uv = EnvironmentMap::to_uv(d);
+        self.distribution.pdf(uv) * EnvironmentMap::jacobian(uv)
+    }
+
+    /// Same as EnvironmentLight::sample_direct, r chooses between the learned distribution
+    /// and the one of the environment
+    pub fn sample_direct<'a>(
+        &self,
+        env: &'a EnvironmentLight,
+        p: &Point3<f32>,
+        r: f32,
+        uv: Point2<f32>,
+    ) -> LightSampling<'a> {
+        let d = if r < DEFENSIVE_PROBABILITY {
+            env.sample_direction(r / DEFENSIVE_PROBABILITY, uv).0
+        } else {
+            EnvironmentMap::to_direction(self.distribution.sample(uv).0)
+        };
+        let pdf = self.direction_pdf(env, d);
+        let weight = if pdf == 0.0 {
+            Color::zero()
+        } else {
+            env.emitted_luminance(d) / pdf
+        };
+        LightSampling {
+            emitter: env,
+            pdf: PDF::SolidAngle(pdf),
+            p: env.world.outside_point(p, d),
+            p_error: Vector3::zero(),
+            n: -d,
+            d,
+            weight,
+        }
+    }
+
+    /// Density (solid angle) of the directions sampled by sample_direct
+    pub fn direction_pdf(&self, env: &EnvironmentLight, d: Vector3<f32>) -> f32 {
+        DEFENSIVE_PROBABILITY * env.direction_pdf(d)
+            + (1.0 - DEFENSIVE_PROBABILITY) * self.guide_pdf(d)
+    }
+}
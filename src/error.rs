@@ -0,0 +1,87 @@
+use std;
+use std::fmt;
+
+/// Errors that can happen when loading a scene or reading/writing images
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Obj(tobj::LoadError),
+    #[cfg(feature = "image")]
+    Image(image::ImageError),
+    Exr(exr::error::Error),
+    /// A mesh referenced by the scene description is not in the OBJ file
+    MeshNotFound(String),
+    /// Several meshes share the name referenced by the scene description
+    MeshDuplicated(String),
+    /// No loader/writer for this file extension
+    UnsupportedFormat(String),
+    /// Rustlight was built without the needed feature
+    MissingFeature(&'static str),
+    /// Malformed or incomplete file content
+    InvalidData(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Json(e) => write!(f, "JSON error: {}", e),
+            Error::Obj(e) => write!(f, "OBJ loading error: {:?}", e),
+            #[cfg(feature = "image")]
+            Error::Image(e) => write!(f, "image error: {}", e),
+            Error::Exr(e) => write!(f, "EXR error: {}", e),
+            Error::MeshNotFound(name) => write!(f, "mesh {} not found in the OBJ file", name),
+            Error::MeshDuplicated(name) => {
+                write!(f, "several meshes named {} in the OBJ file", name)
+            }
+            Error::UnsupportedFormat(ext) => write!(f, "unsupported file format: '{}'", ext),
+            Error::MissingFeature(feature) => {
+                write!(f, "rustlight wasn't built with {} support", feature)
+            }
+            Error::InvalidData(msg) => write!(f, "invalid data: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            #[cfg(feature = "image")]
+            Error::Image(e) => Some(e),
+            Error::Exr(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+impl From<tobj::LoadError> for Error {
+    fn from(e: tobj::LoadError) -> Self {
+        Error::Obj(e)
+    }
+}
+#[cfg(feature = "image")]
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
+impl From<exr::error::Error> for Error {
+    fn from(e: exr::error::Error) -> Self {
+        Error::Exr(e)
+    }
+}
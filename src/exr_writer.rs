@@ -0,0 +1,116 @@
+use crate::error::Result;
+use crate::structure::Bitmap;
+use cgmath::{Point2, Vector2};
+use exr::prelude::{
+    AnyChannel, AnyChannels, AttributeValue, Encoding, FlatSamples, Image, Layer, LayerAttributes,
+    SpecificChannels, Text, Vec2, WritableImage,
+};
+
+/// Text of the EXR headers (Latin-1, the other characters are replaced by '?')
+fn text(s: &str) -> Text {
+    let latin1 = s
+        .chars()
+        .map(|c| if (c as u32) < 256 { c } else { '?' })
+        .collect::<String>();
+    Text::from(latin1.as_str())
+}
+
+/// Header of the single part EXR outputs with the string attributes
+fn layer_attributes(attributes: &[(String, String)]) -> LayerAttributes {
+    let mut layer = LayerAttributes::default();
+    for (name, value) in attributes {
+        layer
+            .other
+            .insert(text(name), AttributeValue::Text(text(value)));
+    }
+    layer
+}
+
+/// Save the RGB (and A with an alpha channel) bitmap with string attributes
+/// in its header (lossless ZIP compression). The exr crate reads the pixels
+/// from the bitmap while compressing the scanlines, without copying the channels.
+pub fn save_bitmap(img: &Bitmap, filename: &str, attributes: &[(String, String)]) -> Result<()> {
+    let size = (img.size.x as usize, img.size.y as usize);
+    let attributes = layer_attributes(attributes);
+    let pixel = |Vec2(x, y): Vec2<usize>| Point2::new(x as u32, y as u32);
+    match img.alpha {
+        None => {
+            let channels = SpecificChannels::rgb(|p: Vec2<usize>| {
+                let c = img.pixel(pixel(p));
+                (c.r, c.g, c.b)
+            });
+            Image::from_layer(Layer::new(
+                size,
+                attributes,
+                Encoding::SMALL_LOSSLESS,
+                channels,
+            ))
+            .write()
+            .to_file(filename)?;
+        }
+        Some(_) => {
+            let channels = SpecificChannels::rgba(|p: Vec2<usize>| {
+                let c = img.pixel(pixel(p));
+                (c.r, c.g, c.b, img.alpha(pixel(p)))
+            });
+            Image::from_layer(Layer::new(
+                size,
+                attributes,
+                Encoding::SMALL_LOSSLESS,
+                channels,
+            ))
+            .write()
+            .to_file(filename)?;
+        }
+    }
+    Ok(())
+}
+
+/// R, G, B (and A with an alpha channel) channels of the bitmap, named "<layer>.<channel>"
+/// (an empty layer name gives the main RGB layer)
+pub fn bitmap_channels(layer: &str, img: &Bitmap) -> Vec<AnyChannel<FlatSamples>> {
+    let prefix = if layer.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", layer)
+    };
+    let mut channels = "RGB"
+        .chars()
+        .enumerate()
+        .map(|(c, name)| {
+            let values = img.colors.iter().map(|v| v.get(c as u8)).collect();
+            AnyChannel::new(
+                text(&format!("{}{}", prefix, name)),
+                FlatSamples::F32(values),
+            )
+        })
+        .collect::<Vec<_>>();
+    if let Some(ref alpha) = img.alpha {
+        channels.push(AnyChannel::new(
+            text(&format!("{}A", prefix)),
+            FlatSamples::F32(alpha.clone()),
+        ));
+    }
+    channels
+}
+
+/// Save the float channels (values in scanline order, grouped in layers by their
+/// "<layer>.<channel>" names) with string attributes in the header of the single part
+/// (lossless ZIP compression)
+pub fn save_channels(
+    filename: &str,
+    size: Vector2<u32>,
+    channels: Vec<AnyChannel<FlatSamples>>,
+    attributes: &[(String, String)],
+) -> Result<()> {
+    let channels = AnyChannels::sort(channels.into_iter().collect());
+    Image::from_layer(Layer::new(
+        (size.x as usize, size.y as usize),
+        layer_attributes(attributes),
+        Encoding::SMALL_LOSSLESS,
+        channels,
+    ))
+    .write()
+    .to_file(filename)?;
+    Ok(())
+}
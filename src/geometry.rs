@@ -1,18 +1,242 @@
 use crate::bsdfs;
+use crate::error::{Error, Result};
 use crate::math::{uniform_sample_triangle, Distribution1D, Distribution1DConstruct};
 use crate::structure::*;
+use crate::transform::Transform;
 use cgmath::*;
 use std;
 use tobj;
 
 // FIXME: Support custom UV
+/// Bounding sphere of the meshes from the union of their bounding boxes
+pub fn bounding_sphere(meshes: &[Mesh]) -> BoundingSphere {
+    meshes
+        .iter()
+        .fold(AABB::default(), |aabb, m| aabb.union_aabb(&m.aabb()))
+        .bounding_sphere()
+}
+
+/// Same triangles in the same order (the acceleration data structure can be reused)
+pub fn same_geometry(a: &[Mesh], b: &[Mesh]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.vertices == b.vertices && a.indices == b.indices)
+}
+
+/// Number of triangles removed by remove_degenerate_triangles, by cause
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct DegenerateTriangles {
+    /// Same vertex used twice
+    pub repeated_indices: usize,
+    /// Vertex missing or with a NaN or infinite position
+    pub invalid_vertices: usize,
+    /// Aligned vertices (no geometric normal)
+    pub zero_area: usize,
+}
+impl DegenerateTriangles {
+    pub fn total(&self) -> usize {
+        self.repeated_indices + self.invalid_vertices + self.zero_area
+    }
+}
+impl std::fmt::Display for DegenerateTriangles {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} degenerated triangles ({} with repeated indices, {} with invalid vertices, {} with a zero area)",
+            self.total(),
+            self.repeated_indices,
+            self.invalid_vertices,
+            self.zero_area
+        )
+    }
+}
+
+/// Remove the triangles without a geometric normal, which would give NaN shading frames,
+/// then the vertices not used anymore (with their normals and texture coordinates)
+pub fn remove_degenerate_triangles(
+    vertices: &mut Vec<Vector3<f32>>,
+    indices: &mut Vec<Vector3<usize>>,
+    normals: &mut Option<Vec<Vector3<f32>>>,
+    uv: &mut Option<Vec<Vector2<f32>>>,
+) -> DegenerateTriangles {
+    let mut removed = DegenerateTriangles::default();
+    let valid = |i: usize| match vertices.get(i) {
+        Some(v) => v.x.is_finite() && v.y.is_finite() && v.z.is_finite(),
+        None => false,
+    };
+    indices.retain(|id| {
+        if id.x == id.y || id.y == id.z || id.x == id.z {
+            removed.repeated_indices += 1;
+            false
+        } else if !(valid(id.x) && valid(id.y) && valid(id.z)) {
+            removed.invalid_vertices += 1;
+            false
+        } else {
+            let (v0, v1, v2) = (vertices[id.x], vertices[id.y], vertices[id.z]);
+            let n = (v1 - v0).cross(v2 - v0).magnitude2();
+            if n > 0.0 && n.is_finite() {
+                true
+            } else {
+                removed.zero_area += 1;
+                false
+            }
+        }
+    });
+    if removed.total() == 0 {
+        return removed;
+    }
+
+    // Compact the vertices in their original order
+    let mut remap = vec![None; vertices.len()];
+    let mut used = vec![];
+    for id in indices.iter_mut() {
+        for k in 0..3 {
+            let v = id[k];
+            id[k] = *remap[v].get_or_insert_with(|| {
+                used.push(v);
+                used.len() - 1
+            });
+        }
+    }
+    *vertices = used.iter().map(|&v| vertices[v]).collect();
+    if let Some(normals) = normals {
+        *normals = used.iter().map(|&v| normals[v]).collect();
+    }
+    if let Some(uv) = uv {
+        *uv = used.iter().map(|&v| uv[v]).collect();
+    }
+    removed
+}
+
+/// Default crease angle (degrees) of the normals generated for the OBJ meshes
+pub const DEFAULT_CREASE_ANGLE: f32 = 60.0;
+
+/// Smooth vertex normals of a mesh without normals. The normal of a triangle corner
+/// averages the normals of the triangles sharing its position (weighted by their area)
+/// whose angle with the triangle is below the crease angle (degrees, 0: flat normals).
+/// The vertices on the creases are duplicated (with their texture coordinates).
+pub fn smooth_normals(
+    vertices: &mut Vec<Vector3<f32>>,
+    indices: &mut [Vector3<usize>],
+    uv: &mut Option<Vec<Vector2<f32>>>,
+    crease_angle: f32,
+) -> Vec<Vector3<f32>> {
+    let face_normals = indices
+        .iter()
+        .map(|id| {
+            let (v0, v1, v2) = (vertices[id.x], vertices[id.y], vertices[id.z]);
+            (v1 - v0).cross(v2 - v0)
+        })
+        .collect::<Vec<_>>();
+    let directions = face_normals
+        .iter()
+        .map(|n| {
+            if n.magnitude2() > 0.0 {
+                n.normalize()
+            } else {
+                Vector3::zero()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Triangles around each position (the vertices split by the texture seams are merged)
+    let mut positions = std::collections::HashMap::new();
+    let position_ids = vertices
+        .iter()
+        .map(|v| {
+            let nb_positions = positions.len();
+            *positions
+                .entry([v.x.to_bits(), v.y.to_bits(), v.z.to_bits()])
+                .or_insert(nb_positions)
+        })
+        .collect::<Vec<_>>();
+    let mut triangles = vec![vec![]; positions.len()];
+    for (t, id) in indices.iter().enumerate() {
+        for k in 0..3 {
+            triangles[position_ids[id[k]]].push(t);
+        }
+    }
+
+    let cos_crease = crease_angle.to_radians().cos();
+    let mut new_vertices = vec![];
+    let mut new_uv = uv.as_ref().map(|_| vec![]);
+    let mut normals = vec![];
+    let mut corners = std::collections::HashMap::new();
+    for (t, id) in indices.iter_mut().enumerate() {
+        for k in 0..3 {
+            let v = id[k];
+            // Degenerated triangles take the normal of all their neighbors
+            let mut n = triangles[position_ids[v]]
+                .iter()
+                .filter(|&&o| {
+                    directions[t].is_zero()
+                        || (crease_angle > 0.0 && directions[t].dot(directions[o]) >= cos_crease)
+                })
+                .fold(Vector3::zero(), |n, &o| n + face_normals[o]);
+            if n.magnitude2() > 0.0 {
+                n = n.normalize();
+            } else {
+                n = directions[t];
+            }
+            let key = (v, [n.x.to_bits(), n.y.to_bits(), n.z.to_bits()]);
+            id[k] = *corners.entry(key).or_insert_with(|| {
+                new_vertices.push(vertices[v]);
+                if let (Some(new_uv), Some(uv)) = (&mut new_uv, uv.as_ref()) {
+                    new_uv.push(uv[v]);
+                }
+                normals.push(n);
+                normals.len() - 1
+            });
+        }
+    }
+    *vertices = new_vertices;
+    *uv = new_uv;
+    normals
+}
+
+/// Texture coordinates generated for the meshes without them (see Mesh::project_uv)
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UVProjection {
+    /// World coordinates on the plane perpendicular to the main axis of the mesh normals
+    Planar,
+    /// World coordinates on the plane perpendicular to the main axis of each triangle
+    Box,
+    /// Longitude and latitude around the center of the mesh (y is up)
+    Spherical,
+}
+
+/// Axis of the largest component
+fn main_axis(n: Vector3<f32>) -> usize {
+    let n = Vector3::new(n.x.abs(), n.y.abs(), n.z.abs());
+    if n.x >= n.y && n.x >= n.z {
+        0
+    } else if n.y >= n.z {
+        1
+    } else {
+        2
+    }
+}
+
+/// Coordinates of p on the plane perpendicular to the axis
+fn planar_uv(p: Vector3<f32>, axis: usize) -> Vector2<f32> {
+    match axis {
+        0 => Vector2::new(p.z, p.y),
+        1 => Vector2::new(p.x, p.z),
+        _ => Vector2::new(p.x, p.y),
+    }
+}
+
 /// Read obj file format and build a list of meshes
 /// for now, only add diffuse color
-/// custom texture coordinates or normals are not supported yet
-pub fn load_obj(file_name: &std::path::Path) -> Result<Vec<Mesh>, tobj::LoadError> {
+/// The meshes without normals get smooth normals (see smooth_normals)
+pub fn load_obj(file_name: &std::path::Path, crease_angle: f32) -> Result<Vec<Mesh>> {
     println!("Try to load {:?}", file_name);
     let (models, materials) = tobj::load_obj(file_name)?;
-    let wk = file_name.parent().unwrap();
+    let wk = file_name
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""));
     info!("Working directory for loading the scene: {:?}", wk);
 
     // Read models
@@ -21,54 +245,69 @@ pub fn load_obj(file_name: &std::path::Path) -> Result<Vec<Mesh>, tobj::LoadErro
         info!("Loading model {}", m.name);
         let mesh = m.mesh;
         // Load vertex position
-        let indices = mesh
+        let mut indices = mesh
             .indices
             .chunks(3)
             .map(|i| Vector3::new(i[0] as usize, i[1] as usize, i[2] as usize))
             .collect::<Vec<_>>();
         info!(" - triangles: {}", indices.len());
-        let vertices = mesh
+        let mut vertices = mesh
             .positions
             .chunks(3)
             .map(|i| Vector3::new(i[0], i[1], i[2]))
             .collect();
-        // Load normal
-        let normals = if mesh.normals.is_empty() {
+        let mut uv = if mesh.texcoords.is_empty() {
             None
         } else {
             Some(
-                mesh.normals
-                    .chunks(3)
-                    .map(|i| Vector3::new(i[0], i[1], i[2]))
+                mesh.texcoords
+                    .chunks(2)
+                    .map(|i| Vector2::new(i[0], i[1]))
                     .collect(),
             )
         };
-
-        let uv = if mesh.texcoords.is_empty() {
+        let mut normals = if mesh.normals.is_empty() {
             None
         } else {
             Some(
-                mesh.texcoords
-                    .chunks(2)
-                    .map(|i| Vector2::new(i[0], i[1]))
+                mesh.normals
+                    .chunks(3)
+                    .map(|i| Vector3::new(i[0], i[1], i[2]))
                     .collect(),
             )
         };
 
+        let removed =
+            remove_degenerate_triangles(&mut vertices, &mut indices, &mut normals, &mut uv);
+        if removed.total() > 0 {
+            warn!("{}: {}", m.name, removed);
+        }
+
+        // Load normal
+        let normals = match normals {
+            Some(normals) => normals,
+            None => {
+                info!(" - smooth normals (crease angle: {})", crease_angle);
+                smooth_normals(&mut vertices, &mut indices, &mut uv, crease_angle)
+            }
+        };
+
         // Read materials and push the mesh
-        let mut tri_mesh = Mesh::new(m.name, vertices, indices, normals, uv);
+        let mut tri_mesh = Mesh::new(m.name, vertices, indices, Some(normals), uv);
 
         // Load the BSDF informations
         tri_mesh.bsdf = {
             if let Some(id) = mesh.material_id {
                 info!(" - BSDF id: {}", id);
                 let mat = &materials[id];
+                tri_mesh.material_name = mat.name.clone();
                 if !mat.diffuse_texture.is_empty() {
                     let path_texture = wk.join(&mat.diffuse_texture);
+                    let path_texture = path_texture.to_str().ok_or_else(|| {
+                        Error::InvalidData(format!("texture path {:?}", path_texture))
+                    })?;
                     Box::new(bsdfs::diffuse::BSDFDiffuse {
-                        diffuse: bsdfs::BSDFColor::TextureColor(bsdfs::Texture::load(
-                            path_texture.to_str().unwrap(),
-                        )),
+                        diffuse: bsdfs::BSDFColor::texture(path_texture)?,
                     })
                 } else {
                     let diffuse_color = Color::new(mat.diffuse[0], mat.diffuse[1], mat.diffuse[2]);
@@ -98,11 +337,19 @@ pub struct Mesh {
     pub uv: Option<Vec<Vector2<f32>>>,
     // Other informations
     pub bsdf: Box<dyn bsdfs::BSDF>,
+    pub material_name: String,
     pub emission: Color,
     pub cdf: Distribution1D,
 }
 
 impl Mesh {
+    /// Bounding box of the vertices
+    pub fn aabb(&self) -> AABB {
+        self.vertices
+            .iter()
+            .fold(AABB::default(), |aabb, v| aabb.union_vec(v))
+    }
+
     pub fn new(
         name: String,
         vertices: Vec<Vector3<f32>>,
@@ -130,6 +377,7 @@ impl Mesh {
             bsdf: Box::new(bsdfs::diffuse::BSDFDiffuse {
                 diffuse: bsdfs::BSDFColor::UniformColor(Color::zero()),
             }),
+            material_name: "default".to_string(),
             emission: Color::zero(),
             cdf: dist_const.normalize(),
         }
@@ -139,6 +387,104 @@ impl Mesh {
         1.0 / (self.cdf.normalization)
     }
 
+    /// Replace the texture coordinates by a projection of the triangles. The vertices shared
+    /// by triangles with different coordinates (box edges, spherical seam) are duplicated.
+    pub fn project_uv(&mut self, projection: UVProjection) {
+        let center = self.aabb().center();
+        let face_normal = |id: &Vector3<usize>| {
+            let (v0, v1, v2) = (
+                self.vertices[id.x],
+                self.vertices[id.y],
+                self.vertices[id.z],
+            );
+            (v1 - v0).cross(v2 - v0)
+        };
+        let mesh_axis = main_axis(
+            self.indices
+                .iter()
+                .fold(Vector3::zero(), |n, id| n + face_normal(id)),
+        );
+        let triangles_uv = self
+            .indices
+            .iter()
+            .map(|id| {
+                let mut uv = [id.x, id.y, id.z].map(|i| {
+                    let p = self.vertices[i];
+                    match projection {
+                        UVProjection::Planar => planar_uv(p, mesh_axis),
+                        UVProjection::Box => planar_uv(p, main_axis(face_normal(id))),
+                        UVProjection::Spherical => {
+                            let d = p - center;
+                            let cos_theta = if d.magnitude2() > 0.0 {
+                                d.y / d.magnitude()
+                            } else {
+                                1.0
+                            };
+                            Vector2::new(
+                                0.5 + d.z.atan2(d.x) * 0.5 * std::f32::consts::FRAC_1_PI,
+                                cos_theta.clamp(-1.0, 1.0).acos() * std::f32::consts::FRAC_1_PI,
+                            )
+                        }
+                    }
+                });
+                // The triangles crossing the seam of the sphere wrap around
+                if projection == UVProjection::Spherical {
+                    let (min, max) = uv.iter().fold((1.0f32, 0.0f32), |(min, max), t| {
+                        (min.min(t.x), max.max(t.x))
+                    });
+                    if max - min > 0.5 {
+                        uv.iter_mut().filter(|t| t.x < 0.5).for_each(|t| t.x += 1.0);
+                    }
+                }
+                uv
+            })
+            .collect::<Vec<_>>();
+
+        let mut vertices = vec![];
+        let mut normals = self.normals.as_ref().map(|_| vec![]);
+        let mut uv = vec![];
+        let mut corners = std::collections::HashMap::new();
+        let (old_vertices, old_normals) = (&self.vertices, &self.normals);
+        for (id, triangle_uv) in self.indices.iter_mut().zip(triangles_uv) {
+            for (k, t) in triangle_uv.iter().enumerate() {
+                let v = id[k];
+                id[k] = *corners
+                    .entry((v, [t.x.to_bits(), t.y.to_bits()]))
+                    .or_insert_with(|| {
+                        vertices.push(old_vertices[v]);
+                        if let (Some(normals), Some(n)) = (&mut normals, old_normals) {
+                            normals.push(n[v]);
+                        }
+                        uv.push(*t);
+                        uv.len() - 1
+                    });
+            }
+        }
+        self.vertices = vertices;
+        self.normals = normals;
+        self.uv = Some(uv);
+    }
+
+    /// Move the mesh (positions and normals) and update the area distribution
+    pub fn transform(&mut self, t: &Transform) {
+        for v in &mut self.vertices {
+            *v = t.transform_point(Point3::from_vec(*v)).to_vec();
+        }
+        if let Some(normals) = &mut self.normals {
+            for n in normals {
+                *n = t.transform_normal(*n).normalize();
+            }
+        }
+        let mut dist_const = Distribution1DConstruct::new(self.indices.len());
+        for id in &self.indices {
+            let v0 = self.vertices[id.x];
+            let v1 = self.vertices[id.y];
+            let v2 = self.vertices[id.z];
+            dist_const.add((v1 - v0).cross(v2 - v0).magnitude() * 0.5);
+        }
+        self.cdf = dist_const.normalize();
+    }
+
     // FIXME: reuse random number
     pub fn sample(&self, s: f32, v: Point2<f32>) -> SampledPosition {
         assert!(self.normals.is_some());
@@ -159,10 +505,12 @@ impl Mesh {
         let b = uniform_sample_triangle(v);
 
         // interpol the point
-        let pos = v0 * b[0] + v1 * b[1] + v2 * (1.0 as f32 - b[0] - b[1]);
-        let normal = n0 * b[0] + n1 * b[1] + n2 * (1.0 as f32 - b[0] - b[1]);
+        let b = [b[0], b[1], 1.0 as f32 - b[0] - b[1]];
+        let pos = v0 * b[0] + v1 * b[1] + v2 * b[2];
+        let normal = n0 * b[0] + n1 * b[1] + n2 * b[2];
         SampledPosition {
             p: Point3::from_vec(pos),
+            p_error: barycentric_error(b, [v0, v1, v2]),
             n: normal,
             pdf: PDF::Area(1.0 / (self.cdf.normalization)),
         }
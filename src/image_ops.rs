@@ -0,0 +1,212 @@
+//! Image processing operations on the bitmaps (crop, resize, flip, channels, exposure),
+//! shared by the command line tools, the reconstructions and the validation.
+//! The operations return a new bitmap and keep the alpha channel.
+use crate::structure::{Bitmap, Color};
+use cgmath::{Point2, Vector2};
+
+/// Reconstruction filter of Bitmap::resize
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ResizeFilter {
+    /// Average of the covered pixels (nearest pixel when upsampling)
+    #[default]
+    Box,
+    /// Windowed sinc with 3 lobes: sharper, but rings around the edges
+    Lanczos,
+}
+impl std::str::FromStr for ResizeFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(ResizeFilter::Box),
+            "lanczos" => Ok(ResizeFilter::Lanczos),
+            _ => Err(format!("unknown resize filter: {} (box or lanczos)", s)),
+        }
+    }
+}
+impl ResizeFilter {
+    /// Radius in pixels of the output image
+    fn radius(&self) -> f32 {
+        match self {
+            ResizeFilter::Box => 0.5,
+            ResizeFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let sinc = |x: f32| {
+            if x.abs() < 1e-5 {
+                1.0
+            } else {
+                (std::f32::consts::PI * x).sin() / (std::f32::consts::PI * x)
+            }
+        };
+        match self {
+            // Half-open so a pixel is never counted twice
+            ResizeFilter::Box if (-0.5..0.5).contains(&x) => 1.0,
+            ResizeFilter::Lanczos if x.abs() < 3.0 => sinc(x) * sinc(x / 3.0),
+            _ => 0.0,
+        }
+    }
+
+    /// Normalized weights of the source pixels of each destination pixel (one axis).
+    /// The filter is stretched when downsampling and the pixels outside are clamped to the edges.
+    fn weights(&self, src: u32, dst: u32) -> Vec<Vec<(usize, f32)>> {
+        let ratio = src as f32 / dst as f32;
+        let scale = ratio.max(1.0);
+        let support = self.radius() * scale;
+        (0..dst)
+            .map(|i| {
+                let center = (i as f32 + 0.5) * ratio - 0.5;
+                let first = (center - support).ceil() as i64;
+                let last = (center + support).floor() as i64;
+                let mut weights = (first..=last)
+                    .map(|j| {
+                        let w = self.weight((j as f32 - center) / scale);
+                        (j.clamp(0, i64::from(src) - 1) as usize, w)
+                    })
+                    .filter(|(_, w)| *w != 0.0)
+                    .collect::<Vec<_>>();
+                let sum = weights.iter().map(|(_, w)| w).sum::<f32>();
+                weights.iter_mut().for_each(|(_, w)| *w /= sum);
+                weights
+            })
+            .collect()
+    }
+}
+
+/// Values of the pixels (x, y) of an image of the given size, read at index(x, y)
+fn gather<T: Copy>(values: &[T], size: Vector2<u32>, index: &dyn Fn(u32, u32) -> usize) -> Vec<T> {
+    let mut res = Vec::with_capacity((size.x * size.y) as usize);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            res.push(values[index(x, y)]);
+        }
+    }
+    res
+}
+
+impl Bitmap {
+    /// Image from the color of each pixel
+    pub fn from_fn(size: Vector2<u32>, f: impl Fn(Point2<u32>) -> Color) -> Bitmap {
+        let mut colors = Vec::with_capacity((size.x * size.y) as usize);
+        for y in 0..size.y {
+            for x in 0..size.x {
+                colors.push(f(Point2::new(x, y)));
+            }
+        }
+        Bitmap {
+            size,
+            colors,
+            alpha: None,
+        }
+    }
+
+    fn gather(&self, size: Vector2<u32>, index: &dyn Fn(u32, u32) -> usize) -> Bitmap {
+        Bitmap {
+            size,
+            colors: gather(&self.colors, size, index),
+            alpha: self.alpha.as_ref().map(|a| gather(a, size, index)),
+        }
+    }
+
+    /// Part of the image of the given size at pos (clipped by the image borders)
+    pub fn crop(&self, pos: Point2<u32>, size: Vector2<u32>) -> Bitmap {
+        assert!(
+            pos.x < self.size.x && pos.y < self.size.y,
+            "Crop at {:?} outside of the image {:?}",
+            pos,
+            self.size
+        );
+        let size = Vector2::new(
+            size.x.min(self.size.x - pos.x),
+            size.y.min(self.size.y - pos.y),
+        );
+        self.gather(size, &|x, y| {
+            ((pos.y + y) * self.size.x + pos.x + x) as usize
+        })
+    }
+
+    /// Mirror image (left and right swapped)
+    pub fn flip_horizontal(&self) -> Bitmap {
+        let w = self.size.x;
+        self.gather(self.size, &|x, y| (y * w + w - 1 - x) as usize)
+    }
+
+    /// Upside down image
+    pub fn flip_vertical(&self) -> Bitmap {
+        let (w, h) = (self.size.x, self.size.y);
+        self.gather(self.size, &|x, y| ((h - 1 - y) * w + x) as usize)
+    }
+
+    /// Resampled image (separable filter, horizontal then vertical pass)
+    pub fn resize(&self, size: Vector2<u32>, filter: ResizeFilter) -> Bitmap {
+        assert!(size.x > 0 && size.y > 0, "Resize to an empty image");
+        let (weights_x, weights_y) = (
+            filter.weights(self.size.x, size.x),
+            filter.weights(self.size.y, size.y),
+        );
+        let resample = |values: &[Color]| {
+            let mut rows = Vec::with_capacity((size.x * self.size.y) as usize);
+            for row in values.chunks(self.size.x as usize) {
+                for w in &weights_x {
+                    rows.push(w.iter().fold(Color::zero(), |c, &(i, w)| c + row[i] * w));
+                }
+            }
+            let mut res = Vec::with_capacity((size.x * size.y) as usize);
+            for w in &weights_y {
+                for x in 0..size.x as usize {
+                    res.push(w.iter().fold(Color::zero(), |c, &(i, w)| {
+                        c + rows[i * size.x as usize + x] * w
+                    }));
+                }
+            }
+            res
+        };
+        let alpha = self.alpha.as_ref().map(|alpha| {
+            let alpha = alpha.iter().map(|a| Color::value(*a)).collect::<Vec<_>>();
+            resample(&alpha).iter().map(|c| c.r).collect()
+        });
+        Bitmap {
+            size,
+            colors: resample(&self.colors),
+            alpha,
+        }
+    }
+
+    /// Values of a channel (0: red, 1: green, 2: blue)
+    pub fn channel(&self, c: u8) -> Vec<f32> {
+        self.colors.iter().map(|v| v.get(c)).collect()
+    }
+
+    /// Luminance of the pixels
+    pub fn luminance(&self) -> Vec<f32> {
+        self.colors.iter().map(|v| v.luminance()).collect()
+    }
+
+    /// Image with f applied to the color of each pixel
+    pub fn map(&self, f: impl Fn(Color) -> Color) -> Bitmap {
+        Bitmap {
+            size: self.size,
+            colors: self.colors.iter().map(|c| f(*c)).collect(),
+            alpha: self.alpha.clone(),
+        }
+    }
+
+    /// Image with the colors brighter than max scaled down to this luminance
+    /// (removes the fireflies of the small and very bright sources, like the sun in an HDRI)
+    pub fn clamp_luminance(&self, max: f32) -> Bitmap {
+        self.map(|c| {
+            let l = c.luminance();
+            if l > max {
+                c * (max / l)
+            } else {
+                c
+            }
+        })
+    }
+
+    /// Exposure correction in stops (the pixels are multiplied by 2^stops)
+    pub fn exposure(&mut self, stops: f32) {
+        self.scale(2.0_f32.powf(stops));
+    }
+}
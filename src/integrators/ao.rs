@@ -7,8 +7,13 @@ pub struct IntegratorAO {
 }
 
 impl Integrator for IntegratorAO {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
-        compute_mc(self, accel, scene)
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        compute_mc(self, accel, scene, observer)
     }
 }
 impl IntegratorMC for IntegratorAO {
@@ -21,7 +26,11 @@ impl IntegratorMC for IntegratorAO {
         _: &EmitterSampler,
     ) -> Color {
         let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let ray = scene.camera.generate(pix);
+        let camera_sample = scene.camera.sample_lens(pix, sampler);
+        if camera_sample.weight == 0.0 {
+            return Color::zero();
+        }
+        let ray = camera_sample.ray;
 
         // Do the intersection for the first path
         let its = match accel.trace(&ray) {
@@ -44,19 +53,16 @@ impl IntegratorMC for IntegratorAO {
         };
 
         // Check the new intersection distance
-        let ray = Ray::new(its.p, d_world);
-        match accel.trace(&ray) {
-            None => Color::one(),
-            Some(new_its) => match self.max_distance {
-                None => Color::zero(),
-                Some(d) => {
-                    if new_its.dist > d {
-                        Color::one()
-                    } else {
-                        Color::zero()
-                    }
-                }
-            },
+        let ray = its.spawn_ray(d_world);
+        let visible = match (accel.trace(&ray), self.max_distance) {
+            (None, _) => true,
+            (Some(new_its), Some(d)) => new_its.dist > d,
+            (Some(_), None) => false,
+        };
+        if visible {
+            Color::value(camera_sample.weight)
+        } else {
+            Color::zero()
         }
     }
 }
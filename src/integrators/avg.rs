@@ -8,7 +8,12 @@ pub struct IntegratorAverage {
 }
 
 impl Integrator for IntegratorAverage {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
         // Get the output file type
         let output_ext = match std::path::Path::new(&scene.output_img_path).extension() {
             None => panic!("No file extension provided"),
@@ -22,39 +27,46 @@ impl Integrator for IntegratorAverage {
         let mut csv = std::fs::File::create(base_output_img_path.clone() + "_time.csv").unwrap();
 
         // Other values
-        let mut bitmap: Option<BufferCollection> = None;
+        let mut accums: HashMap<String, AccumBuffer> = HashMap::new();
+        let mut bitmap;
         let mut iteration = 1;
         let start = Instant::now();
 
         loop {
+            // New random numbers for each pass (with a seed)
+            scene.set_pass(iteration - 1);
             let new_bitmap = match self.integrator {
-                IntegratorType::Primal(ref mut v) => v.compute(accel, scene),
-                IntegratorType::Gradient(ref mut v) => v.compute_gradients(accel, scene),
+                IntegratorType::Primal(ref mut v) => v.compute(accel, scene, observer),
+                IntegratorType::Gradient(ref mut v) => v.compute_gradients(accel, scene, observer),
             };
-            if iteration == 1 {
-                bitmap = Some(new_bitmap);
-            } else {
-                bitmap.as_mut().unwrap().scale(iteration as f32);
-                bitmap.as_mut().unwrap().accumulate_bitmap(&new_bitmap);
-                bitmap.as_mut().unwrap().scale(1.0 / (iteration + 1) as f32);
+            // Average all the buffers over the passes
+            let mut average = BufferCollection::copy(new_bitmap.pos, new_bitmap.size, &new_bitmap);
+            for (name, b) in &new_bitmap.values {
+                let accum = accums
+                    .entry(name.clone())
+                    .or_insert_with(|| AccumBuffer::new(b.size));
+                accum.add_bitmap(b);
+                average.accumulate_local(&accum.mean_bitmap(), name);
             }
+            bitmap = average;
 
             // Save the bitmap for the current iteration
             let imgout_path_str = format!("{}_{}.{}", base_output_img_path, iteration, output_ext);
-            match &self.integrator {
-                IntegratorType::Primal(_) => bitmap
-                    .as_ref()
-                    .unwrap()
-                    .save("primal", imgout_path_str.as_str()),
+            let recons_img = match &self.integrator {
+                IntegratorType::Primal(_) => None,
                 IntegratorType::Gradient(ref v) => {
                     let start_recons = Instant::now();
-                    let recons_img = v.reconstruct().reconstruct(scene, bitmap.as_ref().unwrap());
+                    let recons_img = v.reconstruct().reconstruct(scene, &bitmap);
                     let elapsed_recons = start_recons.elapsed();
                     info!("Reconstruction time: {:?}", elapsed_recons);
-                    // Save the bitmap for the current iteration
-                    recons_img.save("primal", imgout_path_str.as_str());
+                    Some(recons_img)
                 }
             };
+            let current_img = recons_img.as_ref().unwrap_or(&bitmap);
+            observer.on_pass_done(iteration, &current_img.values["primal"].to_f32());
+            if let Err(e) = current_img.save("primal", imgout_path_str.as_str()) {
+                error!("Impossible to save {}: {}", imgout_path_str, e);
+            }
 
             // Check the time elapsed when we started the rendering...
             let elapsed = start.elapsed();
@@ -74,18 +86,20 @@ impl Integrator for IntegratorAverage {
             // Update the number of iterations
             iteration += 1;
         }
+        scene.set_pass(0);
 
-        if let Some(bitmap) = bitmap {
-            match &self.integrator {
-                IntegratorType::Primal(_) => bitmap,
-                IntegratorType::Gradient(v) => {
-                    info!("Do the final reconstruction");
-                    v.reconstruct().reconstruct(scene, &bitmap)
+        match &self.integrator {
+            IntegratorType::Primal(_) => {
+                // The passes are averaged, but the number of samples adds up
+                if bitmap.values.contains_key("spp") {
+                    bitmap.scale_buffer(iteration as f32, "spp");
                 }
+                bitmap
+            }
+            IntegratorType::Gradient(v) => {
+                info!("Do the final reconstruction");
+                v.reconstruct().reconstruct(scene, &bitmap)
             }
-        } else {
-            let buffernames = vec![String::from("primal")];
-            BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames)
         }
     }
 }
@@ -0,0 +1,701 @@
+use crate::error::{Error, Result};
+use crate::integrators::registry::{IntegratorFactory, IntegratorParam, IntegratorParams};
+use crate::integrators::*;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Built-in integrator described by functions
+/// (the parameters are the same for the command line, the JSON objects and Python)
+struct Builtin {
+    about: &'static str,
+    params: fn() -> Vec<IntegratorParam>,
+    create: fn(&IntegratorParams) -> Result<IntegratorType>,
+}
+impl IntegratorFactory for Builtin {
+    fn about(&self) -> &str {
+        self.about
+    }
+    fn params(&self) -> Vec<IntegratorParam> {
+        (self.params)()
+    }
+    fn create(&self, params: &IntegratorParams) -> Result<IntegratorType> {
+        (self.create)(params)
+    }
+}
+
+/// Built-in integrators registered by default inside the IntegratorManager
+pub(crate) fn factories() -> Vec<(&'static str, Rc<dyn IntegratorFactory>)> {
+    let builtin = |about, params, create| -> Rc<dyn IntegratorFactory> {
+        Rc::new(Builtin {
+            about,
+            params,
+            create,
+        })
+    };
+    vec![
+        (
+            "path",
+            builtin(
+                "path tracing generating path from the sensor",
+                path_params,
+                create_path,
+            ),
+        ),
+        (
+            "light",
+            builtin(
+                "light tracing generating path from the lights",
+                light_params,
+                create_light,
+            ),
+        ),
+        (
+            "vpl",
+            builtin(
+                "brute force virtual point light integrator",
+                vpl_params,
+                create_vpl,
+            ),
+        ),
+        (
+            "mrcs",
+            builtin(
+                "virtual point lights with matrix row-column sampling",
+                mrcs_params,
+                create_mrcs,
+            ),
+        ),
+        (
+            "volpath",
+            builtin(
+                "volumetric path tracing with next event estimation in the medium",
+                volpath_params,
+                create_volpath,
+            ),
+        ),
+        ("ao", builtin("ambiant occlusion", ao_params, create_ao)),
+        (
+            "debug",
+            builtin(
+                "false-color views (normals, uv, depth, mesh, cost, BSDF density)",
+                debug_params,
+                create_debug,
+            ),
+        ),
+        (
+            "direct",
+            builtin("direct lighting", direct_params, create_direct),
+        ),
+        (
+            "gradient-path",
+            builtin(
+                "gradient path tracing",
+                gradient_path_params,
+                create_gradient_path,
+            ),
+        ),
+        (
+            "gradient-path-explicit",
+            builtin(
+                "gradient path tracing",
+                gradient_explicit_params,
+                create_gradient_explicit,
+            ),
+        ),
+        (
+            "pssmlt",
+            builtin(
+                "path tracing with MCMC sampling",
+                pssmlt_params,
+                create_pssmlt,
+            ),
+        ),
+        (
+            "vol_primitives",
+            builtin(
+                "BRE/Beam/Planes estimators",
+                vol_primitives_params,
+                create_vol_primitives,
+            ),
+        ),
+        (
+            "cryptomatte",
+            builtin(
+                "cryptomatte ID and coverage layers (by mesh and material)",
+                cryptomatte_params,
+                create_cryptomatte,
+            ),
+        ),
+    ]
+}
+
+fn parse<T: FromStr>(params: &IntegratorParams, name: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    params[name].parse::<T>().map_err(|e| {
+        Error::InvalidData(format!(
+            "Wrong parameter {} ({}): {}",
+            name, params[name], e
+        ))
+    })
+}
+
+/// "inf" for no limit
+fn infinity<T: FromStr>(params: &IntegratorParams, name: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    match params[name].as_str() {
+        "inf" => Ok(None),
+        _ => parse(params, name).map(Some),
+    }
+}
+
+/// 0 to disable
+fn optional<T: FromStr + PartialOrd + Default>(
+    params: &IntegratorParams,
+    name: &str,
+) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    let v = parse::<T>(params, name)?;
+    Ok(if v > T::default() { Some(v) } else { None })
+}
+
+/// At least 1
+fn positive<T: FromStr + PartialOrd + Default>(params: &IntegratorParams, name: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    optional(params, name)?
+        .ok_or_else(|| Error::InvalidData(format!("The parameter {} needs to be > 0", name)))
+}
+
+fn max_param() -> IntegratorParam {
+    IntegratorParam::new(
+        "max",
+        "maximum path depth ('inf' for no limit)",
+        Some("inf"),
+    )
+    .short("m")
+}
+
+fn path_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new("strategy", "all, bsdf or emitter", Some("all")).short("s"),
+        IntegratorParam::new(
+            "lightcuts",
+            "emitter sampling with stochastic lightcuts of this maximum size (0 to disable)",
+            Some("0"),
+        ),
+        IntegratorParam::new(
+            "rr",
+            "russian roulette: throughput or adjoint",
+            Some("throughput"),
+        ),
+        IntegratorParam::new(
+            "rr_depth",
+            "depth of the first vertex using the russian roulette",
+            Some("0"),
+        ),
+        IntegratorParam::new(
+            "splitting",
+            "maximum number of subpaths generated at a vertex",
+            Some("1"),
+        ),
+        IntegratorParam::new(
+            "max_diffuse",
+            "maximum number of diffuse bounces",
+            Some("inf"),
+        ),
+        IntegratorParam::new(
+            "max_glossy",
+            "maximum number of glossy bounces",
+            Some("inf"),
+        ),
+        IntegratorParam::new(
+            "max_specular",
+            "maximum number of specular bounces",
+            Some("inf"),
+        ),
+        IntegratorParam::new(
+            "min_roughness",
+            "minimum roughness after the first glossy or diffuse bounce (0 to disable)",
+            Some("0"),
+        ),
+    ]
+}
+fn create_path(params: &IntegratorParams) -> Result<IntegratorType> {
+    let strategy = match params["strategy"].as_str() {
+        "all" => explicit::path::IntegratorPathTracingStrategies::All,
+        "bsdf" => explicit::path::IntegratorPathTracingStrategies::BSDF,
+        "emitter" => explicit::path::IntegratorPathTracingStrategies::Emitter,
+        s => return Err(Error::InvalidData(format!("invalid strategy: {}", s))),
+    };
+    let mode = match params["rr"].as_str() {
+        "throughput" => explicit::path::RussianRouletteMode::Throughput,
+        "adjoint" => explicit::path::RussianRouletteMode::Adjoint,
+        s => {
+            return Err(Error::InvalidData(format!(
+                "invalid russian roulette: {}",
+                s
+            )))
+        }
+    };
+    Ok(IntegratorType::Primal(Box::new(
+        explicit::path::IntegratorPathTracing {
+            max_depth: infinity(params, "max")?,
+            depth_limits: crate::paths::path::DepthLimits {
+                diffuse: infinity(params, "max_diffuse")?,
+                glossy: infinity(params, "max_glossy")?,
+                specular: infinity(params, "max_specular")?,
+            },
+            strategy,
+            lightcuts: optional(params, "lightcuts")?,
+            rr: explicit::path::RussianRouletteOptions {
+                mode,
+                start_depth: parse(params, "rr_depth")?,
+                max_splitting: positive(params, "splitting")?,
+            },
+            min_roughness: optional(params, "min_roughness")?,
+        },
+    )))
+}
+
+fn light_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new("lightpaths", "all, surface or volume", Some("all")).short("p"),
+    ]
+}
+fn create_light(params: &IntegratorParams) -> Result<IntegratorType> {
+    let (render_surface, render_volume) = match params["lightpaths"].as_str() {
+        "all" => (true, true),
+        "surface" => (true, false),
+        "volume" => (false, true),
+        s => return Err(Error::InvalidData(format!("invalid lightpaths: {}", s))),
+    };
+    Ok(IntegratorType::Primal(Box::new(
+        explicit::light::IntegratorLightTracing {
+            max_depth: infinity(params, "max")?,
+            render_surface,
+            render_volume,
+        },
+    )))
+}
+
+fn vpl_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new(
+            "clamping",
+            "clamping distance of the geometry term (0 to disable)",
+            Some("0.0"),
+        )
+        .short("b"),
+        IntegratorParam::new("nb_vpl", "number of VPLs", Some("128")).short("n"),
+        IntegratorParam::new(
+            "passes",
+            "number of passes with a new set of VPLs",
+            Some("1"),
+        )
+        .short("p"),
+        IntegratorParam::new(
+            "visibility_cache",
+            "cell size of the shadow ray cache (biased, 0 to disable)",
+            Some("0.0"),
+        ),
+    ]
+}
+fn create_vpl(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(
+        explicit::vpl::IntegratorVPL {
+            nb_vpl: parse(params, "nb_vpl")?,
+            max_depth: infinity(params, "max")?,
+            clamping_factor: optional(params, "clamping")?,
+            nb_passes: positive(params, "passes")?,
+            visibility_cache: optional(params, "visibility_cache")?
+                .map(visibility_cache::VisibilityCacheConfig::new),
+        },
+    )))
+}
+
+fn mrcs_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new("nb_vpl", "number of VPLs", Some("1024")).short("n"),
+        IntegratorParam::new(
+            "rows",
+            "number of pixels gathering all the VPLs per image block",
+            Some("32"),
+        )
+        .short("r"),
+        IntegratorParam::new(
+            "clusters",
+            "number of VPL clusters per image block",
+            Some("64"),
+        )
+        .short("c"),
+    ]
+}
+fn create_mrcs(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(
+        explicit::mrcs::IntegratorMRCS {
+            nb_vpl: parse(params, "nb_vpl")?,
+            max_depth: infinity(params, "max")?,
+            nb_rows: positive(params, "rows")?,
+            nb_clusters: positive(params, "clusters")?,
+        },
+    )))
+}
+
+fn volpath_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::flag(
+            "equiangular",
+            "also sample the scattering distances toward the emitters",
+        ),
+        IntegratorParam::new(
+            "single_scattering",
+            "number of stratified distances of the single scattering of the camera rays (0 to disable)",
+            Some("0"),
+        ),
+    ]
+}
+fn create_volpath(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(
+        explicit::volpath::IntegratorVolPath {
+            max_depth: infinity(params, "max")?,
+            equiangular: parse(params, "equiangular")?,
+            single_scattering: optional(params, "single_scattering")?,
+        },
+    )))
+}
+
+fn ao_params() -> Vec<IntegratorParam> {
+    vec![
+        IntegratorParam::new(
+            "distance",
+            "maximum distance of the occluders ('inf' for no limit)",
+            Some("inf"),
+        )
+        .short("d"),
+        IntegratorParam::flag(
+            "normal_correction",
+            "also shade the back faces (flipped normals)",
+        )
+        .short("n"),
+    ]
+}
+fn create_ao(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(ao::IntegratorAO {
+        max_distance: infinity(params, "distance")?,
+        normal_correction: parse(params, "normal_correction")?,
+    })))
+}
+
+fn debug_params() -> Vec<IntegratorParam> {
+    vec![IntegratorParam::new(
+        "view",
+        "ng, ns, uv, depth, mesh, cost or density",
+        Some("ns"),
+    )]
+}
+fn create_debug(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(debug::IntegratorDebug {
+        view: params["view"].parse().map_err(Error::InvalidData)?,
+    })))
+}
+
+fn direct_params() -> Vec<IntegratorParam> {
+    vec![
+        IntegratorParam::new("bsdf", "number of BSDF samples", Some("1")).short("b"),
+        IntegratorParam::new("light", "number of light samples", Some("1")).short("l"),
+    ]
+}
+fn create_direct(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(direct::IntegratorDirect {
+        nb_bsdf_samples: parse(params, "bsdf")?,
+        nb_light_samples: parse(params, "light")?,
+    })))
+}
+
+fn recons_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new("iterations", "number of iterations", Some("50")).short("r"),
+        IntegratorParam::new(
+            "reconstruction_type",
+            "reconstruction: uniform, weighted, bagging, fft or feature",
+            Some("uniform"),
+        )
+        .short("t"),
+        IntegratorParam::new(
+            "alpha",
+            "weight of the primal image in the fft and feature reconstructions",
+            Some("0.2"),
+        ),
+        IntegratorParam::new(
+            "buffers",
+            "number of buffers of the bagging reconstruction (at most the number of samples)",
+            Some("8"),
+        ),
+    ]
+}
+fn create_recons(params: &IntegratorParams) -> Result<Box<dyn PoissonReconstruction + Sync>> {
+    let iterations = parse(params, "iterations")?;
+    Ok(match params["reconstruction_type"].as_str() {
+        "uniform" => Box::new(gradient::recons::UniformPoissonReconstruction { iterations }),
+        "weighted" => Box::new(gradient::recons::WeightedPoissonReconstruction::new(
+            iterations,
+        )),
+        "fft" => {
+            let alpha = parse::<f32>(params, "alpha")?;
+            if alpha <= 0.0 {
+                return Err(Error::InvalidData(format!(
+                    "The fft reconstruction needs alpha > 0: {}",
+                    alpha
+                )));
+            }
+            Box::new(gradient::recons::FourierPoissonReconstruction { alpha })
+        }
+        "feature" => {
+            let alpha = parse::<f32>(params, "alpha")?;
+            if alpha <= 0.0 {
+                return Err(Error::InvalidData(format!(
+                    "The feature reconstruction needs alpha > 0: {}",
+                    alpha
+                )));
+            }
+            Box::new(gradient::recons::FeaturePoissonReconstruction::new(
+                iterations, alpha,
+            ))
+        }
+        "bagging" => {
+            let nb_buffers = parse(params, "buffers")?;
+            if nb_buffers < 2 {
+                return Err(Error::InvalidData(
+                    "The bagging needs at least two buffers".to_string(),
+                ));
+            }
+            Box::new(gradient::recons::BaggingPoissonReconstruction {
+                iterations,
+                nb_buffers,
+            })
+        }
+        s => {
+            return Err(Error::InvalidData(format!(
+                "invalid reconstruction_type: {}",
+                s
+            )))
+        }
+    })
+}
+
+fn gradient_path_params() -> Vec<IntegratorParam> {
+    let mut params = recons_params();
+    params.push(
+        IntegratorParam::new(
+            "min",
+            "minimum path depth ('inf' for no limit)",
+            Some("inf"),
+        )
+        .short("n"),
+    );
+    params
+}
+fn create_gradient_path(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Gradient(Box::new(
+        gradient::path::IntegratorGradientPath {
+            max_depth: infinity(params, "max")?,
+            min_depth: infinity(params, "min")?,
+            recons: create_recons(params)?,
+        },
+    )))
+}
+
+fn gradient_explicit_params() -> Vec<IntegratorParam> {
+    let mut params = recons_params();
+    params.push(
+        IntegratorParam::new(
+            "min_survival",
+            "minimum survival probability of the russian roulette",
+            Some("1.0"),
+        )
+        .short("s"),
+    );
+    params
+}
+fn create_gradient_explicit(params: &IntegratorParams) -> Result<IntegratorType> {
+    let min_survival = parse::<f32>(params, "min_survival")?;
+    if min_survival <= 0.0 || min_survival > 1.0 {
+        return Err(Error::InvalidData(format!(
+            "The parameter min_survival needs to be in ]0, 1]: {}",
+            min_survival
+        )));
+    }
+    Ok(IntegratorType::Gradient(Box::new(
+        gradient::explicit::IntegratorGradientPathTracing {
+            max_depth: infinity(params, "max")?,
+            recons: create_recons(params)?,
+            min_survival: Some(min_survival),
+        },
+    )))
+}
+
+fn pssmlt_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new("large_prob", "probability of the large steps", Some("0.3"))
+            .short("p"),
+        IntegratorParam::new(
+            "temperatures",
+            "temperature ladder of the replica exchange (1,2,4,...)",
+            Some("1"),
+        ),
+        IntegratorParam::new(
+            "swap_frequency",
+            "probability to propose a swap between two replicas",
+            Some("0.1"),
+        ),
+        IntegratorParam::new(
+            "adaptive",
+            "adapt the small steps to this acceptance rate (e.g., 0.234, 0 to disable)",
+            Some("0"),
+        ),
+        IntegratorParam::flag(
+            "delayed_rejection",
+            "try a small step when a large step is rejected",
+        ),
+        IntegratorParam::new(
+            "checkpoint",
+            "write the state of the chains at the end (JSON, empty to disable)",
+            Some(""),
+        ),
+        IntegratorParam::new(
+            "resume",
+            "continue the chains of checkpoints (separated by commas, merged if several)",
+            Some(""),
+        ),
+    ]
+}
+fn create_pssmlt(params: &IntegratorParams) -> Result<IntegratorType> {
+    let large_prob = parse::<f32>(params, "large_prob")?;
+    if !(large_prob > 0.0 && large_prob <= 1.0) {
+        return Err(Error::InvalidData(format!(
+            "The parameter large_prob needs to be in ]0, 1]: {}",
+            large_prob
+        )));
+    }
+    let temperatures = params["temperatures"]
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::InvalidData(format!("Wrong parameter temperatures: {}", e)))?;
+    if temperatures[0] != 1.0 || temperatures.windows(2).any(|t| t[0] >= t[1]) {
+        return Err(Error::InvalidData(format!(
+            "The temperatures need to increase from 1: {:?}",
+            temperatures
+        )));
+    }
+    let swap_frequency = parse::<f32>(params, "swap_frequency")?;
+    if !(0.0..=1.0).contains(&swap_frequency) {
+        return Err(Error::InvalidData(format!(
+            "The parameter swap_frequency needs to be in [0, 1]: {}",
+            swap_frequency
+        )));
+    }
+    let adaptive = optional::<f32>(params, "adaptive")?;
+    if let Some(target) = adaptive.filter(|&t| t >= 1.0) {
+        return Err(Error::InvalidData(format!(
+            "The target acceptance rate must be in ]0, 1[: {}",
+            target
+        )));
+    }
+    let mut resume: Option<pssmlt::MLTCheckpoint> = None;
+    for filename in params["resume"].split(',').filter(|v| !v.is_empty()) {
+        let checkpoint = pssmlt::MLTCheckpoint::load(filename)?;
+        if checkpoint.temperatures != temperatures {
+            return Err(Error::InvalidData(format!(
+                "{}: the temperatures {:?} differ from {:?}",
+                filename, checkpoint.temperatures, temperatures
+            )));
+        }
+        resume = Some(match resume {
+            None => checkpoint,
+            Some(mut merged) => {
+                merged.merge(checkpoint)?;
+                merged
+            }
+        });
+    }
+    Ok(IntegratorType::Primal(Box::new(pssmlt::IntegratorPSSMLT {
+        large_prob,
+        temperatures,
+        swap_frequency,
+        adaptive,
+        delayed_rejection: parse(params, "delayed_rejection")?,
+        resume,
+        checkpoint: Some(params["checkpoint"].clone()).filter(|v| !v.is_empty()),
+        integrator: Box::new(explicit::path::IntegratorPathTracing {
+            max_depth: infinity(params, "max")?,
+            depth_limits: Default::default(),
+            strategy: explicit::path::IntegratorPathTracingStrategies::All,
+            lightcuts: None,
+            rr: Default::default(),
+            min_roughness: None,
+        }),
+    })))
+}
+
+fn vol_primitives_params() -> Vec<IntegratorParam> {
+    vec![
+        max_param(),
+        IntegratorParam::new("nb_primitive", "number of primitives", Some("128")).short("n"),
+        IntegratorParam::new("primitives", "bre, beam, plane or vrl", Some("bre")).short("p"),
+        IntegratorParam::flag(
+            "heatmap",
+            "output the number of BVH nodes visited per camera ray",
+        ),
+    ]
+}
+fn create_vol_primitives(params: &IntegratorParams) -> Result<IntegratorType> {
+    let primitives = match params["primitives"].as_str() {
+        "bre" => explicit::vol_primitives::VolPrimitivies::BRE,
+        "beam" => explicit::vol_primitives::VolPrimitivies::Beams,
+        "plane" => explicit::vol_primitives::VolPrimitivies::Planes,
+        "vrl" => explicit::vol_primitives::VolPrimitivies::VRL,
+        s => return Err(Error::InvalidData(format!("invalid primitives: {}", s))),
+    };
+    Ok(IntegratorType::Primal(Box::new(
+        explicit::vol_primitives::IntegratorVolPrimitives {
+            nb_primitive: parse(params, "nb_primitive")?,
+            max_depth: infinity(params, "max")?,
+            primitives,
+            heatmap: parse(params, "heatmap")?,
+        },
+    )))
+}
+
+fn cryptomatte_params() -> Vec<IntegratorParam> {
+    vec![
+        IntegratorParam::new("depth", "number of ID and coverage pairs", Some("6")).short("k"),
+        IntegratorParam::new(
+            "filter",
+            "pixel filter of the coverages: box or gaussian",
+            Some("box"),
+        ),
+    ]
+}
+fn create_cryptomatte(params: &IntegratorParams) -> Result<IntegratorType> {
+    Ok(IntegratorType::Primal(Box::new(
+        cryptomatte::IntegratorCryptomatte {
+            depth: parse(params, "depth")?,
+            filter: params["filter"].parse().map_err(Error::InvalidData)?,
+        },
+    )))
+}
@@ -0,0 +1,156 @@
+use crate::error::Result;
+use crate::exr_writer::{bitmap_channels, save_channels};
+use crate::integrators::ids::*;
+use crate::integrators::*;
+use crate::tools::murmur3_32;
+use exr::prelude::{AnyChannel, FlatSamples};
+
+/// Cryptomatte ID and coverage layers (by mesh and material names).
+/// The layers are written next to the output image (<output>_cryptomatte.exr)
+/// and the primal buffer contains a preview where each mesh has a random color.
+pub struct IntegratorCryptomatte {
+    /// Number of (id, coverage) pairs stored per pixel
+    pub depth: usize,
+    /// Pixel filter of the coverages
+    pub filter: PixelFilter,
+}
+
+/// Number of (id, coverage) pairs per pixel of the ids AOV
+pub const AOV_DEPTH: usize = 6;
+
+/// Convert a name to its cryptomatte ID.
+/// The exponent is changed to avoid denormals, infinities and NaNs.
+pub fn name_to_id(name: &str) -> f32 {
+    let mut h = murmur3_32(name.as_bytes(), 0);
+    let exponent = (h >> 23) & 255;
+    if exponent == 0 || exponent == 255 {
+        h ^= 1 << 23;
+    }
+    f32::from_bits(h)
+}
+
+/// Layer of the IDs with the highest coverages (depth pairs per pixel)
+/// and its attributes
+fn write_layer(
+    channels: &mut Vec<AnyChannel<FlatSamples>>,
+    attributes: &mut Vec<(String, String)>,
+    layer: &str,
+    names: &[&String],
+    ids: &IdBuffer,
+    depth: usize,
+) {
+    // Each RGBA channel set contains two (id, coverage) pairs
+    for set in 0..depth.div_ceil(2) {
+        for (c, channel) in ["R", "G", "B", "A"].iter().enumerate() {
+            let rank = set * 2 + c / 2;
+            let values = ids
+                .pixels
+                .iter()
+                .map(|p| match p.get(rank) {
+                    Some((id, coverage)) if rank < depth => {
+                        if c % 2 == 0 {
+                            f32::from_bits(*id)
+                        } else {
+                            *coverage
+                        }
+                    }
+                    _ => 0.0,
+                })
+                .collect();
+            channels.push(AnyChannel::new(
+                format!("{}{:02}.{}", layer, set, channel).as_str(),
+                FlatSamples::F32(values),
+            ));
+        }
+    }
+
+    // Metadata
+    let mut manifest = serde_json::Map::new();
+    for name in names {
+        manifest.insert(
+            name.to_string(),
+            serde_json::Value::String(format!("{:08x}", name_to_id(name).to_bits())),
+        );
+    }
+    let key = &format!("{:08x}", murmur3_32(layer.as_bytes(), 0))[..7];
+    let attribute = |name: &str| format!("cryptomatte/{}/{}", key, name);
+    attributes.push((attribute("name"), layer.to_string()));
+    attributes.push((attribute("hash"), "MurmurHash3_32".to_string()));
+    attributes.push((attribute("conversion"), "uint32_to_float32".to_string()));
+    attributes.push((
+        attribute("manifest"),
+        serde_json::Value::Object(manifest).to_string(),
+    ));
+}
+
+/// Write the cryptomatte layers of the meshes and materials of the scene,
+/// with the preview of the objects as RGB layer
+pub fn save_cryptomatte(
+    filename: &str,
+    scene: &Scene,
+    ids: &IdBuffers,
+    depth: usize,
+) -> Result<()> {
+    let mut channels = bitmap_channels("", &ids.objects.preview());
+    let mut attributes = vec![];
+    let mut mesh_names = scene.meshes.iter().map(|m| &m.name).collect::<Vec<_>>();
+    mesh_names.sort();
+    mesh_names.dedup();
+    let mut material_names = scene
+        .meshes
+        .iter()
+        .map(|m| &m.material_name)
+        .collect::<Vec<_>>();
+    material_names.sort();
+    material_names.dedup();
+    write_layer(
+        &mut channels,
+        &mut attributes,
+        "CryptoObject",
+        &mesh_names,
+        &ids.objects,
+        depth,
+    );
+    write_layer(
+        &mut channels,
+        &mut attributes,
+        "CryptoMaterial",
+        &material_names,
+        &ids.materials,
+        depth,
+    );
+    save_channels(filename, ids.objects.size, channels, &attributes)
+}
+
+impl Integrator for IntegratorCryptomatte {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        assert_ne!(scene.nb_samples, 0);
+        let ids = compute_ids(accel, scene, self.filter, scene.nb_samples, observer);
+
+        let output_ext = match std::path::Path::new(&scene.output_img_path).extension() {
+            None => panic!("No file extension provided"),
+            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
+        };
+        let mut base_output_img_path = scene.output_img_path.clone();
+        base_output_img_path.truncate(scene.output_img_path.len() - output_ext.len() - 1);
+        let crypto_path = format!("{}_cryptomatte.exr", base_output_img_path);
+        info!("Write cryptomatte layers: {}", crypto_path);
+        if let Err(e) = save_cryptomatte(&crypto_path, scene, &ids, self.depth) {
+            error!("Impossible to save {}: {}", crypto_path, e);
+        }
+
+        // Preview: random color by mesh weighted by its coverage
+        let mut image =
+            BufferCollection::new(Point2::new(0, 0), ids.objects.size, &["primal".to_string()]);
+        image.values.insert(
+            "primal".to_string(),
+            Bitmap::from_f32(ids.objects.preview()),
+        );
+        image
+    }
+}
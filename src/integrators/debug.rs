@@ -0,0 +1,150 @@
+//! False-color views of the first intersection, to diagnose the scene import problems.
+//! The scalar views (depth, cost, density) are mapped to a black-red-yellow-white scale
+//! where white is the 99th percentile of the image.
+use crate::bsdfs::SampledDirection;
+use crate::color::ColorSpace;
+use crate::integrators::*;
+use crate::metrics::ErrorMap;
+use crate::tools::murmur3_32;
+use cgmath::Vector3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView {
+    GeometricNormal,
+    ShadingNormal,
+    /// Fractional part of the texture coordinates (black without UVs)
+    UV,
+    /// Distance to the camera
+    Depth,
+    /// Random color by mesh name
+    MeshID,
+    /// Time to trace the camera rays
+    /// (embree does not expose its traversal statistics,
+    /// see `vol_primitives --heatmap` for the volumetric primitives)
+    Cost,
+    /// Density (solid angle) of the directions sampled by the BSDF,
+    /// the specular lobes are black
+    BSDFDensity,
+}
+
+impl std::str::FromStr for DebugView {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ng" => Ok(DebugView::GeometricNormal),
+            "ns" => Ok(DebugView::ShadingNormal),
+            "uv" => Ok(DebugView::UV),
+            "depth" => Ok(DebugView::Depth),
+            "mesh" => Ok(DebugView::MeshID),
+            "cost" => Ok(DebugView::Cost),
+            "density" => Ok(DebugView::BSDFDensity),
+            _ => Err(format!(
+                "unknown debug view: {} (ng, ns, uv, depth, mesh, cost, density)",
+                s
+            )),
+        }
+    }
+}
+
+impl DebugView {
+    fn is_scalar(self) -> bool {
+        matches!(
+            self,
+            DebugView::Depth | DebugView::Cost | DebugView::BSDFDensity
+        )
+    }
+}
+
+pub struct IntegratorDebug {
+    pub view: DebugView,
+}
+
+/// The colors are given in sRGB as the bitmap is encoded on save
+fn false_color(c: Color) -> Color {
+    ColorSpace::SRGB.decode_color(c)
+}
+
+fn direction_color(d: Vector3<f32>) -> Color {
+    false_color(Color::new(
+        d.x * 0.5 + 0.5,
+        d.y * 0.5 + 0.5,
+        d.z * 0.5 + 0.5,
+    ))
+}
+
+impl Integrator for IntegratorDebug {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        let mut img = compute_mc(self, accel, scene, observer);
+        if self.view.is_scalar() {
+            let primal = img.values.get_mut("primal").unwrap();
+            let map = ErrorMap {
+                size: primal.size,
+                values: primal.colors.iter().map(|c| c.to_color().r).collect(),
+            };
+            *primal = Bitmap::from_f32(map.false_color(map.percentile(0.99)));
+        }
+        img
+    }
+}
+
+impl IntegratorMC for IntegratorDebug {
+    fn compute_pixel(
+        &self,
+        (ix, iy): (u32, u32),
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        _: &EmitterSampler,
+    ) -> Color {
+        let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
+        let ray = scene.camera.generate(pix).ray;
+        let start = Instant::now();
+        let its = accel.trace(&ray);
+        if self.view == DebugView::Cost {
+            return Color::value(start.elapsed().as_nanos() as f32);
+        }
+        let its = match its {
+            Some(its) => its,
+            None => return Color::zero(),
+        };
+
+        match self.view {
+            DebugView::GeometricNormal => direction_color(its.n_g),
+            DebugView::ShadingNormal => direction_color(its.n_s),
+            DebugView::UV => match its.uv {
+                Some(uv) => {
+                    false_color(Color::new(uv.x.rem_euclid(1.0), uv.y.rem_euclid(1.0), 0.0))
+                }
+                None => Color::zero(),
+            },
+            DebugView::Depth => Color::value(its.dist),
+            DebugView::MeshID => {
+                let h = murmur3_32(its.mesh.name.as_bytes(), 0);
+                false_color(Color::new(
+                    (h & 255) as f32 / 255.0,
+                    ((h >> 8) & 255) as f32 / 255.0,
+                    ((h >> 16) & 255) as f32 / 255.0,
+                ))
+            }
+            DebugView::BSDFDensity => {
+                match its
+                    .mesh
+                    .bsdf
+                    .sample(&its.tex_coords(), &its.wi, sampler.next2d())
+                {
+                    Some(SampledDirection {
+                        pdf: PDF::SolidAngle(pdf),
+                        ..
+                    }) => Color::value(pdf),
+                    _ => Color::zero(),
+                }
+            }
+            DebugView::Cost => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Albedo demodulation for the denoisers: the radiance buffers are divided by the albedo
+//! of the first intersection, which removes the texture details the denoisers would blur,
+//! and the albedo is multiplied back at output time (remodulate).
+//! The Monte Carlo integrators (compute_mc) divide each sample by the albedo of its own
+//! camera path (see FirstHitAlbedo), the other ones use the albedo of separate camera rays.
+use crate::integrators::gradient::features::{albedo, compute_features};
+use crate::integrators::*;
+
+/// Smaller albedos (emitters, background, black surfaces) are not demodulated
+const MIN_ALBEDO: f32 = 1e-3;
+
+/// Albedo dividing the radiance (1 for the channels without albedo)
+fn demodulation_albedo(c: Color) -> Color {
+    let f = |v: f32| if v < MIN_ALBEDO { 1.0 } else { v };
+    Color::new(f(c.r), f(c.g), f(c.b))
+}
+
+/// Acceleration recording the albedo of the first intersection of each sample,
+/// the camera ray of its path (the first ray traced by the Monte Carlo integrators).
+/// Each thread of the rendering pool has its own record.
+pub(crate) struct FirstHitAlbedo<'a> {
+    accel: &'a dyn Acceleration,
+    /// Per thread: None until the first ray of the sample is traced
+    records: Vec<Mutex<Option<Color>>>,
+}
+impl<'a> FirstHitAlbedo<'a> {
+    pub(crate) fn new(accel: &'a dyn Acceleration, nb_threads: usize) -> FirstHitAlbedo<'a> {
+        FirstHitAlbedo {
+            accel,
+            records: (0..nb_threads).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+
+    fn record(&self) -> &Mutex<Option<Color>> {
+        let thread = rayon::current_thread_index().expect("outside of the rendering pool");
+        &self.records[thread]
+    }
+
+    /// Forget the first intersection of the previous sample
+    pub(crate) fn start_sample(&self) {
+        *self.record().lock().unwrap() = None;
+    }
+
+    /// Albedo dividing the radiance of the sample (1 if the camera ray escaped)
+    pub(crate) fn albedo(&self) -> Color {
+        let albedo = self.record().lock().unwrap().unwrap_or_else(Color::zero);
+        demodulation_albedo(albedo)
+    }
+}
+impl<'a> Acceleration for FirstHitAlbedo<'a> {
+    fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let its = self.accel.trace(ray);
+        let mut record = self.record().lock().unwrap();
+        if record.is_none() {
+            *record = Some(its.as_ref().map_or(Color::zero(), albedo));
+        }
+        its
+    }
+    fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool {
+        self.accel.visible(p0, p1)
+    }
+    fn intersect_any(&self, ray: &Ray) -> bool {
+        self.accel.intersect_any(ray)
+    }
+    fn intersect_all_along_ray(&self, ray: &Ray) -> Vec<Intersection<'_>> {
+        self.accel.intersect_all_along_ray(ray)
+    }
+    fn closest_point_query(&self, p: &Point3<f32>, max_distance: f32) -> Option<SurfacePoint<'_>> {
+        self.accel.closest_point_query(p, max_distance)
+    }
+}
+
+/// Power of the albedo dividing each buffer (radiance or variance of the radiance)
+fn albedo_power(name: &str) -> Option<i32> {
+    match name {
+        "primal" | "surface" | "volume" => Some(1),
+        "variance" => Some(2),
+        _ => None,
+    }
+}
+
+/// Multiply the demodulated buffers by the albedo to the power of their unit
+/// (-1 to demodulate, 1 to remodulate)
+fn modulate(img: &mut BufferCollection, sign: i32) {
+    let albedo = img.values.remove("albedo").unwrap();
+    for (name, buffer) in img.values.iter_mut() {
+        let power = match albedo_power(name) {
+            Some(p) => p * sign,
+            None => continue,
+        };
+        for (c, a) in buffer.colors.iter_mut().zip(&albedo.colors) {
+            let a = a.to_color();
+            let f = Color::new(a.r.powi(power), a.g.powi(power), a.b.powi(power));
+            *c = FilmColor::from_color(f) * *c;
+        }
+    }
+    img.values.insert("albedo".to_string(), albedo);
+}
+
+/// Divide the radiance buffers (and their variance) by the albedo of the first intersection
+/// of separate camera rays, added as the "albedo" buffer (the images not rendered by
+/// compute_mc). The channels without albedo have an albedo of 1 in this buffer,
+/// so they are left unchanged.
+pub fn demodulate(accel: &dyn Acceleration, scene: &Scene, img: &mut BufferCollection) {
+    info!("Compute the albedo of the first intersections...");
+    let features = compute_features(accel, scene);
+    let mut albedo = features.values["albedo"].clone();
+    for a in &mut albedo.colors {
+        *a = FilmColor::from_color(demodulation_albedo(a.to_color()));
+    }
+    img.values.insert("albedo".to_string(), albedo);
+    modulate(img, -1);
+}
+
+/// Multiply the buffers demodulated by demodulate (possibly denoised) by their albedo
+pub fn remodulate(img: &mut BufferCollection) {
+    modulate(img, 1);
+}
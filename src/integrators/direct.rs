@@ -7,10 +7,67 @@ pub struct IntegratorDirect {
 }
 
 impl Integrator for IntegratorDirect {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
-        compute_mc(self, accel, scene)
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        let compensated = scene
+            .emitter_environment
+            .as_ref()
+            .map_or(false, |env| env.mis_compensated());
+        if compensated && self.nb_bsdf_samples == 0 {
+            warn!("The MIS compensation of the environment map needs BSDF samples: the image is biased");
+        }
+        compute_mc(self, accel, scene, observer)
+    }
+}
+impl IntegratorDirect {
+    /// Contribution of a light sample, weighted against the BSDF sampling
+    fn light_contribution(
+        &self,
+        accel: &dyn Acceleration,
+        its: &Intersection,
+        light_record: &LightSampling,
+        weight_nb_light: f32,
+        weight_nb_bsdf: f32,
+    ) -> Color {
+        let light_pdf = match light_record.pdf {
+            PDF::SolidAngle(v) => v,
+            _ => panic!("Wrong light PDF"),
+        };
+
+        let d_out_local = its.frame.to_local(light_record.d);
+        if light_record.is_valid()
+            && accel.visible(&its.offset_toward(&light_record.p), &light_record.p)
+            && d_out_local.z > 0.0
+        {
+            // Compute the contribution of direct lighting
+            // FIXME: A bit waste full, need to detect before sampling the light...
+            if let PDF::SolidAngle(pdf_bsdf) =
+                its.mesh
+                    .bsdf
+                    .pdf(&its.tex_coords(), &its.wi, &d_out_local, Domain::SolidAngle)
+            {
+                // Compute MIS weights
+                let weight_light =
+                    mis_weight(light_pdf * weight_nb_light, pdf_bsdf * weight_nb_bsdf);
+                return weight_light
+                    * its.mesh.bsdf.eval(
+                        &its.tex_coords(),
+                        &its.wi,
+                        &d_out_local,
+                        Domain::SolidAngle,
+                    )
+                    * weight_nb_light
+                    * light_record.weight;
+            }
+        }
+        Color::zero()
     }
 }
+
 impl IntegratorMC for IntegratorDirect {
     fn compute_pixel(
         &self,
@@ -21,14 +78,24 @@ impl IntegratorMC for IntegratorDirect {
         emitters: &EmitterSampler,
     ) -> Color {
         let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let ray = scene.camera.generate(pix);
+        let camera_sample = scene.camera.sample_lens(pix, sampler);
+        if camera_sample.weight == 0.0 {
+            return Color::zero();
+        }
+        let ray = camera_sample.ray;
         let mut l_i = Color::zero();
 
         // Do the intersection for the first path
-        let its = match accel.trace(&ray) {
+        let mut its = match accel.trace(&ray) {
             Some(its) => its,
-            None => return scene.enviroment_luminance(ray.d),
+            None => match scene.film {
+                Film::Opaque => return scene.enviroment_luminance(ray.d) * camera_sample.weight,
+                // Replaced by the transparency or the background plate
+                _ => return Color::zero(),
+            },
         };
+        // Footprint of the camera ray for the texture filtering
+        its.set_differentials(&camera_sample.differentials);
 
         // FIXME: Will not work with glass
         // Check if we go the right orientation
@@ -55,37 +122,36 @@ impl IntegratorMC for IntegratorDirect {
         // Light sampling
         /////////////////////////////////
         // Explict connect to the light source
-        for _ in 0..self.nb_light_samples {
-            let light_record =
-                emitters.sample_light(&its.p, sampler.next(), sampler.next(), sampler.next2d());
-            let light_pdf = match light_record.pdf {
-                PDF::SolidAngle(v) => v,
-                _ => panic!("Wrong light PDF"),
-            };
-
-            let d_out_local = its.frame.to_local(light_record.d);
-            if light_record.is_valid()
-                && accel.visible(&its.p, &light_record.p)
-                && d_out_local.z > 0.0
-            {
-                // Compute the contribution of direct lighting
-                // FIXME: A bit waste full, need to detect before sampling the light...
-                if let PDF::SolidAngle(pdf_bsdf) =
-                    its.mesh
-                        .bsdf
-                        .pdf(&its.uv, &its.wi, &d_out_local, Domain::SolidAngle)
-                {
-                    // Compute MIS weights
-                    let weight_light =
-                        mis_weight(light_pdf * weight_nb_light, pdf_bsdf * weight_nb_bsdf);
-                    l_i += &(weight_light
-                        * its
-                            .mesh
-                            .bsdf
-                            .eval(&its.uv, &its.wi, &d_out_local, Domain::SolidAngle)
-                        * weight_nb_light
-                        * light_record.weight);
-                }
+        // (the environment is sampled separately from the emitting meshes)
+        // The emitters of the samples are stratified (see sample_light_stratified)
+        let nb_light_samples = self.nb_light_samples as usize;
+        for i in 0..nb_light_samples {
+            if !emitters.emitters.is_empty() {
+                let light_record = emitters.sample_light_stratified(
+                    &its.p,
+                    (i, nb_light_samples),
+                    sampler.next(),
+                    sampler.next(),
+                    sampler.next2d(),
+                );
+                l_i += self.light_contribution(
+                    accel,
+                    &its,
+                    &light_record,
+                    weight_nb_light,
+                    weight_nb_bsdf,
+                );
+            }
+            if let Some(env) = &scene.emitter_environment {
+                let light_record =
+                    emitters.sample_environment(env, &its.p, sampler.next(), sampler.next2d());
+                l_i += self.light_contribution(
+                    accel,
+                    &its,
+                    &light_record,
+                    weight_nb_light,
+                    weight_nb_bsdf,
+                );
             }
         }
 
@@ -94,18 +160,30 @@ impl IntegratorMC for IntegratorDirect {
         /////////////////////////////////
         // Compute an new direction (diffuse)
         for _ in 0..self.nb_bsdf_samples {
-            if let Some(sampled_bsdf) = its.mesh.bsdf.sample(&its.uv, &its.wi, sampler.next2d()) {
+            if let Some(sampled_bsdf) =
+                its.mesh
+                    .bsdf
+                    .sample(&its.tex_coords(), &its.wi, sampler.next2d())
+            {
                 // Generate the new ray and do the intersection
                 let d_out_world = its.frame.to_world(sampled_bsdf.d);
-                let ray = Ray::new(its.p, d_out_world);
+                let ray = its.spawn_ray(d_out_world);
                 let next_its = match accel.trace(&ray) {
                     Some(x) => x,
                     None => {
-                        l_i += sampled_bsdf.weight
+                        let weight_bsdf = match (&scene.emitter_environment, sampled_bsdf.pdf) {
+                            (Some(env), PDF::SolidAngle(bsdf_pdf)) => mis_weight(
+                                bsdf_pdf * weight_nb_bsdf,
+                                emitters.environment_pdf(env, ray.d) * weight_nb_light,
+                            ),
+                            _ => 1.0,
+                        };
+                        l_i += weight_bsdf
+                            * sampled_bsdf.weight
                             * scene.enviroment_luminance(ray.d)
                             * weight_nb_bsdf;
                         continue;
-                    } // FIXME: Need to implement MIS for BSDF
+                    }
                 };
 
                 // Check that we have intersected a light or not
@@ -130,6 +208,6 @@ impl IntegratorMC for IntegratorDirect {
             }
         }
 
-        l_i
+        l_i * camera_sample.weight
     }
 }
@@ -5,6 +5,10 @@ use crate::samplers;
 use cgmath::InnerSpace;
 use cgmath::Point2;
 
+/// Number of light tracing jobs in the deterministic mode
+/// (otherwise 4 per thread)
+const DETERMINISTIC_LIGHT_JOBS: usize = 64;
+
 pub struct IntegratorLightTracing {
     pub max_depth: Option<u32>,
     pub render_surface: bool,
@@ -37,6 +41,7 @@ impl Technique for TechniqueLightTracing {
         );
         let emitter_vertex = Vertex::Light(EmitterVertex {
             pos: sampled_point.p,
+            p_error: sampled_point.p_error,
             n: sampled_point.n,
             emitter,
             edge_in: None,
@@ -46,7 +51,7 @@ impl Technique for TechniqueLightTracing {
         vec![(path.register_vertex(emitter_vertex), Color::one())]
     }
 
-    fn expand(&self, _vertex: &Vertex, depth: u32) -> bool {
+    fn expand(&self, _path: &Path, _vertex_id: VertexID, depth: u32) -> bool {
         self.max_depth.map_or(true, |max| depth < max)
     }
 
@@ -61,7 +66,7 @@ impl TechniqueLightTracing {
         accel: &dyn Acceleration,
         scene: &'scene Scene,
         vertex_id: VertexID,
-        bitmap: &mut BufferCollection,
+        splats: &SplatBuffer,
         flux: Color,
     ) {
         match path.vertex(vertex_id) {
@@ -85,10 +90,9 @@ impl TechniqueLightTracing {
                             };
 
                             // Accumulate the results
-                            bitmap.accumulate_safe(
+                            splats.add_safe(
                                 Point2::new(uv.x as i32, uv.y as i32),
                                 flux * importance * bsdf_value * transmittance,
-                                &"primal".to_owned(),
                             );
                         }
                     }
@@ -103,7 +107,7 @@ impl TechniqueLightTracing {
                             accel,
                             scene,
                             vertex_next,
-                            bitmap,
+                            splats,
                             flux * edge.weight * edge.rr_weight,
                         );
                     }
@@ -114,14 +118,16 @@ impl TechniqueLightTracing {
                     // Chech the visibility from the point to the sensor
                     let pos_sensor = scene.camera.position();
                     let d = (pos_sensor - v.its.p).normalize();
-                    if !v.its.mesh.bsdf.is_smooth() && accel.visible(&v.its.p, &pos_sensor) {
+                    if !v.its.mesh.bsdf.is_smooth()
+                        && accel.visible(&v.its.offset_toward(&pos_sensor), &pos_sensor)
+                    {
                         // Splat the contribution
                         if let Some((importance, uv)) = scene.camera.sample_direct(&v.its.p) {
                             // Compute BSDF for the splatting
                             let wo_local = v.its.frame.to_local(d);
                             let wi_global = v.its.frame.to_world(v.its.wi);
                             let bsdf_value = v.its.mesh.bsdf.eval(
-                                &v.its.uv,
+                                &v.its.tex_coords(),
                                 &v.its.wi,
                                 &wo_local,
                                 Domain::SolidAngle,
@@ -139,10 +145,9 @@ impl TechniqueLightTracing {
                             };
 
                             // Accumulate the results
-                            bitmap.accumulate_safe(
+                            splats.add_safe(
                                 Point2::new(uv.x as i32, uv.y as i32),
                                 flux * importance * bsdf_value * correction * transmittance,
-                                &"primal".to_owned(),
                             );
                         }
                     }
@@ -156,7 +161,7 @@ impl TechniqueLightTracing {
                             accel,
                             scene,
                             vertex_next,
-                            bitmap,
+                            splats,
                             flux * edge.weight * edge.rr_weight,
                         );
                     }
@@ -167,7 +172,7 @@ impl TechniqueLightTracing {
                 if self.render_surface {
                     let pos_sensor = scene.camera.position();
                     let d = (pos_sensor - v.pos).normalize();
-                    if accel.visible(&v.pos, &pos_sensor) {
+                    if accel.visible(&v.offset_toward(&pos_sensor), &pos_sensor) {
                         if let Some((importance, uv)) = scene.camera.sample_direct(&v.pos) {
                             let transmittance = if let Some(ref m) = scene.volume {
                                 let mut ray = Ray::new(v.pos, d);
@@ -177,14 +182,13 @@ impl TechniqueLightTracing {
                                 Color::one()
                             };
 
-                            bitmap.accumulate_safe(
+                            splats.add_safe(
                                 Point2::new(uv.x as i32, uv.y as i32),
                                 transmittance
                                     * flux
                                     * importance
                                     * d.dot(v.n)
                                     * std::f32::consts::FRAC_1_PI,
-                                &"primal".to_owned(),
                             );
                         }
                     }
@@ -197,7 +201,7 @@ impl TechniqueLightTracing {
                             accel,
                             scene,
                             next_vertex,
-                            bitmap,
+                            splats,
                             edge.weight * flux * edge.rr_weight,
                         );
                     }
@@ -209,67 +213,68 @@ impl TechniqueLightTracing {
 }
 
 impl Integrator for IntegratorLightTracing {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
         // Number of samples that the system will trace
         // The strategy for multithread is to have 4 job per threads
+        // (a fixed number in the deterministic mode)
         // All job will have the same number of samples to deal with
-        let nb_threads = rayon::current_num_threads();
-        let nb_jobs = nb_threads * 4;
+        let nb_jobs = if scene.deterministic {
+            DETERMINISTIC_LIGHT_JOBS
+        } else {
+            nb_threads(scene) * 4
+        };
         let mut samplers = Vec::new();
-        for _ in 0..nb_jobs {
-            samplers.push(samplers::independent::IndependentSampler::default());
+        for id in 0..nb_jobs {
+            samplers.push(samplers::independent::IndependentSampler::for_task(
+                scene.pass_seed(),
+                id,
+            ));
         }
 
         // Ajust the number of light path that we need to generate
         let nb_samples = (scene.nb_samples
             * ((scene.camera.size().x * scene.camera.size().y) as usize))
-            / nb_jobs as usize;
+            / nb_jobs;
 
         // Global information
-        let progress_bar = Mutex::new(ProgressBar::new(samplers.len() as u64));
-        let buffer_names = vec![String::from("primal")];
-        let img = Mutex::new(BufferCollection::new(
-            Point2::new(0, 0),
-            *scene.camera.size(),
-            &buffer_names,
-        ));
-
-        let pool = generate_pool(scene);
-        pool.install(|| {
-            samplers.par_iter_mut().for_each(|s| {
-                let mut my_img =
-                    BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffer_names);
-                let emitters = scene.emitters_sampler();
-                (0..nb_samples).for_each(|_| {
-                    // The sampling strategies
-                    let samplings: Vec<Box<dyn SamplingStrategy>> =
-                        vec![Box::new(DirectionalSamplingStrategy { from_sensor: false })];
-                    // Do the sampling here
-                    let mut technique = TechniqueLightTracing {
-                        max_depth: self.max_depth,
-                        samplings,
-                        flux: None,
-                        render_surface: self.render_surface,
-                        render_volume: self.render_volume,
-                    };
-                    let mut path = Path::default();
-                    let root = generate(&mut path, accel, scene, &emitters, s, &mut technique);
-                    // Evaluate the path generated using camera splatting operation
-                    technique.evaluate(&path, accel, scene, root[0].0, &mut my_img, Color::one());
-                });
-
-                // Scale and add the results
-                my_img.scale(1.0 / (nb_samples as f32));
-                {
-                    img.lock().unwrap().accumulate_bitmap(&my_img);
-                    progress_bar.lock().unwrap().inc();
-                }
+        observer.on_start(samplers.len());
+        let splatted = splat_jobs(scene, &mut samplers, |s, splats| {
+            let emitters = scene.emitters_sampler();
+            // Reused by all the light paths of the job
+            let mut path = Path::default();
+            (0..nb_samples).for_each(|_| {
+                // The sampling strategies
+                let samplings: Vec<Box<dyn SamplingStrategy>> =
+                    vec![Box::new(DirectionalSamplingStrategy {
+                        from_sensor: false,
+                        rr: RussianRoulette::default(),
+                    })];
+                // Do the sampling here
+                let mut technique = TechniqueLightTracing {
+                    max_depth: self.max_depth,
+                    samplings,
+                    flux: None,
+                    render_surface: self.render_surface,
+                    render_volume: self.render_volume,
+                };
+                path.clear();
+                let root = generate(&mut path, accel, scene, &emitters, s, &mut technique);
+                // Evaluate the path generated using camera splatting operation
+                technique.evaluate(&path, accel, scene, root[0].0, splats, Color::one());
             });
+            observer.on_tile_done();
         });
 
-        // All job are independent, so we just merge them...
-        let mut img: BufferCollection = img.into_inner().unwrap();
-        img.scale(1.0 / nb_jobs as f32);
+        // All job are independent and share the same number of samples
+        let buffer_names = vec![String::from("primal")];
+        let mut img = BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffer_names);
+        img.accumulate_local(&splatted, "primal");
+        img.scale(1.0 / (nb_samples * nb_jobs) as f32);
         img.scale((scene.camera.img.x * scene.camera.img.y) as f32);
         img
     }
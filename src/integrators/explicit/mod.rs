@@ -1,4 +1,6 @@
 pub mod light;
+pub mod mrcs;
 pub mod path;
 pub mod vol_primitives;
+pub mod volpath;
 pub mod vpl;
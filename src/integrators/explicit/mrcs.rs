@@ -0,0 +1,187 @@
+//! Matrix row-column sampling of the VPLs (Hasan et al., "Matrix Row-Column Sampling
+//! for the Many-Light Problem", 2007). Inside each image block, the transfer matrix
+//! (pixels x VPLs) is explored with a few complete rows (pixels gathering all the VPLs).
+//! The VPLs are clustered with these reduced columns and each cluster is rendered
+//! for all the pixels of the block with one representative VPL.
+use crate::integrators::explicit::vpl::*;
+use crate::integrators::*;
+use cgmath::Point2;
+
+pub struct IntegratorMRCS {
+    pub nb_vpl: usize,
+    pub max_depth: Option<u32>,
+    /// Number of pixels gathering all the VPLs in each image block
+    pub nb_rows: usize,
+    /// Number of VPL clusters (representatives) in each image block
+    pub nb_clusters: usize,
+}
+
+/// Squared norm of a reduced column
+fn norm2(c: &[f32]) -> f32 {
+    c.iter().map(|v| v * v).sum()
+}
+
+/// Choose an index proportionally to the weights (u in [0, 1))
+fn sample_weights(weights: &[f32], u: f32) -> usize {
+    let total = weights.iter().sum::<f32>();
+    let mut v = u * total;
+    for (i, w) in weights.iter().enumerate() {
+        if v < *w {
+            return i;
+        }
+        v -= w;
+    }
+    // Rounding errors: last non-zero weight
+    weights.iter().rposition(|w| *w > 0.0).unwrap()
+}
+
+/// Clustering by sampling: the seeds are columns chosen proportionally to their norm,
+/// each column joins the seed with the closest direction.
+/// The zero columns (no contribution to the rows) are discarded.
+fn clustering(
+    columns: &[Vec<f32>],
+    nb_clusters: usize,
+    sampler: &mut dyn Sampler,
+) -> Vec<Vec<usize>> {
+    let norms = columns.iter().map(|c| norm2(c).sqrt()).collect::<Vec<_>>();
+    let nonzero = (0..columns.len())
+        .filter(|&i| norms[i] > 0.0)
+        .collect::<Vec<_>>();
+    if nonzero.len() <= nb_clusters {
+        return nonzero.into_iter().map(|i| vec![i]).collect();
+    }
+
+    let mut seeds = (0..nb_clusters)
+        .map(|_| sample_weights(&norms, sampler.next()))
+        .collect::<Vec<_>>();
+    seeds.sort_unstable();
+    seeds.dedup();
+    let mut clusters = vec![vec![]; seeds.len()];
+    for i in nonzero {
+        let cos = |k: usize| {
+            let dot = columns[i]
+                .iter()
+                .zip(&columns[k])
+                .map(|(a, b)| a * b)
+                .sum::<f32>();
+            dot / (norms[i] * norms[k])
+        };
+        let closest = (0..seeds.len())
+            .max_by(|&a, &b| {
+                cos(seeds[a])
+                    .partial_cmp(&cos(seeds[b]))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        clusters[closest].push(i);
+    }
+    // Seeds with the same direction: only one of them gets the columns
+    clusters.retain(|c| !c.is_empty());
+    clusters
+}
+
+impl Integrator for IntegratorMRCS {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        assert!(self.nb_rows > 0 && self.nb_clusters > 0);
+        if scene.volume.is_some() {
+            warn!("MRCS: the participating media are ignored");
+        }
+        info!("Generating the VPL...");
+        let buffernames = vec![String::from("primal")];
+        let VPLPass {
+            vpls,
+            norm: norm_vpl,
+            ..
+        } = generate_vpls(accel, scene, self.nb_vpl, self.max_depth, 0);
+
+        let mut image_blocks = generate_img_blocks(scene, &buffernames);
+        info!("Sampling the rows and the columns...");
+        observer.on_start(image_blocks.len());
+        let pool = generate_pool(scene);
+        pool.install(|| {
+            image_blocks
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(id, im_block)| {
+                    let mut sampler =
+                        independent::IndependentSampler::for_task(scene.pass_seed(), id);
+                    // Gathering points of the pixels (and their self emission)
+                    let mut pixels = vec![];
+                    for iy in 0..im_block.size.y {
+                        for ix in 0..im_block.size.x {
+                            let pos = Point2::new(ix, iy);
+                            let mut gathers = vec![];
+                            for _ in 0..scene.nb_samples {
+                                let pix = Point2::new(
+                                    (ix + im_block.pos.x) as f32 + sampler.next(),
+                                    (iy + im_block.pos.y) as f32 + sampler.next(),
+                                );
+                                if let Some(its) = accel.trace(&scene.camera.generate(pix).ray) {
+                                    if its.cos_theta() > 0.0 {
+                                        im_block.accumulate(pos, its.mesh.emission, "primal");
+                                    }
+                                    gathers.push(its);
+                                }
+                            }
+                            pixels.push((pos, gathers));
+                        }
+                    }
+                    // Matrix element: contribution of the VPL to the pixel
+                    let element = |gathers: &[Intersection], vpl: &VPL| {
+                        gathers
+                            .iter()
+                            .map(|its| contribution_surface(None, accel, vpl, its, None))
+                            .fold(Color::zero(), |a, b| a + b)
+                            * norm_vpl
+                    };
+
+                    // Rows: one pixel in each stratum of the block
+                    let nb_rows = self.nb_rows.min(pixels.len());
+                    let mut columns = vec![vec![]; vpls.len()];
+                    for r in 0..nb_rows {
+                        let begin = r * pixels.len() / nb_rows;
+                        let end = (r + 1) * pixels.len() / nb_rows;
+                        let row =
+                            (begin + (sampler.next() * (end - begin) as f32) as usize).min(end - 1);
+                        for (column, vpl) in columns.iter_mut().zip(&vpls) {
+                            let v = element(&pixels[row].1, vpl);
+                            column.extend_from_slice(&[v.r, v.g, v.b]);
+                        }
+                    }
+
+                    // Columns: one representative per cluster chosen proportionally
+                    // to the norms, scaled by the norm of the cluster
+                    let representatives = clustering(&columns, self.nb_clusters, &mut sampler)
+                        .into_iter()
+                        .map(|cluster| {
+                            let norms = cluster
+                                .iter()
+                                .map(|&i| norm2(&columns[i]).sqrt())
+                                .collect::<Vec<_>>();
+                            let k = sample_weights(&norms, sampler.next());
+                            (cluster[k], norms.iter().sum::<f32>() / norms[k])
+                        })
+                        .collect::<Vec<_>>();
+                    for (pos, gathers) in &pixels {
+                        for &(i, scale) in &representatives {
+                            im_block.accumulate(*pos, element(gathers, &vpls[i]) * scale, "primal");
+                        }
+                    }
+                    im_block.scale(1.0 / (scene.nb_samples as f32));
+                    observer.on_tile_done();
+                });
+        });
+
+        let mut image =
+            BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
+        for im_block in &image_blocks {
+            image.accumulate_bitmap(im_block);
+        }
+        image
+    }
+}
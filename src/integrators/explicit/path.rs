@@ -1,7 +1,10 @@
 use crate::integrators::*;
+use crate::paths::debugger::PathRecord;
 use crate::paths::path::*;
 use crate::paths::vertex::*;
-use cgmath::Point2;
+use crate::samplers::dimensions;
+use cgmath::{Point2, Point3};
+use std::sync::Arc;
 
 /// This structure store the rendering options
 /// That the user have given through the command line
@@ -11,12 +14,56 @@ pub enum IntegratorPathTracingStrategies {
     Emitter,
 }
 pub struct IntegratorPathTracing {
+    /// Maximum number of vertices of the paths, the sensor included
+    /// (2: only the emitters seen from the sensor, 3: direct lighting)
     pub max_depth: Option<u32>,
+    /// Maximum number of diffuse, glossy and specular bounces
+    pub depth_limits: DepthLimits,
     pub strategy: IntegratorPathTracingStrategies,
+    /// Maximum size of the stochastic lightcuts used for the emitter sampling
+    /// (None: one emitter sample per vertex)
+    pub lightcuts: Option<usize>,
+    pub rr: RussianRouletteOptions,
+    /// Minimum roughness of the BSDFs after the first glossy or diffuse bounce
+    /// (path-space roughening: less fireflies on the caustics at the cost of some bias)
+    pub min_roughness: Option<f32>,
 }
+
+/// Survival probability used by the russian roulette
+#[derive(Clone, Copy, Debug)]
+pub enum RussianRouletteMode {
+    /// Maximum channel of the path throughput
+    Throughput,
+    /// Expected contribution of the path relative to the pixel value
+    /// (Vorba and Křivánek, "Adjoint-Driven Russian Roulette and Splitting", 2016).
+    /// A pre-pass estimates the pixel values and caches the radiance arriving
+    /// at its vertices in a coarse grid.
+    Adjoint,
+}
+pub struct RussianRouletteOptions {
+    pub mode: RussianRouletteMode,
+    /// Depth of the first vertex using the russian roulette
+    pub start_depth: u32,
+    /// Maximum number of subpaths generated at a vertex (1: no splitting)
+    pub max_splitting: u32,
+}
+impl Default for RussianRouletteOptions {
+    fn default() -> Self {
+        RussianRouletteOptions {
+            mode: RussianRouletteMode::Throughput,
+            start_depth: 0,
+            max_splitting: 1,
+        }
+    }
+}
+/// Number of samples per pixel of the adjoint pre-pass
+const ADJOINT_NB_SAMPLES: usize = 4;
+/// Number of cells of the adjoint radiance cache along the largest extent of the scene
+const ADJOINT_CACHE_RESOLUTION: usize = 32;
 /// This structure is responsible to the graph generation
 pub struct TechniquePathTracing {
     pub max_depth: Option<u32>,
+    pub depth_limits: DepthLimits,
     pub samplings: Vec<Box<dyn SamplingStrategy>>,
     pub img_pos: Point2<u32>,
 }
@@ -30,10 +77,11 @@ impl Technique for TechniquePathTracing {
         _emitters: &'emitter EmitterSampler,
     ) -> Vec<(VertexID, Color)> {
         // Only generate a path from the sensor
+        let offset = sampler.next2d_dim(dimensions::CAMERA);
         let root = Vertex::Sensor(SensorVertex {
             uv: Point2::new(
-                self.img_pos.x as f32 + sampler.next(),
-                self.img_pos.y as f32 + sampler.next(),
+                self.img_pos.x as f32 + offset.x,
+                self.img_pos.y as f32 + offset.y,
             ),
             pos: scene.camera.position(),
             edge_in: None,
@@ -43,8 +91,8 @@ impl Technique for TechniquePathTracing {
         return vec![(path.register_vertex(root), Color::one())];
     }
 
-    fn expand(&self, _vertex: &Vertex, depth: u32) -> bool {
-        self.max_depth.map_or(true, |max| depth < max)
+    fn expand(&self, path: &Path, vertex_id: VertexID, depth: u32) -> bool {
+        self.max_depth.map_or(true, |max| depth < max) && self.depth_limits.allows(path, vertex_id)
     }
 
     fn strategies(&self, _vertex: &Vertex) -> &Vec<Box<dyn SamplingStrategy>> {
@@ -61,58 +109,119 @@ impl TechniquePathTracing {
         edge_id: EdgeID,
         strategy: &IntegratorPathTracingStrategies,
     ) -> Color {
+        // Compute the contribution
+        let contrib = escaped_contribution(path, scene, edge_id);
+        let contrib =
+            self.weight_contribution(path, scene, emitters, vertex_id, edge_id, strategy, contrib);
+        scene.non_finite.check(contrib, || {
+            let sampling = match path.edge(edge_id).id_sampling {
+                0 => "bsdf",
+                _ => "emitter",
+            };
+            format!(
+                "path tracing, pixel ({}, {}), depth {}, {} sampling",
+                self.img_pos.x,
+                self.img_pos.y,
+                path.depth(vertex_id),
+                sampling
+            )
+        })
+    }
+
+    /// Contribution of the emitter hit along the edge once selected by the strategy
+    /// and weighted by the MIS
+    fn weight_contribution<'scene, 'emitter>(
+        &self,
+        path: &Path<'scene, 'emitter>,
+        scene: &'scene Scene,
+        emitters: &'emitter EmitterSampler,
+        vertex_id: VertexID,
+        edge_id: EdgeID,
+        strategy: &IntegratorPathTracingStrategies,
+        contrib: Color,
+    ) -> Color {
+        if contrib.is_zero() {
+            return Color::zero();
+        }
+        let weight = self.mis_weight(path, scene, emitters, vertex_id, edge_id, strategy);
+        if weight == 0.0 {
+            Color::zero()
+        } else {
+            contrib * weight
+        }
+    }
+
+    /// Weight of the edge: zero if its sampling is not selected by the strategy,
+    /// otherwise the MIS weight
+    fn mis_weight<'scene, 'emitter>(
+        &self,
+        path: &Path<'scene, 'emitter>,
+        scene: &'scene Scene,
+        emitters: &'emitter EmitterSampler,
+        vertex_id: VertexID,
+        edge_id: EdgeID,
+        strategy: &IntegratorPathTracingStrategies,
+    ) -> f32 {
         // Get the edge that we considering
         let edge = path.edge(edge_id);
-        // Compute the contribution
-        let contrib = edge.contribution(path);
-        let contrib = match strategy {
-            IntegratorPathTracingStrategies::All => contrib,
+        match strategy {
+            IntegratorPathTracingStrategies::All => {
+                // Balance heuristic
+                // (the escaped edges are weighted against the environment sampling)
+                if let PDF::SolidAngle(v) = edge.pdf_direction {
+                    let total: f32 = self
+                        .strategies(path.vertex(vertex_id))
+                        .iter()
+                        .map(|s| {
+                            if let Some(v) = s.pdf(path, scene, emitters, vertex_id, edge_id) {
+                                v
+                            } else {
+                                0.0
+                            }
+                        })
+                        .sum();
+                    v / total
+                } else {
+                    1.0
+                }
+            }
+            // No MIS in this case
             IntegratorPathTracingStrategies::BSDF => {
                 if edge.id_sampling != 0 {
-                    Color::zero()
+                    0.0
                 } else {
-                    contrib
+                    1.0
                 }
             }
             IntegratorPathTracingStrategies::Emitter => {
                 if edge.id_sampling != 1 {
-                    Color::zero()
+                    0.0
                 } else {
-                    contrib
+                    1.0
                 }
             }
-        };
+        }
+    }
 
-        // If needed, we compute the MIS weight
-        if !contrib.is_zero() {
-            let weight = match strategy {
-                IntegratorPathTracingStrategies::All => {
-                    // Balance heuristic
-                    if let PDF::SolidAngle(v) = edge.pdf_direction {
-                        let total: f32 = self
-                            .strategies(path.vertex(vertex_id))
-                            .iter()
-                            .map(|s| {
-                                if let Some(v) = s.pdf(path, scene, emitters, vertex_id, edge_id) {
-                                    v
-                                } else {
-                                    0.0
-                                }
-                            })
-                            .sum();
-                        v / total
-                    } else {
-                        1.0
-                    }
-                }
-                // No MIS in this case
-                IntegratorPathTracingStrategies::BSDF
-                | IntegratorPathTracingStrategies::Emitter => 1.0,
+    /// Record the path for the path debugger
+    fn record_path<'scene, 'emitter>(
+        &self,
+        path: &Path<'scene, 'emitter>,
+        scene: &'scene Scene,
+        emitters: &'emitter EmitterSampler,
+        root: VertexID,
+        strategy: &IntegratorPathTracingStrategies,
+    ) -> PathRecord {
+        let pixel = (self.img_pos.x, self.img_pos.y);
+        PathRecord::new("path", pixel, path, root, scene, |vertex_id, edge_id| {
+            let contrib = escaped_contribution(path, scene, edge_id);
+            let weight = match path.vertex(vertex_id) {
+                // The emitters seen directly are not weighted
+                Vertex::Sensor(_) => 1.0,
+                _ => self.mis_weight(path, scene, emitters, vertex_id, edge_id, strategy),
             };
-            contrib * weight
-        } else {
-            Color::zero()
-        }
+            (contrib, weight)
+        })
     }
 
     fn evaluate<'scene, 'emitter>(
@@ -160,10 +269,18 @@ impl TechniquePathTracing {
             }
             Vertex::Sensor(ref v) => {
                 // Only one strategy where...
-                let edge = path.edge(v.edge_out.unwrap());
+                // (no edge if the maximum depth is 1)
+                let edge = match v.edge_out {
+                    Some(edge_id) => path.edge(edge_id),
+                    None => return l_i,
+                };
 
                 // Get the potential contribution
-                let contrib = edge.contribution(path);
+                // (the transparent film and the background plate replace the environment)
+                let contrib = match scene.film {
+                    Film::Opaque => escaped_contribution(path, scene, v.edge_out.unwrap()),
+                    _ => edge.contribution(path),
+                };
                 if !contrib.is_zero() {
                     l_i += contrib;
                 }
@@ -179,31 +296,160 @@ impl TechniquePathTracing {
         };
         l_i
     }
+
+    /// Radiance arriving at the surface and volume vertices along their directional edges
+    /// (luminance, without the weight of the edges)
+    fn record_incident<'scene, 'emitter>(
+        &self,
+        path: &Path<'scene, 'emitter>,
+        scene: &'scene Scene,
+        emitters: &'emitter EmitterSampler,
+        vertex_id: VertexID,
+        strategy: &IntegratorPathTracingStrategies,
+        records: &mut Vec<(Point3<f32>, f32)>,
+    ) {
+        let edges = match path.vertex(vertex_id) {
+            Vertex::Surface(ref v) => v.edge_out.clone(),
+            Vertex::Volume(ref v) => v.edge_out.clone(),
+            Vertex::Sensor(ref v) => v.edge_out.into_iter().collect(),
+            Vertex::Light(_) => vec![],
+        };
+        let from_sensor = matches!(path.vertex(vertex_id), Vertex::Sensor(_));
+        for edge_id in edges {
+            let edge = path.edge(edge_id);
+            let next = match edge.vertices.1 {
+                Some(next) => next,
+                // Escaped (environment) or light sampling failure
+                None => {
+                    if edge.id_sampling == 0 && !from_sensor {
+                        let l_i = scene.enviroment_luminance(edge.d);
+                        records.push((path.vertex(vertex_id).position(), l_i.luminance()));
+                    }
+                    continue;
+                }
+            };
+            if edge.id_sampling == 0 && !from_sensor {
+                let emitted = path.vertex(next).contribution(edge);
+                let l_i = self.weight_contribution(
+                    path, scene, emitters, vertex_id, edge_id, strategy, emitted,
+                ) + self.evaluate(path, scene, emitters, next, strategy);
+                records.push((path.vertex(vertex_id).position(), l_i.luminance()));
+            }
+            self.record_incident(path, scene, emitters, next, strategy, records);
+        }
+    }
 }
 
-impl Integrator for IntegratorPathTracing {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
-        compute_mc(self, accel, scene)
+/// Contribution along the edge, which is the emission of the distant emitters
+/// (environment) when the edge escapes the scene
+fn escaped_contribution(path: &Path, scene: &Scene, edge_id: EdgeID) -> Color {
+    let edge = path.edge(edge_id);
+    match edge.vertices.1 {
+        Some(_) => edge.contribution(path),
+        None => edge.weight * edge.rr_weight * scene.enviroment_luminance(edge.d),
     }
 }
-impl IntegratorMC for IntegratorPathTracing {
-    fn compute_pixel(
+
+impl IntegratorPathTracing {
+    /// Russian roulette with the throughput based survival probability
+    fn throughput_rr(&self) -> RussianRoulette {
+        RussianRoulette {
+            start_depth: self.rr.start_depth,
+            max_splitting: self.rr.max_splitting,
+            ..Default::default()
+        }
+    }
+
+    /// Low sample count estimation of the pixel values (throughput russian roulette)
+    /// and of the radiance arriving at the vertices of these paths
+    fn estimate_pixels(
         &self,
-        (ix, iy): (u32, u32),
         accel: &dyn Acceleration,
         scene: &Scene,
+    ) -> (BufferCollection, RadianceCache) {
+        let buffernames = vec!["primal".to_string()];
+        let mut image_blocks = generate_img_blocks(scene, &buffernames)
+            .into_iter()
+            .map(|b| (b, vec![]))
+            .collect::<Vec<_>>();
+        let nb_blocks = image_blocks.len();
+        let pool = generate_pool(scene);
+        pool.install(|| {
+            image_blocks
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(id, (im_block, records))| {
+                    // Different random numbers than the final rendering
+                    let mut sampler = independent::IndependentSampler::for_task(
+                        scene.pass_seed(),
+                        nb_blocks + id,
+                    );
+                    let emitters = scene.emitters_sampler_with(self.emitter_selection(scene));
+                    let mut path = Path::default();
+                    for iy in 0..im_block.size.y {
+                        for ix in 0..im_block.size.x {
+                            for _ in 0..ADJOINT_NB_SAMPLES {
+                                let c = self.render_pixel(
+                                    (ix + im_block.pos.x, iy + im_block.pos.y),
+                                    accel,
+                                    scene,
+                                    &mut sampler,
+                                    &emitters,
+                                    self.throughput_rr(),
+                                    Some(records),
+                                    &mut path,
+                                );
+                                im_block.accumulate(Point2::new(ix, iy), c, "primal");
+                            }
+                        }
+                    }
+                    im_block.scale(1.0 / ADJOINT_NB_SAMPLES as f32);
+                });
+        });
+
+        let mut image =
+            BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
+        let mut records = vec![];
+        for (im_block, block_records) in &image_blocks {
+            image.accumulate_bitmap(im_block);
+            records.extend_from_slice(block_records);
+        }
+        (
+            image,
+            RadianceCache::new(&records, ADJOINT_CACHE_RESOLUTION),
+        )
+    }
+
+    /// records: incident radiance at the vertices of the path (radiance cache)
+    /// path: cleared and reused to store the vertices of the sample
+    fn render_pixel<'scene, 'emitter>(
+        &self,
+        (ix, iy): (u32, u32),
+        accel: &'scene dyn Acceleration,
+        scene: &'scene Scene,
         sampler: &mut dyn Sampler,
-        emitters: &EmitterSampler,
+        emitters: &'emitter EmitterSampler,
+        rr: RussianRoulette,
+        records: Option<&mut Vec<(Point3<f32>, f32)>>,
+        path: &mut Path<'scene, 'emitter>,
     ) -> Color {
         // Initialize the technique
         let mut samplings: Vec<Box<dyn SamplingStrategy>> = Vec::new();
 
         // Always need the directional strategy to expend the path
-        samplings.push(Box::new(DirectionalSamplingStrategy { from_sensor: true }));
+        samplings.push(Box::new(DirectionalSamplingStrategy {
+            from_sensor: true,
+            rr,
+        }));
         match self.strategy {
             IntegratorPathTracingStrategies::All | IntegratorPathTracingStrategies::Emitter => {
                 // This strategy only make sense in case of light sampling
-                samplings.push(Box::new(LightSamplingStrategy {}));
+                match self.lightcuts {
+                    Some(max_cut) => {
+                        samplings.push(Box::new(StochasticLightcutsStrategy { max_cut }))
+                    }
+                    None => samplings.push(Box::new(LightSamplingStrategy { environment: true })),
+                }
             }
             _ => {}
         }
@@ -211,14 +457,176 @@ impl IntegratorMC for IntegratorPathTracing {
         // Create the technique responsible for the actual tracing
         let mut technique = TechniquePathTracing {
             max_depth: self.max_depth,
+            depth_limits: self.depth_limits,
             samplings,
             img_pos: Point2::new(ix, iy),
         };
         // Call the generator on this technique
         // the generator give back the root nodes
-        let mut path = Path::default();
-        let root = generate(&mut path, accel, scene, emitters, sampler, &mut technique);
+        path.clear();
+        path.min_roughness = self.min_roughness;
+        let root = generate(path, accel, scene, emitters, sampler, &mut technique);
+        if let Some(records) = records {
+            technique.record_incident(path, scene, emitters, root[0].0, &self.strategy, records);
+        } else if scene.path_debugger.records((ix, iy)) {
+            let record = technique.record_path(path, scene, emitters, root[0].0, &self.strategy);
+            scene.path_debugger.record(record);
+        }
         // Evaluate the sampling graph
-        technique.evaluate(&path, scene, emitters, root[0].0, &self.strategy)
+        technique.evaluate(path, scene, emitters, root[0].0, &self.strategy)
+    }
+}
+
+impl Integrator for IntegratorPathTracing {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        if let IntegratorPathTracingStrategies::Emitter = self.strategy {
+            let compensated = scene
+                .emitter_environment
+                .as_ref()
+                .map_or(false, |env| env.mis_compensated());
+            if compensated {
+                warn!("The MIS compensation of the environment map needs the BSDF sampling: the image is biased");
+            }
+        }
+        match self.rr.mode {
+            RussianRouletteMode::Throughput => compute_mc(self, accel, scene, observer),
+            RussianRouletteMode::Adjoint => {
+                info!("Estimating the pixel values for the russian roulette...");
+                let (estimate, radiance) = self.estimate_pixels(accel, scene);
+                let average = estimate.average_pixel("primal").luminance();
+                let mut adjoint = AdjointPathTracing {
+                    integrator: self,
+                    estimate,
+                    average,
+                    radiance: Arc::new(radiance),
+                };
+                adjoint.compute(accel, scene, observer)
+            }
+        }
+    }
+}
+impl IntegratorMC for IntegratorPathTracing {
+    fn compute_pixel(
+        &self,
+        pos: (u32, u32),
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+    ) -> Color {
+        self.render_pixel(
+            pos,
+            accel,
+            scene,
+            sampler,
+            emitters,
+            self.throughput_rr(),
+            None,
+            &mut Path::default(),
+        )
+    }
+
+    fn compute_pixel_scratch<'scene, 'emitter>(
+        &self,
+        pos: (u32, u32),
+        accel: &'scene dyn Acceleration,
+        scene: &'scene Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &'emitter EmitterSampler,
+        scratch: &mut Scratch<'scene, 'emitter>,
+    ) -> Color {
+        self.render_pixel(
+            pos,
+            accel,
+            scene,
+            sampler,
+            emitters,
+            self.throughput_rr(),
+            None,
+            &mut scratch.path,
+        )
+    }
+
+    /// The stochastic lightcuts are made inside the light BVH
+    fn emitter_selection(&self, scene: &Scene) -> EmitterSelection {
+        match self.lightcuts {
+            Some(_) => EmitterSelection::LightBVH,
+            None => scene.emitter_selection,
+        }
+    }
+}
+
+/// Path tracing with the adjoint-driven russian roulette and splitting
+struct AdjointPathTracing<'a> {
+    integrator: &'a IntegratorPathTracing,
+    /// Pre-pass estimation of the pixel values
+    estimate: BufferCollection,
+    /// Average luminance of the image
+    average: f32,
+    /// Radiance arriving at the points of the scene
+    radiance: Arc<RadianceCache>,
+}
+impl<'a> Integrator for AdjointPathTracing<'a> {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        compute_mc(self, accel, scene, observer)
+    }
+}
+impl<'a> AdjointPathTracing<'a> {
+    fn russian_roulette(&self, (ix, iy): (u32, u32)) -> RussianRoulette {
+        // The dark pixels are bounded to avoid too many splits
+        if self.average > 0.0 {
+            let pixel = self.estimate.get(Point2::new(ix, iy), "primal").luminance();
+            RussianRoulette {
+                scale: 1.0 / pixel.max(0.01 * self.average),
+                radiance: Some(self.radiance.clone()),
+                ..self.integrator.throughput_rr()
+            }
+        } else {
+            self.integrator.throughput_rr()
+        }
+    }
+}
+impl<'a> IntegratorMC for AdjointPathTracing<'a> {
+    fn compute_pixel(
+        &self,
+        pos: (u32, u32),
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+    ) -> Color {
+        let rr = self.russian_roulette(pos);
+        let path = &mut Path::default();
+        self.integrator
+            .render_pixel(pos, accel, scene, sampler, emitters, rr, None, path)
+    }
+
+    fn compute_pixel_scratch<'scene, 'emitter>(
+        &self,
+        pos: (u32, u32),
+        accel: &'scene dyn Acceleration,
+        scene: &'scene Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &'emitter EmitterSampler,
+        scratch: &mut Scratch<'scene, 'emitter>,
+    ) -> Color {
+        let rr = self.russian_roulette(pos);
+        let path = &mut scratch.path;
+        self.integrator
+            .render_pixel(pos, accel, scene, sampler, emitters, rr, None, path)
+    }
+
+    fn emitter_selection(&self, scene: &Scene) -> EmitterSelection {
+        self.integrator.emitter_selection(scene)
     }
 }
@@ -1,5 +1,6 @@
 use crate::accel::*;
 use crate::integrators::*;
+use crate::metrics::ErrorMap;
 use crate::paths::path::*;
 use crate::paths::vertex::*;
 use crate::samplers;
@@ -18,6 +19,9 @@ pub struct IntegratorVolPrimitives {
     pub nb_primitive: usize,
     pub max_depth: Option<u32>,
     pub primitives: VolPrimitivies,
+    /// Output the number of BVH nodes visited by the camera rays
+    /// (false color) instead of the radiance
+    pub heatmap: bool,
 }
 
 pub struct TechniqueVolPrimitives {
@@ -43,6 +47,7 @@ impl Technique for TechniqueVolPrimitives {
         );
         let emitter_vertex = Vertex::Light(EmitterVertex {
             pos: sampled_point.p,
+            p_error: sampled_point.p_error,
             n: sampled_point.n,
             emitter,
             edge_in: None,
@@ -52,7 +57,7 @@ impl Technique for TechniqueVolPrimitives {
         vec![(path.register_vertex(emitter_vertex), Color::one())]
     }
 
-    fn expand(&self, _vertex: &Vertex, depth: u32) -> bool {
+    fn expand(&self, _path: &Path, _vertex_id: VertexID, depth: u32) -> bool {
         self.max_depth.map_or(true, |max| depth < max)
     }
 
@@ -100,6 +105,11 @@ impl BVHElement<f32> for Photon {
             }
         }
     }
+
+    // Used by the point queries (photon lookups)
+    fn closest_point(&self, _p: &Point3<f32>) -> Option<Point3<f32>> {
+        Some(self.pos)
+    }
 }
 impl Photon {
     pub fn contribute(&self, ray: &Ray, m: &HomogenousVolume, dist: f32) -> Color {
@@ -587,7 +597,12 @@ impl TechniqueVolPrimitives {
 }
 
 impl Integrator for IntegratorVolPrimitives {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
         // FIXME: The max depth might be wrong in our integrator
         match self.primitives {
             VolPrimitivies::BRE => info!("Render with Beam radiance estimate"),
@@ -598,7 +613,9 @@ impl Integrator for IntegratorVolPrimitives {
 
         info!("Generating the light paths...");
         let buffernames = vec![String::from("primal")];
-        let mut sampler = samplers::independent::IndependentSampler::default();
+        // The last task id is used for the light paths
+        let mut sampler =
+            samplers::independent::IndependentSampler::for_task(scene.pass_seed(), usize::MAX);
         let mut nb_path_shot = 0;
 
         // Primitives vectors
@@ -610,7 +627,10 @@ impl Integrator for IntegratorVolPrimitives {
         let mut still_shoot = true;
         while still_shoot {
             let samplings: Vec<Box<dyn SamplingStrategy>> =
-                vec![Box::new(DirectionalSamplingStrategy { from_sensor: false })];
+                vec![Box::new(DirectionalSamplingStrategy {
+                    from_sensor: false,
+                    rr: RussianRoulette::default(),
+                })];
             let mut technique = TechniqueVolPrimitives {
                 max_depth: self.max_depth,
                 samplings,
@@ -742,92 +762,115 @@ impl Integrator for IntegratorVolPrimitives {
 
         // Render the image blocks VPL integration
         info!("Gathering Photons (BRE/Beams)...");
-        let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
+        observer.on_start(image_blocks.len());
         let norm_photon = 1.0 / nb_path_shot as f32;
         info!(" - Number of path generated: {}", nb_path_shot);
         let pool = generate_pool(scene);
         pool.install(|| {
-            image_blocks.par_iter_mut().for_each(|im_block| {
-                let mut sampler = independent::IndependentSampler::default();
-                for ix in 0..im_block.size.x {
-                    for iy in 0..im_block.size.y {
-                        for _ in 0..scene.nb_samples {
-                            let (ix_c, iy_c) = (ix + im_block.pos.x, iy + im_block.pos.y);
-                            let pix = Point2::new(
-                                ix_c as f32 + sampler.next(),
-                                iy_c as f32 + sampler.next(),
-                            );
-                            let mut ray = scene.camera.generate(pix);
-
-                            // Get the max distance
-                            let max_dist = match accel.trace(&ray) {
-                                Some(x) => x.dist,
-                                None => std::f32::MAX,
-                            };
-                            ray.tfar = max_dist;
-
-                            // Get all photons intersected....
-                            let mut c = Color::value(0.0);
-
-                            let m = scene.volume.as_ref().unwrap();
-                            match self.primitives {
-                                VolPrimitivies::Beams => {
-                                    let bvh = bvh_beams.as_ref().unwrap();
-                                    for (beam_its, b_id) in bvh.gather(ray) {
-                                        c += bvh.elements[b_id].contribute(&ray, m, beam_its)
-                                            * norm_photon;
+            image_blocks
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(id, im_block)| {
+                    let mut sampler =
+                        independent::IndependentSampler::for_task(scene.pass_seed(), id);
+                    for ix in 0..im_block.size.x {
+                        for iy in 0..im_block.size.y {
+                            for _ in 0..scene.nb_samples {
+                                let (ix_c, iy_c) = (ix + im_block.pos.x, iy + im_block.pos.y);
+                                let pix = Point2::new(
+                                    ix_c as f32 + sampler.next(),
+                                    iy_c as f32 + sampler.next(),
+                                );
+                                let mut ray = scene.camera.generate(pix).ray;
+
+                                // Get the max distance
+                                let max_dist = match accel.trace(&ray) {
+                                    Some(x) => x.dist,
+                                    None => std::f32::MAX,
+                                };
+                                ray.tfar = max_dist;
+
+                                // Get all photons intersected....
+                                let mut c = Color::value(0.0);
+                                let mut stats = TraversalStats::default();
+
+                                let m = scene.volume.as_ref().unwrap();
+                                match self.primitives {
+                                    VolPrimitivies::Beams => {
+                                        let bvh = bvh_beams.as_ref().unwrap();
+                                        for (beam_its, b_id) in
+                                            bvh.gather_with_stats(ray, &mut stats)
+                                        {
+                                            c += bvh.elements[b_id].contribute(&ray, m, beam_its)
+                                                * norm_photon;
+                                        }
                                     }
-                                }
-                                VolPrimitivies::VRL => {
-                                    // Form surfaces only
-                                    let bvh = bvh_beams.as_ref().unwrap();
-                                    for (beam_its, b_id) in bvh.gather(ray) {
-                                        c += bvh.elements[b_id].contribute(&ray, m, beam_its)
-                                            * norm_photon;
+                                    VolPrimitivies::VRL => {
+                                        // Form surfaces only
+                                        let bvh = bvh_beams.as_ref().unwrap();
+                                        for (beam_its, b_id) in
+                                            bvh.gather_with_stats(ray, &mut stats)
+                                        {
+                                            c += bvh.elements[b_id].contribute(&ray, m, beam_its)
+                                                * norm_photon;
+                                        }
+                                        // Multiple-scattering
+                                        for vrl in vrls.as_ref().unwrap() {
+                                            // TODO: Hard-coded RR (1 VRL for 100 beams)
+                                            let rr = ((vrl.radiance.channel_max()
+                                                / avg_radiance_vrl)
+                                                * 0.01)
+                                                .min(1.0);
+                                            if rr >= sampler.next() {
+                                                c += (vrl.contribute_vrl(
+                                                    &ray,
+                                                    m,
+                                                    accel,
+                                                    &mut sampler,
+                                                ) / rr)
+                                                    * norm_photon;
+                                            }
+                                        }
                                     }
-                                    // Multiple-scattering
-                                    for vrl in vrls.as_ref().unwrap() {
-                                        // TODO: Hard-coded RR (1 VRL for 100 beams)
-                                        let rr = ((vrl.radiance.channel_max() / avg_radiance_vrl)
-                                            * 0.01)
-                                            .min(1.0);
-                                        if rr >= sampler.next() {
-                                            c += (vrl.contribute_vrl(&ray, m, accel, &mut sampler)
-                                                / rr)
+                                    VolPrimitivies::BRE => {
+                                        let bvh = bvh_photon.as_ref().unwrap();
+                                        for (dist, p_id) in bvh.gather_with_stats(ray, &mut stats) {
+                                            c += bvh.elements[p_id].contribute(&ray, m, dist)
                                                 * norm_photon;
                                         }
                                     }
-                                }
-                                VolPrimitivies::BRE => {
-                                    let bvh = bvh_photon.as_ref().unwrap();
-                                    for (dist, p_id) in bvh.gather(ray) {
-                                        c += bvh.elements[p_id].contribute(&ray, m, dist)
-                                            * norm_photon;
+                                    VolPrimitivies::Planes => {
+                                        let bvh = bvh_beams.as_ref().unwrap();
+                                        for (beam_its, b_id) in
+                                            bvh.gather_with_stats(ray, &mut stats)
+                                        {
+                                            c += bvh.elements[b_id].contribute(&ray, m, beam_its)
+                                                * norm_photon;
+                                        }
+                                        let bvh = bvh_planes.as_ref().unwrap();
+                                        for (plane_its, b_id) in
+                                            bvh.gather_with_stats(ray, &mut stats)
+                                        {
+                                            c += bvh.elements[b_id]
+                                                .contribute(accel, &ray, m, plane_its)
+                                                * norm_photon;
+                                        }
                                     }
                                 }
-                                VolPrimitivies::Planes => {
-                                    let bvh = bvh_beams.as_ref().unwrap();
-                                    for (beam_its, b_id) in bvh.gather(ray) {
-                                        c += bvh.elements[b_id].contribute(&ray, m, beam_its)
-                                            * norm_photon;
-                                    }
-                                    let bvh = bvh_planes.as_ref().unwrap();
-                                    for (plane_its, b_id) in bvh.gather(ray) {
-                                        c += bvh.elements[b_id]
-                                            .contribute(accel, &ray, m, plane_its)
-                                            * norm_photon;
-                                    }
+                                if self.heatmap {
+                                    c = Color::value(stats.nodes as f32);
                                 }
+                                im_block.accumulate(
+                                    Point2 { x: ix, y: iy },
+                                    c,
+                                    &"primal".to_owned(),
+                                );
                             }
-                            im_block.accumulate(Point2 { x: ix, y: iy }, c, &"primal".to_owned());
                         }
                     }
-                }
-                im_block.scale(1.0 / (scene.nb_samples as f32));
-                {
-                    progress_bar.lock().unwrap().inc();
-                }
-            });
+                    im_block.scale(1.0 / (scene.nb_samples as f32));
+                    observer.on_tile_done();
+                });
         });
 
         // Fill the image
@@ -836,6 +879,18 @@ impl Integrator for IntegratorVolPrimitives {
         for im_block in &image_blocks {
             image.accumulate_bitmap(im_block);
         }
+        if self.heatmap {
+            let primal = image.values.get_mut("primal").unwrap();
+            let map = ErrorMap {
+                size: primal.size,
+                values: primal.colors.iter().map(|c| c.to_color().r).collect(),
+            };
+            info!(
+                "Average number of nodes visited: {}",
+                map.values.iter().sum::<f32>() / map.values.len() as f32
+            );
+            *primal = Bitmap::from_f32(map.false_color(map.percentile(0.99)));
+        }
         image
     }
 }
@@ -0,0 +1,547 @@
+//! Volumetric path tracing with next event estimation from the surface and the medium vertices.
+//! With `equiangular`, each segment of the path crossing the medium also samples a scattering
+//! distance toward a point on an emitter (Kulla and Fajardo 2012). The free-flight distances
+//! with the emitter or phase function sampling and the equiangular distances with the emitter
+//! sampling are combined with the balance heuristic (in area measure).
+//!
+//! With `single_scattering`, the single scattering of the camera rays (airlight) is instead
+//! integrated with stratified equiangular distances toward one point of an emitter,
+//! which gives almost noise-free light shafts around the small emitters.
+//!
+//! Only the homogeneous medium of the scene and the emitting meshes are handled: the environment
+//! map is only visible from the paths escaping the scene (without medium).
+use crate::emitter::*;
+use crate::integrators::*;
+use crate::paths::path::RussianRoulette;
+use crate::volume::*;
+use cgmath::{InnerSpace, Point3, Vector3};
+
+pub struct IntegratorVolPath {
+    pub max_depth: Option<u32>,
+    /// Also sample the scattering distances toward the emitters
+    pub equiangular: bool,
+    /// Number of stratified distances of the single scattering of the camera rays
+    /// (None: same estimators as the other bounces)
+    pub single_scattering: Option<u32>,
+}
+
+impl Integrator for IntegratorVolPath {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        compute_mc(self, accel, scene, observer)
+    }
+}
+
+/// Part of a ray inside the medium
+#[derive(Clone, Copy)]
+struct Segment {
+    o: Point3<f32>,
+    d: Vector3<f32>,
+    /// Distance to the surface (infinite without intersection)
+    t_max: f32,
+}
+
+impl Segment {
+    fn point(&self, t: f32) -> Point3<f32> {
+        self.o + self.d * t
+    }
+
+    /// Angles (from the point of the segment closest to p) of the segment ends
+    fn equiangular_setup(&self, p: &Point3<f32>) -> Option<(f32, f32, f32, f32)> {
+        let delta = (p - self.o).dot(self.d);
+        let dist = (p - self.point(delta)).magnitude();
+        if dist < 1e-5 {
+            return None;
+        }
+        let theta_a = (-delta).atan2(dist);
+        let theta_b = (self.t_max - delta).atan2(dist);
+        if theta_b <= theta_a {
+            return None;
+        }
+        Some((delta, dist, theta_a, theta_b))
+    }
+
+    /// Distance sampled proportionally to the inverse squared distance to p
+    fn sample_equiangular(&self, p: &Point3<f32>, u: f32) -> Option<f32> {
+        let (delta, dist, theta_a, theta_b) = self.equiangular_setup(p)?;
+        let theta = theta_a + u * (theta_b - theta_a);
+        let t = delta + dist * theta.tan();
+        if t <= 0.0 || t >= self.t_max {
+            return None;
+        }
+        Some(t)
+    }
+
+    fn pdf_equiangular(&self, p: &Point3<f32>, t: f32) -> f32 {
+        match self.equiangular_setup(p) {
+            Some((delta, dist, theta_a, theta_b)) => {
+                dist / ((theta_b - theta_a) * (dist * dist + (t - delta) * (t - delta)))
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Last scattering event (for the MIS of the emitters hit by the sampled directions)
+enum Scattering {
+    /// Camera or specular bounce
+    Delta,
+    /// BSDF sampling (solid angle density)
+    Surface(f32),
+    /// Free-flight distance t along the segment and phase function sampling
+    Volume(Segment, f32),
+    /// Scattering of the camera ray whose emitter contribution is already integrated
+    Airlight,
+}
+
+/// Density (area measure) of the emitter sampling from o to the point p of the emitter
+fn light_pdf_area(
+    emitters: &EmitterSampler,
+    emitter: &dyn Emitter,
+    o: &Point3<f32>,
+    p: &Point3<f32>,
+    n: &Vector3<f32>,
+) -> f32 {
+    let d = p - o;
+    let dist2 = d.magnitude2();
+    let dir = d / dist2.sqrt();
+    let cos_light = n.dot(-dir).max(0.0);
+    if cos_light == 0.0 {
+        return 0.0;
+    }
+    let light_sampling = LightSamplingPDF {
+        o: *o,
+        p: *p,
+        n: *n,
+        dir,
+    };
+    emitters.direct_pdf(emitter, &light_sampling).value() * cos_light / dist2
+}
+
+impl IntegratorVolPath {
+    /// Densities (area measure) of the ways to sample the emitter point p
+    /// after a scattering at the distance t of the segment: free-flight and emitter sampling,
+    /// free-flight and phase function sampling, equiangular and emitter sampling.
+    /// Returns the sum and the free-flight density (the emitter contributions are
+    /// divided by the sum for the balance heuristic).
+    fn volume_pdfs(
+        &self,
+        emitters: &EmitterSampler,
+        m: &HomogenousVolume,
+        segment: &Segment,
+        t: f32,
+        emitter: &dyn Emitter,
+        p: &Point3<f32>,
+        n: &Vector3<f32>,
+    ) -> (f32, f32) {
+        let x = segment.point(t);
+        let mut ray = Ray::new(segment.o, segment.d);
+        ray.tfar = t;
+        let pdf_distance = m.pdf(ray, false);
+
+        let d = p - x;
+        let dist2 = d.magnitude2();
+        let d = d / dist2.sqrt();
+        let pdf_phase = PhaseFunction::Isotropic().pdf(&-segment.d, &d);
+        let pdf_phase = pdf_phase * n.dot(-d).max(0.0) / dist2;
+        let pdf_light = light_pdf_area(emitters, emitter, &x, p, n);
+        let pdf_equiangular = if self.equiangular {
+            segment.pdf_equiangular(p, t) * light_pdf_area(emitters, emitter, &segment.o, p, n)
+        } else {
+            0.0
+        };
+        (
+            pdf_distance * (pdf_light + pdf_phase) + pdf_equiangular,
+            pdf_distance,
+        )
+    }
+
+    /// Phase function, transmittance, emission and geometry term
+    /// of the connection between x and an emitter point
+    fn connection(
+        &self,
+        m: &HomogenousVolume,
+        segment: &Segment,
+        x: &Point3<f32>,
+        light_record: &LightSampling,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let d = light_record.p - x;
+        let dist = d.magnitude();
+        let d = d / dist;
+        let cos_light = light_record.n.dot(-d).max(0.0);
+        let mut ray = Ray::new(*x, d);
+        ray.tfar = dist;
+        PhaseFunction::Isotropic().eval(&-segment.d, &d)
+            * m.estimate_transmittance(ray, sampler)
+            * light_record.emitter.emitted_luminance(-d)
+            * (cos_light / (dist * dist))
+    }
+
+    /// Single scattering along the segment with the equiangular distance sampling
+    /// toward an emitter point
+    fn sample_equiangular(
+        &self,
+        accel: &dyn Acceleration,
+        emitters: &EmitterSampler,
+        m: &HomogenousVolume,
+        segment: &Segment,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let light_record =
+            emitters.sample_light(&segment.o, sampler.next(), sampler.next(), sampler.next2d());
+        if !light_record.is_valid() {
+            return Color::zero();
+        }
+        let t = match segment.sample_equiangular(&light_record.p, sampler.next()) {
+            Some(t) => t,
+            None => return Color::zero(),
+        };
+        let x = segment.point(t);
+        if !accel.visible(&x, &light_record.p) {
+            return Color::zero();
+        }
+        let (pdf, _) = self.volume_pdfs(
+            emitters,
+            m,
+            segment,
+            t,
+            light_record.emitter,
+            &light_record.p,
+            &light_record.n,
+        );
+        (-(m.sigma_t * t)).exp()
+            * m.sigma_s
+            * self.connection(m, segment, &x, &light_record, sampler)
+            / pdf
+    }
+
+    /// Single scattering along the camera segment toward one emitter point,
+    /// with stratified equiangular distances (airlight integral)
+    fn single_scattering(
+        &self,
+        accel: &dyn Acceleration,
+        emitters: &EmitterSampler,
+        m: &HomogenousVolume,
+        segment: &Segment,
+        nb_samples: u32,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let light_record =
+            emitters.sample_light(&segment.o, sampler.next(), sampler.next(), sampler.next2d());
+        if !light_record.is_valid() {
+            return Color::zero();
+        }
+        let pdf_light = light_pdf_area(
+            emitters,
+            light_record.emitter,
+            &segment.o,
+            &light_record.p,
+            &light_record.n,
+        );
+        if pdf_light == 0.0 {
+            return Color::zero();
+        }
+        let offset = sampler.next();
+        let mut l_i = Color::zero();
+        for i in 0..nb_samples {
+            let u = (i as f32 + offset) / nb_samples as f32;
+            let t = match segment.sample_equiangular(&light_record.p, u) {
+                Some(t) => t,
+                None => continue,
+            };
+            let x = segment.point(t);
+            if !accel.visible(&x, &light_record.p) {
+                continue;
+            }
+            let pdf = segment.pdf_equiangular(&light_record.p, t) * pdf_light;
+            l_i += (-(m.sigma_t * t)).exp()
+                * m.sigma_s
+                * self.connection(m, segment, &x, &light_record, sampler)
+                / pdf;
+        }
+        l_i / nb_samples as f32
+    }
+
+    /// Emitter sampling from the scattering at the distance t of the segment
+    /// (the throughput includes the free-flight sampling)
+    fn volume_nee(
+        &self,
+        accel: &dyn Acceleration,
+        emitters: &EmitterSampler,
+        m: &HomogenousVolume,
+        segment: &Segment,
+        t: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let x = segment.point(t);
+        let light_record =
+            emitters.sample_light(&x, sampler.next(), sampler.next(), sampler.next2d());
+        if !light_record.is_valid() || !accel.visible(&x, &light_record.p) {
+            return Color::zero();
+        }
+        let (pdf, pdf_distance) = self.volume_pdfs(
+            emitters,
+            m,
+            segment,
+            t,
+            light_record.emitter,
+            &light_record.p,
+            &light_record.n,
+        );
+        self.connection(m, segment, &x, &light_record, sampler) * (pdf_distance / pdf)
+    }
+
+    /// Emitter sampling from a surface (balance heuristic with the BSDF sampling)
+    fn surface_nee(
+        &self,
+        accel: &dyn Acceleration,
+        emitters: &EmitterSampler,
+        medium: Option<&HomogenousVolume>,
+        its: &Intersection,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let light_record =
+            emitters.sample_light(&its.p, sampler.next(), sampler.next(), sampler.next2d());
+        let d_out_local = its.frame.to_local(light_record.d);
+        if !light_record.is_valid()
+            || d_out_local.z <= 0.0
+            || !accel.visible(&its.offset_toward(&light_record.p), &light_record.p)
+        {
+            return Color::zero();
+        }
+        let pdf_bsdf =
+            match its
+                .mesh
+                .bsdf
+                .pdf(&its.tex_coords(), &its.wi, &d_out_local, Domain::SolidAngle)
+            {
+                PDF::SolidAngle(v) => v,
+                _ => return Color::zero(),
+            };
+        let transmittance = match medium {
+            Some(m) => {
+                let mut ray = Ray::new(its.p, light_record.d);
+                ray.tfar = (light_record.p - its.p).magnitude();
+                m.estimate_transmittance(ray, sampler)
+            }
+            None => Color::one(),
+        };
+        mis_weight(light_record.pdf.value(), pdf_bsdf)
+            * its
+                .mesh
+                .bsdf
+                .eval(&its.tex_coords(), &its.wi, &d_out_local, Domain::SolidAngle)
+            * light_record.weight
+            * transmittance
+    }
+
+    /// MIS weight of an emitter hit by the sampled direction
+    fn emission_weight(
+        &self,
+        emitters: &EmitterSampler,
+        medium: Option<&HomogenousVolume>,
+        scattering: &Scattering,
+        ray: &Ray,
+        its: &Intersection,
+    ) -> f32 {
+        match scattering {
+            Scattering::Delta => 1.0,
+            Scattering::Airlight => 0.0,
+            Scattering::Surface(pdf) => {
+                let pdf_light = emitters
+                    .direct_pdf(its.mesh, &LightSamplingPDF::new(ray, its))
+                    .value();
+                mis_weight(*pdf, pdf_light)
+            }
+            Scattering::Volume(segment, t) => {
+                let m = medium.unwrap();
+                let (pdf, pdf_distance) =
+                    self.volume_pdfs(emitters, m, segment, *t, its.mesh, &its.p, &its.n_g);
+                let x = segment.point(*t);
+                let d = its.p - x;
+                let dist2 = d.magnitude2();
+                let cos_light = its.n_g.dot(-d / dist2.sqrt()).max(0.0);
+                let pdf_phase = PhaseFunction::Isotropic().pdf(&-segment.d, &(d / dist2.sqrt()))
+                    * cos_light
+                    / dist2;
+                if pdf_phase == 0.0 {
+                    0.0
+                } else {
+                    pdf_distance * pdf_phase / pdf
+                }
+            }
+        }
+    }
+}
+
+impl IntegratorMC for IntegratorVolPath {
+    fn compute_pixel(
+        &self,
+        pix: (u32, u32),
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+    ) -> Color {
+        let (surface, volume) = self.compute_pixel_volume(pix, accel, scene, sampler, emitters);
+        surface + volume
+    }
+
+    fn separates_volume(&self) -> bool {
+        true
+    }
+
+    fn compute_pixel_volume(
+        &self,
+        (ix, iy): (u32, u32),
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+    ) -> (Color, Color) {
+        let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
+        let camera_sample = scene.camera.sample_lens(pix, sampler);
+        if camera_sample.weight == 0.0 {
+            return (Color::zero(), Color::zero());
+        }
+        let mut ray = camera_sample.ray;
+        let medium = scene.volume.as_ref();
+        let has_emitters = !emitters.emitters.is_empty();
+        let rr = RussianRoulette::default();
+        let mut l_i = Color::zero();
+        // Scattered along the camera ray (equiangular or single scattering)
+        let mut l_volume = Color::zero();
+        // The first scattering of the path is inside the medium
+        let mut volume_path = false;
+        let mut throughput = Color::one();
+        let mut scattering = Scattering::Delta;
+        // Number of segments of the path
+        let mut depth = 1;
+        loop {
+            // The emitter sampling and the next bounce add one segment
+            let can_bounce = match self.max_depth {
+                Some(max) => depth < max,
+                None => true,
+            };
+            let mut its = accel.trace(&ray);
+            if depth == 1 {
+                // Footprint of the camera ray for the texture filtering
+                if let Some(its) = its.as_mut() {
+                    its.set_differentials(&camera_sample.differentials);
+                }
+            }
+            let segment = Segment {
+                o: ray.o,
+                d: ray.d,
+                t_max: its.as_ref().map_or(f32::INFINITY, |its| its.dist),
+            };
+
+            // The single scattering of the camera ray is integrated separately
+            let airlight = if depth == 1 {
+                self.single_scattering
+            } else {
+                None
+            };
+            let mut in_medium = false;
+            if let Some(m) = medium {
+                if let Some(nb_samples) = airlight {
+                    if can_bounce && has_emitters {
+                        l_volume += throughput
+                            * self.single_scattering(
+                                accel, emitters, m, &segment, nb_samples, sampler,
+                            );
+                    }
+                } else if self.equiangular && can_bounce && has_emitters {
+                    let l_equiangular =
+                        throughput * self.sample_equiangular(accel, emitters, m, &segment, sampler);
+                    if depth == 1 {
+                        l_volume += l_equiangular;
+                    } else {
+                        l_i += l_equiangular;
+                    }
+                }
+                let mut ray_med = ray;
+                ray_med.tfar = segment.t_max;
+                let mrec = m.sample(&ray_med, sampler.next2d());
+                throughput *= mrec.w;
+                if !mrec.exited {
+                    volume_path |= depth == 1;
+                    if !can_bounce {
+                        break;
+                    }
+                    if has_emitters && airlight.is_none() {
+                        l_i += throughput
+                            * self.volume_nee(accel, emitters, m, &segment, mrec.t, sampler);
+                    }
+                    let sampled_phase =
+                        PhaseFunction::Isotropic().sample(&-ray.d, sampler.next2d());
+                    throughput *= sampled_phase.weight;
+                    scattering = match airlight {
+                        Some(_) => Scattering::Airlight,
+                        None => Scattering::Volume(segment, mrec.t),
+                    };
+                    ray = Ray::new(segment.point(mrec.t), sampled_phase.d);
+                    in_medium = true;
+                }
+            }
+
+            if !in_medium {
+                let its = match its {
+                    Some(its) => its,
+                    None => {
+                        // The environment is only reached by the sampled directions
+                        l_i += throughput * scene.enviroment_luminance(ray.d);
+                        break;
+                    }
+                };
+                if its.mesh.is_light() && its.cos_theta() > 0.0 {
+                    l_i += throughput
+                        * its.mesh.emission
+                        * self.emission_weight(emitters, medium, &scattering, &ray, &its);
+                }
+                if !can_bounce {
+                    break;
+                }
+                if has_emitters && !its.mesh.bsdf.is_smooth() {
+                    l_i += throughput * self.surface_nee(accel, emitters, medium, &its, sampler);
+                }
+                let sampled_bsdf =
+                    match its
+                        .mesh
+                        .bsdf
+                        .sample(&its.tex_coords(), &its.wi, sampler.next2d())
+                    {
+                        Some(v) => v,
+                        None => break,
+                    };
+                throughput *= &sampled_bsdf.weight;
+                scattering = match sampled_bsdf.pdf {
+                    PDF::SolidAngle(pdf) => Scattering::Surface(pdf),
+                    _ => Scattering::Delta,
+                };
+                ray = its.spawn_ray(its.frame.to_world(sampled_bsdf.d));
+            }
+
+            if throughput.is_zero() {
+                break;
+            }
+            let rr_weight = rr.survival(&throughput, depth, &ray.o);
+            if rr_weight < sampler.next() {
+                break;
+            }
+            throughput /= rr_weight;
+            depth += 1;
+        }
+        let (l_i, l_volume) = (l_i * camera_sample.weight, l_volume * camera_sample.weight);
+        if volume_path {
+            (Color::zero(), l_i + l_volume)
+        } else {
+            (l_i, l_volume)
+        }
+    }
+}
@@ -1,38 +1,57 @@
+use crate::integrators::visibility_cache::*;
 use crate::integrators::*;
 use crate::paths::path::*;
 use crate::paths::vertex::*;
 use crate::samplers;
 use crate::volume::*;
 use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector3};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub struct IntegratorVPL {
     pub nb_vpl: usize,
     pub max_depth: Option<u32>,
+    /// Minimum distance between the shading points and the VPLs of the light bounces
+    /// (bounded singularity of the geometry term). It decreases with the passes.
     pub clamping_factor: Option<f32>,
+    /// Each pass renders the image with a new set of VPLs, the passes are averaged
+    pub nb_passes: usize,
+    /// Reuse of the shadow rays between the cells of the scene (biased, see VisibilityCache).
+    /// Each image block has its own cache, emptied at each pass.
+    pub visibility_cache: Option<VisibilityCacheConfig>,
 }
 
-struct VPLSurface<'a> {
+pub(crate) struct VPLSurface<'a> {
     its: Intersection<'a>,
     radiance: Color,
 }
-struct VPLVolume {
+pub(crate) struct VPLVolume {
     pos: Point3<f32>,
     d_in: Vector3<f32>,
     phase_function: PhaseFunction,
     radiance: Color,
 }
-struct VPLEmitter {
+pub(crate) struct VPLEmitter {
     pos: Point3<f32>,
+    p_error: Vector3<f32>,
     n: Vector3<f32>,
     emitted_radiance: Color,
 }
 
-enum VPL<'a> {
+pub(crate) enum VPL<'a> {
     Surface(VPLSurface<'a>),
     Volume(VPLVolume),
     Emitter(VPLEmitter),
 }
 
+/// VPLs generated by one pass
+pub(crate) struct VPLPass<'a> {
+    pub vpls: Vec<VPL<'a>>,
+    /// One over the number of light paths
+    pub norm: f32,
+    /// Clamping distance of the light bounces
+    pub clamping: Option<f32>,
+}
+
 pub struct TechniqueVPL {
     pub max_depth: Option<u32>,
     pub samplings: Vec<Box<dyn SamplingStrategy>>,
@@ -55,6 +74,7 @@ impl Technique for TechniqueVPL {
         );
         let emitter_vertex = Vertex::Light(EmitterVertex {
             pos: sampled_point.p,
+            p_error: sampled_point.p_error,
             n: sampled_point.n,
             emitter,
             edge_in: None,
@@ -64,7 +84,7 @@ impl Technique for TechniqueVPL {
         vec![(path.register_vertex(emitter_vertex), Color::one())]
     }
 
-    fn expand(&self, _vertex: &Vertex, depth: u32) -> bool {
+    fn expand(&self, _path: &Path, _vertex_id: VertexID, depth: u32) -> bool {
         self.max_depth.map_or(true, |max| depth < max)
     }
 
@@ -129,6 +149,7 @@ impl TechniqueVPL {
                 let flux = *self.flux.as_ref().unwrap();
                 vpls.push(VPL::Emitter(VPLEmitter {
                     pos: v.pos,
+                    p_error: v.p_error,
                     n: v.n,
                     emitted_radiance: flux,
                 }));
@@ -152,103 +173,259 @@ impl TechniqueVPL {
 }
 
 impl Integrator for IntegratorVPL {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
-        info!("Generating the VPL...");
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        assert_ne!(self.nb_passes, 0);
         let buffernames = vec![String::from("primal")];
-        let mut sampler = samplers::independent::IndependentSampler::default();
-        let mut nb_path_shot = 0;
-        let mut vpls = vec![];
-        let emitters = scene.emitters_sampler();
-        while vpls.len() < self.nb_vpl as usize {
-            let samplings: Vec<Box<dyn SamplingStrategy>> =
-                vec![Box::new(DirectionalSamplingStrategy { from_sensor: false })];
-            let mut technique = TechniqueVPL {
-                max_depth: self.max_depth,
-                samplings,
-                flux: None,
-            };
-            let mut path = Path::default();
-            let root = generate(
-                &mut path,
-                accel,
-                scene,
-                &emitters,
-                &mut sampler,
-                &mut technique,
-            );
-            technique.convert_vpl(&path, scene, root[0].0, &mut vpls, Color::one());
-            nb_path_shot += 1;
-        }
-        let vpls = vpls;
+        let mut image =
+            BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
 
         // Generate the image block to get VPL efficiently
         let mut image_blocks = generate_img_blocks(scene, &buffernames);
-
-        // Render the image blocks VPL integration
-        info!("Gathering VPL...");
-        let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
-        let norm_vpl = 1.0 / nb_path_shot as f32;
+        let nb_blocks = image_blocks.len();
+        observer.on_start(nb_blocks * self.nb_passes);
         let pool = generate_pool(scene);
-        pool.install(|| {
-            image_blocks.par_iter_mut().for_each(|im_block| {
-                let mut sampler = independent::IndependentSampler::default();
-                for ix in 0..im_block.size.x {
-                    for iy in 0..im_block.size.y {
-                        for _ in 0..scene.nb_samples {
-                            let c = self.compute_vpl_contrib(
-                                (ix + im_block.pos.x, iy + im_block.pos.y),
-                                accel,
-                                scene,
-                                &mut sampler,
-                                &vpls,
-                                norm_vpl,
-                            );
-                            im_block.accumulate(Point2 { x: ix, y: iy }, c, &"primal".to_owned());
+        for pass in 0..self.nb_passes {
+            info!(
+                "Generating the VPL (pass {}/{})...",
+                pass + 1,
+                self.nb_passes
+            );
+            let mut vpls = generate_vpls(accel, scene, self.nb_vpl, self.max_depth, pass);
+            // The clamping bias vanishes with the passes
+            vpls.clamping = self.clamping_factor.map(|c| c / ((pass + 1) as f32).sqrt());
+            // Shadow rays answered by the visibility caches (reused / asked)
+            let cache_hits = AtomicUsize::new(0);
+            let cache_queries = AtomicUsize::new(0);
+
+            // Render the image blocks VPL integration
+            info!("Gathering VPL...");
+            pool.install(|| {
+                image_blocks
+                    .par_iter_mut()
+                    .enumerate()
+                    .for_each(|(id, im_block)| {
+                        im_block.reset();
+                        let mut sampler = independent::IndependentSampler::for_task(
+                            scene.pass_seed(),
+                            pass * nb_blocks + id,
+                        );
+                        // One cache per block: its answers do not depend on the
+                        // order of the blocks between the threads (seeded renderings)
+                        let cache = self
+                            .visibility_cache
+                            .map(|config| VisibilityCache::new(accel, config));
+                        let gather_accel = cache.as_ref().map_or(accel, |c| c as &dyn Acceleration);
+                        let mut samples = vec![];
+                        for ix in 0..im_block.size.x {
+                            for iy in 0..im_block.size.y {
+                                for _ in 0..scene.nb_samples {
+                                    let c = self.compute_vpl_contrib(
+                                        (ix + im_block.pos.x, iy + im_block.pos.y),
+                                        gather_accel,
+                                        scene,
+                                        &mut sampler,
+                                        &vpls,
+                                    );
+                                    match scene.sample_rejection {
+                                        Some(_) => samples.push(c),
+                                        None => im_block.accumulate(
+                                            Point2 { x: ix, y: iy },
+                                            c,
+                                            &"primal".to_owned(),
+                                        ),
+                                    }
+                                }
+                                if let Some(rejection) = scene.sample_rejection {
+                                    // Scaled like the sum of the samples
+                                    let c = rejection.estimate(&samples) * scene.nb_samples as f32;
+                                    im_block.accumulate(Point2 { x: ix, y: iy }, c, "primal");
+                                    samples.clear();
+                                }
+                            }
                         }
-                    }
-                }
-                im_block.scale(1.0 / (scene.nb_samples as f32));
-                {
-                    progress_bar.lock().unwrap().inc();
-                }
+                        im_block.scale(1.0 / (scene.nb_samples as f32));
+                        if let Some(cache) = &cache {
+                            cache_hits.fetch_add(cache.hits(), Ordering::Relaxed);
+                            cache_queries.fetch_add(cache.queries(), Ordering::Relaxed);
+                        }
+                        observer.on_tile_done();
+                    });
             });
-        });
+            if self.visibility_cache.is_some() {
+                info!(
+                    "Visibility cache: {} / {} shadow rays reused",
+                    cache_hits.into_inner(),
+                    cache_queries.into_inner()
+                );
+            }
 
-        // Fill the image
-        let mut image =
-            BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
-        for im_block in &image_blocks {
-            image.accumulate_bitmap(im_block);
+            // Fill the image: the passes are weighted by their number of light paths
+            let mut pass_image =
+                BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
+            for im_block in &image_blocks {
+                pass_image.accumulate_bitmap(im_block);
+            }
+            image.accumulate_pass(&pass_image, 1.0 / vpls.norm);
+            observer.on_pass_done(pass + 1, &image.values["primal"].to_f32());
         }
         image
     }
 }
 
-impl IntegratorVPL {
-    fn transmittance(
-        &self,
-        medium: Option<&HomogenousVolume>,
-        p1: Point3<f32>,
-        p2: Point3<f32>,
-    ) -> Color {
-        if let Some(m) = medium {
-            let mut d = p2 - p1;
+/// Trace light paths until at least nb_vpl VPLs are generated (without clamping).
+/// Each pass uses different random numbers
+pub(crate) fn generate_vpls<'scene>(
+    accel: &'scene dyn Acceleration,
+    scene: &'scene Scene,
+    nb_vpl: usize,
+    max_depth: Option<u32>,
+    pass: usize,
+) -> VPLPass<'scene> {
+    // The last task ids are used for the VPL generation
+    let mut sampler =
+        samplers::independent::IndependentSampler::for_task(scene.pass_seed(), usize::MAX - pass);
+    let mut nb_path_shot = 0;
+    let mut vpls = vec![];
+    let emitters = scene.emitters_sampler();
+    while vpls.len() < nb_vpl {
+        let samplings: Vec<Box<dyn SamplingStrategy>> =
+            vec![Box::new(DirectionalSamplingStrategy {
+                from_sensor: false,
+                rr: RussianRoulette::default(),
+            })];
+        let mut technique = TechniqueVPL {
+            max_depth,
+            samplings,
+            flux: None,
+        };
+        let mut path = Path::default();
+        let root = generate(
+            &mut path,
+            accel,
+            scene,
+            &emitters,
+            &mut sampler,
+            &mut technique,
+        );
+        technique.convert_vpl(&path, scene, root[0].0, &mut vpls, Color::one());
+        nb_path_shot += 1;
+    }
+    VPLPass {
+        vpls,
+        norm: 1.0 / nb_path_shot as f32,
+        clamping: None,
+    }
+}
+
+/// Inverse squared distance of the geometry term
+fn inv_dist2(dist: f32, clamping: Option<f32>) -> f32 {
+    let dist = clamping.map_or(dist, |c| dist.max(c));
+    1.0 / (dist * dist)
+}
+
+fn transmittance(medium: Option<&HomogenousVolume>, p1: Point3<f32>, p2: Point3<f32>) -> Color {
+    if let Some(m) = medium {
+        let mut d = p2 - p1;
+        let dist = d.magnitude();
+        d /= dist;
+        let mut r = Ray::new(p1, d);
+        r.tfar = dist;
+        m.transmittance(r)
+    } else {
+        Color::one()
+    }
+}
+
+/// Radiance reflected at its due to one VPL (without the VPL normalization).
+/// The clamping distance is only used for the VPLs of the light bounces
+pub(crate) fn contribution_surface(
+    medium: Option<&HomogenousVolume>,
+    accel: &dyn Acceleration,
+    vpl: &VPL,
+    its: &Intersection,
+    clamping: Option<f32>,
+) -> Color {
+    if its.mesh.bsdf.is_smooth() {
+        return Color::zero();
+    }
+    match *vpl {
+        VPL::Emitter(ref vpl) => {
+            let p_vpl = offset_ray_origin(vpl.pos, vpl.p_error, vpl.n, its.p - vpl.pos);
+            if accel.visible(&p_vpl, &its.offset_toward(&vpl.pos)) {
+                let mut d = vpl.pos - its.p;
+                let dist = d.magnitude();
+                d /= dist;
+
+                let emitted_radiance =
+                    vpl.emitted_radiance * vpl.n.dot(-d).max(0.0) * std::f32::consts::FRAC_1_PI;
+                let bsdf_val = its.mesh.bsdf.eval(
+                    &its.tex_coords(),
+                    &its.wi,
+                    &its.to_local(&d),
+                    Domain::SolidAngle,
+                );
+                let trans = transmittance(medium, its.p, vpl.pos);
+                trans * emitted_radiance * bsdf_val * inv_dist2(dist, None)
+            } else {
+                Color::zero()
+            }
+        }
+        VPL::Volume(ref vpl) => {
+            let mut d = vpl.pos - its.p;
             let dist = d.magnitude();
             d /= dist;
-            let mut r = Ray::new(p1, d);
-            r.tfar = dist;
-            m.transmittance(r)
-        } else {
-            Color::one()
+
+            let emitted_radiance = vpl.phase_function.eval(&vpl.d_in, &d);
+            let bsdf_val = its.mesh.bsdf.eval(
+                &its.tex_coords(),
+                &its.wi,
+                &its.to_local(&d),
+                Domain::SolidAngle,
+            );
+            let trans = transmittance(medium, its.p, vpl.pos);
+            trans * emitted_radiance * bsdf_val * vpl.radiance * inv_dist2(dist, clamping)
+        }
+        VPL::Surface(ref vpl) => {
+            if accel.visible(
+                &vpl.its.offset_toward(&its.p),
+                &its.offset_toward(&vpl.its.p),
+            ) {
+                let mut d = vpl.its.p - its.p;
+                let dist = d.magnitude();
+                d /= dist;
+
+                let emitted_radiance = vpl.its.mesh.bsdf.eval(
+                    &vpl.its.tex_coords(),
+                    &vpl.its.wi,
+                    &vpl.its.to_local(&-d),
+                    Domain::SolidAngle,
+                );
+                let bsdf_val = its.mesh.bsdf.eval(
+                    &its.tex_coords(),
+                    &its.wi,
+                    &its.to_local(&d),
+                    Domain::SolidAngle,
+                );
+                let trans = transmittance(medium, its.p, vpl.its.p);
+                trans * emitted_radiance * bsdf_val * vpl.radiance * inv_dist2(dist, clamping)
+            } else {
+                Color::zero()
+            }
         }
     }
+}
 
+impl IntegratorVPL {
     fn gathering_surface<'a>(
         &self,
         medium: Option<&HomogenousVolume>,
         accel: &dyn Acceleration,
-        vpls: &[VPL<'a>],
-        norm_vpl: f32,
+        vpls: &VPLPass<'a>,
         its: &Intersection,
     ) -> Color {
         let mut l_i = Color::zero();
@@ -258,73 +435,8 @@ impl IntegratorVPL {
             l_i += &(its.mesh.emission);
         }
 
-        for vpl in vpls {
-            match *vpl {
-                VPL::Emitter(ref vpl) => {
-                    if accel.visible(&vpl.pos, &its.p) {
-                        let mut d = vpl.pos - its.p;
-                        let dist = d.magnitude();
-                        d /= dist;
-
-                        let emitted_radiance = vpl.emitted_radiance
-                            * vpl.n.dot(-d).max(0.0)
-                            * std::f32::consts::FRAC_1_PI;
-                        if !its.mesh.bsdf.is_smooth() {
-                            let bsdf_val = its.mesh.bsdf.eval(
-                                &its.uv,
-                                &its.wi,
-                                &its.to_local(&d),
-                                Domain::SolidAngle,
-                            );
-                            let trans = self.transmittance(medium, its.p, vpl.pos);
-                            l_i += trans * norm_vpl * emitted_radiance * bsdf_val / (dist * dist);
-                        }
-                    }
-                }
-                VPL::Volume(ref vpl) => {
-                    let mut d = vpl.pos - its.p;
-                    let dist = d.magnitude();
-                    d /= dist;
-
-                    if !its.mesh.bsdf.is_smooth() {
-                        let emitted_radiance = vpl.phase_function.eval(&vpl.d_in, &d);
-                        let bsdf_val = its.mesh.bsdf.eval(
-                            &its.uv,
-                            &its.wi,
-                            &its.to_local(&d),
-                            Domain::SolidAngle,
-                        );
-                        let trans = self.transmittance(medium, its.p, vpl.pos);
-                        l_i += trans * norm_vpl * emitted_radiance * bsdf_val * vpl.radiance
-                            / (dist * dist);
-                    }
-                }
-                VPL::Surface(ref vpl) => {
-                    if accel.visible(&vpl.its.p, &its.p) {
-                        let mut d = vpl.its.p - its.p;
-                        let dist = d.magnitude();
-                        d /= dist;
-
-                        if !its.mesh.bsdf.is_smooth() {
-                            let emitted_radiance = vpl.its.mesh.bsdf.eval(
-                                &vpl.its.uv,
-                                &vpl.its.wi,
-                                &vpl.its.to_local(&-d),
-                                Domain::SolidAngle,
-                            );
-                            let bsdf_val = its.mesh.bsdf.eval(
-                                &its.uv,
-                                &its.wi,
-                                &its.to_local(&d),
-                                Domain::SolidAngle,
-                            );
-                            let trans = self.transmittance(medium, its.p, vpl.its.p);
-                            l_i += trans * norm_vpl * emitted_radiance * bsdf_val * vpl.radiance
-                                / (dist * dist);
-                        }
-                    }
-                }
-            }
+        for vpl in &vpls.vpls {
+            l_i += contribution_surface(medium, accel, vpl, its, vpls.clamping) * vpls.norm;
         }
         l_i
     }
@@ -333,17 +445,18 @@ impl IntegratorVPL {
         &self,
         medium: Option<&HomogenousVolume>,
         accel: &dyn Acceleration,
-        vpls: &[VPL<'a>],
-        norm_vpl: f32,
+        vpls: &VPLPass<'a>,
         d_cam: Vector3<f32>,
         pos: Point3<f32>,
         phase: &PhaseFunction,
     ) -> Color {
+        let (norm_vpl, clamping) = (vpls.norm, vpls.clamping);
         let mut l_i = Color::zero();
-        for vpl in vpls {
+        for vpl in &vpls.vpls {
             match *vpl {
                 VPL::Emitter(ref vpl) => {
-                    if accel.visible(&vpl.pos, &pos) {
+                    let p_vpl = offset_ray_origin(vpl.pos, vpl.p_error, vpl.n, pos - vpl.pos);
+                    if accel.visible(&p_vpl, &pos) {
                         let mut d = vpl.pos - pos;
                         let dist = d.magnitude();
                         d /= dist;
@@ -352,8 +465,9 @@ impl IntegratorVPL {
                             * vpl.n.dot(-d).max(0.0)
                             * std::f32::consts::FRAC_1_PI;
                         let phase_val = phase.eval(&d_cam, &d);
-                        let trans = self.transmittance(medium, pos, vpl.pos);
-                        l_i += trans * norm_vpl * emitted_radiance * phase_val / (dist * dist);
+                        let trans = transmittance(medium, pos, vpl.pos);
+                        l_i +=
+                            trans * norm_vpl * emitted_radiance * phase_val * inv_dist2(dist, None);
                     }
                 }
                 VPL::Volume(ref vpl) => {
@@ -363,26 +477,34 @@ impl IntegratorVPL {
 
                     let emitted_radiance = vpl.phase_function.eval(&vpl.d_in, &d);
                     let phase_val = phase.eval(&d_cam, &d);
-                    let trans = self.transmittance(medium, pos, vpl.pos);
-                    l_i += trans * norm_vpl * emitted_radiance * phase_val * vpl.radiance
-                        / (dist * dist);
+                    let trans = transmittance(medium, pos, vpl.pos);
+                    l_i += trans
+                        * norm_vpl
+                        * emitted_radiance
+                        * phase_val
+                        * vpl.radiance
+                        * inv_dist2(dist, clamping);
                 }
                 VPL::Surface(ref vpl) => {
-                    if accel.visible(&vpl.its.p, &pos) {
+                    if accel.visible(&vpl.its.offset_toward(&pos), &pos) {
                         let mut d = vpl.its.p - pos;
                         let dist = d.magnitude();
                         d /= dist;
 
                         let emitted_radiance = vpl.its.mesh.bsdf.eval(
-                            &vpl.its.uv,
+                            &vpl.its.tex_coords(),
                             &vpl.its.wi,
                             &vpl.its.to_local(&-d),
                             Domain::SolidAngle,
                         );
                         let phase_val = phase.eval(&d_cam, &d);
-                        let trans = self.transmittance(medium, pos, vpl.its.p);
-                        l_i += trans * norm_vpl * emitted_radiance * phase_val * vpl.radiance
-                            / (dist * dist);
+                        let trans = transmittance(medium, pos, vpl.its.p);
+                        l_i += trans
+                            * norm_vpl
+                            * emitted_radiance
+                            * phase_val
+                            * vpl.radiance
+                            * inv_dist2(dist, clamping);
                     }
                 }
             }
@@ -396,15 +518,18 @@ impl IntegratorVPL {
         accel: &dyn Acceleration,
         scene: &'a Scene,
         sampler: &mut dyn Sampler,
-        vpls: &[VPL<'a>],
-        norm_vpl: f32,
+        vpls: &VPLPass<'a>,
     ) -> Color {
         let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let ray = scene.camera.generate(pix);
+        let camera_sample = scene.camera.sample_lens(pix, sampler);
+        if camera_sample.weight == 0.0 {
+            return Color::zero();
+        }
+        let ray = camera_sample.ray;
         let mut l_i = Color::zero();
 
         // Check if we have a intersection with the primary ray
-        let its = match accel.trace(&ray) {
+        let mut its = match accel.trace(&ray) {
             Some(x) => x,
             None => {
                 if let Some(m) = &scene.volume {
@@ -417,7 +542,6 @@ impl IntegratorVPL {
                         scene.volume.as_ref(),
                         accel,
                         vpls,
-                        norm_vpl,
                         -ray.d,
                         pos,
                         &phase_function,
@@ -428,6 +552,8 @@ impl IntegratorVPL {
                 }
             }
         };
+        // Footprint of the camera ray for the texture filtering
+        its.set_differentials(&camera_sample.differentials);
 
         if let Some(m) = &scene.volume {
             let mut ray_med = ray;
@@ -440,20 +566,18 @@ impl IntegratorVPL {
                     scene.volume.as_ref(),
                     accel,
                     vpls,
-                    norm_vpl,
                     -ray.d,
                     pos,
                     &phase_function,
                 ) * mrec.w;
-                l_i
+                l_i * camera_sample.weight
             } else {
-                l_i += self.gathering_surface(scene.volume.as_ref(), accel, vpls, norm_vpl, &its)
-                    * mrec.w;
-                l_i
+                l_i += self.gathering_surface(scene.volume.as_ref(), accel, vpls, &its) * mrec.w;
+                l_i * camera_sample.weight
             }
         } else {
-            l_i += self.gathering_surface(scene.volume.as_ref(), accel, vpls, norm_vpl, &its);
-            l_i
+            l_i += self.gathering_surface(scene.volume.as_ref(), accel, vpls, &its);
+            l_i * camera_sample.weight
         }
     }
 }
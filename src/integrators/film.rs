@@ -0,0 +1,88 @@
+//! Transparent film and background plate: the pixel coverage (fraction of the camera rays
+//! hitting the scene) is estimated after the rendering with jittered camera rays.
+use crate::integrators::*;
+
+/// Number of jittered camera rays per pixel
+const NB_SAMPLES: usize = 16;
+
+/// Fraction of the camera rays hitting the scene (buffer "coverage")
+pub fn compute_coverage(accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    let buffernames = vec!["coverage".to_string()];
+    let mut image_blocks = generate_img_blocks(scene, &buffernames);
+    let pool = generate_pool(scene);
+    pool.install(|| {
+        image_blocks
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(id, im_block)| {
+                let mut sampler = independent::IndependentSampler::for_task(scene.pass_seed(), id);
+                for iy in 0..im_block.size.y {
+                    for ix in 0..im_block.size.x {
+                        for _ in 0..NB_SAMPLES {
+                            let pix = Point2::new(
+                                (ix + im_block.pos.x) as f32 + sampler.next(),
+                                (iy + im_block.pos.y) as f32 + sampler.next(),
+                            );
+                            if accel.trace(&scene.camera.generate(pix).ray).is_some() {
+                                im_block.accumulate(Point2::new(ix, iy), Color::one(), "coverage");
+                            }
+                        }
+                    }
+                }
+                im_block.scale(1.0 / NB_SAMPLES as f32);
+            });
+    });
+
+    let mut image = BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
+    for im_block in &image_blocks {
+        image.accumulate_bitmap(im_block);
+    }
+    image
+}
+
+/// Set the alpha channel of the primal buffer (transparent film)
+/// or add the background plate behind the scene
+/// (the texture space film only pads its charts, see TextureSpace::dilate)
+pub fn apply_film(accel: &dyn Acceleration, scene: &Scene, img: &mut BufferCollection) {
+    if let Some(texture_space) = scene.camera.texture_space() {
+        for buffer in img.values.values_mut() {
+            texture_space.dilate(buffer);
+        }
+        return;
+    }
+    if let Film::Opaque = scene.film {
+        return;
+    }
+    if !img.values.contains_key("primal") {
+        warn!("No primal buffer: the film is ignored");
+        return;
+    }
+    info!("Compute the coverage of the pixels...");
+    let coverage = compute_coverage(accel, scene);
+    let coverage = &coverage.values["coverage"];
+    let primal = img.values.get_mut("primal").unwrap();
+    match scene.film {
+        Film::Opaque => {}
+        Film::Transparent => {
+            primal.alpha = Some(coverage.colors.iter().map(|c| c.to_color().r).collect());
+        }
+        Film::Background(ref background) => {
+            let size = primal.size;
+            // Nearest pixel of the background stretched to the image
+            let lookup = |v: u32, size: u32, size_bg: u32| {
+                (((v as f32 + 0.5) * size_bg as f32 / size as f32) as u32).min(size_bg - 1)
+            };
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let p = Point2::new(x, y);
+                    let p_bg = Point2::new(
+                        lookup(x, size.x, background.size.x),
+                        lookup(y, size.y, background.size.y),
+                    );
+                    let visible = 1.0 - coverage.pixel(p).r;
+                    primal.accumulate(p, background.pixel(p_bg) * visible);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Discrete cosine transforms (rustdct, any length). The DCT-II diagonalizes
+//! the image Laplacian with Neumann boundaries, which is used by the Fourier
+//! Poisson reconstruction.
+use rustdct::{Dct2, Dct3, DctPlanner, TransformType2And3};
+use std::sync::Arc;
+
+/// DCT-II of a fixed length and its inverse
+pub struct DCT {
+    n: usize,
+    plan: Arc<dyn TransformType2And3<f64>>,
+}
+
+impl DCT {
+    pub fn new(n: usize) -> DCT {
+        assert!(n > 0);
+        DCT {
+            n,
+            plan: DctPlanner::new().plan_dct2(n),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.n
+    }
+
+    /// X_k = sum_j x_j cos(pi (2j + 1) k / (2n)) (unnormalized)
+    pub fn forward(&self, x: &mut [f64]) {
+        assert_eq!(x.len(), self.n);
+        self.plan.process_dct2(x);
+    }
+
+    /// Exact inverse of forward (DCT-III scaled by 2 / n)
+    pub fn inverse(&self, x: &mut [f64]) {
+        assert_eq!(x.len(), self.n);
+        self.plan.process_dct3(x);
+        let scale = 2.0 / self.n as f64;
+        x.iter_mut().for_each(|v| *v *= scale);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::integrators::gradient::shiftmapping::{random_replay::RandomReplay, ShiftMapping};
 use crate::integrators::{gradient::*, *};
+use crate::paths::debugger::PathRecord;
 use crate::paths::path::*;
 use crate::paths::vertex::*;
 use cgmath::Point2;
@@ -41,7 +42,7 @@ impl Technique for TechniqueGradientPathTracing {
         return vec![(path.register_vertex(root), Color::one())];
     }
 
-    fn expand(&self, _vertex: &Vertex, depth: u32) -> bool {
+    fn expand(&self, _path: &Path, _vertex_id: VertexID, depth: u32) -> bool {
         self.max_depth.map_or(true, |max| depth < max)
     }
 
@@ -59,30 +60,17 @@ impl TechniqueGradientPathTracing {
     ) -> Color {
         let mut l_i = Color::zero();
         match path.vertex(vertex_id) {
-            Vertex::Surface(ref v) => {
-                for edge_id in &v.edge_out {
+            // The scattering distances inside the participating media are
+            // replayed by the shift mapping: the volume vertices are evaluated
+            // as the surface ones (the edge weights include the transmittance)
+            Vertex::Surface(SurfaceVertex { ref edge_out, .. })
+            | Vertex::Volume(VolumeVertex { ref edge_out, .. }) => {
+                for edge_id in edge_out {
                     let edge = path.edge(*edge_id);
                     let contrib = edge.contribution(path);
                     if !contrib.is_zero() {
-                        let weight = if let PDF::SolidAngle(v) = edge.pdf_direction {
-                            let total: f32 = self
-                                .strategies(path.vertex(vertex_id))
-                                .iter()
-                                .map(|s| {
-                                    if let Some(v) =
-                                        s.pdf(path, scene, emitters, vertex_id, *edge_id)
-                                    {
-                                        v
-                                    } else {
-                                        0.0
-                                    }
-                                })
-                                .sum();
-                            v / total
-                        } else {
-                            1.0
-                        };
-                        l_i += contrib * weight;
+                        l_i +=
+                            contrib * self.mis_weight(path, scene, emitters, vertex_id, *edge_id);
                     }
 
                     if let Some(vertex_next_id) = edge.vertices.1 {
@@ -111,6 +99,54 @@ impl TechniqueGradientPathTracing {
         };
         l_i
     }
+
+    /// Balance heuristic of the edge leaving a surface or volume vertex
+    fn mis_weight<'scene>(
+        &self,
+        path: &Path<'scene, '_>,
+        scene: &'scene Scene,
+        emitters: &EmitterSampler,
+        vertex_id: VertexID,
+        edge_id: EdgeID,
+    ) -> f32 {
+        if let PDF::SolidAngle(v) = path.edge(edge_id).pdf_direction {
+            let total: f32 = self
+                .strategies(path.vertex(vertex_id))
+                .iter()
+                .map(|s| {
+                    s.pdf(path, scene, emitters, vertex_id, edge_id)
+                        .unwrap_or(0.0)
+                })
+                .sum();
+            v / total
+        } else {
+            1.0
+        }
+    }
+
+    /// Record the base and offset paths for the path debugger
+    fn record_paths<'scene>(
+        &self,
+        path: &Path<'scene, '_>,
+        scene: &'scene Scene,
+        emitters: &EmitterSampler,
+        labels: &[String],
+    ) {
+        for (root, label) in path.roots().into_iter().zip(labels) {
+            let pixel = match path.vertex(root) {
+                Vertex::Sensor(ref v) => (v.uv.x as u32, v.uv.y as u32),
+                _ => continue,
+            };
+            let record = PathRecord::new(label, pixel, path, root, scene, |vertex_id, edge_id| {
+                let weight = match path.vertex(vertex_id) {
+                    Vertex::Sensor(_) => 1.0,
+                    _ => self.mis_weight(path, scene, emitters, vertex_id, edge_id),
+                };
+                (path.edge(edge_id).contribution(path), weight)
+            });
+            scene.path_debugger.record(record);
+        }
+    }
 }
 impl Integrator for IntegratorGradientPathTracing {}
 impl IntegratorGradient for IntegratorGradientPathTracing {
@@ -118,107 +154,114 @@ impl IntegratorGradient for IntegratorGradientPathTracing {
         self.recons.as_ref()
     }
 
-    fn compute_gradients(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute_gradients(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
         let (nb_buffers, buffernames, mut image_blocks, ids) =
             generate_img_blocks_gradient(scene, self.recons.as_ref());
 
-        let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
+        observer.on_start(image_blocks.len());
         let pool = generate_pool(scene);
         pool.install(|| {
-            image_blocks.par_iter_mut().for_each(|(info, im_block)| {
-                let mut sampler = independent::IndependentSampler::default();
-                let mut shiftmapping = RandomReplay::default();
-                let emitters = scene.emitters_sampler();
-                for ix in info.x_pos_off..im_block.size.x - info.x_size_off {
-                    for iy in info.y_pos_off..im_block.size.y - info.y_size_off {
-                        for n in 0..scene.nb_samples {
-                            shiftmapping.clear();
-                            let c = self.compute_pixel(
-                                (ix + im_block.pos.x, iy + im_block.pos.y),
-                                accel,
-                                scene,
-                                &emitters,
-                                &mut sampler,
-                                &mut shiftmapping,
-                            );
-                            // Accumulate the values inside the buffer
-                            let pos = Point2::new(ix, iy);
-                            let offset_buffers = (n % nb_buffers) * 3; // 3 buffers are in multiple version
-                            im_block.accumulate(
-                                pos,
-                                c.main,
-                                &buffernames[ids.primal + offset_buffers],
-                            );
-                            im_block.accumulate(
-                                pos,
-                                c.very_direct,
-                                &buffernames[ids.very_direct].to_owned(),
-                            );
-                            for i in 0..4 {
-                                // primal reuse
-                                let off = GRADIENT_ORDER[i];
-                                let pos_off = Point2::new(ix as i32 + off.x, iy as i32 + off.y);
-                                im_block.accumulate_safe(
-                                    pos_off,
-                                    c.radiances[i],
+            image_blocks
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(id, (info, im_block))| {
+                    let mut sampler =
+                        independent::IndependentSampler::for_task(scene.pass_seed(), id);
+                    let mut shiftmapping = RandomReplay::default();
+                    let emitters = scene.emitters_sampler();
+                    for ix in info.x_pos_off..im_block.size.x - info.x_size_off {
+                        for iy in info.y_pos_off..im_block.size.y - info.y_size_off {
+                            for n in 0..scene.nb_samples {
+                                shiftmapping.clear();
+                                let c = self.compute_pixel(
+                                    (ix + im_block.pos.x, iy + im_block.pos.y),
+                                    accel,
+                                    scene,
+                                    &emitters,
+                                    &mut sampler,
+                                    &mut shiftmapping,
+                                );
+                                // Accumulate the values inside the buffer
+                                let pos = Point2::new(ix, iy);
+                                let offset_buffers = (n % nb_buffers) * 3; // 3 buffers are in multiple version
+                                im_block.accumulate(
+                                    pos,
+                                    c.main,
                                     &buffernames[ids.primal + offset_buffers],
                                 );
-                                // gradient
-                                match GRADIENT_DIRECTION[i] {
-                                    GradientDirection::X(v) => match v {
-                                        1 => im_block.accumulate(
-                                            pos,
-                                            c.gradients[i],
-                                            &buffernames[ids.gradient_x + offset_buffers],
-                                        ),
-                                        -1 => im_block.accumulate_safe(
-                                            pos_off,
-                                            c.gradients[i] * -1.0,
-                                            &buffernames[ids.gradient_x + offset_buffers],
-                                        ),
-                                        _ => panic!("wrong displacement X"), // FIXME: Fix the enum
-                                    },
-                                    GradientDirection::Y(v) => match v {
-                                        1 => im_block.accumulate(
-                                            pos,
-                                            c.gradients[i],
-                                            &buffernames[ids.gradient_y + offset_buffers],
-                                        ),
-                                        -1 => im_block.accumulate_safe(
-                                            pos_off,
-                                            c.gradients[i] * -1.0,
-                                            &buffernames[ids.gradient_y + offset_buffers],
-                                        ),
-                                        _ => panic!("wrong displacement Y"),
-                                    },
+                                im_block.accumulate(
+                                    pos,
+                                    c.very_direct,
+                                    &buffernames[ids.very_direct].to_owned(),
+                                );
+                                for i in 0..4 {
+                                    // primal reuse
+                                    let off = GRADIENT_ORDER[i];
+                                    let pos_off = Point2::new(ix as i32 + off.x, iy as i32 + off.y);
+                                    im_block.accumulate_safe(
+                                        pos_off,
+                                        c.radiances[i],
+                                        &buffernames[ids.primal + offset_buffers],
+                                    );
+                                    // gradient
+                                    match GRADIENT_DIRECTION[i] {
+                                        GradientDirection::X(v) => match v {
+                                            1 => im_block.accumulate(
+                                                pos,
+                                                c.gradients[i],
+                                                &buffernames[ids.gradient_x + offset_buffers],
+                                            ),
+                                            -1 => im_block.accumulate_safe(
+                                                pos_off,
+                                                c.gradients[i] * -1.0,
+                                                &buffernames[ids.gradient_x + offset_buffers],
+                                            ),
+                                            _ => panic!("wrong displacement X"), // FIXME: Fix the enum
+                                        },
+                                        GradientDirection::Y(v) => match v {
+                                            1 => im_block.accumulate(
+                                                pos,
+                                                c.gradients[i],
+                                                &buffernames[ids.gradient_y + offset_buffers],
+                                            ),
+                                            -1 => im_block.accumulate_safe(
+                                                pos_off,
+                                                c.gradients[i] * -1.0,
+                                                &buffernames[ids.gradient_y + offset_buffers],
+                                            ),
+                                            _ => panic!("wrong displacement Y"),
+                                        },
+                                    }
                                 }
                             }
                         }
                     }
-                }
-                im_block.scale(1.0 / (scene.nb_samples as f32));
-                // Renormalize correctly the buffer informations
-                for i in 0..nb_buffers {
-                    let offset_buffers = i * 3; // 3 buffer that have multiple entries
-                                                // 4 strategies as reuse primal
-                    im_block.scale_buffer(
-                        0.25 * nb_buffers as f32,
-                        &buffernames[ids.primal + offset_buffers],
-                    );
-                    im_block.scale_buffer(
-                        nb_buffers as f32,
-                        &buffernames[ids.gradient_x + offset_buffers],
-                    );
-                    im_block.scale_buffer(
-                        nb_buffers as f32,
-                        &buffernames[ids.gradient_y + offset_buffers],
-                    );
-                }
+                    im_block.scale(1.0 / (scene.nb_samples as f32));
+                    // Renormalize correctly the buffer informations
+                    for i in 0..nb_buffers {
+                        let offset_buffers = i * 3; // 3 buffer that have multiple entries
+                                                    // 4 strategies as reuse primal
+                        im_block.scale_buffer(
+                            0.25 * nb_buffers as f32,
+                            &buffernames[ids.primal + offset_buffers],
+                        );
+                        im_block.scale_buffer(
+                            nb_buffers as f32,
+                            &buffernames[ids.gradient_x + offset_buffers],
+                        );
+                        im_block.scale_buffer(
+                            nb_buffers as f32,
+                            &buffernames[ids.gradient_y + offset_buffers],
+                        );
+                    }
 
-                {
-                    progress_bar.lock().unwrap().inc();
-                }
-            });
+                    observer.on_tile_done();
+                });
         });
 
         // Fill the image & do the reconstruct
@@ -243,8 +286,12 @@ impl IntegratorGradientPathTracing {
     ) -> ColorGradient {
         let mut path = Path::default();
         let mut samplings: Vec<Box<dyn SamplingStrategy>> = Vec::new();
-        samplings.push(Box::new(DirectionalSamplingStrategy { from_sensor: true }));
-        samplings.push(Box::new(LightSamplingStrategy {}));
+        samplings.push(Box::new(DirectionalSamplingStrategy {
+            from_sensor: true,
+            rr: RussianRoulette::default(),
+        }));
+        // The escaped edges are not evaluated: no environment sampling
+        samplings.push(Box::new(LightSamplingStrategy { environment: false }));
         let mut technique = TechniqueGradientPathTracing {
             max_depth: None, // FIXME
             samplings,
@@ -280,14 +327,16 @@ impl IntegratorGradientPathTracing {
                 gradients: [Color::zero(); 4],
             };
 
+            let mut labels = vec!["gradient base".to_string()];
             GRADIENT_ORDER.iter().enumerate().for_each(|(i, off)| {
                 let pix = Point2::new(ix as i32 + off.x, iy as i32 + off.y);
                 if pix.x < 0
-                    || pix.x > scene.camera.size().x as i32
+                    || pix.x >= scene.camera.size().x as i32
                     || pix.y < 0
-                    || pix.y > scene.camera.size().y as i32
+                    || pix.y >= scene.camera.size().y as i32
                 {
-                    // Do nothing
+                    // No offset pixel: the base path keeps the full weight
+                    output.main += base_contrib * weight_survival;
                 } else {
                     // Change the pixel for the sampling technique
                     // and reset the sampler
@@ -304,8 +353,12 @@ impl IntegratorGradientPathTracing {
                     output.main += shift_value.base * weight_survival;
                     output.radiances[i] = shift_value.offset * weight_survival;
                     output.gradients[i] = shift_value.gradient * weight_survival;
+                    labels.push(format!("gradient offset {:+} {:+}", off.x, off.y));
                 }
             });
+            if scene.path_debugger.records((ix, iy)) {
+                technique.record_paths(&path, scene, emitters, &labels);
+            }
             output
         } else {
             ColorGradient::default()
@@ -0,0 +1,64 @@
+//! Auxiliary features of the first intersection (albedo and shading normal)
+//! used to guide the reconstruction of the gradient-domain images.
+use crate::integrators::*;
+use crate::Scale;
+
+/// Number of jittered primary rays per pixel
+const NB_SAMPLES: usize = 4;
+/// The albedo is estimated with a stratified grid of BSDF samples
+const NB_STRATA: usize = 4;
+
+/// Directional albedo of the intersected BSDF
+pub(crate) fn albedo(its: &Intersection) -> Color {
+    let mut albedo = Color::zero();
+    for i in 0..NB_STRATA {
+        for j in 0..NB_STRATA {
+            let u = Point2::new(
+                (i as f32 + 0.5) / NB_STRATA as f32,
+                (j as f32 + 0.5) / NB_STRATA as f32,
+            );
+            if let Some(s) = its.mesh.bsdf.sample(&its.tex_coords(), &its.wi, u) {
+                albedo += s.weight;
+            }
+        }
+    }
+    albedo / (NB_STRATA * NB_STRATA) as f32
+}
+
+/// Render the buffers "albedo" and "normal" (zero if the ray escapes)
+pub fn compute_features(accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    let buffernames = vec!["albedo".to_string(), "normal".to_string()];
+    let mut image_blocks = generate_img_blocks(scene, &buffernames);
+    let pool = generate_pool(scene);
+    pool.install(|| {
+        image_blocks
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(id, im_block)| {
+                let mut sampler = independent::IndependentSampler::for_task(scene.pass_seed(), id);
+                for iy in 0..im_block.size.y {
+                    for ix in 0..im_block.size.x {
+                        let pos = Point2::new(ix, iy);
+                        for _ in 0..NB_SAMPLES {
+                            let pix = Point2::new(
+                                (ix + im_block.pos.x) as f32 + sampler.next(),
+                                (iy + im_block.pos.y) as f32 + sampler.next(),
+                            );
+                            if let Some(its) = accel.trace(&scene.camera.generate(pix).ray) {
+                                let n = its.n_s;
+                                im_block.accumulate(pos, albedo(&its), "albedo");
+                                im_block.accumulate(pos, Color::new(n.x, n.y, n.z), "normal");
+                            }
+                        }
+                    }
+                }
+                im_block.scale(1.0 / NB_SAMPLES as f32);
+            });
+    });
+
+    let mut image = BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
+    for im_block in &image_blocks {
+        image.accumulate_bitmap(im_block);
+    }
+    image
+}
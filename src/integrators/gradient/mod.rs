@@ -67,6 +67,12 @@ pub fn generate_img_blocks_gradient(
     // The buffers names are always:
     // ["very_direct", ("primal", "gradient_x", "gradient_y")+]
     let (nb_buffers, buffernames) = if let Some(number_buffers) = recons.need_variance_estimates() {
+        if scene.nb_samples < number_buffers {
+            warn!(
+                "Only {} samples for {} buffers: some buffers stay empty",
+                scene.nb_samples, number_buffers
+            );
+        }
         let mut buffernames = Vec::new();
         buffernames.reserve((3 * number_buffers) + 1);
         buffernames.push(String::from("very_direct"));
@@ -89,15 +95,16 @@ pub fn generate_img_blocks_gradient(
     };
 
     let mut image_blocks = Vec::new();
-    for ix in StepRangeInt::new(0, scene.camera.size().x as usize, 16) {
-        for iy in StepRangeInt::new(0, scene.camera.size().y as usize, 16) {
+    let tile_size = scene.tile_size.unwrap_or(TILE_SIZE);
+    for ix in StepRangeInt::new(0, scene.camera.size().x as usize, tile_size as usize) {
+        for iy in StepRangeInt::new(0, scene.camera.size().y as usize, tile_size as usize) {
             let pos_off = Point2 {
                 x: cmp::max(0, ix as i32 - 1) as u32,
                 y: cmp::max(0, iy as i32 - 1) as u32,
             };
             let desired_size = Vector2 {
-                x: 16 + if ix == 0 { 1 } else { 2 },
-                y: 16 + if iy == 0 { 1 } else { 2 },
+                x: tile_size + if ix == 0 { 1 } else { 2 },
+                y: tile_size + if iy == 0 { 1 } else { 2 },
             };
             let max_size = Vector2 {
                 x: (scene.camera.size().x - pos_off.x) as u32,
@@ -133,7 +140,9 @@ pub fn generate_img_blocks_gradient(
     )
 }
 
+pub mod dct;
 pub mod explicit;
+pub mod features;
 pub mod path;
 pub mod recons;
 pub mod shiftmapping;
@@ -79,7 +79,7 @@ impl<'a> RayState<'a> {
             return RayState::Dead;
         }
 
-        let ray = scene.camera.generate(pix);
+        let ray = scene.camera.generate(pix).ray;
         let its = match accel.trace(&ray) {
             Some(x) => x,
             None => return RayState::Dead,
@@ -100,104 +100,114 @@ impl IntegratorGradient for IntegratorGradientPath {
         self.recons.as_ref()
     }
 
-    fn compute_gradients(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute_gradients(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        if scene.volume.is_some() {
+            warn!("gradient-path ignores the participating media, use gradient-path-explicit");
+        }
         let (nb_buffers, buffernames, mut image_blocks, ids) =
             generate_img_blocks_gradient(scene, self.recons.as_ref());
 
-        let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
+        observer.on_start(image_blocks.len());
         let pool = generate_pool(scene);
         pool.install(|| {
-            image_blocks.par_iter_mut().for_each(|(info, im_block)| {
-                let emitters = scene.emitters_sampler();
-                let mut sampler = independent::IndependentSampler::default();
-                for ix in info.x_pos_off..im_block.size.x - info.x_size_off {
-                    for iy in info.y_pos_off..im_block.size.y - info.y_size_off {
-                        for n in 0..scene.nb_samples {
-                            let c = self.compute_pixel(
-                                (ix + im_block.pos.x, iy + im_block.pos.y),
-                                accel,
-                                scene,
-                                &emitters,
-                                &mut sampler,
-                            );
-                            // Accumulate the values inside the buffer
-                            let pos = Point2::new(ix, iy);
-                            let offset_buffers = (n % nb_buffers) * 3; // 3 buffers are in multiple version
-                            im_block.accumulate(
-                                pos,
-                                c.main,
-                                &buffernames[ids.primal + offset_buffers],
-                            );
-                            im_block.accumulate(
-                                pos,
-                                c.very_direct,
-                                &buffernames[ids.very_direct].to_owned(),
-                            );
-                            for i in 0..4 {
-                                // primal reuse
-                                let off = GRADIENT_ORDER[i];
-                                let pos_off = Point2::new(ix as i32 + off.x, iy as i32 + off.y);
-                                im_block.accumulate_safe(
-                                    pos_off,
-                                    c.radiances[i],
+            image_blocks
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(id, (info, im_block))| {
+                    let emitters = scene.emitters_sampler();
+                    let mut sampler =
+                        independent::IndependentSampler::for_task(scene.pass_seed(), id);
+                    for ix in info.x_pos_off..im_block.size.x - info.x_size_off {
+                        for iy in info.y_pos_off..im_block.size.y - info.y_size_off {
+                            for n in 0..scene.nb_samples {
+                                let c = self.compute_pixel(
+                                    (ix + im_block.pos.x, iy + im_block.pos.y),
+                                    accel,
+                                    scene,
+                                    &emitters,
+                                    &mut sampler,
+                                );
+                                // Accumulate the values inside the buffer
+                                let pos = Point2::new(ix, iy);
+                                let offset_buffers = (n % nb_buffers) * 3; // 3 buffers are in multiple version
+                                im_block.accumulate(
+                                    pos,
+                                    c.main,
                                     &buffernames[ids.primal + offset_buffers],
                                 );
-                                // gradient
-                                match GRADIENT_DIRECTION[i] {
-                                    GradientDirection::X(v) => match v {
-                                        1 => im_block.accumulate(
-                                            pos,
-                                            c.gradients[i],
-                                            &buffernames[ids.gradient_x + offset_buffers],
-                                        ),
-                                        -1 => im_block.accumulate_safe(
-                                            pos_off,
-                                            c.gradients[i] * -1.0,
-                                            &buffernames[ids.gradient_x + offset_buffers],
-                                        ),
-                                        _ => panic!("wrong displacement X"), // FIXME: Fix the enum
-                                    },
-                                    GradientDirection::Y(v) => match v {
-                                        1 => im_block.accumulate(
-                                            pos,
-                                            c.gradients[i],
-                                            &buffernames[ids.gradient_y + offset_buffers],
-                                        ),
-                                        -1 => im_block.accumulate_safe(
-                                            pos_off,
-                                            c.gradients[i] * -1.0,
-                                            &buffernames[ids.gradient_y + offset_buffers],
-                                        ),
-                                        _ => panic!("wrong displacement Y"),
-                                    },
+                                im_block.accumulate(
+                                    pos,
+                                    c.very_direct,
+                                    &buffernames[ids.very_direct].to_owned(),
+                                );
+                                for i in 0..4 {
+                                    // primal reuse
+                                    let off = GRADIENT_ORDER[i];
+                                    let pos_off = Point2::new(ix as i32 + off.x, iy as i32 + off.y);
+                                    im_block.accumulate_safe(
+                                        pos_off,
+                                        c.radiances[i],
+                                        &buffernames[ids.primal + offset_buffers],
+                                    );
+                                    // gradient
+                                    match GRADIENT_DIRECTION[i] {
+                                        GradientDirection::X(v) => match v {
+                                            1 => im_block.accumulate(
+                                                pos,
+                                                c.gradients[i],
+                                                &buffernames[ids.gradient_x + offset_buffers],
+                                            ),
+                                            -1 => im_block.accumulate_safe(
+                                                pos_off,
+                                                c.gradients[i] * -1.0,
+                                                &buffernames[ids.gradient_x + offset_buffers],
+                                            ),
+                                            _ => panic!("wrong displacement X"), // FIXME: Fix the enum
+                                        },
+                                        GradientDirection::Y(v) => match v {
+                                            1 => im_block.accumulate(
+                                                pos,
+                                                c.gradients[i],
+                                                &buffernames[ids.gradient_y + offset_buffers],
+                                            ),
+                                            -1 => im_block.accumulate_safe(
+                                                pos_off,
+                                                c.gradients[i] * -1.0,
+                                                &buffernames[ids.gradient_y + offset_buffers],
+                                            ),
+                                            _ => panic!("wrong displacement Y"),
+                                        },
+                                    }
                                 }
                             }
                         }
                     }
-                }
-                im_block.scale(1.0 / (scene.nb_samples as f32));
-                // Renormalize correctly the buffer informations
-                for i in 0..nb_buffers {
-                    let offset_buffers = i * 3; // 3 buffer that have multiple entries
-                                                // 4 strategies as reuse primal
-                    im_block.scale_buffer(
-                        0.25 * nb_buffers as f32,
-                        &buffernames[ids.primal + offset_buffers],
-                    );
-                    im_block.scale_buffer(
-                        nb_buffers as f32,
-                        &buffernames[ids.gradient_x + offset_buffers],
-                    );
-                    im_block.scale_buffer(
-                        nb_buffers as f32,
-                        &buffernames[ids.gradient_y + offset_buffers],
-                    );
-                }
+                    im_block.scale(1.0 / (scene.nb_samples as f32));
+                    // Renormalize correctly the buffer informations
+                    for i in 0..nb_buffers {
+                        let offset_buffers = i * 3; // 3 buffer that have multiple entries
+                                                    // 4 strategies as reuse primal
+                        im_block.scale_buffer(
+                            0.25 * nb_buffers as f32,
+                            &buffernames[ids.primal + offset_buffers],
+                        );
+                        im_block.scale_buffer(
+                            nb_buffers as f32,
+                            &buffernames[ids.gradient_x + offset_buffers],
+                        );
+                        im_block.scale_buffer(
+                            nb_buffers as f32,
+                            &buffernames[ids.gradient_y + offset_buffers],
+                        );
+                    }
 
-                {
-                    progress_bar.lock().unwrap().inc();
-                }
-            });
+                    observer.on_tile_done();
+                });
         });
 
         // Fill the image & do the reconstruct
@@ -259,7 +269,10 @@ impl IntegratorGradientPath {
                     (sampler.next(), sampler.next(), sampler.next2d());
                 let main_light_record =
                     emitters.sample_light(&main.its.p, r_sel_rand, r_rand, uv_rand);
-                let main_light_visible = accel.visible(&main.its.p, &main_light_record.p);
+                let main_light_visible = accel.visible(
+                    &main.its.offset_toward(&main_light_record.p),
+                    &main_light_record.p,
+                );
                 let main_emitter_rad = if main_light_visible {
                     main_light_record.weight
                 } else {
@@ -269,7 +282,7 @@ impl IntegratorGradientPath {
                 // Evaluate BSDF values and light values
                 let main_light_pdf = f64::from(main_light_record.pdf.value());
                 let main_bsdf_value = main.its.mesh.bsdf.eval(
-                    &main.its.uv,
+                    &main.its.tex_coords(),
                     &main.its.wi,
                     &main_d_out_local,
                     Domain::SolidAngle,
@@ -280,7 +293,7 @@ impl IntegratorGradientPath {
                             .mesh
                             .bsdf
                             .pdf(
-                                &main.its.uv,
+                                &main.its.tex_coords(),
                                 &main.its.wi,
                                 &main_d_out_local,
                                 Domain::SolidAngle,
@@ -332,7 +345,7 @@ impl IntegratorGradientPath {
                                             .mesh
                                             .bsdf
                                             .pdf(
-                                                &s.its.uv,
+                                                &s.its.tex_coords(),
                                                 &shift_d_in_local,
                                                 &main_d_out_local,
                                                 Domain::SolidAngle,
@@ -340,7 +353,7 @@ impl IntegratorGradientPath {
                                             .value(),
                                     );
                                     let shift_bsdf_value = main.its.mesh.bsdf.eval(
-                                        &s.its.uv,
+                                        &s.its.tex_coords(),
                                         &shift_d_in_local,
                                         &main_d_out_local,
                                         Domain::SolidAngle,
@@ -365,8 +378,10 @@ impl IntegratorGradientPath {
                                     // Sample the light from the point
                                     let shift_light_record = emitters
                                         .sample_light(&s.its.p, r_sel_rand, r_rand, uv_rand);
-                                    let shift_light_visible =
-                                        accel.visible(&s.its.p, &shift_light_record.p);
+                                    let shift_light_visible = accel.visible(
+                                        &s.its.offset_toward(&shift_light_record.p),
+                                        &shift_light_record.p,
+                                    );
                                     let shift_emitter_rad = if shift_light_visible {
                                         shift_light_record.weight
                                             * (shift_light_record.pdf.value()
@@ -379,7 +394,7 @@ impl IntegratorGradientPath {
                                     // BSDF evaluation
                                     let shift_light_pdf = f64::from(shift_light_record.pdf.value());
                                     let shift_bsdf_value = shift_hit_mesh.bsdf.eval(
-                                        &s.its.uv,
+                                        &s.its.tex_coords(),
                                         &s.its.wi,
                                         &shift_d_out_local,
                                         Domain::SolidAngle, // Already check that we are on a non smooth surface
@@ -389,7 +404,7 @@ impl IntegratorGradientPath {
                                             shift_hit_mesh
                                                 .bsdf
                                                 .pdf(
-                                                    &s.its.uv,
+                                                    &s.its.tex_coords(),
                                                     &s.its.wi,
                                                     &shift_d_out_local,
                                                     Domain::SolidAngle,
@@ -447,20 +462,18 @@ impl IntegratorGradientPath {
             // BSDF sampling
             /////////////////////////////////
             // Compute an new direction (diffuse)
-            let main_sampled_bsdf =
-                match main
-                    .its
-                    .mesh
-                    .bsdf
-                    .sample(&main.its.uv, &main.its.wi, sampler.next2d())
-                {
-                    Some(x) => x,
-                    None => return l_i,
-                };
+            let main_sampled_bsdf = match main.its.mesh.bsdf.sample(
+                &main.its.tex_coords(),
+                &main.its.wi,
+                sampler.next2d(),
+            ) {
+                Some(x) => x,
+                None => return l_i,
+            };
 
             // Generate the new ray and do the intersection
             let main_d_out_global = main.its.frame.to_world(main_sampled_bsdf.d);
-            main.ray = Ray::new(main.its.p, main_d_out_global);
+            main.ray = main.its.spawn_ray(main_d_out_global);
             let main_pred_its = main.its; // Need to save the previous hit
             main.its = match accel.trace(&main.ray) {
                 Some(x) => x,
@@ -551,7 +564,7 @@ impl IntegratorGradientPath {
                                             .mesh
                                             .bsdf
                                             .pdf(
-                                                &main_pred_its.uv,
+                                                &main_pred_its.tex_coords(),
                                                 &shift_d_in_local,
                                                 &main_sampled_bsdf.d,
                                                 Domain::SolidAngle,
@@ -559,7 +572,7 @@ impl IntegratorGradientPath {
                                             .value(),
                                     );
                                     let shift_bsdf_value = main_pred_its.mesh.bsdf.eval(
-                                        &main_pred_its.uv,
+                                        &main_pred_its.tex_coords(),
                                         &shift_d_in_local,
                                         &main_sampled_bsdf.d,
                                         Domain::SolidAngle,
@@ -589,7 +602,10 @@ impl IntegratorGradientPath {
                             let shift_bsdf_rought = !s.its.mesh.bsdf.is_smooth();
                             if main_bsdf_rought && main_next_bsdf_rought && shift_bsdf_rought {
                                 // In this case, we can do the reconnection
-                                if !accel.visible(&s.its.p, &main.its.p) {
+                                if !accel.visible(
+                                    &s.its.offset_toward(&main.its.p),
+                                    &main.its.offset_toward(&s.its.p),
+                                ) {
                                     ShiftResult::default()
                                 } else {
                                     // Compute the ratio of geometry factors
@@ -608,7 +624,7 @@ impl IntegratorGradientPath {
                                     assert!(jacobian >= 0.0);
                                     // BSDF
                                     let shift_bsdf_value = s.its.mesh.bsdf.eval(
-                                        &s.its.uv,
+                                        &s.its.tex_coords(),
                                         &s.its.wi,
                                         &shift_d_out_local,
                                         Domain::SolidAngle, // Already checked that we are not on a smooth surface
@@ -618,7 +634,7 @@ impl IntegratorGradientPath {
                                             .mesh
                                             .bsdf
                                             .pdf(
-                                                &s.its.uv,
+                                                &s.its.tex_coords(),
                                                 &s.its.wi,
                                                 &shift_d_out_local,
                                                 Domain::SolidAngle,
@@ -757,7 +773,7 @@ impl IntegratorGradientPath {
                                     s.pdf *= f64::from(jacobian);
                                     // Evaluate the new direction
                                     s.throughput *= &s.its.mesh.bsdf.eval(
-                                        &s.its.uv,
+                                        &s.its.tex_coords(),
                                         &s.its.wi,
                                         &wo,
                                         Domain::Discrete,
@@ -766,12 +782,17 @@ impl IntegratorGradientPath {
                                         s.its
                                             .mesh
                                             .bsdf
-                                            .pdf(&s.its.uv, &s.its.wi, &wo, Domain::Discrete)
+                                            .pdf(
+                                                &s.its.tex_coords(),
+                                                &s.its.wi,
+                                                &wo,
+                                                Domain::Discrete,
+                                            )
                                             .value(),
                                     );
                                     // Shoot a ray to compute the next intersection
                                     let shift_d_out_global = s.its.frame.to_world(wo);
-                                    s.ray = Ray::new(s.its.p, shift_d_out_global);
+                                    s.ray = s.its.spawn_ray(shift_d_out_global);
                                     let new_its = accel.trace(&s.ray);
                                     if let Some(new_its) = new_its {
                                         s.its = new_its;
@@ -1,6 +1,8 @@
+use crate::integrators::gradient::dct::DCT;
 use crate::integrators::gradient::*;
 use crate::Scale;
 use cgmath::Vector2;
+use rayon::prelude::*;
 
 pub struct BaggingPoissonReconstruction {
     pub iterations: usize,
@@ -343,3 +345,260 @@ impl PoissonReconstruction for UniformPoissonReconstruction {
         image
     }
 }
+
+/// Direct solver of the L2 screened Poisson problem (Kettunen et al. 2015):
+/// min alpha^2 |I - primal|^2 + |grad I - gradients|^2 with Neumann boundaries.
+/// The image Laplacian is diagonal in the DCT basis, the rows and the columns
+/// are transformed in parallel (no iterations).
+pub struct FourierPoissonReconstruction {
+    pub alpha: f32,
+}
+
+/// DCT (or inverse DCT) of the rows then of the columns of an image
+fn dct_2d(data: &mut [f64], dct_x: &DCT, dct_y: &DCT, inverse: bool) {
+    let (w, h) = (dct_x.size(), dct_y.size());
+    let apply = |dct: &DCT, v: &mut [f64]| {
+        if inverse {
+            dct.inverse(v)
+        } else {
+            dct.forward(v)
+        }
+    };
+    data.par_chunks_mut(w).for_each(|row| apply(dct_x, row));
+    // The columns are transformed as the rows of the transposed image
+    let mut transposed = vec![0.0; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            transposed[x * h + y] = data[y * w + x];
+        }
+    }
+    transposed
+        .par_chunks_mut(h)
+        .for_each(|column| apply(dct_y, column));
+    for y in 0..h {
+        for x in 0..w {
+            data[y * w + x] = transposed[x * h + y];
+        }
+    }
+}
+
+impl PoissonReconstruction for FourierPoissonReconstruction {
+    fn need_variance_estimates(&self) -> Option<usize> {
+        None
+    }
+
+    fn reconstruct(&self, scene: &Scene, est: &BufferCollection) -> BufferCollection {
+        let img_size = est.size;
+        let (w, h) = (img_size.x as usize, img_size.y as usize);
+        let (dct_x, dct_y) = (DCT::new(w), DCT::new(h));
+        // Eigenvalues of the 1D Laplacians
+        let eigenvalues = |n: usize| {
+            (0..n)
+                .map(|k| 2.0 - 2.0 * (std::f64::consts::PI * k as f64 / n as f64).cos())
+                .collect::<Vec<_>>()
+        };
+        let (eigen_x, eigen_y) = (eigenvalues(w), eigenvalues(h));
+        let alpha2 = f64::from(self.alpha * self.alpha);
+
+        let primal_name = "primal";
+        let gradient_x_name = "gradient_x";
+        let gradient_y_name = "gradient_y";
+        let very_direct_name = "very_direct";
+
+        let mut recons = Bitmap::<FilmColor>::new(img_size);
+        let pool = generate_pool(scene);
+        pool.install(|| {
+            for c in 0..3 {
+                let value = |x: usize, y: usize, name: &str| {
+                    f64::from(est.get(Point2::new(x as u32, y as u32), name).get(c))
+                };
+                // Weighted primal plus the divergence of the gradients
+                let mut data = vec![0.0; w * h];
+                data.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+                    for (x, v) in row.iter_mut().enumerate() {
+                        *v = alpha2 * value(x, y, primal_name);
+                        if x > 0 {
+                            *v += value(x - 1, y, gradient_x_name);
+                        }
+                        if x < w - 1 {
+                            *v -= value(x, y, gradient_x_name);
+                        }
+                        if y > 0 {
+                            *v += value(x, y - 1, gradient_y_name);
+                        }
+                        if y < h - 1 {
+                            *v -= value(x, y, gradient_y_name);
+                        }
+                    }
+                });
+
+                dct_2d(&mut data, &dct_x, &dct_y, false);
+                data.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+                    for (x, v) in row.iter_mut().enumerate() {
+                        *v /= alpha2 + eigen_x[x] + eigen_y[y];
+                    }
+                });
+                dct_2d(&mut data, &dct_x, &dct_y, true);
+
+                for (color, v) in recons.colors.iter_mut().zip(data) {
+                    match c {
+                        0 => color.r = v as FilmFloat,
+                        1 => color.g = v as FilmFloat,
+                        _ => color.b = v as FilmFloat,
+                    }
+                }
+            }
+        });
+
+        // Export the reconstruction
+        let mut image: BufferCollection =
+            BufferCollection::new(Point2::new(0, 0), img_size, &[String::from("primal")]);
+        image.accumulate_local(&recons, primal_name);
+        image.accumulate_bitmap_buffer(est, very_direct_name, primal_name);
+        image
+    }
+}
+
+/// L1 reconstruction (iteratively reweighted least squares) where the gradients
+/// between pixels with different features (albedo, normal) are trusted less,
+/// which avoids smearing the errors of the gradients across the geometric edges.
+pub struct FeaturePoissonReconstruction {
+    /// Jacobi iterations for each set of weights
+    pub iterations: usize,
+    /// Number of times the weights are recomputed from the residuals
+    pub reweighting: usize,
+    /// Weight of the primal image
+    pub alpha: f32,
+    pub sigma_albedo: f32,
+    pub sigma_normal: f32,
+}
+impl FeaturePoissonReconstruction {
+    pub fn new(iterations: usize, alpha: f32) -> FeaturePoissonReconstruction {
+        FeaturePoissonReconstruction {
+            iterations,
+            reweighting: 5,
+            alpha,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.3,
+        }
+    }
+
+    /// Confidence of the gradient between two pixels
+    fn feature_weight(&self, est: &BufferCollection, p: Point2<u32>, q: Point2<u32>) -> f32 {
+        let diff2 = |name: &str| {
+            let d = est.get(p, name) - est.get(q, name);
+            d.r * d.r + d.g * d.g + d.b * d.b
+        };
+        (-diff2("albedo") / (self.sigma_albedo * self.sigma_albedo)
+            - diff2("normal") / (self.sigma_normal * self.sigma_normal))
+            .exp()
+    }
+}
+
+impl PoissonReconstruction for FeaturePoissonReconstruction {
+    fn need_variance_estimates(&self) -> Option<usize> {
+        None
+    }
+    fn need_features(&self) -> bool {
+        true
+    }
+
+    fn reconstruct(&self, scene: &Scene, est: &BufferCollection) -> BufferCollection {
+        // Smallest residual used for the weights (avoid the division by zero)
+        const EPSILON: f32 = 1e-3;
+        let img_size = est.size;
+        let (w, h) = (img_size.x as usize, img_size.y as usize);
+        let pos = |i: usize| Point2::new((i % w) as u32, (i / w) as u32);
+        let primal = (0..w * h)
+            .map(|i| est.get(pos(i), "primal"))
+            .collect::<Vec<_>>();
+        let gradient_x = (0..w * h)
+            .map(|i| est.get(pos(i), "gradient_x"))
+            .collect::<Vec<_>>();
+        let gradient_y = (0..w * h)
+            .map(|i| est.get(pos(i), "gradient_y"))
+            .collect::<Vec<_>>();
+        // Weights of the constraints (primal, gradient to the right and to the bottom)
+        let feature_x = (0..w * h)
+            .map(|i| {
+                if i % w < w - 1 {
+                    self.feature_weight(est, pos(i), pos(i + 1))
+                } else {
+                    0.0
+                }
+            })
+            .collect::<Vec<_>>();
+        let feature_y = (0..w * h)
+            .map(|i| {
+                if i / w < h - 1 {
+                    self.feature_weight(est, pos(i), pos(i + w))
+                } else {
+                    0.0
+                }
+            })
+            .collect::<Vec<_>>();
+        let alpha2 = self.alpha * self.alpha;
+        let mut weight_primal = vec![alpha2; w * h];
+        let (mut weight_x, mut weight_y) = (feature_x.clone(), feature_y.clone());
+
+        let mut current = primal.clone();
+        let mut next = vec![Color::zero(); w * h];
+        let pool = generate_pool(scene);
+        pool.install(|| {
+            for reweight in 0..=self.reweighting {
+                if reweight != 0 {
+                    // L1 weights from the residuals of the current solution
+                    let residual = |c: Color| c.abs().avg().max(EPSILON);
+                    for i in 0..w * h {
+                        weight_primal[i] = self.alpha / residual(current[i] - primal[i]);
+                        if i % w < w - 1 {
+                            weight_x[i] = feature_x[i]
+                                / residual(current[i + 1] - current[i] - gradient_x[i]);
+                        }
+                        if i / w < h - 1 {
+                            weight_y[i] = feature_y[i]
+                                / residual(current[i + w] - current[i] - gradient_y[i]);
+                        }
+                    }
+                }
+
+                for _ in 0..self.iterations {
+                    next.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+                        for (x, v) in row.iter_mut().enumerate() {
+                            let i = y * w + x;
+                            let mut c = primal[i] * weight_primal[i];
+                            let mut sum = weight_primal[i];
+                            if x > 0 {
+                                c += (current[i - 1] + gradient_x[i - 1]) * weight_x[i - 1];
+                                sum += weight_x[i - 1];
+                            }
+                            if x < w - 1 {
+                                c += (current[i + 1] - gradient_x[i]) * weight_x[i];
+                                sum += weight_x[i];
+                            }
+                            if y > 0 {
+                                c += (current[i - w] + gradient_y[i - w]) * weight_y[i - w];
+                                sum += weight_y[i - w];
+                            }
+                            if y < h - 1 {
+                                c += (current[i + w] - gradient_y[i]) * weight_y[i];
+                                sum += weight_y[i];
+                            }
+                            *v = if sum > 0.0 { c / sum } else { current[i] };
+                        }
+                    });
+                    std::mem::swap(&mut current, &mut next);
+                }
+            }
+        });
+
+        // Export the reconstruction
+        let mut recons = Bitmap::new(img_size);
+        recons.colors = current.into_iter().map(FilmColor::from_color).collect();
+        let mut image: BufferCollection =
+            BufferCollection::new(Point2::new(0, 0), img_size, &[String::from("primal")]);
+        image.accumulate_local(&recons, "primal");
+        image.accumulate_bitmap_buffer(est, "very_direct", "primal");
+        image
+    }
+}
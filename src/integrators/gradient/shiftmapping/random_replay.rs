@@ -38,6 +38,10 @@ impl<'sampler, 'seq> Sampler for ReplaySampler<'sampler, 'seq> {
         Point2::new(v1, v2)
     }
 }
+// Shift in the primary sample space: the offset path reuses the random numbers
+// of the base path (including the scattering distances inside the media).
+// The Jacobian of the shift is the ratio of the two path pdfs, which is already
+// taken into account as each path is divided by its own pdf
 pub struct RandomReplay {
     pub random_sequence: Vec<f32>,
     pub base_value: Color,
@@ -0,0 +1,194 @@
+//! Object and material ID buffers: the meshes and materials seen by each pixel
+//! with their coverage, i.e. the filtered fraction of the pixel they cover.
+//! Unlike an ID of the first hit, the coverages give anti-aliased mattes
+//! (cryptomatte layers, selection of objects for the compositing).
+use crate::integrators::cryptomatte::name_to_id;
+use crate::integrators::*;
+use rayon::prelude::*;
+
+/// Pixel filter weighting the camera rays of the coverages
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFilter {
+    /// Uniform over the pixel
+    Box,
+    /// Gaussian (standard deviation of 0.5 pixel) truncated at 1.5 pixels
+    Gaussian,
+}
+
+impl PixelFilter {
+    /// Half size of the footprint (pixels)
+    pub fn radius(&self) -> f32 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Gaussian => 1.5,
+        }
+    }
+
+    /// Weight of a camera ray at the offset d from the pixel center
+    pub fn eval(&self, d: Vector2<f32>) -> f32 {
+        match self {
+            PixelFilter::Box => 1.0,
+            PixelFilter::Gaussian => {
+                let r = self.radius();
+                let g = |x: f32| ((-2.0 * x * x).exp() - (-2.0 * r * r).exp()).max(0.0);
+                g(d.x) * g(d.y)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for PixelFilter {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(PixelFilter::Box),
+            "gaussian" => Ok(PixelFilter::Gaussian),
+            _ => Err(format!("Unknown pixel filter: {} (box or gaussian)", s)),
+        }
+    }
+}
+
+/// IDs (cryptomatte hashes of the names, see name_to_id) and coverages of each pixel,
+/// by decreasing coverage. The coverages of a pixel sum to the fraction of the filter
+/// footprint covered by the scene (1 without background).
+#[derive(Clone, Debug)]
+pub struct IdBuffer {
+    pub size: Vector2<u32>,
+    pub pixels: Vec<Vec<(u32, f32)>>,
+}
+
+impl IdBuffer {
+    fn new(size: Vector2<u32>, coverages: Vec<HashMap<u32, f32>>) -> IdBuffer {
+        let pixels = coverages
+            .into_iter()
+            .map(|coverage| {
+                let mut ranked = coverage.into_iter().collect::<Vec<_>>();
+                // Same coverage: sorted by ID for a deterministic order
+                ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+                ranked
+            })
+            .collect();
+        IdBuffer { size, pixels }
+    }
+
+    pub fn pixel(&self, p: Point2<u32>) -> &[(u32, f32)] {
+        &self.pixels[(p.y * self.size.x + p.x) as usize]
+    }
+
+    /// Coverage of the name (mesh or material) inside the pixel
+    pub fn coverage(&self, p: Point2<u32>, name: &str) -> f32 {
+        let id = name_to_id(name).to_bits();
+        self.pixel(p)
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map_or(0.0, |(_, c)| *c)
+    }
+
+    /// Matte of the selected names (sum of their coverages) to composite them
+    pub fn matte(&self, names: &[&str]) -> Bitmap {
+        let ids = names
+            .iter()
+            .map(|n| name_to_id(n).to_bits())
+            .collect::<Vec<_>>();
+        let mut matte = Bitmap::new(self.size);
+        for (c, p) in matte.colors.iter_mut().zip(&self.pixels) {
+            let coverage = p
+                .iter()
+                .filter(|(id, _)| ids.contains(id))
+                .map(|(_, c)| c)
+                .sum::<f32>();
+            *c = Color::value(coverage);
+        }
+        matte
+    }
+
+    /// Random color by ID weighted by its coverage
+    pub fn preview(&self) -> Bitmap {
+        let mut preview = Bitmap::new(self.size);
+        for (c, p) in preview.colors.iter_mut().zip(&self.pixels) {
+            for (h, coverage) in p {
+                let color = Color::new(
+                    (h & 255) as f32 / 255.0,
+                    ((h >> 8) & 255) as f32 / 255.0,
+                    ((h >> 16) & 255) as f32 / 255.0,
+                );
+                *c += color * *coverage;
+            }
+        }
+        preview
+    }
+}
+
+/// Object (mesh names) and material ID buffers of the camera
+#[derive(Clone, Debug)]
+pub struct IdBuffers {
+    pub objects: IdBuffer,
+    pub materials: IdBuffer,
+}
+
+/// Coverages of the primary hits: nb_samples camera rays per pixel, uniformly distributed
+/// over the footprint of the filter and weighted by it. The weights are normalized by
+/// the sum over all the rays, so the rays escaping the scene reduce the coverages.
+/// The observer is notified after each row.
+pub fn compute_ids(
+    accel: &dyn Acceleration,
+    scene: &Scene,
+    filter: PixelFilter,
+    nb_samples: usize,
+    observer: &dyn RenderObserver,
+) -> IdBuffers {
+    assert_ne!(nb_samples, 0);
+    let size = *scene.camera.size();
+    let radius = filter.radius();
+    observer.on_start(size.y as usize);
+    let pool = generate_pool(scene);
+    let pixels = pool.install(|| {
+        (0..size.y)
+            .into_par_iter()
+            .flat_map(|y| {
+                let mut sampler =
+                    independent::IndependentSampler::for_task(scene.pass_seed(), y as usize);
+                let row = (0..size.x)
+                    .map(|x| {
+                        let mut objects = HashMap::new();
+                        let mut materials = HashMap::new();
+                        let mut total = 0.0;
+                        let mut hits = vec![];
+                        for _ in 0..nb_samples {
+                            let d = Vector2::new(
+                                (2.0 * sampler.next() - 1.0) * radius,
+                                (2.0 * sampler.next() - 1.0) * radius,
+                            );
+                            let weight = filter.eval(d);
+                            if weight == 0.0 {
+                                continue;
+                            }
+                            total += weight;
+                            let pix = Point2::new(x as f32 + 0.5, y as f32 + 0.5) + d;
+                            if let Some(its) = accel.trace(&scene.camera.generate(pix).ray) {
+                                hits.push((its.mesh, weight));
+                            }
+                        }
+                        for (mesh, weight) in hits {
+                            let weight = weight / total;
+                            *objects
+                                .entry(name_to_id(&mesh.name).to_bits())
+                                .or_insert(0.0) += weight;
+                            *materials
+                                .entry(name_to_id(&mesh.material_name).to_bits())
+                                .or_insert(0.0) += weight;
+                        }
+                        (objects, materials)
+                    })
+                    .collect::<Vec<_>>();
+                observer.on_tile_done();
+                row
+            })
+            .collect::<Vec<_>>()
+    });
+    let (objects, materials): (Vec<_>, Vec<_>) = pixels.into_iter().unzip();
+    IdBuffers {
+        objects: IdBuffer::new(size, objects),
+        materials: IdBuffer::new(size, materials),
+    }
+}
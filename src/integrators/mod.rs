@@ -1,17 +1,19 @@
 use crate::emitter::*;
+use crate::paths::vertex::Path;
 use crate::samplers::*;
 use crate::scene::*;
 use crate::structure::*;
 use crate::tools::StepRangeInt;
 use crate::Scale;
 
-use cgmath::{Point2, Vector2};
+use cgmath::{Point2, Point3, Vector2};
 use pbr::ProgressBar;
 use rayon;
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 use std;
 use std::cmp;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::Instant;
 
@@ -21,7 +23,12 @@ use std::time::Instant;
 pub struct BufferCollection {
     pub pos: Point2<u32>,
     pub size: Vector2<u32>,
-    pub values: HashMap<String, Bitmap>,
+    /// Buffers in film precision (see FilmColor)
+    pub values: HashMap<String, Bitmap<FilmColor>>,
+    /// Sum of the weights of the passes merged by accumulate_pass
+    pub pass_weight: f32,
+    /// Object and material ID buffers (see Scene::output_ids)
+    pub ids: Option<ids::IdBuffers>,
 }
 
 impl BufferCollection {
@@ -31,6 +38,8 @@ impl BufferCollection {
             pos,
             size,
             values: HashMap::new(),
+            pass_weight: 0.0,
+            ids: None,
         };
 
         for s in names {
@@ -47,6 +56,8 @@ impl BufferCollection {
             pos,
             size,
             values: HashMap::new(),
+            pass_weight: 0.0,
+            ids: None,
         };
         for key in other.values.keys() {
             bitmap.register(key.clone());
@@ -54,17 +65,15 @@ impl BufferCollection {
         bitmap
     }
 
-    pub fn dump_all(&self, name: &str) {
-        let output_ext = match std::path::Path::new(name).extension() {
-            None => panic!("No file extension provided"),
-            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
-        };
+    pub fn dump_all(&self, name: &str) -> crate::error::Result<()> {
+        let output_ext = crate::tools::extension(name)?;
         let mut trunc_name = name.to_string();
         trunc_name.truncate(name.len() - output_ext.len() - 1);
         for (key, value) in self.values.iter() {
             let new_name = format!("{}_{}.{}", trunc_name, key, output_ext);
-            value.save(new_name.as_str());
+            value.to_f32().save(new_name.as_str())?;
         }
+        Ok(())
     }
 
     /// Register a name for a particular buffer
@@ -126,6 +135,36 @@ impl BufferCollection {
         }
     }
 
+    /// Accumulate a bitmap of the same size as the collection
+    pub fn accumulate_local(&mut self, o: &Bitmap<FilmColor>, name: &str) {
+        assert_eq!(o.size, self.size);
+        self.values
+            .get_mut(name)
+            .unwrap()
+            .accumulate_bitmap(o, Point2::new(0, 0));
+    }
+
+    /// Progressive rendering: the buffers are the weighted average of the passes.
+    /// The pass is already normalized, its weight is usually its number of samples
+    pub fn accumulate_pass(&mut self, o: &BufferCollection, weight: f32) {
+        assert!(weight > 0.0);
+        assert_eq!(o.size, self.size);
+        let total = self.pass_weight + weight;
+        let (w_old, w_new) = (
+            (self.pass_weight / total) as FilmFloat,
+            (weight / total) as FilmFloat,
+        );
+        for (name, bitmap) in &mut self.values {
+            for (c, c_new) in bitmap.colors.iter_mut().zip(&o.values[name].colors) {
+                *c = *c * w_old + *c_new * w_new;
+            }
+        }
+        self.pass_weight = total;
+        if self.ids.is_none() {
+            self.ids = o.ids.clone();
+        }
+    }
+
     pub fn accumulate(&mut self, p: Point2<u32>, f: Color, name: &str) {
         self.values.get_mut(name).unwrap().accumulate(p, f);
     }
@@ -166,8 +205,8 @@ impl BufferCollection {
         self.values.get_mut(name).unwrap().scale(f);
     }
 
-    pub fn save(&self, name: &str, filename: &str) {
-        self.values[name].save(filename);
+    pub fn save(&self, name: &str, filename: &str) -> crate::error::Result<()> {
+        self.values[name].to_f32().save(filename)
     }
 
     pub fn print_buffers_name(&self) {
@@ -185,24 +224,89 @@ impl Scale<f32> for BufferCollection {
 }
 
 /////////////// Integrators code
+/// Statistics given at the end of the rendering
+pub struct RenderStats {
+    pub elapsed: std::time::Duration,
+    pub nb_samples: usize,
+}
+
+/// Hooks to follow the rendering (progress, intermediate images).
+/// The methods are called from the rendering threads.
+pub trait RenderObserver: Sync {
+    /// A rendering pass starts with nb_tiles work units (blocks, rows, chains...)
+    fn on_start(&self, _nb_tiles: usize) {}
+    fn on_tile_done(&self) {}
+    /// Image accumulated after the pass number `_pass` (starting at 1). Only the
+    /// integrators rendering several passes emit it: the iterations of `avg` and the
+    /// light path passes of `vpl`. The other integrators (`pssmlt` included, its chains
+    /// are tiles of a single pass) only report their tiles.
+    fn on_pass_done(&self, _pass: usize, _img: &Bitmap) {}
+    fn on_stats(&self, _stats: &RenderStats) {}
+}
+
+/// Ignore all the rendering events
+pub struct NoObserver;
+impl RenderObserver for NoObserver {}
+
+/// Print the progress on the terminal
+#[derive(Default)]
+pub struct ProgressBarObserver {
+    progress_bar: Mutex<Option<ProgressBar<std::io::Stdout>>>,
+}
+impl RenderObserver for ProgressBarObserver {
+    fn on_start(&self, nb_tiles: usize) {
+        *self.progress_bar.lock().unwrap() = Some(ProgressBar::new(nb_tiles as u64));
+    }
+    fn on_tile_done(&self) {
+        if let Some(progress_bar) = self.progress_bar.lock().unwrap().as_mut() {
+            progress_bar.inc();
+        }
+    }
+}
+
 pub trait Integrator {
-    fn compute(&mut self, _accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute(
+        &mut self,
+        _accel: &dyn Acceleration,
+        scene: &Scene,
+        _observer: &dyn RenderObserver,
+    ) -> BufferCollection {
         let buffernames = vec!["primal".to_string()];
         BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames)
     }
 }
 pub trait IntegratorGradient: Integrator {
-    fn compute_gradients(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection;
+    fn compute_gradients(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection;
     fn reconstruct(&self) -> &(dyn PoissonReconstruction + Sync);
 
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
         // Rendering the gradient informations
         info!("Gradient Rendering...");
         let start = Instant::now();
-        let image = self.compute_gradients(accel, scene);
+        let mut image = self.compute_gradients(accel, scene, observer);
         let elapsed = start.elapsed();
         info!("Gradient Rendering Elapsed: {:?}", elapsed,);
 
+        // Auxiliary features to guide the reconstruction
+        if self.reconstruct().need_features() {
+            info!("Features...");
+            let features = gradient::features::compute_features(accel, scene);
+            for name in &["albedo", "normal"] {
+                image.register(name.to_string());
+                image.accumulate_bitmap_buffer(&features, name, name);
+            }
+        }
+
         // Reconstruct the image
         info!("Reconstruction...");
         let start = Instant::now();
@@ -216,66 +320,170 @@ pub trait IntegratorGradient: Integrator {
 pub trait PoissonReconstruction {
     fn reconstruct(&self, scene: &Scene, est: &BufferCollection) -> BufferCollection;
     fn need_variance_estimates(&self) -> Option<usize>;
+    /// Request the "albedo" and "normal" buffers of the first intersection
+    fn need_features(&self) -> bool {
+        false
+    }
 }
 pub enum IntegratorType {
     Primal(Box<dyn Integrator>),
     Gradient(Box<dyn IntegratorGradient>),
 }
+/// Embree scene with all the meshes (geometry IDs are the mesh indices),
+/// e.g. for the custom queries of an EmbreeAcceleration
+pub fn embree_scene<'a>(
+    device: &'a embree_rs::Device,
+    meshes: &[crate::geometry::Mesh],
+) -> embree_rs::Scene<'a> {
+    let mut embree_scene = embree_rs::Scene::new(device);
+    for m in meshes {
+        let mut tris =
+            embree_rs::TriangleMesh::unanimated(device, m.indices.len(), m.vertices.len());
+        {
+            let mut verts = tris.vertex_buffer.map();
+            let mut tris = tris.index_buffer.map();
+            for i in 0..m.vertices.len() {
+                verts[i] =
+                    cgmath::Vector4::new(m.vertices[i].x, m.vertices[i].y, m.vertices[i].z, 0.0);
+            }
+
+            for i in 0..m.indices.len() {
+                tris[i] = cgmath::Vector3::new(
+                    m.indices[i].x as u32,
+                    m.indices[i].y as u32,
+                    m.indices[i].z as u32,
+                );
+            }
+        }
+        let mut tri_geom = embree_rs::Geometry::Triangle(tris);
+        tri_geom.commit();
+        embree_scene.attach_geometry(tri_geom);
+    }
+    embree_scene
+}
+
 impl IntegratorType {
-    pub fn compute(&mut self, scene: &Scene) -> BufferCollection {
+    pub fn compute(&mut self, scene: &Scene, observer: &dyn RenderObserver) -> BufferCollection {
         info!("Build acceleration data structure...");
         let embree_device = embree_rs::Device::new();
-        let mut embree_scene = embree_rs::Scene::new(&embree_device);
-        // Add all meshes
-        for m in &scene.meshes {
-            let mut tris = embree_rs::TriangleMesh::unanimated(
-                &embree_device,
-                m.indices.len(),
-                m.vertices.len(),
-            );
-            {
-                let mut verts = tris.vertex_buffer.map();
-                let mut tris = tris.index_buffer.map();
-                for i in 0..m.vertices.len() {
-                    verts[i] = cgmath::Vector4::new(
-                        m.vertices[i].x,
-                        m.vertices[i].y,
-                        m.vertices[i].z,
-                        0.0,
-                    );
-                }
+        let embree_scene = embree_scene(&embree_device, &scene.meshes);
+        let accel = EmbreeAcceleration::new(&scene.meshes, &embree_scene);
+        self.compute_accel(&accel, scene, observer)
+    }
+
+    /// Render the named cameras of the scene one after the other,
+    /// the acceleration data structure is built once for all of them.
+    /// The main camera is restored afterwards.
+    pub fn compute_cameras(
+        &mut self,
+        scene: &mut Scene,
+        names: &[String],
+        observer: &dyn RenderObserver,
+    ) -> crate::error::Result<Vec<BufferCollection>> {
+        // Check the names before rendering anything
+        let mut cameras = vec![];
+        for name in names {
+            cameras.push(scene.find_camera(name)?.clone());
+        }
 
-                for i in 0..m.indices.len() {
-                    tris[i] = cgmath::Vector3::new(
-                        m.indices[i].x as u32,
-                        m.indices[i].y as u32,
-                        m.indices[i].z as u32,
-                    );
+        info!("Build acceleration data structure...");
+        let embree_device = embree_rs::Device::new();
+        let embree_scene = embree_scene(&embree_device, &scene.meshes);
+        let accel = EmbreeAcceleration::new(&scene.meshes, &embree_scene);
+        let mut images = vec![];
+        for (name, camera) in names.iter().zip(cameras) {
+            info!("Render the camera {}", name);
+            let main_camera = std::mem::replace(&mut scene.camera, camera);
+            images.push(self.compute_accel(&accel, scene, observer));
+            scene.camera = main_camera;
+        }
+        Ok(images)
+    }
+
+    /// Render the successive versions of a scene (hot-reload): next receives the
+    /// rendered scene with its image and returns the next version (None to stop).
+    /// The acceleration data structure is only rebuilt when the geometry changes.
+    pub fn compute_reload<F>(&mut self, scene: Scene, observer: &dyn RenderObserver, mut next: F)
+    where
+        F: FnMut(&Scene, BufferCollection) -> Option<Scene>,
+    {
+        let embree_device = embree_rs::Device::new();
+        let mut scene = scene;
+        loop {
+            info!("Build acceleration data structure...");
+            let embree_scene = embree_scene(&embree_device, &scene.meshes);
+            loop {
+                let img = {
+                    let accel = EmbreeAcceleration::new(&scene.meshes, &embree_scene);
+                    self.compute_accel(&accel, &scene, observer)
+                };
+                let next_scene = match next(&scene, img) {
+                    Some(s) => s,
+                    None => return,
+                };
+                let reuse = crate::geometry::same_geometry(&scene.meshes, &next_scene.meshes);
+                scene = next_scene;
+                if !reuse {
+                    break;
                 }
+                info!("Same geometry: reuse the acceleration data structure");
             }
-            let mut tri_geom = embree_rs::Geometry::Triangle(tris);
-            tri_geom.commit();
-            embree_scene.attach_geometry(tri_geom);
         }
-        let accel = EmbreeAcceleration::new(scene, &embree_scene);
+    }
 
+    fn compute_accel(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        scene.prepare_emitters(accel);
         info!("Run Integrator...");
         let start = Instant::now();
 
-        let img = match self {
-            IntegratorType::Primal(ref mut v) => v.compute(&accel, scene),
+        let mut img = match self {
+            IntegratorType::Primal(ref mut v) => v.compute(accel, scene, observer),
             IntegratorType::Gradient(ref mut v) => {
-                IntegratorGradient::compute(v.as_mut(), &accel, scene)
+                IntegratorGradient::compute(v.as_mut(), accel, scene, observer)
             }
         };
+        film::apply_film(accel, scene, &mut img);
+        // Already done per sample by compute_mc
+        if scene.demodulate && !img.values.contains_key("albedo") {
+            demodulation::demodulate(accel, scene, &mut img);
+        }
+        if let Some(filter) = scene.output_ids {
+            info!("Compute the object and material ID buffers...");
+            img.ids = Some(ids::compute_ids(
+                accel,
+                scene,
+                filter,
+                scene.nb_samples,
+                &NoObserver,
+            ));
+        }
 
         let elapsed = start.elapsed();
         info!("Elapsed Integrator: {} ms", elapsed.as_millis());
+        observer.on_stats(&RenderStats {
+            elapsed,
+            nb_samples: scene.nb_samples,
+        });
 
         img
     }
 }
 
+/// Temporaries of a rendering thread, reused between its samples
+/// to avoid the allocations in the inner rendering loop
+#[derive(Default)]
+pub struct Scratch<'scene, 'emitter> {
+    /// Vertices and edges of the path of the current sample
+    pub path: Path<'scene, 'emitter>,
+    /// Samples of the current pixel (primal, surface, volume)
+    samples: [Vec<Color>; 3],
+}
+
 /////////////// Implementation gradients
 pub trait IntegratorMC: Sync + Send {
     fn compute_pixel(
@@ -286,20 +494,178 @@ pub trait IntegratorMC: Sync + Send {
         sampler: &mut dyn Sampler,
         emitters: &EmitterSampler,
     ) -> Color;
+
+    /// Same as compute_pixel, with the temporaries of the rendering thread
+    fn compute_pixel_scratch<'scene, 'emitter>(
+        &self,
+        pix: (u32, u32),
+        accel: &'scene dyn Acceleration,
+        scene: &'scene Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &'emitter EmitterSampler,
+        _scratch: &mut Scratch<'scene, 'emitter>,
+    ) -> Color {
+        self.compute_pixel(pix, accel, scene, sampler, emitters)
+    }
+
+    /// The integrator separates the radiance scattered by the medium
+    /// toward the camera (see compute_pixel_volume)
+    fn separates_volume(&self) -> bool {
+        false
+    }
+
+    /// Selection of the emitters given to compute_pixel (the one of the scene by default)
+    fn emitter_selection(&self, scene: &Scene) -> EmitterSelection {
+        scene.emitter_selection
+    }
+
+    /// Radiance of the sample split into the surface part (seen through the medium)
+    /// and the volume part (scattered by the medium along the camera ray)
+    fn compute_pixel_volume(
+        &self,
+        pix: (u32, u32),
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+    ) -> (Color, Color) {
+        (
+            self.compute_pixel(pix, accel, scene, sampler, emitters),
+            Color::zero(),
+        )
+    }
+}
+
+/// Size of the rendering blocks, unless the scene gives one (largest automatic size)
+pub const TILE_SIZE: u32 = 16;
+/// Smallest rendering blocks of the automatic sizing
+const MIN_TILE_SIZE: u32 = 4;
+/// Number of blocks per thread of the automatic sizing (load balancing)
+const TILES_PER_THREAD: usize = 8;
+/// Rays traced inside a block of the automatic sizing, at least (amortize its allocations)
+const MIN_TILE_RAYS: f64 = 4096.0;
+/// Pixels of the warm-up pass of the automatic sizing (grid of WARMUP_GRID x WARMUP_GRID)
+const WARMUP_GRID: u32 = 8;
+
+/// Acceleration counting the traced rays (statistics of the warm-up pass)
+struct RayCounter<'a> {
+    accel: &'a dyn Acceleration,
+    rays: AtomicUsize,
+}
+impl<'a> Acceleration for RayCounter<'a> {
+    fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.rays.fetch_add(1, Ordering::Relaxed);
+        self.accel.trace(ray)
+    }
+    fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool {
+        self.rays.fetch_add(1, Ordering::Relaxed);
+        self.accel.visible(p0, p1)
+    }
+    fn intersect_any(&self, ray: &Ray) -> bool {
+        self.rays.fetch_add(1, Ordering::Relaxed);
+        self.accel.intersect_any(ray)
+    }
+    fn closest_point_query(&self, p: &Point3<f32>, max_distance: f32) -> Option<SurfacePoint<'_>> {
+        self.accel.closest_point_query(p, max_distance)
+    }
+}
+
+/// Block size for an image rendered by nb_threads threads with rays_per_pixel rays:
+/// the largest blocks keeping all the threads busy until the end of the rendering,
+/// unless they are too cheap to be worth their allocations
+pub fn auto_tile_size(size: Vector2<u32>, nb_threads: usize, rays_per_pixel: f64) -> u32 {
+    let nb_tiles = |s: u32| size.x.div_ceil(s) as usize * size.y.div_ceil(s) as usize;
+    let balanced = (MIN_TILE_SIZE..=TILE_SIZE)
+        .rev()
+        .find(|&s| nb_tiles(s) >= TILES_PER_THREAD * nb_threads)
+        .unwrap_or(MIN_TILE_SIZE);
+    let amortized = (MIN_TILE_SIZE..=TILE_SIZE)
+        .find(|&s| f64::from(s * s) * rays_per_pixel >= MIN_TILE_RAYS)
+        .unwrap_or(TILE_SIZE);
+    balanced.max(amortized)
+}
+
+/// Block size of compute_mc: the one of the scene, otherwise the automatic size
+/// from the number of rays per sample of the integrator (warm-up pass on a grid of pixels,
+/// only done by the first pass of a progressive rendering).
+/// The seeded renderings (and the deterministic mode) keep the fixed size,
+/// as the automatic one depends on the number of threads.
+fn mc_tile_size<T: IntegratorMC>(int: &T, accel: &dyn Acceleration, scene: &Scene) -> u32 {
+    if let Some(size) = scene.tile_size {
+        return size;
+    }
+    if scene.pass_seed().is_some() {
+        return TILE_SIZE;
+    }
+    if scene.pass.load(Ordering::Relaxed) > 0 {
+        if let Some(size) = *scene.auto_tile_size.lock().unwrap() {
+            return size;
+        }
+    }
+    let size = *scene.camera.size();
+    let counter = RayCounter {
+        accel,
+        rays: AtomicUsize::new(0),
+    };
+    let light_sampling = scene.emitters_sampler_with(int.emitter_selection(scene));
+    // Different random numbers than the rendering blocks
+    let mut sampler = scene.sampler.for_task(scene.pass_seed(), usize::MAX);
+    let mut nb_pixels = 0;
+    for y in 0..WARMUP_GRID {
+        for x in 0..WARMUP_GRID {
+            let pixel = Point2::new(
+                (2 * x + 1) * size.x / (2 * WARMUP_GRID),
+                (2 * y + 1) * size.y / (2 * WARMUP_GRID),
+            );
+            // Not recorded twice by the path debugger
+            if scene.path_debugger.records((pixel.x, pixel.y)) {
+                continue;
+            }
+            sampler.start_pixel_sample(pixel, 0);
+            int.compute_pixel(
+                (pixel.x, pixel.y),
+                &counter,
+                scene,
+                sampler.as_mut(),
+                &light_sampling,
+            );
+            nb_pixels += 1;
+        }
+    }
+    let rays_per_sample = counter.rays.into_inner() as f64 / f64::from(nb_pixels);
+    let tile_size = auto_tile_size(
+        size,
+        nb_threads(scene),
+        rays_per_sample * scene.nb_samples as f64,
+    );
+    info!(
+        "Tile size: {} ({:.1} rays per sample)",
+        tile_size, rays_per_sample
+    );
+    *scene.auto_tile_size.lock().unwrap() = Some(tile_size);
+    tile_size
 }
 
 pub fn generate_img_blocks(scene: &Scene, buffernames: &[String]) -> Vec<BufferCollection> {
+    generate_img_blocks_sized(scene, buffernames, scene.tile_size.unwrap_or(TILE_SIZE))
+}
+
+pub fn generate_img_blocks_sized(
+    scene: &Scene,
+    buffernames: &[String],
+    tile_size: u32,
+) -> Vec<BufferCollection> {
     let mut image_blocks: Vec<BufferCollection> = Vec::new();
-    for ix in StepRangeInt::new(0, scene.camera.size().x as usize, 16) {
-        for iy in StepRangeInt::new(0, scene.camera.size().y as usize, 16) {
+    for ix in StepRangeInt::new(0, scene.camera.size().x as usize, tile_size as usize) {
+        for iy in StepRangeInt::new(0, scene.camera.size().y as usize, tile_size as usize) {
             let block = BufferCollection::new(
                 Point2 {
                     x: ix as u32,
                     y: iy as u32,
                 },
                 Vector2 {
-                    x: cmp::min(16, scene.camera.size().x - ix as u32),
-                    y: cmp::min(16, scene.camera.size().y - iy as u32),
+                    x: cmp::min(tile_size, scene.camera.size().x - ix as u32),
+                    y: cmp::min(tile_size, scene.camera.size().y - iy as u32),
                 },
                 buffernames,
             );
@@ -313,42 +679,169 @@ pub fn compute_mc<T: IntegratorMC + Integrator>(
     int: &T,
     accel: &dyn Acceleration,
     scene: &Scene,
+    observer: &dyn RenderObserver,
 ) -> BufferCollection {
     // Here we can to the classical parallelisation
     assert_ne!(scene.nb_samples, 0);
-    let buffernames = vec!["primal".to_string()];
+    let mut buffernames = vec!["primal".to_string()];
+    if scene.output_variance {
+        // Per-sample variance and number of samples
+        buffernames.push("variance".to_string());
+        buffernames.push("spp".to_string());
+    }
+    let output_volume = scene.output_volume && int.separates_volume();
+    if scene.output_volume && !output_volume {
+        warn!("The integrator does not separate the volume radiance");
+    }
+    if output_volume {
+        buffernames.push("surface".to_string());
+        buffernames.push("volume".to_string());
+    }
+    if scene.demodulate {
+        // Mean albedo of the samples dividing the radiance
+        buffernames.push("albedo".to_string());
+    }
 
     // Create rendering blocks
-    let mut image_blocks = generate_img_blocks(scene, &buffernames);
+    let tile_size = mc_tile_size(int, accel, scene);
+    let mut image_blocks = generate_img_blocks_sized(scene, &buffernames, tile_size);
 
     // Render the image blocks
-    let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
+    observer.on_start(image_blocks.len());
     let pool = generate_pool(scene);
+    // Each sample is divided by the albedo of its camera path
+    let demodulation = if scene.demodulate {
+        Some(demodulation::FirstHitAlbedo::new(
+            accel,
+            pool.current_num_threads(),
+        ))
+    } else {
+        None
+    };
+    let accel = demodulation
+        .as_ref()
+        .map_or(accel, |d| d as &dyn Acceleration);
+    // Shared by the threads, the scratch paths keep references to its emitters
+    let light_sampling = scene.emitters_sampler_with(int.emitter_selection(scene));
     pool.install(|| {
-        image_blocks.par_iter_mut().for_each(|im_block| {
-            // image_blocks.iter_mut().for_each(|im_block| {
-            let mut sampler = independent::IndependentSampler::default();
-            let light_sampling = scene.emitters_sampler();
-            for iy in 0..im_block.size.y {
-                for ix in 0..im_block.size.x {
-                    for _ in 0..scene.nb_samples {
-                        let c = int.compute_pixel(
-                            (ix + im_block.pos.x, iy + im_block.pos.y),
-                            accel,
-                            scene,
-                            &mut sampler,
-                            &light_sampling,
-                        );
-                        im_block.accumulate(Point2 { x: ix, y: iy }, c, &"primal".to_string());
+        image_blocks.par_iter_mut().enumerate().for_each_init(
+            Scratch::default,
+            |scratch, (id, im_block)| {
+                let mut sampler = scene.sampler.for_task(scene.pass_seed(), id);
+                let mut accum = AccumBuffer::new(im_block.size);
+                let mut accum_surface = AccumBuffer::new(im_block.size);
+                let mut accum_volume = AccumBuffer::new(im_block.size);
+                let mut accum_albedo = AccumBuffer::new(im_block.size);
+                // Robust estimates (primal, surface, volume)
+                let mut robust = [
+                    Bitmap::new(im_block.size),
+                    Bitmap::new(im_block.size),
+                    Bitmap::new(im_block.size),
+                ];
+                for iy in 0..im_block.size.y {
+                    for ix in 0..im_block.size.x {
+                        let pixel = Point2::new(ix + im_block.pos.x, iy + im_block.pos.y);
+                        for i in 0..scene.nb_samples {
+                            sampler.start_pixel_sample(pixel, i);
+                            let p = Point2 { x: ix, y: iy };
+                            let origin = |buffer: &str| {
+                                format!(
+                                    "{} ({}), pixel ({}, {}), sample {}",
+                                    std::any::type_name::<T>(),
+                                    buffer,
+                                    pixel.x,
+                                    pixel.y,
+                                    i
+                                )
+                            };
+                            if let Some(d) = &demodulation {
+                                d.start_sample();
+                            }
+                            if output_volume {
+                                let (surface, volume) = int.compute_pixel_volume(
+                                    (pixel.x, pixel.y),
+                                    accel,
+                                    scene,
+                                    sampler.as_mut(),
+                                    &light_sampling,
+                                );
+                                let surface = scene.non_finite.check(surface, || origin("surface"));
+                                let volume = scene.non_finite.check(volume, || origin("volume"));
+                                let (surface, volume) = match &demodulation {
+                                    Some(d) => {
+                                        let albedo = d.albedo();
+                                        accum_albedo.add(p, albedo);
+                                        (surface / albedo, volume / albedo)
+                                    }
+                                    None => (surface, volume),
+                                };
+                                accum.add(p, surface + volume);
+                                accum_surface.add(p, surface);
+                                accum_volume.add(p, volume);
+                                if scene.sample_rejection.is_some() {
+                                    scratch.samples[0].push(surface + volume);
+                                    scratch.samples[1].push(surface);
+                                    scratch.samples[2].push(volume);
+                                }
+                            } else {
+                                let c = int.compute_pixel_scratch(
+                                    (pixel.x, pixel.y),
+                                    accel,
+                                    scene,
+                                    sampler.as_mut(),
+                                    &light_sampling,
+                                    scratch,
+                                );
+                                let c = scene.non_finite.check(c, || origin("primal"));
+                                let c = match &demodulation {
+                                    Some(d) => {
+                                        let albedo = d.albedo();
+                                        accum_albedo.add(p, albedo);
+                                        c / albedo
+                                    }
+                                    None => c,
+                                };
+                                accum.add(p, c);
+                                if scene.sample_rejection.is_some() {
+                                    scratch.samples[0].push(c);
+                                }
+                            }
+                        }
+                        if let Some(rejection) = scene.sample_rejection {
+                            let p = Point2::new(ix, iy);
+                            for (samples, robust) in scratch.samples.iter_mut().zip(&mut robust) {
+                                robust.accumulate(p, rejection.estimate(samples));
+                                samples.clear();
+                            }
+                        }
                     }
                 }
-            }
-            im_block.scale(1.0 / (scene.nb_samples as f32));
+                // The variance buffers keep the statistics of all the samples
+                let means = match scene.sample_rejection {
+                    Some(_) => robust,
+                    None => [
+                        accum.mean_bitmap(),
+                        accum_surface.mean_bitmap(),
+                        accum_volume.mean_bitmap(),
+                    ],
+                };
+                im_block.accumulate_local(&means[0], "primal");
+                if output_volume {
+                    im_block.accumulate_local(&means[1], "surface");
+                    im_block.accumulate_local(&means[2], "volume");
+                }
+                if scene.output_variance {
+                    // Per-sample variance and number of samples
+                    im_block.accumulate_local(&accum.variance_bitmap(), "variance");
+                    im_block.accumulate_local(&accum.count_bitmap(), "spp");
+                }
+                if demodulation.is_some() {
+                    im_block.accumulate_local(&accum_albedo.mean_bitmap(), "albedo");
+                }
 
-            {
-                progress_bar.lock().unwrap().inc();
-            }
-        });
+                observer.on_tile_done();
+            },
+        );
     });
 
     // Fill the image
@@ -360,12 +853,74 @@ pub fn compute_mc<T: IntegratorMC + Integrator>(
 }
 
 pub fn generate_pool(scene: &Scene) -> rayon::ThreadPool {
-    match scene.nb_threads {
+    thread_pool_builder(scene.nb_threads, &scene.thread_pinning)
+        .build()
+        .unwrap()
+}
+
+/// Pool of nb_threads threads (one per core if None) pinned to the cores.
+/// Also used for the global pool (rayon::ThreadPoolBuilder::build_global)
+pub fn thread_pool_builder(
+    nb_threads: Option<usize>,
+    pinning: &ThreadPinning,
+) -> rayon::ThreadPoolBuilder {
+    let builder = match nb_threads {
         None => rayon::ThreadPoolBuilder::new(),
         Some(x) => rayon::ThreadPoolBuilder::new().num_threads(x),
+    };
+    if *pinning == ThreadPinning::None {
+        return builder;
     }
-    .build()
-    .unwrap()
+    let pinning = pinning.clone();
+    builder.start_handler(move |thread| pinning.pin(thread))
+}
+
+/// Number of threads of the rendering pools
+pub fn nb_threads(scene: &Scene) -> usize {
+    scene.nb_threads.unwrap_or_else(rayon::current_num_threads)
+}
+
+/// Number of jobs splatting at the same time in the deterministic mode
+/// (each one has its own buffer)
+pub const DETERMINISTIC_SPLAT_JOBS: usize = 16;
+
+/// Run the jobs (light paths, Markov chains, ...) splatting their contributions on the image.
+/// The atomic additions of the shared buffer depend on the scheduling of the threads:
+/// in the deterministic mode, the jobs run by groups, each one with its own buffer
+/// (reused by the next groups), and each pixel of the image sums the buffers
+/// in the order of the jobs.
+pub fn splat_jobs<J, F>(scene: &Scene, jobs: &mut [J], splat: F) -> Bitmap<FilmColor>
+where
+    J: Send,
+    F: Fn(&mut J, &SplatBuffer) + Sync,
+{
+    let size = *scene.camera.size();
+    let pool = generate_pool(scene);
+    pool.install(|| {
+        if !scene.deterministic {
+            let splats = SplatBuffer::new(size);
+            jobs.par_iter_mut().for_each(|job| splat(job, &splats));
+            return splats.to_bitmap();
+        }
+        let mut img = Bitmap::new(size);
+        let mut buffers = (0..DETERMINISTIC_SPLAT_JOBS.min(jobs.len()))
+            .map(|_| SplatBuffer::new(size))
+            .collect::<Vec<_>>();
+        for group in jobs.chunks_mut(DETERMINISTIC_SPLAT_JOBS) {
+            group
+                .par_iter_mut()
+                .zip(buffers.par_iter_mut())
+                .for_each(|(job, splats)| splat(job, splats));
+            // Ordered reduction, which also resets the buffers
+            let buffers = &buffers[..group.len()];
+            img.colors.par_iter_mut().enumerate().for_each(|(i, c)| {
+                for splats in buffers {
+                    *c += splats.take(i);
+                }
+            });
+        }
+        img
+    })
 }
 
 /// Power heuristic for path tracing or direct lighting
@@ -387,7 +942,15 @@ pub fn mis_weight(pdf_a: f32, pdf_b: f32) -> f32 {
 
 pub mod ao;
 pub mod avg;
+mod builtin;
+pub mod cryptomatte;
+pub mod debug;
+pub mod demodulation;
 pub mod direct;
 pub mod explicit;
+pub mod film;
 pub mod gradient;
+pub mod ids;
 pub mod pssmlt;
+pub mod registry;
+pub mod visibility_cache;
@@ -1,7 +1,10 @@
+use crate::error::{Error, Result};
 use crate::integrators::*;
 use crate::samplers;
+use crate::samplers::mcmc::{IndependentSamplerReplay, MutatorKelemen, ReplayState};
 use cgmath::Point2;
 
+#[derive(Clone)]
 struct MCMCState {
     pub value: Color,
     pub tf: f32,
@@ -24,12 +27,147 @@ impl MCMCState {
     }
 }
 
+/// State of a Markov chain between two mutations
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChainState {
+    pub sampler: ReplayState,
+    /// Contribution and pixel of the current path
+    pub value: Color,
+    pub pix: Point2<u32>,
+}
+
+/// Everything needed to continue a rendering or to merge independent renderings (JSON file).
+/// The pending weights of the chains are splatted before the checkpoint is created.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MLTCheckpoint {
+    pub size: Vector2<u32>,
+    /// Luminance sum and number of the bootstrap samples:
+    /// the normalization constant is their average
+    pub bootstrap_sum: f64,
+    pub bootstrap_samples: usize,
+    /// Total number of mutations done by the chains
+    pub nb_mutations: usize,
+    /// Temperatures of the replicas, the chains are grouped by ensembles of replicas
+    #[serde(default = "default_temperatures")]
+    pub temperatures: Vec<f32>,
+    pub chains: Vec<ChainState>,
+    /// Splatted contributions before the normalization (row major)
+    pub image: Vec<FilmColor>,
+}
+
+fn default_temperatures() -> Vec<f32> {
+    vec![1.0]
+}
+
+impl MLTCheckpoint {
+    pub fn load(filename: &str) -> Result<MLTCheckpoint> {
+        let data = std::fs::read_to_string(filename)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+    pub fn save(&self, filename: &str) -> Result<()> {
+        std::fs::write(filename, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Average luminance of the image
+    pub fn normalization(&self) -> f32 {
+        (self.bootstrap_sum / self.bootstrap_samples as f64) as f32
+    }
+
+    /// Merge an independent rendering of the same scene:
+    /// the splatted images and the bootstrap samples are added, all the chains are kept.
+    /// As the image is normalized by its average, the runs can have different lengths.
+    pub fn merge(&mut self, other: MLTCheckpoint) -> Result<()> {
+        if self.size != other.size {
+            return Err(Error::InvalidData(format!(
+                "MLT checkpoints of different sizes: {:?} and {:?}",
+                self.size, other.size
+            )));
+        }
+        if self.temperatures != other.temperatures {
+            return Err(Error::InvalidData(format!(
+                "MLT checkpoints of different temperatures: {:?} and {:?}",
+                self.temperatures, other.temperatures
+            )));
+        }
+        self.bootstrap_sum += other.bootstrap_sum;
+        self.bootstrap_samples += other.bootstrap_samples;
+        self.nb_mutations += other.nb_mutations;
+        self.chains.extend(other.chains);
+        for (a, b) in self.image.iter_mut().zip(other.image) {
+            *a += b;
+        }
+        Ok(())
+    }
+
+    /// Image scaled to the normalization constant
+    pub fn bitmap(&self) -> Bitmap<FilmColor> {
+        let mut img = Bitmap {
+            size: self.size,
+            colors: self.image.clone(),
+            alpha: None,
+        };
+        let img_avg = img.average();
+        let img_avg_lum = (img_avg.r + img_avg.g + img_avg.b) / 3.0;
+        if img_avg_lum > 0.0 {
+            img.scale(self.normalization() / img_avg_lum);
+        }
+        img
+    }
+}
+
 pub struct IntegratorPSSMLT {
     pub large_prob: f32,
     pub integrator: Box<dyn IntegratorMC>,
+    /// Temperature ladder of the replica exchange (parallel tempering):
+    /// each chain is replaced by an ensemble of replicas targeting f^(1/T),
+    /// only the first one (T = 1) contributes to the image. vec![1.0] disables it.
+    pub temperatures: Vec<f32>,
+    /// Probability to propose a swap between two neighbor replicas instead of mutating them
+    pub swap_frequency: f32,
+    /// Target acceptance rate of the small steps with adaptive and per-dimension
+    /// step sizes (None: fixed step sizes)
+    pub adaptive: Option<f32>,
+    /// Try a small step when a large step is rejected (delayed rejection)
+    pub delayed_rejection: bool,
+    /// Continue the chains of a previous rendering
+    pub resume: Option<MLTCheckpoint>,
+    /// Where to write the checkpoint at the end of the rendering
+    pub checkpoint: Option<String>,
 }
+
+/// Sampler of a replica: the hotter replicas do larger small steps.
+/// The task is the index of the chain (all the replicas of all the ensembles)
+fn replica_sampler(
+    temperature: f32,
+    adaptive: Option<f32>,
+    seed: Option<u64>,
+    task: usize,
+) -> IndependentSamplerReplay {
+    let mutator = MutatorKelemen::default();
+    let s2 = (mutator.s2 * temperature).min(0.5);
+    let sampler = IndependentSamplerReplay::for_task(seed, task)
+        .mutator(Box::new(MutatorKelemen::new(mutator.s1, s2)));
+    match adaptive {
+        Some(target_acceptance) => sampler.adaptive(target_acceptance),
+        None => sampler,
+    }
+}
+
 impl Integrator for IntegratorPSSMLT {
-    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        assert!(!self.temperatures.is_empty() && self.temperatures[0] == 1.0);
+        let nb_replicas = self.temperatures.len();
+        let nb_samples_total =
+            scene.nb_samples * (scene.camera.size().x * scene.camera.size().y) as usize;
+        let resume = self.resume.take();
+        let seed = scene.pass_seed();
+
         ///////////// Define the closure
         let sample = |s: &mut dyn Sampler, emitters: &EmitterSampler| {
             let x = (s.next() * scene.camera.size().x as f32) as u32;
@@ -41,117 +179,255 @@ impl Integrator for IntegratorPSSMLT {
             MCMCState::new(c, Point2::new(x, y))
         };
 
-        ///////////// Compute the normalization factor
-        info!("Computing normalization factor...");
-        let b = self.compute_normalization(accel, scene, 10000);
-        info!("Normalisation factor: {:?}", b);
+        let (mut checkpoint, nb_samples_per_chains) = match resume {
+            Some(checkpoint) => {
+                assert_eq!(checkpoint.size, *scene.camera.size());
+                assert_eq!(checkpoint.temperatures, self.temperatures);
+                assert!(!checkpoint.chains.is_empty());
+                info!(
+                    "Resuming {} chains ({} mutations)",
+                    checkpoint.chains.len(),
+                    checkpoint.nb_mutations
+                );
+                let nb_samples_per_chains =
+                    nb_replicas * nb_samples_total / checkpoint.chains.len();
+                (checkpoint, nb_samples_per_chains)
+            }
+            None => {
+                ///////////// Compute the normalization factor
+                info!("Computing normalization factor...");
+                let nb_bootstrap = 10000;
+                let bootstrap_sum = self.compute_normalization(accel, scene, nb_bootstrap);
+                info!(
+                    "Normalisation factor: {:?}",
+                    bootstrap_sum / nb_bootstrap as f64
+                );
+                let checkpoint = MLTCheckpoint {
+                    size: *scene.camera.size(),
+                    bootstrap_sum,
+                    bootstrap_samples: nb_bootstrap,
+                    nb_mutations: 0,
+                    temperatures: self.temperatures.clone(),
+                    chains: vec![],
+                    image: vec![],
+                };
+                (checkpoint, 100_000)
+            }
+        };
 
         ///////////// Compute the state initialization
-        let nb_samples_total =
-            scene.nb_samples * (scene.camera.size().x * scene.camera.size().y) as usize;
-        let nb_samples_per_chains = 100_000;
-        let nb_chains = nb_samples_total / nb_samples_per_chains;
-        info!("Number of states: {:?}", nb_chains);
-        // - Initialize the samplers
-        let mut samplers = Vec::new();
-        for _ in 0..nb_chains {
-            samplers.push(samplers::mcmc::IndependentSamplerReplay::default());
+        // - Initialize the samplers (new chains or the ones of the checkpoint),
+        //   grouped by ensembles of replicas (one per temperature)
+        let mut ensembles = Vec::new();
+        if checkpoint.chains.is_empty() {
+            let nb_chains = nb_samples_total / nb_samples_per_chains;
+            info!("Number of states: {:?}", nb_chains);
+            for i in 0..nb_chains {
+                let samplers = self
+                    .temperatures
+                    .iter()
+                    .enumerate()
+                    .map(|(k, t)| replica_sampler(*t, self.adaptive, seed, i * nb_replicas + k))
+                    .collect::<Vec<_>>();
+                ensembles.push((samplers, vec![None; nb_replicas]));
+            }
+        } else {
+            assert_eq!(checkpoint.chains.len() % nb_replicas, 0);
+            let mut chains = checkpoint.chains.drain(..).enumerate();
+            while chains.len() != 0 {
+                let (samplers, states) = self
+                    .temperatures
+                    .iter()
+                    .map(|t| {
+                        let (task, chain) = chains.next().unwrap();
+                        (
+                            replica_sampler(*t, self.adaptive, seed, task).restore(chain.sampler),
+                            Some(MCMCState::new(chain.value, chain.pix)),
+                        )
+                    })
+                    .unzip();
+                ensembles.push((samplers, states));
+            }
         }
+        // The replicas share the mutations of the ensemble
+        let nb_steps = nb_samples_per_chains / nb_replicas;
 
         ///////////// Compute the rendering (with the number of samples)
         info!("Rendering...");
         let start = Instant::now();
-        let progress_bar = Mutex::new(ProgressBar::new(samplers.len() as u64));
-        let buffer_names = vec!["primal".to_string()];
-        let img = Mutex::new(BufferCollection::new(
-            Point2::new(0, 0),
-            *scene.camera.size(),
-            &buffer_names,
-        ));
-        let pool = generate_pool(scene);
-        pool.install(|| {
-            samplers.par_iter_mut().for_each(|s| {
-                let emitters = scene.emitters_sampler();
-                // Initialize the sampler
-                s.large_step = true;
-                let mut current_state = sample(s as &mut dyn Sampler, &emitters);
-                while current_state.tf == 0.0 {
-                    s.reject();
-                    current_state = sample(s as &mut dyn Sampler, &emitters);
+        observer.on_start(ensembles.len());
+        let mut splatted = splat_jobs(scene, &mut ensembles, |(samplers, states), splats| {
+            let emitters = scene.emitters_sampler_with(self.integrator.emitter_selection(scene));
+            let mut current = samplers
+                .iter_mut()
+                .zip(states.iter_mut())
+                .map(|(s, state)| match state.take() {
+                    Some(current_state) => current_state,
+                    None => {
+                        // Initialize the sampler
+                        s.large_step = true;
+                        let mut current_state = sample(s as &mut dyn Sampler, &emitters);
+                        while current_state.tf == 0.0 {
+                            s.reject();
+                            current_state = sample(s as &mut dyn Sampler, &emitters);
+                        }
+                        s.accept();
+                        current_state
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            (0..nb_steps).for_each(|_| {
+                if nb_replicas > 1 && samplers[0].rand() < self.swap_frequency {
+                    // Replica exchange between two neighbor temperatures
+                    let k = ((samplers[0].rand() * (nb_replicas - 1) as f32) as usize)
+                        .min(nb_replicas - 2);
+                    let beta = 1.0 / self.temperatures[k] - 1.0 / self.temperatures[k + 1];
+                    let accept_prob = (current[k + 1].tf / current[k].tf).powf(beta).min(1.0);
+                    if k == 0 {
+                        // Do waste reclycling (only the first replica is splatted)
+                        current[0].weight += 1.0 - accept_prob;
+                        splats.add(
+                            current[1].pix,
+                            current[1].value * (accept_prob / current[1].tf),
+                        );
+                    }
+                    if accept_prob > samplers[0].rand() {
+                        if k == 0 {
+                            splats.add(current[0].pix, current[0].color());
+                            current[0].weight = 0.0;
+                        }
+                        current.swap(k, k + 1);
+                        let (low, high) = samplers.split_at_mut(k + 1);
+                        low[k].swap_state(&mut high[0]);
+                    }
+                    return;
                 }
-                s.accept();
 
-                let mut my_img: BufferCollection =
-                    BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffer_names);
-                (0..nb_samples_per_chains).for_each(|_| {
+                for (k, (s, current_state)) in
+                    samplers.iter_mut().zip(current.iter_mut()).enumerate()
+                {
+                    // Target distribution: f^(1/T)
+                    let target = |state: &MCMCState| state.tf.powf(1.0 / self.temperatures[k]);
                     // Choose randomly between large and small perturbation
                     s.large_step = s.rand() < self.large_prob;
+                    let large_step = s.large_step;
                     let mut proposed_state = sample(s, &emitters);
-                    let accept_prob = (proposed_state.tf / current_state.tf).min(1.0);
-                    // Do waste reclycling
-                    current_state.weight += 1.0 - accept_prob;
-                    proposed_state.weight += accept_prob;
-                    if accept_prob > s.rand() {
-                        my_img.accumulate(
-                            current_state.pix,
-                            current_state.color(),
-                            &buffer_names[0],
-                        );
+                    let rejected_target = target(&proposed_state);
+                    let accept_prob = (rejected_target / target(current_state)).min(1.0);
+                    let accepted = accept_prob > s.rand();
+                    if k == 0 {
+                        // Do waste reclycling
+                        current_state.weight += 1.0 - accept_prob;
+                        proposed_state.weight += accept_prob;
+                        if accepted {
+                            splats.add(current_state.pix, current_state.color());
+                        } else {
+                            splats.add(proposed_state.pix, proposed_state.color());
+                        }
+                    }
+                    if accepted {
                         s.accept();
-                        current_state = proposed_state;
+                        *current_state = proposed_state;
                     } else {
-                        my_img.accumulate(
-                            proposed_state.pix,
-                            proposed_state.color(),
-                            &buffer_names[0],
-                        );
                         s.reject();
                     }
-                });
-                // Flush the last state
-                my_img.accumulate(current_state.pix, current_state.color(), &buffer_names[0]);
+                    s.adapt(accept_prob);
 
-                my_img.scale(1.0 / (nb_samples_per_chains as f32));
-                {
-                    img.lock().unwrap().accumulate_bitmap(&my_img);
-                    progress_bar.lock().unwrap().inc();
+                    if self.delayed_rejection && large_step && !accepted {
+                        // Delayed rejection (Tierney and Mira 1999): second try with a
+                        // small step from the current state. The large steps are
+                        // independent of the current state, so the acceptance probability
+                        // only depends on the three targets. The waste recycling of the
+                        // first try already accounts for this chain step.
+                        s.large_step = false;
+                        let second_state = sample(s, &emitters);
+                        let current_target = target(current_state);
+                        let accept_prob = if current_target > rejected_target {
+                            ((target(&second_state) - rejected_target).max(0.0)
+                                / (current_target - rejected_target))
+                                .min(1.0)
+                        } else {
+                            0.0
+                        };
+                        if accept_prob > s.rand() {
+                            if k == 0 {
+                                splats.add(current_state.pix, current_state.color());
+                            }
+                            s.accept();
+                            *current_state = second_state;
+                        } else {
+                            s.reject();
+                        }
+                        s.adapt(accept_prob);
+                    }
                 }
             });
+            // Flush the last state (the chain continues with a null weight)
+            splats.add(current[0].pix, current[0].color());
+            current[0].weight = 0.0;
+            *states = current.into_iter().map(Some).collect();
+            observer.on_tile_done();
         });
-
-        let mut img: BufferCollection = img.into_inner().unwrap();
         let elapsed = start.elapsed();
         info!("Elapsed: {:?}", elapsed,);
 
-        // ==== Compute and scale to the normalization factor
-        let img_avg = img.average_pixel(&buffer_names[0]);
-        let img_avg_lum = (img_avg.r + img_avg.g + img_avg.b) / 3.0;
-        img.scale(b / img_avg_lum);
+        // Continue the image of the checkpoint
+        for (c, previous) in splatted.colors.iter_mut().zip(&checkpoint.image) {
+            *c += *previous;
+        }
+        checkpoint.nb_mutations += ensembles.len() * nb_steps * nb_replicas;
+        checkpoint.image = splatted.colors;
+        if let Some(filename) = &self.checkpoint {
+            checkpoint.chains = ensembles
+                .iter()
+                .flat_map(|(samplers, states)| samplers.iter().zip(states.iter()))
+                .map(|(s, state)| {
+                    let state = state.as_ref().unwrap();
+                    ChainState {
+                        sampler: s.state(),
+                        value: state.value,
+                        pix: state.pix,
+                    }
+                })
+                .collect();
+            info!("Write MLT checkpoint: {}", filename);
+            if let Err(e) = checkpoint.save(filename) {
+                error!("Impossible to save {}: {}", filename, e);
+            }
+        }
 
+        // ==== Compute and scale to the normalization factor
+        let buffer_names = vec!["primal".to_string()];
+        let mut img = BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffer_names);
+        img.accumulate_local(&checkpoint.bitmap(), "primal");
         img
     }
 }
 impl IntegratorPSSMLT {
+    /// Sum of the luminance of nb_samples independent paths
     fn compute_normalization(
         &self,
         accel: &dyn Acceleration,
         scene: &Scene,
         nb_samples: usize,
-    ) -> f32 {
+    ) -> f64 {
         assert_ne!(nb_samples, 0);
 
-        let mut sampler = samplers::independent::IndependentSampler::default();
+        // The last task id is used for the bootstrap
+        let mut sampler =
+            samplers::independent::IndependentSampler::for_task(scene.pass_seed(), usize::MAX);
         (0..nb_samples)
             .map(|_i| {
-                let emitters = scene.emitters_sampler();
+                let emitters =
+                    scene.emitters_sampler_with(self.integrator.emitter_selection(scene));
                 let x = (sampler.next() * scene.camera.size().x as f32) as u32;
                 let y = (sampler.next() * scene.camera.size().y as f32) as u32;
                 let c =
                     self.integrator
                         .compute_pixel((x, y), accel, scene, &mut sampler, &emitters);
-                (c.r + c.g + c.b) / 3.0
+                f64::from((c.r + c.g + c.b) / 3.0)
             })
-            .sum::<f32>()
-            / (nb_samples as f32)
+            .sum::<f64>()
     }
 }
@@ -0,0 +1,200 @@
+use crate::error::{Error, Result};
+use crate::integrators::{builtin, IntegratorType};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Parameter values given to a factory (name -> value as text)
+pub type IntegratorParams = HashMap<String, String>;
+
+/// Description of a parameter accepted by an integrator factory
+/// (also the long name of its command line argument)
+pub struct IntegratorParam {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// None means that the parameter is mandatory
+    pub default: Option<&'static str>,
+    /// Short name of the command line argument
+    pub short: Option<&'static str>,
+    /// Boolean given without value on the command line ("true" or "false" otherwise)
+    pub flag: bool,
+}
+impl IntegratorParam {
+    pub fn new(name: &'static str, help: &'static str, default: Option<&'static str>) -> Self {
+        IntegratorParam {
+            name,
+            help,
+            default,
+            short: None,
+            flag: false,
+        }
+    }
+    /// Boolean parameter, false by default
+    pub fn flag(name: &'static str, help: &'static str) -> Self {
+        IntegratorParam {
+            flag: true,
+            ..IntegratorParam::new(name, help, Some("false"))
+        }
+    }
+    pub fn short(self, short: &'static str) -> Self {
+        IntegratorParam {
+            short: Some(short),
+            ..self
+        }
+    }
+}
+
+/// Build an integrator from its parameters.
+/// Implement this trait (and register it inside the IntegratorManager)
+/// to make a custom integrator selectable by name like the built-in ones.
+pub trait IntegratorFactory {
+    /// Short description used by the command line help
+    fn about(&self) -> &str;
+    fn params(&self) -> Vec<IntegratorParam>;
+    /// The params contain all the values listed by params()
+    /// (the default values are filled by the manager)
+    fn create(&self, params: &IntegratorParams) -> Result<IntegratorType>;
+}
+
+/// Names of the built-in integrators (command line subcommands),
+/// they cannot be used by the custom integrators
+pub fn builtin_integrators() -> Vec<&'static str> {
+    builtin::factories()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Integrators that can be created by name.
+/// All the built-in integrators are registered by default.
+pub struct IntegratorManager {
+    factories: HashMap<String, Rc<dyn IntegratorFactory>>,
+}
+impl Default for IntegratorManager {
+    fn default() -> Self {
+        IntegratorManager {
+            factories: builtin::factories()
+                .into_iter()
+                .map(|(name, factory)| (name.to_string(), factory))
+                .collect(),
+        }
+    }
+}
+impl IntegratorManager {
+    /// Fails if an integrator is already registered with this name
+    /// or if it is the name of a built-in integrator
+    pub fn register(&mut self, name: &str, factory: Rc<dyn IntegratorFactory>) -> Result<()> {
+        if builtin_integrators().contains(&name) {
+            return Err(Error::InvalidData(format!(
+                "The integrator {} is a built-in integrator",
+                name
+            )));
+        }
+        if self.factories.contains_key(name) {
+            return Err(Error::InvalidData(format!(
+                "The integrator {} is already registered",
+                name
+            )));
+        }
+        self.factories.insert(name.to_string(), factory);
+        Ok(())
+    }
+
+    /// Registered integrators names, the built-in ones included (sorted)
+    pub fn names(&self) -> Vec<&String> {
+        let mut names = self.factories.keys().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Registered integrators with their names (sorted by name)
+    pub fn factories(&self) -> Vec<(&String, &Rc<dyn IntegratorFactory>)> {
+        let mut factories = self.factories.iter().collect::<Vec<_>>();
+        factories.sort_by(|a, b| a.0.cmp(b.0));
+        factories
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rc<dyn IntegratorFactory>> {
+        self.factories.get(name)
+    }
+
+    pub fn create(&self, name: &str, params: &IntegratorParams) -> Result<IntegratorType> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| Error::InvalidData(format!("Unknown integrator {}", name)))?;
+        // Check the parameters and fill the default values
+        let mut all_params = IntegratorParams::new();
+        for p in factory.params() {
+            match (params.get(p.name), p.default) {
+                (Some(v), _) => all_params.insert(p.name.to_string(), v.clone()),
+                (None, Some(v)) => all_params.insert(p.name.to_string(), v.to_string()),
+                (None, None) => {
+                    return Err(Error::InvalidData(format!(
+                        "Missing parameter {} for the integrator {}",
+                        p.name, name
+                    )))
+                }
+            };
+        }
+        if let Some(k) = params.keys().find(|k| !all_params.contains_key(*k)) {
+            return Err(Error::InvalidData(format!(
+                "Unknown parameter {} for the integrator {}",
+                k, name
+            )));
+        }
+        factory.create(&all_params)
+    }
+
+    /// Same as create with the values of the parameters of the factory given by value_of
+    /// (e.g. the command line arguments, None for the missing ones)
+    pub fn create_from_args<'a>(
+        &self,
+        name: &str,
+        value_of: impl Fn(&str) -> Option<&'a str>,
+    ) -> Result<IntegratorType> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| Error::InvalidData(format!("Unknown integrator {}", name)))?;
+        let params = factory
+            .params()
+            .iter()
+            .filter_map(|p| value_of(p.name).map(|v| (p.name.to_string(), v.to_string())))
+            .collect();
+        self.create(name, &params)
+    }
+
+    /// Same as create but with the parameters given as a JSON object (can be null).
+    /// The values are numbers, booleans or texts (null for the default value),
+    /// the unknown parameters and the values of the wrong type are errors.
+    pub fn create_from_json(&self, name: &str, v: &serde_json::Value) -> Result<IntegratorType> {
+        let mut params = IntegratorParams::new();
+        match v {
+            serde_json::Value::Null => {}
+            serde_json::Value::Object(map) => {
+                for (k, v) in map {
+                    let v = match v {
+                        serde_json::Value::Null => continue,
+                        serde_json::Value::String(s) => s.clone(),
+                        serde_json::Value::Bool(b) => b.to_string(),
+                        serde_json::Value::Number(n) => n.to_string(),
+                        _ => {
+                            return Err(Error::InvalidData(format!(
+                                "The parameter {} of {} needs to be a number, a boolean or a text",
+                                k, name
+                            )))
+                        }
+                    };
+                    params.insert(k.clone(), v);
+                }
+            }
+            _ => {
+                return Err(Error::InvalidData(format!(
+                    "The parameters of {} need to be a JSON object",
+                    name
+                )))
+            }
+        }
+        self.create(name, &params)
+    }
+}
@@ -0,0 +1,151 @@
+//! Cache of the shadow ray results between cells of the scene, for the many-light gatherings
+//! (the VPLs): once the rays between two cells all agree (fully blocked or fully open),
+//! the next queries between these cells are answered without tracing.
+//! The cells partially occluded keep tracing all their rays. The answers are biased
+//! near the shadow boundaries, so the cache is emptied regularly to sample them again.
+use crate::scene::{Acceleration, SurfacePoint};
+use crate::structure::*;
+use cgmath::Point3;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Number of independent parts of the cache (less contention between the threads)
+const NB_SHARDS: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct VisibilityCacheConfig {
+    /// Size of the cells (world units) grouping the end points of the shadow rays
+    pub cell_size: f32,
+    /// Shadow rays with the same result needed before a pair of cells is trusted
+    pub min_samples: u32,
+    /// Number of queries after which the cache is emptied
+    pub period: usize,
+}
+impl VisibilityCacheConfig {
+    pub fn new(cell_size: f32) -> VisibilityCacheConfig {
+        VisibilityCacheConfig {
+            cell_size,
+            min_samples: 8,
+            period: 1 << 20,
+        }
+    }
+}
+
+type Cell = (i32, i32, i32);
+
+/// Shadow rays traced between two cells
+#[derive(Default, Clone, Copy)]
+struct CellStats {
+    open: u32,
+    blocked: u32,
+}
+
+/// Part of the cache filled during the period `epoch`
+#[derive(Default)]
+struct Shard {
+    epoch: usize,
+    cells: HashMap<(Cell, Cell), CellStats>,
+}
+
+/// Acceleration answering the shadow rays from the cache when possible
+/// (the other rays are traced by the wrapped acceleration)
+pub struct VisibilityCache<'a> {
+    accel: &'a dyn Acceleration,
+    config: VisibilityCacheConfig,
+    shards: Vec<Mutex<Shard>>,
+    queries: AtomicUsize,
+    hits: AtomicUsize,
+}
+
+impl<'a> VisibilityCache<'a> {
+    pub fn new(accel: &'a dyn Acceleration, config: VisibilityCacheConfig) -> VisibilityCache<'a> {
+        assert!(config.cell_size > 0.0 && config.period > 0);
+        VisibilityCache {
+            accel,
+            config,
+            shards: (0..NB_SHARDS)
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            queries: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    fn cell(&self, p: &Point3<f32>) -> Cell {
+        let c = |v: f32| (v / self.config.cell_size).floor() as i32;
+        (c(p.x), c(p.y), c(p.z))
+    }
+
+    /// Number of shadow rays asked to the cache
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::Relaxed)
+    }
+
+    /// Number of shadow rays answered without tracing
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+impl<'a> Acceleration for VisibilityCache<'a> {
+    fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.accel.trace(ray)
+    }
+
+    fn intersect_any(&self, ray: &Ray) -> bool {
+        self.accel.intersect_any(ray)
+    }
+
+    fn intersect_all_along_ray(&self, ray: &Ray) -> Vec<Intersection<'_>> {
+        self.accel.intersect_all_along_ray(ray)
+    }
+
+    fn closest_point_query(&self, p: &Point3<f32>, max_distance: f32) -> Option<SurfacePoint<'_>> {
+        self.accel.closest_point_query(p, max_distance)
+    }
+
+    fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool {
+        let epoch = self.queries.fetch_add(1, Ordering::Relaxed) / self.config.period;
+        // The visibility is symmetric: same entry for both directions
+        let (c0, c1) = (self.cell(p0), self.cell(p1));
+        let key = if c0 <= c1 { (c0, c1) } else { (c1, c0) };
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let shard = &self.shards[hasher.finish() as usize % NB_SHARDS];
+
+        let cached = {
+            let mut shard = shard.lock().unwrap();
+            if shard.epoch < epoch {
+                shard.epoch = epoch;
+                shard.cells.clear();
+            }
+            let stats = shard.cells.get(&key).copied().unwrap_or_default();
+            let min = self.config.min_samples;
+            match (stats.open, stats.blocked) {
+                (open, 0) if open >= min => Some(true),
+                (0, blocked) if blocked >= min => Some(false),
+                _ => None,
+            }
+        };
+        if let Some(visible) = cached {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return visible;
+        }
+
+        // Traced without holding the lock
+        let visible = self.accel.visible(p0, p1);
+        let mut shard = shard.lock().unwrap();
+        if shard.epoch == epoch {
+            let stats = shard.cells.entry(key).or_default();
+            if visible {
+                stats.open += 1;
+            } else {
+                stats.blocked += 1;
+            }
+        }
+        visible
+    }
+}
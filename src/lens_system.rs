@@ -0,0 +1,500 @@
+//! Camera lens made of spherical elements (Kolb et al. 1995, as the realistic camera of pbrt).
+//! The camera rays leave the film and are refracted by each lens interface, which gives
+//! the depth of field, the bokeh, the vignetting and the aberrations of the real lens.
+//! The lens files use the pbrt format (.dat): one interface per line, from the scene to
+//! the film, with its curvature radius, its thickness, its index of refraction (0 for air)
+//! and its aperture diameter in millimeters. A null curvature radius is the aperture stop.
+//! The lens is placed in the local space of the camera: the film at z = 0, the scene toward +z,
+//! and all the distances are converted to meters (scene units).
+use crate::error::{Error, Result};
+use cgmath::*;
+use rayon::prelude::*;
+
+/// Intervals along the film diagonal with their own bounds of the exit pupil
+const NB_PUPIL_BOUNDS: usize = 64;
+/// Rays traced from each interval to bound its exit pupil
+const PUPIL_SAMPLES: usize = 1 << 14;
+
+#[derive(Clone, Copy, Debug)]
+struct LensInterface {
+    /// Signed radius of the sphere (0 for the aperture stop)
+    curvature_radius: f32,
+    /// Distance to the next interface toward the film
+    thickness: f32,
+    /// Index of refraction after the interface toward the film (0 for the air)
+    eta: f32,
+    aperture_radius: f32,
+}
+
+/// Circular opening in front of the lens (barrel or hood) blocking the oblique rays:
+/// the bokeh of the off-axis points becomes cat-eye shaped (optical vignetting)
+#[derive(Clone, Copy, Debug)]
+struct OpticalVignetting {
+    /// Distance from the front element
+    distance: f32,
+    radius: f32,
+}
+
+impl LensInterface {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+}
+
+/// Axis aligned rectangle on the plane of the rear element
+#[derive(Clone, Copy, Debug)]
+struct Bounds2 {
+    min: Point2<f32>,
+    max: Point2<f32>,
+}
+
+impl Bounds2 {
+    fn square(half_size: f32) -> Bounds2 {
+        Bounds2 {
+            min: Point2::new(-half_size, -half_size),
+            max: Point2::new(half_size, half_size),
+        }
+    }
+    fn empty() -> Bounds2 {
+        Bounds2 {
+            min: Point2::new(f32::MAX, f32::MAX),
+            max: Point2::new(f32::MIN, f32::MIN),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+    }
+    fn contains(&self, p: Point2<f32>) -> bool {
+        (self.min.x..=self.max.x).contains(&p.x) && (self.min.y..=self.max.y).contains(&p.y)
+    }
+    fn union(&mut self, p: Point2<f32>) {
+        self.min = Point2::new(self.min.x.min(p.x), self.min.y.min(p.y));
+        self.max = Point2::new(self.max.x.max(p.x), self.max.y.max(p.y));
+    }
+    fn diagonal(&self) -> Vector2<f32> {
+        self.max - self.min
+    }
+    fn area(&self) -> f32 {
+        let d = self.diagonal();
+        d.x * d.y
+    }
+    fn lerp(&self, u: Point2<f32>) -> Point2<f32> {
+        self.min + self.diagonal().mul_element_wise(u.to_vec())
+    }
+}
+
+/// Refracted direction (both directions leave the interface, n is on the side of wi)
+fn refract(wi: Vector3<f32>, n: Vector3<f32>, eta: f32) -> Option<Vector3<f32>> {
+    let cos_theta_i = n.dot(wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi * eta + n * (eta * cos_theta_i - cos_theta_t))
+}
+
+/// Distance along the ray and normal (facing the ray origin) of the lens sphere
+fn intersect_sphere(
+    radius: f32,
+    z_center: f32,
+    o: Point3<f32>,
+    d: Vector3<f32>,
+) -> Option<(f32, Vector3<f32>)> {
+    let o = o - Vector3::new(0.0, 0.0, z_center);
+    let a = d.magnitude2();
+    let b = 2.0 * d.dot(o.to_vec());
+    let c = o.to_vec().magnitude2() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let q = if b < 0.0 {
+        -0.5 * (b - root)
+    } else {
+        -0.5 * (b + root)
+    };
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = (t0.min(t1), t0.max(t1));
+    // The ray hits the part of the sphere facing it
+    let t = if (d.z > 0.0) ^ (radius < 0.0) { t0 } else { t1 };
+    if t < 0.0 {
+        return None;
+    }
+    let n = (o.to_vec() + d * t).normalize();
+    Some((t, if n.dot(-d) < 0.0 { -n } else { n }))
+}
+
+/// Radical inverses in base 2 and 3 (points of the rear element)
+fn halton2(i: usize) -> Point2<f32> {
+    let radical_inverse = |base: usize, mut i: usize| {
+        let (mut v, mut f) = (0.0, 1.0 / base as f32);
+        while i > 0 {
+            v += (i % base) as f32 * f;
+            i /= base;
+            f /= base as f32;
+        }
+        v
+    };
+    Point2::new(radical_inverse(2, i), radical_inverse(3, i))
+}
+
+pub struct LensSystem {
+    /// From the scene to the film
+    interfaces: Vec<LensInterface>,
+    /// Diagonal of the film (meters)
+    pub film_diagonal: f32,
+    /// Bounds of the exit pupil seen from each interval along the film diagonal
+    exit_pupil_bounds: Vec<Bounds2>,
+    optical_vignetting: Option<OpticalVignetting>,
+}
+
+impl LensSystem {
+    /// Lens file (pbrt .dat), see new for the parameters
+    pub fn load(
+        filename: &str,
+        aperture_diameter: f32,
+        focus_distance: f32,
+        film_diagonal: f32,
+    ) -> Result<LensSystem> {
+        let data = std::fs::read_to_string(filename)?;
+        let values = data
+            .lines()
+            .map(|l| l.split('#').next().unwrap())
+            .flat_map(str::split_whitespace)
+            .map(|v| {
+                v.parse::<f32>()
+                    .map_err(|_| Error::InvalidData(format!("{}: invalid value {}", filename, v)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if values.is_empty() || values.len() % 4 != 0 {
+            return Err(Error::InvalidData(format!(
+                "{}: the lens interfaces need 4 values ({} values)",
+                filename,
+                values.len()
+            )));
+        }
+        let interfaces = values
+            .chunks(4)
+            .map(|v| [v[0], v[1], v[2], v[3]])
+            .collect::<Vec<_>>();
+        LensSystem::new(
+            &interfaces,
+            aperture_diameter,
+            focus_distance,
+            film_diagonal,
+        )
+    }
+
+    /// interfaces: [curvature radius, thickness, index of refraction, aperture diameter]
+    /// in millimeters from the scene to the film (same as the lens files).
+    /// The aperture diameter (mm) sets the diameter of the aperture stop (up to the one
+    /// of the lens), the film is moved so the plane at focus distance (meters) is sharp
+    /// and film diagonal (mm) is the size of the sensor (35 for a full frame).
+    pub fn new(
+        interfaces: &[[f32; 4]],
+        aperture_diameter: f32,
+        focus_distance: f32,
+        film_diagonal: f32,
+    ) -> Result<LensSystem> {
+        let mut interfaces = interfaces
+            .iter()
+            .map(|&[curvature_radius, thickness, eta, diameter]| {
+                let mut diameter = diameter;
+                if curvature_radius == 0.0 {
+                    if aperture_diameter > diameter {
+                        warn!(
+                            "Aperture diameter {} clamped to the maximum of the lens ({})",
+                            aperture_diameter, diameter
+                        );
+                    } else {
+                        diameter = aperture_diameter;
+                    }
+                }
+                LensInterface {
+                    curvature_radius: curvature_radius * 0.001,
+                    thickness: thickness * 0.001,
+                    eta,
+                    aperture_radius: diameter * 0.001 * 0.5,
+                }
+            })
+            .collect::<Vec<_>>();
+        if interfaces.is_empty() || interfaces.iter().any(|i| i.aperture_radius <= 0.0) {
+            return Err(Error::InvalidData(
+                "the lens interfaces need a positive aperture".to_string(),
+            ));
+        }
+        if !(focus_distance > 0.0 && film_diagonal > 0.0) {
+            return Err(Error::InvalidData(format!(
+                "invalid lens focus distance {} or film diagonal {}",
+                focus_distance, film_diagonal
+            )));
+        }
+        let mut lens = LensSystem {
+            interfaces: interfaces.clone(),
+            film_diagonal: film_diagonal * 0.001,
+            exit_pupil_bounds: vec![],
+            optical_vignetting: None,
+        };
+        // Distance between the rear element and the film
+        interfaces.last_mut().unwrap().thickness = lens.focus_thick_lens(focus_distance)?;
+        lens.interfaces = interfaces;
+
+        let half_diagonal = 0.5 * lens.film_diagonal;
+        lens.exit_pupil_bounds = (0..NB_PUPIL_BOUNDS)
+            .into_par_iter()
+            .map(|i| {
+                let r0 = i as f32 / NB_PUPIL_BOUNDS as f32 * half_diagonal;
+                let r1 = (i + 1) as f32 / NB_PUPIL_BOUNDS as f32 * half_diagonal;
+                lens.bound_exit_pupil(r0, r1)
+            })
+            .collect();
+        Ok(lens)
+    }
+
+    /// Opening of the given diameter (mm) at the distance (mm) in front of the lens.
+    /// It only removes rays: the bounds of the exit pupil stay valid.
+    pub fn set_optical_vignetting(&mut self, distance: f32, diameter: f32) -> Result<()> {
+        if !(distance >= 0.0 && diameter > 0.0) {
+            return Err(Error::InvalidData(format!(
+                "invalid optical vignetting distance {} or diameter {}",
+                distance, diameter
+            )));
+        }
+        self.optical_vignetting = Some(OpticalVignetting {
+            distance: distance * 0.001,
+            radius: diameter * 0.001 * 0.5,
+        });
+        Ok(())
+    }
+
+    fn lens_rear_z(&self) -> f32 {
+        self.interfaces.last().unwrap().thickness
+    }
+    fn lens_front_z(&self) -> f32 {
+        self.interfaces.iter().map(|i| i.thickness).sum()
+    }
+    fn rear_element_radius(&self) -> f32 {
+        self.interfaces.last().unwrap().aperture_radius
+    }
+
+    /// Ray leaving the front element of a ray leaving the film (camera space),
+    /// None if the lens blocks it
+    fn trace_from_film(
+        &self,
+        o: Point3<f32>,
+        d: Vector3<f32>,
+    ) -> Option<(Point3<f32>, Vector3<f32>)> {
+        // Lens space: the scene is toward -z
+        let flip = |v: Vector3<f32>| Vector3::new(v.x, v.y, -v.z);
+        let (mut o, mut d) = (Point3::from_vec(flip(o.to_vec())), flip(d));
+        let mut element_z = 0.0;
+        for (i, interface) in self.interfaces.iter().enumerate().rev() {
+            element_z -= interface.thickness;
+            let (t, n) = if interface.is_stop() {
+                if d.z >= 0.0 {
+                    return None;
+                }
+                ((element_z - o.z) / d.z, None)
+            } else {
+                let radius = interface.curvature_radius;
+                let (t, n) = intersect_sphere(radius, element_z + radius, o, d)?;
+                (t, Some(n))
+            };
+            o += d * t;
+            if o.x * o.x + o.y * o.y > interface.aperture_radius * interface.aperture_radius {
+                return None;
+            }
+            if let Some(n) = n {
+                let eta_i = interface.eta;
+                let eta_t = match i {
+                    0 => 1.0,
+                    _ if self.interfaces[i - 1].eta == 0.0 => 1.0,
+                    _ => self.interfaces[i - 1].eta,
+                };
+                let eta_i = if eta_i == 0.0 { 1.0 } else { eta_i };
+                d = refract(-d.normalize(), n, eta_i / eta_t)?;
+            }
+        }
+        Some((Point3::from_vec(flip(o.to_vec())), flip(d)))
+    }
+
+    /// Same as trace_from_film for the rays coming from the scene
+    fn trace_from_scene(
+        &self,
+        o: Point3<f32>,
+        d: Vector3<f32>,
+    ) -> Option<(Point3<f32>, Vector3<f32>)> {
+        let flip = |v: Vector3<f32>| Vector3::new(v.x, v.y, -v.z);
+        let (mut o, mut d) = (Point3::from_vec(flip(o.to_vec())), flip(d));
+        let mut element_z = -self.lens_front_z();
+        for (i, interface) in self.interfaces.iter().enumerate() {
+            let (t, n) = if interface.is_stop() {
+                ((element_z - o.z) / d.z, None)
+            } else {
+                let radius = interface.curvature_radius;
+                let (t, n) = intersect_sphere(radius, element_z + radius, o, d)?;
+                (t, Some(n))
+            };
+            o += d * t;
+            if o.x * o.x + o.y * o.y > interface.aperture_radius * interface.aperture_radius {
+                return None;
+            }
+            if let Some(n) = n {
+                let eta_i = match i {
+                    0 => 1.0,
+                    _ if self.interfaces[i - 1].eta == 0.0 => 1.0,
+                    _ => self.interfaces[i - 1].eta,
+                };
+                let eta_t = if interface.eta == 0.0 {
+                    1.0
+                } else {
+                    interface.eta
+                };
+                d = refract(-d.normalize(), n, eta_i / eta_t)?;
+            }
+            element_z += interface.thickness;
+        }
+        Some((Point3::from_vec(flip(o.to_vec())), flip(d)))
+    }
+
+    /// Principal plane and focal point (z) of a ray parallel to the axis through the lens
+    fn cardinal_points(
+        (o_in, _): (Point3<f32>, Vector3<f32>),
+        (o_out, d_out): (Point3<f32>, Vector3<f32>),
+    ) -> (f32, f32) {
+        let tf = -o_out.x / d_out.x;
+        let tp = (o_in.x - o_out.x) / d_out.x;
+        (-(o_out.z + d_out.z * tp), -(o_out.z + d_out.z * tf))
+    }
+
+    /// Principal planes and focal points (z) on the scene and film sides
+    fn thick_lens(&self) -> Result<([f32; 2], [f32; 2])> {
+        let x = 0.001 * self.film_diagonal;
+        let failed = || {
+            Error::InvalidData(
+                "impossible to trace a ray through the lens (aperture too small?)".to_string(),
+            )
+        };
+        let scene_ray = (
+            Point3::new(x, 0.0, self.lens_front_z() + 1.0),
+            -Vector3::unit_z(),
+        );
+        let film_ray = self
+            .trace_from_scene(scene_ray.0, scene_ray.1)
+            .ok_or_else(failed)?;
+        let (pz0, fz0) = LensSystem::cardinal_points(scene_ray, film_ray);
+        let film_ray = (
+            Point3::new(x, 0.0, self.lens_rear_z() - 1.0),
+            Vector3::unit_z(),
+        );
+        let scene_ray = self
+            .trace_from_film(film_ray.0, film_ray.1)
+            .ok_or_else(failed)?;
+        let (pz1, fz1) = LensSystem::cardinal_points(film_ray, scene_ray);
+        Ok(([pz0, pz1], [fz0, fz1]))
+    }
+
+    /// Effective focal length (meters)
+    pub fn focal_length(&self) -> Result<f32> {
+        let (pz, fz) = self.thick_lens()?;
+        Ok(fz[0] - pz[0])
+    }
+
+    /// Distance between the rear element and the film focusing at the given distance
+    fn focus_thick_lens(&self, focus_distance: f32) -> Result<f32> {
+        let (pz, fz) = self.thick_lens()?;
+        let f = fz[0] - pz[0];
+        let z = -focus_distance;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+        if c <= 0.0 {
+            return Err(Error::InvalidData(format!(
+                "the focus distance {} is too short for the lens",
+                focus_distance
+            )));
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        Ok(self.interfaces.last().unwrap().thickness + delta)
+    }
+
+    /// Bounds of the points of the rear element plane reached by the rays leaving the lens
+    /// from the film segment [r0, r1] along x
+    fn bound_exit_pupil(&self, r0: f32, r1: f32) -> Bounds2 {
+        let rear_bounds = Bounds2::square(1.5 * self.rear_element_radius());
+        let mut bounds = Bounds2::empty();
+        for i in 0..PUPIL_SAMPLES {
+            let x = r0 + (i as f32 + 0.5) / PUPIL_SAMPLES as f32 * (r1 - r0);
+            let p_film = Point3::new(x, 0.0, 0.0);
+            let p_rear = rear_bounds.lerp(halton2(i));
+            if bounds.contains(p_rear)
+                || self
+                    .trace_from_film(
+                        p_film,
+                        Point3::new(p_rear.x, p_rear.y, self.lens_rear_z()) - p_film,
+                    )
+                    .is_some()
+            {
+                bounds.union(p_rear);
+            }
+        }
+        if bounds.is_empty() {
+            return rear_bounds;
+        }
+        // Margin of the missed points between the samples
+        let margin = 2.0 * rear_bounds.diagonal().magnitude() / (PUPIL_SAMPLES as f32).sqrt();
+        bounds.min -= Vector2::new(margin, margin);
+        bounds.max += Vector2::new(margin, margin);
+        bounds
+    }
+
+    /// Physical size of the film (meters) for an image of this size
+    pub fn film_extent(&self, img: Vector2<u32>) -> Vector2<f32> {
+        let aspect = img.y as f32 / img.x as f32;
+        let x = (self.film_diagonal * self.film_diagonal / (1.0 + aspect * aspect)).sqrt();
+        Vector2::new(x, aspect * x)
+    }
+
+    /// Ray leaving the lens (camera space) from the film point (meters, centered on the axis)
+    /// through the point u of the bounds of the exit pupil, and its weight:
+    /// the cos^4 falloff times the area of the bounds relative to the center of the film.
+    /// None if the lens (or the optical vignetting) blocks the ray.
+    pub fn sample(
+        &self,
+        p_film: Point2<f32>,
+        u: Point2<f32>,
+    ) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        let r_film = p_film.to_vec().magnitude();
+        let index = (r_film / (0.5 * self.film_diagonal) * NB_PUPIL_BOUNDS as f32) as usize;
+        let bounds = &self.exit_pupil_bounds[index.min(NB_PUPIL_BOUNDS - 1)];
+        // The bounds are computed along x: rotate them toward the film point
+        let p_lens = bounds.lerp(u);
+        let (sin_theta, cos_theta) = if r_film != 0.0 {
+            (p_film.y / r_film, p_film.x / r_film)
+        } else {
+            (0.0, 1.0)
+        };
+        let p_rear = Point3::new(
+            cos_theta * p_lens.x - sin_theta * p_lens.y,
+            sin_theta * p_lens.x + cos_theta * p_lens.y,
+            self.lens_rear_z(),
+        );
+        let p_film = Point3::new(p_film.x, p_film.y, 0.0);
+        let d_film = p_rear - p_film;
+        let (o, d) = self.trace_from_film(p_film, d_film)?;
+        if let Some(vignetting) = &self.optical_vignetting {
+            if d.z <= 0.0 {
+                return None;
+            }
+            let t = ((self.lens_front_z() + vignetting.distance - o.z) / d.z).max(0.0);
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y > vignetting.radius * vignetting.radius {
+                return None;
+            }
+        }
+        let cos_theta = d_film.normalize().z;
+        let weight = cos_theta.powi(4) * bounds.area() / self.exit_pupil_bounds[0].area();
+        Some((o, d.normalize(), weight))
+    }
+}
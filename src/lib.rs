@@ -16,9 +16,15 @@ extern crate embree_rs;
 // For the image (LDR) export and loading
 #[cfg(feature = "image")]
 extern crate image;
-// For the image (HDR) export and loading
-#[cfg(feature = "exr")]
+// For the 16 bits png loading (image only decodes 8 bits)
+#[cfg(feature = "png")]
+extern crate png;
+// For the EXR loading
 extern crate exr;
+// For the compressed Ptex loading
+extern crate inflate;
+// For the Fourier Poisson reconstruction
+extern crate rustdct;
 // For logging propose
 #[macro_use]
 extern crate log;
@@ -26,11 +32,19 @@ extern crate log;
 extern crate rand;
 // For easy parallelism
 extern crate rayon;
+// For pinning the threads to the cores
+extern crate core_affinity;
+// For the command line interface
+#[macro_use]
+extern crate clap;
+extern crate env_logger;
 // For serialization support
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+// For the render configuration files
+extern crate toml;
 // For loading the obj files
 extern crate tobj;
 // For print a progress bar
@@ -38,10 +52,9 @@ extern crate pbr;
 // For loading other type of scene format
 #[cfg(feature = "pbrt")]
 extern crate pbrt_rs;
-
-mod constants {
-    pub const EPSILON: f32 = 0.0001;
-}
+// For the render server
+#[cfg(feature = "server")]
+extern crate tiny_http;
 
 pub trait Scale<T> {
     fn scale(&mut self, v: T);
@@ -49,16 +62,40 @@ pub trait Scale<T> {
 
 // all the modules
 pub mod accel;
+pub mod bake;
 pub mod bsdfs;
 pub mod camera;
+pub mod cli;
+pub mod color;
+pub mod config;
+pub mod dataset;
 pub mod emitter;
+pub mod env_guiding;
+pub mod error;
+pub mod exr_writer;
 pub mod geometry;
+pub mod image_ops;
 pub mod integrators;
+pub mod lens_system;
+pub mod light_bvh;
+pub mod light_grid;
 pub mod math;
+pub mod metadata;
+pub mod metrics;
 pub mod paths;
+pub mod report;
+pub mod response;
 pub mod samplers;
 pub mod scene;
+pub mod scene_check;
 pub mod scene_loader;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod structure;
+pub mod texture;
 pub mod tools;
+pub mod transform;
+#[cfg(feature = "validation")]
+pub mod validation;
 pub mod volume;
+pub mod watch;
@@ -0,0 +1,351 @@
+//! Light BVH used to select the emitters according to the shading point
+//! (Conty Estevez and Kulla, "Importance Sampling of Many Lights with Adaptive Tree Splitting", 2018).
+//! Each node bounds the power, the positions and the emission directions of its emitters:
+//! the traversal chooses a child proportionally to an upper bound of its contribution.
+use crate::structure::AABB;
+use cgmath::*;
+
+/// Bounding cone of the emission directions
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionCone {
+    pub axis: Vector3<f32>,
+    pub cos_theta: f32,
+}
+
+impl DirectionCone {
+    pub fn new(axis: Vector3<f32>) -> DirectionCone {
+        DirectionCone {
+            axis: axis.normalize(),
+            cos_theta: 1.0,
+        }
+    }
+    pub fn entire_sphere() -> DirectionCone {
+        DirectionCone {
+            axis: Vector3::unit_z(),
+            cos_theta: -1.0,
+        }
+    }
+
+    /// Smallest cone containing the two cones
+    pub fn union(&self, other: &DirectionCone) -> DirectionCone {
+        let pi = std::f32::consts::PI;
+        let theta_a = self.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_b = other.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_d = self.axis.dot(other.axis).clamp(-1.0, 1.0).acos();
+        if (theta_d + theta_b).min(pi) <= theta_a {
+            return *self;
+        }
+        if (theta_d + theta_a).min(pi) <= theta_b {
+            return *other;
+        }
+        let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+        if theta_o >= pi {
+            return DirectionCone::entire_sphere();
+        }
+        // Rotate the axis of self toward other
+        let w_r = self.axis.cross(other.axis);
+        if w_r.magnitude2() == 0.0 {
+            return DirectionCone::entire_sphere();
+        }
+        let rotation = Matrix3::from_axis_angle(w_r.normalize(), Rad(theta_o - theta_a));
+        DirectionCone {
+            axis: (rotation * self.axis).normalize(),
+            cos_theta: theta_o.cos(),
+        }
+    }
+}
+
+/// Spatial and directional bounds of one or several emitters
+#[derive(Clone, Copy, Debug)]
+pub struct LightBounds {
+    pub aabb: AABB,
+    /// Same measure as the power selection (maximum channel of the flux)
+    pub power: f32,
+    pub cone: DirectionCone,
+}
+
+/// cos(max(0, a - b)) from the sines and cosines of a and b
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+/// sin(max(0, a - b)) from the sines and cosines of a and b
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+impl LightBounds {
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        LightBounds {
+            aabb: self.aabb.union_aabb(&other.aabb),
+            power: self.power + other.power,
+            cone: self.cone.union(&other.cone),
+        }
+    }
+
+    /// Upper bound of the contribution received at p
+    /// (cosine emission on the side of the normals)
+    pub fn importance(&self, p: Point3<f32>) -> f32 {
+        if self.power <= 0.0 {
+            return 0.0;
+        }
+        let center = Point3::from_vec(self.aabb.center());
+        let radius2 = (self.aabb.size() * 0.5).magnitude2();
+        let dist2 = (p - center).magnitude2();
+        // The distance is clamped to avoid the singularity inside the bounds
+        let d2 = dist2.max(radius2);
+        if dist2 <= radius2 {
+            return self.power / d2;
+        }
+
+        let safe_sqrt = |v: f32| v.max(0.0).sqrt();
+        // Angle between the cone axis and the direction toward p
+        let cos_w = self.cone.axis.dot((p - center) / dist2.sqrt());
+        let sin_w = safe_sqrt(1.0 - cos_w * cos_w);
+        // Angle covered by the cone
+        let cos_o = self.cone.cos_theta;
+        let sin_o = safe_sqrt(1.0 - cos_o * cos_o);
+        // Angle subtended by the bounds seen from p
+        let cos_b = safe_sqrt(1.0 - radius2 / dist2);
+        let sin_b = safe_sqrt(1.0 - cos_b * cos_b);
+
+        let cos_x = cos_sub_clamped(sin_w, cos_w, sin_o, cos_o);
+        let sin_x = sin_sub_clamped(sin_w, cos_w, sin_o, cos_o);
+        let cos_p = cos_sub_clamped(sin_x, cos_x, sin_b, cos_b);
+        if cos_p <= 0.0 {
+            0.0
+        } else {
+            self.power * cos_p / d2
+        }
+    }
+}
+
+#[derive(Clone)]
+enum LightBVHNode {
+    Leaf(usize),
+    Interior(usize, usize),
+}
+
+#[derive(Clone)]
+pub struct LightBVH {
+    nodes: Vec<(LightBounds, LightBVHNode)>,
+    root: usize,
+    /// Path from the root to the leaf of each emitter (bit i: right child at depth i)
+    trails: Vec<(u64, u32)>,
+}
+
+impl LightBVH {
+    /// The bounds are given in the emitter order
+    pub fn new(bounds: &[LightBounds]) -> LightBVH {
+        assert!(!bounds.is_empty());
+        let mut bvh = LightBVH {
+            nodes: vec![],
+            root: 0,
+            trails: vec![(0, 0); bounds.len()],
+        };
+        let mut ids = (0..bounds.len()).collect::<Vec<_>>();
+        bvh.root = bvh.build(bounds, &mut ids, 0, 0);
+        bvh
+    }
+
+    fn build(
+        &mut self,
+        bounds: &[LightBounds],
+        ids: &mut [usize],
+        trail: u64,
+        depth: u32,
+    ) -> usize {
+        if ids.len() == 1 {
+            self.trails[ids[0]] = (trail, depth);
+            self.nodes
+                .push((bounds[ids[0]], LightBVHNode::Leaf(ids[0])));
+            return self.nodes.len() - 1;
+        }
+        assert!(depth < 64, "light BVH is too deep");
+
+        // Median split along the largest extent of the centers
+        let centers = ids.iter().fold(AABB::default(), |b, &i| {
+            b.union_vec(&bounds[i].aabb.center())
+        });
+        let size = centers.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        ids.sort_by(|&a, &b| {
+            bounds[a].aabb.center()[axis]
+                .partial_cmp(&bounds[b].aabb.center()[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let mid = ids.len() / 2;
+        let (left_ids, right_ids) = ids.split_at_mut(mid);
+        let left = self.build(bounds, left_ids, trail, depth + 1);
+        let right = self.build(bounds, right_ids, trail | (1 << depth), depth + 1);
+        let node_bounds = self.nodes[left].0.union(&self.nodes[right].0);
+        self.nodes
+            .push((node_bounds, LightBVHNode::Interior(left, right)));
+        self.nodes.len() - 1
+    }
+
+    /// Number of nodes (interior and leaves)
+    pub fn nb_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Replace the bounds of an emitter and update the bounds of its ancestors.
+    /// The tree is not rebuilt: its quality decreases if the emitter moves far.
+    pub fn refit(&mut self, id: usize, bounds: LightBounds) {
+        let (trail, depth_leaf) = self.trails[id];
+        let mut path = vec![self.root];
+        for d in 0..depth_leaf {
+            if let LightBVHNode::Interior(left, right) = self.nodes[path[d as usize]].1 {
+                path.push(if trail & (1 << d) == 0 { left } else { right });
+            }
+        }
+        let leaf = path.pop().unwrap();
+        self.nodes[leaf].0 = bounds;
+        for &node in path.iter().rev() {
+            if let LightBVHNode::Interior(left, right) = self.nodes[node].1 {
+                self.nodes[node].0 = self.nodes[left].0.union(&self.nodes[right].0);
+            }
+        }
+    }
+
+    /// Returns the selected emitter and its probability
+    /// (None if no emitter can contribute to p)
+    pub fn sample(&self, p: Point3<f32>, u: f32) -> Option<(usize, f32)> {
+        if self.nodes[self.root].0.importance(p) <= 0.0 {
+            return None;
+        }
+        self.sample_from(self.root, p, u)
+    }
+
+    /// Same as sample, restricted to the emitters below the node
+    pub fn sample_from(&self, node: usize, p: Point3<f32>, u: f32) -> Option<(usize, f32)> {
+        let (mut node, mut u, mut pmf) = (node, u, 1.0);
+        loop {
+            match self.nodes[node].1 {
+                LightBVHNode::Leaf(id) => return Some((id, pmf)),
+                LightBVHNode::Interior(left, right) => {
+                    let i_left = self.nodes[left].0.importance(p);
+                    let i_right = self.nodes[right].0.importance(p);
+                    if i_left + i_right <= 0.0 {
+                        return None;
+                    }
+                    let p_left = i_left / (i_left + i_right);
+                    // Reuse the random number for the next levels
+                    if u < p_left {
+                        u = (u / p_left).min(1.0 - f32::EPSILON);
+                        pmf *= p_left;
+                        node = left;
+                    } else {
+                        u = ((u - p_left) / (1.0 - p_left)).min(1.0 - f32::EPSILON);
+                        pmf *= 1.0 - p_left;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability to select the emitter from p
+    pub fn pmf(&self, p: Point3<f32>, id: usize) -> f32 {
+        if self.nodes[self.root].0.importance(p) <= 0.0 {
+            return 0.0;
+        }
+        self.pmf_from(self.root, 0, p, id)
+    }
+
+    /// Probability to select the emitter with sample_from (node at the given depth)
+    fn pmf_from(&self, node: usize, depth: u32, p: Point3<f32>, id: usize) -> f32 {
+        let (trail, depth_leaf) = self.trails[id];
+        let (mut node, mut pmf) = (node, 1.0);
+        for d in depth..depth_leaf {
+            if let LightBVHNode::Interior(left, right) = self.nodes[node].1 {
+                let i_left = self.nodes[left].0.importance(p);
+                let i_right = self.nodes[right].0.importance(p);
+                if i_left + i_right <= 0.0 {
+                    return 0.0;
+                }
+                if trail & (1 << d) == 0 {
+                    pmf *= i_left / (i_left + i_right);
+                    node = left;
+                } else {
+                    pmf *= i_right / (i_left + i_right);
+                    node = right;
+                }
+            }
+        }
+        pmf
+    }
+
+    /// Lightcut for p (Yuksel, "Stochastic Lightcuts", 2019): starting from the root,
+    /// the node with the largest importance is replaced by its children
+    /// until the cut has max_size nodes or only leaves.
+    /// The nodes that cannot contribute to p are removed.
+    pub fn cut(&self, p: Point3<f32>, max_size: usize) -> Vec<usize> {
+        let mut cut = vec![];
+        let importance = self.nodes[self.root].0.importance(p);
+        if importance > 0.0 {
+            cut.push((importance, self.root));
+        }
+        while cut.len() < max_size {
+            // Interior node with the largest importance
+            let is_interior = |node: usize| match self.nodes[node].1 {
+                LightBVHNode::Interior(..) => true,
+                LightBVHNode::Leaf(_) => false,
+            };
+            let split = cut
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, node))| is_interior(*node))
+                .max_by(|(_, a), (_, b)| {
+                    a.0.partial_cmp(&b.0)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|(i, _)| i);
+            let split = match split {
+                Some(i) => cut.swap_remove(i).1,
+                None => break,
+            };
+            if let LightBVHNode::Interior(left, right) = self.nodes[split].1 {
+                for &child in &[left, right] {
+                    let importance = self.nodes[child].0.importance(p);
+                    if importance > 0.0 {
+                        cut.push((importance, child));
+                    }
+                }
+            }
+        }
+        cut.into_iter().map(|(_, node)| node).collect()
+    }
+
+    /// Probability to select the emitter inside its node of the cut
+    /// (0 if the emitter is not below the cut)
+    pub fn cut_pmf(&self, cut: &[usize], p: Point3<f32>, id: usize) -> f32 {
+        let (trail, depth_leaf) = self.trails[id];
+        let mut node = self.root;
+        for d in 0..=depth_leaf {
+            if cut.contains(&node) {
+                return self.pmf_from(node, d, p, id);
+            }
+            match self.nodes[node].1 {
+                LightBVHNode::Interior(left, right) => {
+                    node = if trail & (1 << d) == 0 { left } else { right };
+                }
+                LightBVHNode::Leaf(_) => break,
+            }
+        }
+        0.0
+    }
+}
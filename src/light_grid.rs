@@ -0,0 +1,135 @@
+//! Light grid: approximate visibility between the emitters and the cells of a coarse grid
+//! over the scene, precomputed with a few shadow rays per pair. The emitters are selected
+//! proportionally to their power among the ones seen from the cell of the shading point,
+//! which skips the occluded lights of the interiors (e.g. the lights of the other rooms).
+//! The visibility is estimated: an emitter reached by none of the rays of a cell is culled
+//! for the whole cell (biased if it only lights a small part of the cell).
+use crate::emitter::Emitter;
+use crate::math::{Distribution1D, Distribution1DConstruct};
+use crate::samplers::independent::IndependentSampler;
+use crate::samplers::Sampler;
+use crate::scene::Acceleration;
+use crate::structure::*;
+use cgmath::*;
+use rayon::prelude::*;
+
+/// Cells along the largest side of the scene
+pub const GRID_RESOLUTION: usize = 16;
+/// Shadow rays between a cell and an emitter before culling the emitter
+pub const GRID_RAYS: usize = 16;
+
+/// Emitters seen from a cell (sorted ids) and their selection probabilities
+#[derive(Clone)]
+struct GridCell {
+    emitters: Vec<usize>,
+    distribution: Option<Distribution1D>,
+}
+
+#[derive(Clone)]
+pub struct LightGrid {
+    p_min: Point3<f32>,
+    cell_size: f32,
+    res: Vector3<usize>,
+    cells: Vec<GridCell>,
+}
+
+impl LightGrid {
+    /// Grid of cubic cells over the aabb (resolution cells along its largest side),
+    /// nb_rays shadow rays between random points of each cell and of each emitter
+    pub fn new(
+        accel: &dyn Acceleration,
+        emitters: &[&dyn Emitter],
+        aabb: &AABB,
+        resolution: usize,
+        nb_rays: usize,
+    ) -> LightGrid {
+        assert!(resolution > 0 && nb_rays > 0);
+        let size = aabb.size();
+        let side = size.x.max(size.y).max(size.z);
+        let cell_size = if side > 0.0 {
+            side / resolution as f32
+        } else {
+            1.0
+        };
+        let res = size.map(|v| ((v / cell_size).ceil() as usize).clamp(1, resolution));
+        let p_min = Point3::from_vec(aabb.p_min);
+
+        let cells = (0..res.x * res.y * res.z)
+            .into_par_iter()
+            .map(|id| {
+                let cell = Vector3::new(id % res.x, (id / res.x) % res.y, id / (res.x * res.y));
+                // Part of the cell inside the aabb (the last cells can overflow it)
+                let corner = p_min + cell.map(|v| v as f32) * cell_size;
+                let extent = (Point3::from_vec(aabb.p_max) - corner).map(|v| v.min(cell_size));
+                let mut sampler = IndependentSampler::for_task(Some(0), id);
+                let visible = (0..emitters.len())
+                    .filter(|&i| {
+                        (0..nb_rays).any(|_| {
+                            let u = Vector3::new(sampler.next(), sampler.next(), sampler.next());
+                            let p = corner + extent.mul_element_wise(u);
+                            let pos = emitters[i].sample_position(sampler.next(), sampler.next2d());
+                            let d = p - pos.p;
+                            // The emitters only light their front side
+                            pos.n.dot(d) > 0.0
+                                && accel
+                                    .visible(&offset_ray_origin(pos.p, pos.p_error, pos.n, d), &p)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let distribution = if visible.is_empty() {
+                    None
+                } else {
+                    let mut cdf = Distribution1DConstruct::new(visible.len());
+                    for &i in &visible {
+                        cdf.add(emitters[i].flux().channel_max());
+                    }
+                    Some(cdf.normalize())
+                };
+                GridCell {
+                    emitters: visible,
+                    distribution,
+                }
+            })
+            .collect();
+        LightGrid {
+            p_min,
+            cell_size,
+            res,
+            cells,
+        }
+    }
+
+    /// Cell containing p (the points outside of the grid use the closest cell)
+    fn cell(&self, p: Point3<f32>) -> &GridCell {
+        let index = |v: f32, res: usize| ((v / self.cell_size).max(0.0) as usize).min(res - 1);
+        let d = p - self.p_min;
+        let (x, y, z) = (
+            index(d.x, self.res.x),
+            index(d.y, self.res.y),
+            index(d.z, self.res.z),
+        );
+        &self.cells[(z * self.res.y + y) * self.res.x + x]
+    }
+
+    /// Ids of the emitters seen from the cell of p
+    pub fn visible_emitters(&self, p: Point3<f32>) -> &[usize] {
+        &self.cell(p).emitters
+    }
+
+    /// Select an emitter seen from p: its id and its probability (None if there is none)
+    pub fn sample(&self, p: Point3<f32>, v: f32) -> Option<(usize, f32)> {
+        let cell = self.cell(p);
+        let distribution = cell.distribution.as_ref()?;
+        let i = distribution.sample(v);
+        Some((cell.emitters[i], distribution.pdf(i)))
+    }
+
+    /// Probability to select the emitter id from p
+    pub fn pmf(&self, p: Point3<f32>, id: usize) -> f32 {
+        let cell = self.cell(p);
+        match (&cell.distribution, cell.emitters.binary_search(&id)) {
+            (Some(distribution), Ok(i)) => distribution.pdf(i),
+            _ => 0.0,
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::structure::Color;
 use cgmath::*;
 use std;
 
@@ -81,6 +82,7 @@ pub struct Distribution1DConstruct {
     pub elements: Vec<f32>,
 }
 
+#[derive(Clone)]
 pub struct Distribution1D {
     pub cdf: Vec<f32>,
     pub normalization: f32,
@@ -134,4 +136,332 @@ impl Distribution1D {
         assert!(i < self.cdf.len() - 1);
         self.cdf[i + 1] - self.cdf[i]
     }
+
+    /// Sample the distribution as a piecewise constant function over [0, 1].
+    /// Returns the sampled position and the index of its element.
+    pub fn sample_continuous(&self, v: f32) -> (f32, usize) {
+        assert!(v >= 0.0);
+        assert!(v < 1.0);
+
+        // Last element with cdf[i] <= v (skips the zero elements)
+        let n = self.cdf.len() - 1;
+        let i = (self.cdf.partition_point(|c| *c <= v) - 1).min(n - 1);
+        let w = self.cdf[i + 1] - self.cdf[i];
+        let offset = if w > 0.0 { (v - self.cdf[i]) / w } else { 0.0 };
+        ((i as f32 + offset.min(1.0)) / n as f32, i)
+    }
+
+    /// Density of sample_continuous() inside the element i
+    pub fn pdf_continuous(&self, i: usize) -> f32 {
+        self.pdf(i) * (self.cdf.len() - 1) as f32
+    }
+}
+
+/// Alias table (Vose's method) for O(1) sampling of a discrete distribution.
+/// Gives the same probabilities as the Distribution1D it is built from.
+#[derive(Clone)]
+pub struct AliasTable {
+    /// Probability to keep the element of the bin
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+    pdf: Vec<f32>,
+}
+
+impl AliasTable {
+    pub fn new(dist: &Distribution1D) -> AliasTable {
+        let n = dist.cdf.len() - 1;
+        let pdf = (0..n).map(|i| dist.pdf(i)).collect::<Vec<_>>();
+        let mut scaled = pdf.iter().map(|p| *p as f64 * n as f64).collect::<Vec<_>>();
+        let mut prob = vec![1.0; n];
+        let mut alias = (0..n).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|i| scaled[*i] < 1.0);
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+        // The remaining bins are full (up to rounding errors)
+        AliasTable { prob, alias, pdf }
+    }
+
+    pub fn sample(&self, v: f32) -> usize {
+        assert!(v >= 0.0);
+        assert!(v < 1.0);
+
+        // Reuse the fractional part to choose between the bin element and its alias.
+        // In double precision, v * n is exact: the fraction keeps all the remaining bits of v
+        // (in single precision, it is rounded to a few bits for the large tables).
+        let v = f64::from(v) * self.prob.len() as f64;
+        let i = (v as usize).min(self.prob.len() - 1);
+        if v - (i as f64) < self.prob[i] {
+            i
+        } else {
+            self.alias[i]
+        }
+    }
+
+    pub fn pdf(&self, i: usize) -> f32 {
+        self.pdf[i]
+    }
+}
+
+/// Piecewise constant 2D distribution over [0, 1]^2
+/// (marginal distribution over the rows and conditional distribution inside each row).
+/// Used for the importance sampling of images: environment maps
+/// (the values need to include the sin(theta) factor) or textured emitters.
+pub struct Distribution2D {
+    pub size: Vector2<usize>,
+    conditionals: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Values are stored in scanline order (size.x * size.y).
+    /// The rows without energy (or an image without energy) are sampled uniformly.
+    pub fn new(values: &[f32], size: Vector2<usize>) -> Distribution2D {
+        assert_eq!(values.len(), size.x * size.y);
+        assert!(size.x > 0 && size.y > 0);
+        let normalize = |row: &[f32]| {
+            let mut dist = Distribution1DConstruct::new(row.len());
+            let uniform = row.iter().sum::<f32>() <= 0.0;
+            for v in row {
+                assert!(*v >= 0.0 && v.is_finite());
+                dist.add(if uniform { 1.0 } else { *v });
+            }
+            dist.normalize()
+        };
+        let conditionals = values.chunks(size.x).map(normalize).collect::<Vec<_>>();
+        let marginal = normalize(
+            &values
+                .chunks(size.x)
+                .map(|r| r.iter().sum())
+                .collect::<Vec<_>>(),
+        );
+        Distribution2D {
+            size,
+            conditionals,
+            marginal,
+        }
+    }
+
+    /// Returns the sampled position in [0, 1]^2 and its pdf
+    pub fn sample(&self, u: Point2<f32>) -> (Point2<f32>, f32) {
+        let (y, iy) = self.marginal.sample_continuous(u.y);
+        let (x, ix) = self.conditionals[iy].sample_continuous(u.x);
+        let pdf = self.marginal.pdf_continuous(iy) * self.conditionals[iy].pdf_continuous(ix);
+        (Point2::new(x, y), pdf)
+    }
+
+    /// Density (w.r.t. the [0, 1]^2 area) of a position
+    pub fn pdf(&self, p: Point2<f32>) -> f32 {
+        let ix = ((p.x * self.size.x as f32) as usize).min(self.size.x - 1);
+        let iy = ((p.y * self.size.y as f32) as usize).min(self.size.y - 1);
+        self.marginal.pdf_continuous(iy) * self.conditionals[iy].pdf_continuous(ix)
+    }
+}
+
+/// Maximum number of spherical harmonics bands (l = 0..4)
+pub const SH_MAX_BANDS: usize = 5;
+
+/// Real spherical harmonics basis evaluated in the direction d (normalized).
+/// The coefficients are ordered by band: index = l * (l + 1) + m.
+/// Only the first bands * bands values of the returned array are computed.
+pub fn sh_basis(d: Vector3<f32>, bands: usize) -> [f32; SH_MAX_BANDS * SH_MAX_BANDS] {
+    assert!(bands > 0 && bands <= SH_MAX_BANDS);
+    let (x, y, z) = (d.x, d.y, d.z);
+    let mut sh = [0.0; SH_MAX_BANDS * SH_MAX_BANDS];
+    sh[0] = 0.282_094_8;
+    if bands > 1 {
+        sh[1] = 0.488_602_5 * y;
+        sh[2] = 0.488_602_5 * z;
+        sh[3] = 0.488_602_5 * x;
+    }
+    if bands > 2 {
+        sh[4] = 1.092_548_4 * x * y;
+        sh[5] = 1.092_548_4 * y * z;
+        sh[6] = 0.315_391_57 * (3.0 * z * z - 1.0);
+        sh[7] = 1.092_548_4 * x * z;
+        sh[8] = 0.546_274_2 * (x * x - y * y);
+    }
+    if bands > 3 {
+        sh[9] = 0.590_043_6 * y * (3.0 * x * x - y * y);
+        sh[10] = 2.890_611_4 * x * y * z;
+        sh[11] = 0.457_045_8 * y * (5.0 * z * z - 1.0);
+        sh[12] = 0.373_176_33 * z * (5.0 * z * z - 3.0);
+        sh[13] = 0.457_045_8 * x * (5.0 * z * z - 1.0);
+        sh[14] = 1.445_305_7 * z * (x * x - y * y);
+        sh[15] = 0.590_043_6 * x * (x * x - 3.0 * y * y);
+    }
+    if bands > 4 {
+        let (x2, y2, z2) = (x * x, y * y, z * z);
+        sh[16] = 2.503_343 * x * y * (x2 - y2);
+        sh[17] = 1.770_130_8 * y * z * (3.0 * x2 - y2);
+        sh[18] = 0.946_174_7 * x * y * (7.0 * z2 - 1.0);
+        sh[19] = 0.669_046_5 * y * z * (7.0 * z2 - 3.0);
+        sh[20] = 0.105_785_55 * (35.0 * z2 * z2 - 30.0 * z2 + 3.0);
+        sh[21] = 0.669_046_5 * x * z * (7.0 * z2 - 3.0);
+        sh[22] = 0.473_087_34 * (x2 - y2) * (7.0 * z2 - 1.0);
+        sh[23] = 1.770_130_8 * x * z * (x2 - 3.0 * y2);
+        sh[24] = 0.625_835_7 * (x2 * (x2 - 3.0 * y2) - y2 * (3.0 * x2 - y2));
+    }
+    sh
+}
+
+/// Projection of a spherical function (color) on the real spherical harmonics
+#[derive(Clone, Debug)]
+pub struct SphericalHarmonics {
+    pub bands: usize,
+    pub coeffs: Vec<Color>,
+}
+
+impl SphericalHarmonics {
+    pub fn new(bands: usize) -> SphericalHarmonics {
+        assert!(bands > 0 && bands <= SH_MAX_BANDS);
+        SphericalHarmonics {
+            bands,
+            coeffs: vec![Color::zero(); bands * bands],
+        }
+    }
+
+    /// Project f with a stratified grid of nb_strata * nb_strata uniform directions
+    pub fn project<F: Fn(Vector3<f32>) -> Color>(
+        bands: usize,
+        nb_strata: usize,
+        f: F,
+    ) -> SphericalHarmonics {
+        let mut sh = SphericalHarmonics::new(bands);
+        let weight = 4.0 * std::f32::consts::PI / (nb_strata * nb_strata) as f32;
+        for i in 0..nb_strata {
+            for j in 0..nb_strata {
+                let d = sample_uniform_sphere(Point2::new(
+                    (i as f32 + 0.5) / nb_strata as f32,
+                    (j as f32 + 0.5) / nb_strata as f32,
+                ));
+                sh.add_sample(d, f(d), weight);
+            }
+        }
+        sh
+    }
+
+    /// Accumulate a Monte Carlo sample: weight is 1 / (pdf * number of samples)
+    pub fn add_sample(&mut self, d: Vector3<f32>, value: Color, weight: f32) {
+        let basis = sh_basis(d, self.bands);
+        for (c, b) in self.coeffs.iter_mut().zip(basis.iter()) {
+            *c += value * (b * weight);
+        }
+    }
+
+    pub fn eval(&self, d: Vector3<f32>) -> Color {
+        let basis = sh_basis(d, self.bands);
+        self.coeffs
+            .iter()
+            .zip(basis.iter())
+            .fold(Color::zero(), |acc, (c, b)| acc + *c * *b)
+    }
+
+    /// Convolution with the clamped cosine lobe (Ramamoorthi and Hanrahan):
+    /// if self is the incoming radiance, eval gives the irradiance around a normal.
+    pub fn convolve_cosine(&self) -> SphericalHarmonics {
+        let pi = std::f32::consts::PI;
+        let a = [pi, 2.0 * pi / 3.0, pi / 4.0, 0.0, -pi / 24.0];
+        let mut sh = self.clone();
+        for (l, a_l) in a.iter().enumerate().take(self.bands) {
+            for c in &mut sh.coeffs[l * l..(l + 1) * (l + 1)] {
+                *c *= *a_l;
+            }
+        }
+        sh
+    }
+
+    /// Integral over the sphere of the product of the two functions
+    pub fn dot(&self, other: &SphericalHarmonics) -> Color {
+        self.coeffs
+            .iter()
+            .zip(other.coeffs.iter())
+            .fold(Color::zero(), |acc, (a, b)| acc + *a * *b)
+    }
+}
+
+/// Spherical gaussian: amplitude * exp(sharpness * (dot(axis, d) - 1))
+#[derive(Clone, Copy, Debug)]
+pub struct SphericalGaussian {
+    pub axis: Vector3<f32>,
+    pub sharpness: f32,
+    pub amplitude: Color,
+}
+
+impl SphericalGaussian {
+    /// Approximation of the clamped cosine lobe around n
+    pub fn cosine_lobe(n: Vector3<f32>) -> SphericalGaussian {
+        SphericalGaussian {
+            axis: n,
+            sharpness: 2.133,
+            amplitude: Color::value(1.17),
+        }
+    }
+
+    pub fn eval(&self, d: Vector3<f32>) -> Color {
+        self.amplitude * (self.sharpness * (self.axis.dot(d) - 1.0)).exp()
+    }
+
+    /// Integral over the sphere
+    pub fn integral(&self) -> Color {
+        self.amplitude * sg_unit_integral(self.sharpness)
+    }
+
+    /// The product of two spherical gaussians is a spherical gaussian
+    pub fn product(&self, other: &SphericalGaussian) -> SphericalGaussian {
+        let um = self.axis * self.sharpness + other.axis * other.sharpness;
+        let lm = um.magnitude();
+        SphericalGaussian {
+            axis: if lm > 0.0 { um / lm } else { self.axis },
+            sharpness: lm,
+            amplitude: self.amplitude
+                * other.amplitude
+                * (lm - self.sharpness - other.sharpness).exp(),
+        }
+    }
+
+    /// Integral over the sphere of the product of the two spherical gaussians
+    pub fn inner_product(&self, other: &SphericalGaussian) -> Color {
+        self.product(other).integral()
+    }
+
+    /// Fit a spherical gaussian on samples (direction, value) uniformly distributed
+    /// over the sphere. The axis and the sharpness are estimated from the mean direction
+    /// (von Mises-Fisher approximation of Banerjee et al.), the amplitude keeps the integral.
+    /// Returns None if the samples do not have energy.
+    pub fn fit(samples: &[(Vector3<f32>, Color)]) -> Option<SphericalGaussian> {
+        let mut sum_d = Vector3::zero();
+        let mut sum_w = 0.0;
+        let mut sum_value = Color::zero();
+        for (d, v) in samples {
+            let w = v.luminance();
+            sum_d += d * w;
+            sum_w += w;
+            sum_value += v;
+        }
+        if sum_w <= 0.0 || sum_d.magnitude2() == 0.0 {
+            return None;
+        }
+        let r = (sum_d.magnitude() / sum_w).min(0.9999);
+        let sharpness = (r * (3.0 - r * r) / (1.0 - r * r)).max(1e-3);
+        let integral = sum_value * (4.0 * std::f32::consts::PI / samples.len() as f32);
+        Some(SphericalGaussian {
+            axis: sum_d.normalize(),
+            sharpness,
+            amplitude: integral / sg_unit_integral(sharpness),
+        })
+    }
+}
+
+/// Integral over the sphere of a spherical gaussian with an unit amplitude
+fn sg_unit_integral(sharpness: f32) -> f32 {
+    2.0 * std::f32::consts::PI * -(-2.0 * sharpness).exp_m1() / sharpness
 }
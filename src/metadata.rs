@@ -0,0 +1,100 @@
+use crate::color::ColorSpace;
+use crate::error::Result;
+use crate::structure::Bitmap;
+use crate::tools::{extension, murmur3_32};
+use serde_json;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Render settings stored with the output images
+/// (EXR header attributes and sidecar JSON file)
+/// to be able to trace how a result was produced.
+#[derive(Serialize, Debug, Clone)]
+pub struct RenderMetadata {
+    pub integrator: String,
+    pub command_line: String,
+    pub nb_samples: usize,
+    /// Seed of the samplers (None: seeded from the OS entropy)
+    pub seed: Option<u64>,
+    pub scene: String,
+    /// MurmurHash3 of the scene file content
+    pub scene_hash: String,
+    /// Git commit at build time
+    pub commit: String,
+    /// Unix timestamp (seconds) of the end of the rendering
+    pub date: u64,
+    /// Rendering time (seconds)
+    pub render_time: f32,
+}
+
+impl RenderMetadata {
+    pub fn new(
+        integrator: &str,
+        scene: &str,
+        nb_samples: usize,
+        seed: Option<u64>,
+        render_time: Duration,
+    ) -> Self {
+        let scene_hash = match std::fs::read(scene) {
+            Ok(data) => format!("{:08x}", murmur3_32(&data, 0)),
+            Err(_) => "unknown".to_string(),
+        };
+        RenderMetadata {
+            integrator: integrator.to_string(),
+            command_line: std::env::args().collect::<Vec<_>>().join(" "),
+            nb_samples,
+            seed,
+            scene: scene.to_string(),
+            scene_hash,
+            commit: env!("RUSTLIGHT_GIT_COMMIT").to_string(),
+            date: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            render_time: render_time.as_secs_f32(),
+        }
+    }
+
+    /// Flatten the metadata as (rustlight/<field>, value)
+    pub fn attributes(&self) -> Vec<(String, String)> {
+        match serde_json::to_value(self).unwrap() {
+            serde_json::Value::Object(map) => map
+                .into_iter()
+                .map(|(k, v)| {
+                    let v = match v {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Null => "none".to_string(),
+                        v => v.to_string(),
+                    };
+                    (format!("rustlight/{}", k), v)
+                })
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write the metadata next to the image (<imgout_path_str>.json)
+    pub fn save_json(&self, imgout_path_str: &str) -> Result<()> {
+        let filename = format!("{}.json", imgout_path_str);
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(&filename, data)?;
+        Ok(())
+    }
+
+    /// Save the image with the metadata inside the EXR header
+    /// (other formats only get the sidecar JSON file).
+    /// color_space: encoding of the LDR images, png_16bits: 16 bits png instead of 8 bits
+    pub fn save(
+        &self,
+        img: &Bitmap,
+        imgout_path_str: &str,
+        color_space: ColorSpace,
+        png_16bits: bool,
+    ) -> Result<()> {
+        if extension(imgout_path_str)? == "exr" {
+            crate::exr_writer::save_bitmap(img, imgout_path_str, &self.attributes())?;
+        } else {
+            img.save_with(imgout_path_str, color_space, png_16bits)?;
+        }
+        self.save_json(imgout_path_str)
+    }
+}
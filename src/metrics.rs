@@ -0,0 +1,372 @@
+use crate::color::ColorSpace;
+use crate::error::Error;
+use crate::structure::{Bitmap, Color};
+use cgmath::Vector2;
+use rayon::prelude::*;
+
+/// Avoid division by zero for relative metrics
+const RELATIVE_EPSILON: f32 = 0.01;
+/// Pixels per degree of visual angle used by FLIP
+/// (0.7 meter from a 0.7 meter wide 4K monitor)
+pub const FLIP_PPD: f32 = 67.0;
+
+/// Error metrics used to compare an image against a reference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    MSE,
+    RelMSE,
+    MAPE,
+    FLIP,
+}
+
+impl Metric {
+    pub fn all() -> [Metric; 4] {
+        [Metric::MSE, Metric::RelMSE, Metric::MAPE, Metric::FLIP]
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::MSE => "mse",
+            Metric::RelMSE => "relmse",
+            Metric::MAPE => "mape",
+            Metric::FLIP => "flip",
+        }
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mse" => Ok(Metric::MSE),
+            "relmse" => Ok(Metric::RelMSE),
+            "mape" => Ok(Metric::MAPE),
+            "flip" => Ok(Metric::FLIP),
+            _ => Err(format!("unknown metric: {} (mse, relmse, mape, flip)", s)),
+        }
+    }
+}
+
+/// Per-pixel error between two images
+pub struct ErrorMap {
+    pub size: Vector2<u32>,
+    pub values: Vec<f32>,
+}
+
+impl ErrorMap {
+    /// The two images need to have the same size
+    pub fn new(
+        metric: Metric,
+        reference: &Bitmap,
+        test: &Bitmap,
+    ) -> crate::error::Result<ErrorMap> {
+        if reference.size != test.size {
+            return Err(Error::InvalidData(format!(
+                "the two images need to have the same size: {:?} and {:?}",
+                reference.size, test.size
+            )));
+        }
+        let per_channel = |f: &dyn Fn(f32, f32) -> f32| {
+            reference
+                .colors
+                .iter()
+                .zip(test.colors.iter())
+                .map(|(r, t)| (f(r.r, t.r) + f(r.g, t.g) + f(r.b, t.b)) / 3.0)
+                .collect::<Vec<_>>()
+        };
+        let values = match metric {
+            Metric::MSE => per_channel(&|r, t| (t - r).powi(2)),
+            Metric::RelMSE => per_channel(&|r, t| (t - r).powi(2) / (r * r + RELATIVE_EPSILON)),
+            Metric::MAPE => per_channel(&|r, t| (t - r).abs() / (r.abs() + RELATIVE_EPSILON)),
+            Metric::FLIP => flip(reference, test, FLIP_PPD),
+        };
+        Ok(ErrorMap {
+            size: reference.size,
+            values,
+        })
+    }
+
+    /// 0 for an empty map
+    pub fn mean(&self) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        self.values.iter().map(|v| f64::from(*v)).sum::<f64>() as f32 / self.values.len() as f32
+    }
+
+    /// p in [0, 1] (0 for an empty map)
+    pub fn percentile(&self, p: f32) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let mut values = self.values.clone();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let i = ((values.len() - 1) as f32 * p.clamp(0.0, 1.0)).round() as usize;
+        values[i]
+    }
+
+    /// Map the errors to a black-red-yellow-white color scale,
+    /// where max is the error mapped to white
+    pub fn false_color(&self, max: f32) -> Bitmap {
+        // sRGB stops, converted to linear as the bitmap is encoded on save
+        let stops = [
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+        ];
+        let colors = self
+            .values
+            .iter()
+            .map(|v| {
+                let t = if max > 0.0 { v / max } else { 0.0 };
+                let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+                let i = (t as usize).min(stops.len() - 2);
+                let c = stops[i] * (1.0 - (t - i as f32)) + stops[i + 1] * (t - i as f32);
+                ColorSpace::SRGB.decode_color(c)
+            })
+            .collect();
+        Bitmap {
+            size: self.size,
+            colors,
+            alpha: None,
+        }
+    }
+}
+
+/////////////// FLIP
+// Implementation of LDR-FLIP (Andersson et al. 2020) following the reference
+// python implementation. The inputs are linear RGB clamped to [0, 1].
+
+const FLIP_QC: f32 = 0.7;
+const FLIP_QF: f32 = 0.5;
+const FLIP_PC: f32 = 0.4;
+const FLIP_PT: f32 = 0.95;
+
+type Vec3 = [f32; 3];
+
+fn linrgb2xyz(c: Vec3) -> Vec3 {
+    [
+        (10135552.0 * c[0] + 8788810.0 * c[1] + 4435075.0 * c[2]) / 24577794.0,
+        (2613072.0 * c[0] + 8788810.0 * c[1] + 887015.0 * c[2]) / 12288897.0,
+        (1425312.0 * c[0] + 8788810.0 * c[1] + 70074185.0 * c[2]) / 73733382.0,
+    ]
+}
+fn xyz2linrgb(c: Vec3) -> Vec3 {
+    [
+        3.241003275 * c[0] - 1.537398934 * c[1] - 0.498615861 * c[2],
+        -0.969224334 * c[0] + 1.875930071 * c[1] + 0.041554224 * c[2],
+        0.055639423 * c[0] - 0.204011202 * c[1] + 1.057148933 * c[2],
+    ]
+}
+fn white_point() -> Vec3 {
+    linrgb2xyz([1.0, 1.0, 1.0])
+}
+fn xyz2ycxcz(c: Vec3) -> Vec3 {
+    let w = white_point();
+    let (x, y, z) = (c[0] / w[0], c[1] / w[1], c[2] / w[2]);
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+fn ycxcz2xyz(c: Vec3) -> Vec3 {
+    let w = white_point();
+    let y = (c[0] + 16.0) / 116.0;
+    [
+        (c[1] / 500.0 + y) * w[0],
+        y * w[1],
+        (y - c[2] / 200.0) * w[2],
+    ]
+}
+fn xyz2lab(c: Vec3) -> Vec3 {
+    let w = white_point();
+    let delta = 6.0 / 29.0;
+    let f = |t: f32| {
+        if t > delta * delta * delta {
+            t.cbrt()
+        } else {
+            t / (3.0 * delta * delta) + 4.0 / 29.0
+        }
+    };
+    let (x, y, z) = (f(c[0] / w[0]), f(c[1] / w[1]), f(c[2] / w[2]));
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+fn hunt_adjustment(c: Vec3) -> Vec3 {
+    [c[0], 0.01 * c[0] * c[1], 0.01 * c[0] * c[2]]
+}
+fn hyab(a: Vec3, b: Vec3) -> f32 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+fn clamp_rgb(c: Vec3) -> Vec3 {
+    [
+        c[0].clamp(0.0, 1.0),
+        c[1].clamp(0.0, 1.0),
+        c[2].clamp(0.0, 1.0),
+    ]
+}
+
+/// Square kernel of (2 * radius + 1)^2 weights
+struct Kernel {
+    radius: i32,
+    weights: Vec<f32>,
+}
+
+impl Kernel {
+    /// Contrast sensitivity function of the opponent channel (0: achromatic, 1: red-green, 2: blue-yellow)
+    fn csf(ppd: f32, channel: usize) -> Kernel {
+        let (a1, b1, a2, b2) = match channel {
+            0 => (1.0, 0.0047, 0.0, 1e-5),
+            1 => (1.0, 0.0053, 0.0, 1e-5),
+            _ => (34.1, 0.04, 13.5, 0.025),
+        };
+        // The radius is given by the widest of all the channels
+        let pi = std::f32::consts::PI;
+        let max_b: f32 = 0.04;
+        let radius = (3.0 * (max_b / (2.0 * pi * pi)).sqrt() * ppd).ceil() as i32;
+        Kernel::build(radius, |x, y| {
+            let z = (x * x + y * y) / (ppd * ppd);
+            a1 * (pi / b1).sqrt() * (-pi * pi * z / b1).exp()
+                + a2 * (pi / b2).sqrt() * (-pi * pi * z / b2).exp()
+        })
+        .normalize()
+    }
+
+    /// Gaussian derivatives detecting edges (first order) or points (second order)
+    fn feature(ppd: f32, edge: bool, transpose: bool) -> Kernel {
+        let sd = 0.5 * 0.082 * ppd;
+        let radius = (3.0 * sd).ceil() as i32;
+        let mut kernel = Kernel::build(radius, |x, y| {
+            let (x, y) = if transpose { (y, x) } else { (x, y) };
+            let g = (-(x * x + y * y) / (2.0 * sd * sd)).exp();
+            if edge {
+                -x * g
+            } else {
+                (x * x / (sd * sd) - 1.0) * g
+            }
+        });
+        let neg = -kernel.weights.iter().filter(|w| **w < 0.0).sum::<f32>();
+        let pos = kernel.weights.iter().filter(|w| **w > 0.0).sum::<f32>();
+        for w in &mut kernel.weights {
+            *w /= if *w < 0.0 { neg } else { pos };
+        }
+        kernel
+    }
+
+    fn build(radius: i32, f: impl Fn(f32, f32) -> f32) -> Kernel {
+        let mut weights = vec![];
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                weights.push(f(x as f32, y as f32));
+            }
+        }
+        Kernel { radius, weights }
+    }
+
+    fn normalize(mut self) -> Kernel {
+        let sum = self.weights.iter().sum::<f32>();
+        for w in &mut self.weights {
+            *w /= sum;
+        }
+        self
+    }
+
+    /// Convolution with mirrored boundaries
+    fn apply(&self, size: Vector2<u32>, values: &[f32]) -> Vec<f32> {
+        let (w, h) = (size.x as i32, size.y as i32);
+        let mirror = |i: i32, n: i32| {
+            let i = if i < 0 {
+                -i - 1
+            } else if i >= n {
+                2 * n - i - 1
+            } else {
+                i
+            };
+            i.min(n - 1).max(0)
+        };
+        let k = 2 * self.radius + 1;
+        (0..(w * h))
+            .into_par_iter()
+            .map(|i| {
+                let (px, py) = (i % w, i / w);
+                let mut v = 0.0;
+                for ky in 0..k {
+                    let y = mirror(py + ky - self.radius, h);
+                    for kx in 0..k {
+                        let x = mirror(px + kx - self.radius, w);
+                        v += self.weights[(ky * k + kx) as usize] * values[(y * w + x) as usize];
+                    }
+                }
+                v
+            })
+            .collect()
+    }
+}
+
+/// Per-pixel FLIP error in [0, 1]
+pub fn flip(reference: &Bitmap, test: &Bitmap, ppd: f32) -> Vec<f32> {
+    let size = reference.size;
+    let csf = (0..3).map(|c| Kernel::csf(ppd, c)).collect::<Vec<_>>();
+    let edges = [
+        Kernel::feature(ppd, true, false),
+        Kernel::feature(ppd, true, true),
+    ];
+    let points = [
+        Kernel::feature(ppd, false, false),
+        Kernel::feature(ppd, false, true),
+    ];
+    let magnitude = |kernels: &[Kernel; 2], values: &[f32]| {
+        let fx = kernels[0].apply(size, values);
+        let fy = kernels[1].apply(size, values);
+        fx.iter()
+            .zip(fy.iter())
+            .map(|(x, y)| (x * x + y * y).sqrt())
+            .collect::<Vec<_>>()
+    };
+
+    // Returns the Hunt adjusted colors and the edges/points features
+    let preprocess = |img: &Bitmap| {
+        let ycxcz = img
+            .colors
+            .iter()
+            .map(|c| xyz2ycxcz(linrgb2xyz(clamp_rgb([c.r, c.g, c.b]))))
+            .collect::<Vec<_>>();
+        let filtered = (0..3)
+            .map(|c| csf[c].apply(size, &ycxcz.iter().map(|v| v[c]).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let colors = (0..ycxcz.len())
+            .map(|i| {
+                let c = [filtered[0][i], filtered[1][i], filtered[2][i]];
+                let c = clamp_rgb(xyz2linrgb(ycxcz2xyz(c)));
+                hunt_adjustment(xyz2lab(linrgb2xyz(c)))
+            })
+            .collect::<Vec<_>>();
+        let y = ycxcz
+            .iter()
+            .map(|c| (c[0] + 16.0) / 116.0)
+            .collect::<Vec<_>>();
+        (colors, magnitude(&edges, &y), magnitude(&points, &y))
+    };
+    let (colors_ref, edges_ref, points_ref) = preprocess(reference);
+    let (colors_test, edges_test, points_test) = preprocess(test);
+
+    // Maximum color difference: between green and blue
+    let cmax = hyab(
+        hunt_adjustment(xyz2lab(linrgb2xyz([0.0, 1.0, 0.0]))),
+        hunt_adjustment(xyz2lab(linrgb2xyz([0.0, 0.0, 1.0]))),
+    )
+    .powf(FLIP_QC);
+    let pccmax = FLIP_PC * cmax;
+
+    (0..colors_ref.len())
+        .map(|i| {
+            let delta_c = hyab(colors_ref[i], colors_test[i]).powf(FLIP_QC);
+            let delta_c = if delta_c < pccmax {
+                (FLIP_PT / pccmax) * delta_c
+            } else {
+                FLIP_PT + ((delta_c - pccmax) / (cmax - pccmax)) * (1.0 - FLIP_PT)
+            };
+            let delta_f = (edges_ref[i] - edges_test[i])
+                .abs()
+                .max((points_ref[i] - points_test[i]).abs());
+            let delta_f = (delta_f / std::f32::consts::SQRT_2).powf(FLIP_QF);
+            delta_c.powf(1.0 - delta_f)
+        })
+        .collect()
+}
@@ -0,0 +1,209 @@
+//! Path debugger: the vertices and edges of the paths traced for one pixel,
+//! saved as JSON (all the sampling informations) or as OBJ polylines
+//! (e.g., to visualize the light transport inside Blender).
+use crate::error::{Error, Result};
+use crate::paths::vertex::*;
+use crate::scene::Scene;
+use crate::structure::*;
+use cgmath::Point2;
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VertexRecord {
+    /// "sensor", "surface", "light" or "volume"
+    pub kind: &'static str,
+    pub position: [f32; 3],
+    /// Name of the mesh of the surface vertices
+    pub mesh: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EdgeRecord {
+    pub from: usize,
+    /// None if the edge escaped the scene (or the light sampling failed)
+    pub to: Option<usize>,
+    /// End of the polyline: the next vertex or a point outside of the scene
+    pub end: [f32; 3],
+    pub direction: [f32; 3],
+    /// Sampling strategy that generated the edge (0: BSDF or phase function, 1: emitter)
+    pub strategy: usize,
+    /// "solid angle", "area" or "discrete"
+    pub pdf_measure: &'static str,
+    pub pdf: f32,
+    /// BSDF (times transmittance) divided by the pdf
+    pub weight: Color,
+    pub rr_weight: f32,
+    /// Emitted radiance arriving along the edge (times its weights)
+    pub contribution: Color,
+    /// MIS weight of the contribution (zero without contribution)
+    pub mis_weight: f32,
+}
+
+/// Vertices and edges of a path (or of a tree of subpaths with the splitting)
+#[derive(Serialize, Clone, Debug)]
+pub struct PathRecord {
+    /// Integrator and role of the path (e.g., base or offset path)
+    pub label: String,
+    pub pixel: (u32, u32),
+    pub vertices: Vec<VertexRecord>,
+    pub edges: Vec<EdgeRecord>,
+}
+
+fn array(v: impl Into<[f32; 3]>) -> [f32; 3] {
+    v.into()
+}
+
+fn edges_out(vertex: &Vertex) -> Vec<EdgeID> {
+    match vertex {
+        Vertex::Surface(ref v) => v.edge_out.clone(),
+        Vertex::Volume(ref v) => v.edge_out.clone(),
+        Vertex::Sensor(ref v) => v.edge_out.into_iter().collect(),
+        Vertex::Light(ref v) => v.edge_out.into_iter().collect(),
+    }
+}
+
+impl PathRecord {
+    /// Record the vertices reachable from the root. The contribution gives the emitted
+    /// radiance along an edge (from the vertex) and its MIS weight for the integrator.
+    pub fn new(
+        label: &str,
+        pixel: (u32, u32),
+        path: &Path,
+        root: VertexID,
+        scene: &Scene,
+        contribution: impl Fn(VertexID, EdgeID) -> (Color, f32),
+    ) -> PathRecord {
+        let mut record = PathRecord {
+            label: label.to_string(),
+            pixel,
+            vertices: vec![],
+            edges: vec![],
+        };
+        let world = scene.bounding_sphere();
+        let mut stack = vec![(root, record.add_vertex(path.vertex(root)))];
+        while let Some((vertex_id, from)) = stack.pop() {
+            let vertex = path.vertex(vertex_id);
+            for edge_id in edges_out(vertex) {
+                let edge = path.edge(edge_id);
+                let (to, end) = match edge.vertices.1 {
+                    Some(next) => {
+                        let to = record.add_vertex(path.vertex(next));
+                        stack.push((next, to));
+                        (Some(to), path.vertex(next).position())
+                    }
+                    None => (None, world.outside_point(&vertex.position(), edge.d)),
+                };
+                let (pdf_measure, pdf) = match edge.pdf_direction {
+                    PDF::SolidAngle(v) => ("solid angle", v),
+                    PDF::Area(v) => ("area", v),
+                    PDF::Discrete(v) => ("discrete", v),
+                };
+                let (contribution, mis_weight) = match contribution(vertex_id, edge_id) {
+                    (c, _) if c.is_zero() => (c, 0.0),
+                    v => v,
+                };
+                record.edges.push(EdgeRecord {
+                    from,
+                    to,
+                    end: array(end),
+                    direction: array(edge.d),
+                    strategy: edge.id_sampling,
+                    pdf_measure,
+                    pdf,
+                    weight: edge.weight,
+                    rr_weight: edge.rr_weight,
+                    contribution,
+                    mis_weight,
+                });
+            }
+        }
+        record
+    }
+
+    fn add_vertex(&mut self, vertex: &Vertex) -> usize {
+        let (kind, mesh) = match vertex {
+            Vertex::Sensor(_) => ("sensor", None),
+            Vertex::Surface(ref v) => ("surface", Some(v.its.mesh.name.clone())),
+            Vertex::Light(_) => ("light", None),
+            Vertex::Volume(_) => ("volume", None),
+        };
+        self.vertices.push(VertexRecord {
+            kind,
+            position: array(vertex.position()),
+            mesh,
+        });
+        self.vertices.len() - 1
+    }
+}
+
+/// Records the paths traced for one pixel (disabled if pixel is None)
+#[derive(Default)]
+pub struct PathDebugger {
+    pub pixel: Option<Point2<u32>>,
+    paths: Mutex<Vec<PathRecord>>,
+}
+impl PathDebugger {
+    /// The paths of this pixel need to be recorded
+    pub fn records(&self, (x, y): (u32, u32)) -> bool {
+        self.pixel == Some(Point2::new(x, y))
+    }
+
+    pub fn record(&self, path: PathRecord) {
+        self.paths.lock().unwrap().push(path);
+    }
+
+    /// Recorded paths, in the order of the samples
+    pub fn paths(&self) -> Vec<PathRecord> {
+        self.paths.lock().unwrap().clone()
+    }
+
+    /// Save the recorded paths: JSON or OBJ (one object per path and sampling strategy)
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let ext = std::path::Path::new(filename)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or("");
+        if ext != "json" && ext != "obj" {
+            return Err(Error::UnsupportedFormat(ext.to_string()));
+        }
+        let mut file = BufWriter::new(std::fs::File::create(filename)?);
+        match ext {
+            "json" => serde_json::to_writer_pretty(file, &self.paths())?,
+            "obj" => {
+                let mut nb_points = 0;
+                for (i, path) in self.paths().iter().enumerate() {
+                    let mut groups: HashMap<usize, Vec<&EdgeRecord>> = HashMap::new();
+                    for e in &path.edges {
+                        groups.entry(e.strategy).or_default().push(e);
+                    }
+                    let mut strategies = groups.keys().cloned().collect::<Vec<_>>();
+                    strategies.sort_unstable();
+                    for strategy in strategies {
+                        let name = match strategy {
+                            0 => "bsdf",
+                            _ => "emitter",
+                        };
+                        writeln!(
+                            file,
+                            "o path{}_{}_{}",
+                            i,
+                            path.label.replace(' ', "_"),
+                            name
+                        )?;
+                        for e in &groups[&strategy] {
+                            let p = path.vertices[e.from].position;
+                            writeln!(file, "v {} {} {}", p[0], p[1], p[2])?;
+                            writeln!(file, "v {} {} {}", e.end[0], e.end[1], e.end[2])?;
+                            writeln!(file, "l {} {}", nb_points + 1, nb_points + 2)?;
+                            nb_points += 2;
+                        }
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
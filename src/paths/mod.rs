@@ -1,2 +1,3 @@
+pub mod debugger;
 pub mod path;
 pub mod vertex;
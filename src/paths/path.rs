@@ -1,3 +1,4 @@
+use crate::bsdfs::Lobe;
 use crate::cgmath::InnerSpace;
 use crate::emitter::*;
 use crate::math::*;
@@ -7,10 +8,13 @@ use crate::scene::*;
 use crate::structure::*;
 use crate::volume::*;
 use crate::Scale;
+use cgmath::{EuclideanSpace, Point3, Vector3};
 use std;
 use std::mem;
+use std::sync::Arc;
 
 pub trait SamplingStrategy {
+    /// Returns the new vertices to continue the path from (several ones with splitting)
     fn sample<'scene, 'emitter>(
         &self,
         path: &mut Path<'scene, 'emitter>,
@@ -22,7 +26,7 @@ pub trait SamplingStrategy {
         sampler: &mut dyn Sampler,
         medium: Option<&HomogenousVolume>,
         id_strategy: usize,
-    ) -> Option<(VertexID, Color)>;
+    ) -> Vec<(VertexID, Color)>;
 
     // All PDF have to be inside the same domain
     fn pdf<'scene, 'emitter>(
@@ -35,10 +39,202 @@ pub trait SamplingStrategy {
     ) -> Option<f32>;
 }
 
+/// Russian roulette and splitting of the paths at the surface and volume vertices.
+/// The survival probability is the maximum channel of the throughput times the scale
+/// and the cached incident radiance at the vertex (bounded by 0.95),
+/// the path is split when this value is above 2. With the cached radiance, the survival
+/// probability is at least MIN_SURVIVAL: a cell recording no radiance would kill
+/// all its paths (biased).
+#[derive(Clone, Debug)]
+pub struct RussianRoulette {
+    /// Depth of the first vertex using the roulette and the splitting
+    pub start_depth: u32,
+    /// Expected contribution of the path relative to its throughput (1: throughput based)
+    pub scale: f32,
+    /// Maximum number of subpaths generated at a vertex (1: no splitting)
+    pub max_splitting: u32,
+    /// Incident radiance at the vertices (None: the throughput alone)
+    pub radiance: Option<Arc<RadianceCache>>,
+}
+impl Default for RussianRoulette {
+    fn default() -> Self {
+        RussianRoulette {
+            start_depth: 0,
+            scale: 1.0,
+            max_splitting: 1,
+            radiance: None,
+        }
+    }
+}
+impl RussianRoulette {
+    pub const MIN_SURVIVAL: f32 = 0.05;
+    /// Expected contribution of the path continuing from the point p
+    fn expected(&self, throughput: &Color, p: &Point3<f32>) -> f32 {
+        let radiance = self.radiance.as_ref().map_or(1.0, |cache| cache.get(p));
+        throughput.channel_max() * self.scale * radiance
+    }
+    /// Survival probability after the bounce at the vertex p of the given depth
+    pub fn survival(&self, throughput: &Color, depth: u32, p: &Point3<f32>) -> f32 {
+        if depth < self.start_depth {
+            1.0
+        } else {
+            // A path without throughput does not contribute
+            let min = if self.radiance.is_some() && !throughput.is_zero() {
+                Self::MIN_SURVIVAL
+            } else {
+                0.0
+            };
+            self.expected(throughput, p).max(min).min(0.95)
+        }
+    }
+    /// Number of subpaths continuing the path at the vertex p of the given depth
+    pub fn splitting(&self, throughput: &Color, depth: u32, p: &Point3<f32>) -> u32 {
+        if depth < self.start_depth || self.max_splitting <= 1 {
+            return 1;
+        }
+        let n = self.expected(throughput, p).floor();
+        if n.is_finite() && n >= 1.0 {
+            (n as u32).min(self.max_splitting)
+        } else {
+            1
+        }
+    }
+}
+
+/// Coarse estimate of the radiance arriving at the points of the scene (luminance),
+/// averaged over the cells of a uniform grid. The empty cells use the average of all the
+/// records. Used by the adjoint-driven russian roulette instead of the path guiding
+/// structures (Vorba and Křivánek 2016).
+#[derive(Debug)]
+pub struct RadianceCache {
+    aabb: AABB,
+    cell_size: f32,
+    resolution: Vector3<usize>,
+    /// Average radiance of the cells (None: no record)
+    cells: Vec<Option<f32>>,
+    average: f32,
+}
+impl RadianceCache {
+    /// Cache of the incident radiance records (position, luminance), with cubic cells
+    /// and the given number of cells along the largest extent of the records
+    pub fn new(records: &[(Point3<f32>, f32)], max_resolution: usize) -> RadianceCache {
+        let aabb = records
+            .iter()
+            .fold(AABB::default(), |aabb, (p, _)| aabb.union_vec(&p.to_vec()));
+        let size = aabb.size();
+        let extent = size.x.max(size.y).max(size.z);
+        let cell_size = if records.is_empty() || extent <= 0.0 {
+            1.0
+        } else {
+            extent / max_resolution as f32
+        };
+        let nb_cells = |s: f32| ((s / cell_size).ceil() as usize).clamp(1, max_resolution);
+        let resolution = Vector3::new(nb_cells(size.x), nb_cells(size.y), nb_cells(size.z));
+        let mut cache = RadianceCache {
+            aabb,
+            cell_size,
+            resolution,
+            cells: vec![],
+            average: 0.0,
+        };
+
+        let mut sums = vec![(0.0, 0); resolution.x * resolution.y * resolution.z];
+        for (p, radiance) in records {
+            let sum = &mut sums[cache.index(p)];
+            sum.0 += f64::from(*radiance);
+            sum.1 += 1;
+        }
+        cache.cells = sums
+            .iter()
+            .map(|&(sum, n)| {
+                if n > 0 {
+                    Some((sum / n as f64) as f32)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if !records.is_empty() {
+            let sum = records.iter().map(|(_, r)| f64::from(*r)).sum::<f64>();
+            cache.average = (sum / records.len() as f64) as f32;
+        }
+        cache
+    }
+
+    fn index(&self, p: &Point3<f32>) -> usize {
+        let cell = |v: f32, min: f32, res: usize| {
+            // Also clamps the points outside of the records (and NaN) to the border cells
+            (((v - min) / self.cell_size) as usize).min(res - 1)
+        };
+        let x = cell(p.x, self.aabb.p_min.x, self.resolution.x);
+        let y = cell(p.y, self.aabb.p_min.y, self.resolution.y);
+        let z = cell(p.z, self.aabb.p_min.z, self.resolution.z);
+        (z * self.resolution.y + y) * self.resolution.x + x
+    }
+
+    /// Average radiance arriving around the point p
+    pub fn get(&self, p: &Point3<f32>) -> f32 {
+        if self.cells.is_empty() {
+            return self.average;
+        }
+        self.cells[self.index(p)].unwrap_or(self.average)
+    }
+
+    /// Average radiance of all the records
+    pub fn average(&self) -> f32 {
+        self.average
+    }
+}
+
+/// Maximum number of bounces of each lobe type (None: no limit), counted like Cycles:
+/// with 0 diffuse bounces, the diffuse surfaces only receive the direct lighting.
+/// The volume vertices only count toward the maximum depth.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DepthLimits {
+    pub diffuse: Option<u32>,
+    pub glossy: Option<u32>,
+    pub specular: Option<u32>,
+}
+impl DepthLimits {
+    fn limit(&self, lobe: Lobe) -> Option<u32> {
+        match lobe {
+            Lobe::Diffuse => self.diffuse,
+            Lobe::Glossy => self.glossy,
+            Lobe::Specular => self.specular,
+        }
+    }
+
+    /// Check if the vertex can scatter the light (all the strategies):
+    /// the number of previous bounces of its lobe type is below the limit
+    pub fn allows(&self, path: &Path, vertex_id: VertexID) -> bool {
+        let lobe = match path.vertex(vertex_id) {
+            Vertex::Surface(ref v) => v.its.mesh.bsdf.lobe(&v.its.tex_coords()),
+            _ => return true,
+        };
+        let max = match self.limit(lobe) {
+            Some(max) => max,
+            None => return true,
+        };
+        let mut bounces = 0;
+        let mut current = vertex_id;
+        while let Some(previous) = path.previous(current) {
+            if let Vertex::Surface(ref v) = path.vertex(previous) {
+                if v.its.mesh.bsdf.lobe(&v.its.tex_coords()) == lobe {
+                    bounces += 1;
+                }
+            }
+            current = previous;
+        }
+        bounces <= max
+    }
+}
+
 pub struct DirectionalSamplingStrategy {
     pub from_sensor: bool,
+    pub rr: RussianRoulette,
 }
 impl DirectionalSamplingStrategy {
+    /// split_weight: one over the number of subpaths generated at the vertex
     pub fn bounce<'scene>(
         &self,
         path: &mut Path<'scene, '_>,
@@ -49,32 +245,44 @@ impl DirectionalSamplingStrategy {
         sampler: &mut dyn Sampler,
         medium: Option<&HomogenousVolume>,
         id_strategy: usize,
+        split_weight: f32,
     ) -> (Option<EdgeID>, Option<VertexID>) {
+        // The first intersection is the bounce 0
+        let bsdf_dim = dimensions::bsdf(path.depth(vertex_id).saturating_sub(1));
         match path.vertex(vertex_id) {
             Vertex::Sensor(ref v) => {
-                // Generate the path from the sensor
-                let ray = scene.camera.generate(v.uv);
+                // Generate the path from the sensor (weighted by the lens vignetting)
+                let camera_sample = scene.camera.sample_lens(v.uv, sampler);
+                if camera_sample.weight == 0.0 {
+                    return (None, None);
+                }
                 let (edge, new_vertex) = Edge::from_ray(
                     path,
-                    &ray,
+                    &camera_sample.ray,
                     vertex_id,
                     PDF::SolidAngle(1.0),
-                    Color::one(),
+                    Color::value(camera_sample.weight),
                     1.0,
                     sampler,
                     accel,
                     medium,
                     id_strategy,
                 );
+                // Footprint of the camera ray for the texture filtering
+                if let Some(id) = new_vertex {
+                    if let Vertex::Surface(ref mut v) = path.vertex_mut(id) {
+                        v.its.set_differentials(&camera_sample.differentials);
+                    }
+                }
                 (Some(edge), new_vertex)
             }
             Vertex::Surface(ref v) => {
-                if let Some(sampled_bsdf) =
-                    v.its
-                        .mesh
-                        .bsdf
-                        .sample(&v.its.uv, &v.its.wi, sampler.next2d())
-                {
+                let sampled_bsdf = path.bsdf(vertex_id).sample(
+                    &v.its.tex_coords(),
+                    &v.its.wi,
+                    sampler.next2d_dim(bsdf_dim),
+                );
+                if let Some(sampled_bsdf) = sampled_bsdf {
                     let d_out_global = v.its.frame.to_world(sampled_bsdf.d);
 
                     // Update the throughput
@@ -94,15 +302,17 @@ impl DirectionalSamplingStrategy {
                     }
 
                     // Check RR
-                    let rr_weight = throughput.channel_max().min(0.95);
+                    let rr_weight = self
+                        .rr
+                        .survival(throughput, path.depth(vertex_id), &v.its.p);
                     if rr_weight < sampler.next() {
                         return (None, None);
                     }
-                    let rr_weight = 1.0 / rr_weight;
+                    let rr_weight = split_weight / rr_weight;
                     throughput.scale(rr_weight);
 
                     // Generate the new ray and do the intersection
-                    let ray = Ray::new(v.its.p, d_out_global);
+                    let ray = v.its.spawn_ray(d_out_global);
                     let (edge, new_vertex) = Edge::from_ray(
                         path,
                         &ray,
@@ -121,7 +331,9 @@ impl DirectionalSamplingStrategy {
                 (None, None)
             }
             Vertex::Volume(ref v) => {
-                let sampled_phase = v.phase_function.sample(&v.d_in, sampler.next2d());
+                let sampled_phase = v
+                    .phase_function
+                    .sample(&v.d_in, sampler.next2d_dim(bsdf_dim));
 
                 // Update the throughput
                 *throughput *= &sampled_phase.weight;
@@ -130,11 +342,11 @@ impl DirectionalSamplingStrategy {
                 }
 
                 // Check RR
-                let rr_weight = throughput.channel_max().min(0.95);
+                let rr_weight = self.rr.survival(throughput, path.depth(vertex_id), &v.pos);
                 if rr_weight < sampler.next() {
                     return (None, None);
                 }
-                let rr_weight = 1.0 / rr_weight;
+                let rr_weight = split_weight / rr_weight;
                 throughput.scale(rr_weight);
 
                 // Generate the new ray and do the intersection
@@ -164,7 +376,7 @@ impl DirectionalSamplingStrategy {
 
                 let frame = Frame::new(v.n);
                 let d_out_global = frame.to_world(d_out);
-                let ray = Ray::new(v.pos, d_out_global);
+                let ray = v.spawn_ray(d_out_global);
                 let weight = Color::one(); // Perfectly importance sampled
 
                 // This will generate the edge
@@ -196,49 +408,61 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
         accel: &'scene dyn Acceleration,
         scene: &'scene Scene,
         _emitters: &'emitter EmitterSampler,
-        mut throughput: Color,
+        throughput: Color,
         sampler: &mut dyn Sampler,
         medium: Option<&HomogenousVolume>,
         id_strategy: usize,
-    ) -> Option<(VertexID, Color)> {
-        // Generate the next edge and the next vertex
-        let (edge, new_vertex) = self.bounce(
-            path,
-            vertex_id,
-            accel,
-            scene,
-            &mut throughput,
-            sampler,
-            medium,
-            id_strategy,
-        );
-
-        // Update the edge if we sucesfull sample it
-        if let Some(e) = edge {
-            match path.vertex_mut(vertex_id) {
-                Vertex::Sensor(ref mut v) => {
-                    v.edge_out = Some(e);
-                }
-                Vertex::Surface(ref mut v) => {
-                    v.edge_out.push(e);
-                }
-                Vertex::Light(ref mut v) => {
-                    // For light tracing
-                    // note that the direction of light
-                    // if not correct in this case
-                    v.edge_out = Some(e);
-                }
-                Vertex::Volume(ref mut v) => {
-                    v.edge_out.push(e);
+    ) -> Vec<(VertexID, Color)> {
+        // Only the surface and volume vertices have several outgoing edges
+        let nb_split = match path.vertex(vertex_id) {
+            Vertex::Surface(_) | Vertex::Volume(_) => {
+                let p = path.vertex(vertex_id).position();
+                self.rr.splitting(&throughput, path.depth(vertex_id), &p)
+            }
+            _ => 1,
+        };
+        let mut new_vertices = vec![];
+        for _ in 0..nb_split {
+            // Generate the next edge and the next vertex
+            let mut throughput = throughput;
+            let (edge, new_vertex) = self.bounce(
+                path,
+                vertex_id,
+                accel,
+                scene,
+                &mut throughput,
+                sampler,
+                medium,
+                id_strategy,
+                1.0 / nb_split as f32,
+            );
+
+            // Update the edge if we sucesfull sample it
+            if let Some(e) = edge {
+                match path.vertex_mut(vertex_id) {
+                    Vertex::Sensor(ref mut v) => {
+                        v.edge_out = Some(e);
+                    }
+                    Vertex::Surface(ref mut v) => {
+                        v.edge_out.push(e);
+                    }
+                    Vertex::Light(ref mut v) => {
+                        // For light tracing
+                        // note that the direction of light
+                        // if not correct in this case
+                        v.edge_out = Some(e);
+                    }
+                    Vertex::Volume(ref mut v) => {
+                        v.edge_out.push(e);
+                    }
                 }
             }
-        }
 
-        if let Some(new_vertex) = new_vertex {
-            Some((new_vertex, throughput))
-        } else {
-            None
+            if let Some(new_vertex) = new_vertex {
+                new_vertices.push((new_vertex, throughput));
+            }
         }
+        new_vertices
     }
     fn pdf<'scene, 'emitter>(
         &self,
@@ -252,8 +476,9 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
         // here the pdf here is the light sampling opponent
         // in this case, it makes sense that the PDF for this strategy
         // if None in case of delta distribution...
+        // (the escaped edges reach the environment)
         let edge = path.edge(edge_id);
-        if !edge.next_on_light_source(path) {
+        if !edge.next_on_light_source(path) && edge.vertices.1.is_some() {
             return None;
         }
 
@@ -264,11 +489,11 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
         match path.vertex(vertex_id) {
             Vertex::Surface(ref v) => {
                 // TODO: Check why in the case of smooth, we cannot sample the light source...
-                if v.its.mesh.bsdf.is_smooth() {
+                if path.bsdf(vertex_id).is_smooth() {
                     return None;
                 }
-                if let PDF::SolidAngle(pdf) = v.its.mesh.bsdf.pdf(
-                    &v.its.uv,
+                if let PDF::SolidAngle(pdf) = path.bsdf(vertex_id).pdf(
+                    &v.its.tex_coords(),
                     &v.its.wi,
                     &v.its.frame.to_local(edge.d),
                     Domain::SolidAngle,
@@ -284,46 +509,298 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
     }
 }
 
-pub struct LightSamplingStrategy {}
-impl LightSamplingStrategy {
-    fn pdf_emitter<'scene, 'emitter>(
-        &self,
-        path: &Path<'scene, 'emitter>,
-        emitters: &'emitter EmitterSampler,
-        ray: Ray,
-        next_vertex_id: VertexID,
-    ) -> Option<f32> {
-        match path.vertex(next_vertex_id) {
-            Vertex::Surface(ref v) => {
-                // We could create a emitter sampling
-                // if we have intersected the light source randomly
-                if let PDF::SolidAngle(light_pdf) =
-                    emitters.direct_pdf(v.its.mesh, &LightSamplingPDF::new(&ray, &v.its))
-                {
-                    Some(light_pdf)
-                } else {
-                    None
+/// Solid angle pdf of the emitter reached by the edge (ray), given by emitter_pdf
+fn pdf_emitter<'scene, 'emitter>(
+    path: &Path<'scene, 'emitter>,
+    ray: Ray,
+    next_vertex_id: VertexID,
+    emitter_pdf: impl Fn(&dyn Emitter, &LightSamplingPDF) -> PDF,
+) -> Option<f32> {
+    match path.vertex(next_vertex_id) {
+        Vertex::Surface(ref v) => {
+            // We could create a emitter sampling
+            // if we have intersected the light source randomly
+            if let PDF::SolidAngle(light_pdf) =
+                emitter_pdf(v.its.mesh, &LightSamplingPDF::new(&ray, &v.its))
+            {
+                Some(light_pdf)
+            } else {
+                None
+            }
+        }
+        Vertex::Light(ref v) => {
+            if let PDF::SolidAngle(light_pdf) = emitter_pdf(
+                v.emitter,
+                &LightSamplingPDF {
+                    o: ray.o,
+                    p: v.pos,
+                    n: v.n,
+                    dir: ray.d,
+                },
+            ) {
+                Some(light_pdf)
+            } else {
+                None
+            }
+        }
+        Vertex::Sensor(ref _v) => None,
+        Vertex::Volume(ref _v) => None,
+    }
+}
+
+/// Pdf of the emitter sampling strategies for an edge (None if the edge
+/// cannot be generated by sampling an emitter)
+fn pdf_light_sampling<'scene, 'emitter>(
+    path: &Path<'scene, 'emitter>,
+    vertex_id: VertexID,
+    edge_id: EdgeID,
+    emitter_pdf: impl Fn(&dyn Emitter, &LightSamplingPDF) -> PDF,
+) -> Option<f32> {
+    // Get the edge and check the condition
+    let edge = path.edge(edge_id);
+    if !edge.next_on_light_source(path) {
+        return None;
+    }
+
+    // Retrive the proper pdf in this case
+    let vertex = path.vertex(vertex_id);
+    match vertex {
+        Vertex::Volume(ref _v) => {
+            // Always ok for have sampling a light source
+            let ray = Ray::new(vertex.position(), edge.d);
+            if let Some(next_vertex_id) = edge.vertices.1 {
+                pdf_emitter(path, ray, next_vertex_id, emitter_pdf)
+            } else {
+                None
+            }
+        }
+        Vertex::Surface(_) => {
+            // Impossible to sample from a Dirac distribution
+            if path.bsdf(vertex_id).is_smooth() {
+                return None;
+            }
+            // Know the the light is intersectable so have a solid angle PDF
+            let ray = Ray::new(vertex.position(), edge.d);
+            if let Some(next_vertex_id) = edge.vertices.1 {
+                pdf_emitter(path, ray, next_vertex_id, emitter_pdf)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Weight of the emitter sample without the emission toward the vertex
+/// (zero for the channels without emission)
+fn without_emission(light_record: &LightSampling) -> Color {
+    let emission = light_record.emitter.emitted_luminance(light_record.d);
+    let divide = |w: f32, e: f32| if e > 0.0 { w / e } else { 0.0 };
+    Color::new(
+        divide(light_record.weight.r, emission.r),
+        divide(light_record.weight.g, emission.g),
+        divide(light_record.weight.b, emission.b),
+    )
+}
+
+/// The edge reaches the environment: escaped from the scene,
+/// or connected to the environment by the emitter sampling
+fn reaches_environment(path: &Path, edge_id: EdgeID, emitters: &EmitterSampler) -> bool {
+    let edge = path.edge(edge_id);
+    match edge.vertices.1 {
+        None => true,
+        Some(next_vertex_id) => match path.vertex(next_vertex_id) {
+            Vertex::Light(ref v) => emitters.is_environment(v.emitter),
+            _ => false,
+        },
+    }
+}
+
+/// Pdf of the environment sampling for an edge reaching the environment
+/// (None if the vertex cannot sample it)
+fn pdf_environment(
+    path: &Path,
+    vertex_id: VertexID,
+    edge_id: EdgeID,
+    emitters: &EmitterSampler,
+) -> Option<f32> {
+    let env = emitters.environment?;
+    match path.vertex(vertex_id) {
+        Vertex::Surface(_) if path.bsdf(vertex_id).is_smooth() => None,
+        Vertex::Surface(_) | Vertex::Volume(_) => {
+            Some(emitters.environment_pdf(env, path.edge(edge_id).d))
+        }
+        _ => None,
+    }
+}
+
+/// Connect the vertex to a direction sampled on the environment (if any)
+fn connect_environment<'scene, 'emitter>(
+    path: &mut Path<'scene, 'emitter>,
+    vertex_id: VertexID,
+    accel: &'scene dyn Acceleration,
+    emitters: &'emitter EmitterSampler,
+    p: Point3<f32>,
+    medium: Option<&HomogenousVolume>,
+    sampler: &mut dyn Sampler,
+    id_strategy: usize,
+) {
+    if let Some(env) = emitters.environment {
+        let light_record = emitters.sample_environment(env, &p, sampler.next(), sampler.next2d());
+        connect_emitter(
+            path,
+            vertex_id,
+            accel,
+            light_record,
+            medium,
+            sampler,
+            id_strategy,
+        );
+    }
+}
+
+/// Connect the vertex to the point sampled on the emitter
+/// (None if the connection is not possible)
+fn connect_emitter<'scene, 'emitter>(
+    path: &mut Path<'scene, 'emitter>,
+    vertex_id: VertexID,
+    accel: &'scene dyn Acceleration,
+    light_record: LightSampling<'emitter>,
+    medium: Option<&HomogenousVolume>,
+    sampler: &mut dyn Sampler,
+    id_strategy: usize,
+) -> Option<EdgeID> {
+    let (edge, _next_vertex) = match path.vertex(vertex_id) {
+        Vertex::Surface(ref v) => {
+            if path.bsdf(vertex_id).is_smooth() {
+                return None;
+            }
+
+            // Note that during this procedure, we did not evaluate the product of the path throughput
+            // and the incomming direct light. This evaluation will be done later when MIS
+            // will be computed.
+            let visible = accel.visible(&v.its.offset_toward(&light_record.p), &light_record.p);
+            if light_record.is_valid() && visible {
+                // We create a new vertex as it is a light
+                let next_vertex = Vertex::Light(EmitterVertex {
+                    pos: light_record.p,
+                    p_error: light_record.p_error,
+                    n: light_record.n,
+                    emitter: light_record.emitter,
+                    edge_in: None,
+                    edge_out: None,
+                });
+
+                // The emission is evaluated with the light vertex
+                let mut weight = without_emission(&light_record);
+
+                // Need to evaluate the BSDF
+                weight *= &path.bsdf(vertex_id).eval(
+                    &v.its.tex_coords(),
+                    &v.its.wi,
+                    &v.its.to_local(&light_record.d),
+                    Domain::SolidAngle,
+                );
+
+                if let Some(m) = medium {
+                    // Evaluate the transmittance
+                    let mut ray = Ray::new(v.its.p, light_record.d);
+                    let d = light_record.p - v.its.p;
+                    // Trick to compute the distance
+                    ray.tfar = d.dot(light_record.d);
+                    assert!(ray.tfar > 0.0);
+                    // Compute the transmittance
+                    let transmittance = m.estimate_transmittance(ray, sampler);
+                    weight *= transmittance;
                 }
+
+                let next_vertex_id = path.register_vertex(next_vertex);
+                (
+                    Edge::from_vertex(
+                        path,
+                        vertex_id,
+                        light_record.pdf,
+                        weight,
+                        1.0,
+                        next_vertex_id,
+                        id_strategy,
+                    ),
+                    next_vertex_id,
+                )
+            } else {
+                return None;
             }
-            Vertex::Light(ref v) => {
-                if let PDF::SolidAngle(light_pdf) = emitters.direct_pdf(
-                    v.emitter,
-                    &LightSamplingPDF {
-                        o: ray.o,
-                        p: v.pos,
-                        n: v.n,
-                        dir: ray.d,
-                    },
-                ) {
-                    Some(light_pdf)
-                } else {
-                    None
+        }
+        Vertex::Volume(ref v) => {
+            // Note that during this procedure, we did not evaluate the product of the path throughput
+            // and the incomming direct light. This evaluation will be done later when MIS
+            // will be computed.
+            let visible = accel.visible(&v.pos, &light_record.p);
+            if light_record.is_valid() && visible {
+                let next_vertex = Vertex::Light(EmitterVertex {
+                    pos: light_record.p,
+                    p_error: light_record.p_error,
+                    n: light_record.n,
+                    emitter: light_record.emitter,
+                    edge_in: None,
+                    edge_out: None,
+                });
+
+                // The emission is evaluated with the light vertex
+                let mut weight = without_emission(&light_record);
+
+                // Need to evaluate the phase function
+                weight *= &v.phase_function.eval(&v.d_in, &light_record.d);
+
+                if let Some(m) = medium {
+                    // Evaluate the transmittance
+                    let mut ray = Ray::new(v.pos, light_record.d);
+                    let d = light_record.p - v.pos;
+                    // Trick to compute the distance
+                    ray.tfar = d.dot(light_record.d);
+                    assert!(ray.tfar > 0.0);
+                    // Generate the ray and compute the transmittance
+                    let transmittance = m.estimate_transmittance(ray, sampler);
+                    weight *= transmittance;
                 }
+
+                let next_vertex_id = path.register_vertex(next_vertex);
+                (
+                    Edge::from_vertex(
+                        path,
+                        vertex_id,
+                        light_record.pdf,
+                        weight,
+                        1.0,
+                        next_vertex_id,
+                        id_strategy,
+                    ),
+                    next_vertex_id,
+                )
+            } else {
+                return None;
             }
-            Vertex::Sensor(ref _v) => None,
-            Vertex::Volume(ref _v) => None,
         }
+        _ => return None,
+    };
+
+    // Update the out edge
+    match path.vertex_mut(vertex_id) {
+        Vertex::Surface(ref mut v) => {
+            v.edge_out.push(edge);
+        }
+        Vertex::Volume(ref mut v) => {
+            v.edge_out.push(edge);
+        }
+        _ => unimplemented!(),
     }
+    Some(edge)
+}
+
+/// One emitter sample per vertex, and one environment sample
+/// if the technique evaluates the edges reaching the environment
+pub struct LightSamplingStrategy {
+    pub environment: bool,
 }
 impl SamplingStrategy for LightSamplingStrategy {
     fn sample<'scene, 'emitter>(
@@ -337,151 +814,50 @@ impl SamplingStrategy for LightSamplingStrategy {
         sampler: &mut dyn Sampler,
         medium: Option<&HomogenousVolume>,
         id_strategy: usize,
-    ) -> Option<(VertexID, Color)> {
-        let (edge, _next_vertex) = match path.vertex(vertex_id) {
+    ) -> Vec<(VertexID, Color)> {
+        let p = match path.vertex(vertex_id) {
             Vertex::Surface(ref v) => {
-                if v.its.mesh.bsdf.is_smooth() {
-                    return None;
-                }
-
-                // Generate the light sampling record based on the current vertex location
-                // Note that during this procedure, we did not evaluate the product of the path throughput
-                // and the incomming direct light. This evaluation will be done later when MIS
-                // will be computed.
-                let light_record = emitters.sample_light(
-                    &v.its.p,
-                    sampler.next(),
-                    sampler.next(),
-                    sampler.next2d(),
-                );
-                let visible = accel.visible(&v.its.p, &light_record.p);
-                if light_record.is_valid() && visible {
-                    // We create a new vertex as it is a light
-                    let next_vertex = Vertex::Light(EmitterVertex {
-                        pos: light_record.p,
-                        n: light_record.n,
-                        emitter: light_record.emitter,
-                        edge_in: None,
-                        edge_out: None,
-                    });
-
-                    // FIXME: Only work for diffuse light
-                    // FIXME: Check the direction of hte light
-                    let mut weight = light_record.weight;
-                    let emission = light_record.emitter.emitted_luminance(light_record.d);
-                    weight.r /= emission.r;
-                    weight.g /= emission.g;
-                    weight.b /= emission.b;
-
-                    // Need to evaluate the BSDF
-                    weight *= &v.its.mesh.bsdf.eval(
-                        &v.its.uv,
-                        &v.its.wi,
-                        &v.its.to_local(&light_record.d),
-                        Domain::SolidAngle,
-                    );
-
-                    if let Some(m) = medium {
-                        // Evaluate the transmittance
-                        let mut ray = Ray::new(v.its.p, light_record.d);
-                        let d = light_record.p - v.its.p;
-                        // Trick to compute the distance
-                        ray.tfar = d.dot(light_record.d);
-                        assert!(ray.tfar > 0.0);
-                        // Compute the transmittance
-                        let transmittance = m.transmittance(ray);
-                        weight *= transmittance;
-                    }
-
-                    let next_vertex_id = path.register_vertex(next_vertex);
-                    (
-                        Edge::from_vertex(
-                            path,
-                            vertex_id,
-                            light_record.pdf,
-                            weight,
-                            1.0,
-                            next_vertex_id,
-                            id_strategy,
-                        ),
-                        next_vertex_id,
-                    )
-                } else {
-                    return None;
+                if path.bsdf(vertex_id).is_smooth() {
+                    return vec![];
                 }
+                v.its.p
             }
-            Vertex::Volume(ref v) => {
-                // Generate the light sampling record based on the current vertex location
-                // Note that during this procedure, we did not evaluate the product of the path throughput
-                // and the incomming direct light. This evaluation will be done later when MIS
-                // will be computed.
-                let light_record =
-                    emitters.sample_light(&v.pos, sampler.next(), sampler.next(), sampler.next2d());
-                let visible = accel.visible(&v.pos, &light_record.p);
-                if light_record.is_valid() && visible {
-                    let next_vertex = Vertex::Light(EmitterVertex {
-                        pos: light_record.p,
-                        n: light_record.n,
-                        emitter: light_record.emitter,
-                        edge_in: None,
-                        edge_out: None,
-                    });
-
-                    // FIXME: Only work for diffuse light
-                    // FIXME: This is the wrong -d_out_local, no?
-                    let mut weight = light_record.weight;
-                    let emission = light_record.emitter.emitted_luminance(-light_record.d);
-                    weight.r /= emission.r;
-                    weight.g /= emission.g;
-                    weight.b /= emission.b;
-
-                    // Need to evaluate the phase function
-                    weight *= &v.phase_function.eval(&v.d_in, &light_record.d);
-
-                    if let Some(m) = medium {
-                        // Evaluate the transmittance
-                        let mut ray = Ray::new(v.pos, light_record.d);
-                        let d = light_record.p - v.pos;
-                        // Trick to compute the distance
-                        ray.tfar = d.dot(light_record.d);
-                        assert!(ray.tfar > 0.0);
-                        // Generate the ray and compute the transmittance
-                        let transmittance = m.transmittance(ray);
-                        weight *= transmittance;
-                    }
-
-                    let next_vertex_id = path.register_vertex(next_vertex);
-                    (
-                        Edge::from_vertex(
-                            path,
-                            vertex_id,
-                            light_record.pdf,
-                            weight,
-                            1.0,
-                            next_vertex_id,
-                            id_strategy,
-                        ),
-                        next_vertex_id,
-                    )
-                } else {
-                    return None;
-                }
-            }
-            _ => return None,
+            Vertex::Volume(ref v) => v.pos,
+            _ => return vec![],
         };
-
-        // Update the out edge
-        match path.vertex_mut(vertex_id) {
-            Vertex::Surface(ref mut v) => {
-                v.edge_out.push(edge);
-            }
-            Vertex::Volume(ref mut v) => {
-                v.edge_out.push(edge);
-            }
-            _ => unimplemented!(),
+        // Generate the light sampling record based on the current vertex location
+        // (none if only lit by the environment)
+        if !emitters.emitters.is_empty() {
+            let bounce = path.depth(vertex_id).saturating_sub(1);
+            let light_record = emitters.sample_light(
+                &p,
+                sampler.next_dim(dimensions::light_selection(bounce)),
+                sampler.next(),
+                sampler.next2d_dim(dimensions::light_position(bounce)),
+            );
+            connect_emitter(
+                path,
+                vertex_id,
+                accel,
+                light_record,
+                medium,
+                sampler,
+                id_strategy,
+            );
         }
-
-        None // Finish the sampling here
+        if self.environment {
+            connect_environment(
+                path,
+                vertex_id,
+                accel,
+                emitters,
+                p,
+                medium,
+                sampler,
+                id_strategy,
+            );
+        }
+        vec![] // Finish the sampling here
     }
 
     fn pdf<'scene, 'emitter>(
@@ -492,39 +868,86 @@ impl SamplingStrategy for LightSamplingStrategy {
         vertex_id: VertexID,
         edge_id: EdgeID,
     ) -> Option<f32> {
-        // Get the edge and check the condition
-        let edge = path.edge(edge_id);
-        if !edge.next_on_light_source(path) {
-            return None;
+        if self.environment && reaches_environment(path, edge_id, emitters) {
+            return pdf_environment(path, vertex_id, edge_id, emitters);
         }
+        pdf_light_sampling(path, vertex_id, edge_id, |emitter, light_sampling| {
+            emitters.direct_pdf(emitter, light_sampling)
+        })
+    }
+}
 
-        // Retrive the proper pdf in this case
-        let vertex = path.vertex(vertex_id);
-        match vertex {
-            Vertex::Volume(ref _v) => {
-                // Always ok for have sampling a light source
-                let ray = Ray::new(vertex.position(), edge.d);
-                if let Some(next_vertex_id) = edge.vertices.1 {
-                    self.pdf_emitter(path, emitters, ray, next_vertex_id)
-                } else {
-                    None
-                }
-            }
+/// Stochastic lightcuts (Yuksel 2019): one emitter is sampled inside each node
+/// of a cut of the light BVH (at most max_cut connections per vertex).
+/// All the emitters are covered by the cut, so the estimator stays unbiased.
+/// The environment is sampled once per vertex, as by LightSamplingStrategy.
+pub struct StochasticLightcutsStrategy {
+    pub max_cut: usize,
+}
+impl SamplingStrategy for StochasticLightcutsStrategy {
+    fn sample<'scene, 'emitter>(
+        &self,
+        path: &mut Path<'scene, 'emitter>,
+        vertex_id: VertexID,
+        accel: &'scene dyn Acceleration,
+        _scene: &'scene Scene,
+        emitters: &'emitter EmitterSampler,
+        _throughput: Color,
+        sampler: &mut dyn Sampler,
+        medium: Option<&HomogenousVolume>,
+        id_strategy: usize,
+    ) -> Vec<(VertexID, Color)> {
+        let p = match path.vertex(vertex_id) {
             Vertex::Surface(ref v) => {
-                // Impossible to sample from a Dirac distribution
-                if v.its.mesh.bsdf.is_smooth() {
-                    return None;
-                }
-                // Know the the light is intersectable so have a solid angle PDF
-                let ray = Ray::new(vertex.position(), edge.d);
-                if let Some(next_vertex_id) = edge.vertices.1 {
-                    self.pdf_emitter(path, emitters, ray, next_vertex_id)
-                } else {
-                    None
+                if path.bsdf(vertex_id).is_smooth() {
+                    return vec![];
                 }
+                v.its.p
             }
-            _ => None,
+            Vertex::Volume(ref v) => v.pos,
+            _ => return vec![],
+        };
+        for (pdf_sel, emitter) in emitters.select_lightcut(&p, self.max_cut, sampler) {
+            let mut light_record = emitter.sample_direct(&p, sampler.next(), sampler.next2d());
+            light_record.pdf = light_record.pdf * pdf_sel;
+            light_record.weight /= pdf_sel;
+            connect_emitter(
+                path,
+                vertex_id,
+                accel,
+                light_record,
+                medium,
+                sampler,
+                id_strategy,
+            );
         }
+        connect_environment(
+            path,
+            vertex_id,
+            accel,
+            emitters,
+            p,
+            medium,
+            sampler,
+            id_strategy,
+        );
+        vec![]
+    }
+
+    fn pdf<'scene, 'emitter>(
+        &self,
+        path: &Path<'scene, 'emitter>,
+        _scene: &'scene Scene,
+        emitters: &'emitter EmitterSampler,
+        vertex_id: VertexID,
+        edge_id: EdgeID,
+    ) -> Option<f32> {
+        if reaches_environment(path, edge_id, emitters) {
+            return pdf_environment(path, vertex_id, edge_id, emitters);
+        }
+        pdf_light_sampling(path, vertex_id, edge_id, |emitter, light_sampling| {
+            emitters.lightcut_pdf(emitter, light_sampling, self.max_cut)
+        })
     }
 }
 
@@ -547,14 +970,14 @@ pub fn generate<'scene, 'emitter, T: Technique>(
             // For all the sampling techniques
             // This is the continue if we want to continue or not
             // For example, we might want to not push the vertex if we have reach the depth limit
-            if technique.expand(path.vertex(*curr_vertex_id), depth) {
+            if technique.expand(path, *curr_vertex_id, depth) {
                 for (id_sampling, sampling) in technique
                     .strategies(path.vertex(*curr_vertex_id))
                     .iter()
                     .enumerate()
                 {
-                    // If we want to continue the tracing toward this direction
-                    if let Some((new_vertex, new_throughput)) = sampling.sample(
+                    // If we want to continue the tracing toward these directions
+                    next.extend(sampling.sample(
                         path,
                         *curr_vertex_id,
                         accel,
@@ -564,9 +987,7 @@ pub fn generate<'scene, 'emitter, T: Technique>(
                         sampler,
                         scene.volume.as_ref(), // TODO: For now volume is global
                         id_sampling,
-                    ) {
-                        next.push((new_vertex, new_throughput));
-                    }
+                    ));
                 }
             }
         }
@@ -588,5 +1009,6 @@ pub trait Technique {
         emitters: &'emitter EmitterSampler,
     ) -> Vec<(VertexID, Color)>;
     fn strategies(&self, vertex: &Vertex) -> &Vec<Box<dyn SamplingStrategy>>;
-    fn expand(&self, vertex: &Vertex, depth: u32) -> bool;
+    /// depth: number of vertices from the root (1 for the root)
+    fn expand(&self, path: &Path, vertex_id: VertexID, depth: u32) -> bool;
 }
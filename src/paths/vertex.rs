@@ -1,3 +1,4 @@
+use crate::bsdfs::{Lobe, BSDF};
 use crate::emitter::Emitter;
 use crate::samplers::*;
 use crate::scene::*;
@@ -217,11 +218,23 @@ pub struct SurfaceVertex<'scene> {
 #[derive(Clone)]
 pub struct EmitterVertex<'emitter> {
     pub pos: Point3<f32>,
+    /// Floating point error bound of pos
+    pub p_error: Vector3<f32>,
     pub n: Vector3<f32>,
     pub emitter: &'emitter dyn Emitter,
     pub edge_in: Option<EdgeID>,
     pub edge_out: Option<EdgeID>,
 }
+impl<'emitter> EmitterVertex<'emitter> {
+    /// Ray leaving the emitter surface
+    pub fn spawn_ray(&self, d: Vector3<f32>) -> Ray {
+        Ray::spawn(self.pos, self.p_error, self.n, d)
+    }
+    /// Origin of the rays leaving the emitter surface toward p
+    pub fn offset_toward(&self, p: &Point3<f32>) -> Point3<f32> {
+        offset_ray_origin(self.pos, self.p_error, self.n, p - self.pos)
+    }
+}
 
 #[derive(Clone)]
 pub struct VolumeVertex {
@@ -283,7 +296,8 @@ impl<'scene, 'emitter> Vertex<'scene, 'emitter> {
             }
             Vertex::Volume(ref _v) => Color::zero(),
             Vertex::Sensor(ref _v) => Color::zero(),
-            Vertex::Light(ref v) => v.emitter.emitted_luminance(-edge.d), // FIXME: Check the normal orientation
+            // Emission toward the vertex (the direction of the edge for the environment)
+            Vertex::Light(ref v) => v.emitter.emitted_luminance(edge.d),
         }
     }
 }
@@ -292,19 +306,43 @@ impl<'scene, 'emitter> Vertex<'scene, 'emitter> {
 pub struct VertexID(usize);
 #[derive(Clone, Copy, Debug)]
 pub struct EdgeID(usize);
+/// BSDF of a surface vertex (possibly roughened)
+pub enum VertexBSDF<'a> {
+    Mesh(&'a dyn BSDF),
+    Roughened(Box<dyn BSDF + 'a>),
+}
+impl<'a> std::ops::Deref for VertexBSDF<'a> {
+    type Target = dyn BSDF + 'a;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            VertexBSDF::Mesh(bsdf) => *bsdf,
+            VertexBSDF::Roughened(bsdf) => bsdf.as_ref(),
+        }
+    }
+}
+
 pub struct Path<'scene, 'emitter> {
     vertices: Vec<Vertex<'scene, 'emitter>>,
     edges: Vec<Edge>,
+    /// Minimum roughness of the BSDFs after the first non-specular bounce
+    /// (path-space roughening: removes the fireflies of the caustics with some bias)
+    pub min_roughness: Option<f32>,
 }
 impl<'scene, 'emitter> Default for Path<'scene, 'emitter> {
     fn default() -> Self {
         Path {
             vertices: vec![],
             edges: vec![],
+            min_roughness: None,
         }
     }
 }
 impl<'scene, 'emitter> Path<'scene, 'emitter> {
+    /// Remove the vertices and the edges, their memory is kept for the next path
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.edges.clear();
+    }
     pub fn register_edge(&mut self, e: Edge) -> EdgeID {
         let id = self.edges.len();
         self.edges.push(e);
@@ -327,6 +365,59 @@ impl<'scene, 'emitter> Path<'scene, 'emitter> {
     pub fn edge_mut(&mut self, id: EdgeID) -> &mut Edge {
         &mut self.edges[id.0]
     }
+    /// Vertex before this one (None for the root of the path)
+    pub fn previous(&self, vertex_id: VertexID) -> Option<VertexID> {
+        let edge_in = match self.vertex(vertex_id) {
+            Vertex::Sensor(ref v) => v.edge_in,
+            Vertex::Light(ref v) => v.edge_in,
+            Vertex::Surface(ref v) => Some(v.edge_in),
+            Vertex::Volume(ref v) => Some(v.edge_in),
+        };
+        edge_in.map(|e| self.edge(e).vertices.0)
+    }
+    /// BSDF to use at a surface vertex
+    pub fn bsdf(&self, vertex_id: VertexID) -> VertexBSDF<'_> {
+        let bsdf = match self.vertex(vertex_id) {
+            Vertex::Surface(ref v) => v.its.mesh.bsdf.as_ref(),
+            _ => panic!("BSDF of a vertex which is not on a surface"),
+        };
+        let min_roughness = match self.min_roughness {
+            Some(r) => r,
+            None => return VertexBSDF::Mesh(bsdf),
+        };
+        // Roughening only after a glossy or diffuse bounce
+        let mut current = vertex_id;
+        while let Some(previous) = self.previous(current) {
+            if let Vertex::Surface(ref v) = self.vertex(previous) {
+                if v.its.mesh.bsdf.lobe(&v.its.tex_coords()) != Lobe::Specular {
+                    return match bsdf.roughen(min_roughness) {
+                        Some(roughened) => VertexBSDF::Roughened(roughened),
+                        None => VertexBSDF::Mesh(bsdf),
+                    };
+                }
+            }
+            current = previous;
+        }
+        VertexBSDF::Mesh(bsdf)
+    }
+    /// Vertices without a previous one, in their creation order
+    /// (e.g., the base and the offset paths of the shift mappings)
+    pub fn roots(&self) -> Vec<VertexID> {
+        (0..self.vertices.len())
+            .map(VertexID)
+            .filter(|id| self.previous(*id).is_none())
+            .collect()
+    }
+    /// Number of edges between the vertex and the root of the path
+    pub fn depth(&self, vertex_id: VertexID) -> u32 {
+        let mut depth = 0;
+        let mut current = vertex_id;
+        while let Some(previous) = self.previous(current) {
+            depth += 1;
+            current = previous;
+        }
+        depth
+    }
     pub fn have_next_vertices(&self, vertex_id: VertexID) -> bool {
         !self.next_vertices(vertex_id).is_empty()
     }
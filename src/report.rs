@@ -0,0 +1,162 @@
+//! Statistics of a scene (geometry, textures, emitters and acceleration structures)
+//! to find why a huge scene fails to load or renders slowly (see Scene::report).
+use crate::accel::{Triangle, TriangleBVH};
+use crate::emitter::Emitter;
+use crate::geometry::Mesh;
+use crate::integrators::embree_scene;
+use crate::scene::Scene;
+use crate::structure::Color;
+use cgmath::{Vector2, Vector3};
+use std::mem::size_of;
+use std::time::Instant;
+
+/// Geometry of a mesh
+#[derive(Serialize, Debug, Clone)]
+pub struct MeshReport {
+    pub name: String,
+    pub material: String,
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Bytes of the vertex attributes, indices and area distribution
+    pub memory: usize,
+    /// Bytes of the textures of its BSDF
+    pub texture_memory: usize,
+}
+
+/// Power of an emitter
+#[derive(Serialize, Debug, Clone)]
+pub struct EmitterReport {
+    /// Mesh name ("environment" for the environment light)
+    pub name: String,
+    pub flux: Color,
+    /// Fraction of the total flux (luminance)
+    pub fraction: f32,
+}
+
+/// Acceleration structures of the scene
+#[derive(Serialize, Debug, Clone)]
+pub struct BVHReport {
+    /// Nodes of the native triangle BVH (see accel::TriangleBVH)
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Build time of the native triangle BVH (seconds)
+    pub build_time: f32,
+    /// Build time of the embree scene used by the renderings (seconds).
+    /// Embree does not expose its nodes.
+    pub embree_build_time: f32,
+    /// Nodes of the light BVH (only with the bvh emitter selection)
+    pub light_nodes: Option<usize>,
+}
+
+/// Statistics returned by Scene::report (JSON with `info --json`)
+#[derive(Serialize, Debug, Clone)]
+pub struct SceneReport {
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Bytes of the geometry of all the meshes
+    pub geometry_memory: usize,
+    /// Bytes of the textures of all the meshes and of the environment map
+    pub texture_memory: usize,
+    pub meshes: Vec<MeshReport>,
+    /// Sorted by decreasing flux
+    pub emitters: Vec<EmitterReport>,
+    pub bvh: BVHReport,
+}
+
+/// Bytes of the geometry of a mesh
+fn mesh_memory(m: &Mesh) -> usize {
+    m.vertices.len() * size_of::<Vector3<f32>>()
+        + m.indices.len() * size_of::<Vector3<usize>>()
+        + m.normals
+            .as_ref()
+            .map(|n| n.len() * size_of::<Vector3<f32>>())
+            .unwrap_or(0)
+        + m.uv
+            .as_ref()
+            .map(|uv| uv.len() * size_of::<Vector2<f32>>())
+            .unwrap_or(0)
+        + m.cdf.cdf.len() * size_of::<f32>()
+}
+
+impl SceneReport {
+    /// Builds the acceleration structures to measure them
+    pub fn new(scene: &Scene) -> SceneReport {
+        let meshes = scene
+            .meshes
+            .iter()
+            .map(|m| MeshReport {
+                name: m.name.clone(),
+                material: m.material_name.clone(),
+                triangles: m.indices.len(),
+                vertices: m.vertices.len(),
+                memory: mesh_memory(m),
+                texture_memory: m.bsdf.texture_memory(),
+            })
+            .collect::<Vec<_>>();
+
+        // Emitters
+        let mut emitters = scene
+            .meshes
+            .iter()
+            .filter(|m| m.is_light())
+            .map(|m| (m.name.clone(), m.flux()))
+            .chain(
+                scene
+                    .emitter_environment
+                    .iter()
+                    .map(|env| ("environment".to_string(), env.flux())),
+            )
+            .map(|(name, flux)| EmitterReport {
+                name,
+                flux,
+                fraction: 0.0,
+            })
+            .collect::<Vec<_>>();
+        let total = emitters.iter().map(|e| e.flux.luminance()).sum::<f32>();
+        if total > 0.0 {
+            for e in &mut emitters {
+                e.fraction = e.flux.luminance() / total;
+            }
+        }
+        emitters.sort_by(|a, b| b.fraction.partial_cmp(&a.fraction).unwrap());
+
+        // Acceleration structures
+        let start = Instant::now();
+        let bvh = TriangleBVH::create(Triangle::from_meshes(&scene.meshes));
+        let build_time = start.elapsed().as_secs_f32();
+        let device = embree_rs::Device::new();
+        let start = Instant::now();
+        let rtscene = embree_scene(&device, &scene.meshes);
+        rtscene.commit();
+        let embree_build_time = start.elapsed().as_secs_f32();
+        let light_nodes = scene
+            .emitters_sampler()
+            .data
+            .light_bvh
+            .as_ref()
+            .map(|b| b.nb_nodes());
+
+        let environment_memory = scene
+            .emitter_environment
+            .as_ref()
+            .and_then(|env| env.map.as_ref())
+            .map(|map| map.image.memory())
+            .unwrap_or(0);
+        SceneReport {
+            triangles: meshes.iter().map(|m| m.triangles).sum(),
+            vertices: meshes.iter().map(|m| m.vertices).sum(),
+            geometry_memory: meshes.iter().map(|m| m.memory).sum(),
+            texture_memory: meshes.iter().map(|m| m.texture_memory).sum::<usize>()
+                + environment_memory,
+            meshes,
+            emitters,
+            bvh: BVHReport {
+                nodes: bvh.nb_nodes(),
+                leaves: bvh.nb_leaves(),
+                build_time,
+                embree_build_time,
+                light_nodes,
+            },
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Photographic exposure and camera response curves of the rendered images.
+//! The radiance is considered in cd/m^2: the exposure converts it to the normalized irradiance
+//! of the sensor (1 saturates), and the response curve maps this irradiance to the pixel values
+//! of the LDR outputs, replacing their transfer function (sRGB or gamma).
+use crate::error::{Error, Result};
+use crate::structure::Color;
+
+/// Camera settings giving the exposure (saturation based sensitivity, ISO 12232)
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    /// Sensitivity (100 by default)
+    #[serde(default = "Exposure::default_iso")]
+    pub iso: f32,
+    /// Exposure time in seconds
+    pub shutter: f32,
+    /// Relative aperture (N of f/N)
+    pub f_number: f32,
+}
+
+impl Exposure {
+    fn default_iso() -> f32 {
+        100.0
+    }
+
+    pub fn new(iso: f32, shutter: f32, f_number: f32) -> Result<Exposure> {
+        if iso <= 0.0 || shutter <= 0.0 || f_number <= 0.0 {
+            return Err(Error::InvalidData(format!(
+                "invalid exposure: ISO {}, {} s, f/{}",
+                iso, shutter, f_number
+            )));
+        }
+        Ok(Exposure {
+            iso,
+            shutter,
+            f_number,
+        })
+    }
+
+    /// Exposure value of the settings at ISO 100
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    /// Factor from the radiance to the normalized sensor irradiance:
+    /// the luminance 78 / (S q) N^2 / t saturates the sensor (lens transmittance q = 0.65)
+    pub fn scale(&self) -> f32 {
+        1.0 / (1.2 * self.ev100().exp2())
+    }
+}
+
+/// Measured response curve: pixel values of the normalized irradiances in [0, 1],
+/// linearly interpolated between samples uniformly spaced on its domain
+#[derive(Clone, Debug)]
+pub struct ResponseCurve {
+    /// Irradiances of the first and last samples (per channel)
+    pub domain: [[f32; 3]; 2],
+    /// Samples of the red, green and blue channels
+    pub curves: [Vec<f32>; 3],
+}
+
+impl ResponseCurve {
+    fn new(curves: [Vec<f32>; 3]) -> Result<ResponseCurve> {
+        if curves.iter().any(|c| c.len() < 2) {
+            return Err(Error::InvalidData(
+                "response curve with less than 2 samples".to_string(),
+            ));
+        }
+        Ok(ResponseCurve {
+            domain: [[0.0; 3], [1.0; 3]],
+            curves,
+        })
+    }
+
+    /// Empirical model of response (Grossberg and Nayar 2004):
+    /// f0 + sum_i c_i h_i, with the mean curve and the basis of the emor.txt file of DoRF
+    pub fn from_emor(filename: &str, coefficients: &[f32]) -> Result<ResponseCurve> {
+        let data = std::fs::read_to_string(filename)?;
+        // Sections "E =", "f0 =", "h(1)=", ... followed by their samples
+        let mut sections: Vec<(String, Vec<f32>)> = vec![];
+        for token in data.split_whitespace() {
+            match token.parse::<f32>() {
+                Ok(v) => match sections.last_mut() {
+                    Some((_, values)) => values.push(v),
+                    None => {
+                        return Err(Error::InvalidData(format!(
+                            "{}: samples before the first curve",
+                            filename
+                        )))
+                    }
+                },
+                Err(_) => {
+                    let name = token.trim_end_matches('=');
+                    if !name.is_empty() {
+                        sections.push((name.to_string(), vec![]));
+                    }
+                }
+            }
+        }
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v)
+                .ok_or_else(|| Error::InvalidData(format!("{}: no {} curve", filename, name)))
+        };
+        let mut curve = section("f0")?.clone();
+        for (i, c) in coefficients.iter().enumerate() {
+            let h = section(&format!("h({})", i + 1))?;
+            if h.len() != curve.len() {
+                return Err(Error::InvalidData(format!(
+                    "{}: h({}) has {} samples instead of {}",
+                    filename,
+                    i + 1,
+                    h.len(),
+                    curve.len()
+                )));
+            }
+            curve.iter_mut().zip(h).for_each(|(f, h)| *f += c * h);
+        }
+        ResponseCurve::new([curve.clone(), curve.clone(), curve])
+    }
+
+    /// User LUT: an Adobe/Resolve 1D .cube file (LUT_1D_SIZE, DOMAIN_MIN/MAX)
+    /// or a text file with one (gray) or three (RGB) values per line
+    pub fn from_lut(filename: &str) -> Result<ResponseCurve> {
+        let data = std::fs::read_to_string(filename)?;
+        let invalid =
+            |line: &str| Error::InvalidData(format!("{}: invalid line {}", filename, line));
+        let parse = |values: &[&str], line: &str| {
+            values
+                .iter()
+                .map(|v| v.parse::<f32>().map_err(|_| invalid(line)))
+                .collect::<Result<Vec<_>>>()
+        };
+        let mut domain = [[0.0; 3], [1.0; 3]];
+        let mut size = None;
+        let mut curves = [vec![], vec![], vec![]];
+        for line in data.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            match tokens[0] {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    return Err(Error::InvalidData(format!(
+                        "{}: 3D LUTs are not response curves",
+                        filename
+                    )))
+                }
+                "LUT_1D_SIZE" if tokens.len() == 2 => {
+                    size = Some(tokens[1].parse::<usize>().map_err(|_| invalid(line))?)
+                }
+                "DOMAIN_MIN" | "DOMAIN_MAX" if tokens.len() == 4 => {
+                    let v = parse(&tokens[1..], line)?;
+                    let bound = if tokens[0] == "DOMAIN_MIN" { 0 } else { 1 };
+                    domain[bound] = [v[0], v[1], v[2]];
+                }
+                _ => {
+                    let v = parse(&tokens, line)?;
+                    match v.len() {
+                        1 => curves.iter_mut().for_each(|c| c.push(v[0])),
+                        3 => curves.iter_mut().zip(v).for_each(|(c, v)| c.push(v)),
+                        _ => return Err(invalid(line)),
+                    }
+                }
+            }
+        }
+        if let Some(size) = size {
+            if size != curves[0].len() {
+                return Err(Error::InvalidData(format!(
+                    "{}: {} samples instead of {}",
+                    filename,
+                    curves[0].len(),
+                    size
+                )));
+            }
+        }
+        if (0..3).any(|c| domain[1][c] <= domain[0][c]) {
+            return Err(Error::InvalidData(format!("{}: empty domain", filename)));
+        }
+        let mut curve = ResponseCurve::new(curves)?;
+        curve.domain = domain;
+        Ok(curve)
+    }
+
+    /// Pixel value of a normalized irradiance (clamped to the domain)
+    pub fn evaluate(&self, channel: usize, v: f32) -> f32 {
+        let curve = &self.curves[channel];
+        let (min, max) = (self.domain[0][channel], self.domain[1][channel]);
+        let x = ((v - min) / (max - min)).clamp(0.0, 1.0) * (curve.len() - 1) as f32;
+        let i = (x as usize).min(curve.len() - 2);
+        let t = x - i as f32;
+        curve[i] * (1.0 - t) + curve[i + 1] * t
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        Color::new(
+            self.evaluate(0, c.r),
+            self.evaluate(1, c.g),
+            self.evaluate(2, c.b),
+        )
+    }
+}
+
+/// Response curve of the configuration: a LUT file or the EMoR coefficients
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ResponseDesc {
+    /// "curve.cube"
+    Lut(String),
+    /// {emor = "emor.txt", coefficients = [...]}
+    Emor {
+        emor: String,
+        #[serde(default)]
+        coefficients: Vec<f32>,
+    },
+}
+
+impl ResponseDesc {
+    pub fn load(&self) -> Result<ResponseCurve> {
+        match self {
+            ResponseDesc::Lut(filename) => ResponseCurve::from_lut(filename),
+            ResponseDesc::Emor { emor, coefficients } => {
+                ResponseCurve::from_emor(emor, coefficients)
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::samplers::independent::IndependentSampler;
+use crate::samplers::*;
+use crate::tools::mix64;
+
+/// Bases of the Halton dimensions (the other dimensions are padded)
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+fn radical_inverse(base: u64, mut i: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut f = inv_base;
+    let mut v = 0.0;
+    while i > 0 {
+        v += (i % base) as f64 * f;
+        i /= base;
+        f *= inv_base;
+    }
+    v
+}
+
+/// Halton sequence over the samples of a pixel, rotated (Cranley-Patterson)
+/// by a random offset per pixel and dimension.
+/// Only the budgeted dimensions (next_dim) follow the sequence, each of them once per sample:
+/// the other random numbers come from an independent sampler.
+pub struct HaltonSampler {
+    padding: IndependentSampler,
+    seed: u64,
+    pixel: Point2<u32>,
+    index: u64,
+    /// Dimensions used by the current sample
+    used: u64,
+}
+
+impl HaltonSampler {
+    pub fn for_task(seed: Option<u64>, task: usize) -> HaltonSampler {
+        HaltonSampler {
+            padding: IndependentSampler::for_task(seed, task),
+            seed: seed.unwrap_or_else(rand::random),
+            pixel: Point2::new(0, 0),
+            index: 0,
+            used: 0,
+        }
+    }
+
+    fn rotation(&self, dim: u32) -> f64 {
+        let pixel = (u64::from(self.pixel.x) << 32) | u64::from(self.pixel.y);
+        let h = mix64(self.seed ^ mix64(pixel ^ mix64(u64::from(dim))));
+        (h >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn next(&mut self) -> f32 {
+        self.padding.next()
+    }
+    fn next2d(&mut self) -> Point2<f32> {
+        self.padding.next2d()
+    }
+
+    fn start_pixel_sample(&mut self, pixel: Point2<u32>, index: usize) {
+        self.pixel = pixel;
+        self.index = index as u64;
+        self.used = 0;
+    }
+    fn next_dim(&mut self, dim: u32) -> f32 {
+        if dim as usize >= PRIMES.len() || self.used & (1 << dim) != 0 {
+            return self.padding.next();
+        }
+        self.used |= 1 << dim;
+        let v = (radical_inverse(PRIMES[dim as usize], self.index) + self.rotation(dim)).fract();
+        // The rounding can give 1
+        (v as f32).min(1.0 - f32::EPSILON)
+    }
+    fn next2d_dim(&mut self, dim: u32) -> Point2<f32> {
+        let x = self.next_dim(dim);
+        let y = self.next_dim(dim + 1);
+        Point2::new(x, y)
+    }
+}
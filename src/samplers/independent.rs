@@ -17,6 +17,23 @@ impl Sampler for IndependentSampler {
     }
 }
 
+impl IndependentSampler {
+    pub fn from_seed(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            rnd: rand::rngs::StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Sampler of the i-th rendering task (image block, row, ...).
+    /// With a seed, the task samplers are reproducible whatever the number of threads.
+    pub fn for_task(seed: Option<u64>, task: usize) -> IndependentSampler {
+        match seed {
+            None => IndependentSampler::default(),
+            Some(seed) => IndependentSampler::from_seed(task_seed(seed, task)),
+        }
+    }
+}
+
 impl Default for IndependentSampler {
     fn default() -> IndependentSampler {
         IndependentSampler {
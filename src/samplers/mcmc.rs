@@ -4,9 +4,14 @@ use rand::prelude::*;
 
 pub trait Mutator: Send {
     fn mutate(&self, v: f32, r: f32) -> f32;
+    /// Perturbation with a step size scaled by the factor (adaptive step sizes)
+    fn mutate_scaled(&self, v: f32, r: f32, _scale: f32) -> f32 {
+        self.mutate(v, r)
+    }
 }
 
-struct MutatorKelemen {
+/// Exponential perturbation of Kelemen et al. (size between s1 and s2)
+pub struct MutatorKelemen {
     pub s1: f32,
     pub s2: f32,
     log_ratio: f32,
@@ -30,12 +35,17 @@ impl Default for MutatorKelemen {
 
 impl Mutator for MutatorKelemen {
     fn mutate(&self, v: f32, r: f32) -> f32 {
+        self.mutate_scaled(v, r, 1.0)
+    }
+
+    fn mutate_scaled(&self, v: f32, r: f32, scale: f32) -> f32 {
         let (add, r) = if r < 0.5 {
             (true, r * 2.0)
         } else {
             (false, 2.0 * (r - 0.5))
         };
-        let dv = self.s2 * (r * self.log_ratio).exp();
+        // Larger steps than half of the primary space are not useful (wrap around)
+        let dv = (scale * self.s2 * (r * self.log_ratio).exp()).min(0.5);
         assert!(dv < 1.0);
         let mut v = if add {
             let mut v = v + dv;
@@ -61,11 +71,127 @@ impl Mutator for MutatorKelemen {
     }
 }
 
+/// Number of values of a dimension before its spread is used
+const ADAPTATION_MIN_VALUES: usize = 64;
+/// Number of steps between the updates of the per-dimension scales
+const ADAPTATION_PERIOD: usize = 256;
+/// Bound of the global step scale (the steps are also bounded by half of the primary space)
+const ADAPTATION_MAX_GLOBAL_SCALE: f32 = 1024.0;
+/// Bound of the per-dimension step scales
+const ADAPTATION_MAX_SCALE: f32 = 16.0;
+
+/// Adaptive small step sizes (Andrieu and Thoms 2008, Haario et al. 2005): a global scale
+/// follows a Robbins-Monro recursion toward the target acceptance rate of the small steps,
+/// and each primary dimension is scaled by the spread of its values along the chain
+/// (circular statistics as the primary space wraps around), relatively to the other ones.
+/// The adaptation vanishes with the steps, so the chain keeps its stationary distribution.
+#[derive(Clone, Debug)]
+pub struct StepAdaptation {
+    pub target_acceptance: f32,
+    log_scale: f32,
+    nb_steps: usize,
+    /// Step of the next update of the per-dimension scales
+    next_update: usize,
+    /// Sums of cos(2 pi v) and sin(2 pi v) of the values of each dimension and their number
+    moments: Vec<(f64, f64, usize)>,
+    /// Relative scales of the dimensions
+    scales: Vec<f32>,
+}
+
+impl StepAdaptation {
+    pub fn new(target_acceptance: f32) -> Self {
+        assert!(target_acceptance > 0.0 && target_acceptance < 1.0);
+        StepAdaptation {
+            target_acceptance,
+            log_scale: 0.0,
+            nb_steps: 0,
+            next_update: ADAPTATION_PERIOD,
+            moments: vec![],
+            scales: vec![],
+        }
+    }
+
+    /// Step size factor of a primary dimension
+    pub fn scale(&self, i: usize) -> f32 {
+        self.log_scale.exp() * self.scales.get(i).copied().unwrap_or(1.0)
+    }
+
+    /// Update with the values of the current state of the chain, and for a small step,
+    /// with its acceptance probability
+    fn update(&mut self, values: &[SampleReplayValue], accept_prob: Option<f32>) {
+        if self.moments.len() < values.len() {
+            self.moments.resize(values.len(), (0.0, 0.0, 0));
+        }
+        for (m, v) in self.moments.iter_mut().zip(values) {
+            let angle = 2.0 * std::f64::consts::PI * f64::from(v.value);
+            m.0 += angle.cos();
+            m.1 += angle.sin();
+            m.2 += 1;
+        }
+        self.nb_steps += 1;
+
+        if let Some(accept_prob) = accept_prob {
+            let gain = (self.nb_steps as f32).powf(-0.6);
+            let max = ADAPTATION_MAX_GLOBAL_SCALE.ln();
+            self.log_scale =
+                (self.log_scale + gain * (accept_prob - self.target_acceptance)).clamp(-max, max);
+        }
+        if self.nb_steps == self.next_update {
+            self.next_update += ADAPTATION_PERIOD;
+            self.update_scales();
+        }
+    }
+
+    /// Standard deviations of the dimensions (wrapped normal approximation),
+    /// relative to their geometric mean
+    fn update_scales(&mut self) {
+        let spreads = self
+            .moments
+            .iter()
+            .map(|&(c, s, n)| {
+                if n < ADAPTATION_MIN_VALUES {
+                    return None;
+                }
+                // Mean resultant length: 1 for a constant, 0 for a uniform distribution
+                let r = ((c * c + s * s).sqrt() / n as f64).clamp(1e-3, 1.0);
+                Some(((-2.0 * r.ln()).sqrt() / (2.0 * std::f64::consts::PI)).max(1e-4))
+            })
+            .collect::<Vec<_>>();
+        let (sum_log, nb) = spreads
+            .iter()
+            .flatten()
+            .fold((0.0, 0), |(sum, nb), s| (sum + s.ln(), nb + 1));
+        if nb == 0 {
+            return;
+        }
+        let mean = (sum_log / f64::from(nb)).exp();
+        self.scales = spreads
+            .iter()
+            .map(|s| match s {
+                Some(s) => {
+                    ((s / mean) as f32).clamp(1.0 / ADAPTATION_MAX_SCALE, ADAPTATION_MAX_SCALE)
+                }
+                None => 1.0,
+            })
+            .collect();
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 struct SampleReplayValue {
     pub value: f32,
     pub modify: usize,
 }
 
+/// Primary sample values and mutation times of an accepted sampler
+/// (saved inside the MLT checkpoints, the random number generator is not kept)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplayState {
+    values: Vec<SampleReplayValue>,
+    time: usize,
+    time_large: usize,
+}
+
 pub struct IndependentSamplerReplay {
     rnd: StdRng,
     values: Vec<SampleReplayValue>,
@@ -74,7 +200,11 @@ pub struct IndependentSamplerReplay {
     time: usize,
     time_large: usize,
     indice: usize,
+    /// Number of values used by the last sample (accepted or rejected)
+    last_indice: usize,
     pub large_step: bool,
+    /// Adaptive small step sizes (None: the step sizes of the mutator)
+    adaptation: Option<StepAdaptation>,
 }
 
 impl Sampler for IndependentSamplerReplay {
@@ -102,6 +232,7 @@ impl SamplerMCMC for IndependentSamplerReplay {
             self.time_large = self.time;
         }
         self.time += 1;
+        self.last_indice = self.indice;
         self.indice = 0;
     }
 
@@ -111,6 +242,7 @@ impl SamplerMCMC for IndependentSamplerReplay {
         }
         self.backup.clear();
         self.time += 1;
+        self.last_indice = self.indice;
         self.indice = 0;
     }
 }
@@ -125,19 +257,85 @@ impl Default for IndependentSamplerReplay {
             time: 0,
             time_large: 0,
             indice: 0,
+            last_indice: 0,
             large_step: false,
+            adaptation: None,
         }
     }
 }
 
 //FIXME: Make not representable a sampler that are not accept
 impl IndependentSamplerReplay {
+    /// Sampler of the i-th chain, random without a seed (see IndependentSampler::for_task)
+    pub fn for_task(seed: Option<u64>, task: usize) -> Self {
+        let sampler = IndependentSamplerReplay::default();
+        match seed {
+            None => sampler,
+            Some(seed) => IndependentSamplerReplay {
+                rnd: rand::rngs::StdRng::seed_from_u64(task_seed(seed, task)),
+                ..sampler
+            },
+        }
+    }
+
     // Constructor to change the mutator technique
     pub fn mutator(mut self, mutator: Box<dyn Mutator>) -> Self {
         self.mutator = mutator;
         self
     }
 
+    // Constructor to adapt the small step sizes toward a target acceptance rate
+    pub fn adaptive(mut self, target_acceptance: f32) -> Self {
+        self.adaptation = Some(StepAdaptation::new(target_acceptance));
+        self
+    }
+
+    pub fn adaptation(&self) -> Option<&StepAdaptation> {
+        self.adaptation.as_ref()
+    }
+
+    /// Update the adaptive step sizes after a sample (accepted or rejected)
+    /// with its acceptance probability (ignored for the large steps)
+    pub fn adapt(&mut self, accept_prob: f32) {
+        assert!(self.backup.is_empty() && self.indice == 0);
+        if let Some(adaptation) = &mut self.adaptation {
+            let accept_prob = if self.large_step {
+                None
+            } else {
+                Some(accept_prob)
+            };
+            adaptation.update(&self.values[..self.last_indice], accept_prob);
+        }
+    }
+
+    /// Current state, only valid between two samples (after accept or reject)
+    pub fn state(&self) -> ReplayState {
+        assert!(self.backup.is_empty() && self.indice == 0);
+        ReplayState {
+            values: self.values.clone(),
+            time: self.time,
+            time_large: self.time_large,
+        }
+    }
+    /// Exchange the states of two chains (replica exchange),
+    /// the random number generators and the mutators stay with their chain
+    pub fn swap_state(&mut self, other: &mut IndependentSamplerReplay) {
+        assert!(self.backup.is_empty() && self.indice == 0);
+        assert!(other.backup.is_empty() && other.indice == 0);
+        std::mem::swap(&mut self.values, &mut other.values);
+        std::mem::swap(&mut self.time, &mut other.time);
+        std::mem::swap(&mut self.time_large, &mut other.time_large);
+    }
+    // Constructor to continue a chain from a saved state
+    pub fn restore(mut self, state: ReplayState) -> Self {
+        self.values = state.values;
+        self.time = state.time;
+        self.time_large = state.time_large;
+        self.backup.clear();
+        self.indice = 0;
+        self
+    }
+
     fn sample(&mut self, i: usize) -> f32 {
         while i >= self.values.len() {
             let value = self.rand();
@@ -157,15 +355,20 @@ impl IndependentSamplerReplay {
                     self.values[i].modify = self.time_large;
                 }
 
+                let scale = self.adaptation.as_ref().map_or(1.0, |a| a.scale(i));
                 while self.values[i].modify + 1 < self.time {
                     let random = self.rand();
-                    self.values[i].value = self.mutator.mutate(self.values[i].value, random);
+                    self.values[i].value =
+                        self.mutator
+                            .mutate_scaled(self.values[i].value, random, scale);
                     self.values[i].modify += 1;
                 }
 
                 self.backup.push((i, self.values[i].value));
                 let random = self.rand();
-                self.values[i].value = self.mutator.mutate(self.values[i].value, random);
+                self.values[i].value =
+                    self.mutator
+                        .mutate_scaled(self.values[i].value, random, scale);
                 self.values[i].modify += 1;
             }
         }
@@ -3,6 +3,80 @@ use cgmath::Point2;
 pub trait Sampler: Send {
     fn next(&mut self) -> f32;
     fn next2d(&mut self) -> Point2<f32>;
+
+    /// Start a new sample of the pixel (the QMC samplers restart their sequence)
+    fn start_pixel_sample(&mut self, _pixel: Point2<u32>, _index: usize) {}
+    /// Random number of a budgeted dimension (see dimensions).
+    /// The samplers without dimensions return the next random number.
+    fn next_dim(&mut self, _dim: u32) -> f32 {
+        self.next()
+    }
+    fn next2d_dim(&mut self, _dim: u32) -> Point2<f32> {
+        self.next2d()
+    }
+}
+
+/// Seed of the sampler of the i-th rendering task, so the tasks are reproducible
+/// whatever the number of threads
+pub fn task_seed(seed: u64, task: usize) -> u64 {
+    seed.wrapping_add((task as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+/// Allocation of the dimensions of the QMC samplers to the events of a path,
+/// so the same events always use the same (well stratified) dimensions
+/// whatever the number of random numbers used by the other events.
+/// The random numbers outside of this budget (russian roulette, the dimensions
+/// already used by the sample, ...) are padded with scrambled random numbers.
+pub mod dimensions {
+    /// Position inside the pixel (0-1)
+    pub const CAMERA: u32 = 0;
+    /// Position on the lens (2-3)
+    pub const LENS: u32 = 2;
+    /// Number of dimensions of each bounce
+    pub const BOUNCE: u32 = 5;
+
+    /// Emitter selection at the bounce (4), the point on the emitter uses the two next ones
+    pub fn light_selection(bounce: u32) -> u32 {
+        4 + BOUNCE * bounce
+    }
+    pub fn light_position(bounce: u32) -> u32 {
+        5 + BOUNCE * bounce
+    }
+    /// BSDF (or phase function) direction at the bounce (7-8)
+    pub fn bsdf(bounce: u32) -> u32 {
+        7 + BOUNCE * bounce
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum SamplerType {
+    #[default]
+    Independent,
+    /// Halton sequence with a random rotation per pixel
+    Halton,
+}
+
+impl std::str::FromStr for SamplerType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerType::Independent),
+            "halton" => Ok(SamplerType::Halton),
+            _ => Err(format!("unknown sampler: {} (independent, halton)", s)),
+        }
+    }
+}
+
+impl SamplerType {
+    /// Sampler of the i-th rendering task (see IndependentSampler::for_task)
+    pub fn for_task(self, seed: Option<u64>, task: usize) -> Box<dyn Sampler> {
+        match self {
+            SamplerType::Independent => {
+                Box::new(independent::IndependentSampler::for_task(seed, task))
+            }
+            SamplerType::Halton => Box::new(halton::HaltonSampler::for_task(seed, task)),
+        }
+    }
 }
 
 pub trait SamplerMCMC {
@@ -10,5 +84,6 @@ pub trait SamplerMCMC {
     fn reject(&mut self);
 }
 
+pub mod halton;
 pub mod independent;
 pub mod mcmc;
@@ -1,30 +1,88 @@
+use crate::accel::{Triangle, TriangleBVH};
 use crate::camera::Camera;
 use crate::emitter::*;
+use crate::error::{Error, Result};
 use crate::geometry;
-use crate::math::Distribution1DConstruct;
 use crate::math::Frame;
+use crate::samplers::SamplerType;
 use crate::structure::*;
 use crate::volume;
 use cgmath::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Relative shortening of the shadow rays (avoids to hit the surface of p1)
+const SHADOW_EPSILON: f32 = 1e-4;
 
 pub trait Acceleration: Sync + Send {
     fn trace(&self, ray: &Ray) -> Option<Intersection>;
+    /// The points on a surface need to be offset by the caller
+    /// (see Intersection::offset_toward)
     fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool;
+
+    /// Is there an intersection between ray.tnear and ray.tfar
+    /// (cheaper than trace for the implementations stopping at the first one)
+    fn intersect_any(&self, ray: &Ray) -> bool {
+        self.trace(ray).is_some()
+    }
+
+    /// All the intersections along the ray by increasing distance, e.g. for the
+    /// transparent shadows or the deep images. The search continues slightly
+    /// after each hit (relative SHADOW_EPSILON), so the closer hits are merged.
+    fn intersect_all_along_ray(&self, ray: &Ray) -> Vec<Intersection<'_>> {
+        let mut hits = vec![];
+        let mut ray = *ray;
+        while let Some(its) = self.trace(&ray) {
+            ray.tnear = its.dist + its.dist.abs().max(1.0) * SHADOW_EPSILON;
+            hits.push(its);
+        }
+        hits
+    }
+
+    /// Closest surface point to p, closer than max_distance
+    /// (e.g. probes of the BSSRDF sampling). None if not supported.
+    fn closest_point_query(
+        &self,
+        _p: &Point3<f32>,
+        _max_distance: f32,
+    ) -> Option<SurfacePoint<'_>> {
+        None
+    }
+}
+
+/// Point of a mesh found by Acceleration::closest_point_query
+#[derive(Clone)]
+pub struct SurfacePoint<'a> {
+    pub p: Point3<f32>,
+    /// Distance to the query point
+    pub dist: f32,
+    /// Geometric normal of the triangle (orientation of its vertices)
+    pub n_g: Vector3<f32>,
+    /// Index of the triangle inside the mesh
+    pub face: usize,
+    /// Barycentric coordinates inside the triangle (weights of its second and third vertices)
+    pub face_uv: Vector2<f32>,
+    pub mesh: &'a geometry::Mesh,
 }
 
+/// Only the meshes are borrowed: the other fields of the scene
+/// (i.e. the camera) can change while the acceleration structure is alive
 pub struct EmbreeAcceleration<'a, 'scene> {
-    pub scene: &'a Scene,
+    pub meshes: &'a [geometry::Mesh],
     pub rtscene: embree_rs::CommittedScene<'scene>,
+    /// Native BVH of the point queries, built by the first one
+    triangles: OnceLock<TriangleBVH>,
 }
 
 impl<'a, 'scene> EmbreeAcceleration<'a, 'scene> {
     pub fn new(
-        scene: &'a Scene,
+        meshes: &'a [geometry::Mesh],
         embree_scene: &'scene embree_rs::Scene,
     ) -> EmbreeAcceleration<'a, 'scene> {
         EmbreeAcceleration {
-            scene,
+            meshes,
             rtscene: embree_scene.commit(),
+            triangles: OnceLock::new(),
         }
     }
 }
@@ -41,7 +99,7 @@ impl<'a, 'scene> Acceleration for EmbreeAcceleration<'a, 'scene> {
         let mut ray_hit = embree_rs::RayHit::new(embree_ray);
         self.rtscene.intersect(&mut intersection_ctx, &mut ray_hit);
         if ray_hit.hit.hit() {
-            let mesh = &self.scene.meshes[ray_hit.hit.geomID as usize];
+            let mesh = &self.meshes[ray_hit.hit.geomID as usize];
             let index = mesh.indices[ray_hit.hit.primID as usize];
 
             // Retrive the mesh
@@ -92,21 +150,35 @@ impl<'a, 'scene> Acceleration for EmbreeAcceleration<'a, 'scene> {
                 None
             };
 
+            // The position is computed from the barycentric coordinates:
+            // its error is bounded independently of the ray origin and distance
+            let b = [
+                1.0 - ray_hit.hit.u - ray_hit.hit.v,
+                ray_hit.hit.u,
+                ray_hit.hit.v,
+            ];
+            let v = [
+                mesh.vertices[index.x],
+                mesh.vertices[index.y],
+                mesh.vertices[index.z],
+            ];
+            let p = v[0] * b[0] + v[1] * b[1] + v[2] * b[2];
+
             let frame = Frame::new(n_s);
             let wi = frame.to_local(-ray.d);
             Some(Intersection {
                 dist: ray_hit.ray.tfar,
                 n_g,
                 n_s,
-                p: Point3::new(
-                    ray_hit.ray.org_x + ray_hit.ray.tfar * ray_hit.ray.dir_x,
-                    ray_hit.ray.org_y + ray_hit.ray.tfar * ray_hit.ray.dir_y,
-                    ray_hit.ray.org_z + ray_hit.ray.tfar * ray_hit.ray.dir_z,
-                ),
+                p: Point3::from_vec(p),
+                p_error: barycentric_error(b, v),
                 uv,
+                face: ray_hit.hit.primID as usize,
+                face_uv: Vector2::new(ray_hit.hit.u, ray_hit.hit.v),
                 mesh,
                 frame,
                 wi,
+                uv_width: 0.0,
             })
         } else {
             None
@@ -117,25 +189,259 @@ impl<'a, 'scene> Acceleration for EmbreeAcceleration<'a, 'scene> {
         let mut d = p1 - p0;
         let length = d.magnitude();
         d /= length;
-        let mut embree_ray =
-            embree_rs::Ray::segment(Vector3::new(p0.x, p0.y, p0.z), d, 0.00001, length - 0.00001);
+        let mut embree_ray = embree_rs::Ray::segment(
+            Vector3::new(p0.x, p0.y, p0.z),
+            d,
+            0.0,
+            length * (1.0 - SHADOW_EPSILON),
+        );
         self.rtscene
             .occluded(&mut intersection_ctx, &mut embree_ray);
         embree_ray.tfar != std::f32::NEG_INFINITY
     }
+    fn intersect_any(&self, ray: &Ray) -> bool {
+        let mut intersection_ctx = embree_rs::IntersectContext::coherent();
+        let mut embree_ray = embree_rs::Ray::segment(
+            Vector3::new(ray.o.x, ray.o.y, ray.o.z),
+            ray.d,
+            ray.tnear,
+            ray.tfar,
+        );
+        self.rtscene
+            .occluded(&mut intersection_ctx, &mut embree_ray);
+        embree_ray.tfar == f32::NEG_INFINITY
+    }
+    fn closest_point_query(
+        &self,
+        p: &Point3<f32>,
+        max_distance: f32,
+    ) -> Option<SurfacePoint<'_>> {
+        let bvh = self.triangles.get_or_init(|| {
+            info!("Build the BVH of the point queries...");
+            TriangleBVH::create(Triangle::from_meshes(self.meshes))
+        });
+        let (i, q) = bvh.closest_point(p, max_distance)?;
+        let triangle = &bvh.elements[i];
+        let [a, b, c] = triangle.vertices;
+        let n_g = (b - a).cross(c - a);
+        let n_g = if n_g.magnitude2() > 0.0 { n_g.normalize() } else { n_g };
+        Some(SurfacePoint {
+            p: q,
+            dist: (q - p).magnitude(),
+            n_g,
+            face: triangle.face,
+            face_uv: triangle.closest(p).1,
+            mesh: &self.meshes[triangle.mesh],
+        })
+    }
+}
+
+/// What the camera rays see when they escape the scene
+#[derive(Default)]
+pub enum Film {
+    /// The environment (black without environment)
+    #[default]
+    Opaque,
+    /// Nothing: the output has an alpha channel (0 where the camera rays escape)
+    Transparent,
+    /// Background plate only visible from the camera (stretched to the image).
+    /// The environment still lights the scene.
+    Background(Bitmap),
+}
+
+/// Handling of the NaN and infinite contributions (see NonFiniteCheck)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NonFiniteMode {
+    /// Log them and keep them in the image
+    Log,
+    /// Log them and discard them (quarantine)
+    Discard,
+}
+impl std::str::FromStr for NonFiniteMode {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(NonFiniteMode::Log),
+            "discard" => Ok(NonFiniteMode::Discard),
+            _ => Err(format!("unknown NaN check: {} (log, discard)", s)),
+        }
+    }
+}
+
+/// Number of non-finite contributions logged with their origin
+const MAX_NON_FINITE_LOGS: usize = 16;
+
+/// Detection of the NaN and infinite contributions when they are accumulated (debugging)
+#[derive(Default)]
+pub struct NonFiniteCheck {
+    /// No detection if None
+    pub mode: Option<NonFiniteMode>,
+    count: AtomicUsize,
+}
+impl NonFiniteCheck {
+    /// Contribution to accumulate. The non-finite ones are counted, the first ones are logged
+    /// with their origin (integrator, pixel, ...) and they are discarded in the Discard mode.
+    pub fn check(&self, c: Color, origin: impl FnOnce() -> String) -> Color {
+        let mode = match self.mode {
+            Some(mode) if !c.is_finite() => mode,
+            _ => return c,
+        };
+        let nb = self.count.fetch_add(1, Ordering::Relaxed);
+        if nb < MAX_NON_FINITE_LOGS {
+            warn!("Non-finite contribution {:?}: {}", c, origin());
+        } else if nb == MAX_NON_FINITE_LOGS {
+            warn!("Too many non-finite contributions, the next ones are not logged");
+        }
+        match mode {
+            NonFiniteMode::Log => c,
+            NonFiniteMode::Discard => Color::zero(),
+        }
+    }
+
+    /// Number of non-finite contributions detected
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Log the number of non-finite contributions and of colors scaled by non-finite values
+    /// (set to zero, see structure::non_finite_scales)
+    pub fn report(&self) {
+        if let Some(mode) = self.mode {
+            let action = match mode {
+                NonFiniteMode::Log => "kept",
+                NonFiniteMode::Discard => "discarded",
+            };
+            info!(
+                "Non-finite contributions: {} ({}), colors scaled by a non-finite value: {}",
+                self.count(),
+                action,
+                non_finite_scales()
+            );
+        }
+    }
+}
+
+/// Pinning of the rendering threads to the CPU cores (see integrators::generate_pool)
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum ThreadPinning {
+    /// The threads are scheduled by the operating system
+    #[default]
+    None,
+    /// The thread i runs on the core i (modulo the number of cores)
+    Cores,
+    /// The thread i runs on the i-th core of the list (modulo its length)
+    List(Vec<usize>),
+}
+impl std::str::FromStr for ThreadPinning {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ThreadPinning::None),
+            "cores" => Ok(ThreadPinning::Cores),
+            _ => s
+                .split(',')
+                .map(|v| v.trim().parse::<usize>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map(ThreadPinning::List)
+                .map_err(|_| {
+                    format!(
+                        "unknown thread pinning: {} (none, cores or a list of core ids)",
+                        s
+                    )
+                }),
+        }
+    }
+}
+impl ThreadPinning {
+    /// Core of the thread of the pool (None if the threads are not pinned)
+    pub fn core(&self, thread: usize) -> Option<usize> {
+        match self {
+            ThreadPinning::None => None,
+            ThreadPinning::Cores => {
+                let cores = core_affinity::get_core_ids().unwrap_or_default();
+                cores.get(thread % cores.len().max(1)).map(|c| c.id)
+            }
+            ThreadPinning::List(ids) => Some(ids[thread % ids.len()]),
+        }
+    }
+
+    /// Pin the current thread (index of the thread inside its pool)
+    pub fn pin(&self, thread: usize) {
+        if let Some(id) = self.core(thread) {
+            if !core_affinity::set_for_current(core_affinity::CoreId { id }) {
+                warn!("Impossible to pin the thread {} to the core {}", thread, id);
+            }
+        }
+    }
 }
 
 /// Scene representation
 pub struct Scene {
     /// Main camera
     pub camera: Camera,
+    /// Named cameras of the scene file (empty if the scene has a single camera)
+    pub cameras: Vec<(String, Camera)>,
     pub nb_samples: usize,
     pub nb_threads: Option<usize>,
+    /// Pinning of the threads of the rendering pools
+    pub thread_pinning: ThreadPinning,
+    /// Size of the rendering blocks (automatic if not set, see integrators::compute_mc)
+    pub tile_size: Option<u32>,
     pub output_img_path: String,
+    /// Output per-pixel variance and sample count buffers
+    pub output_variance: bool,
+    /// Output the surface and volume (in-scattered) radiance buffers
+    pub output_volume: bool,
+    /// Output the object and material ID buffers with this pixel filter
+    pub output_ids: Option<crate::integrators::ids::PixelFilter>,
+    /// Radiance buffers divided by the albedo of the first intersection ("albedo" buffer)
+    pub demodulate: bool,
+    /// Seed of the samplers (random if not set)
+    pub seed: Option<u64>,
+    /// Current pass of a progressive rendering, scrambled with the seed
+    /// so the passes do not repeat the same random numbers (see pass_seed)
+    pub pass: AtomicUsize,
+    /// All the passes use the random numbers of the first one (debugging)
+    pub lock_passes: bool,
+    /// Bitwise identical images whatever the number of threads: seed 0 by default
+    /// and the splatting integrators sum their buffers in a fixed order (see splat_jobs)
+    pub deterministic: bool,
+    /// Sampler of the Monte Carlo integrators (compute_mc)
+    pub sampler: SamplerType,
+    /// Strategy to select the emitter to sample
+    pub emitter_selection: EmitterSelection,
+    /// Learn the sampling of the environment from its contributions with this number
+    /// of camera rays per pixel before the rendering (see the env_guiding module)
+    pub env_guiding: Option<usize>,
+    pub film: Film,
+    /// Robust pixel estimates instead of the mean of the samples (outlier rejection)
+    pub sample_rejection: Option<SampleRejection>,
+    /// Detection of the NaN and infinite contributions
+    pub non_finite: NonFiniteCheck,
+    /// Paths traced for one pixel (path debugger)
+    pub path_debugger: crate::paths::debugger::PathDebugger,
     // Geometry information
     pub meshes: Vec<geometry::Mesh>,
     pub emitter_environment: Option<EnvironmentLight>,
     pub volume: Option<volume::HomogenousVolume>,
+    /// Emitter selection structures kept between the renderings (see emitters_sampler)
+    pub emitters_cache: Mutex<Option<EmittersCache>>,
+    /// Automatic tile size chosen by the warm-up of the first pass,
+    /// reused by the next passes of the same rendering (see integrators::compute_mc)
+    pub auto_tile_size: Mutex<Option<u32>>,
+}
+
+/// Mesh ids of the emitters and their selection structures
+pub struct EmittersCache {
+    ids: Vec<usize>,
+    data: Arc<EmitterSelectionData>,
+}
+
+/// Ids of the meshes that emit light
+fn emitter_ids(meshes: &[geometry::Mesh]) -> Vec<usize> {
+    (0..meshes.len())
+        .filter(|&i| !meshes[i].emission.is_zero())
+        .collect()
 }
 
 impl Scene {
@@ -147,33 +453,289 @@ impl Scene {
         self.nb_threads = Some(n);
         self
     }
+    pub fn thread_pinning(mut self, p: ThreadPinning) -> Self {
+        self.thread_pinning = p;
+        self
+    }
+    pub fn tile_size(mut self, size: Option<u32>) -> Self {
+        self.tile_size = size;
+        self
+    }
     pub fn nb_samples(mut self, n: usize) -> Self {
         self.nb_samples = n;
         self
     }
+    pub fn output_variance(mut self, v: bool) -> Self {
+        self.output_variance = v;
+        self
+    }
+    pub fn output_volume(mut self, v: bool) -> Self {
+        self.output_volume = v;
+        self
+    }
+    pub fn output_ids(mut self, filter: Option<crate::integrators::ids::PixelFilter>) -> Self {
+        self.output_ids = filter;
+        self
+    }
+    pub fn demodulate(mut self, v: bool) -> Self {
+        self.demodulate = v;
+        self
+    }
+    pub fn seed(mut self, s: u64) -> Self {
+        self.seed = Some(s);
+        self
+    }
+    pub fn deterministic(mut self, d: bool) -> Self {
+        self.deterministic = d;
+        self
+    }
+    pub fn lock_passes(mut self, l: bool) -> Self {
+        self.lock_passes = l;
+        self
+    }
+    pub fn sampler(mut self, s: SamplerType) -> Self {
+        self.sampler = s;
+        self
+    }
+    pub fn emitter_selection(mut self, s: EmitterSelection) -> Self {
+        self.emitter_selection = s;
+        self
+    }
+    pub fn env_guiding(mut self, nb_samples: Option<usize>) -> Self {
+        self.env_guiding = nb_samples;
+        self
+    }
+    pub fn film(mut self, f: Film) -> Self {
+        self.film = f;
+        self
+    }
+    pub fn sample_rejection(mut self, r: Option<SampleRejection>) -> Self {
+        self.sample_rejection = r;
+        self
+    }
+    pub fn non_finite(mut self, mode: Option<NonFiniteMode>) -> Self {
+        self.non_finite.mode = mode;
+        self
+    }
+    pub fn debug_pixel(mut self, pixel: Option<Point2<u32>>) -> Self {
+        self.path_debugger.pixel = pixel;
+        self
+    }
+
+    /// Start a new pass of a progressive rendering
+    pub fn set_pass(&self, pass: usize) {
+        self.pass.store(pass, Ordering::Relaxed);
+    }
+
+    /// Seed of the samplers for the current pass (the task samplers are derived from it).
+    /// The first pass uses the seed unchanged, the next ones hash it with the pass index.
+    /// Without a seed, the passes are random, unless they are locked or deterministic (seed 0).
+    pub fn pass_seed(&self) -> Option<u64> {
+        let seed = match self.seed {
+            None if self.deterministic => Some(0),
+            seed => seed,
+        };
+        if self.lock_passes {
+            return Some(seed.unwrap_or(0));
+        }
+        let pass = self.pass.load(Ordering::Relaxed) as u64;
+        seed.map(|seed| match pass {
+            0 => seed,
+            _ => crate::tools::mix64(seed ^ crate::tools::mix64(pass)),
+        })
+    }
+
+    /// Named camera of the scene file
+    pub fn find_camera(&self, name: &str) -> Result<&Camera> {
+        self.cameras
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, c)| c)
+            .ok_or_else(|| {
+                let names = self.cameras.iter().map(|(n, _)| n.as_str());
+                Error::InvalidData(format!(
+                    "unknown camera {} (available: {})",
+                    name,
+                    names.collect::<Vec<_>>().join(", ")
+                ))
+            })
+    }
 
+    /// Main and named cameras (to change their image size)
+    pub fn cameras_mut(&mut self) -> impl Iterator<Item = &mut Camera> {
+        std::iter::once(&mut self.camera).chain(self.cameras.iter_mut().map(|(_, c)| c))
+    }
+
+    /// Emission mesh sampler. The selection structures are built at the first call
+    /// and reused by the next ones: the changes of the emitters have to be notified
+    /// (see emitter_changed) to only rebuild the parts they invalidate.
     pub fn emitters_sampler(&self) -> EmitterSampler {
-        // Append emission mesh to the emitter list
-        let mut emitters: Vec<&dyn Emitter> = vec![];
-        for e in &self.meshes {
-            if !e.emission.is_zero() {
-                emitters.push(e)
+        self.emitters_sampler_with(self.emitter_selection)
+    }
+
+    /// Same as emitters_sampler with another selection than the one of the scene
+    /// (integrators needing a specific structure, e.g. the light BVH of the lightcuts)
+    pub fn emitters_sampler_with(&self, selection: EmitterSelection) -> EmitterSampler {
+        let ids = emitter_ids(&self.meshes);
+        let emitters = ids
+            .iter()
+            .map(|&i| &self.meshes[i] as &dyn Emitter)
+            .collect::<Vec<_>>();
+        let mut cache = self.emitters_cache.lock().unwrap();
+        let reuse = match cache.as_ref() {
+            Some(c) => c.ids == ids && c.data.requested_selection == selection,
+            None => false,
+        };
+        if !reuse {
+            let mut data = EmitterSelectionData::new(&emitters, selection);
+            // The learned environment sampling does not depend on the selection
+            if let Some(c) = cache.as_ref().filter(|c| c.ids == ids) {
+                data.environment_guide = c.data.environment_guide.clone();
             }
+            *cache = Some(EmittersCache {
+                ids,
+                data: Arc::new(data),
+            });
+        }
+        let cache = cache.as_mut().unwrap();
+        if cache.data.is_outdated() {
+            Arc::make_mut(&mut cache.data).update(&emitters);
         }
-        // Construct the CDF for all the emitters
-        let emitters_cdf = {
-            let mut cdf_construct = Distribution1DConstruct::new(emitters.len());
-            emitters
-                .iter()
-                .map(|e| e.flux())
-                .for_each(|f| cdf_construct.add(f.channel_max()));
-            cdf_construct.normalize()
-        };
-
         EmitterSampler {
             emitters,
-            emitters_cdf,
+            data: cache.data.clone(),
+            environment: self.emitter_environment.as_ref(),
+        }
+    }
+
+    /// Build the emitter selection structures needing the acceleration data structure
+    /// (light grid over the meshes, learned environment sampling), before a rendering.
+    /// They are kept with the other ones.
+    pub fn prepare_emitters(&self, accel: &dyn Acceleration) {
+        if self.emitter_selection == EmitterSelection::LightGrid {
+            self.prepare_light_grid(accel);
+        }
+        self.prepare_environment_guide(accel);
+    }
+
+    fn prepare_light_grid(&self, accel: &dyn Acceleration) {
+        // Up to date selection structures (the sampler is dropped before the changes)
+        let EmitterSampler { emitters, .. } = self.emitters_sampler();
+        let mut cache = self.emitters_cache.lock().unwrap();
+        let cache = cache.as_mut().unwrap();
+        if cache.data.light_grid.is_some() || emitters.is_empty() {
+            return;
+        }
+        info!("Build the light grid...");
+        let aabb = self
+            .meshes
+            .iter()
+            .fold(AABB::default(), |aabb, m| aabb.union_aabb(&m.aabb()));
+        let grid = crate::light_grid::LightGrid::new(
+            accel,
+            &emitters,
+            &aabb,
+            crate::light_grid::GRID_RESOLUTION,
+            crate::light_grid::GRID_RAYS,
+        );
+        Arc::make_mut(&mut cache.data).light_grid = Some(Arc::new(grid));
+    }
+
+    /// Learned until the meshes are added or removed (the occlusions changed)
+    fn prepare_environment_guide(&self, accel: &dyn Acceleration) {
+        // Up to date selection structures (the guide is kept with them)
+        self.emitters_sampler();
+        let mut cache = self.emitters_cache.lock().unwrap();
+        let cache = cache.as_mut().unwrap();
+        let (env, nb_samples) = match (&self.emitter_environment, self.env_guiding) {
+            (Some(env), Some(n)) if n > 0 => (env, n),
+            _ => {
+                if cache.data.environment_guide.is_some() {
+                    Arc::make_mut(&mut cache.data).environment_guide = None;
+                }
+                return;
+            }
+        };
+        if cache.data.environment_guide.is_some() {
+            return;
+        }
+        info!("Learn the environment sampling...");
+        match crate::env_guiding::EnvironmentGuide::learn(accel, self, env, nb_samples) {
+            Some(guide) => Arc::make_mut(&mut cache.data).environment_guide = Some(Arc::new(guide)),
+            None => warn!("No contribution of the environment, keep its sampling"),
+        }
+    }
+
+    /// Notify that the mesh changed after a direct modification (emission or geometry,
+    /// the cdf of the mesh has to be up to date). The emitter selection structures
+    /// are updated by the next emitters_sampler call.
+    pub fn emitter_changed(&mut self, id: usize) {
+        let cache = match self.emitters_cache.get_mut().unwrap() {
+            Some(c) => c,
+            None => return,
+        };
+        let ids = emitter_ids(&self.meshes);
+        let data = Arc::make_mut(&mut cache.data);
+        if ids != cache.ids {
+            // The mesh started or stopped to emit light
+            data.emitters_changed();
+            cache.ids = ids;
+        } else if let Some(pos) = ids.iter().position(|&i| i == id) {
+            data.emitter_changed(pos);
+        }
+    }
+
+    pub fn set_emission(&mut self, id: usize, emission: Color) {
+        self.meshes[id].emission = emission;
+        self.emitter_changed(id);
+    }
+
+    /// Sphere bounding the meshes, used by the distant emitters (environment)
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        geometry::bounding_sphere(&self.meshes)
+    }
+
+    /// Triangles, memory, emitter flux and acceleration structures of the scene
+    /// (builds the acceleration structures to measure them)
+    pub fn report(&self) -> crate::report::SceneReport {
+        crate::report::SceneReport::new(self)
+    }
+
+    /// Update the bounding sphere of the environment after a change of the meshes
+    fn world_changed(&mut self) {
+        let world = self.bounding_sphere();
+        if let Some(env) = &mut self.emitter_environment {
+            env.world = world;
+        }
+        if let Some(cache) = self.emitters_cache.get_mut().unwrap() {
+            if cache.data.environment_guide.is_some() {
+                Arc::make_mut(&mut cache.data).environment_guide = None;
+            }
+        }
+    }
+
+    /// Add a mesh (the acceleration data structure has to be rebuilt), returns its id
+    pub fn add_mesh(&mut self, mesh: geometry::Mesh) -> usize {
+        self.meshes.push(mesh);
+        let id = self.meshes.len() - 1;
+        self.emitter_changed(id);
+        self.world_changed();
+        id
+    }
+
+    /// Remove a mesh (the acceleration data structure has to be rebuilt),
+    /// the ids of the following meshes are shifted
+    pub fn remove_mesh(&mut self, id: usize) -> geometry::Mesh {
+        let mesh = self.meshes.remove(id);
+        if let Some(cache) = self.emitters_cache.get_mut().unwrap() {
+            if cache.ids.contains(&id) {
+                Arc::make_mut(&mut cache.data).emitters_changed();
+            }
+            cache.ids.retain(|&i| i != id);
+            cache.ids.iter_mut().filter(|i| **i > id).for_each(|i| *i -= 1);
         }
+        self.world_changed();
+        mesh
     }
 
     pub fn enviroment_luminance(&self, d: Vector3<f32>) -> Color {
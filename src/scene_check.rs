@@ -0,0 +1,377 @@
+use crate::bsdfs::parse_bsdf;
+use crate::error::{Error, Result};
+use crate::geometry::{Mesh, UVProjection};
+use crate::scene_loader::{parse_environment, SceneLoaderManager};
+use crate::structure::Color;
+use crate::texture::udim::UDIM_TOKEN;
+use crate::tools::extension;
+use cgmath::*;
+use std::path::Path;
+
+/// Triangles with a smaller area are reported as degenerated
+const DEGENERATE_AREA: f32 = 1e-12;
+
+/// Check a scene description without rendering it.
+/// Returns the list of problems found (empty if the scene looks fine).
+/// An error is only returned if the scene file itself cannot be read.
+pub fn check_scene(filename: &str) -> Result<Vec<String>> {
+    match extension(filename)? {
+        "json" => check_json(filename),
+        "pbrt" => check_pbrt(filename),
+        ext => Err(Error::UnsupportedFormat(ext.to_string())),
+    }
+}
+
+/// Check the geometry of a mesh:
+/// indices out of bounds, degenerated triangles and non-finite vertex data
+pub fn check_geometry(
+    name: &str,
+    vertices: &[Vector3<f32>],
+    indices: &[Vector3<usize>],
+    normals: Option<&[Vector3<f32>]>,
+    uv: Option<&[Vector2<f32>]>,
+) -> Vec<String> {
+    let mut warnings = vec![];
+    let finite = |v: &[f32]| v.iter().all(|x| x.is_finite());
+
+    let nb_invalid = vertices
+        .iter()
+        .filter(|v| !finite(v.as_ref() as &[f32; 3]))
+        .count();
+    if nb_invalid != 0 {
+        warnings.push(format!(
+            "mesh {}: {} non-finite vertex positions",
+            name, nb_invalid
+        ));
+    }
+    if let Some(normals) = normals {
+        let nb_invalid = normals
+            .iter()
+            .filter(|n| !finite(n.as_ref() as &[f32; 3]) || n.magnitude2() == 0.0)
+            .count();
+        if nb_invalid != 0 {
+            warnings.push(format!(
+                "mesh {}: {} non-finite or zero normals",
+                name, nb_invalid
+            ));
+        }
+        if normals.len() != vertices.len() {
+            warnings.push(format!(
+                "mesh {}: {} normals for {} vertices",
+                name,
+                normals.len(),
+                vertices.len()
+            ));
+        }
+    }
+    if let Some(uv) = uv {
+        let nb_invalid = uv
+            .iter()
+            .filter(|uv| !finite(uv.as_ref() as &[f32; 2]))
+            .count();
+        if nb_invalid != 0 {
+            warnings.push(format!(
+                "mesh {}: {} non-finite texture coordinates",
+                name, nb_invalid
+            ));
+        }
+    }
+
+    let mut nb_out_of_bounds = 0;
+    let mut nb_degenerated = 0;
+    for id in indices {
+        if id.x >= vertices.len() || id.y >= vertices.len() || id.z >= vertices.len() {
+            nb_out_of_bounds += 1;
+            continue;
+        }
+        let v0 = vertices[id.x];
+        let v1 = vertices[id.y];
+        let v2 = vertices[id.z];
+        let area = (v1 - v0).cross(v2 - v0).magnitude() * 0.5;
+        // NaN areas are already reported by the vertex check
+        if area < DEGENERATE_AREA {
+            nb_degenerated += 1;
+        }
+    }
+    if nb_out_of_bounds != 0 {
+        warnings.push(format!(
+            "mesh {}: {} triangles with out of bounds indices",
+            name, nb_out_of_bounds
+        ));
+    }
+    if nb_degenerated != 0 {
+        warnings.push(format!(
+            "mesh {}: {} degenerated triangles (out of {})",
+            name,
+            nb_degenerated,
+            indices.len()
+        ));
+    }
+    if indices.is_empty() {
+        warnings.push(format!("mesh {}: no triangles", name));
+    }
+    warnings
+}
+
+/// Check the mesh references inside the "emitters" or "bsdfs" list
+fn check_mesh_reference(section: &str, name: &str, mesh_names: &[String]) -> Option<String> {
+    match mesh_names.iter().filter(|n| *n == name).count() {
+        0 => Some(format!(
+            "{}: mesh {} not found in the OBJ file",
+            section, name
+        )),
+        1 => None,
+        _ => Some(format!(
+            "{}: several meshes named {} in the OBJ file",
+            section, name
+        )),
+    }
+}
+
+fn check_json(filename: &str) -> Result<Vec<String>> {
+    let mut warnings = vec![];
+    let scene_path = Path::new(filename);
+    let data = std::fs::read_to_string(scene_path)?;
+    let wk = scene_path.parent().unwrap_or_else(|| Path::new(""));
+    let v: serde_json::Value = serde_json::from_str(&data)?;
+
+    // Meshes (read directly with tobj to report all the problems)
+    let mut mesh_names = None;
+    match v.get("meshes").and_then(|v| v.as_str()) {
+        None => warnings.push("meshes: missing OBJ file name".to_string()),
+        Some(obj_path) => {
+            let obj_path = wk.join(obj_path);
+            match tobj::load_obj(&obj_path) {
+                Err(e) => warnings.push(format!(
+                    "meshes: impossible to load {:?}: {}",
+                    obj_path,
+                    Error::from(e)
+                )),
+                Ok((models, materials)) => {
+                    let obj_wk = obj_path.parent().unwrap_or_else(|| Path::new(""));
+                    for m in &materials {
+                        // The UDIM tiles are checked when loading the scene
+                        if !m.diffuse_texture.is_empty()
+                            && !m.diffuse_texture.contains(UDIM_TOKEN)
+                            && !obj_wk.join(&m.diffuse_texture).exists()
+                        {
+                            warnings.push(format!(
+                                "material {}: missing texture {}",
+                                m.name, m.diffuse_texture
+                            ));
+                        }
+                    }
+                    for m in &models {
+                        let mesh = &m.mesh;
+                        let vertices = mesh
+                            .positions
+                            .chunks(3)
+                            .map(|i| Vector3::new(i[0], i[1], i[2]))
+                            .collect::<Vec<_>>();
+                        let indices = mesh
+                            .indices
+                            .chunks(3)
+                            .map(|i| Vector3::new(i[0] as usize, i[1] as usize, i[2] as usize))
+                            .collect::<Vec<_>>();
+                        let normals = mesh
+                            .normals
+                            .chunks(3)
+                            .map(|i| Vector3::new(i[0], i[1], i[2]))
+                            .collect::<Vec<_>>();
+                        let uv = mesh
+                            .texcoords
+                            .chunks(2)
+                            .map(|i| Vector2::new(i[0], i[1]))
+                            .collect::<Vec<_>>();
+                        warnings.extend(check_geometry(
+                            &m.name,
+                            &vertices,
+                            &indices,
+                            if normals.is_empty() {
+                                None
+                            } else {
+                                Some(normals.as_slice())
+                            },
+                            if uv.is_empty() {
+                                None
+                            } else {
+                                Some(uv.as_slice())
+                            },
+                        ));
+                    }
+                    mesh_names = Some(models.into_iter().map(|m| m.name).collect::<Vec<_>>());
+                }
+            }
+        }
+    }
+
+    // Emitters
+    let mut nb_emitters = 0;
+    if let Some(emitters) = v.get("emitters").and_then(|v| v.as_array()) {
+        for e in emitters {
+            let name = match e.get("mesh").and_then(|v| v.as_str()) {
+                Some(name) => name,
+                None => {
+                    warnings.push(format!("emitters: entry without mesh name: {}", e));
+                    continue;
+                }
+            };
+            match serde_json::from_value::<Color>(e["emission"].clone()) {
+                Err(err) => warnings.push(format!("emitters: {}: wrong emission: {}", name, err)),
+                Ok(c) => {
+                    if !(c.r.is_finite() && c.g.is_finite() && c.b.is_finite())
+                        || c.r < 0.0
+                        || c.g < 0.0
+                        || c.b < 0.0
+                    {
+                        warnings.push(format!(
+                            "emitters: {}: emission needs to be positive and finite ({:?})",
+                            name, c
+                        ));
+                    } else if !c.is_zero() {
+                        nb_emitters += 1;
+                    }
+                }
+            }
+            if let Some(mesh_names) = &mesh_names {
+                warnings.extend(check_mesh_reference("emitters", name, mesh_names));
+            }
+        }
+    }
+    // Environment (parsing it also loads the map)
+    if let Some(e) = v.get("environment") {
+        match parse_environment(e) {
+            Err(err) => warnings.push(format!("environment: {}", err)),
+            Ok(_) => nb_emitters += 1,
+        }
+    }
+    if nb_emitters == 0 {
+        warnings.push("emitters: no emitting mesh, the image will be black".to_string());
+    }
+
+    // BSDFs (parsing them also loads the textures)
+    if let Some(bsdfs) = v.get("bsdfs").and_then(|v| v.as_array()) {
+        for b in bsdfs {
+            let name = match b.get("mesh").and_then(|v| v.as_str()) {
+                Some(name) => name,
+                None => {
+                    warnings.push(format!("bsdfs: entry without mesh name: {}", b));
+                    continue;
+                }
+            };
+            if let Err(e) = parse_bsdf(b) {
+                warnings.push(format!("bsdfs: {}: {}", name, e));
+            }
+            if let Some(mesh_names) = &mesh_names {
+                warnings.extend(check_mesh_reference("bsdfs", name, mesh_names));
+            }
+        }
+    }
+
+    // Texture coordinates projections
+    if let Some(projections) = v.get("uv_projections").and_then(|v| v.as_array()) {
+        for p in projections {
+            let name = match p.get("mesh").and_then(|v| v.as_str()) {
+                Some(name) => name,
+                None => {
+                    warnings.push(format!("uv_projections: entry without mesh name: {}", p));
+                    continue;
+                }
+            };
+            if let Err(e) = serde_json::from_value::<UVProjection>(p["projection"].clone()) {
+                warnings.push(format!("uv_projections: {}: {}", name, e));
+            }
+            if let Some(mesh_names) = &mesh_names {
+                warnings.extend(check_mesh_reference("uv_projections", name, mesh_names));
+            }
+        }
+    }
+
+    // Camera
+    match v.get("camera") {
+        None => warnings.push("camera: not set".to_string()),
+        Some(camera) => {
+            match serde_json::from_value::<f32>(camera["fov"].clone()) {
+                Err(e) => warnings.push(format!("camera: wrong fov: {}", e)),
+                Ok(fov) if fov <= 0.0 || fov >= 180.0 => {
+                    warnings.push(format!("camera: fov needs to be in ]0, 180[ ({})", fov))
+                }
+                _ => {}
+            }
+            match serde_json::from_value::<Vector2<u32>>(camera["img"].clone()) {
+                Err(e) => warnings.push(format!("camera: wrong image size: {}", e)),
+                Ok(img) if img.x == 0 || img.y == 0 => {
+                    warnings.push(format!("camera: empty image ({}x{})", img.x, img.y))
+                }
+                _ => {}
+            }
+            match serde_json::from_value::<Vec<f32>>(camera["matrix"].clone()) {
+                Err(e) => warnings.push(format!("camera: wrong matrix: {}", e)),
+                Ok(m) if m.len() != 16 => warnings.push(format!(
+                    "camera: the matrix needs 16 values (found {})",
+                    m.len()
+                )),
+                Ok(m) => {
+                    let matrix = Matrix4::new(
+                        m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11],
+                        m[12], m[13], m[14], m[15],
+                    );
+                    if matrix.invert().is_none() {
+                        warnings.push("camera: the matrix is not invertible".to_string());
+                    }
+                }
+            }
+            if let Some(distortion) = camera.get("distortion") {
+                if let Err(e) = serde_json::from_value::<[f32; 2]>(distortion.clone()) {
+                    warnings.push(format!("camera: wrong distortion [k1, k2]: {}", e));
+                }
+            }
+            if let Some(lens) = camera.get("lens") {
+                match lens["filename"].as_str() {
+                    Some(filename) if !wk.join(filename).exists() => {
+                        warnings.push(format!("camera: missing lens file {}", filename))
+                    }
+                    Some(_) => {}
+                    None => warnings.push("camera: lens without filename".to_string()),
+                }
+                if let Some(vignetting) = lens.get("optical_vignetting") {
+                    if let Err(e) = serde_json::from_value::<[f32; 2]>(vignetting.clone()) {
+                        warnings.push(format!(
+                            "camera: wrong optical vignetting [distance, diameter]: {}",
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// The PBRT scenes are checked after loading
+/// (the loader already reports missing textures and camera problems)
+fn check_pbrt(filename: &str) -> Result<Vec<String>> {
+    let scene = match SceneLoaderManager::default().load(filename.to_string()) {
+        Ok(scene) => scene,
+        Err(e) => return Ok(vec![format!("impossible to load the scene: {}", e)]),
+    };
+    let mut warnings = vec![];
+    for (i, m) in scene.meshes.iter().enumerate() {
+        warnings.extend(check_mesh(&format!("{} ({})", m.name, i), m));
+    }
+    if !scene.meshes.iter().any(|m| m.is_light()) && scene.emitter_environment.is_none() {
+        warnings.push("emitters: no emitter, the image will be black".to_string());
+    }
+    Ok(warnings)
+}
+
+fn check_mesh(name: &str, m: &Mesh) -> Vec<String> {
+    check_geometry(
+        name,
+        &m.vertices,
+        &m.indices,
+        m.normals.as_deref(),
+        m.uv.as_deref(),
+    )
+}
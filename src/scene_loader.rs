@@ -1,21 +1,29 @@
 use crate::bsdfs;
 use crate::bsdfs::*;
 use crate::camera::Camera;
+use crate::color::{ColorSpace, WhitePoint};
 use crate::emitter::*;
+use crate::error::{Error, Result};
 use crate::geometry;
+use crate::lens_system::LensSystem;
+use crate::samplers::SamplerType;
 use crate::scene::*;
 use crate::structure::*;
+use crate::tools::extension;
+#[cfg(feature = "pbrt")]
+use crate::transform::Transform;
 use cgmath::*;
 #[cfg(feature = "pbrt")]
 use pbrt_rs;
 use serde_json;
 use std::collections::HashMap;
-use std::error::Error;
 use std::io::Read;
 use std::rc::Rc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
 
 pub trait SceneLoader {
-    fn load(&self, filename: &str) -> Result<Scene, Box<dyn Error>>;
+    fn load(&self, filename: &str) -> Result<Scene>;
 }
 pub struct SceneLoaderManager {
     loader: HashMap<String, Rc<dyn SceneLoader>>,
@@ -24,18 +32,12 @@ impl SceneLoaderManager {
     pub fn register(&mut self, name: &str, loader: Rc<dyn SceneLoader>) {
         self.loader.insert(name.to_string(), loader);
     }
-    pub fn load(&self, filename: String) -> Result<Scene, Box<dyn Error>> {
-        let filename_ext = match std::path::Path::new(&filename).extension() {
-            None => panic!("No file extension provided"),
-            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
-        };
+    pub fn load(&self, filename: String) -> Result<Scene> {
+        let filename_ext = extension(&filename)?;
         if let Some(loader) = self.loader.get(filename_ext) {
             loader.load(&filename)
         } else {
-            panic!(
-                "Impossible to found scene loader for {} extension",
-                filename_ext
-            )
+            Err(Error::UnsupportedFormat(filename_ext.to_string()))
         }
     }
 }
@@ -52,143 +54,391 @@ impl Default for SceneLoaderManager {
     }
 }
 
+fn default_luminance() -> Color {
+    Color::one()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    /// Latitude-longitude map (y is up)
+    filename: Option<String>,
+    /// Uniform luminance or scale of the map
+    #[serde(default = "default_luminance")]
+    luminance: Color,
+    color_space: Option<ColorSpace>,
+    white_point: Option<WhitePoint>,
+    #[serde(default)]
+    mis_compensation: bool,
+    /// Multiplier of the luminance
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    /// Rotation of the map in degrees: around the up axis, then tilt of the horizon
+    #[serde(default)]
+    azimuth: f32,
+    #[serde(default)]
+    elevation: f32,
+    /// Maximum luminance of the texels of the map
+    clamp: Option<f32>,
+    sun: Option<SunDesc>,
+}
+
+/// Sun disk of the environment, its direction given by its azimuth around the up axis
+/// (toward -z for 0, counterclockwise seen from above) and its elevation above the horizon
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SunDesc {
+    /// Radiance at the center of the disk
+    #[serde(default = "default_luminance")]
+    radiance: Color,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    #[serde(default)]
+    azimuth: f32,
+    elevation: f32,
+    /// Angular radius in degrees
+    #[serde(default = "default_sun_radius")]
+    radius: f32,
+    #[serde(default = "default_limb_darkening")]
+    limb_darkening: f32,
+}
+
+fn default_sun_radius() -> f32 {
+    0.2665
+}
+
+fn default_limb_darkening() -> f32 {
+    0.6
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+/// {"filename": "sky.exr", "luminance": {"r": 1.0, "g": 1.0, "b": 1.0}, "mis_compensation": true,
+///  "intensity": 2.0, "azimuth": 90.0, "elevation": 10.0, "clamp": 100.0,
+///  "sun": {"radiance": {"r": 1.0, "g": 0.9, "b": 0.8}, "intensity": 1e5, "azimuth": 30.0,
+///  "elevation": 45.0, "radius": 0.2665, "limb_darkening": 0.6}}
+/// The bounding sphere of the scene is set by the caller.
+pub fn parse_environment(v: &serde_json::Value) -> Result<EnvironmentLight> {
+    let desc: EnvironmentDesc = serde_json::from_value(v.clone())?;
+    let map = match desc.filename {
+        Some(filename) => {
+            let mut image = Bitmap::read_linear(&filename, desc.color_space, desc.white_point)?;
+            if let Some(max) = desc.clamp {
+                image = image.clamp_luminance(max);
+            }
+            let map = EnvironmentMap::new(image, desc.mis_compensation);
+            Some(map.rotate(Deg(desc.azimuth), Deg(desc.elevation)))
+        }
+        None => None,
+    };
+    let sun = match desc.sun {
+        Some(sun) => {
+            let valid_radius = sun.radius > 0.0 && sun.radius < 180.0;
+            if !valid_radius || !(0.0..=1.0).contains(&sun.limb_darkening) {
+                return Err(Error::InvalidData(
+                    "the sun needs a radius in (0, 180) degrees and a limb darkening in [0, 1]"
+                        .to_string(),
+                ));
+            }
+            let elevation = Rad::from(Deg(sun.elevation));
+            let direction = Matrix3::from_angle_y(Deg(sun.azimuth))
+                * Vector3::new(0.0, elevation.sin(), -elevation.cos());
+            Some(SunDisk::new(
+                direction,
+                Rad::from(Deg(sun.radius)).0,
+                sun.radiance * sun.intensity,
+                sun.limb_darkening,
+            ))
+        }
+        None => None,
+    };
+    Ok(EnvironmentLight {
+        luminance: desc.luminance * desc.intensity,
+        map,
+        sun,
+        world: BoundingSphere::default(),
+    })
+}
+
+/// {"fov": 40.0, "img": [512, 512], "matrix": [16 values, row major]}
+/// and optionally the radial "distortion": [k1, k2] or a "lens" system
+/// ({"filename": "dgauss.50mm.dat", "aperture_diameter": 10.0, "focus_distance": 2.0,
+/// "optical_vignetting": [distance, diameter]})
+pub fn parse_camera(camera_json: &serde_json::Value, wk: &std::path::Path) -> Result<Camera> {
+    let fov: f32 = serde_json::from_value(camera_json["fov"].clone())?;
+    let img: Vector2<u32> = serde_json::from_value(camera_json["img"].clone())?;
+    let m: Vec<f32> = serde_json::from_value(camera_json["matrix"].clone())?;
+    if m.len() != 16 {
+        return Err(Error::InvalidData(format!(
+            "the camera matrix needs 16 values (found {})",
+            m.len()
+        )));
+    }
+
+    //let matrix = Matrix4::new(
+    //    m[0], m[4], m[8], m[12], m[1], m[5], m[9], m[13], m[2], m[6], m[10], m[14],
+    //    m[3], m[7], m[11], m[15],
+    //);
+    let matrix = Matrix4::new(
+        m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11],
+        m[12], m[13], m[14], m[15],
+    );
+
+    if matrix.invert().is_none() {
+        return Err(Error::InvalidData(
+            "the camera matrix is not invertible".to_string(),
+        ));
+    }
+
+    info!("m: {:?}", matrix);
+    let mut camera = Camera::new(img, fov, matrix);
+    if let Some(distortion) = camera_json.get("distortion") {
+        let [k1, k2]: [f32; 2] = serde_json::from_value(distortion.clone())?;
+        camera.set_distortion(k1, k2)?;
+    }
+    if let Some(lens) = camera_json.get("lens") {
+        let filename: String = serde_json::from_value(lens["filename"].clone())?;
+        let value = |name: &str, default: f32| -> Result<f32> {
+            match lens.get(name) {
+                Some(v) => Ok(serde_json::from_value(v.clone())?),
+                None => Ok(default),
+            }
+        };
+        let mut lens_system = LensSystem::load(
+            &wk.join(filename).to_string_lossy(),
+            value("aperture_diameter", 1.0)?,
+            value("focus_distance", 10.0)?,
+            value("film_diagonal", 35.0)?,
+        )?;
+        if let Some(vignetting) = lens.get("optical_vignetting") {
+            let [distance, diameter]: [f32; 2] = serde_json::from_value(vignetting.clone())?;
+            lens_system.set_optical_vignetting(distance, diameter)?;
+        }
+        info!(
+            "lens system: focal length {:?} m",
+            lens_system.focal_length()
+        );
+        camera.set_lens_system(Arc::new(lens_system));
+    }
+    Ok(camera)
+}
+
 pub struct JSONSceneLoader {}
 impl SceneLoader for JSONSceneLoader {
-    fn load(&self, filename: &str) -> Result<Scene, Box<dyn Error>> {
+    fn load(&self, filename: &str) -> Result<Scene> {
         // Reading the scene
         let scene_path = std::path::Path::new(filename);
-        let mut fscene = std::fs::File::open(scene_path).expect("scene file not found");
+        let mut fscene = std::fs::File::open(scene_path)?;
         let mut data = String::new();
-        fscene
-            .read_to_string(&mut data)
-            .expect("impossible to read the file");
+        fscene.read_to_string(&mut data)?;
         let wk = scene_path
             .parent()
-            .expect("impossible to extract parent directory for OBJ loading");
+            .unwrap_or_else(|| std::path::Path::new(""));
 
         // Read json string
         let v: serde_json::Value = serde_json::from_str(&data)?;
+        parse_scene(&v, wk)
+    }
+}
 
-        // Read the object
-        let obj_path_str: String = v["meshes"].as_str().unwrap().to_string();
-        let obj_path = wk.join(obj_path_str);
-        let mut meshes = geometry::load_obj(obj_path.as_path())?;
+/// Scene of a JSON description (same format as the scene files),
+/// the file paths are relative to the working directory wk
+pub fn parse_scene(v: &serde_json::Value, wk: &std::path::Path) -> Result<Scene> {
+    // Read the object
+    let obj_path_str: String = serde_json::from_value(v["meshes"].clone())?;
+    let obj_path = wk.join(obj_path_str);
+    // Crease angle (degrees) of the normals generated for the meshes without normals
+    let crease_angle = match v.get("crease_angle") {
+        Some(a) => serde_json::from_value(a.clone())?,
+        None => geometry::DEFAULT_CREASE_ANGLE,
+    };
+    let mut meshes = geometry::load_obj(obj_path.as_path(), crease_angle)?;
 
-        // Update meshes information
-        //  - which are light?
-        info!("Emitters:");
-        if let Some(emitters_json) = v.get("emitters") {
-            for e in emitters_json.as_array().unwrap() {
-                let name: String = e["mesh"].as_str().unwrap().to_string();
-                let emission: Color = serde_json::from_value(e["emission"].clone())?;
-                info!(" - emission: {}", name);
-                // Get the set of matched meshes
-                let mut matched_meshes = meshes
-                    .iter_mut()
-                    .filter(|m| m.name == name)
-                    .collect::<Vec<_>>();
-                match matched_meshes.len() {
-                    0 => panic!("Not found {} in the obj list", name),
-                    1 => {
-                        matched_meshes[0].emission = emission;
-                        info!("   * flux: {:?}", matched_meshes[0].flux());
-                    }
-                    _ => panic!("Several {} in the obj list", name),
-                };
-            }
+    // Update meshes information
+    //  - which are light?
+    info!("Emitters:");
+    if let Some(emitters_json) = v.get("emitters") {
+        let emitters_json: Vec<serde_json::Value> = serde_json::from_value(emitters_json.clone())?;
+        for e in &emitters_json {
+            let name: String = serde_json::from_value(e["mesh"].clone())?;
+            let emission: Color = serde_json::from_value(e["emission"].clone())?;
+            info!(" - emission: {}", name);
+            // Get the set of matched meshes
+            let mut matched_meshes = meshes
+                .iter_mut()
+                .filter(|m| m.name == name)
+                .collect::<Vec<_>>();
+            match matched_meshes.len() {
+                0 => return Err(Error::MeshNotFound(name)),
+                1 => {
+                    matched_meshes[0].emission = emission;
+                    info!("   * flux: {:?}", matched_meshes[0].flux());
+                }
+                _ => return Err(Error::MeshDuplicated(name)),
+            };
         }
-        // - BSDF
-        info!("BSDFS:");
-        if let Some(bsdfs_json) = v.get("bsdfs") {
-            for b in bsdfs_json.as_array().unwrap() {
-                let name: String = serde_json::from_value(b["mesh"].clone())?;
-                info!(" - replace bsdf: {}", name);
-                let new_bsdf = parse_bsdf(&b)?;
-                let mut matched_meshes = meshes
-                    .iter_mut()
-                    .filter(|m| m.name == name)
-                    .collect::<Vec<_>>();
-                match matched_meshes.len() {
-                    0 => panic!("Not found {} in the obj list", name),
-                    1 => {
-                        matched_meshes[0].bsdf = new_bsdf;
+    }
+    // - BSDF
+    info!("BSDFS:");
+    if let Some(bsdfs_json) = v.get("bsdfs") {
+        let bsdfs_json: Vec<serde_json::Value> = serde_json::from_value(bsdfs_json.clone())?;
+        for b in &bsdfs_json {
+            let name: String = serde_json::from_value(b["mesh"].clone())?;
+            info!(" - replace bsdf: {}", name);
+            let new_bsdf = parse_bsdf(&b)?;
+            let mut matched_meshes = meshes
+                .iter_mut()
+                .filter(|m| m.name == name)
+                .collect::<Vec<_>>();
+            match matched_meshes.len() {
+                0 => return Err(Error::MeshNotFound(name)),
+                1 => {
+                    matched_meshes[0].bsdf = new_bsdf;
+                    if let Some(material_name) = b.get("name").and_then(|v| v.as_str()) {
+                        matched_meshes[0].material_name = material_name.to_string();
                     }
-                    _ => panic!("Several {} in the obj list", name),
-                };
+                }
+                _ => return Err(Error::MeshDuplicated(name)),
+            };
+        }
+    }
+    // - Texture coordinates (box projection for the meshes without them)
+    let mut projections = HashMap::new();
+    if let Some(uvs_json) = v.get("uv_projections") {
+        let uvs_json: Vec<serde_json::Value> = serde_json::from_value(uvs_json.clone())?;
+        for p in &uvs_json {
+            let name: String = serde_json::from_value(p["mesh"].clone())?;
+            let projection: geometry::UVProjection =
+                serde_json::from_value(p["projection"].clone())?;
+            match meshes.iter().filter(|m| m.name == name).count() {
+                0 => return Err(Error::MeshNotFound(name)),
+                1 => {
+                    projections.insert(name, projection);
+                }
+                _ => return Err(Error::MeshDuplicated(name)),
             }
         }
+    }
+    for m in &mut meshes {
+        let projection = match projections.get(&m.name) {
+            Some(&p) => p,
+            None if m.uv.is_none() => geometry::UVProjection::Box,
+            None => continue,
+        };
+        info!(" - uv projection: {} ({:?})", m.name, projection);
+        m.project_uv(projection);
+    }
 
-        // Read the camera config
-        let camera = {
-            if let Some(camera_json) = v.get("camera") {
-                let fov: f32 = serde_json::from_value(camera_json["fov"].clone())?;
-                let img: Vector2<u32> = serde_json::from_value(camera_json["img"].clone())?;
-                let m: Vec<f32> = serde_json::from_value(camera_json["matrix"].clone())?;
-
-                //let matrix = Matrix4::new(
-                //    m[0], m[4], m[8], m[12], m[1], m[5], m[9], m[13], m[2], m[6], m[10], m[14],
-                //    m[3], m[7], m[11], m[15],
-                //);
-                let matrix = Matrix4::new(
-                    m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11],
-                    m[12], m[13], m[14], m[15],
-                );
+    // - Environment (uniform or map)
+    let emitter_environment = match v.get("environment") {
+        Some(e) => {
+            let mut env = parse_environment(e)?;
+            env.world = geometry::bounding_sphere(&meshes);
+            info!(" - environment (flux: {:?})", env.flux());
+            Some(env)
+        }
+        None => None,
+    };
 
-                info!("m: {:?}", matrix);
-                Camera::new(img, fov, matrix)
-            } else {
-                panic!("The camera is not set!");
+    // Read the camera config
+    // "cameras" is a list of named cameras, the first one is the main camera
+    // if "camera" is not given
+    let mut cameras: Vec<(String, Camera)> = vec![];
+    if let Some(cameras_json) = v.get("cameras") {
+        let cameras_json = cameras_json
+            .as_array()
+            .ok_or_else(|| Error::InvalidData("cameras needs to be a list".to_string()))?;
+        for camera_json in cameras_json {
+            let name: String = serde_json::from_value(camera_json["name"].clone())?;
+            if cameras.iter().any(|(n, _)| *n == name) {
+                return Err(Error::InvalidData(format!("duplicated camera {}", name)));
             }
-        };
-        camera.print_info();
-
-        // Define a default scene
-        Ok(Scene {
-            camera,
-            meshes,
-            nb_samples: 1,
-            nb_threads: None,
-            output_img_path: "out.pfm".to_string(),
-            emitter_environment: None,
-            volume: None,
-        })
+            info!("camera {}", name);
+            cameras.push((name, parse_camera(camera_json, wk)?));
+        }
     }
+    let camera = if let Some(camera_json) = v.get("camera") {
+        parse_camera(camera_json, wk)?
+    } else if let Some((_, camera)) = cameras.first() {
+        camera.clone()
+    } else {
+        return Err(Error::InvalidData("The camera is not set!".to_string()));
+    };
+    camera.print_info();
+
+    // Define a default scene
+    Ok(Scene {
+        camera,
+        cameras,
+        meshes,
+        nb_samples: 1,
+        nb_threads: None,
+        thread_pinning: ThreadPinning::None,
+        tile_size: None,
+        output_img_path: "out.pfm".to_string(),
+        output_variance: false,
+        output_volume: false,
+        output_ids: None,
+        demodulate: false,
+        seed: None,
+        pass: AtomicUsize::new(0),
+        lock_passes: false,
+        deterministic: false,
+        sampler: SamplerType::default(),
+        emitter_selection: EmitterSelection::default(),
+        env_guiding: None,
+        film: Film::default(),
+        sample_rejection: None,
+        non_finite: Default::default(),
+        path_debugger: Default::default(),
+        emitter_environment,
+        volume: None,
+        emitters_cache: Default::default(),
+        auto_tile_size: Default::default(),
+    })
 }
 
 #[cfg(feature = "pbrt")]
 pub struct PBRTSceneLoader {}
 #[cfg(feature = "pbrt")]
 impl SceneLoader for PBRTSceneLoader {
-    fn load(&self, filename: &str) -> Result<Scene, Box<dyn Error>> {
+    fn load(&self, filename: &str) -> Result<Scene> {
         let mut scene_info = pbrt_rs::Scene::default();
         let mut state = pbrt_rs::State::default();
-        let working_dir = std::path::Path::new(filename).parent().unwrap();
+        let working_dir = std::path::Path::new(filename)
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new(""));
         pbrt_rs::read_pbrt_file(filename, &working_dir, &mut scene_info, &mut state);
 
         // Load the data
         let mut meshes: Vec<geometry::Mesh> = scene_info
             .shapes
             .iter()
-            .map(|m| match m.data {
+            .enumerate()
+            .map(|(i, m)| match m.data {
                 pbrt_rs::Shape::TriMesh(ref data) => {
-                    let mat = m.matrix;
-                    let uv = data.uv.clone();
-                    let normals = match data.normals {
-                        Some(ref v) => {
-                            Some(v.iter().map(|n| mat.transform_vector(n.clone())).collect())
-                        }
-                        None => None,
-                    };
-                    let points = data
-                        .points
-                        .iter()
-                        .map(|n| mat.transform_point(n.clone()).to_vec())
-                        .collect();
-                    let indices = data.indices.clone();
+                    let transform = Transform::new(m.matrix).ok_or_else(|| {
+                        Error::InvalidData("the shape matrix is not invertible".to_string())
+                    })?;
+                    let mut uv = data.uv.clone();
+                    let mut normals = data.normals.clone();
+                    let mut points = data.points.iter().map(|p| p.to_vec()).collect();
+                    let mut indices = data.indices.clone();
+                    let removed = geometry::remove_degenerate_triangles(
+                        &mut points,
+                        &mut indices,
+                        &mut normals,
+                        &mut uv,
+                    );
+                    if removed.total() > 0 {
+                        warn!("shape {}: {}", i, removed);
+                    }
 
                     let bsdf = if let Some(ref name) = m.material_name {
                         if let Some(bsdf_name) = scene_info.materials.get(name) {
-                            bsdfs::bsdf_pbrt(bsdf_name, &scene_info)
+                            bsdfs::bsdf_pbrt(bsdf_name, &scene_info)?
                         } else {
                             Box::new(bsdfs::diffuse::BSDFDiffuse {
                                 diffuse: bsdfs::BSDFColor::UniformColor(Color::value(0.8)),
@@ -201,11 +451,15 @@ impl SceneLoader for PBRTSceneLoader {
                     };
                     let mut mesh =
                         geometry::Mesh::new("noname".to_string(), points, indices, normals, uv);
+                    mesh.transform(&transform);
                     mesh.bsdf = bsdf;
-                    mesh
+                    if let Some(ref name) = m.material_name {
+                        mesh.material_name = name.clone();
+                    }
+                    Ok(mesh)
                 }
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         // Assign materials and emissions
         for (i, shape) in scene_info.shapes.iter().enumerate() {
@@ -223,18 +477,22 @@ impl SceneLoader for PBRTSceneLoader {
         let mut emitter_environment = None;
         {
             let mut have_env = false;
+            let world = geometry::bounding_sphere(&meshes);
             for l in scene_info.lights {
                 match l {
                     pbrt_rs::Light::Infinite(ref infinite) => {
                         match infinite.luminance {
                             pbrt_rs::Param::RGB(ref rgb) => {
                                 if have_env {
-                                    panic!("Multiple env map is NOT supported");
+                                    return Err(Error::InvalidData(
+                                        "Multiple env map is NOT supported".to_string(),
+                                    ));
                                 }
                                 emitter_environment = Some(EnvironmentLight {
                                     luminance: Color::new(rgb.r, rgb.g, rgb.b),
-                                    world_radius: 1.0, // TODO: Add the correct radius
-                                    world_position: Point3::new(0.0, 0.0, 0.0), // TODO:
+                                    map: None,
+                                    sun: None,
+                                    world,
                                 });
                                 have_env = true;
                             }
@@ -250,29 +508,57 @@ impl SceneLoader for PBRTSceneLoader {
             }
         };
 
-        let camera = {
-            if let Some(camera) = scene_info.cameras.get(0) {
-                match camera {
-                    pbrt_rs::Camera::Perspective(ref cam) => {
-                        let mat = cam.world_to_camera.inverse_transform().unwrap();
-                        info!("camera matrix: {:?}", mat);
-                        Camera::new(scene_info.image_size, cam.fov, mat)
-                    }
+        // PBRT cameras are not named: "camera<index>" in the file order
+        let mut cameras = vec![];
+        for (i, camera) in scene_info.cameras.iter().enumerate() {
+            match camera {
+                pbrt_rs::Camera::Perspective(ref cam) => {
+                    let mat = cam.world_to_camera.inverse_transform().ok_or_else(|| {
+                        Error::InvalidData("the camera matrix is not invertible".to_string())
+                    })?;
+                    info!("camera matrix: {:?}", mat);
+                    let camera = Camera::new(scene_info.image_size, cam.fov, mat);
+                    cameras.push((format!("camera{}", i), camera));
                 }
-            } else {
-                panic!("The camera is not set!");
             }
+        }
+        let camera = match cameras.first() {
+            Some((_, camera)) => camera.clone(),
+            None => return Err(Error::InvalidData("The camera is not set!".to_string())),
         };
+        if cameras.len() == 1 {
+            cameras.clear();
+        }
 
         info!("image size: {:?}", scene_info.image_size);
         Ok(Scene {
             camera,
+            cameras,
             meshes,
             nb_samples: 1,
             nb_threads: None,
+            thread_pinning: ThreadPinning::None,
+            tile_size: None,
             output_img_path: "out.pfm".to_string(),
+            output_variance: false,
+            output_volume: false,
+            output_ids: None,
+            demodulate: false,
+            seed: None,
+            pass: AtomicUsize::new(0),
+            lock_passes: false,
+            deterministic: false,
+            sampler: SamplerType::default(),
+            emitter_selection: EmitterSelection::default(),
+            env_guiding: None,
+            film: Film::default(),
+            sample_rejection: None,
+            non_finite: Default::default(),
+            path_debugger: Default::default(),
             emitter_environment,
             volume: None,
+            emitters_cache: Default::default(),
+            auto_tile_size: Default::default(),
         })
     }
 }
@@ -0,0 +1,349 @@
+//! Small HTTP render service (feature "server").
+//!
+//! - `POST /jobs`: submit a job (JSON, see JobRequest), returns its status
+//! - `GET /jobs`: status of all the jobs
+//! - `GET /jobs/<id>`: status of a job (state, pass, progress, error)
+//! - `GET /jobs/<id>/preview.png`: image of the last finished pass
+//! - `GET /jobs/<id>/image.exr`: final image
+//!
+//! The jobs are rendered one at a time (each job uses all the cores).
+use crate::error::{Error, Result};
+use crate::integrators::registry::IntegratorManager;
+use crate::integrators::RenderObserver;
+use crate::scene_loader::SceneLoaderManager;
+use crate::structure::AccumBuffer;
+use std::collections::BTreeMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Response, Server};
+
+fn default_nb_samples() -> usize {
+    16
+}
+fn default_passes() -> usize {
+    1
+}
+
+/// Job description (body of POST /jobs)
+///
+/// ```json
+/// {"scene": "data/cbox.json", "nb_samples": 16, "passes": 4,
+///  "integrator": {"type": "path", "max": 5}}
+/// ```
+/// The scene path is read on the server side. The integrator parameters are the same
+/// as the command line ones (see IntegratorManager::create_from_json).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JobRequest {
+    pub scene: String,
+    /// Number of samples per pixel for each pass
+    #[serde(default = "default_nb_samples")]
+    pub nb_samples: usize,
+    /// The passes are averaged, a preview is available after each of them
+    #[serde(default = "default_passes")]
+    pub passes: usize,
+    pub image_scale: Option<f32>,
+    /// Seed of the samplers, hashed with the pass index (random if not set)
+    pub seed: Option<u64>,
+    /// All the passes use the same random numbers (debugging)
+    #[serde(default)]
+    pub lock_passes: bool,
+    pub integrator: serde_json::Value,
+}
+impl JobRequest {
+    /// Checked when the job is submitted
+    fn check(&self) -> Result<()> {
+        if self.passes == 0 || self.nb_samples == 0 {
+            return Err(Error::InvalidData(
+                "passes and nb_samples need to be positive".to_string(),
+            ));
+        }
+        match self.image_scale {
+            Some(s) if s <= 0.0 => Err(Error::InvalidData(
+                "the image scale needs to be positive".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Rendering,
+    Done,
+    Failed,
+}
+
+/// Job status (answer of GET /jobs/<id>)
+#[derive(Serialize, Debug, Clone)]
+pub struct JobStatus {
+    pub id: usize,
+    pub state: JobState,
+    /// Number of finished passes
+    pub pass: usize,
+    pub passes: usize,
+    /// Progress of the whole job in [0, 1]
+    pub progress: f32,
+    pub error: Option<String>,
+}
+
+struct Job {
+    status: Mutex<JobStatus>,
+    // Progress of the current pass
+    nb_tiles: AtomicUsize,
+    nb_tiles_done: AtomicUsize,
+}
+impl Job {
+    fn status(&self) -> JobStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        status.progress = match status.state {
+            JobState::Queued => 0.0,
+            JobState::Done => 1.0,
+            JobState::Rendering | JobState::Failed => {
+                let nb_tiles = self.nb_tiles.load(Ordering::SeqCst);
+                let current = if nb_tiles == 0 || status.pass == status.passes {
+                    0.0
+                } else {
+                    self.nb_tiles_done.load(Ordering::SeqCst) as f32 / nb_tiles as f32
+                };
+                (status.pass as f32 + current) / status.passes.max(1) as f32
+            }
+        };
+        status
+    }
+}
+impl RenderObserver for Job {
+    fn on_start(&self, nb_tiles: usize) {
+        self.nb_tiles_done.store(0, Ordering::SeqCst);
+        self.nb_tiles.store(nb_tiles, Ordering::SeqCst);
+    }
+    fn on_tile_done(&self) {
+        self.nb_tiles_done.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+type Jobs = Arc<Mutex<BTreeMap<usize, Arc<Job>>>>;
+
+fn preview_path(output_dir: &Path, id: usize) -> PathBuf {
+    output_dir.join(format!("job_{}_preview.png", id))
+}
+fn image_path(output_dir: &Path, id: usize) -> PathBuf {
+    output_dir.join(format!("job_{}.exr", id))
+}
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::InvalidData(format!("output path {:?}", path)))
+}
+
+/// Start the server and render the submitted jobs.
+/// The images are written inside output_dir.
+/// The rendering is done on the calling thread (the custom integrators do not need to be Send),
+/// the HTTP requests are handled by another thread. This function never returns on success.
+pub fn run(address: &str, output_dir: &Path, integrators: &IntegratorManager) -> Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+    let server = Server::http(address)
+        .map_err(|e| Error::InvalidData(format!("Impossible to listen on {}: {}", address, e)))?;
+    info!("Render server listening on http://{}", address);
+
+    let jobs: Jobs = Arc::new(Mutex::new(BTreeMap::new()));
+    let (sender, receiver) = channel();
+    {
+        let jobs = jobs.clone();
+        let output_dir = output_dir.to_path_buf();
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let response = handle(&mut request, &jobs, &sender, &output_dir);
+                if let Err(e) = request.respond(response) {
+                    warn!("Impossible to send the response: {}", e);
+                }
+            }
+        });
+    }
+    render_jobs(receiver, &jobs, output_dir, integrators);
+    Ok(())
+}
+
+/// Render the jobs in the submission order
+fn render_jobs(
+    receiver: Receiver<(usize, JobRequest)>,
+    jobs: &Jobs,
+    output_dir: &Path,
+    integrators: &IntegratorManager,
+) {
+    for (id, request) in receiver {
+        let job = jobs.lock().unwrap()[&id].clone();
+        job.status.lock().unwrap().state = JobState::Rendering;
+        info!("Render job {}: {}", id, request.scene);
+        // A panicking job fails without stopping the server
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            render_job(id, &request, &job, output_dir, integrators)
+        }))
+        .unwrap_or_else(|e| {
+            let msg = if let Some(s) = e.downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = e.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "unknown panic".to_string()
+            };
+            Err(Error::InvalidData(format!(
+                "the rendering panicked: {}",
+                msg
+            )))
+        });
+        let mut status = job.status.lock().unwrap();
+        match result {
+            Ok(()) => status.state = JobState::Done,
+            Err(e) => {
+                error!("Job {} failed: {}", id, e);
+                status.state = JobState::Failed;
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+fn render_job(
+    id: usize,
+    request: &JobRequest,
+    job: &Job,
+    output_dir: &Path,
+    integrators: &IntegratorManager,
+) -> Result<()> {
+    let name = request
+        .integrator
+        .get("type")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| Error::InvalidData("the integrator type is missing".to_string()))?;
+    let mut params = request.integrator.clone();
+    if let Some(params) = params.as_object_mut() {
+        params.remove("type");
+    }
+    let mut int = integrators.create_from_json(name, &params)?;
+
+    let mut scene = SceneLoaderManager::default()
+        .load(request.scene.clone())?
+        .nb_samples(request.nb_samples)
+        .lock_passes(request.lock_passes)
+        .output_img(path_str(&image_path(output_dir, id))?);
+    scene.seed = request.seed;
+    if let Some(s) = request.image_scale {
+        scene.camera.scale_image(s);
+    }
+
+    // Progressive rendering: average the passes
+    let preview = preview_path(output_dir, id);
+    let mut img: Option<AccumBuffer> = None;
+    for pass in 0..request.passes {
+        scene.set_pass(pass);
+        let new_img = int.compute(&scene, job);
+        let primal = &new_img.values["primal"];
+        let accum = img.get_or_insert_with(|| AccumBuffer::new(primal.size));
+        accum.add_bitmap(primal);
+        accum.mean_bitmap().to_f32().save(path_str(&preview)?)?;
+        job.status.lock().unwrap().pass = pass + 1;
+    }
+    match img {
+        Some(img) => img.mean_bitmap().to_f32().save(&scene.output_img_path),
+        None => Err(Error::InvalidData("no pass to render".to_string())),
+    }
+}
+
+fn json_response<T: serde::Serialize>(v: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(serde_json::to_string(v).unwrap())
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+}
+
+fn error_response(code: u16, msg: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(msg).with_status_code(code)
+}
+
+/// Answer with the content of an image file
+fn file_response(path: &Path, content_type: &[u8]) -> Response<std::io::Cursor<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(data) => Response::from_data(data)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], content_type).unwrap()),
+        Err(_) => error_response(404, "image not available yet"),
+    }
+}
+
+fn handle(
+    request: &mut tiny_http::Request,
+    jobs: &Jobs,
+    sender: &Sender<(usize, JobRequest)>,
+    output_dir: &Path,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let url = request.url().trim_end_matches('/').to_string();
+    let path = url.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    match (request.method(), path.as_slice()) {
+        (Method::Post, ["jobs"]) => {
+            let mut body = String::new();
+            if let Err(e) = request.as_reader().read_to_string(&mut body) {
+                return error_response(400, &e.to_string());
+            }
+            let job_request: JobRequest = match serde_json::from_str(&body) {
+                Ok(v) => v,
+                Err(e) => return error_response(400, &e.to_string()),
+            };
+            if let Err(e) = job_request.check() {
+                return error_response(400, &e.to_string());
+            }
+            let mut jobs = jobs.lock().unwrap();
+            let id = jobs.len();
+            let job = Arc::new(Job {
+                status: Mutex::new(JobStatus {
+                    id,
+                    state: JobState::Queued,
+                    pass: 0,
+                    passes: job_request.passes,
+                    progress: 0.0,
+                    error: None,
+                }),
+                nb_tiles: AtomicUsize::new(0),
+                nb_tiles_done: AtomicUsize::new(0),
+            });
+            jobs.insert(id, job.clone());
+            if sender.send((id, job_request)).is_err() {
+                return error_response(500, "the renderer is stopped");
+            }
+            json_response(&job.status())
+        }
+        (Method::Get, ["jobs"]) => {
+            let status = jobs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|j| j.status())
+                .collect::<Vec<_>>();
+            json_response(&status)
+        }
+        (Method::Get, ["jobs", id, rest @ ..]) => {
+            let job = match id
+                .parse()
+                .ok()
+                .and_then(|id| jobs.lock().unwrap().get(&id).cloned())
+            {
+                Some(job) => job,
+                None => return error_response(404, "unknown job"),
+            };
+            let status = job.status();
+            match rest {
+                [] => json_response(&status),
+                ["preview.png"] => {
+                    file_response(&preview_path(output_dir, status.id), b"image/png")
+                }
+                ["image.exr"] if status.state == JobState::Done => {
+                    file_response(&image_path(output_dir, status.id), b"image/x-exr")
+                }
+                ["image.exr"] => error_response(404, "the job is not finished"),
+                _ => error_response(404, "not found"),
+            }
+        }
+        _ => error_response(404, "not found"),
+    }
+}
@@ -1,19 +1,25 @@
-use crate::constants;
+use crate::camera::RayDifferentials;
+use crate::color::{rgb_adaptation, transform_color, ColorSpace, WhitePoint};
+use crate::error::{Error, Result};
 use crate::geometry::Mesh;
 use crate::math::Frame;
 use crate::tools::*;
 use crate::Scale;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use cgmath::{EuclideanSpace, Point2, Point3, Vector2, Vector3};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector2, Vector3};
 #[cfg(feature = "image")]
 use image::{DynamicImage, GenericImage, Pixel};
-#[cfg(feature = "exr")]
-use exr;
 use std;
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::ops::*;
 use std::path::Path;
+#[cfg(not(feature = "f64-film"))]
+use std::sync::atomic::AtomicU32;
+#[cfg(feature = "f64-film")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Clone, Debug)]
 pub enum PDF {
@@ -40,6 +46,21 @@ impl PDF {
             PDF::Discrete(v) | PDF::SolidAngle(v) | PDF::Area(v) => *v,
         }
     }
+
+    /// Solid angle density of a point at the distance dist whose normal makes
+    /// the cosine cos_light with the direction toward it (zero for a grazing point)
+    pub fn to_solid_angle(&self, dist: f32, cos_light: f32) -> PDF {
+        match self {
+            PDF::Area(v) => {
+                if cos_light <= 0.0 {
+                    PDF::SolidAngle(0.0)
+                } else {
+                    PDF::SolidAngle(v * dist * dist / cos_light)
+                }
+            }
+            _ => self.clone(),
+        }
+    }
 }
 
 impl Mul<f32> for PDF {
@@ -55,6 +76,8 @@ impl Mul<f32> for PDF {
 
 pub struct SampledPosition {
     pub p: Point3<f32>,
+    /// Floating point error bound of p
+    pub p_error: Vector3<f32>,
     pub n: Vector3<f32>,
     pub pdf: PDF,
 }
@@ -105,14 +128,16 @@ impl Color {
         self.r == 0.0 && self.g == 0.0 && self.b == 0.0
     }
 
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
+    /// Encoded and quantized color (the values outside [0, 1] are clamped)
     #[cfg(feature = "image")]
-    pub fn to_rgba(&self) -> image::Rgba<u8> {
-        image::Rgba::from_channels(
-            (self.r.min(1.0).powf(1.0 / 2.2) * 255.0) as u8,
-            (self.g.min(1.0).powf(1.0 / 2.2) * 255.0) as u8,
-            (self.b.min(1.0).powf(1.0 / 2.2) * 255.0) as u8,
-            255,
-        )
+    pub fn to_rgba(&self, color_space: ColorSpace) -> image::Rgba<u8> {
+        let c = color_space.encode_color(*self);
+        let quantize = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::Rgba::from_channels(quantize(c.r), quantize(c.g), quantize(c.b), 255)
     }
     pub fn channel_max(&self) -> f32 {
         self.r.max(self.g.max(self.b))
@@ -218,10 +243,18 @@ impl Div<Color> for Color {
     }
 }
 
+/// Number of colors scaled by a NaN or infinite value (set to zero instead)
+static NON_FINITE_SCALES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of colors set to zero by Mul<f32> since the start of the program
+/// because of a NaN or infinite factor (see NonFiniteCheck)
+pub fn non_finite_scales() -> usize {
+    NON_FINITE_SCALES.load(Ordering::Relaxed)
+}
+
 impl Mul<f32> for Color {
     type Output = Self;
     fn mul(self, other: f32) -> Color {
-        //assert!(other.is_finite());
         if other.is_finite() {
             Color {
                 r: self.r * other,
@@ -229,6 +262,7 @@ impl Mul<f32> for Color {
                 b: self.b * other,
             }
         } else {
+            NON_FINITE_SCALES.fetch_add(1, Ordering::Relaxed);
             Color::zero()
         }
     }
@@ -322,30 +356,168 @@ impl<'a> Add<&'a Color> for Color {
     }
 }
 
-pub struct Bitmap {
+/// Pixel color in double precision (see FilmColor)
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Copy, Default)]
+pub struct Color64 {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl Color64 {
+    pub fn new(r: f64, g: f64, b: f64) -> Color64 {
+        Color64 { r, g, b }
+    }
+    pub fn zero() -> Color64 {
+        Color64::new(0.0, 0.0, 0.0)
+    }
+}
+
+impl Scale<f32> for Color64 {
+    fn scale(&mut self, v: f32) {
+        let v = f64::from(v);
+        self.r *= v;
+        self.g *= v;
+        self.b *= v;
+    }
+}
+
+impl AddAssign<Color64> for Color64 {
+    fn add_assign(&mut self, other: Color64) {
+        self.r += other.r;
+        self.g += other.g;
+        self.b += other.b;
+    }
+}
+
+impl Add<Color64> for Color64 {
+    type Output = Self;
+    fn add(self, other: Color64) -> Color64 {
+        Color64::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl Sub<Color64> for Color64 {
+    type Output = Self;
+    fn sub(self, other: Color64) -> Color64 {
+        Color64::new(self.r - other.r, self.g - other.g, self.b - other.b)
+    }
+}
+
+impl Mul<Color64> for Color64 {
+    type Output = Self;
+    fn mul(self, other: Color64) -> Color64 {
+        Color64::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+impl Mul<f64> for Color64 {
+    type Output = Self;
+    fn mul(self, other: f64) -> Color64 {
+        Color64::new(self.r * other, self.g * other, self.b * other)
+    }
+}
+
+impl Div<f64> for Color64 {
+    type Output = Self;
+    fn div(self, other: f64) -> Color64 {
+        Color64::new(self.r / other, self.g / other, self.b / other)
+    }
+}
+
+/// Values stored by a bitmap: Color, or Color64 to accumulate
+/// a large number of contributions without losing precision
+pub trait PixelValue: Copy + Default + PartialEq + Send + Sync + AddAssign + Scale<f32> {
+    fn from_color(c: Color) -> Self;
+    fn to_color(self) -> Color;
+    /// Single precision bitmap (borrowed if already in single precision)
+    fn to_f32(img: &Bitmap<Self>) -> Cow<'_, Bitmap>;
+}
+
+impl PixelValue for Color {
+    fn from_color(c: Color) -> Self {
+        c
+    }
+    fn to_color(self) -> Color {
+        self
+    }
+    fn to_f32(img: &Bitmap<Self>) -> Cow<'_, Bitmap> {
+        Cow::Borrowed(img)
+    }
+}
+
+impl PixelValue for Color64 {
+    fn from_color(c: Color) -> Self {
+        Color64::new(f64::from(c.r), f64::from(c.g), f64::from(c.b))
+    }
+    fn to_color(self) -> Color {
+        Color::new(self.r as f32, self.g as f32, self.b as f32)
+    }
+    fn to_f32(img: &Bitmap<Self>) -> Cow<'_, Bitmap> {
+        Cow::Owned(Bitmap {
+            size: img.size,
+            colors: img.colors.iter().map(|c| c.to_color()).collect(),
+            alpha: img.alpha.clone(),
+        })
+    }
+}
+
+/// Values of the rendered buffers (BufferCollection, AccumBuffer and SplatBuffer).
+/// With the "f64-film" feature, the contributions are accumulated in double precision
+/// (e.g., for the references rendered with 10^5 samples per pixel or more)
+/// and the images are converted to single precision when they are written.
+#[cfg(not(feature = "f64-film"))]
+pub type FilmColor = Color;
+#[cfg(feature = "f64-film")]
+pub type FilmColor = Color64;
+/// Channel type of FilmColor
+#[cfg(not(feature = "f64-film"))]
+pub type FilmFloat = f32;
+#[cfg(feature = "f64-film")]
+pub type FilmFloat = f64;
+
+#[derive(Clone)]
+pub struct Bitmap<C = Color> {
     pub size: Vector2<u32>,
-    pub colors: Vec<Color>,
+    pub colors: Vec<C>,
+    /// Optional alpha channel (coverage), written by the PNG and EXR outputs
+    pub alpha: Option<Vec<f32>>,
 }
-impl Bitmap {
-    pub fn new(size: Vector2<u32>) -> Bitmap {
+impl<C: PixelValue> Bitmap<C> {
+    pub fn new(size: Vector2<u32>) -> Bitmap<C> {
         Bitmap {
             size,
-            colors: vec![Color::default(); (size.x * size.y) as usize],
+            colors: vec![C::default(); (size.x * size.y) as usize],
+            alpha: None,
+        }
+    }
+    /// Bytes of the pixels and of the alpha channel
+    pub fn memory(&self) -> usize {
+        self.colors.len() * std::mem::size_of::<C>()
+            + self.alpha.as_ref().map(|a| a.len() * std::mem::size_of::<f32>()).unwrap_or(0)
+    }
+    /// Alpha of the pixel (1 without alpha channel)
+    pub fn alpha(&self, p: Point2<u32>) -> f32 {
+        assert!(p.x < self.size.x);
+        assert!(p.y < self.size.y);
+        match self.alpha {
+            Some(ref alpha) => alpha[(p.y * self.size.x + p.x) as usize],
+            None => 1.0,
         }
     }
     pub fn clear(&mut self) {
-        self.colors.iter_mut().for_each(|x| *x = Color::default());
+        self.colors.iter_mut().for_each(|x| *x = C::default());
     }
     pub fn accumulate(&mut self, p: Point2<u32>, f: Color) {
         assert!(p.x < self.size.x);
         assert!(p.y < self.size.y);
         let index = (p.y * self.size.x + p.x) as usize;
-        self.colors[index] += f;
+        self.colors[index] += C::from_color(f);
     }
     /**
      * pos: Position where to splat the buffer
      */
-    pub fn accumulate_bitmap(&mut self, o: &Bitmap, pos: Point2<u32>) {
+    pub fn accumulate_bitmap(&mut self, o: &Bitmap<C>, pos: Point2<u32>) {
         for y in 0..o.size.y {
             for x in 0..o.size.x {
                 let p = Point2::new(pos.x + x, pos.y + y);
@@ -359,202 +531,516 @@ impl Bitmap {
         self.colors.iter_mut().for_each(|x| x.scale(v));
     }
     pub fn average(&self) -> Color {
-        let mut s = Color::default();
-        self.colors.iter().for_each(|x| s += x);
+        let mut s = C::default();
+        self.colors.iter().for_each(|x| s += *x);
         s.scale(1.0 / self.colors.len() as f32);
-        s
+        s.to_color()
     }
 
-    // Get the pixel value at the given position
-    pub fn pixel_uv(&self, mut uv: Vector2<f32>) -> Color {
-        uv.x = uv.x.modulo(1.0);
-        uv.y = uv.y.modulo(1.0);
-        let (x, y) = (
-            (uv.x * self.size.x as f32) as usize,
-            (uv.y * self.size.y as f32) as usize,
-        );
-        let i = self.size.x as usize * y + x;
-        if i >= self.colors.len() {
-            warn!(
-                "Exceed UV coordinates: {:?} | {:?} | {:?}",
-                uv,
-                self.size,
-                (x, y)
-            );
-            Color::default()
-        } else {
-            self.colors[i]
-        }
-    }
     pub fn pixel(&self, p: Point2<u32>) -> Color {
         assert!(p.x < self.size.x);
         assert!(p.y < self.size.y);
-        self.colors[(p.y * self.size.x + p.x) as usize]
+        self.colors[(p.y * self.size.x + p.x) as usize].to_color()
     }
 
+    /// Single precision image, for the outputs
+    pub fn to_f32(&self) -> Cow<'_, Bitmap> {
+        C::to_f32(self)
+    }
+    pub fn from_f32(img: Bitmap) -> Bitmap<C> {
+        Bitmap {
+            size: img.size,
+            colors: img.colors.into_iter().map(C::from_color).collect(),
+            alpha: img.alpha,
+        }
+    }
+}
+
+/// Next field of a PFM header: the whitespace before it and the one after it are skipped
+fn read_pfm_field<R: Read>(f: &mut R) -> Result<String> {
+    let mut field = String::new();
+    let mut c = [0];
+    while f.read(&mut c)? == 1 {
+        if !c[0].is_ascii_whitespace() {
+            field.push(char::from(c[0]));
+        } else if !field.is_empty() {
+            break;
+        }
+    }
+    Ok(field)
+}
+
+impl Bitmap {
     // Save functions
+    /// Chromatic adaptation of all the pixels (see color::rgb_adaptation)
+    pub fn adapt_white_point(&mut self, src: WhitePoint, dst: WhitePoint) {
+        if src == dst {
+            return;
+        }
+        let m = rgb_adaptation(src, dst);
+        self.colors
+            .iter_mut()
+            .for_each(|c| *c = transform_color(&m, *c));
+    }
+
     #[cfg(not(feature = "image"))]
-    pub fn save_ldr_image(&self, _imgout_path_str: &str) {
-        panic!("Rustlight wasn't built with Image support.");
+    pub fn save_ldr_image(&self, _imgout_path_str: &str, _color_space: ColorSpace) -> Result<()> {
+        Err(Error::MissingFeature("image"))
     }
     #[cfg(feature = "image")]
-    pub fn save_ldr_image(&self, imgout_path_str: &str) {
+    pub fn save_ldr_image(&self, imgout_path_str: &str, color_space: ColorSpace) -> Result<()> {
         // The image that we will render
-        let mut image_ldr = DynamicImage::new_rgb8(self.size.x, self.size.y);
+        let mut image_ldr = if self.alpha.is_some() {
+            DynamicImage::new_rgba8(self.size.x, self.size.y)
+        } else {
+            DynamicImage::new_rgb8(self.size.x, self.size.y)
+        };
         for x in 0..self.size.x {
             for y in 0..self.size.y {
                 let p = Point2::new(x, y);
-                image_ldr.put_pixel(x, y, self.pixel(p).to_rgba())
+                let mut rgba = self.pixel(p).to_rgba(color_space);
+                rgba[3] = (self.alpha(p).clamp(0.0, 1.0) * 255.0).round() as u8;
+                image_ldr.put_pixel(x, y, rgba)
             }
         }
-        image_ldr
-            .save(&Path::new(imgout_path_str))
-            .expect("failed to write img into file");
+        image_ldr.save(Path::new(imgout_path_str))?;
+        Ok(())
     }
 
-    #[cfg(not(feature = "exr"))]
-    pub fn save_exr(&self, _imgout_path_str: &str) {
-        panic!("Rustlight wasn't built with OpenExr support.");
+    /// RGB(A) EXR (ZIP compressed), the scanlines are compressed directly from the pixels
+    pub fn save_exr(&self, imgout_path_str: &str) -> Result<()> {
+        crate::exr_writer::save_bitmap(self, imgout_path_str, &[])
     }
-    #[cfg(feature = "exr")]
-    pub fn save_exr(&self, imgout_path_str: &str) {
-
-        // Pixel data for floating point RGB image.
-        let mut pixel_data = Vec::with_capacity((self.size.x * self.size.y * 3) as usize);
-
-        // exrs TODO write without pre-allocating a buffer!
-        for y in 0..self.size.y {
-            for x in 0..self.size.x {
-                let rgb = self.pixel(Point2::new(x, y));
-                pixel_data.extend_from_slice(&[rgb.r, rgb.g, rgb.b]);
-            }
+    #[cfg(not(feature = "image"))]
+    pub fn save_hdr(&self, _imgout_path_str: &str) -> Result<()> {
+        Err(Error::MissingFeature("image"))
+    }
+    #[cfg(feature = "image")]
+    pub fn save_hdr(&self, imgout_path_str: &str) -> Result<()> {
+        // Radiance RGBE, values are stored linearly (no negative values)
+        let data = self
+            .colors
+            .iter()
+            .map(|c| image::Rgb([c.r.max(0.0), c.g.max(0.0), c.b.max(0.0)]))
+            .collect::<Vec<_>>();
+        let file = File::create(Path::new(imgout_path_str))?;
+        image::hdr::HDREncoder::new(BufWriter::new(file)).encode(
+            &data,
+            self.size.x as usize,
+            self.size.y as usize,
+        )?;
+        Ok(())
+    }
+
+    /// Values encoded by encode (the identity for linear values),
+    /// clamped to [0, 1] and quantized on 16 bits
+    fn to_u16(&self, encode: impl Fn(f32) -> f32) -> Vec<u16> {
+        let quantize = |v: f32| (encode(v.max(0.0)).min(1.0) * 65535.0 + 0.5) as u16;
+        let mut data = Vec::with_capacity((self.size.x * self.size.y * 3) as usize);
+        for c in &self.colors {
+            data.extend_from_slice(&[quantize(c.r), quantize(c.g), quantize(c.b)]);
         }
+        data
+    }
+    /// Same as to_u16 with the alpha channel after each pixel
+    fn to_u16_alpha(&self, encode: impl Fn(f32) -> f32) -> Vec<u16> {
+        let quantize = |v: f32| (v.clamp(0.0, 1.0) * 65535.0 + 0.5) as u16;
+        let mut data = Vec::with_capacity((self.size.x * self.size.y * 4) as usize);
+        for (i, rgb) in self.to_u16(encode).chunks(3).enumerate() {
+            data.extend_from_slice(rgb);
+            data.push(self.alpha.as_ref().map_or(65535, |a| quantize(a[i])));
+        }
+        data
+    }
 
-        // Create a file to write to.
-        use exr::prelude::*;
-
-        let output_file = rgba::Image::new(
-            Vec2(self.size.x as usize, self.size.y as usize),
-            false, true,
-            rgba::Pixels::F32(pixel_data)
-        );
-
-        // Write pixel data to the file.
-        output_file.write_to_file(imgout_path_str, write_options::default()).unwrap();
+    #[cfg(not(feature = "image"))]
+    pub fn save_png16(&self, _imgout_path_str: &str, _color_space: ColorSpace) -> Result<()> {
+        Err(Error::MissingFeature("image"))
     }
-    pub fn save(&self, imgout_path_str: &str) {
-        let output_ext = match std::path::Path::new(imgout_path_str).extension() {
-            None => panic!("No file extension provided"),
-            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
+    #[cfg(feature = "image")]
+    pub fn save_png16(&self, imgout_path_str: &str, color_space: ColorSpace) -> Result<()> {
+        let encode = |v: f32| color_space.encode(v);
+        // PNG stores 16 bits samples in big endian
+        let (values, color_type) = if self.alpha.is_some() {
+            (self.to_u16_alpha(encode), image::ColorType::RGBA(16))
+        } else {
+            (self.to_u16(encode), image::ColorType::RGB(16))
         };
-        match output_ext {
-            "pfm" => {
-                self.save_pfm(imgout_path_str);
-            }
-            "png" => {
-                self.save_ldr_image(imgout_path_str);
-            }
-            "exr" => {
-                self.save_exr(imgout_path_str);
+        let mut data = Vec::with_capacity(values.len() * 2);
+        for v in values {
+            data.write_u16::<BigEndian>(v)?;
+        }
+        let file = File::create(Path::new(imgout_path_str))?;
+        image::png::PNGEncoder::new(BufWriter::new(file)).encode(
+            &data,
+            self.size.x,
+            self.size.y,
+            color_type,
+        )?;
+        Ok(())
+    }
+
+    pub fn save_tiff16(&self, imgout_path_str: &str) -> Result<()> {
+        // Baseline uncompressed RGB(A) TIFF with a single strip:
+        // header | IFD | BitsPerSample values | pixels
+        let (nb_channels, nb_entries) = if self.alpha.is_some() { (4, 11) } else { (3, 10) };
+        let ifd_size = 2 + nb_entries * 12 + 4;
+        let bps_offset = 8 + ifd_size;
+        let data_offset = bps_offset + nb_channels * 2;
+        let data_size = self.size.x * self.size.y * nb_channels * 2;
+
+        let file = File::create(Path::new(imgout_path_str))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(b"II")?;
+        file.write_u16::<LittleEndian>(42)?;
+        file.write_u32::<LittleEndian>(8)?;
+
+        // Entries need to be sorted by tag
+        file.write_u16::<LittleEndian>(nb_entries as u16)?;
+        let entry = |file: &mut BufWriter<File>, tag: u16, short: bool, count: u32, value: u32| {
+            file.write_u16::<LittleEndian>(tag)?;
+            file.write_u16::<LittleEndian>(if short { 3 } else { 4 })?;
+            file.write_u32::<LittleEndian>(count)?;
+            if short && count == 1 {
+                // Inline SHORT values are left justified
+                file.write_u16::<LittleEndian>(value as u16)?;
+                file.write_u16::<LittleEndian>(0)
+            } else {
+                file.write_u32::<LittleEndian>(value)
             }
-            _ => panic!("Unknown output file extension"),
+        };
+        entry(&mut file, 256, false, 1, self.size.x)?; // ImageWidth
+        entry(&mut file, 257, false, 1, self.size.y)?; // ImageLength
+        entry(&mut file, 258, true, nb_channels, bps_offset)?; // BitsPerSample
+        entry(&mut file, 259, true, 1, 1)?; // Compression (none)
+        entry(&mut file, 262, true, 1, 2)?; // PhotometricInterpretation (RGB)
+        entry(&mut file, 273, false, 1, data_offset)?; // StripOffsets
+        entry(&mut file, 277, true, 1, nb_channels)?; // SamplesPerPixel
+        entry(&mut file, 278, false, 1, self.size.y)?; // RowsPerStrip
+        entry(&mut file, 279, false, 1, data_size)?; // StripByteCounts
+        entry(&mut file, 284, true, 1, 1)?; // PlanarConfiguration (chunky)
+        if self.alpha.is_some() {
+            // The colors are weighted by the coverage (associated alpha)
+            entry(&mut file, 338, true, 1, 1)?; // ExtraSamples
+        }
+        file.write_u32::<LittleEndian>(0)?; // No next IFD
+
+        for _ in 0..nb_channels {
+            file.write_u16::<LittleEndian>(16)?;
+        }
+        let values = if self.alpha.is_some() {
+            self.to_u16_alpha(|v| v)
+        } else {
+            self.to_u16(|v| v)
+        };
+        for v in values {
+            file.write_u16::<LittleEndian>(v)?;
+        }
+        Ok(())
+    }
+
+    /// The format is deduced from the extension, the png images are sRGB encoded on 8 bits
+    pub fn save(&self, imgout_path_str: &str) -> Result<()> {
+        self.save_with(imgout_path_str, ColorSpace::SRGB, false)
+    }
+    /// color_space: encoding of the png images, written on 8 bits or on 16 bits with png_16bits
+    /// (the other formats are linear)
+    pub fn save_with(
+        &self,
+        imgout_path_str: &str,
+        color_space: ColorSpace,
+        png_16bits: bool,
+    ) -> Result<()> {
+        match extension(imgout_path_str)? {
+            "pfm" => self.save_pfm(imgout_path_str),
+            "png" if png_16bits => self.save_png16(imgout_path_str, color_space),
+            "png" => self.save_ldr_image(imgout_path_str, color_space),
+            "exr" => self.save_exr(imgout_path_str),
+            "hdr" => self.save_hdr(imgout_path_str),
+            "tif" | "tiff" => self.save_tiff16(imgout_path_str),
+            ext => Err(Error::UnsupportedFormat(ext.to_string())),
         }
     }
 
-    pub fn save_pfm(&self, imgout_path_str: &str) {
-        let file = File::create(Path::new(imgout_path_str)).unwrap();
+    pub fn save_pfm(&self, imgout_path_str: &str) -> Result<()> {
+        let file = File::create(Path::new(imgout_path_str))?;
         let mut file = BufWriter::new(file);
         let header = format!("PF\n{} {}\n-1.0\n", self.size.x, self.size.y);
-        file.write_all(header.as_bytes()).unwrap();
+        file.write_all(header.as_bytes())?;
         for y in 0..self.size.y {
             for x in 0..self.size.x {
                 let p = self.pixel(Point2::new(x, self.size.y - y - 1));
-                file.write_f32::<LittleEndian>(p.r.abs()).unwrap();
-                file.write_f32::<LittleEndian>(p.g.abs()).unwrap();
-                file.write_f32::<LittleEndian>(p.b.abs()).unwrap();
+                file.write_f32::<LittleEndian>(p.r.abs())?;
+                file.write_f32::<LittleEndian>(p.g.abs())?;
+                file.write_f32::<LittleEndian>(p.b.abs())?;
             }
         }
+        Ok(())
     }
 
     // Load images
-    pub fn read_pfm(filename: &str) -> Self {
-        let f = File::open(Path::new(filename)).unwrap();
+    /// Color (PF) or grayscale (Pf) PFM image. The header fields can be separated by any whitespace,
+    /// the sign of the scale gives the endianness (negative: little endian)
+    /// and the values are multiplied by its absolute value.
+    pub fn read_pfm(filename: &str) -> Result<Self> {
+        let invalid = |msg: &str| Error::InvalidData(format!("{} in {}", msg, filename));
+        let f = File::open(Path::new(filename))?;
         let mut f = BufReader::new(f);
         // Check the flag
-        {
-            let mut header_str = String::new();
-            f.read_line(&mut header_str).unwrap();
-            if header_str != "PF\n" {
-                panic!("Wrong PF flag encounter");
-            }
-        }
+        let nb_channels = match read_pfm_field(&mut f)?.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid("wrong PF flag")),
+        };
         // Check the dim
         let size = {
-            let mut img_dim_y = String::new();
-            f.read_line(&mut img_dim_y).unwrap();
-            let mut img_dim_x = String::new();
-            f.read_line(&mut img_dim_x).unwrap();
-            Vector2::new(
-                img_dim_x.parse::<u32>().unwrap(),
-                img_dim_y.parse::<u32>().unwrap(),
-            )
+            let mut parse_dim = || -> Result<u32> {
+                read_pfm_field(&mut f)?
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|v| *v > 0)
+                    .ok_or_else(|| invalid("wrong PFM size"))
+            };
+            let x = parse_dim()?;
+            Vector2::new(x, parse_dim()?)
         };
+        let scale = read_pfm_field(&mut f)?
+            .parse::<f32>()
+            .ok()
+            .filter(|v| *v != 0.0 && v.is_finite())
+            .ok_or_else(|| invalid("wrong PFM scale"))?;
+
+        let mut values = vec![0.0; (size.x * size.y) as usize * nb_channels];
+        if scale < 0.0 {
+            f.read_f32_into::<LittleEndian>(&mut values)?;
+        } else {
+            f.read_f32_into::<BigEndian>(&mut values)?;
+        }
+        // The scanlines are stored from the bottom to the top
+        let colors = values
+            .chunks(size.x as usize * nb_channels)
+            .rev()
+            .flat_map(|row| row.chunks(nb_channels))
+            .map(|v| match v {
+                [r, g, b] => Color::new(*r, *g, *b),
+                _ => Color::value(v[0]),
+            })
+            .map(|c| c * scale.abs())
+            .collect();
+
+        Ok(Bitmap {
+            size,
+            colors,
+            alpha: None,
+        })
+    }
+
+    /// Read the main RGB(A) layer of an EXR file (the R, G, B and A channels without
+    /// a layer prefix): half, float or uint channels, any compression, scanlines or tiles.
+    /// A luminance (Y) image gives gray colors. The image covers the display window,
+    /// the pixels outside of the data window are black (and transparent).
+    pub fn read_exr(filename: &str) -> Result<Self> {
+        use exr::prelude::{traits::*, AnyChannels, FlatSamples, Layer, Vec2};
+        let invalid = |msg: &str| Error::InvalidData(format!("EXR {}: {}", filename, msg));
+        let image = read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_file(filename)?;
+
+        // First part with colors (multi-part files)
+        let has = |layer: &Layer<AnyChannels<FlatSamples>>, name: &str| {
+            layer.channel_data.list.iter().any(|c| c.name.eq(name))
+        };
+        let rgb = |layer: &Layer<AnyChannels<FlatSamples>>| {
+            ["R", "G", "B"].iter().all(|&c| has(layer, c))
+        };
+        let layer = image
+            .layer_data
+            .iter()
+            .find(|&l| rgb(l) || has(l, "Y"))
+            .ok_or_else(|| invalid("missing R, G and B (or Y) channels"))?;
+        if layer
+            .channel_data
+            .list
+            .iter()
+            .any(|c| c.sampling != Vec2(1, 1))
+        {
+            return Err(invalid("subsampled channels are not supported"));
+        }
+        let gray = !rgb(layer);
 
-        let mut colors = vec![Color::zero(); (size.x * size.y) as usize];
-        for y in 0..size.y {
-            for x in 0..size.x {
-                let r = f.read_f32::<LittleEndian>().unwrap();
-                let g = f.read_f32::<LittleEndian>().unwrap();
-                let b = f.read_f32::<LittleEndian>().unwrap();
-                //
-                let p = Point2::new(x, size.y - y - 1);
-                colors[(p.y * size.x + p.x) as usize] = Color::new(r, g, b);
+        let display_window = image.attributes.display_window;
+        let (w, h) = (display_window.size.0, display_window.size.1);
+        if w == 0 || h == 0 || layer.size.0 == 0 {
+            return Err(invalid("empty image"));
+        }
+        let nb_pixels = w * h;
+        let mut colors = vec![Color::zero(); nb_pixels];
+        let mut alpha = if has(layer, "A") {
+            Some(vec![0.0; nb_pixels])
+        } else {
+            None
+        };
+        // Data window inside the display window
+        let offset = (
+            i64::from(layer.attributes.layer_position.0) - i64::from(display_window.position.0),
+            i64::from(layer.attributes.layer_position.1) - i64::from(display_window.position.1),
+        );
+        for channel in &layer.channel_data.list {
+            // Components (0: R, 1: G, 2: B, 3: A) of the channel
+            let components: &[usize] = match channel.name.to_string().as_str() {
+                "R" => &[0],
+                "G" => &[1],
+                "B" => &[2],
+                "Y" if gray => &[0, 1, 2],
+                "A" => &[3],
+                _ => continue,
+            };
+            for (i, v) in channel.sample_data.values_as_f32().enumerate() {
+                let x = (i % layer.size.0) as i64 + offset.0;
+                let y = (i / layer.size.0) as i64 + offset.1;
+                if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 {
+                    continue;
+                }
+                let p = y as usize * w + x as usize;
+                for c in components {
+                    match c {
+                        0 => colors[p].r = v,
+                        1 => colors[p].g = v,
+                        2 => colors[p].b = v,
+                        _ => alpha.as_mut().unwrap()[p] = v,
+                    }
+                }
             }
         }
-
-        Bitmap { size, colors }
+        Ok(Bitmap {
+            size: Vector2::new(w as u32, h as u32),
+            colors,
+            alpha,
+        })
     }
 
-    #[cfg(not(feature = "exr"))]
-    pub fn read_exr(_filename: &str) -> Self {
-        panic!("Rustlight wasn't built with OpenEXR support");
+    #[cfg(not(feature = "image"))]
+    pub fn read_hdr(_filename: &str) -> Result<Self> {
+        Err(Error::MissingFeature("image"))
+    }
+    #[cfg(feature = "image")]
+    pub fn read_hdr(filename: &str) -> Result<Self> {
+        let f = File::open(Path::new(filename))?;
+        let decoder = image::hdr::HDRDecoder::new(BufReader::new(f))?;
+        let meta = decoder.metadata();
+        let size = Vector2::new(meta.width, meta.height);
+        let colors = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|p| Color::new(p[0], p[1], p[2]))
+            .collect();
+        Ok(Bitmap {
+            size,
+            colors,
+            alpha: None,
+        })
+    }
+
+    /// Build a bitmap from 16 bits samples (RGB or RGBA),
+    /// the values are kept as stored (not decoded), as for the 8 bits images.
+    fn from_u16(size: Vector2<u32>, nb_channels: usize, data: &[u16]) -> Self {
+        let colors = data
+            .chunks(nb_channels)
+            .map(|p| {
+                Color::new(
+                    f32::from(p[0]) / 65535.0,
+                    f32::from(p[1]) / 65535.0,
+                    f32::from(p[2]) / 65535.0,
+                )
+            })
+            .collect();
+        let alpha = if nb_channels == 4 {
+            Some(
+                data.chunks(4)
+                    .map(|p| f32::from(p[3]) / 65535.0)
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        Bitmap {
+            size,
+            colors,
+            alpha,
+        }
     }
 
-    #[cfg(feature = "exr")]
-    pub fn read_exr(filename: &str) -> Self {
-        use exr::prelude::*;
-
-        // Open the EXR file.
-        // exrs TODO does not support all compression types
-        // exrs TODO write without pre-allocating a buffer but instead take a closure that convers the stuff
-        let image = rgba::Image::read_from_file(filename, read_options::default()).unwrap();
-
-        debug_assert!(!image.has_alpha_channel); // exrs TODO add simple pixel iterator!
-
-        match image.data {
-            rgba::Pixels::F32(data) => {
-                let colors = image.data.into_iter()
-                    .windows(3)
-                    .map(|color| Color::new(v[0], v[1], v[2]))
-                    .collect();
-
-                Bitmap { size, colors }
-            },
+    /// Returns None if the image is not encoded in 16 bits RGB(A),
+    /// as these images are not supported by image::open
+    #[cfg(not(feature = "png"))]
+    fn read_png16(_filename: &str) -> Option<Self> {
+        None
+    }
+    #[cfg(feature = "png")]
+    fn read_png16(filename: &str) -> Option<Self> {
+        let f = File::open(Path::new(filename)).ok()?;
+        // Keep the 16 bits samples (stripped by default)
+        let mut decoder = png::Decoder::new(BufReader::new(f));
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info().ok()?;
+        let nb_channels = match (info.color_type, info.bit_depth) {
+            (png::ColorType::RGB, png::BitDepth::Sixteen) => 3,
+            (png::ColorType::RGBA, png::BitDepth::Sixteen) => 4,
+            _ => return None,
+        };
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).ok()?;
+        // PNG samples are big endian
+        let data = buf
+            .chunks(2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .collect::<Vec<_>>();
+        let size = Vector2::new(info.width, info.height);
+        Some(Bitmap::from_u16(size, nb_channels, &data))
+    }
 
-            _ => unimplemented!("only f32 data for now")
-        }
+    #[cfg(feature = "image")]
+    fn read_tiff16(filename: &str) -> Option<Self> {
+        use image::{ColorType, ImageDecoder};
+        let f = File::open(Path::new(filename)).ok()?;
+        let decoder = image::tiff::TIFFDecoder::new(BufReader::new(f)).ok()?;
+        let nb_channels = match decoder.colortype() {
+            ColorType::RGB(16) => 3,
+            ColorType::RGBA(16) => 4,
+            _ => return None,
+        };
+        let (w, h) = decoder.dimensions();
+        // Samples are already converted to the native endianness
+        let data = decoder
+            .read_image()
+            .ok()?
+            .chunks(2)
+            .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+            .collect::<Vec<_>>();
+        let size = Vector2::new(w as u32, h as u32);
+        Some(Bitmap::from_u16(size, nb_channels, &data))
     }
 
     #[cfg(not(feature = "image"))]
-    pub fn read_ldr_image(_filename: &str) -> Self {
-        panic!("Rustlight wasn't built with image support");
-        Bitmap::default()
+    pub fn read_ldr_image(_filename: &str) -> Result<Self> {
+        Err(Error::MissingFeature("image"))
     }
     #[cfg(feature = "image")]
-    pub fn read_ldr_image(filename: &str) -> Self {
+    pub fn read_ldr_image(filename: &str) -> Result<Self> {
+        let img16 = match Path::new(filename).extension().and_then(|v| v.to_str()) {
+            Some("png") => Bitmap::read_png16(filename),
+            Some("tif") | Some("tiff") => Bitmap::read_tiff16(filename),
+            _ => None,
+        };
+        if let Some(img) = img16 {
+            return Ok(img);
+        }
         // The image that we will render
-        let image_ldr = image::open(filename)
-            .unwrap_or_else(|_| panic!("Impossible to read image: {}", filename));
+        let image_ldr = image::open(filename)?;
         let image_ldr = image_ldr.to_rgb();
         let size = Vector2::new(image_ldr.width(), image_ldr.height());
         let mut colors = vec![Color::zero(); (size.x * size.y) as usize];
@@ -569,23 +1055,49 @@ impl Bitmap {
             }
         }
 
-        Bitmap { size, colors }
+        Ok(Bitmap {
+            size,
+            colors,
+            alpha: None,
+        })
     }
 
-    pub fn read(filename: &str) -> Self {
-        let ext = match std::path::Path::new(filename).extension() {
-            None => panic!("No file extension provided"),
-            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
-        };
-        match ext {
+    /// Read the stored values, without any decoding
+    pub fn read(filename: &str) -> Result<Self> {
+        match extension(filename)? {
             "pfm" => Bitmap::read_pfm(filename),
             "exr" => Bitmap::read_exr(filename),
+            "hdr" => Bitmap::read_hdr(filename),
             _ => {
                 // Try the default implementation support
                 Bitmap::read_ldr_image(filename)
             }
         }
     }
+
+    /// Read the image as linear sRGB values (D65).
+    /// color_space: encoding of the values (deduced from the extension if not set)
+    /// white_point: white of the image, adapted to D65 if set
+    pub fn read_linear(
+        filename: &str,
+        color_space: Option<ColorSpace>,
+        white_point: Option<WhitePoint>,
+    ) -> Result<Self> {
+        let mut img = Bitmap::read(filename)?;
+        let color_space = match color_space {
+            Some(v) => v,
+            None => ColorSpace::from_extension(filename)?,
+        };
+        if color_space != ColorSpace::Linear {
+            img.colors
+                .iter_mut()
+                .for_each(|c| *c = color_space.decode_color(*c));
+        }
+        if let Some(white_point) = white_point {
+            img.adapt_white_point(white_point, WhitePoint::D65);
+        }
+        Ok(img)
+    }
 }
 // By default, create a black image
 impl Default for Bitmap {
@@ -593,6 +1105,7 @@ impl Default for Bitmap {
         Bitmap {
             size: Vector2::new(1, 1),
             colors: vec![Color::zero()],
+            alpha: None,
         }
     }
 }
@@ -607,14 +1120,74 @@ pub struct Ray {
 }
 
 impl Ray {
+    /// Rays leaving a surface need to be created with Ray::spawn
     pub fn new(o: Point3<f32>, d: Vector3<f32>) -> Ray {
         Ray {
             o,
             d,
-            tnear: constants::EPSILON,
+            tnear: 0.0,
             tfar: std::f32::MAX,
         }
     }
+
+    /// Ray leaving a surface point (see offset_ray_origin)
+    pub fn spawn(
+        p: Point3<f32>,
+        p_error: Vector3<f32>,
+        n: Vector3<f32>,
+        d: Vector3<f32>,
+    ) -> Ray {
+        Ray::new(offset_ray_origin(p, p_error, n, d), d)
+    }
+}
+
+/// Bound of the relative error after n floating point operations
+/// (from "Physically Based Rendering", 3rd edition)
+pub fn gamma(n: i32) -> f32 {
+    let e = f32::EPSILON * 0.5;
+    (n as f32 * e) / (1.0 - n as f32 * e)
+}
+
+/// Error bound of a point interpolated with barycentric coordinates
+pub fn barycentric_error(b: [f32; 3], v: [Vector3<f32>; 3]) -> Vector3<f32> {
+    let abs = |v: Vector3<f32>| Vector3::new(v.x.abs(), v.y.abs(), v.z.abs());
+    (abs(v[0] * b[0]) + abs(v[1] * b[1]) + abs(v[2] * b[2])) * gamma(7)
+}
+
+fn next_float_up(v: f32) -> f32 {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    f32::from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+fn next_float_down(v: f32) -> f32 {
+    -next_float_up(-v)
+}
+
+/// Move a surface point outside of its error bounds along the normal (on the side of d).
+/// The rays starting from the returned point cannot intersect the surface again
+/// whatever the scale of the scene.
+pub fn offset_ray_origin(
+    p: Point3<f32>,
+    p_error: Vector3<f32>,
+    n: Vector3<f32>,
+    d: Vector3<f32>,
+) -> Point3<f32> {
+    let dist = n.x.abs() * p_error.x + n.y.abs() * p_error.y + n.z.abs() * p_error.z;
+    let offset = if d.dot(n) < 0.0 { -n * dist } else { n * dist };
+    let mut po = p + offset;
+    // Round away from p
+    for i in 0..3 {
+        if offset[i] > 0.0 {
+            po[i] = next_float_up(po[i]);
+        } else if offset[i] < 0.0 {
+            po[i] = next_float_down(po[i]);
+        }
+    }
+    po
 }
 
 // Some function based on vectors
@@ -642,7 +1215,7 @@ fn vec_min_coords(v: Vector3<f32>) -> f32 {
     v.x.min(v.y.min(v.z))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct AABB {
     pub p_min: Vector3<f32>,
     pub p_max: Vector3<f32>,
@@ -680,6 +1253,33 @@ impl AABB {
         self.size() * 0.5 + self.p_min
     }
 
+    /// No point added to the box
+    pub fn is_empty(&self) -> bool {
+        self.p_min.x > self.p_max.x
+    }
+
+    /// Sphere centered on the box, unit sphere at the origin for an empty box
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        if self.is_empty() {
+            return BoundingSphere::default();
+        }
+        BoundingSphere {
+            center: Point3::from_vec(self.center()),
+            radius: self.size().magnitude() * 0.5,
+        }
+    }
+
+    /// Squared distance from p to the box (0 inside)
+    pub fn distance2(&self, p: &Point3<f32>) -> f32 {
+        let d = |x: f32, min: f32, max: f32| (min - x).max(x - max).max(0.0);
+        let v = Vector3::new(
+            d(p.x, self.p_min.x, self.p_max.x),
+            d(p.y, self.p_min.y, self.p_max.y),
+            d(p.z, self.p_min.z, self.p_max.z),
+        );
+        v.magnitude2()
+    }
+
     pub fn intersect(&self, r: &Ray) -> Option<f32> {
         // TODO: direction inverse could be precomputed
         let t_0 = vec_div(&(self.p_min - r.o.to_vec()), &r.d);
@@ -699,6 +1299,66 @@ impl AABB {
     }
 }
 
+/// Sphere bounding the scene. It gives an area measure to the distant emitters
+/// (environment): their points are on the disk of the sphere facing the emission direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        Self {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+        }
+    }
+}
+
+impl BoundingSphere {
+    /// Area of the disk of the sphere perpendicular to a direction
+    pub fn disk_area(&self) -> f32 {
+        std::f32::consts::PI * self.radius * self.radius
+    }
+
+    /// Area of the sphere
+    pub fn area(&self) -> f32 {
+        4.0 * self.disk_area()
+    }
+
+    /// Point outside of the sphere from p in the direction d (end of the shadow rays
+    /// toward the distant emitters)
+    pub fn outside_point(&self, p: &Point3<f32>, d: Vector3<f32>) -> Point3<f32> {
+        let dist = 2.0 * (self.radius + (p - self.center).magnitude());
+        p + d * dist
+    }
+}
+
+/// Coordinates of a texture lookup: the uv coordinates,
+/// the position and the shading normal (world space) for the projections
+/// and the triangle with the barycentric coordinates for the per-face textures.
+/// width is the footprint of the lookup in the uv space (0 for the finest level)
+#[derive(Clone, Copy, Debug)]
+pub struct TexCoords {
+    pub uv: Option<Vector2<f32>>,
+    pub p: Point3<f32>,
+    pub n: Vector3<f32>,
+    pub face: Option<(usize, Vector2<f32>)>,
+    pub width: f32,
+}
+impl Default for TexCoords {
+    fn default() -> Self {
+        TexCoords {
+            uv: None,
+            p: Point3::origin(),
+            n: Vector3::new(0.0, 0.0, 0.0),
+            face: None,
+            width: 0.0,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Intersection<'a> {
     /// Intersection distance
@@ -709,14 +1369,22 @@ pub struct Intersection<'a> {
     pub n_s: Vector3<f32>,
     /// Intersection point
     pub p: Point3<f32>,
+    /// Floating point error bound of p
+    pub p_error: Vector3<f32>,
     /// Textures coordinates
     pub uv: Option<Vector2<f32>>,
+    /// Index of the triangle inside the mesh
+    pub face: usize,
+    /// Barycentric coordinates inside the triangle (weights of its second and third vertices)
+    pub face_uv: Vector2<f32>,
     /// Mesh which we have intersected
     pub mesh: &'a Mesh,
     /// Frame from the intersection point
     pub frame: Frame,
     /// Incomming direction in the local coordinates
     pub wi: Vector3<f32>,
+    /// Footprint in the uv space (0 without ray differentials: finest level)
+    pub uv_width: f32,
 }
 
 impl<'a> Intersection<'a> {
@@ -729,32 +1397,333 @@ impl<'a> Intersection<'a> {
     pub fn to_world(&self, d: &Vector3<f32>) -> Vector3<f32> {
         self.frame.to_world(*d)
     }
+    /// Texture lookup at the intersection point (BSDF parameters)
+    pub fn tex_coords(&self) -> TexCoords {
+        TexCoords {
+            uv: self.uv,
+            p: self.p,
+            n: self.n_s,
+            face: Some((self.face, self.face_uv)),
+            width: self.uv_width,
+        }
+    }
+    /// Footprint of a camera ray in the uv space from its differentials,
+    /// to filter the textures at the first intersection
+    pub fn set_differentials(&mut self, differentials: &RayDifferentials) {
+        self.uv_width = self.footprint(differentials).unwrap_or(0.0);
+    }
+    fn footprint(&self, differentials: &RayDifferentials) -> Option<f32> {
+        let uvs = self.mesh.uv.as_ref()?;
+        // Offsets of the differential rays on the tangent plane
+        let plane = self.n_g.dot(self.p.to_vec());
+        let offset = |r: &Ray| {
+            let t = (plane - self.n_g.dot(r.o.to_vec())) / self.n_g.dot(r.d);
+            if t.is_finite() {
+                Some(r.o + r.d * t - self.p)
+            } else {
+                None
+            }
+        };
+        let dpdx = offset(&differentials.dx)?;
+        let dpdy = offset(&differentials.dy)?;
+
+        // Partial derivatives of the position along u and v
+        let index = self.mesh.indices[self.face];
+        let dp02 = self.mesh.vertices[index.x] - self.mesh.vertices[index.z];
+        let dp12 = self.mesh.vertices[index.y] - self.mesh.vertices[index.z];
+        let duv02 = uvs[index.x] - uvs[index.z];
+        let duv12 = uvs[index.y] - uvs[index.z];
+        let det = duv02.x * duv12.y - duv02.y * duv12.x;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let dpdu = (dp02 * duv12.y - dp12 * duv02.y) / det;
+        let dpdv = (dp12 * duv02.x - dp02 * duv12.x) / det;
+
+        // Least squares solution of dp = dpdu * du + dpdv * dv
+        let (a00, a01, a11) = (dpdu.dot(dpdu), dpdu.dot(dpdv), dpdv.dot(dpdv));
+        let det = a00 * a11 - a01 * a01;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let duv = |dp: Vector3<f32>| {
+            let (b0, b1) = (dpdu.dot(dp), dpdv.dot(dp));
+            Vector2::new((a11 * b0 - a01 * b1) / det, (a00 * b1 - a01 * b0) / det)
+        };
+        let (duvdx, duvdy) = (duv(dpdx), duv(dpdy));
+        let width = duvdx
+            .x
+            .abs()
+            .max(duvdx.y.abs())
+            .max(duvdy.x.abs())
+            .max(duvdy.y.abs());
+        if width.is_finite() {
+            Some(width)
+        } else {
+            None
+        }
+    }
+    /// Ray leaving the intersection point
+    pub fn spawn_ray(&self, d: Vector3<f32>) -> Ray {
+        Ray::spawn(self.p, self.p_error, self.n_g, d)
+    }
+    /// Origin of the rays leaving the intersection point toward p
+    /// (used for the visibility tests)
+    pub fn offset_toward(&self, p: &Point3<f32>) -> Point3<f32> {
+        offset_ray_origin(self.p, self.p_error, self.n_g, p - self.p)
+    }
 }
 
-#[derive(Clone, Debug, Copy)]
-pub struct VarianceEstimator {
-    pub mean: f32,
-    pub mean_sqr: f32,
-    pub sample_count: u32,
+/// Per-pixel running mean, sum of squared differences (M2) and sample count
+/// (Welford's algorithm). Used for the progressive rendering and the variance buffers.
+#[derive(Clone, Debug)]
+pub struct AccumBuffer {
+    pub size: Vector2<u32>,
+    mean: Vec<FilmColor>,
+    m2: Vec<FilmColor>,
+    count: Vec<u32>,
 }
-impl VarianceEstimator {
-    fn add(&mut self, v: f32) {
-        self.sample_count += 1;
-        let delta = v - self.mean;
-        self.mean += delta / self.sample_count as f32;
-        self.mean_sqr += delta * (v - self.mean);
+impl AccumBuffer {
+    pub fn new(size: Vector2<u32>) -> AccumBuffer {
+        let nb_pixels = (size.x * size.y) as usize;
+        AccumBuffer {
+            size,
+            mean: vec![FilmColor::zero(); nb_pixels],
+            m2: vec![FilmColor::zero(); nb_pixels],
+            count: vec![0; nb_pixels],
+        }
+    }
+    pub fn clear(&mut self) {
+        self.mean.iter_mut().for_each(|x| *x = FilmColor::zero());
+        self.m2.iter_mut().for_each(|x| *x = FilmColor::zero());
+        self.count.iter_mut().for_each(|x| *x = 0);
+    }
+    fn index(&self, p: Point2<u32>) -> usize {
+        assert!(p.x < self.size.x);
+        assert!(p.y < self.size.y);
+        (p.y * self.size.x + p.x) as usize
+    }
+
+    /// Add a sample to the pixel p
+    pub fn add(&mut self, p: Point2<u32>, c: Color) {
+        let i = self.index(p);
+        self.add_index(i, FilmColor::from_color(c));
+    }
+    fn add_index(&mut self, i: usize, c: FilmColor) {
+        self.count[i] += 1;
+        let delta = c - self.mean[i];
+        self.mean[i] += delta / self.count[i] as FilmFloat;
+        self.m2[i] += delta * (c - self.mean[i]);
+    }
+    /// Add each pixel of the bitmap as a new sample
+    pub fn add_bitmap(&mut self, o: &Bitmap<FilmColor>) {
+        assert_eq!(self.size, o.size);
+        for (i, c) in o.colors.iter().enumerate() {
+            self.add_index(i, *c);
+        }
+    }
+    /// Merge the statistics of another buffer (Chan et al.)
+    /// pos: Position where to merge the buffer
+    pub fn merge(&mut self, o: &AccumBuffer, pos: Point2<u32>) {
+        for y in 0..o.size.y {
+            for x in 0..o.size.x {
+                let i = self.index(Point2::new(pos.x + x, pos.y + y));
+                let j = o.index(Point2::new(x, y));
+                if o.count[j] == 0 {
+                    continue;
+                }
+                let (n_a, n_b) = (self.count[i] as FilmFloat, o.count[j] as FilmFloat);
+                let n = n_a + n_b;
+                let delta = o.mean[j] - self.mean[i];
+                self.mean[i] += delta * (n_b / n);
+                self.m2[i] += o.m2[j] + delta * delta * (n_a * n_b / n);
+                self.count[i] += o.count[j];
+            }
+        }
     }
 
-    fn variance(&self) -> f32 {
-        self.mean_sqr / (self.sample_count - 1) as f32
+    pub fn count(&self, p: Point2<u32>) -> u32 {
+        self.count[self.index(p)]
+    }
+    pub fn mean(&self, p: Point2<u32>) -> Color {
+        self.mean[self.index(p)].to_color()
+    }
+    /// Unbiased sample variance (0 if not enough samples)
+    pub fn variance(&self, p: Point2<u32>) -> Color {
+        self.variance_index(self.index(p)).to_color()
+    }
+    fn variance_index(&self, i: usize) -> FilmColor {
+        if self.count[i] < 2 {
+            FilmColor::zero()
+        } else {
+            self.m2[i] / (self.count[i] - 1) as FilmFloat
+        }
+    }
+
+    fn to_bitmap<F: Fn(usize) -> FilmColor>(&self, f: F) -> Bitmap<FilmColor> {
+        Bitmap {
+            size: self.size,
+            colors: (0..self.count.len()).map(f).collect(),
+            alpha: None,
+        }
+    }
+    pub fn mean_bitmap(&self) -> Bitmap<FilmColor> {
+        self.to_bitmap(|i| self.mean[i])
+    }
+    pub fn variance_bitmap(&self) -> Bitmap<FilmColor> {
+        self.to_bitmap(|i| self.variance_index(i))
+    }
+    pub fn count_bitmap(&self) -> Bitmap<FilmColor> {
+        self.to_bitmap(|i| FilmColor::from_color(Color::value(self.count[i] as f32)))
     }
 }
-impl Default for VarianceEstimator {
-    fn default() -> Self {
-        Self {
-            mean: 0.0,
-            mean_sqr: 0.0,
-            sample_count: 0,
+
+/// Robust pixel estimate rejecting the outlier samples (fireflies) instead of clamping them:
+/// geometric median of the means of groups of consecutive samples (GMoM).
+/// The more groups, the more outliers are rejected, but the closer the estimate is
+/// to the (biased) median of the samples.
+#[derive(Clone, Copy, Debug)]
+pub struct SampleRejection {
+    /// Probability in ]0, 1[ that the estimate is not spoiled by the outliers.
+    /// It gives ceil(8 ln(1 / (1 - confidence))) groups (37 groups for 0.99)
+    pub confidence: f32,
+}
+impl SampleRejection {
+    /// Number of groups for the samples of a pixel (at least one sample per group)
+    pub fn nb_groups(&self, nb_samples: usize) -> usize {
+        let nb_groups = (8.0 * (1.0 / (1.0 - self.confidence)).ln()).ceil() as usize;
+        nb_groups.max(1).min(nb_samples.max(1))
+    }
+
+    /// Estimate of the pixel from its samples, in the order of the sampler
+    /// (the consecutive samples are grouped to keep their stratification)
+    pub fn estimate(&self, samples: &[Color]) -> Color {
+        if samples.is_empty() {
+            return Color::zero();
+        }
+        let nb_samples = samples.len();
+        let nb_groups = self.nb_groups(nb_samples);
+        let means = (0..nb_groups)
+            .map(|g| {
+                let group = &samples[g * nb_samples / nb_groups..(g + 1) * nb_samples / nb_groups];
+                group.iter().fold(Color::zero(), |acc, c| acc + *c) / group.len() as f32
+            })
+            .collect::<Vec<_>>();
+        geometric_median(&means)
+    }
+}
+
+fn distance(a: Color, b: Color) -> f32 {
+    let d = a - b;
+    (d.r * d.r + d.g * d.g + d.b * d.b).sqrt()
+}
+
+/// Point minimizing the sum of the RGB distances to the colors (Weiszfeld iterations)
+pub fn geometric_median(colors: &[Color]) -> Color {
+    let mut median = colors.iter().fold(Color::zero(), |acc, c| acc + *c) / colors.len() as f32;
+    for _ in 0..64 {
+        let (mut sum, mut sum_weights) = (Color::zero(), 0.0);
+        for c in colors {
+            // Bounded weight when the estimate is on one of the colors
+            let w = 1.0 / distance(*c, median).max(1e-6);
+            sum += *c * w;
+            sum_weights += w;
+        }
+        let next = sum / sum_weights;
+        let step = distance(next, median);
+        median = next;
+        if step <= 1e-5 * distance(median, Color::zero()) {
+            break;
+        }
+    }
+    median
+}
+
+/// Image shared by the rendering threads for the contributions that land at arbitrary pixels
+/// (light tracing, BDPT t=1 connections, MLT), unlike the tile-local buffers.
+/// The channels are stored as FilmFloat bits and accumulated with compare-and-swap loops.
+pub struct SplatBuffer {
+    pub size: Vector2<u32>,
+    values: Vec<[AtomicFilm; 3]>,
+}
+#[cfg(not(feature = "f64-film"))]
+type AtomicFilm = AtomicU32;
+#[cfg(feature = "f64-film")]
+type AtomicFilm = AtomicU64;
+
+impl SplatBuffer {
+    pub fn new(size: Vector2<u32>) -> SplatBuffer {
+        let nb_pixels = (size.x * size.y) as usize;
+        SplatBuffer {
+            size,
+            values: (0..nb_pixels)
+                .map(|_| [AtomicFilm::new(0), AtomicFilm::new(0), AtomicFilm::new(0)])
+                .collect(),
+        }
+    }
+    pub fn clear(&mut self) {
+        for v in self.values.iter_mut().flatten() {
+            *v.get_mut() = 0;
+        }
+    }
+
+    fn atomic_add(a: &AtomicFilm, v: FilmFloat) {
+        let mut current = a.load(Ordering::Relaxed);
+        loop {
+            let new = (FilmFloat::from_bits(current) + v).to_bits();
+            match a.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(x) => current = x,
+            }
+        }
+    }
+
+    /// Add a contribution to the pixel p (can be called concurrently)
+    pub fn add(&self, p: Point2<u32>, c: Color) {
+        assert!(p.x < self.size.x);
+        assert!(p.y < self.size.y);
+        let pixel = &self.values[(p.y * self.size.x + p.x) as usize];
+        let c = FilmColor::from_color(c);
+        for (a, v) in pixel.iter().zip(&[c.r, c.g, c.b]) {
+            if *v != 0.0 {
+                SplatBuffer::atomic_add(a, *v);
+            }
+        }
+    }
+    /// Same as add, the contributions outside the image are ignored
+    pub fn add_safe(&self, p: Point2<i32>, c: Color) {
+        if p.x >= 0 && p.y >= 0 && p.x < (self.size.x as i32) && p.y < (self.size.y as i32) {
+            self.add(Point2::new(p.x as u32, p.y as u32), c);
+        }
+    }
+
+    fn value(&self, i: usize) -> FilmColor {
+        let pixel = &self.values[i];
+        FilmColor::new(
+            FilmFloat::from_bits(pixel[0].load(Ordering::Relaxed)),
+            FilmFloat::from_bits(pixel[1].load(Ordering::Relaxed)),
+            FilmFloat::from_bits(pixel[2].load(Ordering::Relaxed)),
+        )
+    }
+    pub fn pixel(&self, p: Point2<u32>) -> Color {
+        self.value((p.y * self.size.x + p.x) as usize).to_color()
+    }
+    /// Value of the pixel of index i, which is reset to 0
+    /// (the buffer can be reused without clear)
+    pub fn take(&self, i: usize) -> FilmColor {
+        let pixel = &self.values[i];
+        FilmColor::new(
+            FilmFloat::from_bits(pixel[0].swap(0, Ordering::Relaxed)),
+            FilmFloat::from_bits(pixel[1].swap(0, Ordering::Relaxed)),
+            FilmFloat::from_bits(pixel[2].swap(0, Ordering::Relaxed)),
+        )
+    }
+    /// Copy of the splatted values (to be called once the threads are done)
+    pub fn to_bitmap(&self) -> Bitmap<FilmColor> {
+        Bitmap {
+            size: self.size,
+            colors: (0..self.values.len()).map(|i| self.value(i)).collect(),
+            alpha: None,
         }
     }
 }
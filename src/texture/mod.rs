@@ -0,0 +1,461 @@
+use crate::color::{ColorSpace, WhitePoint};
+use crate::error::{Error, Result};
+use crate::structure::{Bitmap, Color, TexCoords};
+use crate::texture::noise::*;
+use crate::texture::ptex::PtexTexture;
+use crate::texture::udim::*;
+use cgmath::{Point2, Vector2, Vector3};
+use serde_json;
+
+pub mod noise;
+pub mod ptex;
+pub mod udim;
+
+/// Spatially varying color (BSDF parameters)
+pub trait Texture: Send + Sync {
+    /// Value at the texture coordinates uv.
+    /// width: size of the lookup footprint in the uv space (0 for the finest details)
+    fn eval(&self, uv: Vector2<f32>, width: f32) -> Color;
+    /// Value at a shading point: lookup at its uv coordinates (None without them).
+    /// The projections use its position and normal instead.
+    fn eval_at(&self, coords: &TexCoords, width: f32) -> Option<Color> {
+        coords.uv.map(|uv| self.eval(uv, width))
+    }
+    /// Bytes of the texel data (0 for the procedural textures)
+    fn memory(&self) -> usize {
+        0
+    }
+}
+
+/// Behavior of the bitmap lookups outside of [0, 1]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Mirror,
+    Clamp,
+}
+impl WrapMode {
+    /// Texel index inside [0, size)
+    fn apply(self, i: i64, size: u32) -> u32 {
+        let size = i64::from(size);
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.max(0).min(size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i >= size {
+                    2 * size - 1 - i
+                } else {
+                    i
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Bilinear lookups inside the two closest mipmap levels
+    Trilinear,
+}
+
+/// Scale, rotation (degrees) then offset of the texture coordinates
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct UVTransform {
+    pub scale: Vector2<f32>,
+    pub rotation: f32,
+    pub offset: Vector2<f32>,
+}
+impl Default for UVTransform {
+    fn default() -> Self {
+        UVTransform {
+            scale: Vector2::new(1.0, 1.0),
+            rotation: 0.0,
+            offset: Vector2::new(0.0, 0.0),
+        }
+    }
+}
+impl UVTransform {
+    pub fn apply(&self, uv: Vector2<f32>) -> Vector2<f32> {
+        let uv = Vector2::new(uv.x * self.scale.x, uv.y * self.scale.y);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        Vector2::new(uv.x * cos - uv.y * sin, uv.x * sin + uv.y * cos) + self.offset
+    }
+    /// Footprint after the transformation (the largest scale is kept)
+    fn width(&self, width: f32) -> f32 {
+        width * self.scale.x.abs().max(self.scale.y.abs())
+    }
+}
+
+pub struct ConstantTexture {
+    pub color: Color,
+}
+impl Texture for ConstantTexture {
+    fn eval(&self, _uv: Vector2<f32>, _width: f32) -> Color {
+        self.color
+    }
+}
+
+/// Image texture with its mipmap pyramid (box filtered)
+pub struct BitmapTexture {
+    /// The first level is the full resolution image
+    pub levels: Vec<Bitmap>,
+    pub filter: Filter,
+    pub wrap: WrapMode,
+    pub uv_transform: UVTransform,
+}
+
+impl BitmapTexture {
+    pub fn new(img: Bitmap, filter: Filter, wrap: WrapMode) -> BitmapTexture {
+        let mut levels = vec![img];
+        loop {
+            let last = levels.last().unwrap();
+            if last.size.x == 1 && last.size.y == 1 {
+                break;
+            }
+            let next = downsample(last, wrap);
+            levels.push(next);
+        }
+        BitmapTexture {
+            levels,
+            filter,
+            wrap,
+            uv_transform: UVTransform::default(),
+        }
+    }
+
+    /// Load an image with the default parameters.
+    /// The LDR images (png, jpg, ...) are considered sRGB encoded.
+    pub fn load(path: &str) -> Result<BitmapTexture> {
+        Self::load_with(path, None, None, Filter::default(), WrapMode::default())
+    }
+
+    /// color_space and white_point: see Bitmap::read_linear
+    pub fn load_with(
+        path: &str,
+        color_space: Option<ColorSpace>,
+        white_point: Option<WhitePoint>,
+        filter: Filter,
+        wrap: WrapMode,
+    ) -> Result<BitmapTexture> {
+        let img = Bitmap::read_linear(path, color_space, white_point)?;
+        Ok(BitmapTexture::new(img, filter, wrap))
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Color {
+        let img = &self.levels[level];
+        img.pixel(Point2::new(
+            self.wrap.apply(x, img.size.x),
+            self.wrap.apply(y, img.size.y),
+        ))
+    }
+
+    fn nearest(&self, level: usize, uv: Vector2<f32>) -> Color {
+        let size = self.levels[level].size;
+        self.texel(
+            level,
+            (uv.x * size.x as f32).floor() as i64,
+            (uv.y * size.y as f32).floor() as i64,
+        )
+    }
+
+    fn bilinear(&self, level: usize, uv: Vector2<f32>) -> Color {
+        let size = self.levels[level].size;
+        // Texel centers are at half integers
+        let x = uv.x * size.x as f32 - 0.5;
+        let y = uv.y * size.y as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.texel(level, x0, y0) * ((1.0 - fx) * (1.0 - fy))
+            + self.texel(level, x0 + 1, y0) * (fx * (1.0 - fy))
+            + self.texel(level, x0, y0 + 1) * ((1.0 - fx) * fy)
+            + self.texel(level, x0 + 1, y0 + 1) * (fx * fy)
+    }
+
+    fn trilinear(&self, uv: Vector2<f32>, width: f32) -> Color {
+        let size = self.levels[0].size;
+        let nb_levels = self.levels.len();
+        // Level where the footprint covers a texel
+        let level = (width * size.x.max(size.y) as f32).max(1e-8).log2();
+        if level <= 0.0 {
+            self.bilinear(0, uv)
+        } else if level >= (nb_levels - 1) as f32 {
+            self.bilinear(nb_levels - 1, uv)
+        } else {
+            let l0 = level.floor();
+            let t = level - l0;
+            let l0 = l0 as usize;
+            self.bilinear(l0, uv) * (1.0 - t) + self.bilinear(l0 + 1, uv) * t
+        }
+    }
+}
+
+impl Texture for BitmapTexture {
+    fn eval(&self, uv: Vector2<f32>, width: f32) -> Color {
+        let uv = self.uv_transform.apply(uv);
+        match self.filter {
+            Filter::Nearest => self.nearest(0, uv),
+            Filter::Bilinear => self.bilinear(0, uv),
+            Filter::Trilinear => self.trilinear(uv, self.uv_transform.width(width)),
+        }
+    }
+    /// All the mipmap levels
+    fn memory(&self) -> usize {
+        self.levels.iter().map(Bitmap::memory).sum()
+    }
+}
+
+/// Next mipmap level: half resolution (rounded up) with a 2x2 box filter
+fn downsample(img: &Bitmap, wrap: WrapMode) -> Bitmap {
+    let size = Vector2::new(img.size.x.div_ceil(2), img.size.y.div_ceil(2));
+    let mut res = Bitmap::new(size);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let mut c = Color::zero();
+            for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                c += img.pixel(Point2::new(
+                    wrap.apply(i64::from(2 * x + dx), img.size.x),
+                    wrap.apply(i64::from(2 * y + dy), img.size.y),
+                ));
+            }
+            res.accumulate(Point2::new(x, y), c * 0.25);
+        }
+    }
+    res
+}
+
+/// Alternating colors (scale checks per unit of uv)
+pub struct CheckerboardTexture {
+    pub color1: Color,
+    pub color2: Color,
+    pub scale: f32,
+    pub uv_transform: UVTransform,
+}
+impl Texture for CheckerboardTexture {
+    fn eval(&self, uv: Vector2<f32>, _width: f32) -> Color {
+        let uv = self.uv_transform.apply(uv) * self.scale;
+        if (uv.x.floor() as i64 + uv.y.floor() as i64).rem_euclid(2) == 0 {
+            self.color1
+        } else {
+            self.color2
+        }
+    }
+}
+
+/// Three planar projections of a texture (world coordinates) blended by the shading normal,
+/// for the meshes without (or with distorted) texture coordinates
+pub struct TriplanarTexture {
+    pub texture: Box<dyn Texture>,
+    /// Repetitions of the texture per world unit
+    pub scale: f32,
+    /// Exponent of the blending weights (the higher, the narrower the transitions)
+    pub sharpness: f32,
+}
+impl Texture for TriplanarTexture {
+    fn eval(&self, uv: Vector2<f32>, width: f32) -> Color {
+        self.texture.eval(uv, width)
+    }
+    fn eval_at(&self, coords: &TexCoords, width: f32) -> Option<Color> {
+        let weights = Vector3::new(coords.n.x.abs(), coords.n.y.abs(), coords.n.z.abs())
+            .map(|v| v.powf(self.sharpness));
+        let sum = weights.x + weights.y + weights.z;
+        if sum <= 0.0 {
+            return None;
+        }
+        let p = coords.p * self.scale;
+        let width = width * self.scale;
+        // Planes facing the x, y and z axes
+        Some(
+            (self.texture.eval(Vector2::new(p.z, p.y), width) * weights.x
+                + self.texture.eval(Vector2::new(p.x, p.z), width) * weights.y
+                + self.texture.eval(Vector2::new(p.x, p.y), width) * weights.z)
+                / sum,
+        )
+    }
+    fn memory(&self) -> usize {
+        self.texture.memory()
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+fn default_sharpness() -> f32 {
+    4.0
+}
+fn default_octaves() -> u32 {
+    1
+}
+fn default_lacunarity() -> f32 {
+    2.0
+}
+fn default_gain() -> f32 {
+    0.5
+}
+fn default_distortion() -> f32 {
+    5.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BitmapTextureDesc {
+    filename: String,
+    color_space: Option<ColorSpace>,
+    white_point: Option<WhitePoint>,
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default)]
+    wrap: WrapMode,
+    #[serde(default)]
+    uv: UVTransform,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UdimTextureDesc {
+    filename: String,
+    color_space: Option<ColorSpace>,
+    white_point: Option<WhitePoint>,
+    #[serde(default)]
+    filter: Filter,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PtexTextureDesc {
+    filename: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatternTextureDesc {
+    color1: Color,
+    color2: Color,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default)]
+    uv: UVTransform,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoiseTextureDesc {
+    color1: Color,
+    color2: Color,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default)]
+    seed: u32,
+    #[serde(default)]
+    noise: NoiseType,
+    #[serde(default)]
+    pattern: NoisePattern,
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    #[serde(default = "default_lacunarity")]
+    lacunarity: f32,
+    #[serde(default = "default_gain")]
+    gain: f32,
+    #[serde(default = "default_distortion")]
+    distortion: f32,
+    #[serde(default)]
+    uv: UVTransform,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriplanarTextureDesc {
+    /// Projected texture (same layout as the other textures)
+    texture: serde_json::Value,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_sharpness")]
+    sharpness: f32,
+}
+
+/// Dispatch the texture types, same layout as the BSDFs:
+/// {"type": "bitmap", "data": {"filename": "wood.png", "filter": "trilinear"}}
+pub fn parse_texture(t: &serde_json::Value) -> Result<Box<dyn Texture>> {
+    let texture_type: String = serde_json::from_value(t["type"].clone())?;
+    let data = t["data"].clone();
+    Ok(match texture_type.as_ref() {
+        "constant" => Box::new(ConstantTexture {
+            color: serde_json::from_value(data)?,
+        }),
+        "bitmap" => {
+            let desc: BitmapTextureDesc = serde_json::from_value(data)?;
+            let mut texture = BitmapTexture::load_with(
+                &desc.filename,
+                desc.color_space,
+                desc.white_point,
+                desc.filter,
+                desc.wrap,
+            )?;
+            texture.uv_transform = desc.uv;
+            Box::new(texture)
+        }
+        "udim" => {
+            let desc: UdimTextureDesc = serde_json::from_value(data)?;
+            Box::new(UdimTexture::load(
+                &desc.filename,
+                desc.color_space,
+                desc.white_point,
+                desc.filter,
+            )?)
+        }
+        "ptex" => {
+            let desc: PtexTextureDesc = serde_json::from_value(data)?;
+            Box::new(PtexTexture::load(&desc.filename)?)
+        }
+        "checkerboard" => {
+            let desc: PatternTextureDesc = serde_json::from_value(data)?;
+            Box::new(CheckerboardTexture {
+                color1: desc.color1,
+                color2: desc.color2,
+                scale: desc.scale,
+                uv_transform: desc.uv,
+            })
+        }
+        "noise" => {
+            let desc: NoiseTextureDesc = serde_json::from_value(data)?;
+            Box::new(NoiseTexture {
+                color1: desc.color1,
+                color2: desc.color2,
+                scale: desc.scale,
+                seed: desc.seed,
+                fbm: Fbm {
+                    noise: desc.noise,
+                    pattern: desc.pattern,
+                    octaves: desc.octaves,
+                    lacunarity: desc.lacunarity,
+                    gain: desc.gain,
+                    distortion: desc.distortion,
+                },
+                uv_transform: desc.uv,
+            })
+        }
+        "triplanar" => {
+            let desc: TriplanarTextureDesc = serde_json::from_value(data)?;
+            Box::new(TriplanarTexture {
+                texture: parse_texture(&desc.texture)?,
+                scale: desc.scale,
+                sharpness: desc.sharpness,
+            })
+        }
+        _ => {
+            return Err(Error::InvalidData(format!(
+                "Unknown texture type {}",
+                texture_type
+            )))
+        }
+    })
+}
@@ -0,0 +1,222 @@
+//! Procedural noises (value, Perlin, simplex and Worley) and their fractal sums.
+//! All the noises are defined over the plane (texture coordinates).
+use crate::structure::Color;
+use crate::texture::{Texture, UVTransform};
+use cgmath::{InnerSpace, Vector2};
+
+/// Integer lattice hash
+fn hash(x: i64, y: i64, seed: u32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h
+}
+
+fn hash_f32(x: i64, y: i64, seed: u32) -> f32 {
+    hash(x, y, seed) as f32 / u32::MAX as f32
+}
+
+/// Value noise: random values on an integer lattice, smoothly interpolated. In [0, 1].
+pub fn value_noise(p: Vector2<f32>, seed: u32) -> f32 {
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (x0, y0) = (p.x.floor(), p.y.floor());
+    let (fx, fy) = (smooth(p.x - x0), smooth(p.y - y0));
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let v0 = hash_f32(x0, y0, seed) * (1.0 - fx) + hash_f32(x0 + 1, y0, seed) * fx;
+    let v1 = hash_f32(x0, y0 + 1, seed) * (1.0 - fx) + hash_f32(x0 + 1, y0 + 1, seed) * fx;
+    v0 * (1.0 - fy) + v1 * fy
+}
+
+/// One of 8 unit gradients, dotted with (x, y)
+fn gradient(h: u32, x: f32, y: f32) -> f32 {
+    const D: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    let (gx, gy) = match h & 7 {
+        0 => (1.0, 0.0),
+        1 => (-1.0, 0.0),
+        2 => (0.0, 1.0),
+        3 => (0.0, -1.0),
+        4 => (D, D),
+        5 => (-D, D),
+        6 => (D, -D),
+        _ => (-D, -D),
+    };
+    gx * x + gy * y
+}
+
+/// Perlin gradient noise (quintic interpolation). In [-1, 1].
+pub fn perlin_noise(p: Vector2<f32>, seed: u32) -> f32 {
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (x0, y0) = (p.x.floor(), p.y.floor());
+    let (dx, dy) = (p.x - x0, p.y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let n00 = gradient(hash(x0, y0, seed), dx, dy);
+    let n10 = gradient(hash(x0 + 1, y0, seed), dx - 1.0, dy);
+    let n01 = gradient(hash(x0, y0 + 1, seed), dx, dy - 1.0);
+    let n11 = gradient(hash(x0 + 1, y0 + 1, seed), dx - 1.0, dy - 1.0);
+    let (u, v) = (fade(dx), fade(dy));
+    let n0 = n00 * (1.0 - u) + n10 * u;
+    let n1 = n01 * (1.0 - u) + n11 * u;
+    // The extrema of the 2D noise are +-sqrt(2)/2
+    (std::f32::consts::SQRT_2 * (n0 * (1.0 - v) + n1 * v)).clamp(-1.0, 1.0)
+}
+
+/// Simplex noise (Gustavson's implementation). In [-1, 1].
+pub fn simplex_noise(p: Vector2<f32>, seed: u32) -> f32 {
+    let f2 = 0.5 * (3.0f32.sqrt() - 1.0);
+    let g2 = (3.0 - 3.0f32.sqrt()) / 6.0;
+    // Skew to find the simplex cell
+    let s = (p.x + p.y) * f2;
+    let (i, j) = ((p.x + s).floor(), (p.y + s).floor());
+    let t = (i + j) * g2;
+    let (x0, y0) = (p.x - (i - t), p.y - (j - t));
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let corners = [
+        (0, 0, x0, y0),
+        (i1, j1, x0 - i1 as f32 + g2, y0 - j1 as f32 + g2),
+        (1, 1, x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2),
+    ];
+    let (i, j) = (i as i64, j as i64);
+    let n = corners
+        .iter()
+        .map(|(di, dj, x, y)| {
+            let t = 0.5 - x * x - y * y;
+            if t < 0.0 {
+                0.0
+            } else {
+                t.powi(4) * gradient(hash(i + di, j + dj, seed), *x, *y)
+            }
+        })
+        .sum::<f32>();
+    // Unit gradients: 70 * sqrt(2) instead of the usual 70
+    (99.0 * n).clamp(-1.0, 1.0)
+}
+
+/// Worley (cellular) noise: distance to the closest feature point,
+/// one random point per lattice cell. In [0, 1].
+pub fn worley_noise(p: Vector2<f32>, seed: u32) -> f32 {
+    let (cx, cy) = (p.x.floor() as i64, p.y.floor() as i64);
+    let mut dist = f32::MAX;
+    for y in cy - 1..=cy + 1 {
+        for x in cx - 1..=cx + 1 {
+            let feature = Vector2::new(
+                x as f32 + hash_f32(x, y, seed),
+                y as f32 + hash_f32(x, y, seed ^ 0x68e3_1da4),
+            );
+            dist = dist.min((feature - p).magnitude2());
+        }
+    }
+    dist.sqrt().min(1.0)
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseType {
+    #[default]
+    Value,
+    Perlin,
+    Simplex,
+    Worley,
+}
+impl NoiseType {
+    /// Noise remapped to [-1, 1]
+    pub fn eval(self, p: Vector2<f32>, seed: u32) -> f32 {
+        match self {
+            NoiseType::Value => 2.0 * value_noise(p, seed) - 1.0,
+            NoiseType::Perlin => perlin_noise(p, seed),
+            NoiseType::Simplex => simplex_noise(p, seed),
+            NoiseType::Worley => 2.0 * worley_noise(p, seed) - 1.0,
+        }
+    }
+}
+
+/// How the octaves are combined into a value in [0, 1]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NoisePattern {
+    /// Fractal sum of the noise (clouds)
+    #[default]
+    Fbm,
+    /// Fractal sum of the absolute value of the noise
+    Turbulence,
+    /// Stripes along u distorted by the turbulence
+    Marble,
+}
+
+/// Fractal sum of a noise
+#[derive(Clone, Copy, Debug)]
+pub struct Fbm {
+    pub noise: NoiseType,
+    pub pattern: NoisePattern,
+    pub octaves: u32,
+    /// Frequency multiplier between the octaves
+    pub lacunarity: f32,
+    /// Amplitude multiplier between the octaves
+    pub gain: f32,
+    /// Strength of the turbulence for the marble pattern
+    pub distortion: f32,
+}
+
+impl Default for Fbm {
+    fn default() -> Self {
+        Fbm {
+            noise: NoiseType::Value,
+            pattern: NoisePattern::Fbm,
+            octaves: 1,
+            lacunarity: 2.0,
+            gain: 0.5,
+            distortion: 5.0,
+        }
+    }
+}
+
+impl Fbm {
+    /// Normalized sum of the octaves of f (in [-1, 1] if f is)
+    fn sum<F: Fn(f32) -> f32>(&self, p: Vector2<f32>, seed: u32, f: F) -> f32 {
+        let (mut freq, mut amplitude) = (1.0, 1.0);
+        let (mut sum, mut norm) = (0.0, 0.0);
+        for octave in 0..self.octaves.max(1) {
+            // Different seeds avoid the correlations at the lattice origin
+            let n = self.noise.eval(p * freq, seed.wrapping_add(octave));
+            sum += amplitude * f(n);
+            norm += amplitude;
+            freq *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        sum / norm
+    }
+
+    /// Value in [0, 1]
+    pub fn eval(&self, p: Vector2<f32>, seed: u32) -> f32 {
+        match self.pattern {
+            NoisePattern::Fbm => 0.5 * (self.sum(p, seed, |n| n) + 1.0),
+            NoisePattern::Turbulence => self.sum(p, seed, f32::abs),
+            NoisePattern::Marble => {
+                let turbulence = self.sum(p, seed, f32::abs);
+                let phase = 2.0 * std::f32::consts::PI * p.x + self.distortion * turbulence;
+                0.5 * (phase.sin() + 1.0)
+            }
+        }
+    }
+}
+
+/// Blend between two colors driven by a noise (scale lattice cells per unit of uv)
+pub struct NoiseTexture {
+    pub color1: Color,
+    pub color2: Color,
+    pub scale: f32,
+    pub seed: u32,
+    pub fbm: Fbm,
+    pub uv_transform: UVTransform,
+}
+impl Texture for NoiseTexture {
+    fn eval(&self, uv: Vector2<f32>, _width: f32) -> Color {
+        let t = self
+            .fbm
+            .eval(self.uv_transform.apply(uv) * self.scale, self.seed)
+            .clamp(0.0, 1.0);
+        self.color1 * (1.0 - t) + self.color2 * t
+    }
+}
@@ -0,0 +1,260 @@
+//! Ptex per-face textures (Burley and Lacewell 2008): one image per face of the mesh,
+//! addressed by the face index and the coordinates inside the face, so the meshes need no uv.
+//! Only the full resolution level is read (nearest texel, no filtering across the faces).
+//! The quad textures need meshes made of quads, each one triangulated
+//! as the triangles (0, 1, 2) and (0, 2, 3) like the OBJ loader does.
+use crate::error::{Error, Result};
+use crate::structure::{Color, TexCoords};
+use crate::texture::Texture;
+use byteorder::{LittleEndian, ReadBytesExt};
+use cgmath::Vector2;
+use exr::prelude::f16;
+
+const MAGIC: u32 = 0x7865_7450; // "Ptex"
+const HEADER_SIZE: usize = 64;
+const FACE_INFO_SIZE: usize = 20;
+const LEVEL_INFO_SIZE: usize = 16;
+const FLAG_CONSTANT: u8 = 1;
+
+/// Faces of the Ptex file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PtexMeshType {
+    Triangle,
+    Quad,
+}
+
+/// Texels of a face (u first), a single one for the constant faces
+struct PtexFace {
+    res: Vector2<u32>,
+    texels: Vec<Color>,
+}
+
+pub struct PtexTexture {
+    pub mesh_type: PtexMeshType,
+    faces: Vec<PtexFace>,
+}
+
+/// Values of the texels: 8 and 16 bits integers (normalized), half or float
+#[derive(Clone, Copy, PartialEq)]
+enum DataType {
+    U8,
+    U16,
+    Half,
+    Float,
+}
+impl DataType {
+    fn size(self) -> usize {
+        match self {
+            DataType::U8 => 1,
+            DataType::U16 | DataType::Half => 2,
+            DataType::Float => 4,
+        }
+    }
+    fn read(self, data: &mut &[u8]) -> Result<f32> {
+        Ok(match self {
+            DataType::U8 => f32::from(data.read_u8()?) / 255.0,
+            DataType::U16 => f32::from(data.read_u16::<LittleEndian>()?) / 65535.0,
+            DataType::Half => f16::from_bits(data.read_u16::<LittleEndian>()?).to_f32(),
+            DataType::Float => data.read_f32::<LittleEndian>()?,
+        })
+    }
+    /// Undo the delta encoding of the integer values (running sum over the block)
+    fn decode_difference(self, data: &mut [u8]) {
+        match self {
+            DataType::U8 => {
+                for i in 1..data.len() {
+                    data[i] = data[i].wrapping_add(data[i - 1]);
+                }
+            }
+            DataType::U16 => {
+                let mut previous = 0u16;
+                for v in data.chunks_exact_mut(2) {
+                    previous = previous.wrapping_add(u16::from_le_bytes([v[0], v[1]]));
+                    v.copy_from_slice(&previous.to_le_bytes());
+                }
+            }
+            DataType::Half | DataType::Float => {}
+        }
+    }
+}
+
+impl PtexTexture {
+    /// Read the full resolution faces of a Ptex file (zipped or constant faces)
+    pub fn load(filename: &str) -> Result<PtexTexture> {
+        let invalid = |msg: &str| Error::InvalidData(format!("Ptex {}: {}", filename, msg));
+        let file = std::fs::read(filename)?;
+        let block = |pos: usize, size: usize| {
+            file.get(pos..pos + size)
+                .ok_or_else(|| invalid("truncated file"))
+        };
+        let unzip = |pos: usize, size: usize| {
+            inflate::inflate_bytes_zlib(block(pos, size)?).map_err(|e| invalid(&e))
+        };
+
+        // Header
+        let mut header = block(0, HEADER_SIZE)?;
+        if header.read_u32::<LittleEndian>()? != MAGIC {
+            return Err(invalid("not a Ptex file"));
+        }
+        if header.read_u32::<LittleEndian>()? != 1 {
+            return Err(invalid("unsupported version"));
+        }
+        let mesh_type = match header.read_u32::<LittleEndian>()? {
+            0 => PtexMeshType::Triangle,
+            1 => PtexMeshType::Quad,
+            _ => return Err(invalid("unknown mesh type")),
+        };
+        let data_type = match header.read_u32::<LittleEndian>()? {
+            0 => DataType::U8,
+            1 => DataType::U16,
+            2 => DataType::Half,
+            3 => DataType::Float,
+            _ => return Err(invalid("unknown data type")),
+        };
+        let alpha_channel = header.read_i32::<LittleEndian>()?;
+        let nb_channels = usize::from(header.read_u16::<LittleEndian>()?);
+        let _nb_levels = header.read_u16::<LittleEndian>()?;
+        let nb_faces = header.read_u32::<LittleEndian>()? as usize;
+        let ext_header_size = header.read_u32::<LittleEndian>()? as usize;
+        let face_info_size = header.read_u32::<LittleEndian>()? as usize;
+        let const_data_size = header.read_u32::<LittleEndian>()? as usize;
+        let level_info_size = header.read_u32::<LittleEndian>()? as usize;
+        let pixel_size = nb_channels * data_type.size();
+        if nb_channels == 0 {
+            return Err(invalid("no channel"));
+        }
+
+        // Color channels (gray with a single one)
+        let channels = (0..nb_channels)
+            .filter(|c| *c as i32 != alpha_channel)
+            .take(3)
+            .collect::<Vec<_>>();
+        let to_color = |values: &[f32]| match channels.as_slice() {
+            [c] => Color::value(values[*c]),
+            _ => {
+                let c = |i: usize| channels.get(i).map_or(0.0, |c| values[*c]);
+                Color::new(c(0), c(1), c(2))
+            }
+        };
+
+        // Face resolutions and constant values, then the first level
+        let face_info_pos = HEADER_SIZE + ext_header_size;
+        let face_info = unzip(face_info_pos, face_info_size)?;
+        let const_data = unzip(face_info_pos + face_info_size, const_data_size)?;
+        let level_info_pos = face_info_pos + face_info_size + const_data_size;
+        if face_info.len() != nb_faces * FACE_INFO_SIZE
+            || const_data.len() != nb_faces * pixel_size
+            || level_info_size < LEVEL_INFO_SIZE
+        {
+            return Err(invalid("wrong face information size"));
+        }
+        let mut level_info = block(level_info_pos, LEVEL_INFO_SIZE)?;
+        let _level_data_size = level_info.read_u64::<LittleEndian>()?;
+        let level_header_size = level_info.read_u32::<LittleEndian>()? as usize;
+        let level_pos = level_info_pos + level_info_size;
+        let level_header = unzip(level_pos, level_header_size)?;
+        if level_header.len() != nb_faces * 4 {
+            return Err(invalid("wrong level header size"));
+        }
+
+        let mut faces = Vec::with_capacity(nb_faces);
+        let mut pos = level_pos + level_header_size;
+        let mut level_header = &level_header[..];
+        for f in 0..nb_faces {
+            let info = &face_info[f * FACE_INFO_SIZE..(f + 1) * FACE_INFO_SIZE];
+            let res = Vector2::new(1 << info[0], 1 << info[1]);
+            let face_header = level_header.read_u32::<LittleEndian>()?;
+            let (size, encoding) = ((face_header & 0x3fff_ffff) as usize, face_header >> 30);
+            let face = if info[3] & FLAG_CONSTANT != 0 || encoding == 0 {
+                // Constant face: stored in the constant data (or inside the level)
+                let data = if encoding == 0 {
+                    block(pos, pixel_size)?
+                } else {
+                    &const_data[f * pixel_size..(f + 1) * pixel_size]
+                };
+                let mut data = data;
+                let values = (0..nb_channels)
+                    .map(|_| data_type.read(&mut data))
+                    .collect::<Result<Vec<_>>>()?;
+                PtexFace {
+                    res: Vector2::new(1, 1),
+                    texels: vec![to_color(&values)],
+                }
+            } else if encoding == 1 {
+                return Err(invalid("tiled faces are not supported"));
+            } else {
+                let mut data = unzip(pos, size)?;
+                let nb_texels = (res.x * res.y) as usize;
+                if data.len() != nb_texels * pixel_size {
+                    return Err(invalid("wrong face data size"));
+                }
+                if encoding == 3 {
+                    data_type.decode_difference(&mut data);
+                }
+                // One plane per channel
+                let mut planes = vec![vec![0.0; nb_texels]; nb_channels];
+                let mut data = &data[..];
+                for plane in &mut planes {
+                    for v in plane.iter_mut() {
+                        *v = data_type.read(&mut data)?;
+                    }
+                }
+                let texels = (0..nb_texels)
+                    .map(|i| to_color(&planes.iter().map(|p| p[i]).collect::<Vec<_>>()))
+                    .collect();
+                PtexFace { res, texels }
+            };
+            faces.push(face);
+            pos += size;
+        }
+        Ok(PtexTexture { mesh_type, faces })
+    }
+
+    pub fn nb_faces(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Nearest texel of the face at the coordinates uv inside the face.
+    /// The triangle faces store the texels above the diagonal rotated by 180 degrees.
+    pub fn lookup(&self, face: usize, uv: Vector2<f32>) -> Color {
+        let face = &self.faces[face];
+        let res = face.res;
+        let (u, v) = (uv.x * res.x as f32, uv.y * res.y as f32);
+        let clamp = |v: f32, res: u32| (v.max(0.0) as u32).min(res - 1);
+        let (ui, vi) = (clamp(u, res.x), clamp(v, res.y));
+        let (ui, vi) = match self.mesh_type {
+            PtexMeshType::Triangle if u - ui as f32 + v - vi as f32 > 1.0 => {
+                (res.x - 1 - vi, res.y - 1 - ui)
+            }
+            _ => (ui, vi),
+        };
+        face.texels[(vi * res.x + ui) as usize]
+    }
+}
+
+impl Texture for PtexTexture {
+    /// Needs the face of the shading point: black for the plain uv lookups
+    fn eval(&self, _uv: Vector2<f32>, _width: f32) -> Color {
+        Color::zero()
+    }
+    fn eval_at(&self, coords: &TexCoords, _width: f32) -> Option<Color> {
+        let (triangle, b) = coords.face?;
+        let (face, uv) = match self.mesh_type {
+            PtexMeshType::Triangle => (triangle, b),
+            // Corners (0, 0), (1, 0), (1, 1) then (0, 0), (1, 1), (0, 1)
+            PtexMeshType::Quad if triangle % 2 == 0 => (triangle / 2, Vector2::new(b.x + b.y, b.y)),
+            PtexMeshType::Quad => (triangle / 2, Vector2::new(b.x, b.x + b.y)),
+        };
+        if face < self.faces.len() {
+            Some(self.lookup(face, uv))
+        } else {
+            None
+        }
+    }
+    fn memory(&self) -> usize {
+        self.faces
+            .iter()
+            .map(|f| f.texels.len() * std::mem::size_of::<Color>())
+            .sum()
+    }
+}
@@ -0,0 +1,112 @@
+//! UDIM texture sets: one image per unit square of the uv space.
+//! The tile 1001 covers [0, 1]^2, the tile numbers increase by 1 along u (10 tiles per row)
+//! and by 10 along v.
+use crate::color::{ColorSpace, WhitePoint};
+use crate::error::{Error, Result};
+use crate::structure::Color;
+use crate::texture::{BitmapTexture, Filter, Texture, WrapMode};
+use cgmath::Vector2;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Token replaced by the tile number inside the file names (Mari convention)
+pub const UDIM_TOKEN: &str = "<UDIM>";
+
+pub fn udim_tile(uv: Vector2<f32>) -> Option<u32> {
+    let (u, v) = (uv.x.floor(), uv.y.floor());
+    if !(0.0..10.0).contains(&u) || v < 0.0 {
+        None
+    } else {
+        Some(1001 + u as u32 + 10 * v as u32)
+    }
+}
+
+/// Tile numbers and files matching the pattern (for example "albedo.<UDIM>.png")
+pub fn udim_tiles(pattern: &str) -> Result<Vec<(u32, PathBuf)>> {
+    let file_pattern = Path::new(pattern)
+        .file_name()
+        .and_then(|v| v.to_str())
+        .ok_or_else(|| Error::InvalidData(format!("UDIM pattern {}", pattern)))?;
+    let token = file_pattern
+        .find(UDIM_TOKEN)
+        .ok_or_else(|| Error::InvalidData(format!("{} is missing in {}", UDIM_TOKEN, pattern)))?;
+    let (prefix, suffix) = (
+        &file_pattern[..token],
+        &file_pattern[token + UDIM_TOKEN.len()..],
+    );
+    let dir = Path::new(pattern)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let mut tiles = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|v| v.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.len() != prefix.len() + 4 + suffix.len()
+            || !name.starts_with(prefix)
+            || !name.ends_with(suffix)
+        {
+            continue;
+        }
+        match name[prefix.len()..prefix.len() + 4].parse::<u32>() {
+            Ok(tile) if tile > 1000 => tiles.push((tile, path)),
+            _ => continue,
+        }
+    }
+    Ok(tiles)
+}
+
+pub struct UdimTexture {
+    pub tiles: HashMap<u32, BitmapTexture>,
+}
+
+impl UdimTexture {
+    /// Load all the tiles matching the pattern (for example "albedo.<UDIM>.png").
+    /// color_space and white_point: see Bitmap::read_linear
+    pub fn load(
+        pattern: &str,
+        color_space: Option<ColorSpace>,
+        white_point: Option<WhitePoint>,
+        filter: Filter,
+    ) -> Result<UdimTexture> {
+        let mut tiles = HashMap::new();
+        for (tile, path) in udim_tiles(pattern)? {
+            let path = path
+                .to_str()
+                .ok_or_else(|| Error::InvalidData(format!("texture path {:?}", path)))?;
+            info!("UDIM tile {}: {}", tile, path);
+            // The lookups do not cross the tile borders
+            tiles.insert(
+                tile,
+                BitmapTexture::load_with(path, color_space, white_point, filter, WrapMode::Clamp)?,
+            );
+        }
+        if tiles.is_empty() {
+            return Err(Error::InvalidData(format!(
+                "no UDIM tile found for {}",
+                pattern
+            )));
+        }
+        Ok(UdimTexture { tiles })
+    }
+}
+
+impl Texture for UdimTexture {
+    /// The missing tiles are black
+    fn eval(&self, uv: Vector2<f32>, width: f32) -> Color {
+        match udim_tile(uv).and_then(|t| self.tiles.get(&t)) {
+            Some(tile) => tile.eval(
+                Vector2::new(uv.x - uv.x.floor(), uv.y - uv.y.floor()),
+                width,
+            ),
+            None => Color::zero(),
+        }
+    }
+    fn memory(&self) -> usize {
+        self.tiles.values().map(|t| t.memory()).sum()
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::{Error, Result};
 use std;
 use std::iter::Iterator;
 
@@ -43,3 +44,54 @@ macro_rules! modulo_signed_ext_impl {
     )*)
 }
 modulo_signed_ext_impl! { f32 }
+
+/// SplitMix64 finalizer (bijective 64 bits hash)
+pub fn mix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// MurmurHash3 (x86, 32 bits)
+pub fn murmur3_32(key: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |mut k: u32| {
+        k = k.wrapping_mul(C1);
+        k = k.rotate_left(15);
+        k.wrapping_mul(C2)
+    };
+
+    let mut h = seed;
+    let blocks = key.chunks_exact(4);
+    let tail = blocks.remainder();
+    for b in blocks {
+        h ^= mix(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        h = h.rotate_left(13);
+        h = h.wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let mut k = 0;
+        for (i, v) in tail.iter().enumerate() {
+            k ^= u32::from(*v) << (8 * i);
+        }
+        h ^= mix(k);
+    }
+
+    // Finalization
+    h ^= key.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+/// Extension of a file name
+pub fn extension(filename: &str) -> Result<&str> {
+    std::path::Path::new(filename)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .ok_or_else(|| Error::UnsupportedFormat(String::new()))
+}
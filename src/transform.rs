@@ -0,0 +1,196 @@
+use crate::structure::Ray;
+use cgmath::*;
+// The matrix trait (the name is used by the struct)
+use cgmath::Transform as _;
+
+/// Affine transformation with its inverse
+/// (the inverse transpose is needed to transform the normals)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub m: Matrix4<f32>,
+    pub m_inv: Matrix4<f32>,
+}
+
+impl Transform {
+    /// None if the matrix is not invertible
+    pub fn new(m: Matrix4<f32>) -> Option<Transform> {
+        m.invert().map(|m_inv| Transform { m, m_inv })
+    }
+    pub fn identity() -> Transform {
+        Transform {
+            m: Matrix4::identity(),
+            m_inv: Matrix4::identity(),
+        }
+    }
+    pub fn translate(v: Vector3<f32>) -> Transform {
+        Transform {
+            m: Matrix4::from_translation(v),
+            m_inv: Matrix4::from_translation(-v),
+        }
+    }
+    /// The scale factors cannot be zero
+    pub fn scale(v: Vector3<f32>) -> Transform {
+        assert!(v.x != 0.0 && v.y != 0.0 && v.z != 0.0);
+        Transform {
+            m: Matrix4::from_nonuniform_scale(v.x, v.y, v.z),
+            m_inv: Matrix4::from_nonuniform_scale(1.0 / v.x, 1.0 / v.y, 1.0 / v.z),
+        }
+    }
+    pub fn rotate(axis: Vector3<f32>, angle: Deg<f32>) -> Transform {
+        let m = Matrix4::from_axis_angle(axis.normalize(), angle);
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+    /// self * other: other is applied first
+    pub fn compose(&self, other: &Transform) -> Transform {
+        Transform {
+            m: self.m * other.m,
+            m_inv: other.m_inv * self.m_inv,
+        }
+    }
+    /// True if the transformation changes the orientation of the triangles
+    pub fn swaps_handedness(&self) -> bool {
+        Matrix3::new(
+            self.m.x.x, self.m.x.y, self.m.x.z, self.m.y.x, self.m.y.y, self.m.y.z, self.m.z.x,
+            self.m.z.y, self.m.z.z,
+        )
+        .determinant()
+            < 0.0
+    }
+
+    pub fn transform_point(&self, p: Point3<f32>) -> Point3<f32> {
+        self.m.transform_point(p)
+    }
+    pub fn transform_vector(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.m.transform_vector(v)
+    }
+    /// The normal is not normalized
+    pub fn transform_normal(&self, n: Vector3<f32>) -> Vector3<f32> {
+        self.m_inv.transpose().transform_vector(n)
+    }
+    /// The ray direction is not normalized so the distances are kept
+    pub fn transform_ray(&self, r: &Ray) -> Ray {
+        Ray {
+            o: self.transform_point(r.o),
+            d: self.transform_vector(r.d),
+            tnear: r.tnear,
+            tfar: r.tfar,
+        }
+    }
+}
+
+impl std::ops::Mul for Transform {
+    type Output = Transform;
+    fn mul(self, other: Transform) -> Transform {
+        self.compose(&other)
+    }
+}
+
+/// Translation, rotation and scale (applied in the reverse order).
+/// Used as key frames: they can be interpolated without shearing.
+#[derive(Clone, Copy, Debug)]
+pub struct TransformKey {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl TransformKey {
+    pub fn to_transform(&self) -> Transform {
+        Transform::translate(self.translation)
+            * Transform {
+                m: Matrix4::from(self.rotation),
+                m_inv: Matrix4::from(self.rotation.conjugate()),
+            }
+            * Transform::scale(self.scale)
+    }
+    pub fn interpolate(&self, other: &TransformKey, t: f32) -> TransformKey {
+        TransformKey {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+/// Local transformation of a node, possibly animated
+#[derive(Clone, Debug)]
+pub enum NodeTransform {
+    Static(Transform),
+    /// Key frames sorted by time (clamped outside of their range)
+    Animated(Vec<(f32, TransformKey)>),
+}
+
+impl NodeTransform {
+    pub fn at(&self, time: f32) -> Transform {
+        match self {
+            NodeTransform::Static(t) => *t,
+            NodeTransform::Animated(keys) => {
+                assert!(!keys.is_empty());
+                let i = keys
+                    .iter()
+                    .position(|(t, _)| *t > time)
+                    .unwrap_or(keys.len());
+                if i == 0 {
+                    keys[0].1.to_transform()
+                } else if i == keys.len() {
+                    keys[i - 1].1.to_transform()
+                } else {
+                    let (t0, k0) = &keys[i - 1];
+                    let (t1, k1) = &keys[i];
+                    k0.interpolate(k1, (time - t0) / (t1 - t0)).to_transform()
+                }
+            }
+        }
+    }
+}
+
+struct TransformNode {
+    local: NodeTransform,
+    parent: Option<usize>,
+}
+
+/// Transform hierarchy for instances and animated nodes:
+/// the world transformation of a node is its parents ones composed with its local one.
+#[derive(Default)]
+pub struct TransformHierarchy {
+    nodes: Vec<TransformNode>,
+}
+
+impl TransformHierarchy {
+    /// Returns the node id. The parent needs to be added before its children.
+    pub fn add(&mut self, parent: Option<usize>, local: NodeTransform) -> usize {
+        if let Some(p) = parent {
+            assert!(p < self.nodes.len(), "unknown parent node {}", p);
+        }
+        self.nodes.push(TransformNode { local, parent });
+        self.nodes.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Local to world transformation of a node
+    pub fn world(&self, node: usize, time: f32) -> Transform {
+        let mut t = self.nodes[node].local.at(time);
+        let mut parent = self.nodes[node].parent;
+        while let Some(p) = parent {
+            t = self.nodes[p].local.at(time) * t;
+            parent = self.nodes[p].parent;
+        }
+        t
+    }
+}
@@ -0,0 +1,332 @@
+//! Statistical tests of the BSDF sampling routines (similar to Mitsuba's chi2 tests):
+//! - the histogram of the sampled directions needs to match the integrated pdf()
+//! - sample() needs to return the same pdf and weight as pdf() and eval()
+//! - eval() needs to be reciprocal
+use crate::bsdfs::blend::BSDFBlend;
+use crate::bsdfs::diffuse::BSDFDiffuse;
+use crate::bsdfs::phong::BSDFPhong;
+use crate::bsdfs::{BSDFColor, BSDF};
+use crate::math::sample_uniform_sphere;
+use crate::samplers::independent::IndependentSampler;
+use crate::samplers::Sampler;
+use crate::structure::{Color, Domain, TexCoords, PDF};
+use cgmath::*;
+
+/// Cells with a smaller expected number of samples are pooled together
+const MIN_EXPECTED: f64 = 5.0;
+/// Relative tolerance used to compare the pdf and the weights
+const TOLERANCE: f32 = 1e-3;
+
+/// BSDFs (non-smooth) checked by the tests.
+/// New BSDFs need to be added here to be covered.
+pub fn bsdf_zoo() -> Vec<(String, Box<dyn BSDF>)> {
+    let diffuse = || {
+        Box::new(BSDFDiffuse {
+            diffuse: BSDFColor::UniformColor(Color::value(0.5)),
+        })
+    };
+    let phong = |exponent| {
+        Box::new(BSDFPhong {
+            specular: BSDFColor::UniformColor(Color::value(0.5)),
+            exponent,
+        })
+    };
+    let mut zoo: Vec<(String, Box<dyn BSDF>)> = vec![("diffuse".to_string(), diffuse())];
+    for &exponent in &[1.0, 10.0, 30.0] {
+        zoo.push((format!("phong {}", exponent), phong(exponent)));
+    }
+    zoo.push((
+        "blend (diffuse, phong 10)".to_string(),
+        Box::new(BSDFBlend {
+            bsdf1: diffuse(),
+            bsdf2: phong(10.0),
+        }),
+    ));
+    zoo
+}
+
+/// Incoming direction (local frame) from its elevation in degrees
+pub fn direction(theta: f32, phi: f32) -> Vector3<f32> {
+    let (theta, phi) = (theta.to_radians(), phi.to_radians());
+    Vector3::new(
+        theta.sin() * phi.cos(),
+        theta.sin() * phi.sin(),
+        theta.cos(),
+    )
+}
+
+/// Chi-square goodness of fit test between sample() and pdf().
+/// The sphere is split in cells uniform in (cos theta, phi).
+pub struct ChiSquareTest {
+    pub res_theta: usize,
+    pub res_phi: usize,
+    pub nb_samples: usize,
+    /// Subdivisions (per dimension) used to integrate pdf() inside a cell
+    pub nb_integration: usize,
+    /// The sampling is rejected if the p-value is below
+    pub significance: f64,
+}
+
+impl Default for ChiSquareTest {
+    fn default() -> Self {
+        ChiSquareTest {
+            res_theta: 20,
+            res_phi: 40,
+            nb_samples: 500_000,
+            nb_integration: 16,
+            significance: 0.01,
+        }
+    }
+}
+
+impl ChiSquareTest {
+    fn cell(&self, d: Vector3<f32>) -> usize {
+        let z = (d.z * 0.5 + 0.5).clamp(0.0, 1.0);
+        let phi = d.y.atan2(d.x).rem_euclid(2.0 * std::f32::consts::PI);
+        let i = ((z * self.res_theta as f32) as usize).min(self.res_theta - 1);
+        let j = ((phi * self.res_phi as f32 / (2.0 * std::f32::consts::PI)) as usize)
+            .min(self.res_phi - 1);
+        i * self.res_phi + j
+    }
+
+    /// Number of samples falling inside each cell
+    fn observed(&self, bsdf: &dyn BSDF, d_in: &Vector3<f32>) -> Result<Vec<f64>, String> {
+        let mut sampler = IndependentSampler::default();
+        let mut observed = vec![0.0; self.res_theta * self.res_phi];
+        for _ in 0..self.nb_samples {
+            if let Some(s) = bsdf.sample(&TexCoords::default(), d_in, sampler.next2d()) {
+                match s.pdf {
+                    PDF::SolidAngle(_) => observed[self.cell(s.d.normalize())] += 1.0,
+                    pdf => return Err(format!("unsupported pdf measure: {:?}", pdf)),
+                }
+            }
+        }
+        Ok(observed)
+    }
+
+    /// Expected number of samples inside each cell (integration of pdf())
+    fn expected(&self, bsdf: &dyn BSDF, d_in: &Vector3<f32>) -> Vec<f64> {
+        let n = self.nb_integration;
+        let dz = 2.0 / (self.res_theta * n) as f64;
+        let dphi = 2.0 * std::f64::consts::PI / (self.res_phi * n) as f64;
+        let mut expected = vec![0.0; self.res_theta * self.res_phi];
+        for i in 0..self.res_theta * n {
+            let z = -1.0 + (i as f64 + 0.5) * dz;
+            let r = (1.0 - z * z).sqrt();
+            for j in 0..self.res_phi * n {
+                let phi = (j as f64 + 0.5) * dphi;
+                let d = Vector3::new(r * phi.cos(), r * phi.sin(), z)
+                    .cast::<f32>()
+                    .unwrap();
+                let pdf = bsdf
+                    .pdf(&TexCoords::default(), d_in, &d, Domain::SolidAngle)
+                    .value() as f64;
+                expected[(i / n) * self.res_phi + j / n] += pdf * dz * dphi;
+            }
+        }
+        expected
+            .iter()
+            .map(|v| v * self.nb_samples as f64)
+            .collect()
+    }
+
+    /// Run the test for one incoming direction, returns the p-value
+    pub fn run(&self, bsdf: &dyn BSDF, d_in: &Vector3<f32>) -> Result<f64, String> {
+        let observed = self.observed(bsdf, d_in)?;
+        let expected = self.expected(bsdf, d_in);
+
+        let total = expected.iter().sum::<f64>() / self.nb_samples as f64;
+        if total > 1.0 + TOLERANCE as f64 {
+            return Err(format!("the pdf integrates to {} (> 1)", total));
+        }
+
+        // Sort the cells by expected values and pool the small ones
+        let mut cells = observed.into_iter().zip(expected).collect::<Vec<_>>();
+        cells.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let (mut pooled_obs, mut pooled_exp) = (0.0, 0.0);
+        let mut chi2 = 0.0;
+        let mut dof = 0;
+        for (obs, exp) in cells {
+            if exp < MIN_EXPECTED {
+                // Includes the cells where the pdf is zero at all the integration points
+                pooled_obs += obs;
+                pooled_exp += exp;
+            } else if (pooled_obs > 0.0 || pooled_exp > 0.0) && pooled_exp < MIN_EXPECTED {
+                // Merge the pool with this cell
+                let (obs, exp) = (obs + pooled_obs, exp + pooled_exp);
+                pooled_obs = 0.0;
+                pooled_exp = 0.0;
+                chi2 += (obs - exp) * (obs - exp) / exp;
+                dof += 1;
+            } else {
+                chi2 += (obs - exp) * (obs - exp) / exp;
+                dof += 1;
+            }
+        }
+        if pooled_exp > 0.0 {
+            chi2 += (pooled_obs - pooled_exp) * (pooled_obs - pooled_exp) / pooled_exp;
+            dof += 1;
+        } else if pooled_obs > 0.0 {
+            return Err(format!(
+                "{} samples in a region where the pdf is zero",
+                pooled_obs
+            ));
+        }
+        if dof < 2 {
+            return Err("not enough cells with samples".to_string());
+        }
+
+        let p_value = chi2_survival(chi2, (dof - 1) as f64);
+        if p_value < self.significance {
+            Err(format!(
+                "chi2 test rejected: chi2 = {}, dof = {}, p-value = {} < {}",
+                chi2,
+                dof - 1,
+                p_value,
+                self.significance
+            ))
+        } else {
+            Ok(p_value)
+        }
+    }
+}
+
+/// Check that sample() gives the same pdf and weight (eval / pdf) as pdf() and eval()
+pub fn check_sampling_consistency(
+    bsdf: &dyn BSDF,
+    d_in: &Vector3<f32>,
+    nb_samples: usize,
+) -> Result<(), String> {
+    let close = |a: f32, b: f32| (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1e-3);
+    let mut sampler = IndependentSampler::default();
+    for _ in 0..nb_samples {
+        let s = match bsdf.sample(&TexCoords::default(), d_in, sampler.next2d()) {
+            Some(s) => s,
+            None => continue,
+        };
+        let domain = match s.pdf {
+            PDF::Discrete(_) => Domain::Discrete,
+            _ => Domain::SolidAngle,
+        };
+        let pdf = bsdf.pdf(&TexCoords::default(), d_in, &s.d, domain).value();
+        if !close(s.pdf.value(), pdf) {
+            return Err(format!(
+                "sample() pdf {} != pdf() {} (d_out: {:?})",
+                s.pdf.value(),
+                pdf,
+                s.d
+            ));
+        }
+        let weight = bsdf.eval(&TexCoords::default(), d_in, &s.d, domain) / pdf;
+        if !(close(s.weight.r, weight.r)
+            && close(s.weight.g, weight.g)
+            && close(s.weight.b, weight.b))
+        {
+            return Err(format!(
+                "sample() weight {:?} != eval() / pdf() {:?} (d_out: {:?})",
+                s.weight, weight, s.d
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that eval(wi, wo) / cos(wo) == eval(wo, wi) / cos(wi)
+/// (eval includes the cosine of the outgoing direction)
+pub fn check_reciprocity(bsdf: &dyn BSDF, nb_samples: usize) -> Result<(), String> {
+    let mut sampler = IndependentSampler::default();
+    let mut upper = || {
+        let d = sample_uniform_sphere(sampler.next2d());
+        Vector3::new(d.x, d.y, d.z.abs().max(1e-3)).normalize()
+    };
+    for _ in 0..nb_samples {
+        let (wi, wo) = (upper(), upper());
+        let f_1 = bsdf.eval(&TexCoords::default(), &wi, &wo, Domain::SolidAngle) / wo.z;
+        let f_2 = bsdf.eval(&TexCoords::default(), &wo, &wi, Domain::SolidAngle) / wi.z;
+        let diff = (f_1 - f_2)
+            .channel_max()
+            .abs()
+            .max((f_2 - f_1).channel_max().abs());
+        if diff > TOLERANCE * f_1.channel_max().max(f_2.channel_max()).max(1e-3) {
+            return Err(format!(
+                "not reciprocal: f(wi, wo) = {:?}, f(wo, wi) = {:?} (wi: {:?}, wo: {:?})",
+                f_1, f_2, wi, wo
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Probability that a chi2 variable with dof degrees of freedom is above x
+pub fn chi2_survival(x: f64, dof: f64) -> f64 {
+    gamma_q(dof * 0.5, x * 0.5)
+}
+
+fn ln_gamma(x: f64) -> f64 {
+    // Lanczos approximation
+    const COEFS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000000000190015;
+    for (i, c) in COEFS.iter().enumerate() {
+        ser += c / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const MAX_ITER: usize = 1000;
+    const EPS: f64 = 1e-14;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    if x < a + 1.0 {
+        // Series representation of P(a, x)
+        let mut sum = 1.0 / a;
+        let mut del = sum;
+        let mut ap = a;
+        for _ in 0..MAX_ITER {
+            ap += 1.0;
+            del *= x / ap;
+            sum += del;
+            if del.abs() < sum.abs() * EPS {
+                break;
+            }
+        }
+        1.0 - sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+    } else {
+        // Continued fraction representation of Q(a, x) (Lentz)
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..MAX_ITER {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+            if (del - 1.0).abs() < EPS {
+                break;
+            }
+        }
+        (-x + a * x.ln() - ln_gamma(a)).exp() * h
+    }
+}
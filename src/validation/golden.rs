@@ -0,0 +1,194 @@
+//! Golden image regression: small scenes rendered at low spp with a fixed seed
+//! and compared against reference EXRs stored in tests/golden (tests/golden.rs).
+//! The tolerances are above the noise difference between two seeds,
+//! so refactors changing the random number consumption still pass.
+use super::*;
+use crate::bsdfs::phong::BSDFPhong;
+use crate::image_ops::ResizeFilter;
+use crate::integrators::ao::IntegratorAO;
+use crate::integrators::direct::IntegratorDirect;
+use crate::integrators::explicit::path::{IntegratorPathTracing, IntegratorPathTracingStrategies};
+use crate::integrators::explicit::vpl::IntegratorVPL;
+use crate::metrics::{ErrorMap, Metric};
+use crate::structure::Bitmap;
+use std::path::{Path, PathBuf};
+
+/// Seed used for all the golden images
+pub const GOLDEN_SEED: u64 = 42;
+const GOLDEN_SPP: usize = 16;
+/// The images are compared after a box filter (reduces the noise, keeps the bias)
+const GOLDEN_FILTER: u32 = 4;
+
+pub struct GoldenCase {
+    pub name: &'static str,
+    pub scene: Scene,
+    pub integrator: IntegratorType,
+    /// Maximum relMSE against the golden image
+    pub tolerance: f32,
+}
+
+impl GoldenCase {
+    pub fn render(&mut self) -> Bitmap {
+        let img = self.integrator.compute(&self.scene, &NoObserver);
+        img.values["primal"].to_f32().into_owned()
+    }
+
+    pub fn golden_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.exr", self.name))
+    }
+
+    /// Render and overwrite the golden image
+    pub fn update(&mut self, dir: &Path) -> crate::error::Result<()> {
+        let img = self.render();
+        let attributes = [
+            ("seed".to_string(), GOLDEN_SEED.to_string()),
+            ("spp".to_string(), self.scene.nb_samples.to_string()),
+        ];
+        crate::exr_writer::save_bitmap(&img, self.golden_path(dir).to_str().unwrap(), &attributes)
+    }
+
+    /// Render and compare against the golden image, returns the relMSE
+    pub fn compare(&mut self, dir: &Path) -> std::result::Result<f32, String> {
+        let path = self.golden_path(dir);
+        let golden = Bitmap::read_exr(path.to_str().unwrap())
+            .map_err(|e| format!("{}: impossible to read {:?}: {}", self.name, path, e))?;
+        let img = self.render();
+        if img.size != golden.size {
+            return Err(format!(
+                "{}: image size {:?} instead of {:?}",
+                self.name, img.size, golden.size
+            ));
+        }
+        let error = ErrorMap::new(Metric::RelMSE, &downsample(&golden), &downsample(&img))
+            .map_err(|e| format!("{}: {}", self.name, e))?
+            .mean();
+        if error.is_finite() && error <= self.tolerance {
+            Ok(error)
+        } else {
+            Err(format!(
+                "{}: relMSE {} > {}",
+                self.name, error, self.tolerance
+            ))
+        }
+    }
+}
+
+fn downsample(img: &Bitmap) -> Bitmap {
+    img.resize(img.size / GOLDEN_FILTER, ResizeFilter::Box)
+}
+
+/// Ground lit by a square emitter with a small occluder in between
+/// (soft shadow seen from above)
+fn shadow_scene(ground_bsdf: Box<dyn crate::bsdfs::BSDF>) -> Scene {
+    let mut ground = square("ground", 0.0, 5.0, true);
+    ground.bsdf = ground_bsdf;
+    let mut occluder = square("occluder", 0.5, 0.2, true);
+    occluder.bsdf = diffuse(0.5);
+    let mut emitter = square("emitter", 1.0, 0.3, false);
+    emitter.bsdf = diffuse(0.0);
+    emitter.emission = Color::value(50.0);
+    new_scene(
+        camera_down(2.5, 60.0),
+        vec![ground, occluder, emitter],
+        GOLDEN_SPP,
+    )
+}
+
+fn path(strategy: IntegratorPathTracingStrategies) -> IntegratorType {
+    IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: None,
+        depth_limits: Default::default(),
+        strategy,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }))
+}
+
+fn case(
+    name: &'static str,
+    scene: Scene,
+    integrator: IntegratorType,
+    tolerance: f32,
+) -> GoldenCase {
+    GoldenCase {
+        name,
+        scene: scene.seed(GOLDEN_SEED),
+        integrator,
+        tolerance,
+    }
+}
+
+/// All the golden cases (the names are the file names)
+pub fn golden_cases() -> Vec<GoldenCase> {
+    let shadow = || shadow_scene(diffuse(0.8));
+    let glossy = || {
+        shadow_scene(Box::new(BSDFPhong {
+            specular: BSDFColor::UniformColor(Color::value(0.8)),
+            exponent: 5.0,
+        }))
+    };
+    let analytic = |s: AnalyticScene| s.scene.nb_samples(GOLDEN_SPP);
+    vec![
+        case(
+            "furnace_path",
+            analytic(furnace(0.5, 1.0)),
+            path(IntegratorPathTracingStrategies::All),
+            0.006,
+        ),
+        case(
+            "point_light_path",
+            analytic(point_light(0.5, 10000.0)),
+            path(IntegratorPathTracingStrategies::All),
+            0.001,
+        ),
+        case(
+            "shadow_path",
+            shadow(),
+            path(IntegratorPathTracingStrategies::All),
+            0.005,
+        ),
+        case(
+            "shadow_direct",
+            shadow(),
+            IntegratorType::Primal(Box::new(IntegratorDirect {
+                nb_bsdf_samples: 1,
+                nb_light_samples: 1,
+            })),
+            0.004,
+        ),
+        case(
+            "shadow_ao",
+            shadow(),
+            IntegratorType::Primal(Box::new(IntegratorAO {
+                max_distance: Some(1.0),
+                normal_correction: false,
+            })),
+            0.001,
+        ),
+        case(
+            "shadow_vpl",
+            shadow(),
+            IntegratorType::Primal(Box::new(IntegratorVPL {
+                nb_vpl: 128,
+                max_depth: None,
+                clamping_factor: Some(0.1),
+                nb_passes: 1,
+                visibility_cache: None,
+            })),
+            0.005,
+        ),
+        case(
+            "glossy_path",
+            glossy().nb_samples(4 * GOLDEN_SPP),
+            path(IntegratorPathTracingStrategies::All),
+            0.003,
+        ),
+        case(
+            "parallel_planes_path_bsdf",
+            analytic(parallel_planes(0.5, 1.0)),
+            path(IntegratorPathTracingStrategies::BSDF),
+            0.012,
+        ),
+    ]
+}
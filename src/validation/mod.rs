@@ -0,0 +1,377 @@
+//! Analytic scenes to validate the integrators and the BSDFs (feature "validation").
+//! Each scene comes with the closed-form average radiance seen by the camera,
+//! the tests (tests/validation.rs) render them and compare the averages.
+//! The BSDF sampling routines are checked by the chi2 module (tests/chi2.rs).
+use crate::bsdfs::diffuse::BSDFDiffuse;
+use crate::bsdfs::BSDFColor;
+use crate::camera::Camera;
+use crate::emitter::{EmitterSelection, EnvironmentLight, EnvironmentMap, SunDisk};
+use crate::geometry::{bounding_sphere, Mesh};
+use crate::integrators::{IntegratorType, NoObserver};
+use crate::samplers::SamplerType;
+use crate::scene::{Film, Scene, ThreadPinning};
+use crate::structure::{Bitmap, Color};
+use crate::volume::HomogenousVolume;
+use cgmath::*;
+use std::sync::atomic::AtomicUsize;
+
+pub mod chi2;
+pub mod golden;
+
+/// Seed of the analytic scenes: the tests give the same results at each run
+/// (Scene::seed to change it)
+pub const VALIDATION_SEED: u64 = 1;
+
+/// Scene with a known average radiance over the image
+pub struct AnalyticScene {
+    pub name: &'static str,
+    pub scene: Scene,
+    pub expected: f32,
+}
+
+impl AnalyticScene {
+    /// Render the scene and return the relative error of the image average.
+    /// Fails if the relative error is above the tolerance.
+    pub fn validate(
+        &self,
+        integrator: &mut IntegratorType,
+        tolerance: f32,
+    ) -> std::result::Result<f32, String> {
+        let img = integrator.compute(&self.scene, &NoObserver);
+        let avg = img.values["primal"].average();
+        let avg = (avg.r + avg.g + avg.b) / 3.0;
+        let error = (avg - self.expected).abs() / self.expected;
+        if error.is_finite() && error <= tolerance {
+            Ok(error)
+        } else {
+            Err(format!(
+                "{}: average {} instead of {} (relative error {} > {})",
+                self.name, avg, self.expected, error, tolerance
+            ))
+        }
+    }
+}
+
+fn diffuse(albedo: f32) -> Box<BSDFDiffuse> {
+    Box::new(BSDFDiffuse {
+        diffuse: BSDFColor::UniformColor(Color::value(albedo)),
+    })
+}
+
+fn new_scene(camera: Camera, meshes: Vec<Mesh>, nb_samples: usize) -> Scene {
+    Scene {
+        camera,
+        cameras: vec![],
+        meshes,
+        nb_samples,
+        nb_threads: None,
+        thread_pinning: ThreadPinning::None,
+        // Fixed blocks: the seeded images do not depend on the number of threads
+        tile_size: Some(crate::integrators::TILE_SIZE),
+        output_img_path: "validation.pfm".to_string(),
+        output_variance: false,
+        output_volume: false,
+        output_ids: None,
+        demodulate: false,
+        seed: Some(VALIDATION_SEED),
+        pass: AtomicUsize::new(0),
+        lock_passes: false,
+        deterministic: false,
+        sampler: SamplerType::default(),
+        emitter_selection: EmitterSelection::default(),
+        env_guiding: None,
+        film: Film::default(),
+        sample_rejection: None,
+        non_finite: Default::default(),
+        path_debugger: Default::default(),
+        emitter_environment: None,
+        volume: None,
+        emitters_cache: Default::default(),
+        auto_tile_size: Default::default(),
+    }
+}
+
+/// Camera at the given height looking down (-z)
+fn camera_down(height: f32, fov: f32) -> Camera {
+    let matrix = Matrix4::from_translation(Vector3::new(0.0, 0.0, height))
+        * Matrix4::from_angle_x(Deg(180.0));
+    Camera::new(Vector2::new(32, 32), fov, matrix)
+}
+
+/// Square [-half_size, half_size]^2 at the height z (normal +z or -z)
+fn square(name: &str, z: f32, half_size: f32, facing_up: bool) -> Mesh {
+    square_at(name, Point2::new(0.0, 0.0), z, half_size, facing_up)
+}
+
+/// Same as square, centered on (c.x, c.y)
+fn square_at(name: &str, c: Point2<f32>, z: f32, half_size: f32, facing_up: bool) -> Mesh {
+    let s = half_size;
+    let vertices = vec![
+        Vector3::new(c.x - s, c.y - s, z),
+        Vector3::new(c.x + s, c.y - s, z),
+        Vector3::new(c.x + s, c.y + s, z),
+        Vector3::new(c.x - s, c.y + s, z),
+    ];
+    let (n, indices) = if facing_up {
+        (1.0, vec![Vector3::new(0, 1, 2), Vector3::new(0, 2, 3)])
+    } else {
+        (-1.0, vec![Vector3::new(0, 2, 1), Vector3::new(0, 3, 2)])
+    };
+    let normals = vec![Vector3::new(0.0, 0.0, n); 4];
+    Mesh::new(name.to_string(), vertices, indices, Some(normals), None)
+}
+
+/// Unit sphere tessellation with the normals pointing inside.
+/// The triangles do not share their vertices to use the geometric normals
+/// (interpolated normals break the energy conservation of the furnace)
+fn inside_sphere(name: &str, nb_theta: usize, nb_phi: usize) -> Mesh {
+    let point = |i: usize, j: usize| {
+        let theta = std::f32::consts::PI * i as f32 / nb_theta as f32;
+        let phi = 2.0 * std::f32::consts::PI * j as f32 / nb_phi as f32;
+        Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    };
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    for i in 0..nb_theta {
+        for j in 0..nb_phi {
+            let quad = [
+                point(i, j),
+                point(i, j + 1),
+                point(i + 1, j + 1),
+                point(i + 1, j),
+            ];
+            for &(v0, v1, v2) in &[(quad[0], quad[1], quad[2]), (quad[0], quad[2], quad[3])] {
+                let n = (v1 - v0).cross(v2 - v0);
+                // Skip the degenerated triangles at the poles
+                if n.magnitude2() < 1e-12 {
+                    continue;
+                }
+                if n.dot(v0 + v1 + v2) > 0.0 {
+                    vertices.extend_from_slice(&[v0, v2, v1]);
+                    normals.extend_from_slice(&[-n.normalize(); 3]);
+                } else {
+                    vertices.extend_from_slice(&[v0, v1, v2]);
+                    normals.extend_from_slice(&[n.normalize(); 3]);
+                }
+            }
+        }
+    }
+    let indices = (0..vertices.len() / 3)
+        .map(|i| Vector3::new(3 * i, 3 * i + 1, 3 * i + 2))
+        .collect();
+    Mesh::new(name.to_string(), vertices, indices, Some(normals), None)
+}
+
+/// Form factor between a point (facing +z) and a parallel rectangle
+/// [x0, x1] x [y0, y1] located h above it (facing -z)
+fn form_factor_rectangle(p: Point2<f32>, x: (f32, f32), y: (f32, f32), h: f32) -> f32 {
+    // Rectangle with a corner above the point (odd in a and b)
+    let corner = |a: f32, b: f32| {
+        let (a, b) = (a / h, b / h);
+        let sa = (1.0 + a * a).sqrt();
+        let sb = (1.0 + b * b).sqrt();
+        (a / sa * (b / sa).atan() + b / sb * (a / sb).atan()) / (2.0 * std::f32::consts::PI)
+    };
+    let (x0, x1) = (x.0 - p.x, x.1 - p.x);
+    let (y0, y1) = (y.0 - p.y, y.1 - p.y);
+    corner(x1, y1) - corner(x0, y1) - corner(x1, y0) + corner(x0, y0)
+}
+
+/// Average over the pixels of the radiance reflected by a diffuse ground (z = 0)
+/// lit by a square emitter of half size s at the height h
+fn expected_ground(camera: &Camera, albedo: f32, emission: f32, s: f32, h: f32) -> f32 {
+    expected_ground_lights(camera, albedo, &[(Point2::new(0.0, 0.0), emission)], s, h)
+}
+
+/// Same as expected_ground with several square emitters (center, emission)
+fn expected_ground_lights(
+    camera: &Camera,
+    albedo: f32,
+    lights: &[(Point2<f32>, f32)],
+    s: f32,
+    h: f32,
+) -> f32 {
+    let size = *camera.size();
+    let mut sum = 0.0;
+    for iy in 0..size.y {
+        for ix in 0..size.x {
+            let ray = camera.generate(Point2::new(ix as f32 + 0.5, iy as f32 + 0.5)).ray;
+            let t = -ray.o.z / ray.d.z;
+            let p = Point2::new(ray.o.x + t * ray.d.x, ray.o.y + t * ray.d.y);
+            for &(c, emission) in lights {
+                let (x, y) = ((c.x - s, c.x + s), (c.y - s, c.y + s));
+                sum += albedo * emission * form_factor_rectangle(p, x, y, h);
+            }
+        }
+    }
+    sum / (size.x * size.y) as f32
+}
+
+/// White furnace: the camera is inside a closed diffuse emitting sphere.
+/// Every bounce adds the same emission: L = Le / (1 - albedo)
+/// (needs an infinite maximum depth)
+pub fn furnace(albedo: f32, emission: f32) -> AnalyticScene {
+    let mut sphere = inside_sphere("sphere", 8, 16);
+    sphere.bsdf = diffuse(albedo);
+    sphere.emission = Color::value(emission);
+    AnalyticScene {
+        name: "furnace",
+        scene: new_scene(camera_down(0.0, 60.0), vec![sphere], 128),
+        expected: emission / (1.0 - albedo),
+    }
+}
+
+/// Furnace filled with a non-absorbing medium: the scattering keeps the radiance uniform
+pub fn furnace_medium(albedo: f32, emission: f32, sigma_s: f32) -> AnalyticScene {
+    let mut furnace = furnace(albedo, emission);
+    furnace.name = "furnace medium";
+    furnace.scene.volume = Some(HomogenousVolume {
+        sigma_a: Color::zero(),
+        sigma_s: Color::value(sigma_s),
+        sigma_t: Color::value(sigma_s),
+        density: 1.0,
+        transmittance: Default::default(),
+    });
+    furnace
+}
+
+/// Diffuse ground under a large parallel emitting plane (direct lighting only)
+pub fn parallel_planes(albedo: f32, emission: f32) -> AnalyticScene {
+    let (s, h) = (10.0, 1.0);
+    let mut ground = square("ground", 0.0, 100.0, true);
+    ground.bsdf = diffuse(albedo);
+    let mut emitter = square("emitter", h, s, false);
+    emitter.bsdf = diffuse(0.0);
+    emitter.emission = Color::value(emission);
+    let camera = camera_down(0.5 * h, 60.0);
+    let expected = expected_ground(&camera, albedo, emission, s, h);
+    AnalyticScene {
+        name: "parallel planes",
+        scene: new_scene(camera, vec![ground, emitter], 128),
+        expected,
+    }
+}
+
+/// Diffuse ground lit by a small emitter (inverse square falloff)
+pub fn point_light(albedo: f32, emission: f32) -> AnalyticScene {
+    let (s, h) = (0.01, 1.0);
+    let mut ground = square("ground", 0.0, 100.0, true);
+    ground.bsdf = diffuse(albedo);
+    let mut emitter = square("emitter", h, s, false);
+    emitter.bsdf = diffuse(0.0);
+    emitter.emission = Color::value(emission);
+    // The camera sees the ground up to ~1.3 h away from the light
+    let camera = camera_down(0.5 * h, 90.0);
+    let expected = expected_ground(&camera, albedo, emission, s, h);
+    AnalyticScene {
+        name: "point light",
+        scene: new_scene(camera, vec![ground, emitter], 128),
+        expected,
+    }
+}
+
+/// Diffuse ground lit by a grid of small emitters with different powers
+/// (some of them facing away from the ground)
+pub fn many_lights(albedo: f32, emission: f32) -> AnalyticScene {
+    let (s, h) = (0.02, 1.0);
+    let mut ground = square("ground", 0.0, 100.0, true);
+    ground.bsdf = diffuse(albedo);
+    let mut meshes = vec![ground];
+    let mut lights = vec![];
+    for i in 0..16 {
+        let c = Point2::new((i % 4) as f32 * 0.4 - 0.6, (i / 4) as f32 * 0.4 - 0.6);
+        let facing_down = i % 5 != 0;
+        let mut emitter = square_at(&format!("emitter{}", i), c, h, s, !facing_down);
+        emitter.bsdf = diffuse(0.0);
+        emitter.emission = Color::value(emission * (1 + i % 3) as f32);
+        if facing_down {
+            lights.push((c, emitter.emission.r));
+        }
+        meshes.push(emitter);
+    }
+    let camera = camera_down(0.5 * h, 90.0);
+    let expected = expected_ground_lights(&camera, albedo, &lights, s, h);
+    AnalyticScene {
+        name: "many lights",
+        scene: new_scene(camera, meshes, 128),
+        expected,
+    }
+}
+
+/// Dim sky with a small bright sun above the ground (y is the up axis of the maps)
+pub fn sun_sky_map() -> Bitmap {
+    let size = Vector2::new(64, 32);
+    let mut colors = vec![Color::value(0.2); (size.x * size.y) as usize];
+    for y in 10..12 {
+        for x in 31..33 {
+            colors[(y * size.x + x) as usize] = Color::value(50.0);
+        }
+    }
+    Bitmap {
+        size,
+        colors,
+        alpha: None,
+    }
+}
+
+/// Diffuse ground lit by the environment sun_sky_map (direct lighting only)
+pub fn environment_ground(albedo: f32, mis_compensation: bool) -> AnalyticScene {
+    let mut ground = square("ground", 0.0, 100.0, true);
+    ground.bsdf = diffuse(albedo);
+    let meshes = vec![ground];
+    let env = EnvironmentLight {
+        luminance: Color::one(),
+        map: Some(EnvironmentMap::new(sun_sky_map(), mis_compensation)),
+        sun: None,
+        world: bounding_sphere(&meshes),
+    };
+    // Irradiance of the ground (normal +z): midpoint rule on (cos theta, phi)
+    let (nb_z, nb_phi) = (512, 2048);
+    let mut irradiance = 0.0;
+    for i in 0..nb_z {
+        let z = (i as f32 + 0.5) / nb_z as f32;
+        let r = (1.0 - z * z).sqrt();
+        for j in 0..nb_phi {
+            let phi = 2.0 * std::f32::consts::PI * (j as f32 + 0.5) / nb_phi as f32;
+            let d = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+            irradiance += env.map.as_ref().unwrap().eval(d).r * z;
+        }
+    }
+    irradiance *= 2.0 * std::f32::consts::PI / (nb_z * nb_phi) as f32;
+
+    let mut scene = new_scene(camera_down(1.0, 60.0), meshes, 128);
+    scene.emitter_environment = Some(env);
+    AnalyticScene {
+        name: "environment ground",
+        scene,
+        expected: albedo * irradiance * std::f32::consts::FRAC_1_PI,
+    }
+}
+
+/// Diffuse ground lit by a uniform sky and a sun disk with limb darkening
+/// (direct lighting only). The disk is large enough to be found by the BSDF sampling.
+pub fn sun_ground(albedo: f32) -> AnalyticScene {
+    let mut ground = square("ground", 0.0, 100.0, true);
+    ground.bsdf = diffuse(albedo);
+    let meshes = vec![ground];
+    let sun = SunDisk::new(Vector3::new(0.6, 0.0, 0.8), 0.05, Color::value(500.0), 0.6);
+    // The cosine is linear over the (symmetric) disk: the one of its center
+    let irradiance = 0.2 * std::f32::consts::PI + sun.irradiance().r * 0.8;
+    let env = EnvironmentLight {
+        luminance: Color::value(0.2),
+        map: None,
+        sun: Some(sun),
+        world: bounding_sphere(&meshes),
+    };
+    let mut scene = new_scene(camera_down(1.0, 60.0), meshes, 128);
+    scene.emitter_environment = Some(env);
+    AnalyticScene {
+        name: "sun ground",
+        scene,
+        expected: albedo * irradiance * std::f32::consts::FRAC_1_PI,
+    }
+}
@@ -1,4 +1,5 @@
 use crate::math;
+use crate::samplers::Sampler;
 use crate::structure::*;
 use cgmath::*;
 
@@ -48,12 +49,68 @@ impl PhaseFunction {
     }
 }
 
+/// Estimators of the transmittance of the shadow rays.
+/// The medium is homogeneous, so the null-collision estimators use a majorant
+/// larger than the extinction (`majorant_scale` times its largest channel)
+/// to behave as with a heterogeneous medium.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum TransmittanceEstimator {
+    /// Closed form exp(-sigma_t d)
+    #[default]
+    Analytic,
+    /// Delta tracking: 1 if the ray escapes without a real collision, 0 otherwise
+    TrackLength,
+    /// Product of the null-collision probabilities along the ray
+    RatioTracking,
+    /// Ratio tracking of the extinction above a control (the smallest channel),
+    /// whose transmittance is analytic
+    ResidualRatioTracking,
+    /// Power series of the exponential of the optical depth with a russian roulette
+    /// on its terms (Georgiev et al. 2019)
+    PowerSeries,
+}
+
+impl std::str::FromStr for TransmittanceEstimator {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "analytic" => Ok(TransmittanceEstimator::Analytic),
+            "track-length" => Ok(TransmittanceEstimator::TrackLength),
+            "ratio" => Ok(TransmittanceEstimator::RatioTracking),
+            "residual-ratio" => Ok(TransmittanceEstimator::ResidualRatioTracking),
+            "power-series" => Ok(TransmittanceEstimator::PowerSeries),
+            _ => Err(format!(
+                "unknown transmittance estimator: {} (analytic, track-length, ratio, residual-ratio, power-series)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TransmittanceOptions {
+    pub estimator: TransmittanceEstimator,
+    /// Ratio between the majorant and the largest extinction (>= 1)
+    pub majorant_scale: f32,
+}
+
+impl Default for TransmittanceOptions {
+    fn default() -> Self {
+        TransmittanceOptions {
+            estimator: TransmittanceEstimator::default(),
+            majorant_scale: 2.0,
+        }
+    }
+}
+
 // Consider isotropic participating media
 pub struct HomogenousVolume {
     pub sigma_a: Color,
     pub sigma_s: Color,
     pub sigma_t: Color,
     pub density: f32,
+    /// Estimator used by the shadow rays of the path tracers
+    pub transmittance: TransmittanceOptions,
 }
 
 // Take the tungsten convention
@@ -119,6 +176,103 @@ impl HomogenousVolume {
         (-tau).exp()
     }
 
+    /// Stochastic estimate of the transmittance along the ray (see TransmittanceEstimator)
+    pub fn estimate_transmittance(&self, r: Ray, sampler: &mut dyn Sampler) -> Color {
+        self.estimate_transmittance_cost(r, sampler).0
+    }
+
+    /// Estimate of the transmittance with the number of extinction lookups
+    /// (the cost of the estimator in a heterogeneous medium)
+    pub fn estimate_transmittance_cost(&self, r: Ray, sampler: &mut dyn Sampler) -> (Color, u32) {
+        let d = r.tfar;
+        let majorant =
+            |sigma: Color| sigma.channel_max() * self.transmittance.majorant_scale.max(1.0);
+        // Product of the null-collision probabilities (ratio tracking)
+        let ratio_tracking = |sigma: Color, sampler: &mut dyn Sampler| {
+            let majorant = majorant(sigma);
+            let mut tr = Color::one();
+            let mut nb_lookups = 0;
+            if majorant == 0.0 {
+                return (tr, nb_lookups);
+            }
+            let mut t = 0.0;
+            loop {
+                t -= (1.0 - sampler.next()).ln() / majorant;
+                if t >= d {
+                    return (tr, nb_lookups);
+                }
+                nb_lookups += 1;
+                tr *= &(Color::one() - sigma / majorant);
+            }
+        };
+        match self.transmittance.estimator {
+            TransmittanceEstimator::Analytic => (self.transmittance(r), 0),
+            TransmittanceEstimator::TrackLength => {
+                // The same random number decides the real collision of every channel
+                let majorant = majorant(self.sigma_t);
+                let mut tr = Color::one();
+                let mut nb_lookups = 0;
+                if majorant == 0.0 {
+                    return (tr, nb_lookups);
+                }
+                let mut t = 0.0;
+                loop {
+                    t -= (1.0 - sampler.next()).ln() / majorant;
+                    if t >= d || tr.is_zero() {
+                        return (tr, nb_lookups);
+                    }
+                    nb_lookups += 1;
+                    let u = sampler.next() * majorant;
+                    if u < self.sigma_t.r {
+                        tr.r = 0.0;
+                    }
+                    if u < self.sigma_t.g {
+                        tr.g = 0.0;
+                    }
+                    if u < self.sigma_t.b {
+                        tr.b = 0.0;
+                    }
+                }
+            }
+            TransmittanceEstimator::RatioTracking => ratio_tracking(self.sigma_t, sampler),
+            TransmittanceEstimator::ResidualRatioTracking => {
+                let control = self.sigma_t.r.min(self.sigma_t.g.min(self.sigma_t.b));
+                let (tr, nb_lookups) =
+                    ratio_tracking(self.sigma_t - Color::value(control), sampler);
+                (tr * (-control * d).exp(), nb_lookups)
+            }
+            TransmittanceEstimator::PowerSeries => {
+                // exp(-tau) = exp(-majorant d) sum_k (majorant d)^k / k! prod_i (1 - sigma_t(x_i) / majorant)
+                // The terms are evaluated up to the mode of the series, then continued
+                // with the probability of the ratio of two successive terms
+                let majorant = majorant(self.sigma_t);
+                let tau_majorant = majorant * d;
+                if majorant == 0.0 {
+                    return (Color::one(), 0);
+                }
+                let ratio = Color::one() - self.sigma_t / majorant;
+                let nb_deterministic = tau_majorant.ceil() as u32;
+                let mut term = Color::one();
+                let mut sum = Color::one();
+                let mut k = 0;
+                loop {
+                    k += 1;
+                    let mut continue_prob = 1.0;
+                    if k > nb_deterministic {
+                        continue_prob = (tau_majorant / k as f32).min(1.0);
+                        if sampler.next() >= continue_prob {
+                            break;
+                        }
+                    }
+                    // The extinction is looked up at a uniform point of the ray
+                    term *= &(ratio * (tau_majorant / (k as f32 * continue_prob)));
+                    sum += term;
+                }
+                (sum * (-tau_majorant).exp(), k - 1)
+            }
+        }
+    }
+
     pub fn pdf(&self, r: Ray, end_on_surface: bool) -> f32 {
         let tau = self.sigma_t * (r.tfar);
         if end_on_surface {
@@ -0,0 +1,135 @@
+//! Scene hot-reload: the files read to load a scene are polled for modifications
+//! (no file system notification, so it also works on network drives).
+use crate::error::Result;
+use crate::texture::udim::{udim_tiles, UDIM_TOKEN};
+use crate::tools::extension;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Lines "<keyword> <value>" of a text file (OBJ or MTL)
+fn keyword_values(filename: &Path, keyword: &str) -> Result<Vec<String>> {
+    let file = std::io::BufReader::new(std::fs::File::open(filename)?);
+    let mut values = vec![];
+    for line in file.lines() {
+        let line = line?;
+        let mut tokens = line.trim().splitn(2, char::is_whitespace);
+        if tokens.next() == Some(keyword) {
+            if let Some(v) = tokens.next() {
+                values.push(v.trim().to_string());
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Values of the "filename" entries of the JSON scene (textures and environment map)
+fn json_filenames(v: &serde_json::Value, filenames: &mut Vec<String>) {
+    match v {
+        serde_json::Value::Object(map) => {
+            for (k, v) in map {
+                match (k.as_str(), v.as_str()) {
+                    ("filename", Some(filename)) => filenames.push(filename.to_string()),
+                    _ => json_filenames(v, filenames),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            values.iter().for_each(|v| json_filenames(v, filenames));
+        }
+        _ => {}
+    }
+}
+
+/// Files read when loading the scene: the scene file itself and for a JSON scene,
+/// the OBJ file with its material libraries and textures and the images of the scene.
+/// The UDIM textures add their directory (new tiles) and their current tiles.
+pub fn scene_files(filename: &str) -> Result<Vec<PathBuf>> {
+    let scene_path = Path::new(filename);
+    let mut files = vec![scene_path.to_path_buf()];
+    if extension(filename)? != "json" {
+        // The PBRT includes are not followed
+        return Ok(files);
+    }
+
+    let data = std::fs::read_to_string(scene_path)?;
+    let v: serde_json::Value = serde_json::from_str(&data)?;
+    let wk = scene_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut images = vec![];
+    if let Some(obj_path) = v.get("meshes").and_then(|v| v.as_str()) {
+        let obj_path = wk.join(obj_path);
+        let obj_wk = obj_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        for mtl in keyword_values(&obj_path, "mtllib")? {
+            let mtl_path = obj_wk.join(mtl);
+            // Missing material libraries are ignored by the OBJ loader
+            if let Ok(textures) = keyword_values(&mtl_path, "map_Kd") {
+                images.extend(textures.iter().map(|t| obj_wk.join(t)));
+            }
+            files.push(mtl_path);
+        }
+        files.push(obj_path);
+    }
+    let mut filenames = vec![];
+    json_filenames(&v, &mut filenames);
+    images.extend(filenames.iter().map(PathBuf::from));
+
+    for image in images {
+        match image.to_str() {
+            Some(pattern) if pattern.contains(UDIM_TOKEN) => {
+                let dir = image
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."));
+                files.push(dir.to_path_buf());
+                files.extend(udim_tiles(pattern)?.into_iter().map(|(_, path)| path));
+            }
+            _ => files.push(image),
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Modification times of a list of files
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new(files: Vec<PathBuf>) -> FileWatcher {
+        FileWatcher {
+            files: files
+                .into_iter()
+                .map(|f| {
+                    let time = modified(&f);
+                    (f, time)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(f, _)| f.as_path())
+    }
+
+    /// Files modified, created or removed since the previous call (or the creation)
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        for (f, time) in &mut self.files {
+            let current = modified(f);
+            if current != *time {
+                *time = current;
+                changed.push(f.clone());
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,162 @@
+extern crate cgmath;
+extern crate embree_rs;
+extern crate rustlight;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+use rustlight::accel::{BVHElement, Triangle, TriangleBVH};
+use rustlight::geometry::Mesh;
+use rustlight::integrators::embree_scene;
+use rustlight::scene::{Acceleration, EmbreeAcceleration};
+use rustlight::structure::Ray;
+
+/// Square [-s, s]^2 at the height z facing +z
+fn quad(name: &str, z: f32, s: f32) -> Mesh {
+    let vertices = vec![
+        Vector3::new(-s, -s, z),
+        Vector3::new(s, -s, z),
+        Vector3::new(s, s, z),
+        Vector3::new(-s, s, z),
+    ];
+    let indices = vec![Vector3::new(0, 1, 2), Vector3::new(0, 2, 3)];
+    Mesh::new(name.to_string(), vertices, indices, None, None)
+}
+
+fn meshes() -> Vec<Mesh> {
+    vec![quad("bottom", 0.0, 1.0), quad("top", 1.0, 0.5)]
+}
+
+#[test]
+fn all_hits_along_ray() {
+    let meshes = meshes();
+    let device = embree_rs::Device::new();
+    let scene = embree_scene(&device, &meshes);
+    let accel = EmbreeAcceleration::new(&meshes, &scene);
+    let names = |r: &Ray| {
+        accel
+            .intersect_all_along_ray(r)
+            .iter()
+            .map(|its| (its.mesh.name.clone(), its.dist))
+            .collect::<Vec<_>>()
+    };
+
+    let up = Ray::new(Point3::new(0.1, 0.2, -1.0), Vector3::new(0.0, 0.0, 1.0));
+    let hits = names(&up);
+    assert_eq!(hits.len(), 2, "{:?}", hits);
+    for ((name, dist), (expected_name, expected_dist)) in
+        hits.iter().zip(&[("bottom", 1.0), ("top", 2.0)])
+    {
+        assert_eq!(name, expected_name);
+        assert!((dist - expected_dist).abs() < 1e-4, "{:?}", hits);
+    }
+    assert!(accel.intersect_any(&up));
+
+    // Segment ending before the bottom quad
+    let mut short = up;
+    short.tfar = 0.5;
+    assert!(!accel.intersect_any(&short));
+    assert!(names(&short).is_empty());
+
+    // Next to the top quad, then outside of both
+    let side = Ray::new(Point3::new(0.8, 0.0, -1.0), up.d);
+    assert_eq!(names(&side).len(), 1);
+    let miss = Ray::new(Point3::new(2.0, 0.0, -1.0), up.d);
+    assert!(!accel.intersect_any(&miss));
+    assert!(names(&miss).is_empty());
+}
+
+#[test]
+fn closest_points() {
+    let meshes = meshes();
+    let device = embree_rs::Device::new();
+    let scene = embree_scene(&device, &meshes);
+    let accel = EmbreeAcceleration::new(&meshes, &scene);
+
+    // (query, mesh, closest point)
+    let cases = [
+        (
+            Point3::new(0.2, 0.3, 0.4),
+            "bottom",
+            Point3::new(0.2, 0.3, 0.0),
+        ),
+        (
+            Point3::new(0.9, 0.0, 0.9),
+            "top",
+            Point3::new(0.5, 0.0, 1.0),
+        ),
+        (
+            Point3::new(0.0, 0.0, 1.2),
+            "top",
+            Point3::new(0.0, 0.0, 1.0),
+        ),
+    ];
+    for (p, name, expected) in &cases {
+        let q = accel.closest_point_query(p, 10.0).unwrap();
+        assert_eq!(&q.mesh.name, name);
+        assert!(
+            (q.p - expected).magnitude() < 1e-4,
+            "{:?} instead of {:?}",
+            q.p,
+            expected
+        );
+        assert!((q.dist - (p - expected).magnitude()).abs() < 1e-4);
+        assert!((q.n_g - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-4);
+        // Same point from the barycentric coordinates
+        let i = q.mesh.indices[q.face];
+        let v = &q.mesh.vertices;
+        let b = v[i.x] * (1.0 - q.face_uv.x - q.face_uv.y)
+            + v[i.y] * q.face_uv.x
+            + v[i.z] * q.face_uv.y;
+        assert!((b - Vector3::new(q.p.x, q.p.y, q.p.z)).magnitude() < 1e-4);
+    }
+    assert!(accel
+        .closest_point_query(&Point3::new(0.0, 0.0, 0.5), 0.4)
+        .is_none());
+}
+
+/// Triangles of different sizes and orientations
+fn soup() -> Vec<Triangle> {
+    let mut state = 1u32;
+    let mut next = move || {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    (0..200)
+        .map(|face| {
+            let c = Vector3::new(next(), next(), next()) * 4.0;
+            let mut corner = || c + Vector3::new(next() - 0.5, next() - 0.5, next() - 0.5) * 0.6;
+            Triangle {
+                mesh: 0,
+                face,
+                vertices: [corner(), corner(), corner()],
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn native_bvh_queries() {
+    let bvh = TriangleBVH::create(soup());
+    let triangles = soup();
+    for i in 0..64 {
+        let p = Point3::new((i % 4) as f32, ((i / 4) % 4) as f32, (i / 16) as f32)
+            + Vector3::new(0.3, 0.6, 0.1);
+
+        // Closest point against all the triangles
+        let (id, q) = bvh.closest_point(&p, 100.0).unwrap();
+        let nearest = triangles
+            .iter()
+            .map(|t| (t.closest(&p).0 - p).magnitude())
+            .fold(f32::MAX, f32::min);
+        assert!(((q - p).magnitude() - nearest).abs() < 1e-5);
+        assert!(((bvh.elements[id].closest(&p).0 - q).magnitude()) < 1e-6);
+        assert!(bvh.closest_point(&p, 0.99 * nearest).is_none());
+
+        // Any intersection against all the triangles
+        let d = Vector3::new(1.0, 0.5 - (i % 3) as f32, 0.25).normalize();
+        let ray = Ray::new(p, d);
+        let expected = triangles.iter().any(|t| t.intersection(&ray).is_some());
+        assert_eq!(bvh.any(&ray), expected);
+        let gathered = bvh.gather(ray);
+        assert_eq!(!gathered.is_empty(), expected);
+    }
+}
@@ -0,0 +1,145 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Vector2};
+use rustlight::integrators::BufferCollection;
+use rustlight::structure::{AccumBuffer, Bitmap, Color, Color64, PixelValue, SplatBuffer};
+use std::borrow::Cow;
+
+/// Deterministic values with a large offset (catastrophic cancellation for sum(x^2) - n mean^2)
+fn values(n: usize) -> Vec<f32> {
+    (0..n)
+        .map(|i| 1e4 + ((i * 7919) % 101) as f32 / 10.0)
+        .collect()
+}
+
+fn reference(v: &[f32]) -> (f64, f64) {
+    let n = v.len() as f64;
+    let mean = v.iter().map(|x| *x as f64).sum::<f64>() / n;
+    let var = v.iter().map(|x| (*x as f64 - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, var)
+}
+
+fn assert_close(a: f32, b: f64, tolerance: f64) {
+    assert!(
+        (a as f64 - b).abs() <= tolerance * b.abs().max(1.0),
+        "{} != {}",
+        a,
+        b
+    );
+}
+
+#[test]
+fn accum_mean_variance() {
+    let v = values(1000);
+    let (mean, var) = reference(&v);
+    let mut accum = AccumBuffer::new(Vector2::new(2, 1));
+    let p = Point2::new(1, 0);
+    for x in &v {
+        accum.add(p, Color::value(*x));
+    }
+    assert_eq!(accum.count(p), 1000);
+    assert_close(accum.mean(p).r, mean, 1e-6);
+    assert_close(accum.variance(p).g, var, 1e-3);
+    // The other pixel is untouched
+    assert_eq!(accum.count(Point2::new(0, 0)), 0);
+    assert!(accum.variance(Point2::new(0, 0)).is_zero());
+}
+
+#[test]
+fn accum_merge() {
+    let v = values(300);
+    let (mean, var) = reference(&v);
+    let mut full = AccumBuffer::new(Vector2::new(4, 4));
+    let mut tile_a = AccumBuffer::new(Vector2::new(2, 2));
+    let mut tile_b = AccumBuffer::new(Vector2::new(2, 2));
+    let p = Point2::new(1, 1);
+    for (i, x) in v.iter().enumerate() {
+        // Unbalanced split
+        if i < 50 {
+            tile_a.add(p, Color::value(*x));
+        } else {
+            tile_b.add(p, Color::value(*x));
+        }
+    }
+    full.merge(&tile_a, Point2::new(2, 2));
+    full.merge(&tile_b, Point2::new(2, 2));
+    let q = Point2::new(3, 3);
+    assert_eq!(full.count(q), 300);
+    assert_close(full.mean(q).b, mean, 1e-6);
+    assert_close(full.variance(q).r, var, 1e-3);
+    assert_eq!(full.count_bitmap().pixel(q).r, 300.0);
+}
+
+#[test]
+fn splat_concurrent() {
+    let splats = SplatBuffer::new(Vector2::new(3, 2));
+    let nb_threads = 8;
+    // Multiple of the image width
+    let nb_splats = 9_999;
+    std::thread::scope(|scope| {
+        for t in 0..nb_threads {
+            let splats = &splats;
+            scope.spawn(move || {
+                for i in 0..nb_splats {
+                    // All the threads write to the same pixels
+                    splats.add(Point2::new(i % 3, 1), Color::new(1.0, 0.5, 0.0));
+                    splats.add_safe(Point2::new(-1, t), Color::one());
+                }
+            });
+        }
+    });
+    let img = splats.to_bitmap();
+    let total = nb_threads as f32 * nb_splats as f32;
+    let sum = (0..3).map(|x| img.pixel(Point2::new(x, 1)).r).sum::<f32>();
+    assert_eq!(sum, total);
+    assert_eq!(img.pixel(Point2::new(2, 1)).g, 0.5 * total / 3.0);
+    assert!(img.pixel(Point2::new(0, 0)).is_zero());
+
+    // The values are reset once taken (reused buffers of the deterministic mode)
+    assert_eq!(splats.take(5).to_color(), img.pixel(Point2::new(2, 1)));
+    assert!(splats.pixel(Point2::new(2, 1)).is_zero());
+}
+
+#[test]
+fn weighted_passes() {
+    // Passes normalized by their own number of samples: same as a single pass
+    let names = vec!["primal".to_string()];
+    let size = Vector2::new(3, 2);
+    let p = Point2::new(2, 1);
+    let mut image = BufferCollection::new(Point2::new(0, 0), size, &names);
+    let (mut sum, mut count) = (0.0, 0.0);
+    for (i, &nb_samples) in [3.0, 1.0, 6.0].iter().enumerate() {
+        let value = 1.0 + i as f32;
+        sum += value * nb_samples;
+        count += nb_samples;
+        let mut pass = BufferCollection::new(Point2::new(0, 0), size, &names);
+        pass.accumulate(p, Color::value(value), "primal");
+        image.accumulate_pass(&pass, nb_samples);
+        assert_close(image.get(p, "primal").r, (sum / count) as f64, 1e-6);
+    }
+    assert_eq!(image.pass_weight, 10.0);
+    assert_eq!(image.get(Point2::new(0, 0), "primal").r, 0.0);
+}
+
+#[test]
+fn bitmap_double_precision() {
+    let size = Vector2::new(2, 1);
+    let p = Point2::new(1, 0);
+    let (mut single, mut double) = (Bitmap::<Color>::new(size), Bitmap::<Color64>::new(size));
+    let n = 1_000_000;
+    for _ in 0..n {
+        single.accumulate(p, Color::value(0.1));
+        double.accumulate(p, Color::value(0.1));
+    }
+    let exact = n as f64 * f64::from(0.1f32);
+    let error = |v: f32| (f64::from(v) - exact).abs() / exact;
+    assert!(error(single.pixel(p).r) > 1e-3);
+    assert!(error(double.pixel(p).r) < 1e-6);
+    // Only converted to single precision for the outputs
+    let img = double.to_f32();
+    assert!(matches!(img, Cow::Owned(_)));
+    assert_eq!(img.pixel(p), double.pixel(p));
+    assert!(img.pixel(Point2::new(0, 0)).is_zero());
+    assert!(matches!(single.to_f32(), Cow::Borrowed(_)));
+}
@@ -0,0 +1,33 @@
+extern crate rustlight;
+
+use rustlight::math::{AliasTable, Distribution1DConstruct};
+
+#[test]
+fn alias_table_frequencies() {
+    // Large table: the coin flip needs the low bits of the sample
+    let n = 1000;
+    let mut dist = Distribution1DConstruct::new(n);
+    for i in 0..n {
+        dist.add(if i % 7 == 0 { 0.0 } else { (i % 13 + 1) as f32 });
+    }
+    let dist = dist.normalize();
+    let table = AliasTable::new(&dist);
+
+    // Stratified samples: the frequencies are close to the probabilities
+    let nb_samples = 1 << 22;
+    let mut counts = vec![0usize; n];
+    for k in 0..nb_samples {
+        counts[table.sample((k as f32 + 0.5) / nb_samples as f32)] += 1;
+    }
+    for (i, c) in counts.iter().enumerate() {
+        let freq = *c as f32 / nb_samples as f32;
+        assert_eq!(table.pdf(i), dist.pdf(i));
+        assert!(
+            (freq - dist.pdf(i)).abs() <= 1e-2 * dist.pdf(i) + 1e-6,
+            "{}: {} != {}",
+            i,
+            freq,
+            dist.pdf(i)
+        );
+    }
+}
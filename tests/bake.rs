@@ -0,0 +1,209 @@
+//! Baking checks against closed-form results: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Point3, Vector2, Vector3};
+use rustlight::bake::{save_vertex_colors, IrradianceVolume, IrradianceVolumeConfig, TextureSpace};
+use rustlight::geometry::Mesh;
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::IntegratorType;
+use rustlight::structure::{Bitmap, Color, AABB};
+use rustlight::validation::*;
+use std::sync::Arc;
+
+#[test]
+fn furnace_probes() {
+    // Uniform radiance inside the furnace: irradiance of pi * L around any normal
+    let furnace = furnace(0.5, 1.0);
+    let config = IrradianceVolumeConfig {
+        resolution: Vector3::new(2, 3, 2),
+        nb_strata: 32,
+        max_depth: 16,
+        aabb: Some(AABB {
+            p_min: Vector3::new(-0.5, -0.5, -0.5),
+            p_max: Vector3::new(0.5, 0.5, 0.5),
+        }),
+        ..Default::default()
+    };
+    let volume = IrradianceVolume::bake_scene(&furnace.scene, &config);
+    assert_eq!(volume.probes.len(), 12);
+    assert_eq!(volume.probes[11].position, [0.5, 0.5, 0.5]);
+    assert!(volume.probes.iter().all(|p| p.backface == 0.0));
+    assert!(volume.probes.iter().all(|p| p.radiance.len() == 9));
+
+    let expected = std::f32::consts::PI * furnace.expected;
+    let normals = [
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, -1.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+    ];
+    for p in &[Point3::new(0.0, 0.0, 0.0), Point3::new(-0.5, 0.3, 0.1)] {
+        for n in &normals {
+            let e = volume.irradiance(*p, *n).r;
+            assert!(
+                (e - expected).abs() < 0.03 * expected,
+                "irradiance {} instead of {} ({:?}, {:?})",
+                e,
+                expected,
+                p,
+                n
+            );
+        }
+    }
+
+    // Same probes after a round trip to the disk
+    let filename = std::env::temp_dir().join("rustlight_probes.json");
+    let filename = filename.to_str().unwrap();
+    volume.save(filename).unwrap();
+    let loaded = IrradianceVolume::load(filename).unwrap();
+    std::fs::remove_file(filename).unwrap();
+    assert_eq!(loaded.resolution, [2, 3, 2]);
+    assert_eq!(loaded.probes[5].radiance, volume.probes[5].radiance);
+}
+
+/// Texture coordinates of the ground of parallel_planes (square on the xy plane)
+fn ground_uv(mesh: &mut Mesh, scale: f32, offset: f32) {
+    let uv = mesh
+        .vertices
+        .iter()
+        .map(|v| Vector2::new(v.x * scale + offset, v.y * scale + offset))
+        .collect();
+    mesh.uv = Some(uv);
+}
+
+fn path() -> IntegratorType {
+    IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: None,
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }))
+}
+
+#[test]
+fn parallel_planes_lightmap() {
+    // The texture covers [-0.5, 0.5]^2 under the center of the emitter
+    let (albedo, emission) = (0.5, 2.0);
+    let mut scene = parallel_planes(albedo, emission);
+    ground_uv(&mut scene.scene.meshes[0], 1.0, 0.5);
+    let texture_space = TextureSpace::new(&scene.scene.meshes[0], Vector2::new(8, 8)).unwrap();
+    assert!(texture_space.is_covered(Point2::new(0, 0)));
+    assert!(texture_space.is_covered(Point2::new(7, 7)));
+    scene
+        .scene
+        .camera
+        .set_texture_space(Arc::new(texture_space));
+    scene.scene.nb_samples = 256;
+
+    // Form factor of the emitter (20 x 20 at the height 1) from its center
+    let a = 10.0 / 101.0f32.sqrt();
+    let form_factor = 4.0 * a * a.atan() / std::f32::consts::PI;
+    scene.expected = albedo * emission * form_factor;
+    if let Err(e) = scene.validate(&mut path(), 0.02) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn lightmap_dilation() {
+    // The ground only covers the left half of the texture
+    let mut scene = parallel_planes(0.5, 1.0);
+    let ground = &mut scene.scene.meshes[0];
+    ground_uv(ground, 0.0025, 0.25);
+    ground
+        .uv
+        .as_mut()
+        .unwrap()
+        .iter_mut()
+        .for_each(|uv| uv.y *= 2.0);
+    let mut texture_space = TextureSpace::new(ground, Vector2::new(16, 4)).unwrap();
+    texture_space.dilation = 2;
+    for x in 0..16 {
+        assert_eq!(texture_space.is_covered(Point2::new(x, 1)), x < 8, "{}", x);
+    }
+
+    let mut img: Bitmap = Bitmap::new(Vector2::new(16, 4));
+    for y in 0..4 {
+        for x in 0..16 {
+            let v = if x < 8 { 1.0 + y as f32 } else { 100.0 };
+            img.accumulate(Point2::new(x, y), Color::value(v));
+        }
+    }
+    texture_space.dilate(&mut img);
+    for y in 0..4 {
+        for x in 0..16 {
+            let expected = if x < 10 { 1.0 + y as f32 } else { 0.0 };
+            assert_eq!(img.pixel(Point2::new(x, y)).r, expected, "({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn furnace_vertex_colors() {
+    // Uniform radiance leaving the inside of the furnace
+    let mut furnace = furnace(0.5, 1.0);
+    let texture_space = TextureSpace::vertices(&furnace.scene.meshes[0]);
+    let nb_vertices = furnace.scene.meshes[0].vertices.len();
+    assert_eq!(texture_space.size, Vector2::new(nb_vertices as u32, 1));
+    furnace
+        .scene
+        .camera
+        .set_texture_space(Arc::new(texture_space));
+    furnace.scene.nb_samples = 64;
+    let img = path().compute(&furnace.scene, &rustlight::integrators::NoObserver);
+    let colors = img.values["primal"].to_f32();
+    let values = (0..nb_vertices)
+        .map(|i| colors.pixel(TextureSpace::vertex_texel(i)).r)
+        .collect::<Vec<_>>();
+    // Few samples per vertex: only catch the missed or biased vertices
+    for (i, c) in values.iter().enumerate() {
+        assert!(
+            (c - furnace.expected).abs() < 0.4 * furnace.expected,
+            "vertex {}: {} instead of {}",
+            i,
+            c,
+            furnace.expected
+        );
+    }
+    let mean = values.iter().sum::<f32>() / nb_vertices as f32;
+    assert!(
+        (mean - furnace.expected).abs() < 0.02 * furnace.expected,
+        "mean {} instead of {}",
+        mean,
+        furnace.expected
+    );
+
+    let mesh = &furnace.scene.meshes[0];
+    for ext in &["ply", "obj"] {
+        let filename = std::env::temp_dir().join(format!("rustlight_vertex_colors.{}", ext));
+        let filename = filename.to_str().unwrap();
+        save_vertex_colors(mesh, &colors, filename).unwrap();
+        let content = std::fs::read_to_string(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        // Header lines before the vertices (the furnace has its normals)
+        let header = match *ext {
+            "ply" => content.lines().position(|l| l == "end_header").unwrap() + 1,
+            _ => 1,
+        };
+        assert_eq!(header, if *ext == "ply" { 16 } else { 1 });
+        let nb_normals = if *ext == "obj" { nb_vertices } else { 0 };
+        assert_eq!(
+            content.lines().count(),
+            header + nb_vertices + nb_normals + mesh.indices.len()
+        );
+        let first_vertex = content.lines().nth(header).unwrap();
+        let color = first_vertex
+            .split(' ')
+            .last()
+            .unwrap()
+            .parse::<f32>()
+            .unwrap();
+        assert_eq!(color, colors.pixel(TextureSpace::vertex_texel(0)).b);
+    }
+    assert!(save_vertex_colors(mesh, &colors, "vertex_colors.png").is_err());
+}
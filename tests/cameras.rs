@@ -0,0 +1,83 @@
+//! Several named cameras rendered with the same scene: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Deg, Matrix4, Vector2, Vector3};
+use rustlight::camera::Camera;
+use rustlight::integrators::direct::IntegratorDirect;
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::scene::Scene;
+use rustlight::validation::*;
+
+fn direct() -> IntegratorType {
+    IntegratorType::Primal(Box::new(IntegratorDirect {
+        nb_bsdf_samples: 1,
+        nb_light_samples: 1,
+    }))
+}
+
+fn camera(angle: f32, size: u32) -> Camera {
+    let matrix =
+        Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0)) * Matrix4::from_angle_x(Deg(angle));
+    Camera::new(Vector2::new(size, size), 60.0, matrix)
+}
+
+fn scene() -> Scene {
+    let mut scene = environment_ground(0.5, false).scene;
+    scene.nb_samples = 2;
+    scene.seed = Some(42);
+    scene.cameras = vec![
+        ("down".to_string(), camera(180.0, 16)),
+        ("horizon".to_string(), camera(90.0, 8)),
+    ];
+    scene
+}
+
+#[test]
+fn compute_cameras() {
+    let mut scene = scene();
+    let names = vec!["horizon".to_string(), "down".to_string()];
+    let imgs = direct()
+        .compute_cameras(&mut scene, &names, &NoObserver)
+        .unwrap();
+    assert_eq!(imgs.len(), 2);
+    // The main camera is restored
+    assert_eq!(*scene.camera.size(), Vector2::new(32, 32));
+
+    // Same images as the single camera renderings
+    for (name, img) in names.iter().zip(imgs) {
+        scene.camera = scene.find_camera(name).unwrap().clone();
+        let expected = direct().compute(&scene, &NoObserver);
+        let (img, expected) = (&img.values["primal"], &expected.values["primal"]);
+        assert_eq!(img.size, expected.size);
+        assert_eq!(img.colors, expected.colors, "camera {}", name);
+    }
+}
+
+#[test]
+fn unknown_camera() {
+    let mut scene = scene();
+    let names = vec!["down".to_string(), "top".to_string()];
+    assert!(direct()
+        .compute_cameras(&mut scene, &names, &NoObserver)
+        .is_err());
+    assert!(scene.find_camera("top").is_err());
+}
+
+#[test]
+fn resize_all_cameras() {
+    let mut scene = scene();
+    for camera in scene.cameras_mut() {
+        camera.scale_image(0.5);
+    }
+    assert_eq!(*scene.camera.size(), Vector2::new(16, 16));
+    assert_eq!(
+        *scene.find_camera("down").unwrap().size(),
+        Vector2::new(8, 8)
+    );
+    assert_eq!(
+        *scene.find_camera("horizon").unwrap().size(),
+        Vector2::new(4, 4)
+    );
+}
@@ -0,0 +1,107 @@
+//! BSDF sampling checks: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+
+use rustlight::bsdfs::phong::BSDFPhong;
+use rustlight::bsdfs::specular::BSDFSpecular;
+use rustlight::bsdfs::{BSDFColor, BSDF};
+use rustlight::structure::{Color, TexCoords};
+use rustlight::validation::chi2::*;
+
+/// Incoming directions tested for each BSDF (elevation, azimuth)
+const DIRECTIONS: [(f32, f32); 4] = [(0.0, 0.0), (30.0, 45.0), (60.0, 200.0), (85.0, 300.0)];
+
+#[test]
+fn chi2_sampling() {
+    let zoo = bsdf_zoo();
+    // Sidak correction for the multiple tests
+    let nb_tests = zoo.len() * DIRECTIONS.len();
+    let test = ChiSquareTest {
+        significance: 1.0 - (1.0 - 0.01f64).powf(1.0 / nb_tests as f64),
+        ..Default::default()
+    };
+    for (name, bsdf) in &zoo {
+        for &(theta, phi) in &DIRECTIONS {
+            if let Err(e) = test.run(bsdf.as_ref(), &direction(theta, phi)) {
+                panic!("{} (theta: {}, phi: {}): {}", name, theta, phi, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn sampling_consistency() {
+    let mut zoo = bsdf_zoo();
+    zoo.push((
+        "specular".to_string(),
+        Box::new(BSDFSpecular {
+            specular: BSDFColor::UniformColor(Color::value(0.5)),
+        }),
+    ));
+    for (name, bsdf) in &zoo {
+        for &(theta, phi) in &DIRECTIONS {
+            if let Err(e) =
+                check_sampling_consistency(bsdf.as_ref(), &direction(theta, phi), 10_000)
+            {
+                panic!("{} (theta: {}, phi: {}): {}", name, theta, phi, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn roughening() {
+    let color = || BSDFColor::UniformColor(Color::value(0.5));
+    let specular = BSDFSpecular { specular: color() };
+    let phong = BSDFPhong {
+        specular: color(),
+        exponent: 30.0,
+    };
+    let diffuse = &bsdf_zoo()[0].1;
+    assert!(diffuse.roughen(0.5).is_none());
+    assert!(specular.roughen(0.0).is_none());
+    // Already rougher than the minimum
+    assert!(phong
+        .roughen(phong.roughness(&TexCoords::default()) * 0.5)
+        .is_none());
+    let test = ChiSquareTest::default();
+    for (name, bsdf) in &[("specular", &specular as &dyn BSDF), ("phong", &phong)] {
+        let roughened = bsdf.roughen(0.5).unwrap();
+        assert!(!roughened.is_smooth());
+        assert!(
+            (roughened.roughness(&TexCoords::default()) - 0.5).abs() < 1e-4,
+            "{}",
+            name
+        );
+        for &(theta, phi) in &DIRECTIONS {
+            let d_in = direction(theta, phi);
+            if let Err(e) = test.run(roughened.as_ref(), &d_in) {
+                panic!("roughened {} (theta: {}, phi: {}): {}", name, theta, phi, e);
+            }
+            if let Err(e) = check_sampling_consistency(roughened.as_ref(), &d_in, 10_000) {
+                panic!("roughened {} (theta: {}, phi: {}): {}", name, theta, phi, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn reciprocity() {
+    for (name, bsdf) in &bsdf_zoo() {
+        if let Err(e) = check_reciprocity(bsdf.as_ref(), 10_000) {
+            panic!("{}: {}", name, e);
+        }
+    }
+}
+
+#[test]
+fn chi2_distribution() {
+    // Reference values of the chi2 survival function
+    for &(x, dof, p) in &[
+        (3.84, 1.0, 0.05),
+        (18.307, 10.0, 0.05),
+        (124.342, 100.0, 0.05),
+    ] {
+        assert!((chi2_survival(x, dof) - p).abs() < 1e-3);
+    }
+}
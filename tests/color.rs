@@ -0,0 +1,53 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use rustlight::color::{rgb_adaptation, rgb_to_xyz, transform_color, ColorSpace, WhitePoint};
+use rustlight::structure::Color;
+
+fn assert_close(a: f32, b: f32, tolerance: f32) {
+    assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+}
+
+#[test]
+fn transfer_functions_roundtrip() {
+    for &cs in &[ColorSpace::Linear, ColorSpace::SRGB, ColorSpace::Gamma(2.2)] {
+        for i in 0..=100 {
+            let v = i as f32 / 100.0;
+            assert_close(cs.decode(cs.encode(v)), v, 1e-5);
+        }
+    }
+    // Both sides of the linear segment of sRGB
+    assert_close(ColorSpace::SRGB.decode(0.04045), 0.04045 / 12.92, 1e-7);
+    assert_close(ColorSpace::SRGB.decode(0.5), 0.214_041, 1e-5);
+    assert_close(ColorSpace::SRGB.encode(1.0), 1.0, 1e-6);
+}
+
+#[test]
+fn adaptation_maps_the_white_points() {
+    let white = Color::one();
+    let same = transform_color(&rgb_adaptation(WhitePoint::D65, WhitePoint::D65), white);
+    for c in 0..3 {
+        assert_close(same.get(c), 1.0, 1e-4);
+    }
+
+    // The RGB white (D65) adapted to D50 gets the D50 chromaticity
+    let c = transform_color(&rgb_adaptation(WhitePoint::D65, WhitePoint::D50), white);
+    let xyz = rgb_to_xyz() * cgmath::Vector3::new(c.r, c.g, c.b);
+    let sum = xyz.x + xyz.y + xyz.z;
+    assert_close(xyz.x / sum, WhitePoint::D50.x, 1e-4);
+    assert_close(xyz.y / sum, WhitePoint::D50.y, 1e-4);
+    // Bradford keeps the luminance of the white
+    assert_close(xyz.y, 1.0, 1e-3);
+}
+
+#[test]
+fn temperatures() {
+    // The D65 daylight is close to a 6504K black body
+    let wp = WhitePoint::from_temperature(6504.0);
+    assert_close(wp.x, WhitePoint::D65.x, 5e-3);
+    assert_close(wp.y, WhitePoint::D65.y, 1e-2);
+    // The CIE illuminant A is a 2856K black body
+    let wp = WhitePoint::from_temperature(2856.0);
+    assert_close(wp.x, WhitePoint::A.x, 1e-3);
+    assert_close(wp.y, WhitePoint::A.y, 1e-3);
+}
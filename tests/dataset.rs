@@ -0,0 +1,63 @@
+//! Denoising dataset generation: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+extern crate serde_json;
+
+use rustlight::dataset::{generate_scene, DatasetConfig};
+use rustlight::integrators::registry::IntegratorManager;
+use rustlight::integrators::NoObserver;
+use rustlight::structure::Bitmap;
+use rustlight::validation::*;
+
+#[test]
+fn generate_ground() {
+    let output = std::env::temp_dir().join("rustlight_dataset");
+    let config = DatasetConfig {
+        output: output.to_str().unwrap().to_string(),
+        scenes: vec![],
+        seeds: vec![3, 7],
+        noisy_samples: 2,
+        reference_samples: 64,
+        image_scale: None,
+        integrator: serde_json::json!({"type": "direct"}),
+    };
+    let mut integrator = IntegratorManager::default()
+        .create_from_json("direct", &serde_json::Value::Null)
+        .unwrap();
+    let analytic = environment_ground(0.5, false);
+    let mut scene = analytic.scene;
+    let samples = generate_scene(
+        "ground",
+        "ground.json",
+        &mut scene,
+        &config,
+        &mut integrator,
+        &NoObserver,
+    )
+    .unwrap();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[1].seed, 7);
+    assert_eq!(samples[1].noisy, "ground/7/noisy.exr");
+    assert_eq!(samples[0].reference, samples[1].reference);
+
+    let read = |filename: &str| Bitmap::read_exr(output.join(filename).to_str().unwrap()).unwrap();
+    let average = |filename: &str| read(filename).average().avg();
+    let reference = average(&samples[0].reference);
+    assert!(
+        (reference - analytic.expected).abs() < 0.02 * analytic.expected,
+        "{} != {}",
+        reference,
+        analytic.expected
+    );
+    // Different noise for each seed
+    let noisy = samples.iter().map(|s| read(&s.noisy)).collect::<Vec<_>>();
+    assert_eq!(noisy[0].size, read(&samples[0].reference).size);
+    assert_ne!(noisy[0].colors, noisy[1].colors);
+    for s in &samples {
+        // The ground fills the image
+        let albedo = average(&s.albedo);
+        assert!((albedo - 0.5).abs() < 1e-3, "albedo: {}", albedo);
+        assert!((average(&s.normal) - 1.0 / 3.0).abs() < 1e-3);
+        assert!(average(&s.variance) > 0.0);
+    }
+}
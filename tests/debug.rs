@@ -0,0 +1,88 @@
+//! False-color debug views: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Point2;
+use rustlight::color::ColorSpace;
+use rustlight::integrators::debug::{DebugView, IntegratorDebug};
+use rustlight::integrators::explicit::vol_primitives::{IntegratorVolPrimitives, VolPrimitivies};
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::structure::{Bitmap, Color};
+use rustlight::validation::*;
+
+/// Ground seen from above (fills the image)
+fn render(view: DebugView) -> Bitmap {
+    let mut scene = environment_ground(0.5, false).scene;
+    scene.nb_samples = 4;
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorDebug { view }));
+    let img = integrator.compute(&scene, &NoObserver);
+    img.values["primal"].to_f32().into_owned()
+}
+
+fn assert_uniform(img: &Bitmap, expected: Color) {
+    for c in &img.colors {
+        assert!(
+            (c.r - expected.r).abs() < 1e-4
+                && (c.g - expected.g).abs() < 1e-4
+                && (c.b - expected.b).abs() < 1e-4,
+            "{:?} != {:?}",
+            c,
+            expected
+        );
+    }
+}
+
+#[test]
+fn normals() {
+    let up = ColorSpace::SRGB.decode_color(Color::new(0.5, 0.5, 1.0));
+    assert_uniform(&render(DebugView::GeometricNormal), up);
+    assert_uniform(&render(DebugView::ShadingNormal), up);
+}
+
+#[test]
+fn mesh_and_uv() {
+    let img = render(DebugView::MeshID);
+    assert!(!img.colors[0].is_zero());
+    assert_uniform(&img, img.colors[0]);
+    // The ground does not have texture coordinates
+    assert_uniform(&render(DebugView::UV), Color::zero());
+}
+
+#[test]
+fn scalar_views() {
+    // Closer at the center of the image (camera above the ground)
+    let depth = render(DebugView::Depth);
+    let center = depth.pixel(Point2::new(16, 16)).avg();
+    let corner = depth.pixel(Point2::new(0, 0)).avg();
+    assert!(center < corner, "{} >= {}", center, corner);
+
+    for &view in &[DebugView::Cost, DebugView::BSDFDensity] {
+        let img = render(view);
+        assert!(img.average().avg() > 0.0, "{:?}", view);
+    }
+}
+
+#[test]
+fn parse_views() {
+    assert_eq!("density".parse(), Ok(DebugView::BSDFDensity));
+    assert_eq!("ng".parse(), Ok(DebugView::GeometricNormal));
+    assert!("heatmap".parse::<DebugView>().is_err());
+}
+
+#[test]
+fn vol_primitives_heatmap() {
+    let mut scene = furnace_medium(0.5, 1.0, 1.0).scene;
+    scene.nb_samples = 1;
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorVolPrimitives {
+        nb_primitive: 16,
+        max_depth: Some(2),
+        primitives: VolPrimitivies::Beams,
+        heatmap: true,
+    }));
+    let img = integrator.compute(&scene, &NoObserver);
+    let img = img.values["primal"].to_f32().into_owned();
+    // Every camera ray visits at least the root of the BVH
+    assert!(img.colors.iter().all(|c| !c.is_zero()));
+    assert!(img.colors.iter().all(|c| c.r.is_finite()));
+}
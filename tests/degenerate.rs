@@ -0,0 +1,74 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Vector2, Vector3};
+use rustlight::geometry::{load_obj, remove_degenerate_triangles, DegenerateTriangles};
+
+#[test]
+fn remove_triangles() {
+    let mut vertices = vec![
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(f32::NAN, 0.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(2.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+    ];
+    let mut indices = vec![
+        Vector3::new(0, 2, 4),
+        Vector3::new(0, 0, 4),
+        Vector3::new(0, 1, 4),
+        Vector3::new(0, 2, 3),
+        Vector3::new(0, 2, 7),
+    ];
+    let mut normals = Some(vec![Vector3::new(0.0, 0.0, 1.0); 5]);
+    let mut uv = Some((0..5).map(|i| Vector2::new(i as f32, 0.0)).collect());
+    let removed = remove_degenerate_triangles(&mut vertices, &mut indices, &mut normals, &mut uv);
+    assert_eq!(
+        removed,
+        DegenerateTriangles {
+            repeated_indices: 1,
+            invalid_vertices: 2,
+            zero_area: 1,
+        }
+    );
+    assert_eq!(removed.total(), 4);
+
+    // The unused vertices are removed with their attributes
+    assert_eq!(indices, vec![Vector3::new(0, 1, 2)]);
+    assert_eq!(vertices.len(), 3);
+    assert_eq!(vertices[2], Vector3::new(0.0, 1.0, 0.0));
+    assert_eq!(normals.unwrap().len(), 3);
+    assert_eq!(uv.unwrap()[2], Vector2::new(4.0, 0.0));
+}
+
+#[test]
+fn valid_mesh_unchanged() {
+    let mut vertices = vec![
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(5.0, 5.0, 5.0),
+    ];
+    let mut indices = vec![Vector3::new(0, 1, 2)];
+    let removed = remove_degenerate_triangles(&mut vertices, &mut indices, &mut None, &mut None);
+    assert_eq!(removed.total(), 0);
+    // Nothing to compact
+    assert_eq!(vertices.len(), 4);
+}
+
+#[test]
+fn obj_with_degenerated_triangles() {
+    let file = std::env::temp_dir().join("rustlight_degenerate.obj");
+    std::fs::write(
+        &file,
+        "o sliver\nv 0 0 0\nv 1 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 4\n",
+    )
+    .unwrap();
+    let meshes = load_obj(&file, 60.0).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(meshes[0].indices.len(), 1);
+    let normals = meshes[0].normals.as_ref().unwrap();
+    assert!(normals
+        .iter()
+        .all(|n| n.x.is_finite() && n.y.is_finite() && n.z.is_finite()));
+}
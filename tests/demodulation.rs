@@ -0,0 +1,52 @@
+//! Albedo demodulation: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+
+use rustlight::integrators::demodulation::remodulate;
+use rustlight::integrators::direct::IntegratorDirect;
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::validation::*;
+
+#[test]
+fn demodulated_ground() {
+    let albedo = 0.5;
+    let mut scene = parallel_planes(albedo, 2.0);
+    scene.scene.nb_samples = 16;
+    scene.scene.seed = Some(1);
+    scene.scene.output_variance = true;
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorDirect {
+        nb_bsdf_samples: 1,
+        nb_light_samples: 1,
+    }));
+    let reference = integrator.compute(&scene.scene, &NoObserver);
+    scene.scene.demodulate = true;
+    let mut img = integrator.compute(&scene.scene, &NoObserver);
+    assert!(!reference.values.contains_key("albedo"));
+
+    // The diffuse ground covers the image
+    let a = img.values["albedo"].to_f32();
+    assert!(a.colors.iter().all(|c| (c.r - albedo).abs() < 1e-5));
+    let demodulated = img.values["primal"].average().r;
+    let expected = scene.expected / albedo;
+    assert!(
+        (demodulated - expected).abs() < 0.02 * expected,
+        "demodulated average {} instead of {}",
+        demodulated,
+        expected
+    );
+
+    // Same samples: the remodulation gives the radiance back
+    remodulate(&mut img);
+    for name in &["primal", "variance"] {
+        let (a, b) = (img.values[*name].to_f32(), reference.values[*name].to_f32());
+        for (c, r) in a.colors.iter().zip(&b.colors) {
+            assert!(
+                (c.r - r.r).abs() <= 1e-5 * r.r.abs(),
+                "{}: {} instead of {}",
+                name,
+                c.r,
+                r.r
+            );
+        }
+    }
+}
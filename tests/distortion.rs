@@ -0,0 +1,69 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{InnerSpace, Matrix4, Point2, SquareMatrix, Vector2};
+use rustlight::camera::Camera;
+
+fn camera(k1: f32, k2: f32) -> Camera {
+    let mut camera = Camera::new(Vector2::new(64, 48), 60.0, Matrix4::identity());
+    camera.set_distortion(k1, k2).unwrap();
+    camera
+}
+
+#[test]
+fn radial_distortion() {
+    let reference = Camera::new(Vector2::new(64, 48), 60.0, Matrix4::identity());
+    let center = Point2::new(32.0, 24.0);
+    let corner = Point2::new(0.5, 0.5);
+    let forward = reference.generate(center).ray.d;
+    let angle = |camera: &Camera, px| camera.generate(px).ray.d.dot(forward).acos();
+
+    // Without coefficients: same rays as the pinhole
+    let identity = camera(0.0, 0.0);
+    for &px in &[center, corner, Point2::new(10.3, 40.1)] {
+        let (a, b) = (identity.generate(px), reference.generate(px));
+        assert!((a.ray.d - b.ray.d).magnitude() < 1e-5);
+        assert!((a.we_pdf - b.we_pdf).abs() < 1e-4 * b.we_pdf);
+    }
+    // Pincushion (k1 > 0): the corners see closer to the center, and the opposite for barrel
+    let pincushion = camera(0.2, 0.05);
+    let barrel = camera(-0.1, 0.0);
+    assert!(angle(&pincushion, center) < 1e-3);
+    assert!(angle(&pincushion, corner) < angle(&reference, corner) - 0.01);
+    assert!(angle(&barrel, corner) > angle(&reference, corner) + 0.01);
+}
+
+#[test]
+fn distorted_splatting() {
+    // The splatting finds the pixel of the camera rays with their importance
+    for camera in &[camera(0.2, 0.05), camera(-0.1, 0.0)] {
+        for &(x, y) in &[(32.0, 24.0), (0.5, 0.5), (63.5, 10.2), (12.7, 40.1)] {
+            let px = Point2::new(x, y);
+            let sample = camera.generate(px);
+            let dist = 3.0;
+            let p = sample.ray.o + sample.ray.d * dist;
+            let (importance, splat_px) = camera.sample_direct(&p).unwrap();
+            assert!(
+                (splat_px - px).magnitude() < 1e-2,
+                "{:?} instead of {:?}",
+                splat_px,
+                px
+            );
+            let importance = importance.r * dist * dist;
+            assert!(
+                (importance - sample.we_pdf).abs() < 1e-3 * sample.we_pdf,
+                "{} instead of {}",
+                importance,
+                sample.we_pdf
+            );
+        }
+    }
+}
+
+#[test]
+fn folded_distortion() {
+    // r - r^3 is not monotonic before the corners (radius 1.05 for this camera)
+    let mut camera = Camera::new(Vector2::new(64, 48), 60.0, Matrix4::identity());
+    assert!(camera.set_distortion(-1.0, 0.0).is_err());
+    assert!(camera.distortion().is_none());
+}
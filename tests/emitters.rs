@@ -0,0 +1,138 @@
+//! Incremental updates of the emitter selection: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Point3};
+use rustlight::emitter::{Emitter, EmitterSampler, EmitterSelection};
+use rustlight::scene::Scene;
+use rustlight::structure::Color;
+use rustlight::validation::*;
+use std::sync::Arc;
+
+fn scene(selection: EmitterSelection) -> Scene {
+    many_lights(0.5, 1.0).scene.emitter_selection(selection)
+}
+
+/// Selection probabilities of the cached structures against new ones
+fn assert_same_selection(scene: &Scene) {
+    let cached = scene.emitters_sampler();
+    let emitters = scene
+        .meshes
+        .iter()
+        .filter(|m| !m.emission.is_zero())
+        .map(|m| m as &dyn Emitter)
+        .collect::<Vec<_>>();
+    assert_eq!(cached.emitters.len(), emitters.len());
+    let rebuilt = EmitterSampler::new(emitters, scene.emitter_selection);
+    let p = Point3::new(0.1, -0.2, 0.0);
+    for i in 0..256 {
+        let v = (i as f32 + 0.5) / 256.0;
+        let pdf = |s: &EmitterSampler| s.select_emitter(&p, v).map(|(pdf, e)| (pdf, e.flux()));
+        let (a, b) = (pdf(&cached), pdf(&rebuilt));
+        match (a, b) {
+            (Some((pdf_a, flux_a)), Some((pdf_b, flux_b))) => {
+                assert!((pdf_a - pdf_b).abs() <= 1e-5, "{} != {}", pdf_a, pdf_b);
+                assert_eq!(flux_a, flux_b);
+            }
+            (None, None) => {}
+            _ => panic!("{:?} != {:?}", a.map(|v| v.0), b.map(|v| v.0)),
+        }
+    }
+}
+
+#[test]
+fn cache_is_reused() {
+    let scene = scene(EmitterSelection::LightBVH);
+    let (a, b) = (scene.emitters_sampler(), scene.emitters_sampler());
+    assert!(Arc::ptr_eq(&a.data, &b.data));
+}
+
+#[test]
+fn emission_changes() {
+    for &selection in &[EmitterSelection::Power, EmitterSelection::LightBVH] {
+        let mut scene = scene(selection);
+        scene.emitters_sampler();
+        // Flux changes only
+        scene.set_emission(3, Color::value(10.0));
+        scene.set_emission(7, Color::value(0.5));
+        assert_same_selection(&scene);
+        // Emitters turned off and on
+        scene.set_emission(5, Color::zero());
+        assert_same_selection(&scene);
+        scene.set_emission(0, Color::value(0.1));
+        assert_same_selection(&scene);
+    }
+}
+
+#[test]
+fn added_and_removed_meshes() {
+    let mut scene = scene(EmitterSelection::LightBVH);
+    scene.emitters_sampler();
+    let mut light = scene.remove_mesh(2);
+    assert_same_selection(&scene);
+    light.emission = Color::value(20.0);
+    scene.add_mesh(light);
+    assert_same_selection(&scene);
+    // The ground: only the ids of the emitters are shifted
+    let data = scene.emitters_sampler().data;
+    scene.remove_mesh(0);
+    assert!(Arc::ptr_eq(&data, &scene.emitters_sampler().data));
+    assert_same_selection(&scene);
+}
+
+#[test]
+fn sampler_updates() {
+    let scene = scene(EmitterSelection::LightBVH);
+    let mut sampler = scene.emitters_sampler();
+    let first = sampler.emitters[0];
+    sampler.remove_emitter(0);
+    sampler.update();
+    assert_eq!(sampler.emitters.len(), 15);
+    sampler.add_emitter(first);
+    sampler.update();
+    assert_eq!(sampler.emitters.len(), 16);
+    // The scene cache is not modified
+    assert_eq!(scene.emitters_sampler().emitters.len(), 16);
+}
+
+#[test]
+fn stratified_allocation() {
+    let scene = scene(EmitterSelection::Power);
+    let sampler = scene.emitters_sampler();
+    let p = Point3::new(0.1, -0.2, 0.0);
+    let index = |e: &dyn Emitter| {
+        sampler
+            .emitters
+            .iter()
+            .position(|o| {
+                std::ptr::eq(
+                    *o as *const dyn Emitter as *const (),
+                    e as *const _ as *const (),
+                )
+            })
+            .unwrap()
+    };
+    let n = 8;
+    for k in 0..64 {
+        // Same jitter in all the strata: each emitter gets its share of the n samples
+        // up to one (independent draws can give several samples to a weak emitter)
+        let r_sel = (k as f32 + 0.5) / 64.0;
+        let mut counts = vec![0; sampler.emitters.len()];
+        for i in 0..n {
+            let record =
+                sampler.sample_light_stratified(&p, (i, n), r_sel, 0.5, Point2::new(0.5, 0.5));
+            counts[index(record.emitter)] += 1;
+        }
+        for (i, &count) in counts.iter().enumerate() {
+            let expected = n as f32 * sampler.data.emitters_cdf.pdf(i);
+            assert!(
+                (count as f32 - expected).abs() < 1.0,
+                "emitter {}: {} samples instead of {}",
+                i,
+                count,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Learned environment sampling: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+use rustlight::emitter::Emitter;
+use rustlight::env_guiding::{EnvironmentGuide, GUIDE_RESOLUTION};
+use rustlight::geometry::Mesh;
+use rustlight::integrators::direct::IntegratorDirect;
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::math::sample_uniform_sphere;
+use rustlight::structure::PDF;
+use rustlight::validation::*;
+
+fn grid(n: usize) -> impl Iterator<Item = Point2<f32>> {
+    (0..n * n).map(move |i| {
+        Point2::new(
+            ((i % n) as f32 + 0.5) / n as f32,
+            ((i / n) as f32 + 0.5) / n as f32,
+        )
+    })
+}
+
+#[test]
+fn pdf_is_normalized() {
+    let env = environment_ground(0.5, false)
+        .scene
+        .emitter_environment
+        .unwrap();
+    // Band of bins with a bright one
+    let (w, h) = GUIDE_RESOLUTION;
+    let mut histogram = vec![0.0; w * h];
+    for (i, v) in histogram.iter_mut().enumerate() {
+        if (i / w) == h / 2 {
+            *v = if i % w == 5 { 20.0 } else { 1.0 };
+        }
+    }
+    let guide = EnvironmentGuide::from_histogram(&histogram).unwrap();
+    assert!(EnvironmentGuide::from_histogram(&vec![0.0; w * h]).is_none());
+
+    let n = 512;
+    let integral = grid(n)
+        .map(|u| guide.direction_pdf(&env, sample_uniform_sphere(u)))
+        .sum::<f32>()
+        * 4.0
+        * std::f32::consts::PI
+        / (n * n) as f32;
+    assert!((integral - 1.0).abs() < 0.01, "integral: {}", integral);
+
+    let p = Point3::new(0.0, 0.0, 0.0);
+    for (i, u) in grid(32).enumerate() {
+        let r = (i as f32 + 0.5) / 1024.0;
+        let record = guide.sample_direct(&env, &p, r, u);
+        assert!((record.d.magnitude() - 1.0).abs() < 1e-4);
+        let pdf = match record.pdf {
+            PDF::SolidAngle(v) => v,
+            _ => panic!("the environment samples the solid angle"),
+        };
+        let expected = guide.direction_pdf(&env, record.d);
+        assert!(
+            (pdf - expected).abs() <= 1e-3 * expected,
+            "{} != {}",
+            pdf,
+            expected
+        );
+        let weight = env.emitted_luminance(record.d).r / pdf;
+        assert!((record.weight.r - weight).abs() <= 1e-3 * weight);
+    }
+}
+
+/// Black roof of half size 5 at the height 2: the ground under the camera only sees
+/// the sky below ~22 degrees of elevation (the sun of the map is hidden)
+fn roof() -> Mesh {
+    let (s, z) = (5.0, 2.0);
+    let vertices = vec![
+        Vector3::new(-s, -s, z),
+        Vector3::new(s, -s, z),
+        Vector3::new(s, s, z),
+        Vector3::new(-s, s, z),
+    ];
+    let indices = vec![Vector3::new(0, 2, 1), Vector3::new(0, 3, 2)];
+    let normals = vec![Vector3::new(0.0, 0.0, -1.0); 4];
+    Mesh::new("roof".to_string(), vertices, indices, Some(normals), None)
+}
+
+#[test]
+fn occluded_sky() {
+    let mut scene = environment_ground(0.5, false).scene;
+    scene.add_mesh(roof());
+    scene.nb_samples = 256;
+    scene.seed = Some(1);
+    scene.output_variance = true;
+    // Only the emitter sampling (the roof stops most of the BSDF samples)
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorDirect {
+        nb_bsdf_samples: 0,
+        nb_light_samples: 1,
+    }));
+    let reference = integrator.compute(&scene, &NoObserver);
+    assert!(scene.emitters_sampler().data.environment_guide.is_none());
+    scene.env_guiding = Some(4);
+    let guided = integrator.compute(&scene, &NoObserver);
+    assert!(scene.emitters_sampler().data.environment_guide.is_some());
+
+    // Same estimate, with less noise
+    let (a, b) = (
+        guided.values["primal"].average().r,
+        reference.values["primal"].average().r,
+    );
+    assert!((a - b).abs() < 0.03 * b, "guided {} instead of {}", a, b);
+    let (va, vb) = (
+        guided.values["variance"].average().r,
+        reference.values["variance"].average().r,
+    );
+    assert!(va < 0.5 * vb, "variance {} (without guide {})", va, vb);
+
+    // The meshes changed: learned again at the next rendering
+    scene.add_mesh(roof());
+    assert!(scene.emitters_sampler().data.environment_guide.is_none());
+}
@@ -0,0 +1,208 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{InnerSpace, Point2, Vector2};
+use rustlight::emitter::EnvironmentMap;
+use rustlight::math::sample_uniform_sphere;
+use rustlight::structure::{Bitmap, Color};
+
+/// Smooth gradient with a bright spot
+fn map() -> Bitmap {
+    let size = Vector2::new(32, 16);
+    let colors = (0..size.x * size.y)
+        .map(|i| {
+            let (x, y) = (i % size.x, i / size.x);
+            if x == 20 && y == 5 {
+                Color::value(100.0)
+            } else {
+                Color::new(x as f32 / 32.0, 0.5, y as f32 / 16.0)
+            }
+        })
+        .collect();
+    Bitmap {
+        size,
+        colors,
+        alpha: None,
+    }
+}
+
+fn grid(n: usize) -> impl Iterator<Item = Point2<f32>> {
+    (0..n * n).map(move |i| {
+        Point2::new(
+            ((i % n) as f32 + 0.5) / n as f32,
+            ((i / n) as f32 + 0.5) / n as f32,
+        )
+    })
+}
+
+#[test]
+fn pdf_is_normalized() {
+    for &mis_compensation in &[false, true] {
+        let env = EnvironmentMap::new(map(), mis_compensation);
+        // Integral over the sphere with uniform directions
+        let n = 512;
+        let integral = grid(n)
+            .map(|u| env.pdf(sample_uniform_sphere(u)))
+            .sum::<f32>()
+            * 4.0
+            * std::f32::consts::PI
+            / (n * n) as f32;
+        assert!((integral - 1.0).abs() < 0.01, "integral: {}", integral);
+    }
+}
+
+#[test]
+fn sample_matches_pdf() {
+    for &mis_compensation in &[false, true] {
+        let env = EnvironmentMap::new(map(), mis_compensation);
+        for u in grid(64) {
+            let (d, pdf) = env.sample(u);
+            assert!((d.magnitude() - 1.0).abs() < 1e-4);
+            let expected = env.pdf(d);
+            assert!(
+                (pdf - expected).abs() <= 1e-3 * expected.max(1.0),
+                "{} != {}",
+                pdf,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn compensation_skips_dark_texels() {
+    let env = EnvironmentMap::new(map(), true);
+    // Dark corner of the gradient (theta ~ 0.2 rad, y up)
+    let d = cgmath::Vector3::new(0.0, 0.98, -0.2).normalize();
+    assert_eq!(env.pdf(d), 0.0);
+    assert!(EnvironmentMap::new(map(), false).pdf(d) > 0.0);
+
+    // A constant map keeps its distribution
+    let constant = Bitmap {
+        size: Vector2::new(8, 4),
+        colors: vec![Color::one(); 32],
+        alpha: None,
+    };
+    assert!(EnvironmentMap::new(constant, true).pdf(d) > 0.0);
+}
+
+#[test]
+fn world_bounding_sphere() {
+    use cgmath::{Point3, Vector3};
+    use rustlight::emitter::{Emitter, EnvironmentLight};
+    use rustlight::structure::{BoundingSphere, AABB, PDF};
+    assert_eq!(AABB::default().bounding_sphere(), BoundingSphere::default());
+    let world = AABB::default()
+        .union_vec(&Vector3::new(-1.0, 0.0, 0.0))
+        .union_vec(&Vector3::new(1.0, 2.0, 2.0))
+        .bounding_sphere();
+    assert_eq!(world.center, Point3::new(0.0, 1.0, 1.0));
+    assert!((world.radius - 3.0f32.sqrt()).abs() < 1e-5);
+
+    // Uniform environment: flux through the disk facing each direction
+    let env = EnvironmentLight {
+        luminance: Color::one(),
+        map: None,
+        sun: None,
+        world,
+    };
+    let flux = env.flux();
+    assert!((flux.r - 3.0 * std::f32::consts::PI).abs() < 1e-4);
+
+    // The shadow rays end outside of the scene
+    let p = Point3::new(0.5, 0.5, 0.5);
+    for u in grid(8) {
+        let sample = env.sample_direct(&p, 0.5, u);
+        assert!((sample.p - world.center).magnitude() > world.radius);
+        assert!((sample.p - p).normalize().dot(sample.d) > 0.999);
+        match sample.pdf {
+            PDF::SolidAngle(v) => assert!((v - 0.25 / std::f32::consts::PI).abs() < 1e-6),
+            _ => panic!("solid angle pdf expected"),
+        }
+    }
+
+    // Area density seen from a distance of 2 with a 60 degrees angle
+    match PDF::Area(0.5).to_solid_angle(2.0, 0.5) {
+        PDF::SolidAngle(v) => assert!((v - 4.0).abs() < 1e-6),
+        _ => panic!("solid angle pdf expected"),
+    }
+    assert!(PDF::Area(0.5).to_solid_angle(2.0, -0.1).is_zero());
+}
+
+#[test]
+fn rotated_map() {
+    use cgmath::{Deg, Matrix3};
+    let env = EnvironmentMap::new(map(), false);
+    let rotated = EnvironmentMap::new(map(), false).rotate(Deg(90.0), Deg(20.0));
+    let to_world = Matrix3::from_angle_y(Deg(90.0)) * Matrix3::from_angle_x(Deg(20.0));
+    for u in grid(16) {
+        let d = sample_uniform_sphere(u);
+        assert_eq!(rotated.eval(to_world * d), env.eval(d));
+        let (pdf, expected) = (rotated.pdf(to_world * d), env.pdf(d));
+        assert!((pdf - expected).abs() <= 1e-3 * expected.max(1.0));
+        let (d, pdf) = rotated.sample(u);
+        let expected = rotated.pdf(d);
+        assert!((pdf - expected).abs() <= 1e-3 * expected.max(1.0));
+    }
+    // The bright spot is clamped
+    let clamped = EnvironmentMap::new(map().clamp_luminance(1.0), false);
+    let max = clamped.image.luminance().into_iter().fold(0.0, f32::max);
+    assert!(max <= 1.0 + 1e-5, "max luminance: {}", max);
+    assert!(clamped.average.r < env.average.r);
+}
+
+#[test]
+fn sun_disk_sampling() {
+    use cgmath::Vector3;
+    use rustlight::emitter::{Emitter, EnvironmentLight, SunDisk};
+    use rustlight::structure::{BoundingSphere, PDF};
+    let direction = Vector3::new(0.3, 0.8, -0.5).normalize();
+    for &(radius, limb_darkening) in &[(0.00465, 0.6), (0.2, 0.0), (0.2, 1.0)] {
+        let sun = SunDisk::new(direction, radius, Color::new(1.0, 2.0, 3.0), limb_darkening);
+        assert!((sun.direction() - direction).magnitude() < 1e-5);
+        for u in grid(32) {
+            let (d, pdf) = sun.sample(u);
+            assert!((d.magnitude() - 1.0).abs() < 1e-4);
+            assert!(d.dot(direction) >= radius.cos() - 1e-6);
+            let expected = sun.pdf(d);
+            assert!(
+                (pdf - expected).abs() <= 1e-2 * expected,
+                "{} != {}",
+                pdf,
+                expected
+            );
+            // Exactly proportional to the radiance
+            let weight = sun.eval(d) / pdf;
+            let irradiance = sun.irradiance();
+            assert!((weight.b - irradiance.b).abs() <= 1e-2 * irradiance.b);
+        }
+        // Darker on the edges
+        let edge = sun.sample(Point2::new(0.999, 0.0)).0;
+        assert!(sun.eval(edge).r <= sun.eval(direction).r);
+        assert!(sun.eval(-direction).is_zero() && sun.pdf(-direction) == 0.0);
+    }
+
+    // Combined with the sky: same density for the sampling and the MIS
+    let sun = SunDisk::new(direction, 0.05, Color::value(1e4), 0.6);
+    let env = EnvironmentLight {
+        luminance: Color::value(0.5),
+        map: None,
+        sun: Some(sun),
+        world: BoundingSphere::default(),
+    };
+    let p = cgmath::Point3::new(0.0, 0.0, 0.0);
+    let mut nb_sun = 0;
+    for (i, u) in grid(16).enumerate() {
+        let sample = env.sample_direct(&p, (i as f32 + 0.5) / 256.0, u);
+        let pdf = match sample.pdf {
+            PDF::SolidAngle(v) => v,
+            _ => panic!("solid angle pdf expected"),
+        };
+        assert!((pdf - env.direction_pdf(sample.d)).abs() <= 1e-4 * pdf);
+        if sample.d.dot(direction) > 0.05f32.cos() {
+            nb_sun += 1;
+        }
+    }
+    // The sun is ten times brighter than the sky, but keeps at most 90% of the samples
+    assert!((228..=234).contains(&nb_sun), "{}", nb_sun);
+}
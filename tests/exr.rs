@@ -0,0 +1,265 @@
+extern crate byteorder;
+extern crate cgmath;
+extern crate rustlight;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use cgmath::Vector2;
+use rustlight::structure::{Bitmap, Color};
+
+/// Scanline EXR file written by hand, blocks given as (first line, data)
+fn write_exr(
+    name: &str,
+    channels: &[(&str, i32)],
+    compression: u8,
+    data_window: [i32; 4],
+    display_window: [i32; 4],
+    blocks: &[(i32, Vec<u8>)],
+) -> String {
+    let attribute = |header: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]| {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(type_name.as_bytes());
+        header.push(0);
+        header
+            .write_i32::<LittleEndian>(value.len() as i32)
+            .unwrap();
+        header.extend_from_slice(value);
+    };
+    let window = |w: [i32; 4]| w.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+    let mut chlist = vec![];
+    for (name, pixel_type) in channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        for v in &[*pixel_type, 0, 1, 1] {
+            chlist.write_i32::<LittleEndian>(*v).unwrap();
+        }
+    }
+    chlist.push(0);
+    let mut header = vec![];
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(&mut header, "compression", "compression", &[compression]);
+    attribute(&mut header, "dataWindow", "box2i", &window(data_window));
+    attribute(
+        &mut header,
+        "displayWindow",
+        "box2i",
+        &window(display_window),
+    );
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    header.push(0);
+
+    let mut file = vec![];
+    file.write_u32::<LittleEndian>(20000630).unwrap();
+    file.write_u32::<LittleEndian>(2).unwrap();
+    file.extend_from_slice(&header);
+    let mut offset = (file.len() + 8 * blocks.len()) as u64;
+    for (_, data) in blocks {
+        file.write_u64::<LittleEndian>(offset).unwrap();
+        offset += 8 + data.len() as u64;
+    }
+    for (y, data) in blocks {
+        file.write_i32::<LittleEndian>(*y).unwrap();
+        file.write_i32::<LittleEndian>(data.len() as i32).unwrap();
+        file.extend_from_slice(data);
+    }
+    let filename = std::env::temp_dir().join(name);
+    std::fs::write(&filename, file).unwrap();
+    filename.to_str().unwrap().to_string()
+}
+
+/// Byte reordering of the RLE and ZIP compressions (even and odd bytes split, delta encoded)
+fn reorder(raw: &[u8]) -> Vec<u8> {
+    let mut res = raw.iter().step_by(2).copied().collect::<Vec<_>>();
+    res.extend(raw.iter().skip(1).step_by(2));
+    for i in (1..res.len()).rev() {
+        res[i] = res[i].wrapping_sub(res[i - 1]).wrapping_add(128);
+    }
+    res
+}
+
+fn rle(raw: &[u8]) -> Vec<u8> {
+    let data = reorder(raw);
+    let mut res = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(128)
+            .take_while(|v| **v == data[i])
+            .count();
+        if run > 1 {
+            res.extend_from_slice(&[(run - 1) as u8, data[i]]);
+        } else {
+            // Single literal byte
+            res.extend_from_slice(&[255, data[i]]);
+        }
+        i += run;
+    }
+    res
+}
+
+/// zlib stream with stored (not compressed) deflate blocks
+fn zip(raw: &[u8]) -> Vec<u8> {
+    let data = reorder(raw);
+    let mut res = vec![0x78, 0x01];
+    let chunks = data.chunks(65535).collect::<Vec<_>>();
+    for (i, chunk) in chunks.iter().enumerate() {
+        res.push((i + 1 == chunks.len()) as u8);
+        let len = chunk.len() as u16;
+        res.extend_from_slice(&len.to_le_bytes());
+        res.extend_from_slice(&(!len).to_le_bytes());
+        res.extend_from_slice(chunk);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for v in &data {
+        a = (a + u32::from(*v)) % 65521;
+        b = (b + a) % 65521;
+    }
+    res.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    res
+}
+
+#[test]
+fn half_rle_data_window() {
+    // 2x2 data window inside a 4x3 display window, channels sorted by name
+    let channels = [("A", 1), ("B", 1), ("G", 1), ("R", 1)];
+    let lines = [
+        // A: 1.0, B: 0.5, G: -2.0, R: 65504 (largest half) / smallest subnormal
+        [
+            [0x3c00, 0x3c00],
+            [0x3800, 0x3800],
+            [0xc000, 0xc000],
+            [0x7bff, 0x0001],
+        ],
+        // A: 0.5, B: 0, G: 1.0, R: 0.5
+        [
+            [0x3800, 0x3800],
+            [0x0000, 0x0000],
+            [0x3c00, 0x3c00],
+            [0x3800, 0x3800],
+        ],
+    ];
+    let blocks = lines
+        .iter()
+        .enumerate()
+        .map(|(y, line)| {
+            let mut raw = vec![];
+            for channel in line {
+                for v in channel {
+                    raw.write_u16::<LittleEndian>(*v).unwrap();
+                }
+            }
+            (1 + y as i32, rle(&raw))
+        })
+        .collect::<Vec<_>>();
+    let filename = write_exr(
+        "rustlight_half_rle.exr",
+        &channels,
+        1,
+        [1, 1, 2, 2],
+        [0, 0, 3, 2],
+        &blocks,
+    );
+    let img = Bitmap::read_exr(&filename).unwrap();
+    assert_eq!(img.size, Vector2::new(4, 3));
+    let pixel = |x: u32, y: u32| img.colors[(y * 4 + x) as usize];
+    let alpha = img.alpha.as_ref().unwrap();
+    assert_eq!(pixel(1, 1), Color::new(65504.0, -2.0, 0.5));
+    assert_eq!(pixel(2, 1).r, 2.0_f32.powi(-24));
+    assert_eq!(pixel(2, 2), Color::new(0.5, 1.0, 0.0));
+    assert_eq!(alpha[4 + 1], 1.0);
+    assert_eq!(alpha[2 * 4 + 2], 0.5);
+    // Outside of the data window
+    assert!(pixel(0, 0).is_zero() && pixel(3, 2).is_zero());
+    assert_eq!(alpha[0], 0.0);
+}
+
+#[test]
+fn float_zip_blocks() {
+    // 16 scanlines per ZIP block: the last block is partial
+    let size = Vector2::new(3, 20);
+    let img = Bitmap::from_fn(size, |p| {
+        Color::new(p.x as f32, p.y as f32, (p.x * p.y) as f32 * 0.25)
+    });
+    let blocks = (0..2)
+        .map(|block| {
+            let mut raw = vec![];
+            for y in block * 16..(20.min(block * 16 + 16)) {
+                for c in &[2, 1, 0] {
+                    for x in 0..size.x {
+                        let v = img.colors[(y * size.x + x) as usize].get(*c);
+                        raw.write_f32::<LittleEndian>(v).unwrap();
+                    }
+                }
+            }
+            (block as i32 * 16, zip(&raw))
+        })
+        .collect::<Vec<_>>();
+    let channels = [("B", 2), ("G", 2), ("R", 2)];
+    let filename = write_exr(
+        "rustlight_zip.exr",
+        &channels,
+        3,
+        [0, 0, 2, 19],
+        [0, 0, 2, 19],
+        &blocks,
+    );
+    let read = Bitmap::read(&filename).unwrap();
+    assert_eq!(read.size, size);
+    assert_eq!(read.colors, img.colors);
+    assert!(read.alpha.is_none());
+}
+
+#[test]
+fn uint_luminance_zips() {
+    // Gray image from the Y channel, other channels ignored
+    let channels = [("Y", 0), ("Z", 2)];
+    let blocks = (0..2)
+        .map(|y| {
+            let mut raw = vec![];
+            for x in 0..2 {
+                raw.write_u32::<LittleEndian>(y * 2 + x).unwrap();
+            }
+            for _ in 0..2 {
+                raw.write_f32::<LittleEndian>(-1.0).unwrap();
+            }
+            (y as i32, zip(&raw))
+        })
+        .collect::<Vec<_>>();
+    let filename = write_exr(
+        "rustlight_zips.exr",
+        &channels,
+        2,
+        [0, 0, 1, 1],
+        [0, 0, 1, 1],
+        &blocks,
+    );
+    let img = Bitmap::read_exr(&filename).unwrap();
+    let expected = (0..4).map(|v| Color::value(v as f32)).collect::<Vec<_>>();
+    assert_eq!(img.colors, expected);
+
+    // Neither colors nor luminance
+    let blocks = (0..2)
+        .map(|y| {
+            let mut raw = vec![];
+            for _ in 0..2 {
+                raw.write_f32::<LittleEndian>(y as f32).unwrap();
+            }
+            (y, raw)
+        })
+        .collect::<Vec<_>>();
+    let filename = write_exr(
+        "rustlight_depth.exr",
+        &[("Z", 2)],
+        0,
+        [0, 0, 1, 1],
+        [0, 0, 1, 1],
+        &blocks,
+    );
+    assert!(Bitmap::read_exr(&filename).is_err());
+
+    // Not an EXR file
+    let filename = std::env::temp_dir().join("rustlight_not_exr.exr");
+    std::fs::write(&filename, b"not an exr file").unwrap();
+    assert!(Bitmap::read_exr(filename.to_str().unwrap()).is_err());
+}
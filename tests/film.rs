@@ -0,0 +1,142 @@
+//! Transparent film and background plate: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate exr;
+extern crate rustlight;
+
+use cgmath::{Deg, Matrix4, Point2, Vector2, Vector3};
+use exr::prelude::{AnyChannel, FlatSamples};
+use rustlight::camera::Camera;
+use rustlight::exr_writer;
+use rustlight::integrators::direct::IntegratorDirect;
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::scene::Film;
+use rustlight::structure::{Bitmap, Color};
+use rustlight::validation::*;
+
+fn direct() -> IntegratorType {
+    IntegratorType::Primal(Box::new(IntegratorDirect {
+        nb_bsdf_samples: 1,
+        nb_light_samples: 1,
+    }))
+}
+
+/// Ground lit by the environment, seen from a camera looking at the horizon
+/// (half of the image is the sky)
+fn horizon(film: Film) -> Bitmap {
+    let mut scene = environment_ground(0.5, false).scene;
+    scene.nb_samples = 4;
+    scene.camera = Camera::new(
+        Vector2::new(32, 32),
+        60.0,
+        Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0)) * Matrix4::from_angle_x(Deg(90.0)),
+    );
+    scene.film = film;
+    let img = direct().compute(&scene, &NoObserver);
+    img.values["primal"].to_f32().into_owned()
+}
+
+fn row_alpha(img: &Bitmap, y: u32) -> f32 {
+    (0..img.size.x)
+        .map(|x| img.alpha(Point2::new(x, y)))
+        .sum::<f32>()
+        / img.size.x as f32
+}
+
+#[test]
+fn transparent_film() {
+    let img = horizon(Film::Transparent);
+    let alpha = img.alpha.as_ref().expect("no alpha channel");
+    let average = alpha.iter().sum::<f32>() / alpha.len() as f32;
+    assert!((average - 0.5).abs() < 0.05, "average alpha: {}", average);
+    assert!(alpha.iter().all(|a| (0.0..=1.0).contains(a)));
+
+    // One side is the sky (transparent and black), the other the ground
+    let (first, last) = (row_alpha(&img, 0), row_alpha(&img, img.size.y - 1));
+    let sky = if first < last { 0 } else { img.size.y - 1 };
+    assert_eq!(first.min(last), 0.0);
+    assert_eq!(first.max(last), 1.0);
+    for x in 0..img.size.x {
+        assert!(img.pixel(Point2::new(x, sky)).is_zero());
+    }
+
+    // The environment is still visible without the alpha channel
+    let opaque = horizon(Film::Opaque);
+    assert!(opaque.alpha.is_none());
+    assert!(!opaque.pixel(Point2::new(0, sky)).is_zero());
+}
+
+#[test]
+fn background_plate() {
+    let sky = if row_alpha(&horizon(Film::Transparent), 0) == 0.0 {
+        0
+    } else {
+        31
+    };
+    let background = Bitmap {
+        size: Vector2::new(4, 4),
+        colors: vec![Color::value(3.0); 16],
+        alpha: None,
+    };
+    let img = horizon(Film::Background(background));
+    assert!(img.alpha.is_none());
+    for x in 0..img.size.x {
+        let c = img.pixel(Point2::new(x, sky));
+        assert!((c.r - 3.0).abs() < 1e-4, "{} != 3.0", c.r);
+    }
+}
+
+fn with_alpha() -> Bitmap {
+    let size = Vector2::new(8, 4);
+    Bitmap {
+        size,
+        colors: (0..32).map(|i| Color::value(i as f32 / 32.0)).collect(),
+        alpha: Some((0..32).map(|i| (i % 5) as f32 / 4.0).collect()),
+    }
+}
+
+fn assert_same_alpha(a: &Bitmap, b: &Bitmap, tolerance: f32) {
+    let (a, b) = (a.alpha.as_ref().unwrap(), b.alpha.as_ref().unwrap());
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+}
+
+#[test]
+fn alpha_roundtrip() {
+    let img = with_alpha();
+    let filename = std::env::temp_dir().join("rustlight_alpha.exr");
+    let filename = filename.to_str().unwrap();
+    let channels = exr_writer::bitmap_channels("", &img);
+    exr_writer::save_channels(filename, img.size, channels, &[]).unwrap();
+    let read = Bitmap::read_exr(filename).unwrap();
+    assert_same_alpha(&img, &read, 0.0);
+
+    let filename = std::env::temp_dir().join("rustlight_alpha.tif");
+    let filename = filename.to_str().unwrap();
+    img.save(filename).unwrap();
+    let read = Bitmap::read(filename).unwrap();
+    assert_same_alpha(&img, &read, 1.0 / 65535.0);
+}
+
+#[test]
+fn layers_roundtrip() {
+    // The main layer is read back from the scanlines interleaved with the other channels
+    let img = with_alpha();
+    let albedo = img.map(|c| c * 0.5);
+    let filename = std::env::temp_dir().join("rustlight_layers.exr");
+    let filename = filename.to_str().unwrap();
+    let mut channels = exr_writer::bitmap_channels("", &img);
+    channels.extend(exr_writer::bitmap_channels("albedo", &albedo));
+    channels.push(AnyChannel::new("depth", FlatSamples::F32(img.luminance())));
+    exr_writer::save_channels(filename, img.size, channels, &[]).unwrap();
+    let read = Bitmap::read_exr(filename).unwrap();
+    assert_same_alpha(&img, &read, 0.0);
+    assert_eq!(read.colors, img.colors);
+
+    img.save_exr(filename).unwrap();
+    let read = Bitmap::read(filename).unwrap();
+    assert_same_alpha(&img, &read, 0.0);
+    assert_eq!(read.colors, img.colors);
+}
@@ -0,0 +1,22 @@
+//! Golden image regression: cargo test --release --features validation --test golden
+//! Set RUSTLIGHT_UPDATE_GOLDEN=1 to regenerate the images after an intended change.
+#![cfg(feature = "validation")]
+extern crate rustlight;
+
+use rustlight::validation::golden::*;
+use std::path::Path;
+
+#[test]
+fn golden_images() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let update = std::env::var_os("RUSTLIGHT_UPDATE_GOLDEN").is_some();
+    let mut failures = vec![];
+    for mut case in golden_cases() {
+        if update {
+            case.update(&dir).expect("Impossible to save the golden image");
+        } else if let Err(e) = case.compare(&dir) {
+            failures.push(e);
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,75 @@
+//! QMC sampler and dimension budget: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Point2;
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::IntegratorType;
+use rustlight::samplers::halton::HaltonSampler;
+use rustlight::samplers::{dimensions, Sampler, SamplerType};
+use rustlight::validation::*;
+
+/// Values of a dimension over the samples of a pixel
+fn pixel_values(sampler: &mut dyn Sampler, dim: u32, nb_samples: usize) -> Vec<f32> {
+    (0..nb_samples)
+        .map(|i| {
+            sampler.start_pixel_sample(Point2::new(3, 5), i);
+            sampler.next_dim(dim)
+        })
+        .collect()
+}
+
+#[test]
+fn stratified_dimensions() {
+    let mut sampler = HaltonSampler::for_task(Some(1), 0);
+    // Base 2 and 3: one sample per stratum
+    for &(dim, n) in &[(0, 16), (1, 9)] {
+        let mut strata = pixel_values(&mut sampler, dim, n)
+            .iter()
+            .map(|v| (v * n as f32) as usize)
+            .collect::<Vec<_>>();
+        strata.sort_unstable();
+        assert_eq!(strata, (0..n).collect::<Vec<_>>(), "dimension {}", dim);
+    }
+}
+
+#[test]
+fn padded_dimensions() {
+    let mut sampler = HaltonSampler::for_task(Some(1), 0);
+    sampler.start_pixel_sample(Point2::new(0, 0), 0);
+    let bsdf = dimensions::bsdf(0);
+    let first = sampler.next_dim(bsdf);
+    // A dimension is only used once per sample
+    assert_ne!(first, sampler.next_dim(bsdf));
+    sampler.start_pixel_sample(Point2::new(0, 0), 0);
+    assert_eq!(first, sampler.next_dim(bsdf));
+    // Beyond the sequence dimensions
+    let v = sampler.next_dim(dimensions::bsdf(100));
+    assert!((0.0..1.0).contains(&v));
+}
+
+#[test]
+fn halton_furnace() {
+    let mut analytic = furnace(0.5, 1.0);
+    analytic.scene = analytic.scene.sampler(SamplerType::Halton).seed(7);
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: None,
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }));
+    if let Err(e) = analytic.validate(&mut integrator, 0.02) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn parse_sampler() {
+    assert_eq!("halton".parse(), Ok(SamplerType::Halton));
+    assert!("sobol".parse::<SamplerType>().is_err());
+}
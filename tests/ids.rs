@@ -0,0 +1,98 @@
+//! ID buffers coverage: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Vector3};
+use rustlight::camera::Camera;
+use rustlight::geometry::Mesh;
+use rustlight::integrators::ao::IntegratorAO;
+use rustlight::integrators::ids::{IdBuffers, PixelFilter};
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::validation::*;
+
+/// Patch covering [10.25, 20.5] x [8.75, 24] in the image (partially covered pixels)
+const PATCH: [[f32; 2]; 2] = [[10.25, 20.5], [8.75, 24.0]];
+
+/// Point of the plane at the height z seen through the image position
+fn on_plane(camera: &Camera, px: Point2<f32>, z: f32) -> Vector3<f32> {
+    let ray = camera.generate(px).ray;
+    let p = ray.o + ray.d * ((z - ray.o.z) / ray.d.z);
+    Vector3::new(p.x, p.y, p.z)
+}
+
+fn render_ids(filter: PixelFilter) -> IdBuffers {
+    let mut scene = parallel_planes(0.5, 1.0).scene;
+    let z = 0.01;
+    let corners = [(0, 0), (1, 0), (1, 1), (0, 1)];
+    let vertices = corners
+        .iter()
+        .map(|&(i, j)| on_plane(&scene.camera, Point2::new(PATCH[0][i], PATCH[1][j]), z))
+        .collect();
+    let indices = vec![Vector3::new(0, 1, 2), Vector3::new(0, 2, 3)];
+    let normals = vec![Vector3::new(0.0, 0.0, 1.0); 4];
+    let mut patch = Mesh::new("patch".to_string(), vertices, indices, Some(normals), None);
+    patch.material_name = "red".to_string();
+    scene.meshes.push(patch);
+    scene.nb_samples = 1024;
+    scene.output_ids = Some(filter);
+
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorAO {
+        max_distance: None,
+        normal_correction: false,
+    }));
+    integrator.compute(&scene, &NoObserver).ids.unwrap()
+}
+
+/// Fraction of the filter weight inside [a, b] for the pixel starting at x
+fn filter_coverage(filter: PixelFilter, x: f32, [a, b]: [f32; 2]) -> f32 {
+    let (n, r, c) = (1000, filter.radius(), x + 0.5);
+    let (mut inside, mut total) = (0.0, 0.0);
+    for i in 0..n {
+        let t = c - r + (i as f32 + 0.5) / n as f32 * 2.0 * r;
+        let w = filter.eval(cgmath::Vector2::new(t - c, 0.0));
+        total += w;
+        if t >= a && t < b {
+            inside += w;
+        }
+    }
+    inside / total
+}
+
+fn check_coverages(filter: PixelFilter) {
+    let ids = render_ids(filter);
+    let size = ids.objects.size;
+    let matte = ids.objects.matte(&["patch"]);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let p = Point2::new(x, y);
+            let expected = filter_coverage(filter, x as f32, PATCH[0])
+                * filter_coverage(filter, y as f32, PATCH[1]);
+            let coverage = ids.objects.coverage(p, "patch");
+            assert!(
+                (coverage - expected).abs() < 0.08,
+                "{:?} {:?}: coverage {} instead of {}",
+                filter,
+                p,
+                coverage,
+                expected
+            );
+            // All the camera rays hit the scene
+            let total = ids.objects.pixel(p).iter().map(|(_, c)| c).sum::<f32>();
+            assert!((total - 1.0).abs() < 1e-4, "{:?}: {}", p, total);
+            assert!((coverage + ids.objects.coverage(p, "ground") - 1.0).abs() < 1e-4);
+            assert_eq!(matte.pixel(p).r, coverage);
+            assert_eq!(ids.materials.coverage(p, "red"), coverage);
+        }
+    }
+}
+
+#[test]
+fn box_coverage() {
+    check_coverages(PixelFilter::Box);
+}
+
+#[test]
+fn gaussian_coverage() {
+    check_coverages(PixelFilter::Gaussian);
+}
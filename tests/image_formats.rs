@@ -0,0 +1,86 @@
+#![cfg(all(feature = "image", feature = "png"))]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Vector2;
+use rustlight::color::ColorSpace;
+use rustlight::structure::{Bitmap, Color};
+
+fn temp(name: &str) -> String {
+    std::env::temp_dir()
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn image(size: Vector2<u32>, color: impl Fn(u32, u32) -> Color) -> Bitmap {
+    let mut img = Bitmap::new(size);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            img.colors[(y * size.x + x) as usize] = color(x, y);
+        }
+    }
+    img
+}
+
+#[test]
+fn png16_roundtrip() {
+    // Dark values lost on 8 bits, and values clamped to [0, 1]
+    let img = image(Vector2::new(3, 2), |x, y| {
+        Color::new(x as f32 * 1e-3, y as f32 * 0.5, 2.0)
+    });
+    let filename = temp("rustlight_roundtrip16.png");
+    img.save_with(&filename, ColorSpace::Linear, true).unwrap();
+    let read = Bitmap::read(&filename).unwrap();
+    assert_eq!(read.size, img.size);
+    for (a, b) in read.colors.iter().zip(&img.colors) {
+        assert!((a.r - b.r).abs() <= 1.0 / 65535.0, "{:?} != {:?}", a, b);
+        assert!((a.g - b.g).abs() <= 1.0 / 65535.0, "{:?} != {:?}", a, b);
+        assert_eq!(a.b, 1.0);
+    }
+
+    // The sRGB outputs are decoded like the 8 bits images
+    img.save_with(&filename, ColorSpace::SRGB, true).unwrap();
+    let read = Bitmap::read_linear(&filename, None, None).unwrap();
+    for (a, b) in read.colors.iter().zip(&img.colors) {
+        assert!((a.r - b.r).abs() < 2e-5, "{:?} != {:?}", a, b);
+        assert!((a.g - b.g).abs() < 2e-5, "{:?} != {:?}", a, b);
+        assert_eq!(a.b, 1.0);
+    }
+}
+
+#[test]
+fn png8_default() {
+    // 8 bits sRGB: the dark values are quantized
+    let img = image(Vector2::new(3, 2), |x, y| {
+        Color::new(x as f32 * 1e-3, y as f32 * 0.5, 2.0)
+    });
+    let filename = temp("rustlight_roundtrip8.png");
+    img.save(&filename).unwrap();
+    let read = Bitmap::read_linear(&filename, None, None).unwrap();
+    assert_eq!(read.size, img.size);
+    for (a, b) in read.colors.iter().zip(&img.colors) {
+        assert!((a.r - b.r).abs() < 1e-2, "{:?} != {:?}", a, b);
+        assert!((a.g - b.g).abs() < 1e-2, "{:?} != {:?}", a, b);
+        assert_eq!(a.b, 1.0);
+    }
+}
+
+#[test]
+fn hdr_negative_values() {
+    let img = image(Vector2::new(2, 1), |x, _| {
+        if x == 0 {
+            Color::new(-1.0, 0.5, 4.0)
+        } else {
+            Color::value(0.25)
+        }
+    });
+    let filename = temp("rustlight_negative.hdr");
+    img.save(&filename).unwrap();
+    let read = Bitmap::read(&filename).unwrap();
+    assert_eq!(read.colors[0].r, 0.0);
+    assert!((read.colors[0].g - 0.5).abs() < 1e-2);
+    assert!((read.colors[0].b - 4.0).abs() < 1e-1);
+    assert!((read.colors[1].r - 0.25).abs() < 1e-2);
+}
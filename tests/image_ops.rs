@@ -0,0 +1,68 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Vector2};
+use rustlight::image_ops::ResizeFilter;
+use rustlight::structure::{Bitmap, Color};
+
+/// Pixel values encoding their position
+fn ramp(size: Vector2<u32>) -> Bitmap {
+    Bitmap::from_fn(size, |p| Color::new(p.x as f32, p.y as f32, 1.0))
+}
+
+#[test]
+fn crop_flip() {
+    let mut img = ramp(Vector2::new(5, 4));
+    img.alpha = Some((0..20).map(|i| i as f32).collect());
+    let crop = img.crop(Point2::new(3, 1), Vector2::new(4, 2));
+    // Clipped by the image border
+    assert_eq!(crop.size, Vector2::new(2, 2));
+    assert_eq!(crop.pixel(Point2::new(1, 1)), Color::new(4.0, 2.0, 1.0));
+    assert_eq!(crop.alpha(Point2::new(0, 1)), 13.0);
+    let flip = img.flip_horizontal();
+    assert_eq!(flip.pixel(Point2::new(0, 2)), Color::new(4.0, 2.0, 1.0));
+    assert_eq!(flip.alpha(Point2::new(0, 0)), 4.0);
+    let flip = img.flip_vertical();
+    assert_eq!(flip.pixel(Point2::new(1, 0)), Color::new(1.0, 3.0, 1.0));
+    assert_eq!(img.flip_vertical().flip_vertical().colors, img.colors);
+}
+
+#[test]
+fn resize() {
+    let img = ramp(Vector2::new(8, 6));
+    // Box downsampling: average of the blocks of 2x2 pixels
+    let small = img.resize(Vector2::new(4, 3), ResizeFilter::Box);
+    assert_eq!(small.pixel(Point2::new(1, 2)), Color::new(2.5, 4.5, 1.0));
+    // Box upsampling: nearest pixel
+    let large = small.resize(Vector2::new(8, 6), ResizeFilter::Box);
+    assert_eq!(
+        large.pixel(Point2::new(3, 5)),
+        small.pixel(Point2::new(1, 2))
+    );
+    // Lanczos keeps a constant image and the average of a linear ramp
+    let constant = Bitmap::from_fn(Vector2::new(7, 5), |_| Color::value(2.0));
+    for size in [Vector2::new(3, 2), Vector2::new(16, 9)] {
+        let resized = constant.resize(size, ResizeFilter::Lanczos);
+        assert_eq!(resized.size, size);
+        for c in &resized.colors {
+            assert!((c.r - 2.0).abs() < 1e-5, "{:?}", c);
+        }
+    }
+    let avg = img
+        .resize(Vector2::new(4, 3), ResizeFilter::Lanczos)
+        .average();
+    assert!((avg.r - img.average().r).abs() < 1e-4);
+    assert_eq!("lanczos".parse(), Ok(ResizeFilter::Lanczos));
+    assert!("bicubic".parse::<ResizeFilter>().is_err());
+}
+
+#[test]
+fn channels_exposure() {
+    let mut img = ramp(Vector2::new(3, 2));
+    assert_eq!(img.channel(0), vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0]);
+    assert_eq!(img.channel(1), vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+    assert_eq!(img.luminance()[4], Color::new(1.0, 1.0, 1.0).luminance());
+    let doubled = img.map(|c| c * 2.0);
+    img.exposure(1.0);
+    assert_eq!(img.colors, doubled.colors);
+}
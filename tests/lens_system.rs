@@ -0,0 +1,147 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{InnerSpace, Matrix4, Point2, SquareMatrix, Vector2};
+use rustlight::camera::Camera;
+use rustlight::lens_system::LensSystem;
+use rustlight::samplers::independent::IndependentSampler;
+use rustlight::samplers::Sampler;
+use std::sync::Arc;
+
+/// Biconvex singlet (R = 50 mm, 5 mm of glass n = 1.5) followed by the aperture stop
+const SINGLET: [[f32; 4]; 3] = [
+    [50.0, 5.0, 1.5, 20.0],
+    [-50.0, 2.0, 0.0, 20.0],
+    [0.0, 40.0, 0.0, 20.0],
+];
+
+fn singlet(aperture_diameter: f32, focus_distance: f32) -> LensSystem {
+    LensSystem::new(&SINGLET, aperture_diameter, focus_distance, 35.0).unwrap()
+}
+
+#[test]
+fn thick_lens_focal_length() {
+    // Lensmaker's equation of the thick lens
+    let (n, r, d) = (1.5, 0.05, 0.005);
+    let f = 1.0 / ((n - 1.0) * (2.0 / r - (n - 1.0) * d / (n * r * r)));
+    let focal_length = singlet(10.0, 2.0).focal_length().unwrap();
+    assert!(
+        (focal_length - f).abs() < 0.005 * f,
+        "focal length {} instead of {}",
+        focal_length,
+        f
+    );
+}
+
+#[test]
+fn rays_converge_at_focus() {
+    let focus_distance = 2.0;
+    // Small aperture: the spherical aberration moves the focus of the marginal rays
+    let lens = singlet(1.0, focus_distance);
+    // Spread of the rays leaving the center of the film at the given distance
+    let spread = |z: f32| {
+        let mut r_max = 0.0f32;
+        for i in 0..8 {
+            for j in 0..8 {
+                let u = Point2::new((i as f32 + 0.5) / 8.0, (j as f32 + 0.5) / 8.0);
+                if let Some((o, d, weight)) = lens.sample(Point2::new(0.0, 0.0), u) {
+                    assert!(weight > 0.9 && weight <= 1.0, "weight {}", weight);
+                    let p = o + d * ((z - o.z) / d.z);
+                    r_max = r_max.max(Vector2::new(p.x, p.y).magnitude());
+                }
+            }
+        }
+        r_max
+    };
+    let blur = spread(1.0);
+    assert!(blur > 1e-4);
+    assert!(
+        spread(focus_distance) < 0.1 * blur,
+        "circle of confusion {} at focus, {} at 1 m",
+        spread(focus_distance),
+        blur
+    );
+}
+
+#[test]
+fn optical_vignetting() {
+    let lens = singlet(10.0, 2.0);
+    let mut vignetted = singlet(10.0, 2.0);
+    vignetted.set_optical_vignetting(20.0, 20.0).unwrap();
+    // Rays of the film point passing through the lens
+    let count = |lens: &LensSystem, p_film: Point2<f32>| {
+        let mut count = 0;
+        for i in 0..16 {
+            for j in 0..16 {
+                let u = Point2::new((i as f32 + 0.5) / 16.0, (j as f32 + 0.5) / 16.0);
+                if lens.sample(p_film, u).is_some() {
+                    count += 1;
+                }
+            }
+        }
+        count
+    };
+    // The opening only blocks the oblique rays
+    let center = Point2::new(0.0, 0.0);
+    assert!(count(&lens, center) > 0);
+    assert_eq!(count(&vignetted, center), count(&lens, center));
+    let border = Point2::new(0.012, 0.0);
+    assert!(count(&vignetted, border) > 0);
+    assert!(
+        count(&vignetted, border) < count(&lens, border),
+        "{} rays with the optical vignetting, {} without",
+        count(&vignetted, border),
+        count(&lens, border)
+    );
+
+    assert!(vignetted.set_optical_vignetting(-1.0, 20.0).is_err());
+    assert!(vignetted.set_optical_vignetting(20.0, 0.0).is_err());
+}
+
+#[test]
+fn lens_file() {
+    let filename = std::env::temp_dir().join("rustlight_singlet.dat");
+    let filename = filename.to_str().unwrap();
+    let mut content = "# Biconvex singlet\n# radius thickness eta aperture\n".to_string();
+    for interface in &SINGLET {
+        let values = interface.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        content += &format!("{}\t# interface\n", values.join(" "));
+    }
+    std::fs::write(filename, &content).unwrap();
+    let lens = LensSystem::load(filename, 10.0, 2.0, 35.0).unwrap();
+    assert_eq!(
+        lens.focal_length().unwrap(),
+        singlet(10.0, 2.0).focal_length().unwrap()
+    );
+    assert!((lens.film_diagonal - 0.035).abs() < 1e-6);
+
+    // Incomplete interface
+    std::fs::write(filename, content + "10.0 2.0\n").unwrap();
+    let result = LensSystem::load(filename, 10.0, 2.0, 35.0);
+    std::fs::remove_file(filename).unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+fn lens_camera() {
+    let pinhole = Camera::new(Vector2::new(64, 48), 60.0, Matrix4::identity());
+    let mut camera = pinhole.clone();
+    camera.set_lens_system(Arc::new(singlet(10.0, 2.0)));
+    let center = Point2::new(32.0, 24.0);
+    let forward = pinhole.generate(center).ray.d;
+
+    // The pinhole cameras do not consume the lens dimensions
+    let (mut a, mut b) = (
+        IndependentSampler::from_seed(1),
+        IndependentSampler::from_seed(1),
+    );
+    pinhole.sample_lens(center, &mut a);
+    assert_eq!(a.next(), b.next());
+    let sample = camera.sample_lens(center, &mut a);
+    assert_ne!(a.next(), b.next());
+
+    assert!(sample.weight > 0.0);
+    assert!(sample.ray.d.dot(forward) > 0.99);
+    assert_eq!(sample.we_pdf, 0.0);
+    assert!(camera.sample_direct(&(sample.ray.o + forward)).is_none());
+}
@@ -0,0 +1,157 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point3, Vector3};
+use rustlight::emitter::Emitter;
+use rustlight::geometry::Mesh;
+use rustlight::light_bvh::LightBVH;
+use rustlight::structure::Color;
+
+/// Small emitting quads on a grid, facing +z or -z
+fn emitters() -> Vec<Mesh> {
+    let mut meshes = vec![];
+    for i in 0..13 {
+        let (x, y, z) = ((i % 4) as f32 * 2.0, (i / 4) as f32 * 3.0, (i % 3) as f32);
+        let s = 0.1 + 0.05 * i as f32;
+        let vertices = vec![
+            Vector3::new(x - s, y - s, z),
+            Vector3::new(x + s, y - s, z),
+            Vector3::new(x + s, y + s, z),
+            Vector3::new(x - s, y + s, z),
+        ];
+        let up = i % 2 == 0;
+        let indices = if up {
+            vec![Vector3::new(0, 1, 2), Vector3::new(0, 2, 3)]
+        } else {
+            vec![Vector3::new(0, 2, 1), Vector3::new(0, 3, 2)]
+        };
+        let n = Vector3::new(0.0, 0.0, if up { 1.0 } else { -1.0 });
+        let mut mesh = Mesh::new(
+            format!("light{}", i),
+            vertices,
+            indices,
+            Some(vec![n; 4]),
+            None,
+        );
+        mesh.emission = Color::value(1.0 + i as f32);
+        meshes.push(mesh);
+    }
+    meshes
+}
+
+fn points() -> Vec<Point3<f32>> {
+    (0..50)
+        .map(|i| {
+            let t = i as f32;
+            Point3::new(
+                (t * 0.37).sin() * 6.0,
+                (t * 0.71).cos() * 8.0,
+                (t * 0.13).sin() * 4.0,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn selection_probabilities() {
+    let meshes = emitters();
+    let bounds = meshes
+        .iter()
+        .map(|m| m.bounds().unwrap())
+        .collect::<Vec<_>>();
+    let bvh = LightBVH::new(&bounds);
+    let mut nb_tested = 0;
+    for p in points() {
+        let total = (0..meshes.len()).map(|id| bvh.pmf(p, id)).sum::<f32>();
+        if total == 0.0 {
+            assert!(bvh.sample(p, 0.5).is_none());
+            continue;
+        }
+        nb_tested += 1;
+        // Some probability is lost when the children of a node cannot contribute
+        // (looser bounds for the parent)
+        assert!(total <= 1.0 + 1e-4, "sum of the pmfs: {}", total);
+        for i in 0..64 {
+            if let Some((id, pmf)) = bvh.sample(p, (i as f32 + 0.5) / 64.0) {
+                assert!((pmf - bvh.pmf(p, id)).abs() <= 1e-5 * pmf);
+            }
+        }
+    }
+    assert!(nb_tested > 0);
+}
+
+#[test]
+fn back_facing_emitters_are_skipped() {
+    let meshes = emitters();
+    let bounds = meshes
+        .iter()
+        .map(|m| m.bounds().unwrap())
+        .collect::<Vec<_>>();
+    let bvh = LightBVH::new(&bounds);
+    // Far below all the emitters: only the ones facing -z can contribute
+    let p = Point3::new(3.0, 4.0, -100.0);
+    for (id, m) in meshes.iter().enumerate() {
+        let facing_down = m.normals.as_ref().unwrap()[0].z < 0.0;
+        assert_eq!(bvh.pmf(p, id) > 0.0, facing_down, "{}", m.name);
+    }
+}
+
+#[test]
+fn lightcut_probabilities() {
+    let meshes = emitters();
+    let bounds = meshes
+        .iter()
+        .map(|m| m.bounds().unwrap())
+        .collect::<Vec<_>>();
+    let bvh = LightBVH::new(&bounds);
+    for p in points() {
+        for &max_size in &[1, 3, 8, 100] {
+            let cut = bvh.cut(p, max_size);
+            assert!(cut.len() <= max_size);
+            // Every emitter that can contribute is below exactly one node of the cut
+            for id in 0..meshes.len() {
+                let pmf = bvh.cut_pmf(&cut, p, id);
+                assert!(pmf <= 1.0 + 1e-5);
+                if max_size == 1 {
+                    assert!((pmf - bvh.pmf(p, id)).abs() <= 1e-5);
+                }
+                if bvh.pmf(p, id) > 0.0 {
+                    assert!(pmf > 0.0, "emitter {} missing from the cut", id);
+                }
+            }
+            for &node in &cut {
+                for i in 0..16 {
+                    if let Some((id, pmf)) = bvh.sample_from(node, p, (i as f32 + 0.5) / 16.0) {
+                        assert!((pmf - bvh.cut_pmf(&cut, p, id)).abs() <= 1e-5 * pmf);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn refit_matches_rebuild() {
+    let mut meshes = emitters();
+    let bounds = meshes
+        .iter()
+        .map(|m| m.bounds().unwrap())
+        .collect::<Vec<_>>();
+    let mut bvh = LightBVH::new(&bounds);
+    // Same positions: the tree of the rebuild has the same topology
+    for &id in &[0, 5, 12] {
+        meshes[id].emission = Color::value(0.1 * id as f32);
+        bvh.refit(id, meshes[id].bounds().unwrap());
+    }
+    let bounds = meshes
+        .iter()
+        .map(|m| m.bounds().unwrap())
+        .collect::<Vec<_>>();
+    let rebuilt = LightBVH::new(&bounds);
+    for p in points() {
+        for id in 0..meshes.len() {
+            let (pmf, expected) = (bvh.pmf(p, id), rebuilt.pmf(p, id));
+            assert!((pmf - expected).abs() <= 1e-5, "{} != {}", pmf, expected);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point3, Vector3};
+use rustlight::emitter::Emitter;
+use rustlight::geometry::Mesh;
+use rustlight::light_grid::LightGrid;
+use rustlight::scene::Acceleration;
+use rustlight::structure::{Color, Intersection, Ray, AABB};
+
+/// Two rooms separated by a wall at x = 0 (if closed)
+struct Rooms {
+    closed: bool,
+}
+impl Acceleration for Rooms {
+    fn trace(&self, _ray: &Ray) -> Option<Intersection<'_>> {
+        None
+    }
+    fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool {
+        !self.closed || p0.x.signum() == p1.x.signum()
+    }
+}
+
+/// Emitting quad on the floor (z = 0) facing +z
+fn light(x: f32, emission: f32) -> Mesh {
+    let s = 0.2;
+    let vertices = vec![
+        Vector3::new(x - s, 0.5 - s, 0.0),
+        Vector3::new(x + s, 0.5 - s, 0.0),
+        Vector3::new(x + s, 0.5 + s, 0.0),
+        Vector3::new(x - s, 0.5 + s, 0.0),
+    ];
+    let indices = vec![Vector3::new(0, 1, 2), Vector3::new(0, 2, 3)];
+    let n = Vector3::new(0.0, 0.0, 1.0);
+    let mut mesh = Mesh::new(
+        "light".to_string(),
+        vertices,
+        indices,
+        Some(vec![n; 4]),
+        None,
+    );
+    mesh.emission = Color::value(emission);
+    mesh
+}
+
+fn grid(closed: bool) -> LightGrid {
+    let meshes = vec![light(-1.0, 1.0), light(1.0, 3.0)];
+    let emitters = meshes.iter().map(|m| m as &dyn Emitter).collect::<Vec<_>>();
+    let aabb = AABB {
+        p_min: Vector3::new(-2.0, 0.0, 0.0),
+        p_max: Vector3::new(2.0, 1.0, 2.0),
+    };
+    // Cells of size 1: the wall is on their boundaries
+    LightGrid::new(&Rooms { closed }, &emitters, &aabb, 4, 16)
+}
+
+#[test]
+fn occluded_emitters_culled() {
+    let grid = grid(true);
+    for &(x, id) in &[(-1.5, 0), (-0.5, 0), (0.5, 1), (1.5, 1)] {
+        let p = Point3::new(x, 0.5, 0.5);
+        assert_eq!(grid.visible_emitters(p), &[id]);
+        assert_eq!(grid.pmf(p, id), 1.0);
+        assert_eq!(grid.pmf(p, 1 - id), 0.0);
+        for &v in &[0.0, 0.5, 0.99] {
+            assert_eq!(grid.sample(p, v), Some((id, 1.0)));
+        }
+    }
+}
+
+#[test]
+fn selection_by_power() {
+    let grid = grid(false);
+    // Above the lights: selected by power
+    let p = Point3::new(0.0, 0.5, 1.5);
+    assert_eq!(grid.visible_emitters(p), &[0, 1]);
+    assert!((grid.pmf(p, 0) - 0.25).abs() < 1e-5);
+    assert!((grid.pmf(p, 1) - 0.75).abs() < 1e-5);
+    let (id, pdf) = grid.sample(p, 0.9).unwrap();
+    assert_eq!(id, 1);
+    assert!((pdf - grid.pmf(p, id)).abs() < 1e-6);
+    // Outside of the grid: closest cell
+    assert_eq!(grid.visible_emitters(Point3::new(5.0, 5.0, 5.0)), &[0, 1]);
+}
@@ -0,0 +1,68 @@
+extern crate rustlight;
+
+use rustlight::samplers::mcmc::{IndependentSamplerReplay, Mutator, MutatorKelemen};
+use rustlight::samplers::{Sampler, SamplerMCMC};
+
+/// Distance on the primary space (wraps around)
+fn wrapped_distance(a: f32, b: f32) -> f32 {
+    let d = (a - b).abs();
+    d.min(1.0 - d)
+}
+
+#[test]
+fn scaled_mutations() {
+    let mutator = MutatorKelemen::default();
+    for &r in &[0.1, 0.3, 0.6, 0.9] {
+        assert_eq!(mutator.mutate(0.5, r), mutator.mutate_scaled(0.5, r, 1.0));
+        let small = wrapped_distance(mutator.mutate_scaled(0.5, r, 0.5), 0.5);
+        let large = wrapped_distance(mutator.mutate_scaled(0.5, r, 4.0), 0.5);
+        assert!((large - 8.0 * small).abs() < 1e-5);
+    }
+    // The steps are bounded by half of the primary space
+    let v = mutator.mutate_scaled(0.3, 0.0, 1000.0);
+    assert!((0.0..1.0).contains(&v));
+}
+
+#[test]
+fn adaptive_steps() {
+    // Narrow peak along the first dimension, uniform along the second one
+    let target = |u: f32| (-(wrapped_distance(u, 0.5) / 0.01).powi(2) / 2.0).exp();
+    let mut sampler = IndependentSamplerReplay::default().adaptive(0.234);
+    let mut current = 0.0;
+    while current == 0.0 {
+        sampler.large_step = true;
+        current = target(sampler.next2d().x);
+        sampler.reject();
+    }
+    sampler.next2d();
+    sampler.accept();
+
+    let (nb_steps, mut nb_accepted) = (40_000, 0);
+    for step in 0..nb_steps {
+        sampler.large_step = sampler.rand() < 0.1;
+        let proposed = target(sampler.next2d().x);
+        let accept_prob = (proposed / current).min(1.0);
+        if accept_prob > sampler.rand() {
+            sampler.accept();
+            current = proposed;
+            if step >= nb_steps / 2 && !sampler.large_step {
+                nb_accepted += 1;
+            }
+        } else {
+            sampler.reject();
+        }
+        sampler.adapt(accept_prob);
+    }
+
+    // The small steps (90% of the steps) reach the target acceptance rate
+    let rate = nb_accepted as f32 / (0.9 * (nb_steps / 2) as f32);
+    assert!((rate - 0.234).abs() < 0.06, "acceptance rate: {}", rate);
+    // Larger steps along the dimension that does not change the contribution
+    let adaptation = sampler.adaptation().unwrap();
+    assert!(
+        adaptation.scale(1) > 4.0 * adaptation.scale(0),
+        "{} {}",
+        adaptation.scale(0),
+        adaptation.scale(1)
+    );
+}
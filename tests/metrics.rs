@@ -0,0 +1,63 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Vector2;
+use rustlight::metrics::{ErrorMap, Metric};
+use rustlight::structure::{Bitmap, Color};
+
+fn gradient(size: Vector2<u32>) -> Bitmap {
+    Bitmap::from_fn(size, |p| {
+        Color::new(p.x as f32 * 0.1, p.y as f32 * 0.2, 0.5)
+    })
+}
+
+#[test]
+fn identical_images() {
+    let img = gradient(Vector2::new(8, 6));
+    for &metric in Metric::all().iter() {
+        let map = ErrorMap::new(metric, &img, &img).unwrap();
+        assert_eq!(map.size, img.size);
+        assert_eq!(map.mean(), 0.0, "{}", metric.name());
+    }
+}
+
+#[test]
+fn known_values() {
+    let size = Vector2::new(4, 4);
+    let reference = Bitmap::from_fn(size, |_| Color::value(0.5));
+    let test = Bitmap::from_fn(size, |_| Color::value(1.0));
+    let mean = |metric| ErrorMap::new(metric, &reference, &test).unwrap().mean();
+    assert!((mean(Metric::MSE) - 0.25).abs() < 1e-6);
+    // Relative to the squared reference (+ 0.01)
+    assert!((mean(Metric::RelMSE) - 0.25 / 0.26).abs() < 1e-6);
+    assert!((mean(Metric::MAPE) - 0.5 / 0.51).abs() < 1e-6);
+
+    // Error on a quarter of the pixels
+    let test = Bitmap::from_fn(size, |p| {
+        Color::value(if p.x < 2 && p.y < 2 { 1.5 } else { 0.5 })
+    });
+    let map = ErrorMap::new(Metric::MSE, &reference, &test).unwrap();
+    assert!((map.mean() - 0.25).abs() < 1e-6);
+    assert_eq!(map.percentile(0.0), 0.0);
+    assert_eq!(map.percentile(1.0), 1.0);
+}
+
+#[test]
+fn empty_map() {
+    let map = ErrorMap {
+        size: Vector2::new(0, 0),
+        values: vec![],
+    };
+    assert_eq!(map.mean(), 0.0);
+    assert_eq!(map.percentile(0.0), 0.0);
+    assert_eq!(map.percentile(0.99), 0.0);
+}
+
+#[test]
+fn size_mismatch() {
+    let reference = gradient(Vector2::new(8, 6));
+    let test = gradient(Vector2::new(6, 8));
+    for &metric in Metric::all().iter() {
+        assert!(ErrorMap::new(metric, &reference, &test).is_err());
+    }
+}
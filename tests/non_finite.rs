@@ -0,0 +1,127 @@
+//! Detection of the NaN and infinite contributions: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+
+use rustlight::emitter::EmitterSampler;
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::*;
+use rustlight::samplers::Sampler;
+use rustlight::scene::{Acceleration, NonFiniteCheck, NonFiniteMode, Scene};
+use rustlight::structure::{non_finite_scales, Bitmap, Color};
+use rustlight::validation::*;
+
+/// Constant image with NaN samples in the first column
+struct IntegratorNaN;
+impl Integrator for IntegratorNaN {
+    fn compute(
+        &mut self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> BufferCollection {
+        compute_mc(self, accel, scene, observer)
+    }
+}
+impl IntegratorMC for IntegratorNaN {
+    fn compute_pixel(
+        &self,
+        (ix, _): (u32, u32),
+        _: &dyn Acceleration,
+        _: &Scene,
+        _: &mut dyn Sampler,
+        _: &EmitterSampler,
+    ) -> Color {
+        if ix == 0 {
+            Color::new(f32::NAN, 1.0, f32::INFINITY)
+        } else {
+            Color::one()
+        }
+    }
+}
+
+fn render(mut int: IntegratorType, mode: Option<NonFiniteMode>) -> (Bitmap, usize) {
+    let scene = environment_ground(0.5, false).scene.non_finite(mode);
+    let img = int.compute(&scene, &NoObserver);
+    (
+        img.values["primal"].to_f32().into_owned(),
+        scene.non_finite.count(),
+    )
+}
+
+fn all_finite(img: &Bitmap) -> bool {
+    img.colors.iter().all(|c| c.is_finite())
+}
+
+#[test]
+fn check_modes() {
+    let check = NonFiniteCheck::default();
+    let nan = Color::value(f32::NAN);
+    // Disabled by default
+    assert!(!check.check(nan, || unreachable!()).is_finite());
+    assert_eq!(check.count(), 0);
+
+    let mut check = NonFiniteCheck::default();
+    check.mode = Some(NonFiniteMode::Log);
+    assert_eq!(check.check(Color::one(), || unreachable!()), Color::one());
+    assert!(!check.check(nan, || "log".to_string()).is_finite());
+    assert_eq!(check.count(), 1);
+
+    let mut check = NonFiniteCheck::default();
+    check.mode = Some(NonFiniteMode::Discard);
+    let inf = Color::new(0.0, f32::INFINITY, 0.0);
+    assert!(check.check(inf, || "discard".to_string()).is_zero());
+    assert_eq!(check.count(), 1);
+
+    assert_eq!(
+        "discard".parse::<NonFiniteMode>(),
+        Ok(NonFiniteMode::Discard)
+    );
+    assert!("abort".parse::<NonFiniteMode>().is_err());
+}
+
+#[test]
+fn scaled_by_non_finite() {
+    let before = non_finite_scales();
+    assert!((Color::one() * f32::NAN).is_zero());
+    assert!(non_finite_scales() > before);
+}
+
+#[test]
+fn quarantine() {
+    let scene = environment_ground(0.5, false).scene;
+    let nb_nan = scene.camera.size().y as usize * scene.nb_samples;
+    let (img, count) = render(IntegratorType::Primal(Box::new(IntegratorNaN)), None);
+    assert!(!all_finite(&img));
+    assert_eq!(count, 0);
+
+    let (img, count) = render(
+        IntegratorType::Primal(Box::new(IntegratorNaN)),
+        Some(NonFiniteMode::Log),
+    );
+    assert!(!all_finite(&img));
+    assert_eq!(count, nb_nan);
+
+    let (img, count) = render(
+        IntegratorType::Primal(Box::new(IntegratorNaN)),
+        Some(NonFiniteMode::Discard),
+    );
+    assert!(all_finite(&img));
+    assert_eq!(count, nb_nan);
+}
+
+#[test]
+fn path_tracing_is_finite() {
+    let path = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: Some(4),
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }));
+    let (img, count) = render(path, Some(NonFiniteMode::Log));
+    assert!(all_finite(&img));
+    assert_eq!(count, 0);
+}
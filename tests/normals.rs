@@ -0,0 +1,105 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use rustlight::geometry::{load_obj, smooth_normals};
+
+/// Unit cube (8 positions, 2 triangles per face, outward winding)
+fn cube() -> (Vec<Vector3<f32>>, Vec<Vector3<usize>>) {
+    let vertices = (0..8)
+        .map(|i| Vector3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32))
+        .collect();
+    let quads = [
+        [0, 2, 3, 1],
+        [4, 5, 7, 6],
+        [0, 1, 5, 4],
+        [2, 6, 7, 3],
+        [0, 4, 6, 2],
+        [1, 3, 7, 5],
+    ];
+    let indices = quads
+        .iter()
+        .flat_map(|q| {
+            vec![
+                Vector3::new(q[0], q[1], q[2]),
+                Vector3::new(q[0], q[2], q[3]),
+            ]
+        })
+        .collect();
+    (vertices, indices)
+}
+
+#[test]
+fn cube_creases() {
+    // Sharp edges: one vertex per corner of each face with the face normal
+    let (mut vertices, mut indices) = cube();
+    let normals = smooth_normals(&mut vertices, &mut indices, &mut None, 60.0);
+    assert_eq!(vertices.len(), 24);
+    assert_eq!(normals.len(), 24);
+    for id in &indices {
+        let (v0, v1, v2) = (vertices[id.x], vertices[id.y], vertices[id.z]);
+        let face = (v1 - v0).cross(v2 - v0).normalize();
+        for k in 0..3 {
+            assert!((normals[id[k]] - face).magnitude() < 1e-5);
+        }
+    }
+
+    // Above the crease angle: the corners are shared and point outward
+    let (mut vertices, mut indices) = cube();
+    let normals = smooth_normals(&mut vertices, &mut indices, &mut None, 100.0);
+    assert_eq!(vertices.len(), 8);
+    let center = Vector3::new(0.5, 0.5, 0.5);
+    for (v, n) in vertices.iter().zip(&normals) {
+        assert!((n.magnitude() - 1.0).abs() < 1e-5);
+        assert!(n.dot((v - center).normalize()) > 0.9);
+    }
+}
+
+/// Two triangles folded by 20 degrees, split by a texture seam along the fold
+fn fold() -> (Vec<Vector3<f32>>, Vec<Vector3<usize>>) {
+    let angle = 20f32.to_radians();
+    let vertices = vec![
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(-angle.cos(), 0.0, angle.sin()),
+    ];
+    (vertices, vec![Vector3::new(0, 2, 1), Vector3::new(3, 4, 5)])
+}
+
+#[test]
+fn flat_normals_and_seams() {
+    // The fold vertices share the average normal on both sides of the seam
+    let (mut vertices, mut indices) = fold();
+    let mut uv = Some((0..6).map(|i| Vector2::new(i as f32, 0.0)).collect());
+    let normals = smooth_normals(&mut vertices, &mut indices, &mut uv, 30.0);
+    assert_eq!(vertices.len(), 6);
+    let n = normals[indices[0].x];
+    assert!((n - normals[indices[1].x]).magnitude() < 1e-5);
+    assert!(n.dot(Vector3::new(0.0, 0.0, 1.0)) > 0.98);
+    assert_eq!(uv.unwrap()[indices[1].z], Vector2::new(5.0, 0.0));
+
+    // Zero crease angle: faceted
+    let (mut vertices, mut indices) = fold();
+    let normals = smooth_normals(&mut vertices, &mut indices, &mut None, 0.0);
+    assert!((normals[indices[0].x] - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+    assert!((normals[indices[1].x] - normals[indices[0].x]).magnitude() > 0.1);
+}
+
+#[test]
+fn obj_without_normals() {
+    let file = std::env::temp_dir().join("rustlight_normals.obj");
+    std::fs::write(
+        &file,
+        "o tent\nv 0 0 0\nv 0 1 0\nv 1 0 0\nv -1 0 0.2\nf 1 3 2\nf 1 2 4\n",
+    )
+    .unwrap();
+    let meshes = load_obj(&file, 60.0).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(meshes.len(), 1);
+    let normals = meshes[0].normals.as_ref().expect("no generated normals");
+    assert_eq!(normals.len(), meshes[0].vertices.len());
+    assert!(normals.iter().all(|n| n.z > 0.9));
+}
@@ -0,0 +1,166 @@
+//! Random numbers of the progressive rendering passes and of the threads (and their pinning): cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+
+use rustlight::integrators::direct::IntegratorDirect;
+use rustlight::integrators::explicit::light::IntegratorLightTracing;
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::explicit::vpl::IntegratorVPL;
+use rustlight::integrators::pssmlt::IntegratorPSSMLT;
+use rustlight::integrators::visibility_cache::VisibilityCacheConfig;
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::scene::{Scene, ThreadPinning};
+use rustlight::structure::FilmColor;
+use rustlight::validation::*;
+
+fn render(scene: &Scene, pass: usize) -> Vec<FilmColor> {
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorDirect {
+        nb_bsdf_samples: 1,
+        nb_light_samples: 1,
+    }));
+    scene.set_pass(pass);
+    let mut img = integrator.compute(scene, &NoObserver);
+    img.values.remove("primal").unwrap().colors
+}
+
+fn scene(lock_passes: bool) -> Scene {
+    let mut scene = point_light(0.5, 1.0)
+        .scene
+        .seed(42)
+        .lock_passes(lock_passes);
+    scene.nb_samples = 1;
+    scene
+}
+
+#[test]
+fn scrambled_passes() {
+    let scene = scene(false);
+    let first = render(&scene, 0);
+    let second = render(&scene, 1);
+    assert_ne!(first, second);
+    // Reproducible
+    assert_eq!(second, render(&scene, 1));
+    assert_eq!(first, render(&scene, 0));
+    // The first pass uses the seed unchanged
+    assert_eq!(scene.pass_seed(), Some(42));
+}
+
+#[test]
+fn locked_passes() {
+    let scene = scene(true);
+    assert_eq!(render(&scene, 0), render(&scene, 3));
+    assert_eq!(scene.pass_seed(), Some(42));
+}
+
+#[test]
+fn locked_passes_without_seed() {
+    let mut scene = scene(true);
+    scene.seed = None;
+    assert_eq!(render(&scene, 0), render(&scene, 1));
+}
+
+/// Image rendered with the number of threads in the deterministic mode
+fn render_deterministic(
+    mut integrator: IntegratorType,
+    nb_samples: usize,
+    nb_threads: usize,
+) -> Vec<FilmColor> {
+    let mut scene = point_light(0.5, 1.0).scene.deterministic(true);
+    scene.nb_samples = nb_samples;
+    scene.nb_threads = Some(nb_threads);
+    let mut img = integrator.compute(&scene, &NoObserver);
+    img.values.remove("primal").unwrap().colors
+}
+
+fn path_tracing() -> Box<IntegratorPathTracing> {
+    Box::new(IntegratorPathTracing {
+        max_depth: None,
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    })
+}
+
+#[test]
+fn deterministic_threads() {
+    let light_tracing = || {
+        IntegratorType::Primal(Box::new(IntegratorLightTracing {
+            max_depth: None,
+            render_surface: true,
+            render_volume: true,
+        }))
+    };
+    // Several Markov chains (one per 100000 samples)
+    let pssmlt = || {
+        IntegratorType::Primal(Box::new(IntegratorPSSMLT {
+            large_prob: 0.3,
+            integrator: path_tracing(),
+            temperatures: vec![1.0],
+            swap_frequency: 0.1,
+            adaptive: None,
+            delayed_rejection: false,
+            resume: None,
+            checkpoint: None,
+        }))
+    };
+    // Shared shadow rays (cache of each image block)
+    let vpl = || {
+        IntegratorType::Primal(Box::new(IntegratorVPL {
+            nb_vpl: 64,
+            max_depth: None,
+            clamping_factor: None,
+            nb_passes: 1,
+            visibility_cache: Some(VisibilityCacheConfig::new(0.5)),
+        }))
+    };
+    let path = || IntegratorType::Primal(path_tracing());
+    for (integrator, nb_samples) in [
+        (&path as &dyn Fn() -> IntegratorType, 4),
+        (&light_tracing, 4),
+        (&pssmlt, 300),
+        (&vpl, 2),
+    ] {
+        let reference = render_deterministic(integrator(), nb_samples, 1);
+        assert_eq!(reference, render_deterministic(integrator(), nb_samples, 4));
+        assert_eq!(reference, render_deterministic(integrator(), nb_samples, 3));
+    }
+}
+
+#[test]
+fn deterministic_seed() {
+    let mut scene = scene(false).deterministic(true);
+    assert_eq!(scene.pass_seed(), Some(42));
+    scene.seed = None;
+    assert_eq!(scene.pass_seed(), Some(0));
+    assert_eq!(render(&scene, 2), render(&scene, 2));
+    assert_ne!(render(&scene, 0), render(&scene, 1));
+}
+
+#[test]
+fn thread_pinning() {
+    assert_eq!("none".parse(), Ok(ThreadPinning::None));
+    assert_eq!("cores".parse(), Ok(ThreadPinning::Cores));
+    assert_eq!("0, 2,4".parse(), Ok(ThreadPinning::List(vec![0, 2, 4])));
+    assert!("0,a".parse::<ThreadPinning>().is_err());
+    assert_eq!(ThreadPinning::List(vec![3, 1]).core(2), Some(3));
+    assert_eq!(ThreadPinning::None.core(0), None);
+    // The pinning does not change the deterministic images
+    let render = |pinning: ThreadPinning| {
+        let mut scene = point_light(0.5, 1.0)
+            .scene
+            .deterministic(true)
+            .thread_pinning(pinning);
+        scene.nb_samples = 4;
+        scene.nb_threads = Some(2);
+        let mut integrator = IntegratorType::Primal(path_tracing());
+        let mut img = integrator.compute(&scene, &NoObserver);
+        img.values.remove("primal").unwrap().colors
+    };
+    let reference = render(ThreadPinning::None);
+    assert_eq!(reference, render(ThreadPinning::Cores));
+    assert_eq!(reference, render(ThreadPinning::List(vec![0])));
+}
@@ -0,0 +1,130 @@
+//! Paths recorded for one pixel: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+extern crate serde_json;
+
+use cgmath::Point2;
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::gradient::explicit::IntegratorGradientPathTracing;
+use rustlight::integrators::gradient::recons::UniformPoissonReconstruction;
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::paths::debugger::PathRecord;
+use rustlight::scene::Scene;
+use rustlight::validation::*;
+
+fn render(mut int: IntegratorType) -> Scene {
+    let mut scene = environment_ground(0.5, false)
+        .scene
+        .debug_pixel(Some(Point2::new(3, 5)));
+    scene.nb_samples = 16;
+    int.compute(&scene, &NoObserver);
+    scene
+}
+
+fn assert_valid(path: &PathRecord) {
+    assert_eq!(path.vertices[0].kind, "sensor");
+    for e in &path.edges {
+        assert!(e.from < path.vertices.len());
+        if let Some(to) = e.to {
+            assert!(to < path.vertices.len());
+            assert_eq!(e.end, path.vertices[to].position);
+        }
+        assert!(e.pdf >= 0.0);
+        assert!((0.0..=1.0).contains(&e.mis_weight), "{}", e.mis_weight);
+    }
+}
+
+#[test]
+fn path_tracing() {
+    let scene = render(IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: Some(4),
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    })));
+    let paths = scene.path_debugger.paths();
+    // One path per sample
+    assert_eq!(paths.len(), 16);
+    for path in &paths {
+        assert_eq!(path.pixel, (3, 5));
+        assert_valid(path);
+        // The camera sees the ground, the emitter sampling fails (no emitter)
+        assert_eq!(path.vertices[1].kind, "surface");
+        assert_eq!(path.vertices[1].mesh.as_deref(), Some("ground"));
+        assert!(path.edges.iter().all(|e| e.strategy == 0));
+    }
+    // The paths surviving the russian roulette escape toward the environment
+    assert!(paths.iter().any(|p| p
+        .edges
+        .iter()
+        .any(|e| e.to.is_none() && e.contribution.r > 0.0 && e.mis_weight == 1.0)));
+
+    // JSON and OBJ outputs
+    let dir = std::env::temp_dir();
+    let json = dir.join("rustlight_paths.json");
+    scene.path_debugger.save(json.to_str().unwrap()).unwrap();
+    let data: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(data.as_array().unwrap().len(), 16);
+    assert_eq!(data[0]["label"], "path");
+
+    let obj = dir.join("rustlight_paths.obj");
+    scene.path_debugger.save(obj.to_str().unwrap()).unwrap();
+    let data = std::fs::read_to_string(&obj).unwrap();
+    let nb_edges = paths.iter().map(|p| p.edges.len()).sum::<usize>();
+    assert_eq!(
+        data.lines().filter(|l| l.starts_with("l ")).count(),
+        nb_edges
+    );
+    assert_eq!(
+        data.lines().filter(|l| l.starts_with("v ")).count(),
+        2 * nb_edges
+    );
+    assert!(scene.path_debugger.save("paths.txt").is_err());
+}
+
+#[test]
+fn gradient_base_and_offsets() {
+    let scene = render(IntegratorType::Gradient(Box::new(
+        IntegratorGradientPathTracing {
+            max_depth: None,
+            recons: Box::new(UniformPoissonReconstruction { iterations: 1 }),
+            min_survival: None,
+        },
+    )));
+    let paths = scene.path_debugger.paths();
+    // The base path and the four offset paths of each sample
+    assert_eq!(paths.len(), 16 * 5);
+    for (i, path) in paths.iter().enumerate() {
+        assert_valid(path);
+        if i % 5 == 0 {
+            assert_eq!(path.label, "gradient base");
+            assert_eq!(path.pixel, (3, 5));
+        } else {
+            assert!(path.label.starts_with("gradient offset"));
+            let (dx, dy) = (path.pixel.0 as i32 - 3, path.pixel.1 as i32 - 5);
+            assert_eq!(dx.abs() + dy.abs(), 1, "{:?}", path.pixel);
+        }
+    }
+}
+
+#[test]
+fn other_pixels() {
+    let mut scene = environment_ground(0.5, false).scene;
+    scene.nb_samples = 1;
+    let mut int = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: Some(4),
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }));
+    int.compute(&scene, &NoObserver);
+    assert!(scene.path_debugger.paths().is_empty());
+}
@@ -0,0 +1,76 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Vector2;
+use rustlight::structure::{Bitmap, Color};
+
+fn write(name: &str, header: &str, values: &[f32], big_endian: bool) -> String {
+    let mut file = header.as_bytes().to_vec();
+    for v in values {
+        if big_endian {
+            file.extend_from_slice(&v.to_be_bytes());
+        } else {
+            file.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    let filename = std::env::temp_dir().join(name);
+    std::fs::write(&filename, file).unwrap();
+    filename.to_str().unwrap().to_string()
+}
+
+#[test]
+fn pfm_roundtrip() {
+    let img = Bitmap::from_fn(Vector2::new(3, 2), |p| {
+        Color::new(p.x as f32, p.y as f32, 0.5)
+    });
+    let filename = std::env::temp_dir().join("rustlight_roundtrip.pfm");
+    let filename = filename.to_str().unwrap();
+    img.save(filename).unwrap();
+    let read = Bitmap::read(filename).unwrap();
+    assert_eq!(read.size, img.size);
+    assert_eq!(read.colors, img.colors);
+}
+
+#[test]
+fn pfm_grayscale_big_endian() {
+    // Whitespace variations and a scale of 2: big endian, values doubled
+    let filename = write(
+        "rustlight_gray.pfm",
+        "Pf\r\n  2\t2 \n2.0\n",
+        &[0.0, 1.0, 2.0, 3.0],
+        true,
+    );
+    let img = Bitmap::read_pfm(&filename).unwrap();
+    assert_eq!(img.size, Vector2::new(2, 2));
+    // Bottom to top scanlines
+    let expected = [4.0, 6.0, 0.0, 2.0]
+        .iter()
+        .map(|v| Color::value(*v))
+        .collect::<Vec<_>>();
+    assert_eq!(img.colors, expected);
+}
+
+#[test]
+fn pfm_malformed() {
+    let values = [1.0; 6];
+    for (i, header) in [
+        "P6\n2 1\n-1.0\n",
+        "PF\n2\n-1.0\n",
+        "PF\n0 1\n-1.0\n",
+        "PF\n2 1\n0.0\n",
+    ]
+    .iter()
+    .enumerate()
+    {
+        let filename = write(
+            &format!("rustlight_malformed_{}.pfm", i),
+            header,
+            &values,
+            false,
+        );
+        assert!(Bitmap::read_pfm(&filename).is_err(), "{:?}", header);
+    }
+    // Truncated data
+    let filename = write("rustlight_truncated.pfm", "PF\n2 2\n-1.0\n", &values, false);
+    assert!(Bitmap::read_pfm(&filename).is_err());
+}
@@ -0,0 +1,171 @@
+//! Poisson reconstructions of synthetic gradient-domain buffers
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Vector2};
+use rustlight::integrators::gradient::dct::DCT;
+use rustlight::integrators::gradient::recons::*;
+use rustlight::integrators::{BufferCollection, PoissonReconstruction};
+use rustlight::structure::Color;
+
+/// Deterministic pseudo-random values in [0, 1)
+fn hash(x: u32, y: u32) -> f32 {
+    let v = (x as f32 * 12.9898 + y as f32 * 78.233).sin() * 43_758.547;
+    v - v.floor()
+}
+
+/// Estimates (primal, gradients) of a given image
+fn estimates(size: Vector2<u32>, image: impl Fn(u32, u32) -> Color) -> BufferCollection {
+    let names = ["primal", "gradient_x", "gradient_y", "very_direct"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    let mut est = BufferCollection::new(Point2::new(0, 0), size, &names);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let pos = Point2::new(x, y);
+            est.accumulate(pos, image(x, y), "primal");
+            if x < size.x - 1 {
+                est.accumulate(pos, image(x + 1, y) - image(x, y), "gradient_x");
+            }
+            if y < size.y - 1 {
+                est.accumulate(pos, image(x, y + 1) - image(x, y), "gradient_y");
+            }
+        }
+    }
+    est
+}
+
+#[test]
+fn dct_roundtrip() {
+    for &n in &[1, 2, 7, 8, 33, 100] {
+        let dct = DCT::new(n);
+        let x = (0..n)
+            .map(|i| f64::from(hash(i as u32, n as u32)))
+            .collect::<Vec<_>>();
+        let mut v = x.clone();
+        dct.forward(&mut v);
+        for (k, v) in v.iter().enumerate() {
+            let expected = x
+                .iter()
+                .enumerate()
+                .map(|(j, x)| {
+                    x * (std::f64::consts::PI * (2 * j + 1) as f64 * k as f64 / (2 * n) as f64)
+                        .cos()
+                })
+                .sum::<f64>();
+            assert!((v - expected).abs() < 1e-9, "{} != {}", v, expected);
+        }
+        dct.inverse(&mut v);
+        for (a, b) in v.iter().zip(&x) {
+            assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn fft_consistent_estimates() {
+    // The consistent primal and gradients are reconstructed exactly
+    let scene = rustlight::validation::furnace(0.5, 1.0).scene;
+    let size = Vector2::new(37, 20);
+    let image = |x: u32, y: u32| Color::new(hash(x, y), x as f32 * 0.1, (y as f32 * 0.3).sin());
+    let recons =
+        FourierPoissonReconstruction { alpha: 0.2 }.reconstruct(&scene, &estimates(size, image));
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let (a, b) = (recons.get(Point2::new(x, y), "primal"), image(x, y));
+            for c in 0..3 {
+                assert!((a.get(c) - b.get(c)).abs() < 1e-4, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn fft_screened_poisson() {
+    // Inconsistent estimates: the reconstruction solves the normal equations
+    // alpha^2 (I - primal) + div(gradients) - div(grad I) = 0
+    let scene = rustlight::validation::furnace(0.5, 1.0).scene;
+    let size = Vector2::new(24, 45);
+    let mut est = estimates(size, |x, y| Color::value(hash(x, y)));
+    for y in 0..size.y {
+        for x in 0..size.x {
+            est.accumulate(Point2::new(x, y), Color::value(hash(y, x)), "primal");
+        }
+    }
+    let alpha = 0.2;
+    let recons = FourierPoissonReconstruction { alpha }.reconstruct(&scene, &est);
+    let value = |x: u32, y: u32, name: &str| est.get(Point2::new(x, y), name).r;
+    let image = |x: u32, y: u32| recons.get(Point2::new(x, y), "primal").r;
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let mut residual = alpha * alpha * (image(x, y) - value(x, y, "primal"));
+            if x > 0 {
+                residual += image(x, y) - image(x - 1, y) - value(x - 1, y, "gradient_x");
+            }
+            if x < size.x - 1 {
+                residual -= image(x + 1, y) - image(x, y) - value(x, y, "gradient_x");
+            }
+            if y > 0 {
+                residual += image(x, y) - image(x, y - 1) - value(x, y - 1, "gradient_y");
+            }
+            if y < size.y - 1 {
+                residual -= image(x, y + 1) - image(x, y) - value(x, y, "gradient_y");
+            }
+            assert!(
+                residual.abs() < 1e-3,
+                "residual {} at {} {}",
+                residual,
+                x,
+                y
+            );
+        }
+    }
+}
+
+#[test]
+fn feature_guided_edges() {
+    // The gradients miss a step edge that the features (normals) reveal
+    let scene = rustlight::validation::furnace(0.5, 1.0).scene;
+    let size = Vector2::new(32, 16);
+    let image = |x: u32, _: u32| Color::value(if x < 16 { 0.0 } else { 1.0 });
+    let mut est = estimates(size, image);
+    for name in &["albedo", "normal"] {
+        est.register(name.to_string());
+    }
+    for y in 0..size.y {
+        let pos = Point2::new(15, y);
+        est.accumulate(pos, Color::value(-1.0), "gradient_x");
+        for x in 0..size.x {
+            let n = if x < 16 {
+                Color::new(0.0, 0.0, 1.0)
+            } else {
+                Color::new(1.0, 0.0, 0.0)
+            };
+            est.accumulate(Point2::new(x, y), n, "normal");
+            est.accumulate(Point2::new(x, y), Color::value(0.5), "albedo");
+        }
+    }
+    let error = |recons: &BufferCollection| {
+        let mut error: f32 = 0.0;
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let v = recons.get(Point2::new(x, y), "primal");
+                error = error.max((v.r - image(x, y).r).abs());
+            }
+        }
+        error
+    };
+    let feature = FeaturePoissonReconstruction::new(50, 0.2);
+    assert!(feature.need_features());
+    let guided = error(&feature.reconstruct(&scene, &est));
+    let screened = error(&FourierPoissonReconstruction { alpha: 0.2 }.reconstruct(&scene, &est));
+    assert!(
+        guided < 0.01,
+        "error {} (screened Poisson: {})",
+        guided,
+        screened
+    );
+    assert!(screened > 0.1);
+}
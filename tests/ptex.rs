@@ -0,0 +1,182 @@
+extern crate byteorder;
+extern crate cgmath;
+extern crate rustlight;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use cgmath::{Point3, Vector2, Vector3};
+use rustlight::structure::{Color, TexCoords};
+use rustlight::texture::ptex::{PtexMeshType, PtexTexture};
+use rustlight::texture::Texture;
+
+/// zlib stream with a single stored (not compressed) deflate block
+fn zip(data: &[u8]) -> Vec<u8> {
+    let mut res = vec![0x78, 0x01, 1];
+    let len = data.len() as u16;
+    res.extend_from_slice(&len.to_le_bytes());
+    res.extend_from_slice(&(!len).to_le_bytes());
+    res.extend_from_slice(data);
+    let (mut a, mut b) = (1u32, 0u32);
+    for v in data {
+        a = (a + u32::from(*v)) % 65521;
+        b = (b + a) % 65521;
+    }
+    res.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    res
+}
+
+/// Face written in the first level: (log2 resolution, constant flag, encoding, data)
+struct Face {
+    res: (u8, u8),
+    constant: bool,
+    encoding: u32,
+    data: Vec<u8>,
+}
+
+/// Ptex file with a single level, the constant values given for each face
+fn write_ptex(
+    name: &str,
+    mesh_type: u32,
+    data_type: u32,
+    nb_channels: u16,
+    faces: &[Face],
+    const_data: &[u8],
+) -> String {
+    let mut face_info = vec![];
+    for f in faces {
+        face_info.extend_from_slice(&[f.res.0, f.res.1, 0, f.constant as u8]);
+        face_info.extend_from_slice(&[0; 16]); // Adjacent faces and edges
+    }
+    let mut level_header = vec![];
+    let mut level_data = vec![];
+    for f in faces {
+        let data = if f.encoding >= 2 {
+            zip(&f.data)
+        } else {
+            f.data.clone()
+        };
+        level_header
+            .write_u32::<LittleEndian>(data.len() as u32 | f.encoding << 30)
+            .unwrap();
+        level_data.extend_from_slice(&data);
+    }
+    let (face_info, const_data, level_header) =
+        (zip(&face_info), zip(const_data), zip(&level_header));
+
+    let mut file = vec![];
+    for v in &[0x7865_7450, 1, mesh_type, data_type, -1i32 as u32] {
+        file.write_u32::<LittleEndian>(*v).unwrap();
+    }
+    file.write_u16::<LittleEndian>(nb_channels).unwrap();
+    file.write_u16::<LittleEndian>(1).unwrap();
+    // Faces, extended header, face info, constant data and level info sizes, minor version
+    for v in &[faces.len(), 0, face_info.len(), const_data.len(), 16, 0] {
+        file.write_u32::<LittleEndian>(*v as u32).unwrap();
+    }
+    let level_size = level_header.len() + level_data.len();
+    file.write_u64::<LittleEndian>(level_size as u64).unwrap();
+    file.write_u64::<LittleEndian>(0).unwrap(); // Meta data
+    file.resize(64, 0);
+    file.extend_from_slice(&face_info);
+    file.extend_from_slice(&const_data);
+    // Level info: data size, header size, number of faces
+    file.write_u64::<LittleEndian>(level_size as u64).unwrap();
+    file.write_u32::<LittleEndian>(level_header.len() as u32)
+        .unwrap();
+    file.write_u32::<LittleEndian>(faces.len() as u32).unwrap();
+    file.extend_from_slice(&level_header);
+    file.extend_from_slice(&level_data);
+
+    let filename = std::env::temp_dir().join(name);
+    std::fs::write(&filename, file).unwrap();
+    filename.to_str().unwrap().to_string()
+}
+
+fn coords(triangle: usize, b: Vector2<f32>) -> TexCoords {
+    TexCoords {
+        uv: None,
+        p: Point3::new(0.0, 0.0, 0.0),
+        n: Vector3::new(0.0, 0.0, 1.0),
+        face: Some((triangle, b)),
+        width: 0.0,
+    }
+}
+
+#[test]
+fn quad_float_faces() {
+    // Face 0: 2x2 zipped RGB texels (planar channels), face 1: constant
+    let mut texels = vec![];
+    for c in 0..3 {
+        for i in 0..4 {
+            texels
+                .write_f32::<LittleEndian>((c * 4 + i) as f32)
+                .unwrap();
+        }
+    }
+    let mut const_data = vec![];
+    for v in &[0.0, 0.0, 0.0, 0.25, 0.5, 0.75] {
+        const_data.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    let faces = [
+        Face {
+            res: (1, 1),
+            constant: false,
+            encoding: 2,
+            data: texels,
+        },
+        Face {
+            res: (0, 0),
+            constant: true,
+            encoding: 0,
+            data: const_data[12..].to_vec(),
+        },
+    ];
+    let filename = write_ptex("rustlight_quad.ptx", 1, 3, 3, &faces, &const_data);
+    let texture = PtexTexture::load(&filename).unwrap();
+    assert_eq!(texture.mesh_type, PtexMeshType::Quad);
+    assert_eq!(texture.nb_faces(), 2);
+    let texel = |i: usize| Color::new(i as f32, (4 + i) as f32, (8 + i) as f32);
+    assert_eq!(texture.lookup(0, Vector2::new(0.25, 0.75)), texel(2));
+    assert_eq!(texture.lookup(0, Vector2::new(0.75, 0.25)), texel(1));
+
+    // First triangle (0, 1, 2) then second triangle (0, 2, 3) of the quad
+    let eval = |triangle: usize, b: Vector2<f32>| texture.eval_at(&coords(triangle, b), 0.0);
+    assert_eq!(eval(0, Vector2::new(0.8, 0.1)), Some(texel(1)));
+    assert_eq!(eval(0, Vector2::new(0.0, 0.9)), Some(texel(3)));
+    assert_eq!(eval(1, Vector2::new(0.1, 0.8)), Some(texel(2)));
+    assert_eq!(
+        eval(2, Vector2::new(0.3, 0.3)),
+        Some(Color::new(0.25, 0.5, 0.75))
+    );
+    assert_eq!(eval(4, Vector2::new(0.3, 0.3)), None);
+    // Plain uv lookups have no face
+    let mut uv_only = coords(0, Vector2::new(0.0, 0.0));
+    uv_only.face = None;
+    uv_only.uv = Some(Vector2::new(0.5, 0.5));
+    assert_eq!(texture.eval_at(&uv_only, 0.0), None);
+}
+
+#[test]
+fn triangle_gray_diff() {
+    // 2x2 gray u8 texels, delta encoded: 10, 20, 30, 40
+    let faces = [Face {
+        res: (1, 1),
+        constant: false,
+        encoding: 3,
+        data: vec![10, 10, 10, 10],
+    }];
+    let filename = write_ptex("rustlight_triangle.ptx", 0, 0, 1, &faces, &[25]);
+    let texture = PtexTexture::load(&filename).unwrap();
+    assert_eq!(texture.mesh_type, PtexMeshType::Triangle);
+    let value = |v: u8| Color::value(f32::from(v) / 255.0);
+    let eval = |b: Vector2<f32>| texture.eval_at(&coords(0, b), 0.0).unwrap();
+    assert_eq!(eval(Vector2::new(0.1, 0.1)), value(10));
+    assert_eq!(eval(Vector2::new(0.7, 0.1)), value(20));
+    assert_eq!(eval(Vector2::new(0.1, 0.7)), value(30));
+    // Above the diagonal of the texel (0, 0): texel (1, 1), outside of the triangle
+    assert_eq!(eval(Vector2::new(0.45, 0.45)), value(40));
+
+    // Not a Ptex file
+    let filename = std::env::temp_dir().join("rustlight_not_ptex.ptx");
+    std::fs::write(&filename, [0u8; 64]).unwrap();
+    assert!(PtexTexture::load(filename.to_str().unwrap()).is_err());
+}
@@ -0,0 +1,94 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Point3;
+use rustlight::paths::path::{RadianceCache, RussianRoulette};
+use rustlight::structure::Color;
+use std::sync::Arc;
+
+/// Bright records on the x > 0.5 half of the unit cube, dark ones on the other half
+fn records() -> Vec<(Point3<f32>, f32)> {
+    let mut records = vec![];
+    for i in 0..10 {
+        for j in 0..10 {
+            let (y, z) = (i as f32 / 9.0, j as f32 / 9.0);
+            records.push((Point3::new(0.0, y, z), 1.0));
+            records.push((Point3::new(0.2, y, z), 3.0));
+            records.push((Point3::new(1.0, y, z), 10.0));
+        }
+    }
+    records
+}
+
+#[test]
+fn cache_cells() {
+    let cache = RadianceCache::new(&records(), 4);
+    assert!((cache.average() - 14.0 / 3.0).abs() < 1e-4);
+    // Average of the records of the cell
+    assert!((cache.get(&Point3::new(0.1, 0.5, 0.5)) - 2.0).abs() < 1e-4);
+    assert!((cache.get(&Point3::new(0.9, 0.1, 0.9)) - 10.0).abs() < 1e-4);
+    // Empty cell: average of all the records
+    assert!((cache.get(&Point3::new(0.6, 0.5, 0.5)) - cache.average()).abs() < 1e-4);
+    // Outside: closest cell
+    assert!((cache.get(&Point3::new(5.0, 0.5, 0.5)) - 10.0).abs() < 1e-4);
+    assert!((cache.get(&Point3::new(-5.0, -1.0, 2.0)) - 2.0).abs() < 1e-4);
+}
+
+#[test]
+fn empty_cache() {
+    let cache = RadianceCache::new(&[], 32);
+    assert_eq!(cache.get(&Point3::new(1.0, 2.0, 3.0)), 0.0);
+    // A single record
+    let cache = RadianceCache::new(&[(Point3::new(1.0, 2.0, 3.0), 4.0)], 32);
+    assert_eq!(cache.get(&Point3::new(0.0, 0.0, 0.0)), 4.0);
+}
+
+#[test]
+fn adjoint_survival() {
+    let rr = RussianRoulette {
+        scale: 0.5,
+        max_splitting: 8,
+        radiance: Some(Arc::new(RadianceCache::new(&records(), 4))),
+        ..Default::default()
+    };
+    let throughput = Color::value(0.1);
+    // Dark region: the path is likely to be killed
+    let dark = Point3::new(0.0, 0.5, 0.5);
+    assert!((rr.survival(&throughput, 1, &dark) - 0.1).abs() < 1e-4);
+    assert_eq!(rr.splitting(&throughput, 1, &dark), 1);
+    // Bright region: the same path survives or is split
+    let bright = Point3::new(1.0, 0.5, 0.5);
+    assert!((rr.survival(&throughput, 1, &bright) - 0.5).abs() < 1e-4);
+    assert_eq!(rr.splitting(&throughput, 1, &bright), 1);
+    assert_eq!(rr.survival(&Color::value(1.0), 1, &bright), 0.95);
+    assert_eq!(rr.splitting(&Color::value(1.0), 1, &bright), 5);
+    assert_eq!(rr.splitting(&Color::value(4.0), 1, &bright), 8);
+    // Before the start depth
+    let rr = RussianRoulette {
+        start_depth: 2,
+        ..rr
+    };
+    assert_eq!(rr.survival(&throughput, 1, &dark), 1.0);
+}
+
+#[test]
+fn adjoint_survival_without_radiance() {
+    // The cells without radiance still let some paths continue
+    let records = vec![
+        (Point3::new(0.0, 0.0, 0.0), 0.0),
+        (Point3::new(1.0, 1.0, 1.0), 0.0),
+    ];
+    let rr = RussianRoulette {
+        radiance: Some(Arc::new(RadianceCache::new(&records, 4))),
+        ..Default::default()
+    };
+    let p = Point3::new(0.0, 0.0, 0.0);
+    assert_eq!(
+        rr.survival(&Color::value(0.5), 0, &p),
+        RussianRoulette::MIN_SURVIVAL
+    );
+    assert_eq!(rr.survival(&Color::zero(), 0, &p), 0.0);
+    // Only the cached radiance is bounded
+    let rr = RussianRoulette::default();
+    assert_eq!(rr.survival(&Color::value(0.01), 0, &p), 0.01);
+}
@@ -0,0 +1,63 @@
+extern crate cgmath;
+extern crate embree_rs;
+extern crate rustlight;
+
+use cgmath::{Point3, Vector2, Vector3};
+use rustlight::camera::RayDifferentials;
+use rustlight::geometry::Mesh;
+use rustlight::integrators::embree_scene;
+use rustlight::scene::{Acceleration, EmbreeAcceleration};
+use rustlight::structure::Ray;
+
+/// Square [-1, 1]^2 at z = 0 facing +z, with the uv [0, 1]^2 (optional)
+fn quad(uv: bool) -> Mesh {
+    let vertices = vec![
+        Vector3::new(-1.0, -1.0, 0.0),
+        Vector3::new(1.0, -1.0, 0.0),
+        Vector3::new(1.0, 1.0, 0.0),
+        Vector3::new(-1.0, 1.0, 0.0),
+    ];
+    let indices = vec![Vector3::new(0, 1, 2), Vector3::new(0, 2, 3)];
+    let uv = if uv {
+        Some(vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            Vector2::new(1.0, 1.0),
+            Vector2::new(0.0, 1.0),
+        ])
+    } else {
+        None
+    };
+    Mesh::new("quad".to_string(), vertices, indices, None, uv)
+}
+
+/// Width of the footprint of a ray toward -z and its differentials offset by delta
+fn uv_width(mesh: Mesh, o: Point3<f32>, delta: f32) -> f32 {
+    let meshes = vec![mesh];
+    let device = embree_rs::Device::new();
+    let scene = embree_scene(&device, &meshes);
+    let accel = EmbreeAcceleration::new(&meshes, &scene);
+    let d = Vector3::new(0.0, 0.0, -1.0);
+    let mut its = accel.trace(&Ray::new(o, d)).unwrap();
+    assert_eq!(its.tex_coords().width, 0.0);
+    its.set_differentials(&RayDifferentials {
+        dx: Ray::new(o + Vector3::new(delta, 0.0, 0.0), d),
+        dy: Ray::new(o + Vector3::new(0.0, delta, 0.0), d),
+    });
+    its.tex_coords().width
+}
+
+#[test]
+fn footprint_in_uv_space() {
+    // The uv space is half the size of the quad (one point per triangle)
+    for &o in &[Point3::new(0.3, -0.2, 1.0), Point3::new(-0.5, 0.3, 2.0)] {
+        let width = uv_width(quad(true), o, 0.02);
+        assert!((width - 0.01).abs() < 1e-4, "{}", width);
+    }
+}
+
+#[test]
+fn footprint_without_uv() {
+    // Finest level
+    assert_eq!(uv_width(quad(false), Point3::new(0.1, 0.2, 1.0), 0.02), 0.0);
+}
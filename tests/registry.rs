@@ -0,0 +1,167 @@
+extern crate rustlight;
+extern crate serde_json;
+
+use rustlight::error::{Error, Result};
+use rustlight::integrators::direct::IntegratorDirect;
+use rustlight::integrators::registry::*;
+use rustlight::integrators::IntegratorType;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Direct lighting with the number of light samples as mandatory parameter
+struct LightOnly;
+impl IntegratorFactory for LightOnly {
+    fn about(&self) -> &str {
+        "direct lighting with the emitter sampling only"
+    }
+    fn params(&self) -> Vec<IntegratorParam> {
+        vec![
+            IntegratorParam::new("light", "number of light samples", None),
+            IntegratorParam::new("bsdf", "number of BSDF samples", Some("0")),
+        ]
+    }
+    fn create(&self, params: &IntegratorParams) -> Result<IntegratorType> {
+        let parse = |name: &str| {
+            params[name]
+                .parse::<u32>()
+                .map_err(|e| Error::InvalidData(format!("{}: {}", name, e)))
+        };
+        Ok(IntegratorType::Primal(Box::new(IntegratorDirect {
+            nb_bsdf_samples: parse("bsdf")?,
+            nb_light_samples: parse("light")?,
+        })))
+    }
+}
+
+fn manager() -> IntegratorManager {
+    let mut integrators = IntegratorManager::default();
+    integrators
+        .register("light-only", Rc::new(LightOnly))
+        .unwrap();
+    integrators
+}
+
+#[test]
+fn register() {
+    let mut integrators = manager();
+    let builtins = IntegratorManager::default().names().len();
+    assert_eq!(integrators.names().len(), builtins + 1);
+    assert_eq!(integrators.get("light-only").unwrap().params().len(), 2);
+    // The built-in integrators without reconstruction are registered by default
+    assert!(integrators.get("path").is_some());
+    assert!(integrators.get("gradient-path").is_none());
+    // Same name twice: the first factory is kept
+    assert!(integrators
+        .register("light-only", Rc::new(LightOnly))
+        .is_err());
+    // Name of a built-in integrator
+    assert!(integrators.register("path", Rc::new(LightOnly)).is_err());
+    assert!(integrators.register("pssmlt", Rc::new(LightOnly)).is_err());
+    integrators.register("other", Rc::new(LightOnly)).unwrap();
+    assert_eq!(integrators.names().len(), builtins + 2);
+}
+
+#[test]
+fn create_from_json() {
+    let integrators = manager();
+    let json = |s: &str| serde_json::from_str::<serde_json::Value>(s).unwrap();
+    assert!(integrators
+        .create_from_json("light-only", &json(r#"{"light": 4}"#))
+        .is_ok());
+    assert!(integrators
+        .create_from_json("light-only", &json(r#"{"light": "4", "bsdf": 1}"#))
+        .is_ok());
+    // The built-in integrators are created by the same manager
+    // (null for the default values)
+    for (name, params) in &[
+        ("direct", r#"{"light": 2}"#),
+        (
+            "path",
+            r#"{"max": 5, "strategy": "bsdf", "min_roughness": 0.1}"#,
+        ),
+        ("path", r#"{"max": "inf", "rr": null}"#),
+        (
+            "volpath",
+            r#"{"equiangular": true, "single_scattering": 4}"#,
+        ),
+        ("ao", r#"{"normal_correction": false}"#),
+        (
+            "gradient-path",
+            r#"{"reconstruction_type": "bagging", "buffers": 4}"#,
+        ),
+        ("gradient-path-explicit", r#"{"min_survival": 0.5}"#),
+        ("pssmlt", r#"{"temperatures": "1,2,4", "adaptive": 0.234}"#),
+        (
+            "vol_primitives",
+            r#"{"primitives": "vrl", "heatmap": true}"#,
+        ),
+        ("cryptomatte", r#"{"filter": "gaussian"}"#),
+    ] {
+        assert!(
+            integrators.create_from_json(name, &json(params)).is_ok(),
+            "{} {}",
+            name,
+            params
+        );
+    }
+    for (name, params) in &[
+        ("unknown", r#"{"light": 4}"#),
+        // Missing, unknown or invalid parameters
+        ("light-only", "null"),
+        ("light-only", r#"{"light": 4, "depth": 2}"#),
+        ("light-only", r#"{"light": -1}"#),
+        ("light-only", "[4]"),
+        ("light-only", r#"{"light": [4]}"#),
+        // Built-in integrators: unknown parameters and wrong types
+        ("path", r#"{"max_depth": 5}"#),
+        ("path", r#"{"max": "five"}"#),
+        ("path", r#"{"max": 2.5}"#),
+        ("path", r#"{"strategy": 1}"#),
+        ("vpl", r#"{"passes": 0}"#),
+        ("volpath", r#"{"equiangular": 1}"#),
+        ("ao", r#"{"distance": {"max": 1}}"#),
+        ("gradient-path", r#"{"reconstruction_type": "none"}"#),
+        (
+            "gradient-path",
+            r#"{"reconstruction_type": "fft", "alpha": 0}"#,
+        ),
+        (
+            "gradient-path",
+            r#"{"reconstruction_type": "feature", "alpha": -1}"#,
+        ),
+        ("gradient-path-explicit", r#"{"min_survival": 0}"#),
+        ("pssmlt", r#"{"temperatures": "2,1"}"#),
+        ("pssmlt", r#"{"swap_frequency": 1.5}"#),
+        ("pssmlt", r#"{"swap_frequency": -0.1}"#),
+        ("pssmlt", r#"{"resume": "missing_checkpoint.json"}"#),
+        ("vol_primitives", r#"{"primitives": "points"}"#),
+        ("cryptomatte", r#"{"filter": "tent"}"#),
+    ] {
+        assert!(
+            integrators.create_from_json(name, &json(params)).is_err(),
+            "{} {}",
+            name,
+            params
+        );
+    }
+}
+
+#[test]
+fn create_from_args() {
+    let integrators = manager();
+    // Command line arguments (the unrelated ones are ignored)
+    let args = [("light", "2"), ("output", "out.exr")]
+        .iter()
+        .cloned()
+        .collect::<HashMap<_, _>>();
+    let value_of = |name: &str| args.get(name).cloned();
+    assert!(integrators.create_from_args("light-only", value_of).is_ok());
+    assert!(integrators.create_from_args("unknown", value_of).is_err());
+    assert!(integrators
+        .create_from_args("light-only", |_: &str| None)
+        .is_err());
+    // Built-in integrator with its default values
+    assert!(integrators
+        .create_from_args("volpath", |_: &str| None)
+        .is_ok());
+}
@@ -0,0 +1,53 @@
+extern crate rustlight;
+
+use rustlight::structure::{geometric_median, Color, SampleRejection};
+
+/// Deterministic noisy samples around 1 (mean 1)
+fn samples(n: usize) -> Vec<Color> {
+    (0..n)
+        .map(|i| Color::value(0.5 + ((i * 7919) % 101) as f32 / 100.0))
+        .collect()
+}
+
+#[test]
+fn number_of_groups() {
+    let rejection = SampleRejection { confidence: 0.99 };
+    assert_eq!(rejection.nb_groups(1024), 37);
+    // At least one sample per group
+    assert_eq!(rejection.nb_groups(16), 16);
+    assert_eq!(rejection.nb_groups(0), 1);
+    assert_eq!(SampleRejection { confidence: 0.5 }.nb_groups(64), 6);
+}
+
+#[test]
+fn median_of_colors() {
+    let c = Color::new(0.1, 0.2, 0.3);
+    let median = geometric_median(&[c, c, c]);
+    assert!((median - c).abs().channel_max() < 1e-6);
+    // The far point does not move the median of the aligned points
+    let colors = [
+        Color::value(1.0),
+        Color::value(2.0),
+        Color::value(3.0),
+        Color::value(1000.0),
+    ];
+    let median = geometric_median(&colors);
+    assert!(median.r >= 2.0 && median.r <= 3.0, "{:?}", median);
+}
+
+#[test]
+fn firefly_rejection() {
+    let mut samples = samples(1024);
+    let rejection = SampleRejection { confidence: 0.99 };
+    let estimate = rejection.estimate(&samples);
+    assert!((estimate.r - 1.0).abs() < 0.01, "{:?}", estimate);
+
+    // A single firefly moves the mean but not the robust estimate
+    samples[500] = Color::new(1e5, 0.0, 0.0);
+    let mean = samples.iter().fold(Color::zero(), |acc, c| acc + *c) / samples.len() as f32;
+    assert!(mean.r > 90.0);
+    let estimate = rejection.estimate(&samples);
+    assert!((estimate.r - 1.0).abs() < 0.01, "{:?}", estimate);
+    assert!((estimate.g - 1.0).abs() < 0.01, "{:?}", estimate);
+    assert!(rejection.estimate(&[]).is_zero());
+}
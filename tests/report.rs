@@ -0,0 +1,92 @@
+//! Scene statistics and memory report: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+extern crate serde_json;
+
+use cgmath::Vector2;
+use rustlight::bsdfs::diffuse::BSDFDiffuse;
+use rustlight::bsdfs::BSDFColor;
+use rustlight::emitter::EmitterSelection;
+use rustlight::structure::{Bitmap, Color};
+use rustlight::texture::{BitmapTexture, Filter, WrapMode};
+use rustlight::validation::*;
+
+#[test]
+fn many_lights_report() {
+    let mut scene = many_lights(0.5, 1.0).scene;
+    let report = scene.report();
+    assert_eq!(report.meshes.len(), 17);
+    assert_eq!(report.triangles, 34);
+    assert_eq!(report.vertices, 68);
+    assert_eq!(
+        report.geometry_memory,
+        report.meshes.iter().map(|m| m.memory).sum::<usize>()
+    );
+    // Positions, normals, indices and area distribution of a square
+    assert!(report.meshes[0].memory >= 4 * 12 + 4 * 12 + 2 * 24);
+    assert_eq!(report.texture_memory, 0);
+
+    // Emitters sorted by flux: the emission of the brightest ones is 3
+    assert_eq!(report.emitters.len(), 16);
+    let total = report.emitters.iter().map(|e| e.fraction).sum::<f32>();
+    assert!((total - 1.0).abs() < 1e-4);
+    assert!(report
+        .emitters
+        .windows(2)
+        .all(|w| w[0].fraction >= w[1].fraction));
+    let brightest = &report.emitters[0];
+    let expected = 0.04 * 0.04 * 3.0 * std::f32::consts::PI;
+    assert!((brightest.flux.r - expected).abs() < 1e-4 * expected);
+    assert!((brightest.fraction - 3.0 / 31.0).abs() < 1e-4);
+
+    // Binary tree with leaves of at most 4 triangles
+    let bvh = &report.bvh;
+    assert_eq!(bvh.nodes, 2 * bvh.leaves - 1);
+    assert!(bvh.leaves >= 34 / 4);
+    assert!(bvh.build_time >= 0.0 && bvh.embree_build_time >= 0.0);
+    assert_eq!(bvh.light_nodes, None);
+    scene.emitter_selection = EmitterSelection::LightBVH;
+    assert_eq!(scene.report().bvh.light_nodes, Some(2 * 16 - 1));
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["triangles"], 34);
+    assert_eq!(json["meshes"][0]["name"], "ground");
+    assert_eq!(json["emitters"].as_array().unwrap().len(), 16);
+}
+
+#[test]
+fn texture_memory() {
+    let mut scene = environment_ground(0.5, false).scene;
+    let env_memory = scene
+        .emitter_environment
+        .as_ref()
+        .unwrap()
+        .map
+        .as_ref()
+        .unwrap()
+        .image
+        .memory();
+    let report = scene.report();
+    assert_eq!(report.texture_memory, env_memory);
+    assert_eq!(report.emitters.len(), 1);
+    assert_eq!(report.emitters[0].name, "environment");
+    assert_eq!(report.emitters[0].fraction, 1.0);
+
+    // 64x64 image and its mipmaps (32x32, ..., 1x1)
+    let img = Bitmap::<Color>::new(Vector2::new(64, 64));
+    let texture = BitmapTexture::new(img, Filter::default(), WrapMode::default());
+    scene.meshes[0].bsdf = Box::new(BSDFDiffuse {
+        diffuse: BSDFColor::TextureColor(Box::new(texture)),
+    });
+    let texels = (0..7).map(|l| (64 >> l) * (64 >> l)).sum::<usize>();
+    let report = scene.report();
+    assert_eq!(
+        report.meshes[0].texture_memory,
+        texels * std::mem::size_of::<Color>()
+    );
+    assert_eq!(
+        report.texture_memory,
+        env_memory + report.meshes[0].texture_memory
+    );
+}
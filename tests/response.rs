@@ -0,0 +1,98 @@
+extern crate rustlight;
+
+use rustlight::response::{Exposure, ResponseCurve, ResponseDesc};
+use rustlight::structure::Color;
+
+fn assert_close(a: f32, b: f32, tolerance: f32) {
+    assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+}
+
+/// Write a temporary file and return its name
+fn write_temp(name: &str, content: &str) -> String {
+    let filename = std::env::temp_dir().join(name);
+    std::fs::write(&filename, content).unwrap();
+    filename.to_str().unwrap().to_string()
+}
+
+#[test]
+fn exposure_value() {
+    // Sunny 16: EV 15 at ISO 100
+    let sunny = Exposure::new(100.0, 1.0 / 128.0, 16.0).unwrap();
+    assert_close(sunny.ev100(), 15.0, 1e-5);
+    assert_close(sunny.scale() * 1.2 * 32768.0, 1.0, 1e-5);
+    // Doubling the sensitivity or the time, or opening by one stop: twice brighter
+    let scale = Exposure::new(100.0, 0.01, 8.0).unwrap().scale();
+    for e in &[
+        (200.0, 0.01, 8.0),
+        (100.0, 0.02, 8.0),
+        (100.0, 0.01, 8.0 / 2f32.sqrt()),
+    ] {
+        let e = Exposure::new(e.0, e.1, e.2).unwrap();
+        assert_close(e.scale() / scale, 2.0, 1e-4);
+    }
+    assert!(Exposure::new(100.0, 0.0, 8.0).is_err());
+}
+
+#[test]
+fn cube_lut() {
+    let filename = write_temp(
+        "rustlight_response.cube",
+        "# Response\nTITLE \"test\"\nLUT_1D_SIZE 3\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 1\n\
+         0 0 0\n0.5 0.25 0.8\n1 1 1\n",
+    );
+    let curve = ResponseDesc::Lut(filename.clone()).load().unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    let c = curve.apply(Color::new(1.0, 0.5, 0.25));
+    assert_close(c.r, 0.5, 1e-6);
+    assert_close(c.g, 0.125, 1e-6);
+    assert_close(c.b, 0.4, 1e-6);
+    // Clamped outside of the domain
+    let c = curve.apply(Color::new(-1.0, 3.0, 1.5));
+    assert_eq!((c.r, c.g, c.b), (0.0, 1.0, 1.0));
+}
+
+#[test]
+fn text_lut() {
+    let filename = write_temp("rustlight_response.txt", "0.0\n0.6\n0.9\n1.0\n");
+    let curve = ResponseCurve::from_lut(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(curve.curves[0], curve.curves[2]);
+    assert_close(curve.evaluate(1, 0.5), 0.75, 1e-6);
+    assert_close(curve.evaluate(1, 1.0), 1.0, 1e-6);
+
+    let invalid = [
+        ("rustlight_response_3d.cube", "LUT_3D_SIZE 2\n"),
+        (
+            "rustlight_response_size.cube",
+            "LUT_1D_SIZE 3\n0 0 0\n1 1 1\n",
+        ),
+        ("rustlight_response_columns.txt", "0 0\n1 1\n"),
+        ("rustlight_response_short.txt", "0.5\n"),
+    ];
+    for (name, content) in &invalid {
+        let filename = write_temp(name, content);
+        assert!(ResponseCurve::from_lut(&filename).is_err(), "{}", name);
+        std::fs::remove_file(&filename).unwrap();
+    }
+}
+
+#[test]
+fn emor() {
+    // Same layout as the emor.txt file of DoRF (with 3 samples per curve)
+    let filename = write_temp(
+        "rustlight_emor.txt",
+        "E =\n0 0.5 1\nf0 = \n0 0.7 1\nh(1)=\n0 0.1 0\nh(2)=\n0 -0.2 0\n",
+    );
+    let f0 = ResponseCurve::from_emor(&filename, &[]).unwrap();
+    let curve = ResponseDesc::Emor {
+        emor: filename.clone(),
+        coefficients: vec![1.0, 0.5],
+    }
+    .load()
+    .unwrap();
+    assert!(ResponseCurve::from_emor(&filename, &[1.0, 1.0, 1.0]).is_err());
+    std::fs::remove_file(&filename).unwrap();
+    assert_close(f0.evaluate(0, 0.5), 0.7, 1e-6);
+    assert_close(curve.evaluate(0, 0.5), 0.7, 1e-6);
+    assert_close(curve.evaluate(2, 0.25), 0.35, 1e-6);
+}
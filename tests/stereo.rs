@@ -0,0 +1,131 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Deg, InnerSpace, Matrix4, Point2, Point3, Vector2, Vector3};
+use rustlight::camera::{Camera, CameraSample, Stereo, StereoLayout, StereoProjection};
+
+fn camera() -> Camera {
+    let matrix =
+        Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from_angle_y(Deg(30.0));
+    Camera::new(Vector2::new(16, 8), 40.0, matrix)
+}
+
+fn stereo(projection: StereoProjection, layout: StereoLayout) -> Camera {
+    let mut camera = camera();
+    camera.set_stereo(Stereo {
+        projection,
+        layout,
+        interocular: 0.1,
+    });
+    camera
+}
+
+fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+    assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn perspective_side_by_side() {
+    let mono = camera();
+    let camera = stereo(StereoProjection::Perspective, StereoLayout::SideBySide);
+    assert_eq!(*camera.size(), Vector2::new(32, 8));
+    assert!(camera.sample_direct(&Point3::new(1.0, 2.0, 10.0)).is_none());
+
+    let right_axis =
+        mono.generate(Point2::new(16.0, 4.0)).ray.d - mono.generate(Point2::new(0.0, 4.0)).ray.d;
+    for &(x, y) in &[(0.5, 0.5), (8.0, 4.0), (15.5, 7.5)] {
+        let expected = mono.generate(Point2::new(x, y)).ray;
+        let left = camera.generate(Point2::new(x, y)).ray;
+        let right = camera.generate(Point2::new(x + 16.0, y)).ray;
+        assert_close(left.d, expected.d);
+        assert_close(right.d, expected.d);
+        // The eyes are on both sides of the camera position, along the image x axis
+        let baseline = right.o - left.o;
+        assert!((baseline.magnitude() - 0.1).abs() < 1e-4);
+        assert!(baseline.dot(right_axis) > 0.0);
+        assert_close(left.o - mono.position(), mono.position() - right.o);
+    }
+}
+
+#[test]
+fn ods_over_under() {
+    let mono = camera();
+    let camera = stereo(StereoProjection::ODS, StereoLayout::OverUnder);
+    assert_eq!(*camera.size(), Vector2::new(16, 16));
+    let position = mono.position();
+
+    // The center of each panorama looks forward
+    let forward = mono.generate(Point2::new(8.0, 4.0)).ray.d;
+    assert_close(camera.generate(Point2::new(8.0, 4.0)).ray.d, forward);
+    assert_close(camera.generate(Point2::new(8.0, 12.0)).ray.d, forward);
+
+    for iy in 0..8 {
+        for ix in 0..16 {
+            let p = Point2::new(ix as f32 + 0.5, iy as f32 + 0.5);
+            let left = camera.generate(p).ray;
+            let right = camera.generate(Point2::new(p.x, p.y + 8.0)).ray;
+            assert_close(left.d, right.d);
+            // Eyes on the viewing circle, tangent to the direction
+            let (l, r) = (left.o - position, right.o - position);
+            assert!((l.magnitude() - 0.05).abs() < 1e-4);
+            assert_close(l, -r);
+            assert!(l.dot(left.d).abs() < 1e-4);
+        }
+    }
+}
+
+/// Solid angle covered by the pixel (from the ray differentials)
+fn pixel_solid_angle(s: &CameraSample) -> f32 {
+    let dx = s.differentials.dx.d - s.ray.d;
+    let dy = s.differentials.dy.d - s.ray.d;
+    dx.cross(dy).magnitude()
+}
+
+#[test]
+fn camera_samples() {
+    // Small pixels: the footprint of the differentials is almost the pixel
+    let matrix = Matrix4::from_angle_y(Deg(30.0));
+    let mono = Camera::new(Vector2::new(512, 256), 40.0, matrix);
+    let mut ods = Camera::new(Vector2::new(512, 256), 40.0, matrix);
+    ods.set_stereo(Stereo {
+        projection: StereoProjection::ODS,
+        layout: StereoLayout::SideBySide,
+        interocular: 0.1,
+    });
+    let pixels = [(8.5, 8.5), (100.5, 200.5), (256.5, 128.5), (400.5, 30.5)];
+    for camera in &[&mono, &ods] {
+        for &(x, y) in &pixels {
+            let s = camera.generate(Point2::new(x, y));
+            assert_eq!(s.time, 0.0);
+            assert_eq!(s.lens_uv, Point2::new(0.5, 0.5));
+            // Uniform density over the pixels of one eye
+            let pdf_pixel = s.we_pdf * pixel_solid_angle(&s);
+            let expected = 1.0 / (512.0 * 256.0);
+            assert!(
+                (pdf_pixel - expected).abs() < 0.01 * expected,
+                "{} != {}",
+                pdf_pixel,
+                expected
+            );
+            let next = camera.generate(Point2::new(x + 1.0, y)).ray;
+            assert_close(s.differentials.dx.d, next.d);
+            assert_close(s.differentials.dx.o - next.o, Vector3::new(0.0, 0.0, 0.0));
+        }
+    }
+
+    // The density of the pinhole is its importance
+    for &(x, y) in &pixels[..3] {
+        let s = mono.generate(Point2::new(x, y));
+        let (importance, p) = mono.sample_direct(&(s.ray.o + s.ray.d)).unwrap();
+        assert!((importance.r - s.we_pdf).abs() < 1e-3 * s.we_pdf);
+        assert!((p - Point2::new(x, y)).magnitude() < 1e-2);
+    }
+}
+
+#[test]
+fn parse_options() {
+    assert_eq!("ods".parse(), Ok(StereoProjection::ODS));
+    assert_eq!("sbs".parse(), Ok(StereoLayout::SideBySide));
+    assert!("anaglyph".parse::<StereoProjection>().is_err());
+    assert!("vertical".parse::<StereoLayout>().is_err());
+}
@@ -0,0 +1,71 @@
+//! Size of the rendering blocks: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point2, Vector2};
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::{auto_tile_size, IntegratorType, NoObserver, TILE_SIZE};
+use rustlight::validation::*;
+
+#[test]
+fn auto_tile_sizes() {
+    // Enough blocks of the default size
+    assert_eq!(auto_tile_size(Vector2::new(1920, 1080), 16, 1e3), TILE_SIZE);
+    // Small images rendered by an expensive integrator: 8 blocks per thread
+    assert_eq!(auto_tile_size(Vector2::new(32, 32), 8, 1e4), 4);
+    assert_eq!(auto_tile_size(Vector2::new(64, 48), 4, 1e4), 10);
+    // Cheap integrator: the blocks still trace 4096 rays
+    assert_eq!(auto_tile_size(Vector2::new(32, 32), 8, 100.0), 7);
+    assert_eq!(auto_tile_size(Vector2::new(32, 32), 8, 1.0), TILE_SIZE);
+}
+
+#[test]
+fn all_pixels_rendered() {
+    for tile_size in [None, Some(5), Some(TILE_SIZE), Some(100)] {
+        let mut scene = furnace(0.5, 1.0).scene.tile_size(tile_size);
+        // Automatic size (warm-up) without seed
+        scene.seed = None;
+        scene.nb_samples = 3;
+        scene.output_variance = true;
+        let mut integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+            max_depth: Some(2),
+            depth_limits: Default::default(),
+            strategy: IntegratorPathTracingStrategies::All,
+            lightcuts: None,
+            rr: Default::default(),
+            min_roughness: None,
+        }));
+        let img = integrator.compute(&scene, &NoObserver);
+        let spp = &img.values["spp"];
+        assert_eq!(spp.size, *scene.camera.size());
+        for y in 0..spp.size.y {
+            for x in 0..spp.size.x {
+                assert_eq!(spp.pixel(Point2::new(x, y)).r, 3.0, "{:?}", tile_size);
+            }
+        }
+    }
+}
+
+#[test]
+fn seeded_tiles() {
+    // The automatic size is not used with a seed: same image whatever the threads
+    let render = |nb_threads| {
+        let mut scene = furnace(0.5, 1.0).scene.tile_size(None);
+        scene.nb_samples = 2;
+        scene.nb_threads = Some(nb_threads);
+        let mut integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+            max_depth: Some(2),
+            depth_limits: Default::default(),
+            strategy: IntegratorPathTracingStrategies::All,
+            lightcuts: None,
+            rr: Default::default(),
+            min_roughness: None,
+        }));
+        let mut img = integrator.compute(&scene, &NoObserver);
+        img.values.remove("primal").unwrap().colors
+    };
+    assert_eq!(render(1), render(8));
+}
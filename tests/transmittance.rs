@@ -0,0 +1,133 @@
+//! Transmittance estimators: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::{Point3, Vector3};
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::IntegratorType;
+use rustlight::samplers::independent::IndependentSampler;
+use rustlight::structure::{Color, Ray};
+use rustlight::validation::*;
+use rustlight::volume::{HomogenousVolume, TransmittanceEstimator, TransmittanceOptions};
+
+const ESTIMATORS: [TransmittanceEstimator; 5] = [
+    TransmittanceEstimator::Analytic,
+    TransmittanceEstimator::TrackLength,
+    TransmittanceEstimator::RatioTracking,
+    TransmittanceEstimator::ResidualRatioTracking,
+    TransmittanceEstimator::PowerSeries,
+];
+
+/// Colored medium (the residual ratio tracking is not exact)
+fn medium(estimator: TransmittanceEstimator) -> HomogenousVolume {
+    let sigma_t = Color::new(0.5, 1.0, 2.0);
+    HomogenousVolume {
+        sigma_a: sigma_t * 0.2,
+        sigma_s: sigma_t * 0.8,
+        sigma_t,
+        density: 1.0,
+        transmittance: TransmittanceOptions {
+            estimator,
+            majorant_scale: 2.0,
+        },
+    }
+}
+
+fn ray(dist: f32) -> Ray {
+    let mut ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+    ray.tfar = dist;
+    ray
+}
+
+/// Mean, variance (green channel) and average number of lookups
+fn statistics(m: &HomogenousVolume, dist: f32, nb_samples: usize) -> (Color, f32, f32) {
+    let mut sampler = IndependentSampler::from_seed(7);
+    let (mut sum, mut sum2, mut lookups) = ([0.0f64; 3], 0.0, 0);
+    for _ in 0..nb_samples {
+        let (tr, nb_lookups) = m.estimate_transmittance_cost(ray(dist), &mut sampler);
+        for (c, s) in sum.iter_mut().enumerate() {
+            *s += f64::from(tr.get(c as u8));
+        }
+        sum2 += f64::from(tr.g) * f64::from(tr.g);
+        lookups += nb_lookups;
+    }
+    let n = nb_samples as f64;
+    let mean = Color::new(
+        (sum[0] / n) as f32,
+        (sum[1] / n) as f32,
+        (sum[2] / n) as f32,
+    );
+    let var = sum2 / n - (sum[1] / n) * (sum[1] / n);
+    (mean, var as f32, lookups as f32 / n as f32)
+}
+
+#[test]
+fn unbiased_estimators() {
+    for &dist in &[0.1, 1.0, 3.0] {
+        let expected = medium(TransmittanceEstimator::Analytic).transmittance(ray(dist));
+        for &estimator in &ESTIMATORS {
+            let (mean, _, _) = statistics(&medium(estimator), dist, 100_000);
+            for c in 0..3 {
+                let error = (mean.get(c) - expected.get(c)).abs();
+                assert!(
+                    error < 0.01 + 0.02 * expected.get(c),
+                    "{:?} (distance {}): {:?} != {:?}",
+                    estimator,
+                    dist,
+                    mean,
+                    expected
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn variance_and_cost() {
+    let stats = |estimator| statistics(&medium(estimator), 1.0, 100_000);
+    let (_, analytic_var, analytic_cost) = stats(TransmittanceEstimator::Analytic);
+    assert!(analytic_var.abs() < 1e-5);
+    assert_eq!(analytic_cost, 0.0);
+    // The ratio tracking weights do the binary decisions of the track-length estimator
+    let (_, track_length_var, track_length_cost) = stats(TransmittanceEstimator::TrackLength);
+    let (_, ratio_var, ratio_cost) = stats(TransmittanceEstimator::RatioTracking);
+    assert!(ratio_var < track_length_var);
+    assert!(track_length_cost <= ratio_cost);
+    // The control removes most of the extinction to track
+    let (_, residual_var, residual_cost) = stats(TransmittanceEstimator::ResidualRatioTracking);
+    assert!(residual_var < ratio_var);
+    assert!(residual_cost < ratio_cost);
+}
+
+#[test]
+fn parse_estimators() {
+    assert_eq!("ratio".parse(), Ok(TransmittanceEstimator::RatioTracking));
+    assert_eq!(
+        "power-series".parse(),
+        Ok(TransmittanceEstimator::PowerSeries)
+    );
+    assert!("delta".parse::<TransmittanceEstimator>().is_err());
+}
+
+#[test]
+fn furnace_medium_estimators() {
+    // The shadow rays of the path tracer see the whole medium
+    for &estimator in &ESTIMATORS[1..] {
+        let mut scene = furnace_medium(0.5, 1.0, 1.0);
+        scene.scene.volume.as_mut().unwrap().transmittance.estimator = estimator;
+        let mut integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+            max_depth: None,
+            depth_limits: Default::default(),
+            strategy: IntegratorPathTracingStrategies::All,
+            lightcuts: None,
+            rr: Default::default(),
+            min_roughness: None,
+        }));
+        if let Err(e) = scene.validate(&mut integrator, 0.02) {
+            panic!("{:?}: {}", estimator, e);
+        }
+    }
+}
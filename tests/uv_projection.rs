@@ -0,0 +1,177 @@
+extern crate cgmath;
+extern crate rustlight;
+extern crate serde_json;
+
+use cgmath::{Vector2, Vector3};
+use rustlight::geometry::{Mesh, UVProjection};
+use rustlight::scene_loader::{parse_scene, SceneLoaderManager};
+
+/// Unit cube (8 positions, 2 triangles per face, outward winding)
+fn cube() -> Mesh {
+    let vertices = (0..8)
+        .map(|i| Vector3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32))
+        .collect();
+    let quads = [
+        [0, 2, 3, 1],
+        [4, 5, 7, 6],
+        [0, 1, 5, 4],
+        [2, 6, 7, 3],
+        [0, 4, 6, 2],
+        [1, 3, 7, 5],
+    ];
+    let indices = quads
+        .iter()
+        .flat_map(|q| {
+            vec![
+                Vector3::new(q[0], q[1], q[2]),
+                Vector3::new(q[0], q[2], q[3]),
+            ]
+        })
+        .collect();
+    let normals = Some(vec![Vector3::new(0.0, 0.0, 1.0); 8]);
+    Mesh::new("cube".to_string(), vertices, indices, normals, None)
+}
+
+#[test]
+fn box_projection() {
+    let mut mesh = cube();
+    mesh.project_uv(UVProjection::Box);
+    // The corners with different coordinates on the adjacent faces are duplicated
+    assert!(mesh.vertices.len() > 8);
+    assert_eq!(mesh.normals.as_ref().unwrap().len(), mesh.vertices.len());
+    let uv = mesh.uv.as_ref().unwrap();
+    for id in &mesh.indices {
+        let (v0, v1, v2) = (
+            mesh.vertices[id.x],
+            mesh.vertices[id.y],
+            mesh.vertices[id.z],
+        );
+        let n = (v1 - v0).cross(v2 - v0);
+        for k in 0..3 {
+            let (p, t) = (mesh.vertices[id[k]], uv[id[k]]);
+            let expected = if n.x != 0.0 {
+                Vector2::new(p.z, p.y)
+            } else if n.y != 0.0 {
+                Vector2::new(p.x, p.z)
+            } else {
+                Vector2::new(p.x, p.y)
+            };
+            assert_eq!(t, expected);
+        }
+    }
+    // Same triangles (the area distribution is unchanged)
+    assert_eq!(mesh.indices.len(), 12);
+    assert!((mesh.cdf.normalization - 6.0).abs() < 1e-5);
+}
+
+#[test]
+fn planar_and_spherical_projections() {
+    // Planar: the cube is projected along its main axis (no vertex duplicated)
+    let mut mesh = cube();
+    mesh.project_uv(UVProjection::Planar);
+    assert_eq!(mesh.vertices.len(), 8);
+
+    // Spherical: the triangles crossing the seam wrap around
+    let mut mesh = cube();
+    mesh.project_uv(UVProjection::Spherical);
+    let uv = mesh.uv.as_ref().unwrap();
+    assert!(mesh.vertices.len() > 8);
+    for id in &mesh.indices {
+        let u = [uv[id.x].x, uv[id.y].x, uv[id.z].x];
+        let (min, max) = (u[0].min(u[1]).min(u[2]), u[0].max(u[1]).max(u[2]));
+        assert!(max - min <= 0.5, "{:?}", u);
+        assert!(min >= 0.0 && max <= 1.5);
+        for k in 0..3 {
+            assert!((0.0..=1.0).contains(&uv[id[k]].y));
+        }
+    }
+}
+
+#[test]
+fn load_projections() {
+    let dir = std::env::temp_dir().join("rustlight_uv_projection");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("mesh.obj"),
+        "o ground\nv -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nf 1 2 3 4\n\
+         o ball\nv 0 0 1\nv 1 0 2\nv 0 1 2\nf 5 6 7\n",
+    )
+    .unwrap();
+    let load = |scene: serde_json::Value| {
+        let file = dir.join("scene.json");
+        std::fs::write(&file, scene.to_string()).unwrap();
+        SceneLoaderManager::default().load(file.to_str().unwrap().to_string())
+    };
+    let camera = serde_json::json!({"fov": 60.0, "img": [4, 4], "matrix": [
+        1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1
+    ]});
+
+    // Box projection by default
+    let scene = load(serde_json::json!({"meshes": "mesh.obj", "camera": camera})).unwrap();
+    let ground = scene.meshes.iter().find(|m| m.name == "ground").unwrap();
+    let uv = ground.uv.as_ref().expect("no texture coordinates");
+    for (p, t) in ground.vertices.iter().zip(uv) {
+        assert_eq!(*t, Vector2::new(p.x, p.y));
+    }
+
+    let scene = load(serde_json::json!({
+        "meshes": "mesh.obj",
+        "camera": camera,
+        "uv_projections": [{"mesh": "ball", "projection": "spherical"}]
+    }))
+    .unwrap();
+    let ball = scene.meshes.iter().find(|m| m.name == "ball").unwrap();
+    assert!(ball.uv.as_ref().unwrap().iter().all(|t| t.y <= 1.0));
+
+    assert!(load(serde_json::json!({
+        "meshes": "mesh.obj",
+        "camera": camera,
+        "uv_projections": [{"mesh": "sky", "projection": "box"}]
+    }))
+    .is_err());
+    assert!(load(serde_json::json!({
+        "meshes": "mesh.obj",
+        "camera": camera,
+        "uv_projections": [{"mesh": "ball", "projection": "cylindrical"}]
+    }))
+    .is_err());
+
+    // Description without a scene file (paths relative to the given directory)
+    let scene = parse_scene(
+        &serde_json::json!({"meshes": "mesh.obj", "camera": camera}),
+        &dir,
+    )
+    .unwrap();
+    assert_eq!(scene.meshes.len(), 2);
+    assert!(parse_scene(&serde_json::json!({"meshes": "mesh.obj"}), &dir).is_err());
+}
+
+#[test]
+fn triplanar_texture() {
+    use cgmath::Point3;
+    use rustlight::structure::{Color, TexCoords};
+    use rustlight::texture::parse_texture;
+    let json = r#"{"type": "triplanar", "data": {"scale": 2.0, "sharpness": 1.0, "texture":
+        {"type": "checkerboard", "data": {"color1": {"r": 1.0, "g": 1.0, "b": 1.0},
+                                          "color2": {"r": 0.0, "g": 0.0, "b": 0.0}}}}}"#;
+    let texture = parse_texture(&serde_json::from_str(json).unwrap()).unwrap();
+    let coords = |p: Point3<f32>, n: Vector3<f32>| TexCoords {
+        uv: None,
+        p,
+        n,
+        face: None,
+        width: 0.0,
+    };
+    // Facing x: checks of (z, y) * scale
+    let p = Point3::new(0.1, 0.1, 0.6);
+    let eval = |n| texture.eval_at(&coords(p, n), 0.0).unwrap();
+    assert_eq!(eval(Vector3::new(-1.0, 0.0, 0.0)), Color::zero());
+    // Facing y and z: (x, z) and (x, y) checks
+    assert_eq!(eval(Vector3::new(0.0, 1.0, 0.0)), Color::zero());
+    assert_eq!(eval(Vector3::new(0.0, 0.0, 1.0)), Color::one());
+    // Blended by the normal components
+    let n = Vector3::new(1.0, 0.0, 1.0);
+    assert_eq!(eval(n), Color::value(0.5));
+    // Without a normal, nothing to blend
+    assert!(texture.eval_at(&TexCoords::default(), 0.0).is_none());
+}
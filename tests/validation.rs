@@ -0,0 +1,514 @@
+//! Energy checks against closed-form results: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::IntegratorType;
+use rustlight::validation::*;
+
+/// Path tracing without depth limit (the analytic results include all the bounces)
+fn path(strategy: IntegratorPathTracingStrategies) -> IntegratorType {
+    IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: None,
+        depth_limits: Default::default(),
+        strategy,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }))
+}
+
+fn check(scene: AnalyticScene, mut integrator: IntegratorType, tolerance: f32) {
+    if let Err(e) = scene.validate(&mut integrator, tolerance) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn furnace_path() {
+    for &albedo in &[0.0, 0.5, 0.8] {
+        check(
+            furnace(albedo, 1.0),
+            path(IntegratorPathTracingStrategies::All),
+            0.02,
+        );
+    }
+}
+
+#[test]
+fn furnace_path_bsdf() {
+    check(
+        furnace(0.5, 1.0),
+        path(IntegratorPathTracingStrategies::BSDF),
+        0.02,
+    );
+}
+
+#[test]
+fn furnace_medium_path() {
+    check(
+        furnace_medium(0.5, 1.0, 1.0),
+        path(IntegratorPathTracingStrategies::All),
+        0.02,
+    );
+}
+
+#[test]
+fn furnace_medium_gradient() {
+    use rustlight::integrators::gradient::explicit::IntegratorGradientPathTracing;
+    use rustlight::integrators::gradient::recons::UniformPoissonReconstruction;
+    check(
+        furnace_medium(0.5, 1.0, 1.0),
+        IntegratorType::Gradient(Box::new(IntegratorGradientPathTracing {
+            max_depth: None,
+            recons: Box::new(UniformPoissonReconstruction { iterations: 50 }),
+            min_survival: None,
+        })),
+        0.02,
+    );
+}
+
+#[test]
+fn furnace_medium_volpath() {
+    use rustlight::integrators::explicit::volpath::IntegratorVolPath;
+    for &equiangular in &[false, true] {
+        check(
+            furnace_medium(0.5, 1.0, 1.0),
+            IntegratorType::Primal(Box::new(IntegratorVolPath {
+                max_depth: None,
+                equiangular,
+                single_scattering: None,
+            })),
+            0.02,
+        );
+    }
+}
+
+#[test]
+fn point_light_volpath() {
+    // Without medium: surface emitter sampling and BSDF sampling only
+    use rustlight::integrators::explicit::volpath::IntegratorVolPath;
+    check(
+        point_light(0.5, 1000.0),
+        IntegratorType::Primal(Box::new(IntegratorVolPath {
+            max_depth: None,
+            equiangular: true,
+            single_scattering: None,
+        })),
+        0.02,
+    );
+}
+
+#[test]
+fn point_light_medium_volpath() {
+    // No closed form: the equiangular sampling and the stratified single scattering
+    // have to match the free-flight sampling
+    use rustlight::integrators::explicit::volpath::IntegratorVolPath;
+    use rustlight::integrators::NoObserver;
+    use rustlight::structure::Color;
+    use rustlight::volume::HomogenousVolume;
+    let render = |equiangular, single_scattering| {
+        let mut scene = point_light(0.5, 1000.0).scene;
+        scene.nb_samples = 256;
+        scene.volume = Some(HomogenousVolume {
+            sigma_a: Color::value(0.1),
+            sigma_s: Color::value(0.4),
+            sigma_t: Color::value(0.5),
+            density: 1.0,
+            transmittance: Default::default(),
+        });
+        let mut integrator = IntegratorType::Primal(Box::new(IntegratorVolPath {
+            max_depth: None,
+            equiangular,
+            single_scattering,
+        }));
+        let mut img = integrator.compute(&scene, &NoObserver);
+        img.values.remove("primal").unwrap().average().avg()
+    };
+    let free_flight = render(false, None);
+    for &(equiangular, single_scattering) in &[(true, None), (false, Some(8))] {
+        let v = render(equiangular, single_scattering);
+        assert!(
+            (free_flight - v).abs() < 0.02 * free_flight,
+            "{} != {} (equiangular: {}, single scattering: {:?})",
+            free_flight,
+            v,
+            equiangular,
+            single_scattering
+        );
+    }
+}
+
+#[test]
+fn volume_aov_volpath() {
+    use rustlight::integrators::explicit::volpath::IntegratorVolPath;
+    use rustlight::integrators::NoObserver;
+    let mut scene = furnace_medium(0.5, 1.0, 1.0).scene.output_volume(true);
+    scene.nb_samples = 4;
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorVolPath {
+        max_depth: None,
+        equiangular: true,
+        single_scattering: None,
+    }));
+    let img = integrator.compute(&scene, &NoObserver);
+    let (primal, surface, volume) = (
+        img.values["primal"].average(),
+        img.values["surface"].average(),
+        img.values["volume"].average(),
+    );
+    assert!(surface.avg() > 0.0 && volume.avg() > 0.0);
+    assert!(
+        (primal.avg() - surface.avg() - volume.avg()).abs() < 1e-3 * primal.avg(),
+        "{:?} != {:?} + {:?}",
+        primal,
+        surface,
+        volume
+    );
+}
+
+#[test]
+fn parallel_planes_gradient_features() {
+    // The L1 reconstruction is only consistent: larger tolerance
+    use rustlight::integrators::gradient::explicit::IntegratorGradientPathTracing;
+    use rustlight::integrators::gradient::recons::FeaturePoissonReconstruction;
+    check(
+        parallel_planes(0.5, 1.0),
+        IntegratorType::Gradient(Box::new(IntegratorGradientPathTracing {
+            max_depth: None,
+            recons: Box::new(FeaturePoissonReconstruction::new(50, 0.2)),
+            min_survival: None,
+        })),
+        0.05,
+    );
+}
+
+#[test]
+fn parallel_planes_path() {
+    for strategy in [
+        IntegratorPathTracingStrategies::All,
+        IntegratorPathTracingStrategies::BSDF,
+        IntegratorPathTracingStrategies::Emitter,
+    ] {
+        check(parallel_planes(0.5, 1.0), path(strategy), 0.02);
+    }
+}
+
+#[test]
+fn parallel_planes_direct() {
+    check(
+        parallel_planes(0.5, 1.0),
+        IntegratorType::Primal(Box::new(rustlight::integrators::direct::IntegratorDirect {
+            nb_bsdf_samples: 1,
+            nb_light_samples: 1,
+        })),
+        0.02,
+    );
+}
+
+#[test]
+fn point_light_rejection() {
+    // The MIS avoids the outliers: the robust estimate stays close to the mean
+    use rustlight::structure::SampleRejection;
+    let mut scene = point_light(0.5, 1000.0);
+    scene.scene.sample_rejection = Some(SampleRejection { confidence: 0.99 });
+    check(scene, path(IntegratorPathTracingStrategies::All), 0.02);
+}
+
+#[test]
+fn point_light_path() {
+    for strategy in [
+        IntegratorPathTracingStrategies::All,
+        IntegratorPathTracingStrategies::Emitter,
+    ] {
+        check(point_light(0.5, 1000.0), path(strategy), 0.02);
+    }
+}
+
+#[test]
+fn environment_direct() {
+    for &mis_compensation in &[false, true] {
+        check(
+            environment_ground(0.5, mis_compensation),
+            IntegratorType::Primal(Box::new(rustlight::integrators::direct::IntegratorDirect {
+                nb_bsdf_samples: 1,
+                nb_light_samples: 1,
+            })),
+            0.02,
+        );
+    }
+}
+
+#[test]
+fn sun_direct() {
+    // Sun sampling, BSDF sampling and their combination
+    for &(nb_bsdf_samples, nb_light_samples) in &[(0, 1), (1, 1)] {
+        check(
+            sun_ground(0.5),
+            IntegratorType::Primal(Box::new(rustlight::integrators::direct::IntegratorDirect {
+                nb_bsdf_samples,
+                nb_light_samples,
+            })),
+            0.02,
+        );
+    }
+}
+
+#[test]
+fn environment_path() {
+    // BSDF sampling, environment sampling and their combination
+    // (the compensated map needs the BSDF sampling)
+    let check_environment = |mis_compensation, strategy| {
+        let mut scene = environment_ground(0.5, mis_compensation);
+        scene.scene.nb_samples = 1024;
+        check(scene, path(strategy), 0.02);
+    };
+    check_environment(false, IntegratorPathTracingStrategies::BSDF);
+    check_environment(false, IntegratorPathTracingStrategies::Emitter);
+    check_environment(false, IntegratorPathTracingStrategies::All);
+    check_environment(true, IntegratorPathTracingStrategies::All);
+}
+
+#[test]
+fn sun_path() {
+    // The sun is sampled with the environment by the emitter strategy
+    check(
+        sun_ground(0.5),
+        path(IntegratorPathTracingStrategies::Emitter),
+        0.02,
+    );
+    check(
+        sun_ground(0.5),
+        path(IntegratorPathTracingStrategies::All),
+        0.02,
+    );
+}
+
+#[test]
+fn emitter_selection_path() {
+    use rustlight::emitter::EmitterSelection;
+    for &selection in &[
+        EmitterSelection::Uniform,
+        EmitterSelection::LightBVH,
+        EmitterSelection::LightGrid,
+    ] {
+        let mut scene = point_light(0.5, 1000.0);
+        scene.scene.emitter_selection = selection;
+        check(scene, path(IntegratorPathTracingStrategies::All), 0.02);
+        let mut scene = many_lights(0.5, 100.0);
+        scene.scene.emitter_selection = selection;
+        check(scene, path(IntegratorPathTracingStrategies::Emitter), 0.02);
+    }
+}
+
+#[test]
+fn stratified_light_samples_direct() {
+    use rustlight::emitter::EmitterSelection;
+    for &selection in &[
+        EmitterSelection::Power,
+        EmitterSelection::Uniform,
+        EmitterSelection::LightBVH,
+        EmitterSelection::LightGrid,
+    ] {
+        let mut scene = many_lights(0.5, 100.0);
+        scene.scene.emitter_selection = selection;
+        check(
+            scene,
+            IntegratorType::Primal(Box::new(rustlight::integrators::direct::IntegratorDirect {
+                nb_bsdf_samples: 1,
+                nb_light_samples: 4,
+            })),
+            0.02,
+        );
+    }
+}
+
+#[test]
+fn lightcuts_path() {
+    use rustlight::emitter::EmitterSelection;
+    // The light BVH is used whatever the selection of the scene
+    for &(max_cut, selection) in &[
+        (1, EmitterSelection::LightBVH),
+        (4, EmitterSelection::LightBVH),
+        (32, EmitterSelection::LightBVH),
+        (4, EmitterSelection::Power),
+    ] {
+        let mut scene = many_lights(0.5, 100.0);
+        scene.scene.emitter_selection = selection;
+        let integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+            max_depth: None,
+            depth_limits: Default::default(),
+            strategy: IntegratorPathTracingStrategies::All,
+            lightcuts: Some(max_cut),
+            rr: Default::default(),
+            min_roughness: None,
+        }));
+        check(scene, integrator, 0.02);
+    }
+}
+
+#[test]
+fn russian_roulette_path() {
+    use rustlight::integrators::explicit::path::{RussianRouletteMode, RussianRouletteOptions};
+    for &(mode, start_depth, max_splitting) in &[
+        (RussianRouletteMode::Throughput, 3, 1),
+        (RussianRouletteMode::Throughput, 0, 4),
+        (RussianRouletteMode::Adjoint, 0, 1),
+        (RussianRouletteMode::Adjoint, 1, 4),
+    ] {
+        let rr = || RussianRouletteOptions {
+            mode,
+            start_depth,
+            max_splitting,
+        };
+        for scene in vec![furnace(0.8, 1.0), parallel_planes(0.5, 1.0)] {
+            let integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+                max_depth: None,
+                depth_limits: Default::default(),
+                strategy: IntegratorPathTracingStrategies::All,
+                lightcuts: None,
+                rr: rr(),
+                min_roughness: None,
+            }));
+            check(scene, integrator, 0.03);
+        }
+    }
+}
+
+#[test]
+fn depth_limits_path() {
+    use rustlight::paths::path::DepthLimits;
+    // Furnace with N scattering events: L = Le (1 + a + ... + a^N)
+    let albedo: f32 = 0.5;
+    let expected = |nb_scattering: i32| (0..=nb_scattering).map(|k| albedo.powi(k)).sum::<f32>();
+    let limits = |diffuse, specular| DepthLimits {
+        diffuse,
+        glossy: None,
+        specular,
+    };
+    for &(max_depth, depth_limits, nb_scattering) in &[
+        // The maximum depth counts the vertices (sensor included)
+        (Some(2), limits(None, None), 0),
+        (Some(4), limits(None, None), 2),
+        // Only the direct lighting on the diffuse surfaces
+        (None, limits(Some(0), None), 1),
+        (None, limits(Some(2), Some(0)), 3),
+        (Some(3), limits(Some(2), None), 1),
+    ] {
+        let mut scene = furnace(albedo, 1.0);
+        scene.expected = expected(nb_scattering);
+        let integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+            max_depth,
+            depth_limits,
+            strategy: IntegratorPathTracingStrategies::All,
+            lightcuts: None,
+            rr: Default::default(),
+            min_roughness: None,
+        }));
+        check(scene, integrator, 0.02);
+    }
+}
+
+#[test]
+fn vpl_passes() {
+    use rustlight::integrators::explicit::vpl::IntegratorVPL;
+    let mut scene = furnace(0.5, 1.0);
+    scene.scene.nb_samples = 2;
+    let integrator = IntegratorType::Primal(Box::new(IntegratorVPL {
+        nb_vpl: 128,
+        max_depth: None,
+        clamping_factor: Some(0.3),
+        nb_passes: 4,
+        visibility_cache: None,
+    }));
+    check(scene, integrator, 0.03);
+}
+
+#[test]
+fn mrcs_many_lights() {
+    use rustlight::integrators::explicit::mrcs::IntegratorMRCS;
+    let mut scene = many_lights(0.5, 100.0);
+    scene.scene.nb_samples = 4;
+    let integrator = IntegratorType::Primal(Box::new(IntegratorMRCS {
+        nb_vpl: 4096,
+        max_depth: None,
+        nb_rows: 16,
+        nb_clusters: 32,
+    }));
+    check(scene, integrator, 0.03);
+}
+
+#[test]
+fn pssmlt_mutations() {
+    use rustlight::integrators::pssmlt::IntegratorPSSMLT;
+    for &(adaptive, delayed_rejection) in &[(Some(0.234), false), (None, true)] {
+        let integrator = IntegratorType::Primal(Box::new(IntegratorPSSMLT {
+            large_prob: 0.5,
+            integrator: Box::new(IntegratorPathTracing {
+                max_depth: None,
+                depth_limits: Default::default(),
+                strategy: IntegratorPathTracingStrategies::All,
+                lightcuts: None,
+                rr: Default::default(),
+                min_roughness: None,
+            }),
+            temperatures: vec![1.0],
+            swap_frequency: 0.1,
+            adaptive,
+            delayed_rejection,
+            resume: None,
+            checkpoint: None,
+        }));
+        check(furnace(0.5, 1.0), integrator, 0.05);
+    }
+}
+
+#[test]
+fn pssmlt_checkpoint() {
+    // With replica exchange: the checkpoints keep the ensembles of replicas
+    use rustlight::integrators::pssmlt::{IntegratorPSSMLT, MLTCheckpoint};
+    let filename = std::env::temp_dir().join("rustlight_pssmlt_checkpoint.json");
+    let filename = filename.to_str().unwrap().to_string();
+    let pssmlt = |resume, checkpoint| {
+        IntegratorType::Primal(Box::new(IntegratorPSSMLT {
+            large_prob: 0.3,
+            integrator: Box::new(IntegratorPathTracing {
+                max_depth: None,
+                depth_limits: Default::default(),
+                strategy: IntegratorPathTracingStrategies::All,
+                lightcuts: None,
+                rr: Default::default(),
+                min_roughness: None,
+            }),
+            temperatures: vec![1.0, 2.0],
+            swap_frequency: 0.1,
+            adaptive: None,
+            delayed_rejection: true,
+            resume,
+            checkpoint,
+        }))
+    };
+    check(
+        parallel_planes(0.5, 1.0),
+        pssmlt(None, Some(filename.clone())),
+        0.05,
+    );
+    let first = MLTCheckpoint::load(&filename).unwrap();
+    assert!(!first.chains.is_empty());
+    assert_eq!(first.chains.len() % 2, 0);
+
+    // Continue the chains of two copies of the first run
+    let mut merged = first.clone();
+    merged.merge(first.clone()).unwrap();
+    check(
+        parallel_planes(0.5, 1.0),
+        pssmlt(Some(merged), Some(filename.clone())),
+        0.05,
+    );
+    let resumed = MLTCheckpoint::load(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(resumed.chains.len(), 2 * first.chains.len());
+    assert_eq!(resumed.bootstrap_samples, 2 * first.bootstrap_samples);
+    assert!(resumed.nb_mutations > 2 * first.nb_mutations);
+}
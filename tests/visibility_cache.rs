@@ -0,0 +1,64 @@
+extern crate cgmath;
+extern crate rustlight;
+
+use cgmath::Point3;
+use rustlight::integrators::visibility_cache::{VisibilityCache, VisibilityCacheConfig};
+use rustlight::scene::Acceleration;
+use rustlight::structure::{Intersection, Ray};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Wall at x = 0 with a hole for y > 1.25, counting the shadow rays
+#[derive(Default)]
+struct Wall {
+    rays: AtomicUsize,
+}
+impl Acceleration for Wall {
+    fn trace(&self, _ray: &Ray) -> Option<Intersection<'_>> {
+        None
+    }
+    fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool {
+        self.rays.fetch_add(1, Ordering::Relaxed);
+        p0.x.signum() == p1.x.signum() || (p0.y > 1.25 && p1.y > 1.25)
+    }
+}
+
+#[test]
+fn cached_shadow_rays() {
+    let wall = Wall::default();
+    let mut config = VisibilityCacheConfig::new(1.0);
+    config.min_samples = 4;
+    let cache = VisibilityCache::new(&wall, config);
+    let light = Point3::new(-0.5, 0.5, 0.5);
+    let p = |i: usize| Point3::new(0.5 + i as f32 * 0.01, 0.5, 0.5);
+    for i in 0..10 {
+        assert!(!cache.visible(&light, &p(i)));
+        // Symmetric
+        assert!(!cache.visible(&p(i), &light));
+    }
+    assert_eq!(wall.rays.load(Ordering::Relaxed), 4);
+    assert_eq!((cache.queries(), cache.hits()), (20, 16));
+
+    // Partially occluded cells: all the rays are traced
+    let light = Point3::new(-0.5, 1.5, 0.5);
+    for i in 0..10 {
+        let y = if i % 2 == 0 { 1.5 } else { 1.1 };
+        let visible = cache.visible(&light, &Point3::new(0.5, y, 0.5));
+        assert_eq!(visible, i % 2 == 0);
+    }
+    assert_eq!(wall.rays.load(Ordering::Relaxed), 4 + 10);
+}
+
+#[test]
+fn invalidation_period() {
+    let wall = Wall::default();
+    let mut config = VisibilityCacheConfig::new(1.0);
+    config.min_samples = 2;
+    config.period = 5;
+    let cache = VisibilityCache::new(&wall, config);
+    let (p0, p1) = (Point3::new(0.5, 0.5, 0.5), Point3::new(0.6, 0.6, 0.6));
+    for _ in 0..10 {
+        assert!(cache.visible(&p0, &p1));
+    }
+    // 2 rays traced in each period
+    assert_eq!(wall.rays.load(Ordering::Relaxed), 4);
+}
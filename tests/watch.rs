@@ -0,0 +1,124 @@
+//! Scene hot-reload: cargo test --release --features validation
+#![cfg(feature = "validation")]
+extern crate rustlight;
+extern crate serde_json;
+
+use rustlight::geometry::same_geometry;
+use rustlight::integrators::explicit::path::{
+    IntegratorPathTracing, IntegratorPathTracingStrategies,
+};
+use rustlight::integrators::{IntegratorType, NoObserver};
+use rustlight::validation::*;
+use rustlight::watch::{scene_files, FileWatcher};
+use std::path::PathBuf;
+
+#[test]
+fn referenced_files() {
+    let dir = std::env::temp_dir().join("rustlight_watch_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let scene = serde_json::json!({
+        "meshes": "mesh.obj",
+        "bsdfs": [{
+            "mesh": "ground",
+            "type": "diffuse",
+            "data": {"albedo": {"type": "udim", "data": {"filename": path("albedo.<UDIM>.png")}}}
+        }],
+        "environment": {"filename": path("sky.exr")}
+    });
+    std::fs::write(dir.join("scene.json"), scene.to_string()).unwrap();
+    std::fs::write(dir.join("mesh.obj"), "mtllib mesh.mtl\nv 0 0 0\n").unwrap();
+    std::fs::write(dir.join("mesh.mtl"), "newmtl wood\nmap_Kd wood.png\n").unwrap();
+    for tile in &["albedo.1001.png", "albedo.1002.png", "albedo.png"] {
+        std::fs::write(dir.join(tile), "").unwrap();
+    }
+
+    let files = scene_files(&path("scene.json")).unwrap();
+    let mut expected = [
+        "scene.json",
+        "mesh.obj",
+        "mesh.mtl",
+        "wood.png",
+        "sky.exr",
+        "albedo.1001.png",
+        "albedo.1002.png",
+    ]
+    .iter()
+    .map(|f| dir.join(f))
+    .collect::<Vec<_>>();
+    // New UDIM tiles
+    expected.push(dir.clone());
+    expected.sort();
+    assert_eq!(files, expected);
+}
+
+#[test]
+fn modified_files() {
+    let dir = std::env::temp_dir().join("rustlight_watch_modified");
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("scene.json");
+    std::fs::write(&file, "{}").unwrap();
+    let mut watcher = FileWatcher::new(vec![file.clone(), dir.join("missing.png")]);
+    assert!(watcher.changed().is_empty());
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(watcher.changed(), vec![file.clone()]);
+    assert!(watcher.changed().is_empty());
+    std::fs::write(&file, "{}").unwrap();
+    assert_eq!(watcher.changed(), vec![file]);
+}
+
+#[test]
+fn reload_versions() {
+    let mut versions = vec![
+        furnace(0.5, 1.0),
+        // Same geometry: the acceleration data structure is reused
+        furnace(0.5, 2.0),
+        parallel_planes(0.5, 1.0),
+    ];
+    assert!(same_geometry(
+        &versions[0].scene.meshes,
+        &versions[1].scene.meshes
+    ));
+    assert!(!same_geometry(
+        &versions[1].scene.meshes,
+        &versions[2].scene.meshes
+    ));
+
+    let expected = versions.iter().map(|v| v.expected).collect::<Vec<_>>();
+    versions.reverse();
+    let first = versions.pop().unwrap();
+    let mut integrator = IntegratorType::Primal(Box::new(IntegratorPathTracing {
+        max_depth: None,
+        depth_limits: Default::default(),
+        strategy: IntegratorPathTracingStrategies::All,
+        lightcuts: None,
+        rr: Default::default(),
+        min_roughness: None,
+    }));
+    let mut averages = vec![];
+    integrator.compute_reload(first.scene, &NoObserver, |_, img| {
+        averages.push(img.values["primal"].average().avg());
+        versions.pop().map(|v| v.scene)
+    });
+    assert_eq!(averages.len(), 3);
+    for (avg, expected) in averages.iter().zip(&expected) {
+        assert!(
+            (avg - expected).abs() < 0.05 * expected,
+            "{} != {}",
+            avg,
+            expected
+        );
+    }
+}
+
+#[test]
+fn missing_obj_file() {
+    let dir = std::env::temp_dir().join("rustlight_watch_missing");
+    std::fs::create_dir_all(&dir).unwrap();
+    let scene = dir.join("scene.json");
+    std::fs::write(&scene, r#"{"meshes": "missing.obj"}"#).unwrap();
+    assert!(scene_files(scene.to_str().unwrap()).is_err());
+    // The other formats only watch the scene file
+    let pbrt = PathBuf::from("scene.pbrt");
+    assert_eq!(scene_files("scene.pbrt").unwrap(), vec![pbrt]);
+}